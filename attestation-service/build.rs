@@ -5,6 +5,9 @@ fn real_main() -> Result<(), String> {
     tonic_build::compile_protos("../protos/attestation.proto").map_err(|e| format!("{e}"))?;
 
     tonic_build::compile_protos("../protos/reference.proto").map_err(|e| format!("{e}"))?;
+
+    #[cfg(feature = "external-verifier")]
+    tonic_build::compile_protos("../protos/verifier.proto").map_err(|e| format!("{e}"))?;
     Ok(())
 }
 
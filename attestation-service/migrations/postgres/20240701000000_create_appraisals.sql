@@ -0,0 +1,10 @@
+-- Appraisals of the attestation service `Postgres` archive store.
+CREATE TABLE IF NOT EXISTS appraisals (
+    id TEXT PRIMARY KEY,
+    appraised_at BIGINT NOT NULL,
+    tee TEXT NOT NULL,
+    rejected BOOLEAN NOT NULL,
+    record JSONB NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS appraisals_appraised_at ON appraisals (appraised_at);
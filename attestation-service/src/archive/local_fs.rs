@@ -0,0 +1,143 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use std::path::PathBuf;
+
+use super::{AppraisalRecord, ArchiveQuery, ArchiveStore};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::warn;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::fs;
+
+const DIR_PATH: &str = "/opt/confidential-containers/attestation-service/archive";
+
+fn default_dir_path() -> String {
+    DIR_PATH.to_string()
+}
+
+#[derive(Deserialize, Default)]
+struct Config {
+    #[serde(default = "default_dir_path")]
+    dir_path: String,
+}
+
+/// Archive storing each appraisal in a JSON file named after its id.
+pub struct LocalFs {
+    dir_path: PathBuf,
+}
+
+impl LocalFs {
+    pub fn new(config: Value) -> Result<Self> {
+        let config: Config = serde_json::from_value(config)?;
+        std::fs::create_dir_all(&config.dir_path)
+            .with_context(|| format!("create archive dir {}", config.dir_path))?;
+        Ok(Self {
+            dir_path: PathBuf::from(config.dir_path),
+        })
+    }
+
+    fn record_path(&self, id: &str) -> Option<PathBuf> {
+        // The ids are UUIDs, which can not escape the archive dir.
+        uuid::Uuid::parse_str(id).ok()?;
+        Some(self.dir_path.join(format!("{id}.json")))
+    }
+}
+
+#[async_trait]
+impl ArchiveStore for LocalFs {
+    async fn put(&self, record: &AppraisalRecord) -> Result<()> {
+        let path = self
+            .record_path(&record.id)
+            .context("illegal appraisal id")?;
+        let contents = serde_json::to_vec(record)?;
+
+        // Written aside and renamed, so that no partial record is ever read.
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, contents).await?;
+        fs::rename(tmp_path, path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<AppraisalRecord>> {
+        let Some(path) = self.record_path(id) else {
+            return Ok(None);
+        };
+        match fs::read(path).await {
+            Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn query(&self, query: &ArchiveQuery) -> Result<Vec<AppraisalRecord>> {
+        let mut records = Vec::new();
+        let mut entries = fs::read_dir(&self.dir_path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+
+            match serde_json::from_slice(&fs::read(&path).await?) {
+                Ok(record) => records.push(record),
+                Err(e) => warn!("Skip illegal archived appraisal {}: {e}", path.display()),
+            }
+        }
+
+        Ok(query.select(records))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn archive_appraisals() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalFs::new(json!({ "dir_path": dir.path() })).unwrap();
+
+        let mut affirmed = AppraisalRecord::new("snp", b"evidence", &["default".into()]);
+        affirmed.timestamp = 100;
+        affirmed.evaluation_reports = Some(json!([{ "policy-id": "default" }]));
+        let mut rejected = AppraisalRecord::new("tdx", b"forged", &["default".into()]);
+        rejected.timestamp = 200;
+        rejected.error = Some("Verifier evaluate failed".into());
+        store.put(&affirmed).await.unwrap();
+        store.put(&rejected).await.unwrap();
+
+        assert_eq!(
+            store.get(&affirmed.id).await.unwrap(),
+            Some(affirmed.clone())
+        );
+        assert_eq!(store.get("../policies").await.unwrap(), None);
+
+        let all = store.query(&ArchiveQuery::default()).await.unwrap();
+        assert_eq!(all, vec![rejected.clone(), affirmed.clone()]);
+
+        let query = ArchiveQuery {
+            rejected: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(store.query(&query).await.unwrap(), vec![affirmed.clone()]);
+
+        let query = ArchiveQuery {
+            tee: Some("tdx".into()),
+            since: Some(150),
+            ..Default::default()
+        };
+        assert_eq!(store.query(&query).await.unwrap(), vec![rejected]);
+
+        let query = ArchiveQuery {
+            until: Some(150),
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(store.query(&query).await.unwrap(), vec![affirmed]);
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Archive of the appraisals of the evidence, i.e. the raw evidence, the
+//! inputs of the policies and the outcome of each attestation, so that the
+//! reason why an evidence was affirmed or rejected can be audited later.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use strum::EnumString;
+
+use self::local_fs::LocalFs;
+
+pub mod local_fs;
+
+#[cfg(feature = "archive-postgres")]
+pub mod postgres;
+
+#[cfg(feature = "archive-s3")]
+pub mod s3;
+
+#[derive(Deserialize, Debug, Clone, PartialEq, EnumString)]
+pub enum ArchiveStoreType {
+    LocalFs,
+    #[cfg(feature = "archive-postgres")]
+    Postgres,
+    #[cfg(feature = "archive-s3")]
+    S3,
+}
+
+impl ArchiveStoreType {
+    pub async fn to_store(&self, config: Value) -> Result<Box<dyn ArchiveStore + Send + Sync>> {
+        match self {
+            ArchiveStoreType::LocalFs => {
+                Ok(Box::new(LocalFs::new(config)?) as Box<dyn ArchiveStore + Send + Sync>)
+            }
+            #[cfg(feature = "archive-postgres")]
+            ArchiveStoreType::Postgres => Ok(Box::new(postgres::Postgres::new(config).await?)
+                as Box<dyn ArchiveStore + Send + Sync>),
+            #[cfg(feature = "archive-s3")]
+            ArchiveStoreType::S3 => {
+                Ok(Box::new(s3::S3::new(config).await?) as Box<dyn ArchiveStore + Send + Sync>)
+            }
+        }
+    }
+}
+
+fn default_store_config() -> Value {
+    json!({})
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct ArchiveConfig {
+    /// Type of the store of the appraisals.
+    pub store_type: ArchiveStoreType,
+
+    /// Configuration of the store, specific to its type.
+    #[serde(default = "default_store_config")]
+    pub store_config: Value,
+}
+
+/// The appraisal of an evidence.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct AppraisalRecord {
+    /// Unique id of the appraisal.
+    pub id: String,
+
+    /// Unix time of the appraisal, in seconds.
+    pub timestamp: i64,
+
+    pub tee: String,
+
+    /// Base64 encoded evidence, as sent by the attester.
+    pub evidence: String,
+
+    pub policy_ids: Vec<String>,
+
+    /// Flattened claims of the evidence given to the policies, if the
+    /// evidence was verified.
+    pub claims: Option<Value>,
+
+    /// Reference values of the claims given to the policies.
+    pub reference_data: Option<Value>,
+
+    /// Vendor advisories of the reference values given to the policies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub advisories: Option<Value>,
+
+    /// Evaluation reports of the policies that affirmed the evidence.
+    pub evaluation_reports: Option<Value>,
+
+    /// Why the evidence was rejected, if it was.
+    pub error: Option<String>,
+}
+
+impl AppraisalRecord {
+    pub fn new(tee: &str, evidence: &[u8], policy_ids: &[String]) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            timestamp: time::OffsetDateTime::now_utc().unix_timestamp(),
+            tee: tee.to_string(),
+            evidence: STANDARD.encode(evidence),
+            policy_ids: policy_ids.to_vec(),
+            claims: None,
+            reference_data: None,
+            advisories: None,
+            evaluation_reports: None,
+            error: None,
+        }
+    }
+}
+
+/// Filter of the archived appraisals. The fields that are not set match all
+/// the appraisals.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct ArchiveQuery {
+    pub tee: Option<String>,
+
+    /// Earliest Unix time of the appraisals, in seconds.
+    pub since: Option<i64>,
+
+    /// Latest Unix time of the appraisals, in seconds.
+    pub until: Option<i64>,
+
+    /// Only the rejected, or the affirmed, appraisals.
+    pub rejected: Option<bool>,
+
+    /// Maximum number of returned appraisals, the latest first.
+    pub limit: Option<usize>,
+}
+
+impl ArchiveQuery {
+    pub fn matches(&self, record: &AppraisalRecord) -> bool {
+        self.tee.iter().all(|tee| *tee == record.tee)
+            && self.since.iter().all(|since| record.timestamp >= *since)
+            && self.until.iter().all(|until| record.timestamp <= *until)
+            && self
+                .rejected
+                .iter()
+                .all(|rejected| *rejected == record.error.is_some())
+    }
+
+    /// The matching `records`, the latest first, up to the limit.
+    pub fn select(
+        &self,
+        records: impl IntoIterator<Item = AppraisalRecord>,
+    ) -> Vec<AppraisalRecord> {
+        let mut records: Vec<_> = records
+            .into_iter()
+            .filter(|record| self.matches(record))
+            .collect();
+        records.sort_by_key(|record| std::cmp::Reverse(record.timestamp));
+        if let Some(limit) = self.limit {
+            records.truncate(limit);
+        }
+        records
+    }
+}
+
+/// Interface of a store of the appraisals. Stores backed by a database or an
+/// object storage implement the same interface.
+#[async_trait]
+pub trait ArchiveStore {
+    /// Archive an appraisal.
+    async fn put(&self, record: &AppraisalRecord) -> Result<()>;
+
+    /// Retrieve the appraisal `id`.
+    async fn get(&self, id: &str) -> Result<Option<AppraisalRecord>>;
+
+    /// Retrieve the appraisals matching `query`, the latest first.
+    async fn query(&self, query: &ArchiveQuery) -> Result<Vec<AppraisalRecord>>;
+}
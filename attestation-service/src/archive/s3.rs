@@ -0,0 +1,171 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use super::{AppraisalRecord, ArchiveQuery, ArchiveStore};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_s3::{
+    config::Credentials, error::SdkError, operation::get_object::GetObjectError,
+    primitives::ByteStream, Client,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Deserialize)]
+struct Config {
+    /// Name of the bucket where the appraisals are stored.
+    bucket: String,
+
+    /// Optional key prefix inside the bucket, e.g. `as/archive`.
+    prefix: Option<String>,
+
+    /// Region of the bucket. If not given, the region is resolved from the
+    /// environment (`AWS_REGION`, profile, IMDS...).
+    region: Option<String>,
+
+    /// Custom endpoint of an S3-compatible service, e.g. a MinIO server.
+    endpoint: Option<String>,
+
+    /// Static access key id. If not given, credentials are resolved from the
+    /// default AWS credential chain.
+    access_key_id: Option<String>,
+
+    /// Static secret access key, used together with `access_key_id`.
+    secret_access_key: Option<String>,
+
+    /// Use path-style addressing (`endpoint/bucket/key`).
+    #[serde(default)]
+    force_path_style: bool,
+}
+
+/// Archive storing each appraisal in an object named after its id. A query
+/// reads all the objects, so that this store suits write-once buckets, e.g.
+/// with object lock, that are audited from time to time.
+pub struct S3 {
+    client: Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl S3 {
+    pub async fn new(config: Value) -> Result<Self> {
+        let config: Config = serde_json::from_value(config)?;
+        let mut loader = aws_config::defaults(BehaviorVersion::latest());
+        if let Some(region) = &config.region {
+            loader = loader.region(Region::new(region.clone()));
+        }
+
+        if let Some(endpoint) = &config.endpoint {
+            loader = loader.endpoint_url(endpoint);
+        }
+
+        if let (Some(access_key_id), Some(secret_access_key)) =
+            (&config.access_key_id, &config.secret_access_key)
+        {
+            loader = loader.credentials_provider(Credentials::new(
+                access_key_id,
+                secret_access_key,
+                None,
+                None,
+                "as-config",
+            ));
+        }
+
+        let sdk_config = loader.load().await;
+        let s3_config = aws_sdk_s3::config::Builder::from(&sdk_config)
+            .force_path_style(config.force_path_style)
+            .build();
+
+        let prefix = config
+            .prefix
+            .unwrap_or_default()
+            .trim_matches('/')
+            .to_string();
+        Ok(Self {
+            client: Client::from_conf(s3_config),
+            bucket: config.bucket,
+            prefix: match prefix.is_empty() {
+                true => prefix,
+                false => format!("{prefix}/"),
+            },
+        })
+    }
+
+    async fn get_object(&self, key: &str) -> Result<Option<AppraisalRecord>> {
+        let object = match self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(object) => object,
+            Err(SdkError::ServiceError(e)) if matches!(e.err(), GetObjectError::NoSuchKey(_)) => {
+                return Ok(None)
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to get object `{key}` from S3"))
+            }
+        };
+
+        let data = object
+            .body
+            .collect()
+            .await
+            .context("failed to read object body from S3")?;
+        Ok(Some(serde_json::from_slice(&data.into_bytes())?))
+    }
+}
+
+#[async_trait]
+impl ArchiveStore for S3 {
+    async fn put(&self, record: &AppraisalRecord) -> Result<()> {
+        let key = format!("{}{}.json", self.prefix, record.id);
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .content_type("application/json")
+            .body(ByteStream::from(serde_json::to_vec(record)?))
+            .send()
+            .await
+            .with_context(|| format!("failed to put object `{key}` to S3"))?;
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<AppraisalRecord>> {
+        if uuid::Uuid::parse_str(id).is_err() {
+            return Ok(None);
+        }
+
+        self.get_object(&format!("{}{id}.json", self.prefix)).await
+    }
+
+    async fn query(&self, query: &ArchiveQuery) -> Result<Vec<AppraisalRecord>> {
+        let mut records = Vec::new();
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&self.prefix)
+            .delimiter("/")
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            let page = page.context("failed to list objects from S3")?;
+            for key in page.contents().iter().filter_map(|object| object.key()) {
+                if !key.ends_with(".json") {
+                    continue;
+                }
+
+                records.extend(self.get_object(key).await?);
+            }
+        }
+
+        Ok(query.select(records))
+    }
+}
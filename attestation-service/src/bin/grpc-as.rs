@@ -45,8 +45,14 @@ async fn main() -> Result<()> {
 
     let cli = Cli::parse();
 
+    #[cfg(feature = "otel")]
+    attestation_service::telemetry::init()?;
+
     let server = grpc::start(cli.socket, cli.config_file);
     tokio::try_join!(server)?;
 
+    #[cfg(feature = "otel")]
+    attestation_service::telemetry::shutdown();
+
     Ok(())
 }
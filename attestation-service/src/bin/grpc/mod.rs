@@ -1,23 +1,35 @@
-use anyhow::bail;
-use attestation_service::HashAlgorithm;
+use anyhow::{bail, Context};
+use attestation_service::{
+    archive::ArchiveQuery, dry_run::DryRunEvidence, sgx_identity::EnclaveIdentity, HashAlgorithm,
+    ReferenceValueQuery, SeMaterialKind,
+};
 use attestation_service::{
     config::Config, config::ConfigError, AttestationService as Service, ServiceError, Tee,
 };
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::Engine;
+use futures::future::join_all;
 use log::{debug, info};
+use opentelemetry::global;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::FutureExt;
 use std::net::SocketAddr;
 use std::path::Path;
+use std::str::FromStr;
 use std::sync::Arc;
 use thiserror::Error;
 use tokio::sync::RwLock;
+use tonic::metadata::{KeyRef, MetadataMap};
 use tonic::transport::Server;
 use tonic::{Request, Response, Status};
 
 use crate::as_api::attestation_service_server::{AttestationService, AttestationServiceServer};
 use crate::as_api::{
-    AttestationRequest, AttestationResponse, ChallengeRequest, ChallengeResponse, SetPolicyRequest,
-    SetPolicyResponse,
+    batch_attestation_result, dry_run_request, ArchiveQueryRequest, ArchiveQueryResponse,
+    AttestationRequest, AttestationResponse, BatchAttestationRequest, BatchAttestationResponse,
+    BatchAttestationResult, ChallengeRequest, ChallengeResponse, DryRunRequest, DryRunResponse,
+    SetInitDataRequest, SetInitDataResponse, SetPolicyRequest, SetPolicyResponse,
+    SetSeMaterialRequest, SetSeMaterialResponse, SetSgxIdentityRequest, SetSgxIdentityResponse,
 };
 
 use crate::rvps_api::reference_value_provider_service_server::{
@@ -25,8 +37,11 @@ use crate::rvps_api::reference_value_provider_service_server::{
 };
 
 use crate::rvps_api::{
+    AuditLogRequest, AuditLogResponse, ReferenceValueChange, ReferenceValueDeleteRequest,
+    ReferenceValueDeleteResponse, ReferenceValueListRequest, ReferenceValueListResponse,
     ReferenceValueQueryRequest, ReferenceValueQueryResponse, ReferenceValueRegisterRequest,
-    ReferenceValueRegisterResponse,
+    ReferenceValueRegisterResponse, ReferenceValueRollbackRequest, ReferenceValueRollbackResponse,
+    WatchReferenceValuesRequest,
 };
 
 fn to_kbs_tee(tee: &str) -> anyhow::Result<Tee> {
@@ -47,6 +62,135 @@ fn to_kbs_tee(tee: &str) -> anyhow::Result<Tee> {
     Ok(tee)
 }
 
+/// The parsed fields of an [`AttestationRequest`].
+struct ParsedRequest {
+    evidence: Vec<u8>,
+    tee: Tee,
+    runtime_data: Option<attestation_service::Data>,
+    runtime_data_hash_algorithm: HashAlgorithm,
+    init_data: Option<attestation_service::Data>,
+    init_data_hash_algorithm: HashAlgorithm,
+    policy_ids: Vec<String>,
+}
+
+fn parse_request(request: AttestationRequest) -> anyhow::Result<ParsedRequest> {
+    debug!("Evidence: {}", &request.evidence);
+
+    let tee = to_kbs_tee(&request.tee).context("parse TEE type")?;
+    let evidence = URL_SAFE_NO_PAD
+        .decode(request.evidence)
+        .context("Illegal input Evidence")?;
+
+    let runtime_data = match request.runtime_data {
+        Some(runtime_data) => match runtime_data {
+            crate::as_api::attestation_request::RuntimeData::RawRuntimeData(raw) => {
+                let raw_runtime = URL_SAFE_NO_PAD
+                    .decode(raw)
+                    .context("base64 decode runtime data")?;
+                Some(attestation_service::Data::Raw(raw_runtime))
+            }
+            crate::as_api::attestation_request::RuntimeData::StructuredRuntimeData(structured) => {
+                let structured =
+                    serde_json::from_str(&structured).context("parse structured runtime data")?;
+                Some(attestation_service::Data::Structured(structured))
+            }
+        },
+        None => None,
+    };
+
+    let init_data = match request.init_data {
+        Some(init_data) => match init_data {
+            crate::as_api::attestation_request::InitData::RawInitData(raw) => {
+                let raw_init = URL_SAFE_NO_PAD
+                    .decode(raw)
+                    .context("base64 decode init data")?;
+                Some(attestation_service::Data::Raw(raw_init))
+            }
+            crate::as_api::attestation_request::InitData::StructuredInitData(structured) => {
+                let structured =
+                    serde_json::from_str(&structured).context("parse structured init data")?;
+                Some(attestation_service::Data::Structured(structured))
+            }
+        },
+        None => None,
+    };
+
+    let runtime_data_hash_algorithm = match request.runtime_data_hash_algorithm.is_empty() {
+        false => HashAlgorithm::try_from(&request.runtime_data_hash_algorithm[..])
+            .context("parse runtime data HashAlgorithm failed")?,
+        true => {
+            info!("No Runtime Data Hash Algorithm provided, use `sha384` by default.");
+            HashAlgorithm::Sha384
+        }
+    };
+
+    let init_data_hash_algorithm = match request.init_data_hash_algorithm.is_empty() {
+        false => HashAlgorithm::try_from(&request.init_data_hash_algorithm[..])
+            .context("parse init data HashAlgorithm failed")?,
+        true => {
+            info!("No Init Data Hash Algorithm provided, use `sha384` by default.");
+            HashAlgorithm::Sha384
+        }
+    };
+
+    Ok(ParsedRequest {
+        evidence,
+        tee,
+        runtime_data,
+        runtime_data_hash_algorithm,
+        init_data,
+        init_data_hash_algorithm,
+        policy_ids: request.policy_ids,
+    })
+}
+
+struct MetadataExtractor<'a>(&'a MetadataMap);
+
+impl Extractor for MetadataExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .keys()
+            .filter_map(|key| match key {
+                KeyRef::Ascii(key) => Some(key.as_str()),
+                KeyRef::Binary(_) => None,
+            })
+            .collect()
+    }
+}
+
+/// The trace context propagated in the metadata of `request`, e.g. by the
+/// KBS, which the spans of the appraisal are nested in.
+fn parent_context<T>(request: &Request<T>) -> opentelemetry::Context {
+    global::get_text_map_propagator(|propagator| {
+        propagator.extract(&MetadataExtractor(request.metadata()))
+    })
+}
+
+/// Evaluate the evidence of `request`, and return the attestation token.
+async fn evaluate(service: &Service, request: AttestationRequest) -> Result<String, Status> {
+    let request = parse_request(request).map_err(|e| Status::aborted(format!("{e:#}")))?;
+    let attestation_token = service
+        .evaluate(
+            request.evidence,
+            request.tee,
+            request.runtime_data,
+            request.runtime_data_hash_algorithm,
+            request.init_data,
+            request.init_data_hash_algorithm,
+            request.policy_ids,
+        )
+        .await
+        .map_err(|e| Status::aborted(format!("Attestation: {e:?}")))?;
+
+    debug!("Attestation Token: {}", &attestation_token);
+
+    Ok(attestation_token)
+}
+
 #[derive(Error, Debug)]
 pub enum GrpcError {
     #[error("Read AS config file failed: {0}")]
@@ -82,6 +226,7 @@ impl AttestationService for Arc<RwLock<AttestationServer>> {
         &self,
         request: Request<SetPolicyRequest>,
     ) -> Result<Response<SetPolicyResponse>, Status> {
+        let context = parent_context(&request);
         let request: SetPolicyRequest = request.into_inner();
 
         info!("SetPolicy API called.");
@@ -91,119 +236,167 @@ impl AttestationService for Arc<RwLock<AttestationServer>> {
             .await
             .attestation_service
             .set_policy(request.policy_id, request.policy)
+            .with_context(context)
             .await
             .map_err(|e| Status::aborted(format!("Set Attestation Policy Failed: {e}")))?;
 
         Ok(Response::new(SetPolicyResponse {}))
     }
 
-    async fn attestation_evaluate(
+    async fn set_init_data(
         &self,
-        request: Request<AttestationRequest>,
-    ) -> Result<Response<AttestationResponse>, Status> {
-        let request: AttestationRequest = request.into_inner();
+        request: Request<SetInitDataRequest>,
+    ) -> Result<Response<SetInitDataResponse>, Status> {
+        let request: SetInitDataRequest = request.into_inner();
+
+        info!("SetInitData API called.");
+        debug!("SetInitDataInput: {request:#?}");
+
+        let mut service = self.write().await;
+        match request.digests.is_empty() {
+            true => service
+                .attestation_service
+                .unregister_init_data(&request.workload)
+                .await
+                .map(|_| ()),
+            false => {
+                service
+                    .attestation_service
+                    .register_init_data(request.workload, request.digests)
+                    .await
+            }
+        }
+        .map_err(|e| Status::aborted(format!("Set Init Data Failed: {e}")))?;
 
-        info!("AttestationEvaluate API called.");
-        debug!("Evidence: {}", &request.evidence);
-
-        let tee = to_kbs_tee(&request.tee)
-            .map_err(|e| Status::aborted(format!("parse TEE type: {e}")))?;
-        let evidence = URL_SAFE_NO_PAD
-            .decode(request.evidence)
-            .map_err(|e| Status::aborted(format!("Illegal input Evidence: {e}")))?;
-
-        let runtime_data = match request.runtime_data {
-            Some(runtime_data) => match runtime_data {
-                crate::as_api::attestation_request::RuntimeData::RawRuntimeData(raw) => {
-                    let raw_runtime = URL_SAFE_NO_PAD
-                        .decode(raw)
-                        .map_err(|e| Status::aborted(format!("base64 decode runtime data: {e}")))?;
-                    Some(attestation_service::Data::Raw(raw_runtime))
-                }
-                crate::as_api::attestation_request::RuntimeData::StructuredRuntimeData(
-                    structured,
-                ) => {
-                    let structured = serde_json::from_str(&structured).map_err(|e| {
-                        Status::aborted(format!("parse structured runtime data: {e}"))
-                    })?;
-                    Some(attestation_service::Data::Structured(structured))
-                }
-            },
-            None => None,
-        };
+        Ok(Response::new(SetInitDataResponse {}))
+    }
 
-        let init_data = match request.init_data {
-            Some(init_data) => match init_data {
-                crate::as_api::attestation_request::InitData::RawInitData(raw) => {
-                    let raw_init = URL_SAFE_NO_PAD
-                        .decode(raw)
-                        .map_err(|e| Status::aborted(format!("base64 decode init data: {e}")))?;
-                    Some(attestation_service::Data::Raw(raw_init))
-                }
-                crate::as_api::attestation_request::InitData::StructuredInitData(structured) => {
-                    let structured = serde_json::from_str(&structured)
-                        .map_err(|e| Status::aborted(format!("parse structured init data: {e}")))?;
-                    Some(attestation_service::Data::Structured(structured))
-                }
-            },
-            None => None,
+    async fn set_sgx_identity(
+        &self,
+        request: Request<SetSgxIdentityRequest>,
+    ) -> Result<Response<SetSgxIdentityResponse>, Status> {
+        let request: SetSgxIdentityRequest = request.into_inner();
+
+        info!("SetSgxIdentity API called.");
+        debug!("SetSgxIdentityInput: {request:#?}");
+
+        let isv_prod_id = request
+            .isv_prod_id
+            .map(u16::try_from)
+            .transpose()
+            .map_err(|_| Status::invalid_argument("ISVPRODID is not a u16"))?;
+        let identity = EnclaveIdentity {
+            mr_enclave: Some(request.mr_enclave).filter(|it| !it.is_empty()),
+            mr_signer: Some(request.mr_signer).filter(|it| !it.is_empty()),
+            isv_prod_id,
         };
 
-        let runtime_data_hash_algorithm = match request.runtime_data_hash_algorithm.is_empty() {
+        let mut service = self.write().await;
+        match request.remove {
+            true => service
+                .attestation_service
+                .unregister_sgx_identity(identity)
+                .await
+                .map(|_| ()),
             false => {
-                HashAlgorithm::try_from(&request.runtime_data_hash_algorithm[..]).map_err(|e| {
-                    Status::aborted(format!("parse runtime data HashAlgorithm failed: {e}"))
-                })?
-            }
-            true => {
-                info!("No Runtime Data Hash Algorithm provided, use `sha384` by default.");
-                HashAlgorithm::Sha384
+                service
+                    .attestation_service
+                    .register_sgx_identity(identity)
+                    .await
             }
-        };
+        }
+        .map_err(|e| Status::aborted(format!("Set SGX Identity Failed: {e}")))?;
+
+        Ok(Response::new(SetSgxIdentityResponse {}))
+    }
 
-        let init_data_hash_algorithm = match request.init_data_hash_algorithm.is_empty() {
+    async fn set_se_material(
+        &self,
+        request: Request<SetSeMaterialRequest>,
+    ) -> Result<Response<SetSeMaterialResponse>, Status> {
+        let request: SetSeMaterialRequest = request.into_inner();
+
+        info!("SetSeMaterial API called.");
+        debug!("SetSeMaterial: {} {}", request.kind, request.name);
+
+        let kind = SeMaterialKind::from_str(&request.kind).map_err(|_| {
+            Status::invalid_argument(format!("Unknown SE material kind {}", request.kind))
+        })?;
+
+        let server = self.read().await;
+        match request.remove {
+            true => server
+                .attestation_service
+                .remove_se_material(kind, &request.name)
+                .map(|_| ()),
             false => {
-                HashAlgorithm::try_from(&request.init_data_hash_algorithm[..]).map_err(|e| {
-                    Status::aborted(format!("parse init data HashAlgorithm failed: {e}"))
-                })?
+                server
+                    .attestation_service
+                    .add_se_material(kind, &request.name, &request.contents)
             }
-            true => {
-                info!("No Init Data Hash Algorithm provided, use `sha384` by default.");
-                HashAlgorithm::Sha384
-            }
-        };
+        }
+        .map_err(|e| Status::aborted(format!("Set SE Material Failed: {e:#}")))?;
 
-        let policy_ids = match request.policy_ids.is_empty() {
-            true => vec!["default".into()],
-            false => request.policy_ids,
-        };
+        Ok(Response::new(SetSeMaterialResponse {}))
+    }
 
-        let attestation_token = self
-            .read()
-            .await
-            .attestation_service
-            .evaluate(
-                evidence,
-                tee,
-                runtime_data,
-                runtime_data_hash_algorithm,
-                init_data,
-                init_data_hash_algorithm,
-                policy_ids,
-            )
-            .await
-            .map_err(|e| Status::aborted(format!("Attestation: {e:?}")))?;
+    async fn attestation_evaluate(
+        &self,
+        request: Request<AttestationRequest>,
+    ) -> Result<Response<AttestationResponse>, Status> {
+        let context = parent_context(&request);
+        let request: AttestationRequest = request.into_inner();
 
-        debug!("Attestation Token: {}", &attestation_token);
+        info!("AttestationEvaluate API called.");
+        let attestation_token = evaluate(&self.read().await.attestation_service, request)
+            .with_context(context)
+            .await?;
 
         let res = AttestationResponse { attestation_token };
         Ok(Response::new(res))
     }
 
+    async fn attestation_evaluate_batch(
+        &self,
+        request: Request<BatchAttestationRequest>,
+    ) -> Result<Response<BatchAttestationResponse>, Status> {
+        let context = parent_context(&request);
+        let request: BatchAttestationRequest = request.into_inner();
+
+        info!(
+            "AttestationEvaluateBatch API called with {} requests.",
+            request.requests.len()
+        );
+        let server = self.read().await;
+        let results = join_all(
+            request
+                .requests
+                .into_iter()
+                .map(|request| evaluate(&server.attestation_service, request)),
+        )
+        .with_context(context)
+        .await
+        .into_iter()
+        .map(|result| BatchAttestationResult {
+            result: Some(match result {
+                Ok(attestation_token) => {
+                    batch_attestation_result::Result::AttestationToken(attestation_token)
+                }
+                Err(status) => {
+                    batch_attestation_result::Result::Error(status.message().to_string())
+                }
+            }),
+        })
+        .collect();
+
+        Ok(Response::new(BatchAttestationResponse { results }))
+    }
+
     async fn get_attestation_challenge(
         &self,
         request: Request<ChallengeRequest>,
     ) -> Result<Response<ChallengeResponse>, Status> {
+        let context = parent_context(&request);
         let request: ChallengeRequest = request.into_inner();
         info!("get_attestation_challenge API called.");
         debug!("get_attestation_challenge: {request:#?}");
@@ -224,6 +417,7 @@ impl AttestationService for Arc<RwLock<AttestationServer>> {
             .await
             .attestation_service
             .generate_supplemental_challenge(tee, tee_params.clone())
+            .with_context(context)
             .await
             .map_err(|e| Status::aborted(format!("Challenge: {e:?}")))?;
 
@@ -232,10 +426,97 @@ impl AttestationService for Arc<RwLock<AttestationServer>> {
         };
         Ok(Response::new(res))
     }
+
+    async fn query_archive(
+        &self,
+        request: Request<ArchiveQueryRequest>,
+    ) -> Result<Response<ArchiveQueryResponse>, Status> {
+        let request: ArchiveQueryRequest = request.into_inner();
+
+        info!("QueryArchive API called.");
+        debug!("ArchiveQueryRequest: {request:#?}");
+
+        let server = self.read().await;
+        let records = match request.id.is_empty() {
+            false => server
+                .attestation_service
+                .get_archived_appraisal(&request.id)
+                .await
+                .map_err(|e| Status::aborted(format!("Get Archived Appraisal Failed: {e}")))?
+                .into_iter()
+                .collect(),
+            true => {
+                let rejected = match &request.outcome[..] {
+                    "" => None,
+                    "affirmed" => Some(false),
+                    "rejected" => Some(true),
+                    other => {
+                        return Err(Status::invalid_argument(format!("Illegal outcome {other}")))
+                    }
+                };
+                let query = ArchiveQuery {
+                    tee: (!request.tee.is_empty()).then_some(request.tee),
+                    since: (request.since != 0).then_some(request.since),
+                    until: (request.until != 0).then_some(request.until),
+                    rejected,
+                    limit: (request.limit != 0).then_some(request.limit as usize),
+                };
+                server
+                    .attestation_service
+                    .query_archive(&query)
+                    .await
+                    .map_err(|e| Status::aborted(format!("Query Archive Failed: {e}")))?
+            }
+        };
+
+        let records = serde_json::to_string(&records)
+            .map_err(|e| Status::internal(format!("Serialize archived appraisals: {e}")))?;
+        Ok(Response::new(ArchiveQueryResponse { records }))
+    }
+
+    async fn dry_run(
+        &self,
+        request: Request<DryRunRequest>,
+    ) -> Result<Response<DryRunResponse>, Status> {
+        let request: DryRunRequest = request.into_inner();
+
+        info!("DryRun API called.");
+        debug!("DryRunRequest: {request:#?}");
+
+        let evidence = match request.evidence {
+            Some(dry_run_request::Evidence::Attestation(attestation)) => {
+                let attestation = parse_request(attestation)
+                    .map_err(|e| Status::invalid_argument(format!("{e:#}")))?;
+                DryRunEvidence::Evidence {
+                    evidence: attestation.evidence,
+                    tee: attestation.tee,
+                    runtime_data: attestation.runtime_data,
+                    runtime_data_hash_algorithm: attestation.runtime_data_hash_algorithm,
+                    init_data: attestation.init_data,
+                    init_data_hash_algorithm: attestation.init_data_hash_algorithm,
+                }
+            }
+            Some(dry_run_request::Evidence::ArchiveId(id)) => DryRunEvidence::Archived(id),
+            None => return Err(Status::invalid_argument("no evidence to appraise")),
+        };
+
+        let report = self
+            .read()
+            .await
+            .attestation_service
+            .dry_run(evidence, request.policy)
+            .await
+            .map_err(|e| Status::aborted(format!("Dry Run Failed: {e:#}")))?;
+        let report = serde_json::to_string(&report)
+            .map_err(|e| Status::internal(format!("Serialize dry run report: {e}")))?;
+        Ok(Response::new(DryRunResponse { report }))
+    }
 }
 
 #[tonic::async_trait]
 impl ReferenceValueProviderService for Arc<RwLock<AttestationServer>> {
+    type WatchReferenceValuesStream = futures::stream::Empty<Result<ReferenceValueChange, Status>>;
+
     async fn query_reference_value(
         &self,
         _request: Request<ReferenceValueQueryRequest>,
@@ -267,6 +548,79 @@ impl ReferenceValueProviderService for Arc<RwLock<AttestationServer>> {
         let res = ReferenceValueRegisterResponse {};
         Ok(Response::new(res))
     }
+
+    async fn list_reference_values(
+        &self,
+        request: Request<ReferenceValueListRequest>,
+    ) -> Result<Response<ReferenceValueListResponse>, Status> {
+        let request = request.into_inner();
+
+        info!("ListReferenceValues API called.");
+        debug!("ReferenceValueListRequest: {request:#?}");
+
+        let query = ReferenceValueQuery {
+            name_prefix: (!request.name_prefix.is_empty()).then_some(request.name_prefix),
+            provenance: (!request.provenance.is_empty()).then_some(request.provenance),
+            since: (request.since != 0).then_some(request.since),
+            until: (request.until != 0).then_some(request.until),
+            page_size: (request.page_size != 0).then_some(request.page_size as usize),
+            page_token: (!request.page_token.is_empty()).then_some(request.page_token),
+        };
+        let page = self
+            .read()
+            .await
+            .attestation_service
+            .query_reference_values(&query)
+            .await
+            .map_err(|e| Status::aborted(format!("List reference values: {e}")))?;
+        let reference_values = serde_json::to_string(&page.reference_values)
+            .map_err(|e| Status::internal(format!("Serialize reference values: {e}")))?;
+
+        let res = ReferenceValueListResponse {
+            reference_values,
+            next_page_token: page.next_page_token.unwrap_or_default(),
+        };
+        Ok(Response::new(res))
+    }
+
+    async fn delete_reference_value(
+        &self,
+        _request: Request<ReferenceValueDeleteRequest>,
+    ) -> Result<Response<ReferenceValueDeleteResponse>, Status> {
+        let status =
+            Status::aborted("Cannot delete reference values using RVPS as a submodule in AS.");
+
+        Err(status)
+    }
+
+    async fn rollback_reference_values(
+        &self,
+        _request: Request<ReferenceValueRollbackRequest>,
+    ) -> Result<Response<ReferenceValueRollbackResponse>, Status> {
+        let status =
+            Status::aborted("Cannot roll back reference values using RVPS as a submodule in AS.");
+
+        Err(status)
+    }
+
+    async fn get_audit_log(
+        &self,
+        _request: Request<AuditLogRequest>,
+    ) -> Result<Response<AuditLogResponse>, Status> {
+        let status = Status::aborted("Cannot get audit log using RVPS as a submodule in AS.");
+
+        Err(status)
+    }
+
+    async fn watch_reference_values(
+        &self,
+        _request: Request<WatchReferenceValuesRequest>,
+    ) -> Result<Response<Self::WatchReferenceValuesStream>, Status> {
+        let status =
+            Status::aborted("Cannot watch reference values using RVPS as a submodule in AS.");
+
+        Err(status)
+    }
 }
 
 pub async fn start(socket: SocketAddr, config_path: Option<String>) -> Result<(), GrpcError> {
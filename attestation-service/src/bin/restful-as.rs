@@ -13,7 +13,12 @@ use strum::{AsRefStr, EnumString};
 use thiserror::Error;
 use tokio::sync::RwLock;
 
-use crate::restful::{attestation, get_challenge, get_policies, set_policy};
+use crate::restful::{
+    add_se_material, attestation, attestation_batch, dry_run, get_archive, get_challenge,
+    get_init_data, get_policies, get_reference_values, get_se_materials, get_sgx_identities,
+    metrics, register_init_data, register_sgx_identity, remove_se_material, set_policy,
+    unregister_init_data, unregister_sgx_identity,
+};
 
 mod restful;
 
@@ -46,11 +51,44 @@ enum WebApi {
     #[strum(serialize = "/attestation")]
     Attestation,
 
+    #[strum(serialize = "/attestation/batch")]
+    AttestationBatch,
+
     #[strum(serialize = "/policy")]
     Policy,
 
     #[strum(serialize = "/challenge")]
     Challenge,
+
+    #[strum(serialize = "/archive")]
+    Archive,
+
+    #[strum(serialize = "/archive/{id}")]
+    ArchivedAppraisal,
+
+    #[strum(serialize = "/init-data")]
+    InitData,
+
+    #[strum(serialize = "/init-data/{workload}")]
+    InitDataWorkload,
+
+    #[strum(serialize = "/sgx-identities")]
+    SgxIdentities,
+
+    #[strum(serialize = "/dry-run")]
+    DryRun,
+
+    #[strum(serialize = "/reference-values")]
+    ReferenceValues,
+
+    #[strum(serialize = "/se-materials/{kind}")]
+    SeMaterials,
+
+    #[strum(serialize = "/se-materials/{kind}/{name}")]
+    SeMaterial,
+
+    #[strum(serialize = "/metrics")]
+    Metrics,
 }
 
 #[derive(Error, Debug)]
@@ -81,6 +119,9 @@ async fn main() -> Result<(), RestfulError> {
 
     let cli = Cli::parse();
 
+    #[cfg(feature = "otel")]
+    attestation_service::telemetry::init()?;
+
     let config = match cli.config_file {
         Some(path) => {
             info!("Using config file {path}");
@@ -98,12 +139,49 @@ async fn main() -> Result<(), RestfulError> {
     let server = HttpServer::new(move || {
         App::new()
             .service(web::resource(WebApi::Attestation.as_ref()).route(web::post().to(attestation)))
+            .service(
+                web::resource(WebApi::AttestationBatch.as_ref())
+                    .route(web::post().to(attestation_batch)),
+            )
             .service(
                 web::resource(WebApi::Policy.as_ref())
                     .route(web::post().to(set_policy))
                     .route(web::get().to(get_policies)),
             )
             .service(web::resource(WebApi::Challenge.as_ref()).route(web::post().to(get_challenge)))
+            .service(web::resource(WebApi::Archive.as_ref()).route(web::get().to(get_archive)))
+            .service(
+                web::resource(WebApi::ArchivedAppraisal.as_ref()).route(web::get().to(get_archive)),
+            )
+            .service(
+                web::resource(WebApi::InitData.as_ref())
+                    .route(web::post().to(register_init_data))
+                    .route(web::get().to(get_init_data)),
+            )
+            .service(
+                web::resource(WebApi::InitDataWorkload.as_ref())
+                    .route(web::delete().to(unregister_init_data)),
+            )
+            .service(
+                web::resource(WebApi::SgxIdentities.as_ref())
+                    .route(web::post().to(register_sgx_identity))
+                    .route(web::get().to(get_sgx_identities))
+                    .route(web::delete().to(unregister_sgx_identity)),
+            )
+            .service(web::resource(WebApi::DryRun.as_ref()).route(web::post().to(dry_run)))
+            .service(
+                web::resource(WebApi::ReferenceValues.as_ref())
+                    .route(web::get().to(get_reference_values)),
+            )
+            .service(
+                web::resource(WebApi::SeMaterials.as_ref()).route(web::get().to(get_se_materials)),
+            )
+            .service(
+                web::resource(WebApi::SeMaterial.as_ref())
+                    .route(web::post().to(add_se_material))
+                    .route(web::delete().to(remove_se_material)),
+            )
+            .service(web::resource(WebApi::Metrics.as_ref()).route(web::get().to(metrics)))
             .app_data(web::Data::clone(&attestation_service))
     });
 
@@ -135,5 +213,9 @@ async fn main() -> Result<(), RestfulError> {
     };
 
     server.await?;
+
+    #[cfg(feature = "otel")]
+    attestation_service::telemetry::shutdown();
+
     Ok(())
 }
@@ -1,9 +1,13 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, str::FromStr, sync::Arc};
 
 use actix_web::{body::BoxBody, web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::{anyhow, bail, Context};
-use attestation_service::{AttestationService, HashAlgorithm};
+use attestation_service::{
+    archive::ArchiveQuery, dry_run::DryRunEvidence, sgx_identity::EnclaveIdentity,
+    AttestationService, HashAlgorithm, ReferenceValueQuery, SeMaterialKind,
+};
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use futures::future::join_all;
 use kbs_types::Tee;
 use log::{debug, info};
 use serde::{Deserialize, Serialize};
@@ -41,6 +45,7 @@ pub struct AttestationRequest {
     init_data: Option<Data>,
     runtime_data_hash_algorithm: Option<String>,
     init_data_hash_algorithm: Option<String>,
+    #[serde(default)]
     policy_ids: Vec<String>,
 }
 
@@ -91,16 +96,18 @@ fn parse_data(data: Data) -> Result<attestation_service::Data> {
     Ok(res)
 }
 
-/// This handler uses json extractor
-pub async fn attestation(
-    request: web::Json<AttestationRequest>,
-    cocoas: web::Data<Arc<RwLock<AttestationService>>>,
-) -> Result<HttpResponse> {
-    info!("Attestation API called.");
-
-    let request = request.into_inner();
-    debug!("attestation: {request:#?}");
+/// The parsed fields of an [`AttestationRequest`].
+struct ParsedRequest {
+    evidence: Vec<u8>,
+    tee: Tee,
+    runtime_data: Option<attestation_service::Data>,
+    runtime_data_hash_algorithm: HashAlgorithm,
+    init_data: Option<attestation_service::Data>,
+    init_data_hash_algorithm: HashAlgorithm,
+    policy_ids: Vec<String>,
+}
 
+fn parse_request(request: AttestationRequest) -> anyhow::Result<ParsedRequest> {
     let evidence = URL_SAFE_NO_PAD
         .decode(&request.evidence)
         .context("base64 decode evidence")?;
@@ -139,30 +146,104 @@ pub async fn attestation(
         }
     };
 
-    let policy_ids = if request.policy_ids.is_empty() {
-        info!("no policy specified, use `default`");
-        vec!["default".into()]
-    } else {
-        request.policy_ids
-    };
+    Ok(ParsedRequest {
+        evidence,
+        tee,
+        runtime_data,
+        runtime_data_hash_algorithm,
+        init_data,
+        init_data_hash_algorithm,
+        policy_ids: request.policy_ids,
+    })
+}
 
-    let token = cocoas
-        .read()
-        .await
+/// Evaluate the evidence of `request`, and return the attestation token.
+async fn evaluate(
+    request: AttestationRequest,
+    service: &AttestationService,
+) -> anyhow::Result<String> {
+    let request = parse_request(request)?;
+    if request.policy_ids.is_empty() {
+        info!("no policy specified, use the policies of the TEE");
+    }
+
+    let token = service
         .evaluate(
-            evidence,
-            tee,
-            runtime_data,
-            runtime_data_hash_algorithm,
-            init_data,
-            init_data_hash_algorithm,
-            policy_ids,
+            request.evidence,
+            request.tee,
+            request.runtime_data,
+            request.runtime_data_hash_algorithm,
+            request.init_data,
+            request.init_data_hash_algorithm,
+            request.policy_ids,
         )
         .await
         .context("attestation report evaluate")?;
+    Ok(token)
+}
+
+/// This handler uses json extractor
+pub async fn attestation(
+    request: web::Json<AttestationRequest>,
+    cocoas: web::Data<Arc<RwLock<AttestationService>>>,
+) -> Result<HttpResponse> {
+    info!("Attestation API called.");
+
+    let request = request.into_inner();
+    debug!("attestation: {request:#?}");
+
+    let token = evaluate(request, &*cocoas.read().await).await?;
     Ok(HttpResponse::Ok().body(token))
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchAttestationRequest {
+    requests: Vec<AttestationRequest>,
+}
+
+/// POST /attestation/batch
+///
+/// The requests are evaluated independently, and the returned body holds
+/// their results in the same order, like
+/// ```json
+/// {
+///     "results": [
+///         {"attestation_token": <token-1>},
+///         {"error": <why request-2 failed>},
+///         ...
+///     ]
+/// }
+/// ```
+pub async fn attestation_batch(
+    request: web::Json<BatchAttestationRequest>,
+    cocoas: web::Data<Arc<RwLock<AttestationService>>>,
+) -> Result<HttpResponse> {
+    let request = request.into_inner();
+    info!(
+        "Batch attestation API called with {} requests.",
+        request.requests.len()
+    );
+
+    let service = cocoas.read().await;
+    let results = join_all(
+        request
+            .requests
+            .into_iter()
+            .map(|request| evaluate(request, &service)),
+    )
+    .await
+    .into_iter()
+    .map(|result| match result {
+        Ok(token) => json!({ "attestation_token": token }),
+        Err(e) => json!({ "error": format!("{e:#}") }),
+    })
+    .collect::<Vec<_>>();
+
+    let body =
+        serde_json::to_string(&json!({ "results": results })).context("serialize response body")?;
+    Ok(HttpResponse::Ok().body(body))
+}
+
 #[derive(Deserialize, Debug)]
 pub struct SetPolicyInput {
     policy_id: String,
@@ -265,6 +346,348 @@ pub async fn get_policies(
     }
 }
 
+/// GET /archive?tee={tee}&since={unix time}&until={unix time}&rejected={bool}&limit={n}
+/// GET /archive/{id}
+///
+/// All the query parameters are optional. The returned body is the list of
+/// the matching archived appraisals, the latest first, or the appraisal `id`.
+pub async fn get_archive(
+    request: HttpRequest,
+    query: web::Query<ArchiveQuery>,
+    cocoas: web::Data<Arc<RwLock<AttestationService>>>,
+) -> Result<HttpResponse> {
+    info!("get archive.");
+
+    let body = match request.match_info().get("id") {
+        Some(id) => {
+            let record = cocoas
+                .read()
+                .await
+                .get_archived_appraisal(id)
+                .await
+                .context("get archived appraisal")?;
+            let Some(record) = record else {
+                return Ok(HttpResponse::NotFound().body(format!("no appraisal {id}")));
+            };
+            serde_json::to_string(&record)
+        }
+        None => {
+            let records = cocoas
+                .read()
+                .await
+                .query_archive(&query)
+                .await
+                .context("query archive")?;
+            serde_json::to_string(&records)
+        }
+    }
+    .context("serialize response body")?;
+
+    Ok(HttpResponse::Ok().body(body))
+}
+
+/// GET /reference-values?name_prefix={prefix}&provenance={type}&since={unix time}&until={unix time}&page_size={n}&page_token={token}
+///
+/// All the query parameters are optional. The returned body is a page of the
+/// matching reference values, ordered by name, with the `next_page_token`
+/// to get the next page if any.
+pub async fn get_reference_values(
+    query: web::Query<ReferenceValueQuery>,
+    cocoas: web::Data<Arc<RwLock<AttestationService>>>,
+) -> Result<HttpResponse> {
+    info!("get reference values.");
+
+    let page = cocoas
+        .read()
+        .await
+        .query_reference_values(&query)
+        .await
+        .context("query reference values")?;
+    let body = serde_json::to_string(&page).context("serialize response body")?;
+
+    Ok(HttpResponse::Ok().body(body))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DryRunRequest {
+    /// Base64 encoded candidate policy.
+    policy: String,
+
+    /// The evidence to appraise, like in an attestation request whose
+    /// policy ids are ignored.
+    attestation: Option<AttestationRequest>,
+
+    /// The id of an archived appraisal whose claims are appraised, instead
+    /// of an evidence.
+    archive_id: Option<String>,
+}
+
+/// POST /dry-run
+///
+/// Appraise an evidence, or an archived appraisal, against the candidate
+/// policy of the payload, like
+/// ```json
+/// {
+///     "policy": <base64 encoded policy>,
+///     "archive_id": <id of the archived appraisal>
+/// }
+/// ```
+/// No token is issued, and neither the policy nor the appraisal is stored.
+/// The returned body is the report of the appraisal, like
+/// ```json
+/// {
+///     "allowed": false,
+///     "policy_hash": null,
+///     "trust_vector": null,
+///     "claims": {...},
+///     "reference_data": {...},
+///     "error": <why the evidence is rejected>
+/// }
+/// ```
+pub async fn dry_run(
+    request: web::Json<DryRunRequest>,
+    cocoas: web::Data<Arc<RwLock<AttestationService>>>,
+) -> Result<HttpResponse> {
+    info!("Dry run API called.");
+    let request = request.into_inner();
+
+    debug!("dry run: {request:#?}");
+    let evidence = match (request.attestation, request.archive_id) {
+        (Some(attestation), None) => {
+            let attestation = parse_request(attestation)?;
+            DryRunEvidence::Evidence {
+                evidence: attestation.evidence,
+                tee: attestation.tee,
+                runtime_data: attestation.runtime_data,
+                runtime_data_hash_algorithm: attestation.runtime_data_hash_algorithm,
+                init_data: attestation.init_data,
+                init_data_hash_algorithm: attestation.init_data_hash_algorithm,
+            }
+        }
+        (None, Some(id)) => DryRunEvidence::Archived(id),
+        _ => {
+            return Ok(HttpResponse::BadRequest()
+                .body("one of `attestation` and `archive_id` is required"))
+        }
+    };
+
+    let report = cocoas
+        .read()
+        .await
+        .dry_run(evidence, request.policy)
+        .await
+        .context("dry run")?;
+    let body = serde_json::to_string(&report).context("serialize response body")?;
+    Ok(HttpResponse::Ok().body(body))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RegisterInitDataInput {
+    workload: String,
+    digests: Vec<String>,
+}
+
+/// POST /init-data
+///
+/// Register the hex `digests` of the init data `workload` may be launched
+/// with, replacing the ones registered before.
+pub async fn register_init_data(
+    input: web::Json<RegisterInitDataInput>,
+    cocoas: web::Data<Arc<RwLock<AttestationService>>>,
+) -> Result<HttpResponse> {
+    info!("Register Init Data API called.");
+    let input = input.into_inner();
+
+    debug!("register init data: {input:#?}");
+    cocoas
+        .write()
+        .await
+        .register_init_data(input.workload, input.digests)
+        .await
+        .context("register init data")?;
+
+    Ok(HttpResponse::Ok().body(""))
+}
+
+/// GET /init-data
+///
+/// The returned body would look like
+/// ```json
+/// {
+///     <workload-1>: [<digest-1>, <digest-2>],
+///     ...
+/// }
+/// ```
+pub async fn get_init_data(
+    cocoas: web::Data<Arc<RwLock<AttestationService>>>,
+) -> Result<HttpResponse> {
+    info!("get init data.");
+
+    let workloads = cocoas.read().await.list_init_data();
+    let body = serde_json::to_string(&workloads).context("serialize response body")?;
+    Ok(HttpResponse::Ok().body(body))
+}
+
+/// DELETE /init-data/{workload}
+pub async fn unregister_init_data(
+    workload: web::Path<String>,
+    cocoas: web::Data<Arc<RwLock<AttestationService>>>,
+) -> Result<HttpResponse> {
+    info!("Unregister Init Data API called.");
+
+    let removed = cocoas
+        .write()
+        .await
+        .unregister_init_data(&workload)
+        .await
+        .context("unregister init data")?;
+    match removed {
+        true => Ok(HttpResponse::Ok().body("")),
+        false => Ok(HttpResponse::NotFound().body(format!("no workload {workload}"))),
+    }
+}
+
+/// POST /sgx-identities
+///
+/// Allow the SGX enclaves of the identity, whose payload is like
+/// ```json
+/// {
+///     "mr_signer": "83d719e77deaca1470f6baf62a4d774303c899db69020f9c70ee1dfc08c7ce9e",
+///     "isv_prod_id": 1
+/// }
+/// ```
+pub async fn register_sgx_identity(
+    identity: web::Json<EnclaveIdentity>,
+    cocoas: web::Data<Arc<RwLock<AttestationService>>>,
+) -> Result<HttpResponse> {
+    info!("Register SGX Identity API called.");
+
+    debug!("register SGX identity: {identity:#?}");
+    cocoas
+        .write()
+        .await
+        .register_sgx_identity(identity.into_inner())
+        .await
+        .context("register SGX identity")?;
+
+    Ok(HttpResponse::Ok().body(""))
+}
+
+/// GET /sgx-identities
+///
+/// The returned body is the list of the allowed SGX enclave identities.
+pub async fn get_sgx_identities(
+    cocoas: web::Data<Arc<RwLock<AttestationService>>>,
+) -> Result<HttpResponse> {
+    info!("get SGX identities.");
+
+    let identities = cocoas.read().await.list_sgx_identities();
+    let body = serde_json::to_string(&identities).context("serialize response body")?;
+    Ok(HttpResponse::Ok().body(body))
+}
+
+/// DELETE /sgx-identities
+///
+/// Remove the SGX enclave identity of the payload, like the one of POST.
+pub async fn unregister_sgx_identity(
+    identity: web::Json<EnclaveIdentity>,
+    cocoas: web::Data<Arc<RwLock<AttestationService>>>,
+) -> Result<HttpResponse> {
+    info!("Unregister SGX Identity API called.");
+
+    let removed = cocoas
+        .write()
+        .await
+        .unregister_sgx_identity(identity.into_inner())
+        .await
+        .context("unregister SGX identity")?;
+    match removed {
+        true => Ok(HttpResponse::Ok().body("")),
+        false => Ok(HttpResponse::NotFound().body("no such SGX identity")),
+    }
+}
+
+fn to_se_material_kind(kind: &str) -> anyhow::Result<SeMaterialKind> {
+    SeMaterialKind::from_str(kind).map_err(|_| anyhow!("unknown SE material kind `{kind}`"))
+}
+
+/// POST /se-materials/{kind}/{name}
+///
+/// Add the PEM or DER encoded SE material of the body, replacing the one of
+/// the same name. `kind` is one of `host_key_document`, `certificate` and
+/// `crl`. A host-key document must be valid and trusted, and is used from
+/// the next SE challenge on.
+pub async fn add_se_material(
+    path: web::Path<(String, String)>,
+    body: web::Bytes,
+    cocoas: web::Data<Arc<RwLock<AttestationService>>>,
+) -> Result<HttpResponse> {
+    info!("Add SE Material API called.");
+    let (kind, name) = path.into_inner();
+
+    let kind = to_se_material_kind(&kind)?;
+    cocoas
+        .read()
+        .await
+        .add_se_material(kind, &name, &body)
+        .context("add SE material")?;
+
+    Ok(HttpResponse::Ok().body(""))
+}
+
+/// GET /se-materials/{kind}
+///
+/// The returned body would look like
+/// ```json
+/// [
+///     {"name": <name-1>, "valid_from": <time>, "valid_until": <time>},
+///     ...
+/// ]
+/// ```
+pub async fn get_se_materials(
+    kind: web::Path<String>,
+    cocoas: web::Data<Arc<RwLock<AttestationService>>>,
+) -> Result<HttpResponse> {
+    info!("get SE materials.");
+
+    let kind = to_se_material_kind(&kind)?;
+    let materials = cocoas
+        .read()
+        .await
+        .list_se_materials(kind)
+        .context("list SE materials")?;
+    let body = serde_json::to_string(&materials).context("serialize response body")?;
+    Ok(HttpResponse::Ok().body(body))
+}
+
+/// DELETE /se-materials/{kind}/{name}
+pub async fn remove_se_material(
+    path: web::Path<(String, String)>,
+    cocoas: web::Data<Arc<RwLock<AttestationService>>>,
+) -> Result<HttpResponse> {
+    info!("Remove SE Material API called.");
+    let (kind, name) = path.into_inner();
+
+    let kind = to_se_material_kind(&kind)?;
+    let removed = cocoas
+        .read()
+        .await
+        .remove_se_material(kind, &name)
+        .context("remove SE material")?;
+    match removed {
+        true => Ok(HttpResponse::Ok().body("")),
+        false => Ok(HttpResponse::NotFound().body(format!("no SE material {name}"))),
+    }
+}
+
+/// GET /metrics
+pub async fn metrics() -> Result<HttpResponse> {
+    let body = attestation_service::metrics::gather().context("gather metrics")?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body))
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RemovePolicyRequest {
     pub policy_ids: Vec<String>,
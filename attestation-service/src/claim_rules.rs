@@ -0,0 +1,137 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Rules transforming the flattened claims of the evidence before they are
+//! appraised by the policies and issued in the token, so that both see
+//! stable and friendly claim names.
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// A rule of the `claim_rules` of the configuration, e.g.
+/// ```json
+/// [
+///     { "rename": { "from": "snp.measurement", "to": "measurement" } },
+///     { "drop": { "claim": "snp.platform_*" } },
+///     {
+///         "derive": {
+///             "from": "measurement",
+///             "to": "workload",
+///             "table": { "<measurement>": "nginx" },
+///             "default": "unknown"
+///         }
+///     }
+/// ]
+/// ```
+/// A claim ending with `*` matches all the claims with the same prefix. The
+/// rules are applied in order.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClaimRule {
+    /// Rename the claim `from` into `to`.
+    Rename { from: String, to: String },
+
+    /// Drop the matching claims.
+    Drop { claim: String },
+
+    /// Set the claim `to` to the value that `table` maps the value of the
+    /// claim `from` into, or to `default` if the table has no such value.
+    Derive {
+        from: String,
+        to: String,
+        table: HashMap<String, Value>,
+        #[serde(default)]
+        default: Option<Value>,
+    },
+}
+
+/// Apply `rules` to the flattened `claims`.
+pub fn apply_claim_rules(rules: &[ClaimRule], claims: &mut Map<String, Value>) {
+    for rule in rules {
+        match rule {
+            ClaimRule::Rename { from, to } => {
+                if let Some(value) = claims.remove(from) {
+                    claims.insert(to.clone(), value);
+                }
+            }
+            ClaimRule::Drop { claim } => match claim.strip_suffix('*') {
+                Some(prefix) => claims.retain(|key, _| !key.starts_with(prefix)),
+                None => {
+                    claims.remove(claim);
+                }
+            },
+            ClaimRule::Derive {
+                from,
+                to,
+                table,
+                default,
+            } => {
+                let Some(value) = claims.get(from) else {
+                    continue;
+                };
+                let key = match value {
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                if let Some(derived) = table.get(&key).or(default.as_ref()) {
+                    claims.insert(to.clone(), derived.clone());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn apply_rules() {
+        let rules: Vec<ClaimRule> = serde_json::from_value(json!([
+            { "rename": { "from": "snp.measurement", "to": "measurement" } },
+            { "drop": { "claim": "snp.platform_*" } },
+            { "drop": { "claim": "snp.policy_debug_allowed" } },
+            {
+                "derive": {
+                    "from": "measurement",
+                    "to": "workload",
+                    "table": { "abcd": "nginx" },
+                }
+            },
+            {
+                "derive": {
+                    "from": "snp.reported_tcb_snp",
+                    "to": "snp_firmware",
+                    "table": {},
+                    "default": "unknown"
+                }
+            },
+        ]))
+        .unwrap();
+        let mut claims = json!({
+            "snp.measurement": "abcd",
+            "snp.platform_smt_enabled": "true",
+            "snp.platform_tsme_enabled": "false",
+            "snp.policy_debug_allowed": "false",
+            "snp.reported_tcb_snp": "8",
+        })
+        .as_object()
+        .unwrap()
+        .clone();
+
+        apply_claim_rules(&rules, &mut claims);
+        assert_eq!(
+            Value::Object(claims),
+            json!({
+                "measurement": "abcd",
+                "workload": "nginx",
+                "snp.reported_tcb_snp": "8",
+                "snp_firmware": "unknown",
+            })
+        );
+    }
+}
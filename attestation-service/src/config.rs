@@ -1,7 +1,14 @@
+use crate::archive::ArchiveConfig;
+use crate::claim_rules::ClaimRule;
+#[cfg(feature = "external-verifier")]
+use crate::external_verifier::ExternalVerifierConfig;
+use crate::min_tcb::MinTcbConfig;
 use crate::rvps::RvpsConfig;
 use crate::token::{AttestationTokenBrokerType, AttestationTokenConfig};
+use crate::verification_cache::VerificationCacheConfig;
 
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use thiserror::Error;
@@ -25,10 +32,49 @@ pub struct Config {
     ///
     /// Possible values:
     /// * `Simple`
+    /// * `Ear`
     pub attestation_token_broker: AttestationTokenBrokerType,
 
     /// The Attestation Result Token Broker Config
     pub attestation_token_config: AttestationTokenConfig,
+
+    /// The policy ids of the evidence of a TEE type, e.g. `snp`, when no policy
+    /// id is given with the evidence. The `default` policy is used for the TEE
+    /// types without policy ids.
+    #[serde(default)]
+    pub tee_policies: HashMap<String, Vec<String>>,
+
+    /// Rules renaming, dropping or deriving the claims of the evidence
+    /// before they are appraised and issued in the token.
+    #[serde(default)]
+    pub claim_rules: Vec<ClaimRule>,
+
+    /// Cache of the claims of the verified evidence. If set, identical
+    /// evidence verified within the TTL is not verified again.
+    #[serde(default)]
+    pub verification_cache: Option<VerificationCacheConfig>,
+
+    /// Archive of the appraisals. If set, the evidence, the inputs of the
+    /// policies and the outcome of each attestation are archived.
+    #[serde(default)]
+    pub archive: Option<ArchiveConfig>,
+
+    /// Minimum TCB levels of the platforms. The evidence of a platform
+    /// below them is rejected before the policies are evaluated.
+    #[serde(default)]
+    pub min_tcb: Option<MinTcbConfig>,
+
+    /// Configuration of Intel Trust Authority. If set, the appraisal of the
+    /// TDX and SGX quotes is delegated to it instead of the local verifiers.
+    #[cfg(feature = "intel-trust-authority-verifier")]
+    #[serde(default)]
+    pub intel_trust_authority: Option<verifier::intel_trust_authority::IntelTrustAuthorityConfig>,
+
+    /// External verifier services. The evidence of the TEE types that they
+    /// advertise is appraised by them instead of the built-in verifiers.
+    #[cfg(feature = "external-verifier")]
+    #[serde(default)]
+    pub external_verifiers: Vec<ExternalVerifierConfig>,
 }
 
 #[derive(Error, Debug)]
@@ -56,6 +102,15 @@ impl Default for Config {
             rvps_config: RvpsConfig::default(),
             attestation_token_broker: AttestationTokenBrokerType::Simple,
             attestation_token_config: AttestationTokenConfig::default(),
+            tee_policies: HashMap::new(),
+            claim_rules: Vec::new(),
+            verification_cache: None,
+            archive: None,
+            min_tcb: None,
+            #[cfg(feature = "intel-trust-authority-verifier")]
+            intel_trust_authority: None,
+            #[cfg(feature = "external-verifier")]
+            external_verifiers: Vec::new(),
         }
     }
 }
@@ -73,8 +128,35 @@ impl TryFrom<&Path> for Config {
     ///        "attestation_token_broker": "Simple",
     ///        "attestation_token_config": {
     ///            "duration_min": 5
-    ///        }
+    ///        },
+    ///        "tee_policies": {
+    ///            "snp": ["snp-policy"],
+    ///            "tdx": ["tdx-policy"]
+    ///        },
+    ///        "claim_rules": [
+    ///            { "rename": { "from": "snp.measurement", "to": "measurement" } }
+    ///        ],
+    ///        "verification_cache": {
+    ///            "ttl_secs": 30
+    ///        },
+    ///        "archive": {
+    ///            "store_type": "LocalFs",
+    ///            "store_config": {
+    ///                "dir_path": "/var/lib/attestation-service/archive"
+    ///            }
+    ///        },
+    ///        "intel_trust_authority": {
+    ///            "base_url": "https://api.trustauthority.intel.com",
+    ///            "api_key": "...",
+    ///            "certs_file": "/etc/trustee/ita-certs.json"
+    ///        },
+    ///        "external_verifiers": [
+    ///            { "address": "http://127.0.0.1:50005" }
+    ///        ]
     ///    }
+    ///
+    /// `tee_policies`, `claim_rules`, `verification_cache` and `archive` are optional. `intel_trust_authority` is optional and requires the
+    /// `intel-trust-authority-verifier` feature. `external_verifiers` is optional and requires the `external-verifier` feature.
     type Error = ConfigError;
     fn try_from(config_path: &Path) -> Result<Self, ConfigError> {
         let file = File::open(config_path)?;
@@ -0,0 +1,57 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Dry runs of the appraisal. An evidence is appraised against a candidate
+//! policy without issuing a token nor archiving the appraisal, so that the
+//! operators can test a policy change against real evidence before setting
+//! it.
+
+use crate::{Data, HashAlgorithm, Tee};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// The evidence appraised by a dry run.
+pub enum DryRunEvidence {
+    /// An evidence, verified like by `AttestationService::evaluate`.
+    Evidence {
+        evidence: Vec<u8>,
+        tee: Tee,
+        runtime_data: Option<Data>,
+        runtime_data_hash_algorithm: HashAlgorithm,
+        init_data: Option<Data>,
+        init_data_hash_algorithm: HashAlgorithm,
+    },
+
+    /// The id of an archived appraisal, whose archived claims are appraised
+    /// again without verifying the evidence.
+    Archived(String),
+}
+
+/// The outcome of a dry run.
+#[derive(Clone, Debug, Default, Serialize, PartialEq)]
+pub struct DryRunReport {
+    /// Whether the candidate policy affirms the evidence.
+    pub allowed: bool,
+
+    /// The digest of the candidate policy (using **Sha384**), if it affirms
+    /// the evidence.
+    pub policy_hash: Option<String>,
+
+    /// The trustworthiness vector set by the candidate policy, if any.
+    pub trust_vector: Option<Map<String, Value>>,
+
+    /// Flattened claims of the evidence given to the policy, if the evidence
+    /// was verified.
+    pub claims: Option<Value>,
+
+    /// Reference values of the claims given to the policy.
+    pub reference_data: Option<Value>,
+
+    /// Vendor advisories of the reference values given to the policy.
+    pub advisories: Option<Value>,
+
+    /// Why the evidence was rejected, if it was.
+    pub error: Option<String>,
+}
@@ -0,0 +1,249 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Verifiers running as external services, so that the verifiers of new TEE
+//! types can be shipped apart from the attestation service. Such a service
+//! implements the `ExternalVerifier` gRPC service of `protos/verifier.proto`
+//! and advertises the TEE types it appraises.
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use kbs_types::Tee;
+use log::info;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use tonic::transport::Channel;
+use verifier::{InitDataHash, ReportData, TeeEvidenceParsedClaim, Verifier};
+
+use self::verifier_api::{
+    external_verifier_client::ExternalVerifierClient, EvaluateRequest,
+    SupplementalChallengeRequest, VerifierInfoRequest,
+};
+
+pub mod verifier_api {
+    tonic::include_proto!("verifier");
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ExternalVerifierConfig {
+    /// Address of the service, e.g. `http://127.0.0.1:50005`.
+    pub address: String,
+}
+
+#[derive(Clone)]
+pub struct ExternalVerifier {
+    address: String,
+    tee: String,
+    client: ExternalVerifierClient<Channel>,
+}
+
+/// Connect the external verifiers of `configs`, and return them by the TEE
+/// type they advertise.
+pub async fn connect_external_verifiers(
+    configs: &[ExternalVerifierConfig],
+) -> Result<HashMap<String, ExternalVerifier>> {
+    let mut verifiers = HashMap::new();
+    for config in configs {
+        let address = &config.address;
+        let mut client = ExternalVerifierClient::connect(address.clone())
+            .await
+            .with_context(|| format!("connect external verifier {address}"))?;
+        let info = client
+            .get_verifier_info(VerifierInfoRequest {})
+            .await
+            .map_err(|e| anyhow!("get info of external verifier {address}: {}", e.message()))?
+            .into_inner();
+        if info.tees.is_empty() {
+            bail!("external verifier {address} advertises no TEE type");
+        }
+
+        for tee in info.tees {
+            serde_json::from_value::<Tee>(Value::String(tee.clone()))
+                .map_err(|_| anyhow!("external verifier {address} advertises unknown TEE {tee}"))?;
+            if verifiers.contains_key(&tee) {
+                bail!("TEE {tee} is advertised by several external verifiers");
+            }
+
+            info!("External verifier {address} registered for {tee}.");
+            verifiers.insert(
+                tee.clone(),
+                ExternalVerifier {
+                    address: address.clone(),
+                    tee,
+                    client: client.clone(),
+                },
+            );
+        }
+    }
+
+    Ok(verifiers)
+}
+
+#[async_trait]
+impl Verifier for ExternalVerifier {
+    async fn evaluate(
+        &self,
+        evidence: &[u8],
+        expected_report_data: &ReportData,
+        expected_init_data_hash: &InitDataHash,
+    ) -> Result<TeeEvidenceParsedClaim> {
+        let request = EvaluateRequest {
+            tee: self.tee.clone(),
+            evidence: evidence.to_vec(),
+            report_data: match expected_report_data {
+                ReportData::Value(data) => data.to_vec(),
+                ReportData::NotProvided => Vec::new(),
+            },
+            init_data_hash: match expected_init_data_hash {
+                InitDataHash::Value(data) => data.to_vec(),
+                InitDataHash::NotProvided => Vec::new(),
+            },
+        };
+        let response = self
+            .client
+            .clone()
+            .evaluate(request)
+            .await
+            .map_err(|e| anyhow!("external verifier {}: {}", self.address, e.message()))?
+            .into_inner();
+
+        let claims: TeeEvidenceParsedClaim = serde_json::from_str(&response.claims)
+            .with_context(|| format!("parse claims of external verifier {}", self.address))?;
+        if !claims.is_object() {
+            bail!("claims of external verifier {} are not a map", self.address);
+        }
+
+        Ok(claims)
+    }
+
+    async fn generate_supplemental_challenge(&self, tee_parameters: String) -> Result<String> {
+        let request = SupplementalChallengeRequest {
+            tee: self.tee.clone(),
+            tee_parameters,
+        };
+        let response = self
+            .client
+            .clone()
+            .generate_supplemental_challenge(request)
+            .await
+            .map_err(|e| anyhow!("external verifier {}: {}", self.address, e.message()))?
+            .into_inner();
+
+        Ok(response.challenge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verifier_api::external_verifier_server::{
+        ExternalVerifier as ExternalVerifierService, ExternalVerifierServer,
+    };
+    use super::verifier_api::{
+        EvaluateResponse, SupplementalChallengeResponse, VerifierInfoResponse,
+    };
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+    use tonic::{Request, Response, Status};
+
+    struct SampleVerifier;
+
+    #[tonic::async_trait]
+    impl ExternalVerifierService for SampleVerifier {
+        async fn get_verifier_info(
+            &self,
+            _request: Request<VerifierInfoRequest>,
+        ) -> Result<Response<VerifierInfoResponse>, Status> {
+            Ok(Response::new(VerifierInfoResponse {
+                tees: vec!["sample".into()],
+            }))
+        }
+
+        async fn evaluate(
+            &self,
+            request: Request<EvaluateRequest>,
+        ) -> Result<Response<EvaluateResponse>, Status> {
+            let request = request.into_inner();
+            if request.evidence != b"evidence" {
+                return Err(Status::invalid_argument("illegal evidence"));
+            }
+
+            let claims = json!({
+                "tee": request.tee,
+                "report_data": hex::encode(request.report_data),
+                "init_data": hex::encode(request.init_data_hash),
+            });
+            Ok(Response::new(EvaluateResponse {
+                claims: claims.to_string(),
+            }))
+        }
+
+        async fn generate_supplemental_challenge(
+            &self,
+            request: Request<SupplementalChallengeRequest>,
+        ) -> Result<Response<SupplementalChallengeResponse>, Status> {
+            Ok(Response::new(SupplementalChallengeResponse {
+                challenge: format!("challenge of {}", request.into_inner().tee_parameters),
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn evaluate_external_verifier() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(ExternalVerifierServer::new(SampleVerifier))
+                .serve(addr),
+        );
+
+        let configs = [ExternalVerifierConfig {
+            address: format!("http://{addr}"),
+        }];
+        let mut verifiers = None;
+        for _ in 0..50 {
+            if let Ok(connected) = connect_external_verifiers(&configs).await {
+                verifiers = Some(connected);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let verifiers = verifiers.expect("external verifier not reachable");
+        let verifier = &verifiers["sample"];
+
+        let claims = verifier
+            .evaluate(
+                b"evidence",
+                &ReportData::Value(&[1, 2]),
+                &InitDataHash::NotProvided,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            claims,
+            json!({ "tee": "sample", "report_data": "0102", "init_data": "" })
+        );
+
+        let e = verifier
+            .evaluate(
+                b"forged",
+                &ReportData::NotProvided,
+                &InitDataHash::NotProvided,
+            )
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("illegal evidence"));
+
+        let challenge = verifier
+            .generate_supplemental_challenge("params".into())
+            .await
+            .unwrap();
+        assert_eq!(challenge, "challenge of params");
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Registry of the init-data digests expected for each workload. The
+//! digest the TEE was launched with, i.e. the `init_data` claim that the
+//! verifiers parse from the SNP HOSTDATA, the TDX MRCONFIGID or the SGX
+//! CONFIGID, is matched against the registered digests so that the policies
+//! can check the `init_data_verified` claim.
+
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::debug;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+const REGISTRY_FILE: &str = "init_data.json";
+
+/// Registered init-data digests, persisted in the work dir of the AS.
+pub struct InitDataRegistry {
+    path: PathBuf,
+    workloads: BTreeMap<String, Vec<String>>,
+}
+
+impl InitDataRegistry {
+    pub fn new(work_dir: &Path) -> Result<Self> {
+        let path = work_dir.join(REGISTRY_FILE);
+        let workloads = match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .with_context(|| format!("parse init data registry {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self { path, workloads })
+    }
+
+    /// The hex digests registered for each workload.
+    pub fn workloads(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.workloads
+    }
+
+    /// Allow the evidence launched with one of the hex `digests` for
+    /// `workload`, replacing the digests registered before.
+    pub async fn register(&mut self, workload: String, digests: Vec<String>) -> Result<()> {
+        if workload.is_empty() {
+            bail!("empty workload name");
+        }
+
+        if digests.is_empty() {
+            bail!("no init data digest for workload {workload}");
+        }
+
+        let mut normalized = Vec::with_capacity(digests.len());
+        for digest in digests {
+            let bytes = hex::decode(&digest)
+                .with_context(|| format!("illegal init data digest {digest}"))?;
+            if bytes.is_empty() || bytes.iter().all(|b| *b == 0) {
+                bail!("init data digest of workload {workload} must not be zero");
+            }
+
+            normalized.push(hex::encode(bytes));
+        }
+
+        let mut workloads = self.workloads.clone();
+        workloads.insert(workload, normalized);
+        self.persist(workloads).await
+    }
+
+    /// Remove the digests of `workload`. Return whether it was registered.
+    pub async fn unregister(&mut self, workload: &str) -> Result<bool> {
+        let mut workloads = self.workloads.clone();
+        if workloads.remove(workload).is_none() {
+            return Ok(false);
+        }
+
+        self.persist(workloads).await?;
+        Ok(true)
+    }
+
+    async fn persist(&mut self, workloads: BTreeMap<String, Vec<String>>) -> Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(&workloads)?).await?;
+        fs::rename(&tmp_path, &self.path)
+            .await
+            .context("persist init data registry")?;
+        self.workloads = workloads;
+        Ok(())
+    }
+
+    /// Add the `init_data_verified` claim, and the `init_data_workloads`
+    /// whose digests match the `init_data` claim of the evidence.
+    pub fn verify_claims(&self, claims: &mut Map<String, Value>) {
+        let workloads: Vec<_> = claims
+            .get("init_data")
+            .and_then(Value::as_str)
+            .and_then(decode_init_data)
+            .map(|init_data| {
+                self.workloads
+                    .iter()
+                    .filter(|(_, digests)| digests.iter().any(|digest| matches(&init_data, digest)))
+                    .map(|(workload, _)| workload.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        debug!("init data of the workloads {workloads:?}");
+
+        claims.insert("init_data_verified".into(), json!(!workloads.is_empty()));
+        claims.insert("init_data_workloads".into(), json!(workloads));
+    }
+}
+
+/// The `init_data` claims are hex encoded, but for the sample TEE whose
+/// claim is base64 encoded.
+fn decode_init_data(init_data: &str) -> Option<Vec<u8>> {
+    hex::decode(init_data)
+        .or_else(|_| STANDARD.decode(init_data))
+        .ok()
+}
+
+/// The digests shorter than the init data field of the TEE, e.g. a SHA-256
+/// digest in the 48 bytes of the TDX MRCONFIGID, are padded with zeros.
+fn matches(init_data: &[u8], digest: &str) -> bool {
+    let Ok(digest) = hex::decode(digest) else {
+        return false;
+    };
+
+    digest.len() <= init_data.len()
+        && init_data[..digest.len()] == digest[..]
+        && init_data[digest.len()..].iter().all(|b| *b == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn verify_init_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut registry = InitDataRegistry::new(dir.path()).unwrap();
+        let digest = "ab".repeat(32);
+        assert!(registry
+            .register("pod".into(), vec!["00".repeat(32)])
+            .await
+            .is_err());
+        registry
+            .register("pod".into(), vec![digest.to_uppercase()])
+            .await
+            .unwrap();
+
+        // Reloaded from the work dir.
+        let mut registry = InitDataRegistry::new(dir.path()).unwrap();
+        assert_eq!(registry.workloads()["pod"], vec![digest.clone()]);
+
+        let mut claims = Map::new();
+        claims.insert(
+            "init_data".into(),
+            json!(format!("{digest}{}", "00".repeat(16))),
+        );
+        registry.verify_claims(&mut claims);
+        assert_eq!(claims["init_data_verified"], json!(true));
+        assert_eq!(claims["init_data_workloads"], json!(["pod"]));
+
+        claims.insert("init_data".into(), json!("cd".repeat(48)));
+        registry.verify_claims(&mut claims);
+        assert_eq!(claims["init_data_verified"], json!(false));
+
+        assert!(registry.unregister("pod").await.unwrap());
+        assert!(!registry.unregister("pod").await.unwrap());
+        assert!(registry.workloads().is_empty());
+    }
+}
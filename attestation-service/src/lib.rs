@@ -3,31 +3,64 @@
 //! # Features
 //! - `rvps-grpc`: The AS will connect a remote RVPS.
 //! - `rvps-builtin`: The AS will integrate RVPS functionalities itself.
+//! - `external-verifier`: The AS will delegate the appraisal of the evidence
+//! of some TEE types to external verifier services.
+//! - `otel`: The AS binaries will export their traces over OTLP.
 
+pub mod archive;
+mod claim_rules;
 pub mod config;
+pub mod dry_run;
+#[cfg(feature = "external-verifier")]
+mod external_verifier;
+mod init_data;
+pub mod metrics;
+pub mod min_tcb;
 pub mod policy_engine;
 mod rvps;
+pub mod sgx_identity;
+pub mod telemetry;
 mod token;
 mod utils;
+mod verification_cache;
 
 use crate::token::AttestationTokenBroker;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use archive::{AppraisalRecord, ArchiveQuery, ArchiveStore};
 use config::Config;
+use dry_run::{DryRunEvidence, DryRunReport};
 pub use kbs_types::{Attestation, Tee};
 use log::{debug, info};
+use opentelemetry::trace::get_active_span;
+use opentelemetry::KeyValue;
 use policy_engine::{PolicyEngine, PolicyEngineType};
+pub use reference_value_provider_service::query::{ReferenceValuePage, ReferenceValueQuery};
+pub use reference_value_provider_service::Advisory;
 use rvps::{RvpsApi, RvpsError};
-use serde_json::{json, Value};
+use serde_json::{json, Map, Value};
 use serde_variant::to_variant_name;
+use sgx_identity::{EnclaveIdentity, EnclaveIdentityRegistry};
 use sha2::{Digest, Sha256, Sha384, Sha512};
-use std::{collections::HashMap, str::FromStr};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    time::Instant,
+};
 use strum::{AsRefStr, EnumString};
 use thiserror::Error;
 use tokio::fs;
-use verifier::{InitDataHash, ReportData};
+pub use verifier::se::ibmse::{SeMaterialInfo, SeMaterialKind};
+use verifier::{InitDataHash, ReportData, TeeEvidenceParsedClaim, Verifier};
 
-use crate::utils::flatten_claims;
+use crate::claim_rules::apply_claim_rules;
+use crate::init_data::InitDataRegistry;
+use crate::telemetry::traced;
+use crate::utils::{flatten_claims, flatten_device_claims, split_composite_evidence};
+use crate::verification_cache::VerificationCache;
+
+/// The policy used when no policy id is given nor configured for the TEE.
+const DEFAULT_POLICY_ID: &str = "default";
 
 /// Hash algorithms used to calculate runtime/init data binding
 #[derive(EnumString, AsRefStr)]
@@ -97,6 +130,12 @@ pub struct AttestationService {
     policy_engine: Box<dyn PolicyEngine + Send + Sync>,
     rvps: Box<dyn RvpsApi + Send + Sync>,
     token_broker: Box<dyn AttestationTokenBroker + Send + Sync>,
+    verification_cache: Option<VerificationCache>,
+    archive: Option<Box<dyn ArchiveStore + Send + Sync>>,
+    init_data_registry: InitDataRegistry,
+    sgx_identities: EnclaveIdentityRegistry,
+    #[cfg(feature = "external-verifier")]
+    external_verifiers: HashMap<String, external_verifier::ExternalVerifier>,
 }
 
 impl AttestationService {
@@ -120,11 +159,49 @@ impl AttestationService {
             .attestation_token_broker
             .to_token_broker(config.attestation_token_config.clone())?;
 
+        for (tee, policy_ids) in &config.tee_policies {
+            serde_json::from_value::<Tee>(Value::String(tee.clone()))
+                .map_err(|_| anyhow!("tee_policies: unknown TEE type {tee}"))?;
+            if policy_ids.is_empty() {
+                return Err(anyhow!("tee_policies: no policy id for TEE type {tee}").into());
+            }
+        }
+
+        let verification_cache = config
+            .verification_cache
+            .as_ref()
+            .map(VerificationCache::new);
+
+        let archive = match &config.archive {
+            Some(archive) => Some(
+                archive
+                    .store_type
+                    .to_store(archive.store_config.clone())
+                    .await?,
+            ),
+            None => None,
+        };
+
+        let init_data_registry = InitDataRegistry::new(&config.work_dir)?;
+        let sgx_identities = EnclaveIdentityRegistry::new(&config.work_dir)?;
+
+        #[cfg(feature = "external-verifier")]
+        let external_verifiers =
+            external_verifier::connect_external_verifiers(&config.external_verifiers).await?;
+
+        metrics::register();
+
         Ok(Self {
             _config: config,
             policy_engine,
             rvps,
             token_broker,
+            verification_cache,
+            archive,
+            init_data_registry,
+            sgx_identities,
+            #[cfg(feature = "external-verifier")]
+            external_verifiers,
         })
     }
 
@@ -154,7 +231,8 @@ impl AttestationService {
     /// Evaluate Attestation Evidence.
     /// Issue an attestation results token which contain TCB status and TEE public key. Input parameters:
     /// - `evidence`: TEE evidence bytes. This might not be the raw hardware evidence bytes. Definitions
-    /// are in `verifier` crate.
+    /// are in `verifier` crate. A composite evidence also carries the evidence of the devices attached
+    /// to the TEE, whose claims are prefixed with the name of their verifier.
     /// - `tee`: concrete TEE type
     /// - `runtime_data`: These data field will be used to check against the counterpart inside the evidence.
     /// The concrete way of checking is decide by the enum type. If this parameter is set `None`, the comparation
@@ -166,7 +244,11 @@ impl AttestationService {
     /// `init_data`.
     /// - `policy_ids`: The policy ids that used to check this evidence. Any check fails against a policy will
     /// not cause this function to return error. The result check against every policy will be included inside
-    /// the finally Token returned by CoCo-AS.
+    /// the finally Token returned by CoCo-AS. If it is empty, the policy ids configured for `tee` in
+    /// `tee_policies` are used, or else `default`.
+    ///
+    /// If the archive is configured, the appraisal is archived whether the evidence is affirmed or
+    /// rejected, and no token is issued if it can not be archived.
     #[allow(clippy::too_many_arguments)]
     pub async fn evaluate(
         &self,
@@ -178,44 +260,109 @@ impl AttestationService {
         init_data_hash_algorithm: HashAlgorithm,
         policy_ids: Vec<String>,
     ) -> Result<String> {
-        let verifier = verifier::to_verifier(&tee)?;
-
-        let (report_data, runtime_data_claims) =
-            parse_data(runtime_data, &runtime_data_hash_algorithm).context("parse runtime data")?;
-
-        let report_data = match &report_data {
-            Some(data) => ReportData::Value(data),
-            None => ReportData::NotProvided,
+        let policy_ids = match policy_ids.is_empty() {
+            true => self.tee_policy_ids(&tee)?,
+            false => policy_ids,
         };
 
-        let (init_data, init_data_claims) =
-            parse_data(init_data, &init_data_hash_algorithm).context("parse init data")?;
-
-        let init_data_hash = match &init_data {
-            Some(data) => InitDataHash::Value(data),
-            None => InitDataHash::NotProvided,
-        };
+        let tee_name = to_variant_name(&tee)?;
+        let start = Instant::now();
+
+        let attributes = vec![KeyValue::new("as.tee", tee_name)];
+        traced("attestation_service.evaluate", attributes, async move {
+            let Some(archive) = &self.archive else {
+                let result = self
+                    .appraise(
+                        evidence,
+                        tee,
+                        runtime_data,
+                        runtime_data_hash_algorithm,
+                        init_data,
+                        init_data_hash_algorithm,
+                        policy_ids,
+                        None,
+                    )
+                    .await;
+                metrics::observe_attestation(tee_name, start, &result);
+                return result;
+            };
+
+            let mut record = AppraisalRecord::new(tee_name, &evidence, &policy_ids);
+            let result = self
+                .appraise(
+                    evidence,
+                    tee,
+                    runtime_data,
+                    runtime_data_hash_algorithm,
+                    init_data,
+                    init_data_hash_algorithm,
+                    policy_ids,
+                    Some(&mut record),
+                )
+                .await;
+            metrics::observe_attestation(tee_name, start, &result);
+            if let Err(e) = &result {
+                record.error = Some(format!("{e:#}"));
+            }
 
-        let claims_from_tee_evidence = verifier
-            .evaluate(&evidence, &report_data, &init_data_hash)
-            .await
-            .map_err(|e| anyhow!("Verifier evaluate failed: {e:?}"))?;
-        info!("{:?} Verifier/endorsement check passed.", tee);
+            archive
+                .put(&record)
+                .await
+                .context("archive the appraisal")?;
+            info!("Appraisal {} archived.", record.id);
+            result
+        })
+        .await
+    }
 
-        let flattened_claims = flatten_claims(tee, &claims_from_tee_evidence)?;
-        debug!("flattened_claims: {:#?}", flattened_claims);
+    /// Appraise the evidence and issue the token, filling `record` with the
+    /// inputs and the outcome of the policies.
+    #[allow(clippy::too_many_arguments)]
+    async fn appraise(
+        &self,
+        evidence: Vec<u8>,
+        tee: Tee,
+        runtime_data: Option<Data>,
+        runtime_data_hash_algorithm: HashAlgorithm,
+        init_data: Option<Data>,
+        init_data_hash_algorithm: HashAlgorithm,
+        policy_ids: Vec<String>,
+        mut record: Option<&mut AppraisalRecord>,
+    ) -> Result<String> {
+        let (flattened_claims, runtime_data_claims, init_data_claims) = self
+            .verify_claims(
+                evidence,
+                tee,
+                runtime_data,
+                runtime_data_hash_algorithm,
+                init_data,
+                init_data_hash_algorithm,
+            )
+            .await?;
 
         let tcb_json = serde_json::to_string(&flattened_claims)?;
 
-        let reference_data_map = self
+        let (reference_data_map, advisories) = self
             .get_reference_data(flattened_claims.keys())
             .await
             .map_err(|e| anyhow!("Generate reference data failed: {:?}", e))?;
         debug!("reference_data_map: {:#?}", reference_data_map);
+        if let Some(record) = record.as_deref_mut() {
+            record.claims = Some(Value::Object(flattened_claims.clone()));
+            record.reference_data = Some(json!(reference_data_map));
+            if !advisories.is_empty() {
+                record.advisories = Some(json!(advisories));
+            }
+        }
 
         let evaluation_report = self
             .policy_engine
-            .evaluate(reference_data_map.clone(), tcb_json, policy_ids.clone())
+            .evaluate(
+                reference_data_map.clone(),
+                advisories,
+                tcb_json,
+                policy_ids.clone(),
+            )
             .await
             .map_err(|e| anyhow!("Policy Engine evaluation failed: {e}"))?;
 
@@ -223,12 +370,19 @@ impl AttestationService {
         let policies: Vec<_> = evaluation_report
             .into_iter()
             .map(|(k, v)| {
-                json!({
+                let mut report = json!({
                     "policy-id": k,
-                    "policy-hash": v,
-                })
+                    "policy-hash": v.policy_hash,
+                });
+                if let Some(trust_vector) = v.trust_vector {
+                    report["trust-vector"] = Value::Object(trust_vector);
+                }
+                report
             })
             .collect();
+        if let Some(record) = record {
+            record.evaluation_reports = Some(json!(policies));
+        }
 
         let reference_data_map: HashMap<String, Vec<String>> = reference_data_map
             .into_iter()
@@ -255,19 +409,353 @@ impl AttestationService {
         Ok(attestation_results_token)
     }
 
-    async fn get_reference_data<'a, I>(&self, tcb_claims: I) -> Result<HashMap<String, Vec<String>>>
+    /// Verify the evidence and return its flattened claims, given to the
+    /// policies, with the claims of the runtime data and of the init data.
+    async fn verify_claims(
+        &self,
+        evidence: Vec<u8>,
+        tee: Tee,
+        runtime_data: Option<Data>,
+        runtime_data_hash_algorithm: HashAlgorithm,
+        init_data: Option<Data>,
+        init_data_hash_algorithm: HashAlgorithm,
+    ) -> Result<(Map<String, Value>, Value, Value)> {
+        let verifier = self.to_verifier(&tee)?;
+        let (evidence, additional_evidence) =
+            split_composite_evidence(evidence).context("parse composite evidence")?;
+
+        // The devices are bound to the TEE by the report data.
+        let mut devices = Vec::new();
+        for (device, evidence) in additional_evidence {
+            if device == to_variant_name(&tee)? {
+                bail!("additional evidence {device} conflicts with the TEE claims");
+            }
+
+            let device_verifier = verifier::to_device_verifier(&device)?;
+            devices.push((device, device_verifier, evidence));
+        }
+
+        let (report_data, runtime_data_claims) =
+            parse_data(runtime_data, &runtime_data_hash_algorithm).context("parse runtime data")?;
+
+        let report_data = match &report_data {
+            Some(data) => ReportData::Value(data),
+            None => ReportData::NotProvided,
+        };
+
+        let (init_data, init_data_claims) =
+            parse_data(init_data, &init_data_hash_algorithm).context("parse init data")?;
+
+        let init_data_hash = match &init_data {
+            Some(data) => InitDataHash::Value(data),
+            None => InitDataHash::NotProvided,
+        };
+
+        let claims_from_tee_evidence = self
+            .verify(
+                to_variant_name(&tee)?,
+                verifier.as_ref(),
+                &evidence,
+                &report_data,
+                &init_data_hash,
+            )
+            .await
+            .map_err(|e| anyhow!("Verifier evaluate failed: {e:?}"))?;
+        info!("{:?} Verifier/endorsement check passed.", tee);
+
+        let mut flattened_claims = flatten_claims(tee, &claims_from_tee_evidence)?;
+        if let Some(min_tcb) = &self._config.min_tcb {
+            min_tcb
+                .check(tee, &flattened_claims)
+                .context("TCB below the minimum")?;
+        }
+
+        for (device, device_verifier, evidence) in devices {
+            let claims = self
+                .verify(
+                    &device,
+                    device_verifier.as_ref(),
+                    &evidence,
+                    &report_data,
+                    &InitDataHash::NotProvided,
+                )
+                .await
+                .map_err(|e| anyhow!("{device} Verifier evaluate failed: {e:?}"))?;
+            info!("{device} Verifier/endorsement check passed.");
+            flattened_claims.extend(flatten_device_claims(&device, &claims)?);
+        }
+        self.init_data_registry.verify_claims(&mut flattened_claims);
+        if tee == Tee::Sgx {
+            self.sgx_identities.verify_claims(&mut flattened_claims);
+        }
+        apply_claim_rules(&self._config.claim_rules, &mut flattened_claims);
+        debug!("flattened_claims: {:#?}", flattened_claims);
+
+        Ok((flattened_claims, runtime_data_claims, init_data_claims))
+    }
+
+    /// Register the hex digests of the init data `workload` may be launched
+    /// with, replacing the ones registered before.
+    pub async fn register_init_data(
+        &mut self,
+        workload: String,
+        digests: Vec<String>,
+    ) -> Result<()> {
+        self.init_data_registry
+            .register(workload, digests)
+            .await
+            .context("Cannot Register Init Data")
+    }
+
+    /// Remove the init data digests of `workload`. Return whether it was
+    /// registered.
+    pub async fn unregister_init_data(&mut self, workload: &str) -> Result<bool> {
+        self.init_data_registry
+            .unregister(workload)
+            .await
+            .context("Cannot Unregister Init Data")
+    }
+
+    /// Get the `workload` -> `init data digests` map.
+    pub fn list_init_data(&self) -> BTreeMap<String, Vec<String>> {
+        self.init_data_registry.workloads().clone()
+    }
+
+    /// Allow the SGX enclaves of `identity`.
+    pub async fn register_sgx_identity(&mut self, identity: EnclaveIdentity) -> Result<()> {
+        self.sgx_identities
+            .register(identity)
+            .await
+            .context("Cannot Register SGX Identity")
+    }
+
+    /// Remove an allowed SGX enclave identity. Return whether it was
+    /// registered.
+    pub async fn unregister_sgx_identity(&mut self, identity: EnclaveIdentity) -> Result<bool> {
+        self.sgx_identities
+            .unregister(identity)
+            .await
+            .context("Cannot Unregister SGX Identity")
+    }
+
+    /// Get the allowed SGX enclave identities.
+    pub fn list_sgx_identities(&self) -> Vec<EnclaveIdentity> {
+        self.sgx_identities.identities().to_vec()
+    }
+
+    /// Add the PEM or DER encoded host-key document, certificate or CRL
+    /// `name` of the IBM SE verifier, replacing the one of the same name. It
+    /// is used from the next SE challenge on.
+    pub fn add_se_material(&self, kind: SeMaterialKind, name: &str, contents: &[u8]) -> Result<()> {
+        verifier::se::ibmse::add_material(kind, name, contents).context("Cannot Add SE Material")
+    }
+
+    /// Remove a material of the IBM SE verifier. Return whether it existed.
+    pub fn remove_se_material(&self, kind: SeMaterialKind, name: &str) -> Result<bool> {
+        verifier::se::ibmse::remove_material(kind, name).context("Cannot Remove SE Material")
+    }
+
+    /// Get the materials of `kind` of the IBM SE verifier, with their validity.
+    pub fn list_se_materials(&self, kind: SeMaterialKind) -> Result<Vec<SeMaterialInfo>> {
+        verifier::se::ibmse::list_materials(kind).context("Cannot List SE Materials")
+    }
+
+    /// Get an archived appraisal.
+    pub async fn get_archived_appraisal(&self, id: &str) -> Result<Option<AppraisalRecord>> {
+        let archive = self.archive.as_ref().context("archive is not configured")?;
+        archive
+            .get(id)
+            .await
+            .context("Cannot Get Archived Appraisal")
+    }
+
+    /// Query the archived appraisals, the latest first.
+    pub async fn query_archive(&self, query: &ArchiveQuery) -> Result<Vec<AppraisalRecord>> {
+        let archive = self.archive.as_ref().context("archive is not configured")?;
+        archive.query(query).await.context("Cannot Query Archive")
+    }
+
+    /// Appraise the evidence against the base64 encoded candidate `policy`,
+    /// without issuing a token nor archiving the appraisal. The candidate
+    /// policy is not stored. Why the evidence is rejected, by its verifier or
+    /// by the policy, is given in the returned report.
+    pub async fn dry_run(&self, evidence: DryRunEvidence, policy: String) -> Result<DryRunReport> {
+        let claims = match evidence {
+            DryRunEvidence::Evidence {
+                evidence,
+                tee,
+                runtime_data,
+                runtime_data_hash_algorithm,
+                init_data,
+                init_data_hash_algorithm,
+            } => {
+                let verified = self
+                    .verify_claims(
+                        evidence,
+                        tee,
+                        runtime_data,
+                        runtime_data_hash_algorithm,
+                        init_data,
+                        init_data_hash_algorithm,
+                    )
+                    .await;
+                match verified {
+                    Ok((claims, _, _)) => claims,
+                    Err(e) => {
+                        return Ok(DryRunReport {
+                            error: Some(format!("{e:#}")),
+                            ..Default::default()
+                        })
+                    }
+                }
+            }
+            DryRunEvidence::Archived(id) => {
+                let record = self
+                    .get_archived_appraisal(&id)
+                    .await?
+                    .with_context(|| format!("no archived appraisal {id}"))?;
+                let Some(Value::Object(claims)) = record.claims else {
+                    bail!("the evidence of the archived appraisal {id} was not verified");
+                };
+                claims
+            }
+        };
+
+        let (reference_data_map, advisories) = self
+            .get_reference_data(claims.keys())
+            .await
+            .map_err(|e| anyhow!("Generate reference data failed: {:?}", e))?;
+        let mut report = DryRunReport {
+            claims: Some(Value::Object(claims.clone())),
+            reference_data: Some(json!(reference_data_map)),
+            advisories: Some(json!(advisories)),
+            ..Default::default()
+        };
+
+        match self
+            .policy_engine
+            .evaluate_candidate(
+                reference_data_map,
+                advisories,
+                serde_json::to_string(&claims)?,
+                policy,
+            )
+            .await
+        {
+            Ok(evaluation) => {
+                report.allowed = true;
+                report.policy_hash = Some(evaluation.policy_hash);
+                report.trust_vector = evaluation.trust_vector;
+            }
+            Err(e) => report.error = Some(format!("Policy Engine evaluation failed: {e}")),
+        }
+
+        info!(
+            "Dry run of the candidate policy: allowed {}.",
+            report.allowed
+        );
+        Ok(report)
+    }
+
+    /// The verifier of the `tee` evidence, external or remote if one is
+    /// configured for `tee`, or local.
+    fn to_verifier(&self, tee: &Tee) -> Result<Box<dyn verifier::Verifier + Send + Sync>> {
+        #[cfg(feature = "external-verifier")]
+        if let Some(verifier) = self.external_verifiers.get(to_variant_name(tee)?) {
+            return Ok(Box::new(verifier.clone()));
+        }
+
+        #[cfg(feature = "intel-trust-authority-verifier")]
+        if let Some(config) = &self._config.intel_trust_authority {
+            if *tee == Tee::Tdx || *tee == Tee::Sgx {
+                if verifier::offline::enabled() {
+                    bail!("Intel Trust Authority can not be used in offline mode");
+                }
+                let verifier = verifier::intel_trust_authority::IntelTrustAuthority::new(
+                    config.clone(),
+                    *tee,
+                )?;
+                return Ok(Box::new(verifier));
+            }
+        }
+
+        verifier::to_verifier(tee)
+    }
+
+    /// Verify `evidence` by the verifier `name`, or reuse the claims of the
+    /// same evidence verified within the TTL of the verification cache.
+    async fn verify(
+        &self,
+        name: &str,
+        verifier: &(dyn Verifier + Send + Sync),
+        evidence: &[u8],
+        report_data: &ReportData<'_>,
+        init_data_hash: &InitDataHash<'_>,
+    ) -> Result<TeeEvidenceParsedClaim> {
+        let attributes = vec![KeyValue::new("as.verifier", name.to_string())];
+        traced("verifier.evaluate", attributes, async {
+            let Some(cache) = &self.verification_cache else {
+                let claims = verifier
+                    .evaluate(evidence, report_data, init_data_hash)
+                    .await;
+                metrics::observe_verifier_call(name, &claims);
+                return claims;
+            };
+
+            let key = VerificationCache::key(name, evidence, report_data, init_data_hash);
+            if let Some(claims) = cache.get(&key) {
+                debug!("Cached claims of the verified {name} evidence are used.");
+                metrics::observe_cached_verification(name);
+                get_active_span(|span| span.set_attribute(KeyValue::new("as.cached", true)));
+                return Ok(claims);
+            }
+
+            let claims = verifier
+                .evaluate(evidence, report_data, init_data_hash)
+                .await;
+            metrics::observe_verifier_call(name, &claims);
+            let claims = claims?;
+            cache.insert(key, claims.clone());
+            Ok(claims)
+        })
+        .await
+    }
+
+    /// The policy ids of the `tee` evidence appraised without policy ids.
+    fn tee_policy_ids(&self, tee: &Tee) -> Result<Vec<String>> {
+        let policy_ids = match self._config.tee_policies.get(to_variant_name(tee)?) {
+            Some(policy_ids) => policy_ids.clone(),
+            None => vec![DEFAULT_POLICY_ID.into()],
+        };
+        debug!("No policy specified, use {policy_ids:?} for {tee:?}");
+        Ok(policy_ids)
+    }
+
+    /// The reference values of the claims, and the advisories of the ones
+    /// having some.
+    async fn get_reference_data<'a, I>(
+        &self,
+        tcb_claims: I,
+    ) -> Result<(HashMap<String, Vec<String>>, HashMap<String, Vec<Advisory>>)>
     where
         I: Iterator<Item = &'a String>,
     {
         let mut data = HashMap::new();
+        let mut advisories = HashMap::new();
         for key in tcb_claims {
-            let reference_value = self.rvps.get_digests(key).await?;
-            if !reference_value.is_empty() {
+            let attributes = vec![KeyValue::new("rvps.reference_value", key.to_string())];
+            let digests =
+                traced("rvps.get_digests", attributes, self.rvps.get_digests(key)).await?;
+            if !digests.hash_values.is_empty() {
                 debug!("Successfully get reference values of {key} from RVPS.");
             }
-            data.insert(key.to_string(), reference_value);
+            if !digests.advisories.is_empty() {
+                debug!("Reference value of {key} has advisories.");
+                advisories.insert(key.to_string(), digests.advisories);
+            }
+            data.insert(key.to_string(), digests.hash_values);
         }
-        Ok(data)
+        Ok((data, advisories))
     }
 
     /// Registry a new reference value
@@ -275,12 +763,23 @@ impl AttestationService {
         self.rvps.verify_and_extract(message).await
     }
 
+    /// Query the reference values of the RVPS, a page at a time.
+    pub async fn query_reference_values(
+        &self,
+        query: &ReferenceValueQuery,
+    ) -> Result<ReferenceValuePage> {
+        self.rvps
+            .query_reference_values(query)
+            .await
+            .context("Cannot Query Reference Values")
+    }
+
     pub async fn generate_supplemental_challenge(
         &self,
         tee: Tee,
         tee_parameters: String,
     ) -> Result<String> {
-        let verifier = verifier::to_verifier(&tee)?;
+        let verifier = self.to_verifier(&tee)?;
         verifier
             .generate_supplemental_challenge(tee_parameters)
             .await
@@ -314,7 +813,7 @@ mod tests {
     use rstest::rstest;
     use serde_json::{json, Value};
 
-    use crate::{Data, HashAlgorithm};
+    use crate::{config::Config, rvps::RvpsConfig, AttestationService, Data, HashAlgorithm, Tee};
 
     #[rstest]
     #[case(Some(Data::Raw(b"aaaaa".to_vec())), Some(b"aaaaa".to_vec()), HashAlgorithm::Sha384, Value::Null)]
@@ -330,4 +829,42 @@ mod tests {
         assert_eq!(data, expected_data);
         assert_json_eq!(data_claims, expected_claims);
     }
+
+    #[tokio::test]
+    async fn composite_evidence_with_sample_refused() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            work_dir: work_dir.path().to_path_buf(),
+            rvps_config: RvpsConfig {
+                store_type: "LocalJson".into(),
+                store_config: json!({
+                    "file_path": work_dir.path().join("reference_values.json"),
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let service = AttestationService::new(config).await.unwrap();
+
+        // The sample evidence checks no signature, so its claims would be
+        // forged next to the ones of the TEE.
+        let evidence = json!({
+            "primary_evidence": {"quote": "AQID"},
+            "additional_evidence": {
+                "sample": {"svn": "1", "report_data": ""},
+            },
+        });
+        let error = service
+            .verify_claims(
+                serde_json::to_vec(&evidence).unwrap(),
+                Tee::Snp,
+                None,
+                HashAlgorithm::Sha384,
+                None,
+                HashAlgorithm::Sha384,
+            )
+            .await
+            .unwrap_err();
+        assert!(format!("{error:#}").contains("sample verifier cannot verify"));
+    }
 }
@@ -0,0 +1,117 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Prometheus metrics of the appraisals and of the calls of the verifiers,
+//! the plugins of the AS. They are registered in the default registry, so
+//! that a KBS with a built-in AS exposes them with its own metrics.
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, Encoder, HistogramVec, IntCounterVec,
+    TextEncoder,
+};
+use std::time::Instant;
+
+lazy_static! {
+    static ref ATTESTATIONS: IntCounterVec = register_int_counter_vec!(
+        "attestation_service_attestations_total",
+        "Appraisals of the evidence, by TEE type and outcome.",
+        &["tee", "outcome"]
+    )
+    .unwrap();
+    static ref ATTESTATION_DURATION: HistogramVec = register_histogram_vec!(
+        "attestation_service_attestation_duration_seconds",
+        "Duration of the appraisals of the evidence, by TEE type.",
+        &["tee"]
+    )
+    .unwrap();
+    static ref VERIFIER_CALLS: IntCounterVec = register_int_counter_vec!(
+        "attestation_service_verifier_calls_total",
+        "Calls of the verifiers, by verifier and outcome, `cached` if the claims of the verification cache are reused.",
+        &["verifier", "outcome"]
+    )
+    .unwrap();
+}
+
+/// Register the metrics, so that they are exposed before they are observed.
+pub(crate) fn register() {
+    lazy_static::initialize(&ATTESTATIONS);
+    lazy_static::initialize(&ATTESTATION_DURATION);
+    lazy_static::initialize(&VERIFIER_CALLS);
+}
+
+fn outcome<T>(result: &Result<T>) -> &'static str {
+    match result {
+        Ok(_) => "success",
+        Err(_) => "failure",
+    }
+}
+
+/// Observe the appraisal of the `tee` evidence started at `start`.
+pub(crate) fn observe_attestation<T>(tee: &str, start: Instant, result: &Result<T>) {
+    ATTESTATIONS
+        .with_label_values(&[tee, outcome(result)])
+        .inc();
+    ATTESTATION_DURATION
+        .with_label_values(&[tee])
+        .observe(start.elapsed().as_secs_f64());
+}
+
+/// Observe a call of the verifier `name`.
+pub(crate) fn observe_verifier_call<T>(name: &str, result: &Result<T>) {
+    VERIFIER_CALLS
+        .with_label_values(&[name, outcome(result)])
+        .inc();
+}
+
+/// Observe the reuse of the claims of the cache instead of a call of the
+/// verifier `name`.
+pub(crate) fn observe_cached_verification(name: &str) {
+    VERIFIER_CALLS.with_label_values(&[name, "cached"]).inc();
+}
+
+/// The metrics of the default registry in the Prometheus text format.
+pub fn gather() -> Result<String> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_metrics() {
+        register();
+        let calls = |outcome: &str| {
+            VERIFIER_CALLS
+                .with_label_values(&["test-metrics", outcome])
+                .get()
+        };
+
+        observe_verifier_call("test-metrics", &Ok(()));
+        observe_verifier_call::<()>("test-metrics", &Err(anyhow!("test")));
+        observe_verifier_call("test-metrics", &Ok(()));
+        observe_cached_verification("test-metrics");
+        assert_eq!(calls("success"), 2);
+        assert_eq!(calls("failure"), 1);
+        assert_eq!(calls("cached"), 1);
+
+        observe_attestation("test-metrics", Instant::now(), &Ok(()));
+        let metrics = gather().unwrap();
+        assert!(metrics.contains(
+            r#"attestation_service_attestations_total{outcome="success",tee="test-metrics"} 1"#
+        ));
+        assert!(metrics.contains(
+            r#"attestation_service_verifier_calls_total{outcome="cached",verifier="test-metrics"} 1"#
+        ));
+        assert!(metrics.contains(
+            r#"attestation_service_attestation_duration_seconds_count{tee="test-metrics"} 1"#
+        ));
+    }
+}
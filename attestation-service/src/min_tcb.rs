@@ -0,0 +1,165 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Minimum TCB levels of the platforms. The evidence of a platform whose
+//! TCB is below the configured levels, e.g. before a TCB recovery, is
+//! rejected by the AS before the policies are evaluated.
+
+use anyhow::{bail, Context, Result};
+use kbs_types::Tee;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// Minimum SVNs of the reported TCB of the SNP evidence.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct SnpMinTcb {
+    #[serde(default)]
+    pub bootloader: Option<u8>,
+
+    #[serde(default)]
+    pub tee: Option<u8>,
+
+    #[serde(default)]
+    pub snp: Option<u8>,
+
+    #[serde(default)]
+    pub microcode: Option<u8>,
+}
+
+/// TCB statuses of the Intel platforms that are accepted, as given by the
+/// quote verification, e.g. `UpToDate` or `SWHardeningNeeded`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct IntelMinTcb {
+    pub accepted_tcb_status: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct MinTcbConfig {
+    /// Applied to the SNP and Azure SNP vTPM evidence.
+    #[serde(default)]
+    pub snp: Option<SnpMinTcb>,
+
+    /// Applied to the TDX and Azure TDX vTPM evidence.
+    #[serde(default)]
+    pub tdx: Option<IntelMinTcb>,
+
+    #[serde(default)]
+    pub sgx: Option<IntelMinTcb>,
+}
+
+impl MinTcbConfig {
+    /// Check the flattened claims of the evidence of `tee` against the
+    /// minimum TCB of its platform. A missing TCB claim fails the check.
+    pub fn check(&self, tee: Tee, claims: &Map<String, Value>) -> Result<()> {
+        match tee {
+            Tee::Snp | Tee::AzSnpVtpm => {
+                if let Some(snp) = &self.snp {
+                    check_snp(snp, claims)?;
+                }
+            }
+            Tee::Tdx => {
+                if let Some(tdx) = &self.tdx {
+                    check_intel(tdx, "TDX", claims.get("tdx.tcb_status"))?;
+                }
+            }
+            Tee::AzTdxVtpm => {
+                if let Some(tdx) = &self.tdx {
+                    check_intel(tdx, "TDX", claims.get("az-tdx-vtpm.tcb_status"))?;
+                }
+            }
+            Tee::Sgx => {
+                if let Some(sgx) = &self.sgx {
+                    check_intel(sgx, "SGX", claims.get("sgx.tcb_status"))?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn check_snp(min_tcb: &SnpMinTcb, claims: &Map<String, Value>) -> Result<()> {
+    for (name, component, minimum) in [
+        ("bootloader", "bootloader", min_tcb.bootloader),
+        ("TEE", "tee", min_tcb.tee),
+        ("SNP firmware", "snp", min_tcb.snp),
+        ("microcode", "microcode", min_tcb.microcode),
+    ] {
+        let Some(minimum) = minimum else {
+            continue;
+        };
+
+        let svn: u8 = claims
+            .get(&format!("snp.reported_tcb_{component}"))
+            .and_then(Value::as_str)
+            .with_context(|| format!("SNP reported TCB {name} SVN is missing"))?
+            .parse()
+            .with_context(|| format!("illegal SNP reported TCB {name} SVN"))?;
+        if svn < minimum {
+            bail!("SNP reported TCB {name} SVN {svn} is below the minimum {minimum}");
+        }
+    }
+
+    Ok(())
+}
+
+fn check_intel(min_tcb: &IntelMinTcb, platform: &str, tcb_status: Option<&Value>) -> Result<()> {
+    let tcb_status = tcb_status
+        .and_then(Value::as_str)
+        .with_context(|| format!("{platform} TCB status is missing"))?;
+    if !min_tcb
+        .accepted_tcb_status
+        .iter()
+        .any(|accepted| accepted == tcb_status)
+    {
+        bail!(
+            "{platform} TCB status {tcb_status} is not one of the accepted {:?}",
+            min_tcb.accepted_tcb_status
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn enforce_min_tcb() {
+        let config: MinTcbConfig = serde_json::from_value(json!({
+            "snp": { "snp": 8, "microcode": 115 },
+            "tdx": { "accepted_tcb_status": ["UpToDate", "SWHardeningNeeded"] },
+        }))
+        .unwrap();
+
+        let mut claims = Map::new();
+        claims.insert("snp.reported_tcb_snp".into(), json!("8"));
+        claims.insert("snp.reported_tcb_microcode".into(), json!("115"));
+        config.check(Tee::Snp, &claims).unwrap();
+        config.check(Tee::AzSnpVtpm, &claims).unwrap();
+
+        claims.insert("snp.reported_tcb_microcode".into(), json!("100"));
+        let error = config.check(Tee::Snp, &claims).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "SNP reported TCB microcode SVN 100 is below the minimum 115"
+        );
+
+        claims.remove("snp.reported_tcb_microcode");
+        assert!(config.check(Tee::Snp, &claims).is_err());
+
+        claims.insert("tdx.tcb_status".into(), json!("SWHardeningNeeded"));
+        config.check(Tee::Tdx, &claims).unwrap();
+        claims.insert("tdx.tcb_status".into(), json!("OutOfDate"));
+        assert!(config.check(Tee::Tdx, &claims).is_err());
+        assert!(config.check(Tee::AzTdxVtpm, &claims).is_err());
+
+        // No minimum TCB of the SGX platforms.
+        config.check(Tee::Sgx, &claims).unwrap();
+    }
+}
@@ -1,6 +1,7 @@
 use crate::policy_engine::opa::RegoError;
 use anyhow::Result;
 use async_trait::async_trait;
+use reference_value_provider_service::Advisory;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::path::Path;
@@ -25,20 +26,45 @@ impl PolicyEngineType {
 
 type PolicyDigest = String;
 
+/// The result of a policy that allowed the evidence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyEvaluation {
+    /// The digest of the policy (using **Sha384**).
+    pub policy_hash: PolicyDigest,
+
+    /// The trustworthiness vector of the evidence, i.e. the AR4SI
+    /// trustworthiness claims set by the `trust_vector` rule of the
+    /// policy, if any.
+    pub trust_vector: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
 #[async_trait]
 pub trait PolicyEngine {
-    /// Verify an input body against a set of ref values and a list of policies
+    /// Verify an input body against a set of ref values, with the advisories
+    /// of the reference values, and a list of policies
     /// return a list of policy ids with their sha384 at eval time
     /// abort early on first failed validation and any errors.
     /// The result is a key-value map.
     /// - `key`: the policy id
-    /// - `value`: the digest of the policy (using **Sha384**).
+    /// - `value`: the digest of the policy (using **Sha384**) and its
+    /// trustworthiness vector.
     async fn evaluate(
         &self,
         reference_data_map: HashMap<String, Vec<String>>,
+        advisories: HashMap<String, Vec<Advisory>>,
         input: String,
         policy_ids: Vec<String>,
-    ) -> Result<HashMap<String, PolicyDigest>, RegoError>;
+    ) -> Result<HashMap<String, PolicyEvaluation>, RegoError>;
+
+    /// Evaluate the base64 encoded candidate `policy`, which is not stored,
+    /// against an input body and a set of ref values, like `evaluate`.
+    async fn evaluate_candidate(
+        &self,
+        reference_data_map: HashMap<String, Vec<String>>,
+        advisories: HashMap<String, Vec<Advisory>>,
+        input: String,
+        policy: String,
+    ) -> Result<PolicyEvaluation, RegoError>;
 
     async fn set_policy(&mut self, policy_id: String, policy: String) -> Result<(), RegoError>;
 
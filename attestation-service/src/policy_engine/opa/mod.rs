@@ -5,6 +5,8 @@
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use base64::Engine;
+use reference_value_provider_service::Advisory;
+use serde_json::json;
 use sha2::{Digest, Sha384};
 use std::collections::HashMap;
 use std::fs;
@@ -12,7 +14,11 @@ use std::io;
 use std::path::PathBuf;
 use thiserror::Error;
 
-use super::{PolicyDigest, PolicyEngine};
+use super::{PolicyDigest, PolicyEngine, PolicyEvaluation};
+
+/// Id of the candidate policies evaluated by a dry run, e.g. in the
+/// `PolicyDenied` errors.
+const CANDIDATE_POLICY_ID: &str = "candidate";
 
 #[derive(Debug, Clone)]
 pub struct OPA {
@@ -51,6 +57,12 @@ pub enum RegoError {
     EvalPolicyFailed(#[source] anyhow::Error),
     #[error("json serialization failed: {0}")]
     JsonSerializationFailed(#[source] anyhow::Error),
+    #[error("Illegal trust vector of {policy_id}: {source}")]
+    InvalidTrustVector {
+        policy_id: String,
+        #[source]
+        source: anyhow::Error,
+    },
 }
 
 impl OPA {
@@ -88,9 +100,10 @@ impl PolicyEngine for OPA {
     async fn evaluate(
         &self,
         reference_data_map: HashMap<String, Vec<String>>,
+        advisories: HashMap<String, Vec<Advisory>>,
         input: String,
         policy_ids: Vec<String>,
-    ) -> Result<HashMap<String, PolicyDigest>, RegoError> {
+    ) -> Result<HashMap<String, PolicyEvaluation>, RegoError> {
         let mut res = HashMap::new();
 
         let policy_dir_path = self
@@ -99,57 +112,42 @@ impl PolicyEngine for OPA {
             .ok_or_else(|| RegoError::PolicyDirPathToStringFailed)?;
 
         for policy_id in &policy_ids {
-            let input = input.clone();
             let policy_file_path = format!("{policy_dir_path}/{policy_id}.rego");
 
             let policy = tokio::fs::read_to_string(policy_file_path.clone())
                 .await
                 .map_err(RegoError::ReadPolicyFileFailed)?;
 
-            let mut engine = regorus::Engine::new();
-
-            let policy_hash = {
-                use sha2::Digest;
-                let mut hasher = sha2::Sha384::new();
-                hasher.update(&policy);
-                let hex = hasher.finalize().to_vec();
-                hex::encode(hex)
-            };
-
-            // Add policy as data
-            engine
-                .add_policy(policy_id.clone(), policy)
-                .map_err(RegoError::LoadPolicyFailed)?;
-
-            let reference_data_map = serde_json::to_string(&reference_data_map)?;
-            let reference_data_map =
-                regorus::Value::from_json_str(&format!("{{\"reference\":{reference_data_map}}}"))
-                    .map_err(RegoError::JsonSerializationFailed)?;
-            engine
-                .add_data(reference_data_map)
-                .map_err(RegoError::LoadReferenceDataFailed)?;
-
-            // Add TCB claims as input
-            engine
-                .set_input_json(&input)
-                .context("set input")
-                .map_err(RegoError::SetInputDataFailed)?;
-
-            let allow = engine
-                .eval_bool_query("data.policy.allow".to_string(), false)
-                .map_err(RegoError::EvalPolicyFailed)?;
-            if !allow {
-                return Err(RegoError::PolicyDenied {
-                    policy_id: policy_id.clone(),
-                });
-            }
-
-            res.insert(policy_id.clone(), policy_hash);
+            let evaluation =
+                evaluate_rego(policy_id, policy, &reference_data_map, &advisories, &input)?;
+            res.insert(policy_id.clone(), evaluation);
         }
 
         Ok(res)
     }
 
+    async fn evaluate_candidate(
+        &self,
+        reference_data_map: HashMap<String, Vec<String>>,
+        advisories: HashMap<String, Vec<Advisory>>,
+        input: String,
+        policy: String,
+    ) -> Result<PolicyEvaluation, RegoError> {
+        let policy_bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(policy)
+            .map_err(RegoError::Base64DecodeFailed)?;
+        let policy =
+            String::from_utf8(policy_bytes).map_err(|e| RegoError::LoadPolicyFailed(e.into()))?;
+
+        evaluate_rego(
+            CANDIDATE_POLICY_ID,
+            policy,
+            &reference_data_map,
+            &advisories,
+            &input,
+        )
+    }
+
     async fn set_policy(&mut self, policy_id: String, policy: String) -> Result<(), RegoError> {
         let policy_bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
             .decode(policy)
@@ -217,6 +215,95 @@ impl PolicyEngine for OPA {
     }
 }
 
+/// Evaluate the `policy` against the input and the reference values. The
+/// reference values are given as `data.reference`, and their advisories as
+/// `data.advisories`.
+fn evaluate_rego(
+    policy_id: &str,
+    policy: String,
+    reference_data_map: &HashMap<String, Vec<String>>,
+    advisories: &HashMap<String, Vec<Advisory>>,
+    input: &str,
+) -> Result<PolicyEvaluation, RegoError> {
+    let mut engine = regorus::Engine::new();
+
+    let policy_hash = {
+        let mut hasher = Sha384::new();
+        hasher.update(&policy);
+        let hex = hasher.finalize().to_vec();
+        hex::encode(hex)
+    };
+
+    // Add policy as data
+    engine
+        .add_policy(policy_id.to_string(), policy)
+        .map_err(RegoError::LoadPolicyFailed)?;
+
+    let reference_data_map = serde_json::to_string(&json!({
+        "reference": reference_data_map,
+        "advisories": advisories,
+    }))?;
+    let reference_data_map = regorus::Value::from_json_str(&reference_data_map)
+        .map_err(RegoError::JsonSerializationFailed)?;
+    engine
+        .add_data(reference_data_map)
+        .map_err(RegoError::LoadReferenceDataFailed)?;
+
+    // Add TCB claims as input
+    engine
+        .set_input_json(input)
+        .context("set input")
+        .map_err(RegoError::SetInputDataFailed)?;
+
+    let allow = engine
+        .eval_bool_query("data.policy.allow".to_string(), false)
+        .map_err(RegoError::EvalPolicyFailed)?;
+    if !allow {
+        return Err(RegoError::PolicyDenied {
+            policy_id: policy_id.to_string(),
+        });
+    }
+
+    let trust_vector =
+        trust_vector(&mut engine).map_err(|source| RegoError::InvalidTrustVector {
+            policy_id: policy_id.to_string(),
+            source,
+        })?;
+
+    Ok(PolicyEvaluation {
+        policy_hash,
+        trust_vector,
+    })
+}
+
+/// The trustworthiness vector set by the optional `trust_vector` rule of the
+/// policy, e.g.
+/// ```rego
+/// trust_vector := {"executables": 2, "hardware": 2}
+/// ```
+fn trust_vector(
+    engine: &mut regorus::Engine,
+) -> Result<Option<serde_json::Map<String, serde_json::Value>>> {
+    let results = engine.eval_query("data.policy.trust_vector".to_string(), false)?;
+    let Some(value) = results
+        .result
+        .first()
+        .and_then(|result| result.expressions.first())
+        .map(|expression| &expression.value)
+    else {
+        return Ok(None);
+    };
+    if *value == regorus::Value::Undefined {
+        return Ok(None);
+    }
+
+    let serde_json::Value::Object(trust_vector) = serde_json::from_str(&value.to_json_str()?)?
+    else {
+        anyhow::bail!("trust_vector is not an object");
+    };
+    Ok(Some(trust_vector))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -251,6 +338,7 @@ mod tests {
         let res = opa
             .evaluate(
                 reference_data.clone(),
+                HashMap::new(),
                 dummy_input(5, 5),
                 vec![default_policy_id.clone()],
             )
@@ -258,15 +346,109 @@ mod tests {
         let res = res.expect("OPA execution should succeed");
         // this expected value is calculated by `sha384sum`
         let expected_digest = "c0e7929671fb6780387f54760d84d65d2ce96093dfb33efda21f5eb05afcda77bba444c02cd177b23a5d350716726157";
-        assert_eq!(expected_digest, res["default_policy"]);
+        assert_eq!(expected_digest, res["default_policy"].policy_hash);
+        assert_eq!(res["default_policy"].trust_vector, None);
 
         let res = opa
-            .evaluate(reference_data, dummy_input(0, 0), vec![default_policy_id])
+            .evaluate(
+                reference_data,
+                HashMap::new(),
+                dummy_input(0, 0),
+                vec![default_policy_id],
+            )
             .await;
 
         res.expect_err("OPA execution should fail");
     }
 
+    #[tokio::test]
+    async fn test_evaluate_trust_vector() {
+        let dir = tempfile::tempdir().unwrap();
+        let opa = OPA {
+            policy_dir_path: dir.path().to_path_buf(),
+        };
+        let policy = r#"package policy
+default allow = true
+trust_vector := {"hardware": 2, "executables": to_number(input.svn)}"#;
+        fs::write(dir.path().join("tv.rego"), policy).unwrap();
+
+        let res = opa
+            .evaluate(
+                HashMap::new(),
+                HashMap::new(),
+                dummy_input(5, 33),
+                vec!["tv".to_string()],
+            )
+            .await
+            .unwrap();
+        let expected = json!({"hardware": 2, "executables": 33});
+        assert_eq!(
+            res["tv"].trust_vector,
+            Some(expected.as_object().unwrap().clone())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_candidate() {
+        let dir = tempfile::tempdir().unwrap();
+        let opa = OPA {
+            policy_dir_path: dir.path().to_path_buf(),
+        };
+        let policy = r#"package policy
+default allow = false
+allow { input.svn == data.reference.svn[_] }"#;
+        let policy = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(policy);
+        let reference_data: HashMap<String, Vec<String>> =
+            serde_json::from_str(&dummy_reference(5)).unwrap();
+
+        opa.evaluate_candidate(
+            reference_data.clone(),
+            HashMap::new(),
+            dummy_input(5, 5),
+            policy.clone(),
+        )
+        .await
+        .unwrap();
+        let res = opa
+            .evaluate_candidate(
+                reference_data.clone(),
+                HashMap::new(),
+                dummy_input(5, 4),
+                policy,
+            )
+            .await;
+        assert!(matches!(res, Err(RegoError::PolicyDenied { .. })));
+
+        // The matching svn is refused if it is known to be vulnerable.
+        let policy = r#"package policy
+default allow = false
+allow {
+    input.svn == data.reference.svn[_]
+    not vulnerable
+}
+vulnerable { data.advisories.svn[_].severity == "critical" }"#;
+        let policy = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(policy);
+        let advisories: HashMap<String, Vec<Advisory>> = serde_json::from_value(json!({
+            "svn": [{ "id": "CVE-2024-1234", "severity": "critical" }],
+        }))
+        .unwrap();
+        opa.evaluate_candidate(
+            reference_data.clone(),
+            HashMap::new(),
+            dummy_input(5, 5),
+            policy.clone(),
+        )
+        .await
+        .unwrap();
+        let res = opa
+            .evaluate_candidate(reference_data, advisories, dummy_input(5, 5), policy)
+            .await;
+        assert!(matches!(res, Err(RegoError::PolicyDenied { .. })));
+
+        // The candidate policy is not stored.
+        assert!(opa.list_policies().await.unwrap().is_empty());
+    }
+
     #[tokio::test]
     async fn test_policy_management() {
         let mut opa = OPA::new(PathBuf::from("tests/tmp")).unwrap();
@@ -2,15 +2,18 @@ use super::RvpsApi;
 use anyhow::*;
 use async_trait::async_trait;
 use core::result::Result::Ok;
-use reference_value_provider_service::{Config, Core};
+use reference_value_provider_service::{
+    query::{ReferenceValuePage, ReferenceValueQuery},
+    Config, Core, TrustedDigest,
+};
 
 pub struct Rvps {
     core: Core,
 }
 
 impl Rvps {
-    pub fn new(config: Config) -> Result<Self> {
-        let core = Core::new(config)?;
+    pub async fn new(config: Config) -> Result<Self> {
+        let core = Core::new(config).await?;
         Ok(Self { core })
     }
 }
@@ -22,13 +25,22 @@ impl RvpsApi for Rvps {
         Ok(())
     }
 
-    async fn get_digests(&self, name: &str) -> Result<Vec<String>> {
-        let hashes = self
+    async fn get_digests(&self, name: &str) -> Result<TrustedDigest> {
+        let digests = self
             .core
             .get_digests(name)
             .await?
-            .unwrap_or_default()
-            .hash_values;
-        Ok(hashes)
+            .unwrap_or_else(|| TrustedDigest {
+                name: name.to_string(),
+                ..Default::default()
+            });
+        Ok(digests)
+    }
+
+    async fn query_reference_values(
+        &self,
+        query: &ReferenceValueQuery,
+    ) -> Result<ReferenceValuePage> {
+        self.core.query_reference_values(query).await
     }
 }
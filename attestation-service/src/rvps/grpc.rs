@@ -1,10 +1,18 @@
 use crate::rvps::RvpsError;
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use reference_value_provider_service::query::{ReferenceValuePage, ReferenceValueQuery};
+use reference_value_provider_service::TrustedDigest;
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
 use tokio::sync::Mutex;
+use tonic::transport::Channel;
 
 use self::rvps_api::{
     reference_value_provider_service_client::ReferenceValueProviderServiceClient,
-    ReferenceValueQueryRequest, ReferenceValueRegisterRequest,
+    ReferenceValueListRequest, ReferenceValueQueryRequest, ReferenceValueRegisterRequest,
+    WatchReferenceValuesRequest,
 };
 
 use super::RvpsApi;
@@ -13,19 +21,140 @@ pub mod rvps_api {
     tonic::include_proto!("reference");
 }
 
+/// Delay before watching again the changes of the RVPS, once the watch
+/// failed.
+const WATCH_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+type Client = ReferenceValueProviderServiceClient<Channel>;
+
+/// Digests of the RVPS, cached until the RVPS notifies a change of them.
+/// The cache is only used while the changes are watched, so that none of
+/// them is missed.
+#[derive(Default)]
+struct DigestCache {
+    digests: std::sync::Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    digests: HashMap<String, TrustedDigest>,
+
+    /// Whether the changes of the RVPS are watched.
+    watching: bool,
+
+    /// Incremented at each change, so that the digests got before a change
+    /// are not cached after it.
+    generation: u64,
+}
+
+impl DigestCache {
+    /// The cached digests of `name`, or the generation to cache the digests
+    /// of `name` with once got.
+    fn get(&self, name: &str) -> Result<TrustedDigest, Option<u64>> {
+        let Ok(state) = self.digests.lock() else {
+            return Err(None);
+        };
+        if !state.watching {
+            return Err(None);
+        }
+        match state.digests.get(name) {
+            Some(digests) => Ok(digests.clone()),
+            None => Err(Some(state.generation)),
+        }
+    }
+
+    fn insert(&self, generation: u64, digests: TrustedDigest) {
+        if let Ok(mut state) = self.digests.lock() {
+            if state.watching && state.generation == generation {
+                state.digests.insert(digests.name.clone(), digests);
+            }
+        }
+    }
+
+    /// Invalidate the digests of `name`, or all of them.
+    fn invalidate(&self, name: Option<&str>) {
+        if let Ok(mut state) = self.digests.lock() {
+            match name {
+                Some(name) => state.digests.remove(name),
+                None => {
+                    state.digests.clear();
+                    None
+                }
+            };
+            state.generation += 1;
+        }
+    }
+
+    fn set_watching(&self, watching: bool) {
+        self.invalidate(None);
+        if let Ok(mut state) = self.digests.lock() {
+            state.watching = watching;
+        }
+    }
+}
+
 pub struct Agent {
-    client: Mutex<ReferenceValueProviderServiceClient<tonic::transport::Channel>>,
+    client: Mutex<Client>,
+
+    /// Cache of the digests, if enabled.
+    cache: Option<Arc<DigestCache>>,
 }
 
 impl Agent {
-    pub async fn new(addr: &str) -> Result<Self, RvpsError> {
+    /// Connect to the RVPS at `addr`. If `cache_digests`, the digests are
+    /// cached until the RVPS notifies a change of them.
+    pub async fn new(addr: &str, cache_digests: bool) -> Result<Self, RvpsError> {
+        let client = ReferenceValueProviderServiceClient::connect(addr.to_string()).await?;
+        let cache = cache_digests.then(|| {
+            let cache = Arc::new(DigestCache::default());
+            spawn_watch(client.clone(), Arc::downgrade(&cache));
+            cache
+        });
+
         Ok(Self {
-            client: Mutex::new(
-                ReferenceValueProviderServiceClient::connect(addr.to_string()).await?,
-            ),
+            client: Mutex::new(client),
+            cache,
         })
     }
 }
+
+/// Keep the `cache` up to date with the changes of the RVPS until it is
+/// dropped, watching them again whenever the watch fails.
+fn spawn_watch(mut client: Client, cache: Weak<DigestCache>) {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = watch(&mut client, &cache).await {
+                warn!("Watch of the reference values of the RVPS failed: {e:#}");
+            }
+            let Some(cache) = cache.upgrade() else {
+                break;
+            };
+            cache.set_watching(false);
+            drop(cache);
+
+            tokio::time::sleep(WATCH_RETRY_INTERVAL).await;
+        }
+    });
+}
+
+async fn watch(client: &mut Client, cache: &Weak<DigestCache>) -> Result<()> {
+    let req = tonic::Request::new(WatchReferenceValuesRequest { since: 0 });
+    let mut changes = client.watch_reference_values(req).await?.into_inner();
+    match cache.upgrade() {
+        Some(cache) => cache.set_watching(true),
+        None => return Ok(()),
+    }
+    info!("Digests of the RVPS are cached until they change.");
+
+    while let Some(change) = changes.message().await? {
+        let Some(cache) = cache.upgrade() else {
+            return Ok(());
+        };
+        cache.invalidate(Some(&change.name));
+    }
+
+    bail!("the RVPS ended the watch")
+}
 #[async_trait::async_trait]
 impl RvpsApi for Agent {
     async fn verify_and_extract(&mut self, message: &str) -> Result<()> {
@@ -42,7 +171,13 @@ impl RvpsApi for Agent {
         Ok(())
     }
 
-    async fn get_digests(&self, name: &str) -> Result<Vec<String>> {
+    async fn get_digests(&self, name: &str) -> Result<TrustedDigest> {
+        let generation = match self.cache.as_ref().map(|cache| cache.get(name)) {
+            Some(Ok(digests)) => return Ok(digests),
+            Some(Err(generation)) => generation,
+            None => None,
+        };
+
         let req = tonic::Request::new(ReferenceValueQueryRequest {
             name: name.to_string(),
         });
@@ -53,7 +188,48 @@ impl RvpsApi for Agent {
             .query_reference_value(req)
             .await?
             .into_inner();
-        let trust_digest = serde_json::from_str(&res.reference_value_results)?;
-        Ok(trust_digest)
+        let hash_values = serde_json::from_str(&res.reference_value_results)?;
+
+        // The RVPS not giving advisories has none.
+        let advisories = match res.advisories.is_empty() {
+            true => Vec::new(),
+            false => serde_json::from_str(&res.advisories)?,
+        };
+        let digests = TrustedDigest {
+            name: name.to_string(),
+            hash_values,
+            advisories,
+        };
+
+        if let (Some(cache), Some(generation)) = (&self.cache, generation) {
+            cache.insert(generation, digests.clone());
+        }
+        Ok(digests)
+    }
+
+    async fn query_reference_values(
+        &self,
+        query: &ReferenceValueQuery,
+    ) -> Result<ReferenceValuePage> {
+        let req = tonic::Request::new(ReferenceValueListRequest {
+            name_prefix: query.name_prefix.clone().unwrap_or_default(),
+            provenance: query.provenance.clone().unwrap_or_default(),
+            since: query.since.unwrap_or_default(),
+            until: query.until.unwrap_or_default(),
+            page_size: query.page_size.unwrap_or_default().try_into()?,
+            page_token: query.page_token.clone().unwrap_or_default(),
+        });
+        let res = self
+            .client
+            .lock()
+            .await
+            .list_reference_values(req)
+            .await
+            .context("list failed")?
+            .into_inner();
+        Ok(ReferenceValuePage {
+            reference_values: serde_json::from_str(&res.reference_values)?,
+            next_page_token: (!res.next_page_token.is_empty()).then_some(res.next_page_token),
+        })
     }
 }
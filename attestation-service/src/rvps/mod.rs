@@ -5,7 +5,12 @@
 
 use anyhow::Result;
 use log::{info, warn};
-use reference_value_provider_service::config::{Config as RvpsCrateConfig, DEFAULT_STORAGE_TYPE};
+use reference_value_provider_service::config::{
+    BundlePublisher, Config as RvpsCrateConfig, FederationConfig, SigstoreConfig, TrustedBuilder,
+    DEFAULT_GC_INTERVAL_SECS, DEFAULT_STORAGE_TYPE,
+};
+use reference_value_provider_service::query::{ReferenceValuePage, ReferenceValueQuery};
+use reference_value_provider_service::TrustedDigest;
 use serde::Deserialize;
 use serde_json::{json, Value};
 use thiserror::Error;
@@ -13,15 +18,23 @@ use thiserror::Error;
 /// The interfaces of Reference Value Provider Service
 /// * `verify_and_extract` is responsible for verify a message and
 /// store reference values from it.
-/// * `get_digests` gets trusted digests by the artifact's name.
+/// * `get_digests` gets trusted digests, and the advisories, by the
+/// artifact's name.
+/// * `query_reference_values` lists the reference values, a page at a time.
 #[async_trait::async_trait]
 pub trait RvpsApi {
     /// Verify the given message and register the reference value included.
     async fn verify_and_extract(&mut self, message: &str) -> Result<()>;
 
     /// Get the reference values / golden values / expected digests in hex of the
-    /// given component name.
-    async fn get_digests(&self, name: &str) -> Result<Vec<String>>;
+    /// given component name, with the vendor advisories of the component.
+    async fn get_digests(&self, name: &str) -> Result<TrustedDigest>;
+
+    /// Get a page of the reference values matching the query.
+    async fn query_reference_values(
+        &self,
+        query: &ReferenceValueQuery,
+    ) -> Result<ReferenceValuePage>;
 }
 
 #[cfg(feature = "rvps-grpc")]
@@ -38,6 +51,10 @@ fn default_store_config() -> Value {
     json!({})
 }
 
+fn default_gc_interval_secs() -> u64 {
+    DEFAULT_GC_INTERVAL_SECS
+}
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct RvpsConfig {
     /// Address of remote RVPS. If this field is given, a remote RVPS will be connected to.
@@ -45,6 +62,11 @@ pub struct RvpsConfig {
     #[serde(default = "String::default")]
     pub remote_addr: String,
 
+    /// Cache the digests of the remote RVPS until it notifies a change of
+    /// them. This field will be used only if `remote_addr` is given.
+    #[serde(default)]
+    pub cache_remote_digests: bool,
+
     /// This field will be used only if `remote_addr` is not given.
     #[serde(default = "default_store_type")]
     pub store_type: String,
@@ -52,6 +74,32 @@ pub struct RvpsConfig {
     /// This field will be used only if `remote_addr` is not given.
     #[serde(default = "default_store_config")]
     pub store_config: Value,
+
+    /// Builders whose SLSA provenance is trusted.
+    /// This field will be used only if `remote_addr` is not given.
+    #[serde(default)]
+    pub trusted_builders: Vec<TrustedBuilder>,
+
+    /// Publishers whose reference value bundles are trusted.
+    /// This field will be used only if `remote_addr` is not given.
+    #[serde(default)]
+    pub bundle_publishers: Vec<BundlePublisher>,
+
+    /// If set, the messages are required to be signed with Sigstore.
+    /// This field will be used only if `remote_addr` is not given.
+    #[serde(default)]
+    pub sigstore: Option<SigstoreConfig>,
+
+    /// Interval of the garbage collection of the expired and superseded
+    /// reference values, in seconds. `0` disables it.
+    /// This field will be used only if `remote_addr` is not given.
+    #[serde(default = "default_gc_interval_secs")]
+    pub gc_interval_secs: u64,
+
+    /// If set, the reference values are pulled from upstream RVPS.
+    /// This field will be used only if `remote_addr` is not given.
+    #[serde(default)]
+    pub federation: Option<FederationConfig>,
 }
 
 impl From<RvpsConfig> for RvpsCrateConfig {
@@ -59,6 +107,11 @@ impl From<RvpsConfig> for RvpsCrateConfig {
         RvpsCrateConfig {
             store_type: val.store_type,
             store_config: val.store_config,
+            trusted_builders: val.trusted_builders,
+            bundle_publishers: val.bundle_publishers,
+            sigstore: val.sigstore,
+            gc_interval_secs: val.gc_interval_secs,
+            federation: val.federation,
         }
     }
 }
@@ -67,8 +120,14 @@ impl Default for RvpsConfig {
     fn default() -> Self {
         Self {
             remote_addr: String::new(),
+            cache_remote_digests: false,
             store_type: default_store_type(),
             store_config: default_store_config(),
+            trusted_builders: Vec::new(),
+            bundle_publishers: Vec::new(),
+            sigstore: None,
+            gc_interval_secs: default_gc_interval_secs(),
+            federation: None,
         }
     }
 }
@@ -95,12 +154,12 @@ pub async fn initialize_rvps_client(
             if !config.remote_addr.is_empty() {
                 let remote_addr = &config.remote_addr;
                 info!("connect to remote RVPS: {remote_addr}");
-                Ok(Box::new(grpc::Agent::new(remote_addr).await?) as Box<dyn RvpsApi + Send + Sync>)
+                Ok(Box::new(grpc::Agent::new(remote_addr, config.cache_remote_digests).await?) as Box<dyn RvpsApi + Send + Sync>)
             } else {
                 cfg_if::cfg_if! {
                     if #[cfg(feature = "rvps-builtin")] {
                         warn!("No RVPS address provided and will launch a built-in rvps");
-                        Ok(Box::new(builtin::Rvps::new(config.clone().into())?) as Box<dyn RvpsApi + Send + Sync>)
+                        Ok(Box::new(builtin::Rvps::new(config.clone().into()).await?) as Box<dyn RvpsApi + Send + Sync>)
                     } else {
                         return RvpsError::FeatureNotEnabled;
                     }
@@ -108,7 +167,7 @@ pub async fn initialize_rvps_client(
             }
         } else if #[cfg(feature = "rvps-builtin")] {
             info!("launch a built-in RVPS.");
-            Ok(Box::new(builtin::Rvps::new(config.clone().into())) as Box<dyn RvpsApi + Send + Sync>)
+            Ok(Box::new(builtin::Rvps::new(config.clone().into()).await?) as Box<dyn RvpsApi + Send + Sync>)
         } else {
             return RvpsError::FeatureNotEnabled;
         }
@@ -0,0 +1,192 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Allowlist of the SGX enclave identities. The MRENCLAVE, MRSIGNER and
+//! ISVPRODID of the SGX evidence are matched against the registered
+//! identities, and the outcome is given to the policies as the
+//! `sgx.identity_allowed` claim.
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+const REGISTRY_FILE: &str = "sgx_identities.json";
+
+/// An allowed enclave identity. The fields which are not set match any
+/// enclave, but one of `mr_enclave` and `mr_signer` is required.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct EnclaveIdentity {
+    /// Hex MRENCLAVE of the enclave.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mr_enclave: Option<String>,
+
+    /// Hex MRSIGNER of the enclave.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mr_signer: Option<String>,
+
+    /// ISVPRODID of the enclave.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isv_prod_id: Option<u16>,
+}
+
+impl EnclaveIdentity {
+    fn normalize(mut self) -> Result<Self> {
+        if self.mr_enclave.is_none() && self.mr_signer.is_none() {
+            bail!("an enclave identity requires a MRENCLAVE or a MRSIGNER");
+        }
+
+        for (name, measurement) in [
+            ("MRENCLAVE", &mut self.mr_enclave),
+            ("MRSIGNER", &mut self.mr_signer),
+        ] {
+            if let Some(value) = measurement {
+                let bytes =
+                    hex::decode(&value).with_context(|| format!("illegal {name} {value}"))?;
+                if bytes.len() != 32 {
+                    bail!("{name} {value} is not 32 bytes long");
+                }
+                *value = hex::encode(bytes);
+            }
+        }
+
+        Ok(self)
+    }
+
+    fn matches(&self, claims: &Map<String, Value>) -> bool {
+        let claim = |name: &str| claims.get(name).and_then(Value::as_str);
+
+        // The ISVPRODID claim is the hex of the little endian u16.
+        let isv_prod_id = claim("sgx.body.isv_prod_id")
+            .and_then(|id| hex::decode(id).ok())
+            .and_then(|id| Some(u16::from_le_bytes(id.try_into().ok()?)));
+
+        self.mr_enclave
+            .iter()
+            .all(|mr_enclave| claim("sgx.body.mr_enclave") == Some(mr_enclave.as_str()))
+            && self
+                .mr_signer
+                .iter()
+                .all(|mr_signer| claim("sgx.body.mr_signer") == Some(mr_signer.as_str()))
+            && self.isv_prod_id.iter().all(|id| isv_prod_id == Some(*id))
+    }
+}
+
+/// Registered enclave identities, persisted in the work dir of the AS.
+pub(crate) struct EnclaveIdentityRegistry {
+    path: PathBuf,
+    identities: Vec<EnclaveIdentity>,
+}
+
+impl EnclaveIdentityRegistry {
+    pub fn new(work_dir: &Path) -> Result<Self> {
+        let path = work_dir.join(REGISTRY_FILE);
+        let identities = match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .with_context(|| format!("parse SGX identity registry {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self { path, identities })
+    }
+
+    pub fn identities(&self) -> &[EnclaveIdentity] {
+        &self.identities
+    }
+
+    /// Allow the enclaves of `identity`.
+    pub async fn register(&mut self, identity: EnclaveIdentity) -> Result<()> {
+        let identity = identity.normalize()?;
+        if self.identities.contains(&identity) {
+            return Ok(());
+        }
+
+        let mut identities = self.identities.clone();
+        identities.push(identity);
+        self.persist(identities).await
+    }
+
+    /// Remove `identity`. Return whether it was registered.
+    pub async fn unregister(&mut self, identity: EnclaveIdentity) -> Result<bool> {
+        let identity = identity.normalize()?;
+        let mut identities = self.identities.clone();
+        identities.retain(|registered| *registered != identity);
+        if identities.len() == self.identities.len() {
+            return Ok(false);
+        }
+
+        self.persist(identities).await?;
+        Ok(true)
+    }
+
+    async fn persist(&mut self, identities: Vec<EnclaveIdentity>) -> Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(&identities)?).await?;
+        fs::rename(&tmp_path, &self.path)
+            .await
+            .context("persist SGX identity registry")?;
+        self.identities = identities;
+        Ok(())
+    }
+
+    /// Add the `sgx.identity_allowed` claim to the flattened claims of an
+    /// SGX evidence.
+    pub fn verify_claims(&self, claims: &mut Map<String, Value>) {
+        let allowed = self
+            .identities
+            .iter()
+            .any(|identity| identity.matches(claims));
+        debug!("SGX enclave identity allowed: {allowed}");
+
+        claims.insert("sgx.identity_allowed".into(), json!(allowed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn allow_enclave_identities() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut registry = EnclaveIdentityRegistry::new(dir.path()).unwrap();
+        let signer = EnclaveIdentity {
+            mr_enclave: None,
+            mr_signer: Some("83D7".repeat(16)),
+            isv_prod_id: Some(1),
+        };
+        assert!(registry
+            .register(EnclaveIdentity {
+                mr_signer: None,
+                ..signer.clone()
+            })
+            .await
+            .is_err());
+        registry.register(signer.clone()).await.unwrap();
+        registry.register(signer.clone()).await.unwrap();
+
+        // Reloaded from the work dir.
+        let mut registry = EnclaveIdentityRegistry::new(dir.path()).unwrap();
+        assert_eq!(registry.identities().len(), 1);
+
+        let mut claims = Map::new();
+        claims.insert("sgx.body.mr_enclave".into(), json!("8f17".repeat(16)));
+        claims.insert("sgx.body.mr_signer".into(), json!("83d7".repeat(16)));
+        claims.insert("sgx.body.isv_prod_id".into(), json!("0100"));
+        registry.verify_claims(&mut claims);
+        assert_eq!(claims["sgx.identity_allowed"], json!(true));
+
+        claims.insert("sgx.body.isv_prod_id".into(), json!("0200"));
+        registry.verify_claims(&mut claims);
+        assert_eq!(claims["sgx.identity_allowed"], json!(false));
+
+        assert!(registry.unregister(signer.clone()).await.unwrap());
+        assert!(!registry.unregister(signer).await.unwrap());
+        assert!(registry.identities().is_empty());
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! OpenTelemetry traces of the appraisals: a span for each appraisal, with
+//! the spans of the calls of the verifiers and of the queries of the RVPS
+//! nested in it. They are nested in the current span of the caller, e.g. the
+//! one of the KBS propagated to the gRPC AS.
+//!
+//! The spans are exported over OTLP by the AS binaries built with the `otel`
+//! feature, and are not recorded otherwise.
+
+use opentelemetry::trace::{FutureExt, Status, TraceContextExt, Tracer};
+use opentelemetry::{global, Context, KeyValue};
+use std::future::Future;
+
+/// Name of the tracer of the AS.
+const TRACER_NAME: &str = "attestation-service";
+
+#[cfg(feature = "otel")]
+const DEFAULT_SERVICE_NAME: &str = "attestation-service";
+
+/// Install the OTLP exporter of the spans and the W3C trace context
+/// propagator. The exporter is configured by the standard environment
+/// variables, e.g. `OTEL_EXPORTER_OTLP_ENDPOINT`, `OTEL_SERVICE_NAME` and
+/// `OTEL_TRACES_SAMPLER`.
+#[cfg(feature = "otel")]
+pub fn init() -> anyhow::Result<()> {
+    use anyhow::Context as _;
+
+    let service_name =
+        std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| DEFAULT_SERVICE_NAME.into());
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+        .with_trace_config(opentelemetry_sdk::trace::config().with_resource(
+            opentelemetry_sdk::Resource::new(vec![KeyValue::new("service.name", service_name)]),
+        ))
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .context("install OTLP exporter")?;
+    global::set_text_map_propagator(opentelemetry_sdk::propagation::TraceContextPropagator::new());
+    Ok(())
+}
+
+/// Export the spans which are not yet.
+#[cfg(feature = "otel")]
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+/// Run `future` in a span `name` with `attributes`, nested in the current
+/// one, whose status is the error of its result.
+pub(crate) async fn traced<T, E, F>(
+    name: &'static str,
+    attributes: Vec<KeyValue>,
+    future: F,
+) -> Result<T, E>
+where
+    E: std::fmt::Display,
+    F: Future<Output = Result<T, E>>,
+{
+    let span = global::tracer(TRACER_NAME)
+        .span_builder(name)
+        .with_attributes(attributes)
+        .start(&global::tracer(TRACER_NAME));
+    let context = Context::current_with_span(span);
+
+    let result = future.with_context(context.clone()).await;
+    if let Err(e) = &result {
+        context.span().set_status(Status::error(e.to_string()));
+    }
+    context.span().end();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState};
+
+    #[tokio::test]
+    async fn test_traced() {
+        let trace_id = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+        let parent = Context::new().with_remote_span_context(SpanContext::new(
+            trace_id,
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        ));
+
+        // The spans are in the trace of the caller.
+        let result = traced("test", vec![], async {
+            Ok::<_, String>(Context::current().span().span_context().trace_id())
+        })
+        .with_context(parent)
+        .await;
+        assert_eq!(result, Ok(trace_id));
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Attestation results in the [EAR](https://datatracker.ietf.org/doc/draft-fv-rats-ear/)
+//! format, i.e. an EAT with an appraisal of the evidence by policy, made of
+//! the status and the AR4SI trustworthiness vector of the policy.
+
+use anyhow::*;
+use serde_json::{json, Map, Value};
+
+use crate::token::simple::SimpleAttestationTokenBroker;
+use crate::token::{AttestationTokenBroker, AttestationTokenConfig};
+
+const EAR_PROFILE: &str = "tag:github.com,2023:veraison/ear";
+const EAR_DEVELOPER: &str = "https://confidentialcontainers.org";
+
+/// The claims of an AR4SI trustworthiness vector.
+const TRUST_CLAIMS: &[&str] = &[
+    "instance-identity",
+    "configuration",
+    "executables",
+    "file-system",
+    "hardware",
+    "runtime-opaque",
+    "storage-opaque",
+    "sourced-data",
+];
+
+/// Issues EARs signed like the simple tokens. The claims of the simple
+/// tokens that are not part of the EAR, e.g. `customized_claims` binding the
+/// TEE public key, are kept alongside, so that the KBS can use both.
+pub struct EarAttestationTokenBroker {
+    inner: SimpleAttestationTokenBroker,
+}
+
+impl EarAttestationTokenBroker {
+    pub fn new(config: AttestationTokenConfig) -> Result<Self> {
+        Ok(Self {
+            inner: SimpleAttestationTokenBroker::new(config)?,
+        })
+    }
+}
+
+impl AttestationTokenBroker for EarAttestationTokenBroker {
+    fn issue(&self, custom_claims: Value) -> Result<String> {
+        self.inner.issue(to_ear_claims(custom_claims)?)
+    }
+
+    fn pubkey_jwks(&self) -> Result<String> {
+        self.inner.pubkey_jwks()
+    }
+}
+
+/// Convert the claims of a simple token into EAR claims, with a submodule
+/// per evaluated policy.
+fn to_ear_claims(custom_claims: Value) -> Result<Value> {
+    let Value::Object(mut claims) = custom_claims else {
+        bail!("Illegal token custom claims");
+    };
+
+    let reports = match claims.remove("evaluation-reports") {
+        Some(Value::Array(reports)) => reports,
+        _ => Vec::new(),
+    };
+    let annotated_evidence = claims.remove("tcb-status").unwrap_or(json!({}));
+
+    let mut submods = Map::new();
+    for report in reports {
+        let policy_id = report["policy-id"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Evaluation report without policy id"))?;
+        let trust_vector = match &report["trust-vector"] {
+            Value::Object(trust_vector) => trust_vector.clone(),
+            Value::Null => Map::new(),
+            _ => bail!("Illegal trust vector of policy {policy_id}"),
+        };
+
+        submods.insert(
+            policy_id.to_string(),
+            json!({
+                "ear.status": status(policy_id, &trust_vector)?,
+                "ear.trustworthiness-vector": trust_vector,
+                "ear.appraisal-policy-id": policy_id,
+                "ear.veraison.annotated-evidence": annotated_evidence,
+            }),
+        );
+    }
+
+    claims.insert("eat_profile".to_string(), json!(EAR_PROFILE));
+    claims.insert(
+        "ear.verifier-id".to_string(),
+        json!({
+            "developer": EAR_DEVELOPER,
+            "build": format!("attestation-service {}", env!("CARGO_PKG_VERSION")),
+        }),
+    );
+    claims.insert("submods".to_string(), Value::Object(submods));
+
+    Ok(Value::Object(claims))
+}
+
+/// The status of the appraisal of a policy, i.e. the worst tier of its
+/// trustworthiness claims, positive or negative. The evidence is affirmed by a policy that allowed
+/// it without trustworthiness claims.
+fn status(policy_id: &str, trust_vector: &Map<String, Value>) -> Result<&'static str> {
+    if trust_vector.is_empty() {
+        return Ok("affirming");
+    }
+
+    let mut worst = 0;
+    for (claim, value) in trust_vector {
+        if !TRUST_CLAIMS.contains(&claim.as_str()) {
+            bail!("Unknown trustworthiness claim {claim} of policy {policy_id}");
+        }
+        let value = value
+            .as_i64()
+            .filter(|value| (-128..=127).contains(value))
+            .ok_or_else(|| {
+                anyhow!("Illegal trustworthiness claim {claim} of policy {policy_id}")
+            })?;
+
+        // The AR4SI tiers are symmetric around 0, the negative values being
+        // shifted by one, e.g. -32 is still affirming.
+        let tier = match value {
+            -1..=1 => 0,
+            2..=31 | -32..=-2 => 1,
+            32..=95 | -96..=-33 => 2,
+            _ => 3,
+        };
+        worst = worst.max(tier);
+    }
+
+    Ok(["none", "affirming", "warning", "contraindicated"][worst])
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[test]
+    fn convert_ear_claims() {
+        let claims = json!({
+            "tee": "sample",
+            "evaluation-reports": [
+                {
+                    "policy-id": "default",
+                    "policy-hash": "abcd",
+                },
+                {
+                    "policy-id": "sample",
+                    "policy-hash": "ef01",
+                    "trust-vector": { "hardware": 2, "executables": 33 },
+                },
+            ],
+            "tcb-status": { "sample.svn": "1" },
+            "customized_claims": { "runtime_data": { "tee-pubkey": "key" } },
+        });
+
+        let ear = to_ear_claims(claims).unwrap();
+        assert_eq!(ear["eat_profile"], EAR_PROFILE);
+        assert_eq!(ear["tee"], "sample");
+        assert_eq!(
+            ear["customized_claims"]["runtime_data"]["tee-pubkey"],
+            "key"
+        );
+        assert!(ear.get("evaluation-reports").is_none());
+        assert_eq!(
+            ear["submods"]["default"],
+            json!({
+                "ear.status": "affirming",
+                "ear.trustworthiness-vector": {},
+                "ear.appraisal-policy-id": "default",
+                "ear.veraison.annotated-evidence": { "sample.svn": "1" },
+            })
+        );
+        assert_eq!(ear["submods"]["sample"]["ear.status"], "warning");
+    }
+
+    #[test]
+    fn reject_illegal_trust_vector() {
+        let claims = |trust_vector: Value| {
+            json!({
+                "evaluation-reports": [{ "policy-id": "p", "trust-vector": trust_vector }],
+            })
+        };
+
+        to_ear_claims(claims(json!({ "unknown": 2 }))).unwrap_err();
+        to_ear_claims(claims(json!({ "hardware": 128 }))).unwrap_err();
+        to_ear_claims(claims(json!({ "hardware": "2" }))).unwrap_err();
+        let ear = to_ear_claims(claims(json!({ "hardware": 96, "executables": 2 }))).unwrap();
+        assert_eq!(ear["submods"]["p"]["ear.status"], "contraindicated");
+    }
+
+    #[rstest]
+    #[case(json!({ "hardware": 0 }), "none")]
+    #[case(json!({ "hardware": -1 }), "none")]
+    #[case(json!({ "hardware": -2 }), "affirming")]
+    #[case(json!({ "hardware": -32 }), "affirming")]
+    #[case(json!({ "hardware": -33, "executables": 2 }), "warning")]
+    #[case(json!({ "hardware": -96 }), "warning")]
+    #[case(json!({ "hardware": -97 }), "contraindicated")]
+    #[case(json!({ "hardware": 2, "executables": -100 }), "contraindicated")]
+    #[case(json!({ "hardware": -128 }), "contraindicated")]
+    fn negative_trust_tiers(#[case] trust_vector: Value, #[case] expected: &str) {
+        let Value::Object(trust_vector) = trust_vector else {
+            unreachable!()
+        };
+        assert_eq!(status("p", &trust_vector).unwrap(), expected);
+    }
+}
@@ -9,6 +9,7 @@ use serde_json::Value;
 use simple::COCO_AS_ISSUER_NAME;
 use strum::{Display, EnumString};
 
+mod ear;
 mod simple;
 
 const DEFAULT_TOKEN_TIMEOUT: i64 = 5;
@@ -26,6 +27,9 @@ pub trait AttestationTokenBroker {
 #[derive(Deserialize, Debug, Clone, EnumString, Display)]
 pub enum AttestationTokenBrokerType {
     Simple,
+
+    /// EAT Attestation Results, with an appraisal per policy.
+    Ear,
 }
 
 impl AttestationTokenBrokerType {
@@ -38,6 +42,10 @@ impl AttestationTokenBrokerType {
                 Ok(Box::new(simple::SimpleAttestationTokenBroker::new(config)?)
                     as Box<dyn AttestationTokenBroker + Send + Sync>)
             }
+            AttestationTokenBrokerType::Ear => {
+                Ok(Box::new(ear::EarAttestationTokenBroker::new(config)?)
+                    as Box<dyn AttestationTokenBroker + Send + Sync>)
+            }
         }
     }
 }
@@ -4,10 +4,41 @@
 //
 
 use anyhow::*;
+use serde::Deserialize;
 use serde_json::{Map, Value};
 use serde_variant::to_variant_name;
+use std::collections::BTreeMap;
 use verifier::TeeEvidenceParsedClaim;
 
+/// Evidence of a TEE together with the evidence of the devices attached to
+/// it, e.g. GPUs, by device verifier name.
+#[derive(Deserialize)]
+struct CompositeEvidence {
+    primary_evidence: Value,
+    additional_evidence: BTreeMap<String, Value>,
+}
+
+/// Evidence of a device, with the name of its verifier.
+pub type DeviceEvidence = (String, Vec<u8>);
+
+/// Split a composite evidence into the TEE evidence and the device
+/// evidences. Any other evidence is a TEE evidence alone.
+pub fn split_composite_evidence(evidence: Vec<u8>) -> Result<(Vec<u8>, Vec<DeviceEvidence>)> {
+    let Result::Ok(composite) = serde_json::from_slice::<CompositeEvidence>(&evidence) else {
+        return Ok((evidence, Vec::new()));
+    };
+
+    let mut additional_evidence = Vec::new();
+    for (device, evidence) in composite.additional_evidence {
+        additional_evidence.push((device, serde_json::to_vec(&evidence)?));
+    }
+
+    Ok((
+        serde_json::to_vec(&composite.primary_evidence)?,
+        additional_evidence,
+    ))
+}
+
 /// This funciton will transpose the following structured json
 /// ```json
 /// {
@@ -26,6 +57,10 @@ use verifier::TeeEvidenceParsedClaim;
 /// ```
 ///
 /// But the key `init_data` and `report_data` will not be added the prefix.
+///
+/// The hardware claims of a TEE wrapping the evidence of another one, e.g.
+/// the SNP report of an Azure confidential VM, are also added the prefix of
+/// the wrapped TEE, so that policies written for the latter apply to both.
 pub fn flatten_claims(
     tee: kbs_types::Tee,
     claims: &TeeEvidenceParsedClaim,
@@ -39,6 +74,17 @@ pub fn flatten_claims(
                     flatten_helper(&mut map, v, format!("{tee_type}.{}", k.clone()));
                 }
             }
+            if let Some((hardware_tee, wrapper_claims)) = hardware_tee(&tee) {
+                let hardware_tee_type = to_variant_name(&hardware_tee)?;
+                for (k, v) in obj {
+                    if k != "report_data"
+                        && k != "init_data"
+                        && !wrapper_claims.contains(&k.as_str())
+                    {
+                        flatten_helper(&mut map, v, format!("{hardware_tee_type}.{k}"));
+                    }
+                }
+            }
             let report_data = obj
                 .get("report_data")
                 .cloned()
@@ -57,6 +103,34 @@ pub fn flatten_claims(
     Ok(map)
 }
 
+/// The TEE whose evidence is wrapped by `tee`, with the claims that `tee`
+/// adds to the ones of the wrapped evidence.
+fn hardware_tee(tee: &kbs_types::Tee) -> Option<(kbs_types::Tee, &'static [&'static str])> {
+    match tee {
+        kbs_types::Tee::AzSnpVtpm => Some((kbs_types::Tee::Snp, &["tpm", "hcl"])),
+        _ => None,
+    }
+}
+
+/// Flatten the claims of the evidence of a device like [`flatten_claims`],
+/// with a prefix of the device verifier name on all the keys, `init_data`
+/// and `report_data` included.
+pub fn flatten_device_claims(
+    device: &str,
+    claims: &TeeEvidenceParsedClaim,
+) -> Result<Map<String, Value>> {
+    let Value::Object(obj) = claims else {
+        bail!("input claims must be a map");
+    };
+
+    let mut map = Map::new();
+    for (k, v) in obj {
+        flatten_helper(&mut map, v, format!("{device}.{k}"));
+    }
+
+    Ok(map)
+}
+
 /// Recursion algorithm helper of `flatten_claims`
 fn flatten_helper(parent: &mut Map<String, Value>, child: &serde_json::Value, prefix: String) {
     match child {
@@ -89,7 +163,35 @@ mod tests {
     use assert_json_diff::assert_json_eq;
     use serde_json::json;
 
-    use super::flatten_claims;
+    use super::{flatten_claims, flatten_device_claims, split_composite_evidence};
+
+    #[test]
+    fn flatten_azure_snp() {
+        let json = json!({
+            "measurement": "abcd",
+            "reported_tcb_snp": "8",
+            "tpm": {
+                "pcr00": "00"
+            },
+            "hcl": {
+                "vm_configuration": {
+                    "secure-boot": true
+                }
+            }
+        });
+        let flatten = flatten_claims(kbs_types::Tee::AzSnpVtpm, &json).expect("flatten failed");
+        let expected = json!({
+            "azsnpvtpm.measurement": "abcd",
+            "azsnpvtpm.reported_tcb_snp": "8",
+            "azsnpvtpm.tpm.pcr00": "00",
+            "azsnpvtpm.hcl.vm_configuration.secure-boot": true,
+            "snp.measurement": "abcd",
+            "snp.reported_tcb_snp": "8",
+            "report_data": "",
+            "init_data": ""
+        });
+        assert_json_eq!(expected, flatten);
+    }
 
     #[test]
     fn flatten() {
@@ -156,4 +258,39 @@ mod tests {
         });
         assert_json_eq!(expected, flatten);
     }
+
+    #[test]
+    fn composite_evidence() {
+        let evidence = json!({
+            "primary_evidence": {"quote": "AQID"},
+            "additional_evidence": {
+                "nvidia": {"evidence_list": []}
+            }
+        });
+        let (primary, additional) =
+            split_composite_evidence(serde_json::to_vec(&evidence).unwrap()).unwrap();
+        assert_eq!(primary, br#"{"quote":"AQID"}"#);
+        assert_eq!(additional.len(), 1);
+        assert_eq!(additional[0].0, "nvidia");
+        assert_eq!(additional[0].1, br#"{"evidence_list":[]}"#);
+
+        // Other evidences are left intact.
+        let evidence = br#"{"quote":"AQID"}"#.to_vec();
+        let (primary, additional) = split_composite_evidence(evidence.clone()).unwrap();
+        assert_eq!(primary, evidence);
+        assert!(additional.is_empty());
+
+        let claims = json!({
+            "gpu0": {"driver_version": "550.54.15"},
+            "report_data": "0707"
+        });
+        let flatten = flatten_device_claims("nvidia", &claims).unwrap();
+        assert_json_eq!(
+            json!({
+                "nvidia.gpu0.driver_version": "550.54.15",
+                "nvidia.report_data": "0707"
+            }),
+            flatten
+        );
+    }
 }
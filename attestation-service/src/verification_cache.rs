@@ -0,0 +1,162 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Cache of the claims of the evidence verified by the verifiers, so that
+//! identical evidence, e.g. of a fleet of identical CVMs attesting in
+//! bursts, is not verified again within the TTL. The policies are still
+//! evaluated for every evidence.
+
+use serde::Deserialize;
+use sha2::{Digest, Sha384};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use verifier::{InitDataHash, ReportData, TeeEvidenceParsedClaim};
+
+const DEFAULT_MAX_ENTRIES: usize = 1024;
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct VerificationCacheConfig {
+    /// Seconds during which the claims of a verified evidence are reused.
+    pub ttl_secs: u64,
+
+    /// Maximum number of cached evidence. The oldest one is evicted first.
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+}
+
+fn default_max_entries() -> usize {
+    DEFAULT_MAX_ENTRIES
+}
+
+pub struct VerificationCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<Vec<u8>, (Instant, TeeEvidenceParsedClaim)>>,
+}
+
+impl VerificationCache {
+    pub fn new(config: &VerificationCacheConfig) -> Self {
+        Self {
+            ttl: Duration::from_secs(config.ttl_secs),
+            max_entries: config.max_entries,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The key of an evidence verified by `verifier`, against the given
+    /// report data and init data hash.
+    pub fn key(
+        verifier: &str,
+        evidence: &[u8],
+        report_data: &ReportData,
+        init_data_hash: &InitDataHash,
+    ) -> Vec<u8> {
+        let mut hasher = Sha384::new();
+        for data in [
+            Some(verifier.as_bytes()),
+            Some(evidence),
+            match report_data {
+                ReportData::Value(data) => Some(*data),
+                ReportData::NotProvided => None,
+            },
+            match init_data_hash {
+                InitDataHash::Value(data) => Some(*data),
+                InitDataHash::NotProvided => None,
+            },
+        ] {
+            // Length-prefixed, so that the fields can not be shifted.
+            match data {
+                Some(data) => {
+                    hasher.update([1]);
+                    hasher.update((data.len() as u64).to_le_bytes());
+                    hasher.update(data);
+                }
+                None => hasher.update([0]),
+            }
+        }
+        hasher.finalize().to_vec()
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<TeeEvidenceParsedClaim> {
+        let entries = self.entries.lock().ok()?;
+        let (verified_at, claims) = entries.get(key)?;
+        (verified_at.elapsed() < self.ttl).then(|| claims.clone())
+    }
+
+    pub fn insert(&self, key: Vec<u8>, claims: TeeEvidenceParsedClaim) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+
+        let ttl = self.ttl;
+        entries.retain(|_, (verified_at, _)| verified_at.elapsed() < ttl);
+        if entries.len() >= self.max_entries {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (verified_at, _))| *verified_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        if self.max_entries > 0 {
+            entries.insert(key, (Instant::now(), claims));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn cache_claims() {
+        let cache = VerificationCache::new(&VerificationCacheConfig {
+            ttl_secs: 60,
+            max_entries: 1,
+        });
+        let key = VerificationCache::key(
+            "sample",
+            b"evidence",
+            &ReportData::Value(b"nonce"),
+            &InitDataHash::NotProvided,
+        );
+        assert_eq!(cache.get(&key), None);
+
+        cache.insert(key.clone(), json!({ "svn": "1" }));
+        assert_eq!(cache.get(&key), Some(json!({ "svn": "1" })));
+
+        // Another report data is another evidence, evicting the oldest.
+        let other = VerificationCache::key(
+            "sample",
+            b"evidence",
+            &ReportData::Value(b"other nonce"),
+            &InitDataHash::NotProvided,
+        );
+        assert_ne!(key, other);
+        cache.insert(other.clone(), json!({ "svn": "2" }));
+        assert_eq!(cache.get(&key), None);
+        assert_eq!(cache.get(&other), Some(json!({ "svn": "2" })));
+    }
+
+    #[test]
+    fn expire_claims() {
+        let cache = VerificationCache::new(&VerificationCacheConfig {
+            ttl_secs: 0,
+            max_entries: DEFAULT_MAX_ENTRIES,
+        });
+        let key = VerificationCache::key(
+            "sample",
+            b"evidence",
+            &ReportData::NotProvided,
+            &InitDataHash::NotProvided,
+        );
+        cache.insert(key.clone(), json!({}));
+        assert_eq!(cache.get(&key), None);
+    }
+}
@@ -18,12 +18,19 @@ use az_snp_vtpm::vtpm::QuoteError;
 use log::{debug, warn};
 use openssl::pkey::PKey;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
 use sev::firmware::host::{CertTableEntry, CertType};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 
 const HCL_VMPL_VALUE: u32 = 0;
 
+/// Offset of the IGVM request data of a HCL report, following its 32 bytes
+/// header and its hardware report.
+const HCL_DATA_OFFSET: usize = 32 + 1184;
+const HCL_VAR_DATA_SIZE_OFFSET: usize = HCL_DATA_OFFSET + 16;
+const HCL_VAR_DATA_OFFSET: usize = HCL_DATA_OFFSET + 20;
+
 #[derive(Serialize, Deserialize)]
 struct Evidence {
     quote: Quote,
@@ -81,6 +88,46 @@ pub(crate) fn extend_claim_with_tpm_quote(
     Ok(())
 }
 
+/// Parse the runtime data of the HCL report, i.e. its variable data bound to
+/// the SNP report, as the `hcl` claims:
+/// - `hcl.vm_configuration.*`: the configuration of the VM, e.g. whether
+///   secure boot is enabled, and its unique id.
+/// - `hcl.user_data`: the user data of the guest, hex encoded.
+pub(crate) fn extend_claim_with_hcl_runtime_data(
+    claim: &mut TeeEvidenceParsedClaim,
+    report: &[u8],
+    var_data_hash: &[u8; 32],
+) -> Result<()> {
+    let Value::Object(ref mut map) = claim else {
+        bail!("failed to extend the claim, not an object");
+    };
+
+    let size = report
+        .get(HCL_VAR_DATA_SIZE_OFFSET..HCL_VAR_DATA_OFFSET)
+        .context("HCL report is too short")?;
+    let size = u32::from_le_bytes(size.try_into()?) as usize;
+    let var_data = report
+        .get(HCL_VAR_DATA_OFFSET..HCL_VAR_DATA_OFFSET + size)
+        .context("HCL report variable data is truncated")?;
+    if Sha256::digest(var_data)[..] != var_data_hash[..] {
+        bail!("HCL report variable data does not match its hash");
+    }
+
+    let runtime_data: Map<String, Value> =
+        serde_json::from_slice(var_data).context("Failed to parse HCL runtime data")?;
+    let mut hcl_values = Map::new();
+    if let Some(vm_configuration) = runtime_data.get("vm-configuration") {
+        hcl_values.insert("vm_configuration".to_string(), vm_configuration.clone());
+    }
+    if let Some(user_data) = runtime_data.get("user-data") {
+        hcl_values.insert("user_data".to_string(), user_data.clone());
+    }
+    debug!("extending claim with HCL runtime data: {:#?}", hcl_values);
+    map.insert("hcl".to_string(), Value::Object(hcl_values));
+
+    Ok(())
+}
+
 #[async_trait]
 impl Verifier for AzSnpVtpm {
     /// The following verification steps are performed:
@@ -90,6 +137,9 @@ impl Verifier for AzSnpVtpm {
     /// 4. SNP report's report_data field matches hashed HCL variable data
     /// 5. SNP Report is genuine
     /// 6. SNP Report has been issued in VMPL 0
+    ///
+    /// The claims are the ones of the SNP report, extended with the `tpm`
+    /// PCRs of the quote and the `hcl` runtime data of the HCL report.
     async fn evaluate(
         &self,
         evidence: &[u8],
@@ -107,7 +157,7 @@ impl Verifier for AzSnpVtpm {
         let evidence = serde_json::from_slice::<Evidence>(evidence)
             .context("Failed to deserialize Azure vTPM SEV-SNP evidence")?;
 
-        let hcl_report = HclReport::new(evidence.report)?;
+        let hcl_report = HclReport::new(evidence.report.clone())?;
         verify_signature(&evidence.quote, &hcl_report)?;
 
         verify_nonce(&evidence.quote, expected_report_data)?;
@@ -123,6 +173,7 @@ impl Verifier for AzSnpVtpm {
 
         let mut claim = parse_tee_evidence(&snp_report);
         extend_claim_with_tpm_quote(&mut claim, &evidence.quote)?;
+        extend_claim_with_hcl_runtime_data(&mut claim, &evidence.report, &var_data_hash)?;
 
         Ok(claim)
     }
@@ -335,4 +386,31 @@ mod tests {
             assert_eq!(value, hex::encode(pcr));
         }
     }
+
+    #[test]
+    fn test_extend_claim_with_hcl_runtime_data() {
+        let mut claim = json!({"some": "thing"});
+        let hcl_report = HclReport::new(REPORT.to_vec()).unwrap();
+        let var_data_hash = hcl_report.var_data_sha256();
+        extend_claim_with_hcl_runtime_data(&mut claim, REPORT, &var_data_hash).unwrap();
+
+        let hcl = claim.get("hcl").unwrap();
+        assert_eq!(hcl["vm_configuration"]["secure-boot"], json!(true));
+        assert_eq!(
+            hcl["vm_configuration"]["vmUniqueId"],
+            json!("911B0EF8-9DBA-48B0-B275-4E4DF2D39252")
+        );
+        assert_eq!(hcl["user_data"], json!("0".repeat(128)));
+    }
+
+    #[test]
+    fn test_extend_claim_with_hcl_runtime_data_failure() {
+        let mut claim = json!({});
+        let mut wrong_report = REPORT.clone();
+        // messing with the VM configuration in var data
+        wrong_report[HCL_VAR_DATA_OFFSET + 0x0400] ^= 1;
+        let hcl_report = HclReport::new(REPORT.to_vec()).unwrap();
+        let var_data_hash = hcl_report.var_data_sha256();
+        extend_claim_with_hcl_runtime_data(&mut claim, &wrong_report, &var_data_hash).unwrap_err();
+    }
 }
@@ -58,13 +58,14 @@ impl Verifier for AzTdxVtpm {
 
         verify_pcrs(&evidence.tpm_quote)?;
 
-        ecdsa_quote_verification(&evidence.td_quote).await?;
+        let tcb_status = ecdsa_quote_verification(&evidence.td_quote).await?;
         let td_quote = parse_tdx_quote(&evidence.td_quote)?;
 
         verify_hcl_var_data(&hcl_report, &td_quote)?;
 
         let mut claim = generate_parsed_claim(td_quote, None, None)?;
         extend_claim_with_tpm_quote(&mut claim, &evidence.tpm_quote)?;
+        claim["tcb_status"] = serde_json::json!(tcb_status);
 
         Ok(claim)
     }
@@ -0,0 +1,531 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Local verification of CCA attestation tokens.
+//!
+//! A CCA attestation token is a CBOR tagged collection of a realm token,
+//! signed by the Realm Attestation Key (RAK), and of a platform token, signed
+//! by the CCA Platform Attestation Key (CPAK). The RAK is given by the realm
+//! token itself and bound to the platform token by the platform challenge,
+//! and the CPAK must be one of the configured trust anchors, or be certified
+//! by them.
+
+use super::{CcaPlatformClaims, Evidence, RealmClaims, SwComponent};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ciborium::Value;
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey, EcPoint};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Public};
+use openssl::sign::Verifier;
+use openssl::stack::Stack;
+use openssl::x509::store::{X509Store, X509StoreBuilder};
+use openssl::x509::{X509StoreContext, X509};
+
+const CCA_TOKEN_COLLECTION_TAG: u64 = 399;
+const COSE_SIGN1_TAG: u64 = 18;
+
+const PLATFORM_TOKEN: i128 = 44234;
+const REALM_TOKEN: i128 = 44241;
+
+const CHALLENGE: i128 = 10;
+
+const REALM_PERSONALIZATION_VALUE: i128 = 44235;
+const REALM_HASH_ALGO_ID: i128 = 44236;
+const REALM_PUBLIC_KEY: i128 = 44237;
+const REALM_INITIAL_MEASUREMENT: i128 = 44238;
+const REALM_EXTENSIBLE_MEASUREMENTS: i128 = 44239;
+const REALM_PUBLIC_KEY_HASH_ALGO_ID: i128 = 44240;
+
+const PLATFORM_SW_COMPONENTS: i128 = 2399;
+const SW_COMPONENT_MEASUREMENT_TYPE: i128 = 1;
+const SW_COMPONENT_MEASUREMENT_VALUE: i128 = 2;
+const SW_COMPONENT_VERSION: i128 = 4;
+const SW_COMPONENT_SIGNER_ID: i128 = 5;
+
+const COSE_HEADER_ALG: i128 = 1;
+const COSE_ALG_ES256: i128 = -7;
+const COSE_ALG_ES384: i128 = -35;
+const COSE_ALG_ES512: i128 = -36;
+
+const COSE_KEY_CRV: i128 = -1;
+const COSE_KEY_X: i128 = -2;
+const COSE_KEY_Y: i128 = -3;
+
+/// Keys and certificates trusted to be, or to certify, a CPAK.
+pub struct TrustAnchors {
+    keys: Vec<PKey<Public>>,
+    store: X509Store,
+}
+
+impl TrustAnchors {
+    /// Load the PEM encoded public keys and certificates of `pem`.
+    pub fn from_pem(pem: &[u8]) -> Result<Self> {
+        let pem = std::str::from_utf8(pem).context("CCA trust anchors are not PEM")?;
+        let mut keys = Vec::new();
+        let mut store = X509StoreBuilder::new()?;
+        for block in pem.split("-----BEGIN ").skip(1) {
+            let block = format!("-----BEGIN {block}");
+            if block.starts_with("-----BEGIN CERTIFICATE-----") {
+                let cert = X509::from_pem(block.as_bytes()).context("illegal CCA trust anchor")?;
+                keys.push(cert.public_key()?);
+                store.add_cert(cert)?;
+            } else {
+                keys.push(
+                    PKey::public_key_from_pem(block.as_bytes())
+                        .context("illegal CCA trust anchor")?,
+                );
+            }
+        }
+
+        if keys.is_empty() {
+            bail!("no CCA trust anchor found");
+        }
+
+        Ok(Self {
+            keys,
+            store: store.build(),
+        })
+    }
+
+    /// The CPAK of the platform token. With a certificate chain, the CPAK is
+    /// the key of the leaf certificate, that must chain up to a trusted
+    /// certificate. Without, the CPAK is the trusted key verifying the token.
+    fn cpak(&self, platform_token: &CoseSign1, cpak_chain: &[Vec<u8>]) -> Result<PKey<Public>> {
+        let Some((leaf, intermediates)) = cpak_chain.split_first() else {
+            return self
+                .keys
+                .iter()
+                .find(|key| platform_token.verify(key).unwrap_or(false))
+                .cloned()
+                .ok_or_else(|| anyhow!("CCA platform token is not signed by a trusted CPAK"));
+        };
+
+        let leaf = X509::from_der(leaf).context("illegal CPAK certificate")?;
+        let mut chain = Stack::new()?;
+        for cert in intermediates {
+            chain.push(X509::from_der(cert).context("illegal CPAK certificate chain")?)?;
+        }
+
+        let mut context = X509StoreContext::new()?;
+        if !context.init(&self.store, &leaf, &chain, |ctx| ctx.verify_cert())? {
+            bail!("CPAK certificate is not trusted");
+        }
+
+        let cpak = leaf.public_key()?;
+        if !platform_token.verify(&cpak)? {
+            bail!("CCA platform token signature verification failed");
+        }
+
+        Ok(cpak)
+    }
+}
+
+/// A COSE_Sign1 message.
+struct CoseSign1 {
+    protected: Vec<u8>,
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl CoseSign1 {
+    fn parse(message: &[u8]) -> Result<Self> {
+        let value: Value = ciborium::from_reader(message).context("illegal COSE_Sign1")?;
+        let value = match value {
+            Value::Tag(COSE_SIGN1_TAG, value) => *value,
+            value => value,
+        };
+        let Value::Array(items) = value else {
+            bail!("COSE_Sign1 is not an array");
+        };
+        let [Value::Bytes(protected), _, Value::Bytes(payload), Value::Bytes(signature)] =
+            <[Value; 4]>::try_from(items).map_err(|_| anyhow!("illegal COSE_Sign1 length"))?
+        else {
+            bail!("illegal COSE_Sign1 fields");
+        };
+
+        Ok(Self {
+            protected,
+            payload,
+            signature,
+        })
+    }
+
+    fn claims(&self) -> Result<Vec<(Value, Value)>> {
+        match ciborium::from_reader(self.payload.as_slice()).context("illegal token payload")? {
+            Value::Map(claims) => Ok(claims),
+            _ => bail!("token payload is not a map"),
+        }
+    }
+
+    /// Verify the ECDSA signature of the message with `key`.
+    fn verify(&self, key: &PKey<Public>) -> Result<bool> {
+        let digest = match self.alg()? {
+            COSE_ALG_ES256 => MessageDigest::sha256(),
+            COSE_ALG_ES384 => MessageDigest::sha384(),
+            COSE_ALG_ES512 => MessageDigest::sha512(),
+            alg => bail!("unsupported COSE algorithm {alg}"),
+        };
+
+        let (r, s) = self.signature.split_at(self.signature.len() / 2);
+        let signature =
+            EcdsaSig::from_private_components(BigNum::from_slice(r)?, BigNum::from_slice(s)?)?
+                .to_der()?;
+
+        let to_be_signed = Value::Array(vec![
+            Value::Text("Signature1".into()),
+            Value::Bytes(self.protected.clone()),
+            Value::Bytes(Vec::new()),
+            Value::Bytes(self.payload.clone()),
+        ]);
+        let mut message = Vec::new();
+        ciborium::into_writer(&to_be_signed, &mut message)?;
+
+        let mut verifier = Verifier::new(digest, key)?;
+        Ok(verifier.verify_oneshot(&signature, &message)?)
+    }
+
+    fn alg(&self) -> Result<i128> {
+        let Value::Map(header) =
+            ciborium::from_reader(self.protected.as_slice()).context("illegal COSE header")?
+        else {
+            bail!("COSE protected header is not a map");
+        };
+
+        match claim(&header, COSE_HEADER_ALG) {
+            Some(Value::Integer(alg)) => Ok(i128::from(*alg)),
+            _ => bail!("COSE protected header has no algorithm"),
+        }
+    }
+}
+
+/// Verify a CCA attestation token and the binding of its realm challenge to
+/// `expected_challenge`, and return its claims.
+pub fn verify_token(
+    token: &[u8],
+    cpak_chain: &[Vec<u8>],
+    trust_anchors: &TrustAnchors,
+    expected_challenge: &[u8],
+) -> Result<Evidence> {
+    let collection: Value = ciborium::from_reader(token).context("illegal CCA token")?;
+    let collection = match collection {
+        Value::Tag(CCA_TOKEN_COLLECTION_TAG, collection) => *collection,
+        collection => collection,
+    };
+    let Value::Map(collection) = collection else {
+        bail!("CCA token is not a token collection");
+    };
+
+    let platform_token =
+        CoseSign1::parse(bytes_claim(&collection, PLATFORM_TOKEN, "platform token")?)?;
+    let realm_token = CoseSign1::parse(bytes_claim(&collection, REALM_TOKEN, "realm token")?)?;
+    let realm_claims = realm_token.claims()?;
+    let platform_claims = platform_token.claims()?;
+
+    // The realm token is signed by the RAK it holds.
+    let rak_claim = bytes_claim(&realm_claims, REALM_PUBLIC_KEY, "realm public key")?;
+    let rak = parse_public_key(rak_claim)?;
+    if !realm_token.verify(&rak)? {
+        bail!("CCA realm token signature verification failed");
+    }
+
+    // The RAK is bound to the platform by the platform challenge.
+    let rak_hash_algo = text_claim(
+        &realm_claims,
+        REALM_PUBLIC_KEY_HASH_ALGO_ID,
+        "realm public key hash algorithm",
+    )?;
+    let rak_hash = hash(message_digest(rak_hash_algo)?, rak_claim)?;
+    if bytes_claim(&platform_claims, CHALLENGE, "platform challenge")? != &rak_hash[..] {
+        bail!("CCA platform challenge does not match the realm public key");
+    }
+
+    trust_anchors.cpak(&platform_token, cpak_chain)?;
+
+    if bytes_claim(&realm_claims, CHALLENGE, "realm challenge")? != expected_challenge {
+        bail!("CCA realm challenge is different from the expected report data");
+    }
+
+    Ok(Evidence {
+        realm: realm(&realm_claims)?,
+        platform: platform(&platform_claims)?,
+    })
+}
+
+fn realm(claims: &[(Value, Value)]) -> Result<RealmClaims> {
+    let Some(Value::Array(rems)) = claim(claims, REALM_EXTENSIBLE_MEASUREMENTS) else {
+        bail!("CCA realm token has no extensible measurements");
+    };
+    let rems = rems
+        .iter()
+        .map(|rem| match rem {
+            Value::Bytes(rem) => Ok(STANDARD.encode(rem)),
+            _ => bail!("illegal CCA realm extensible measurement"),
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(RealmClaims {
+        cca_realm_personalization_value: STANDARD.encode(bytes_claim(
+            claims,
+            REALM_PERSONALIZATION_VALUE,
+            "realm personalization value",
+        )?),
+        cca_realm_initial_measurement: STANDARD.encode(bytes_claim(
+            claims,
+            REALM_INITIAL_MEASUREMENT,
+            "realm initial measurement",
+        )?),
+        cca_realm_extensible_measurements: rems,
+        cca_realm_hash_algo_id: text_claim(claims, REALM_HASH_ALGO_ID, "realm hash algorithm")?
+            .to_string(),
+        cca_realm_public_key_hash_algo_id: text_claim(
+            claims,
+            REALM_PUBLIC_KEY_HASH_ALGO_ID,
+            "realm public key hash algorithm",
+        )?
+        .to_string(),
+    })
+}
+
+fn platform(claims: &[(Value, Value)]) -> Result<CcaPlatformClaims> {
+    let Some(Value::Array(components)) = claim(claims, PLATFORM_SW_COMPONENTS) else {
+        bail!("CCA platform token has no software components");
+    };
+
+    let mut sw_components = Vec::new();
+    for component in components {
+        let Value::Map(component) = component else {
+            bail!("illegal CCA platform software component");
+        };
+        let text = |key| match claim(component, key) {
+            Some(Value::Text(text)) => text.clone(),
+            _ => String::new(),
+        };
+        let bytes = |key| match claim(component, key) {
+            Some(Value::Bytes(bytes)) => STANDARD.encode(bytes),
+            _ => String::new(),
+        };
+        sw_components.push(SwComponent {
+            measurement_type: text(SW_COMPONENT_MEASUREMENT_TYPE),
+            measurement_value: bytes(SW_COMPONENT_MEASUREMENT_VALUE),
+            version: text(SW_COMPONENT_VERSION),
+            signer_id: bytes(SW_COMPONENT_SIGNER_ID),
+        });
+    }
+
+    Ok(CcaPlatformClaims {
+        cca_platform_challenge: STANDARD.encode(bytes_claim(
+            claims,
+            CHALLENGE,
+            "platform challenge",
+        )?),
+        cca_platform_sw_components: sw_components,
+    })
+}
+
+/// Parse the RAK, either a raw uncompressed EC point or a COSE_Key.
+fn parse_public_key(key: &[u8]) -> Result<PKey<Public>> {
+    let (nid, point) = match key.first() {
+        Some(0x04) => {
+            let nid = match key.len() {
+                65 => Nid::X9_62_PRIME256V1,
+                97 => Nid::SECP384R1,
+                133 => Nid::SECP521R1,
+                len => bail!("illegal realm public key length {len}"),
+            };
+            (nid, key.to_vec())
+        }
+        _ => {
+            let Value::Map(cose_key) =
+                ciborium::from_reader(key).context("illegal realm public key")?
+            else {
+                bail!("realm public key is not a COSE_Key");
+            };
+            let nid = match claim(&cose_key, COSE_KEY_CRV) {
+                Some(Value::Integer(crv)) => match i128::from(*crv) {
+                    1 => Nid::X9_62_PRIME256V1,
+                    2 => Nid::SECP384R1,
+                    3 => Nid::SECP521R1,
+                    crv => bail!("unsupported realm public key curve {crv}"),
+                },
+                _ => bail!("realm public key has no curve"),
+            };
+            let mut point = vec![0x04];
+            point.extend(bytes_claim(&cose_key, COSE_KEY_X, "realm public key x")?);
+            point.extend(bytes_claim(&cose_key, COSE_KEY_Y, "realm public key y")?);
+            (nid, point)
+        }
+    };
+
+    let group = EcGroup::from_curve_name(nid)?;
+    let mut context = BigNumContext::new()?;
+    let point =
+        EcPoint::from_bytes(&group, &point, &mut context).context("illegal realm public key")?;
+    Ok(PKey::from_ec_key(EcKey::from_public_key(&group, &point)?)?)
+}
+
+fn message_digest(algo: &str) -> Result<MessageDigest> {
+    match algo {
+        "sha-256" => Ok(MessageDigest::sha256()),
+        "sha-384" => Ok(MessageDigest::sha384()),
+        "sha-512" => Ok(MessageDigest::sha512()),
+        algo => bail!("unsupported hash algorithm {algo}"),
+    }
+}
+
+fn claim(claims: &[(Value, Value)], key: i128) -> Option<&Value> {
+    claims
+        .iter()
+        .find(|(k, _)| matches!(k, Value::Integer(k) if i128::from(*k) == key))
+        .map(|(_, v)| v)
+}
+
+fn bytes_claim<'a>(claims: &'a [(Value, Value)], key: i128, name: &str) -> Result<&'a [u8]> {
+    match claim(claims, key) {
+        Some(Value::Bytes(bytes)) => Ok(bytes),
+        _ => bail!("CCA token has no {name}"),
+    }
+}
+
+fn text_claim<'a>(claims: &'a [(Value, Value)], key: i128, name: &str) -> Result<&'a str> {
+    match claim(claims, key) {
+        Some(Value::Text(text)) => Ok(text),
+        _ => bail!("CCA token has no {name}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::ec::PointConversionForm;
+    use openssl::pkey::Private;
+    use openssl::sign::Signer;
+
+    fn int(key: i128) -> Value {
+        Value::Integer(key.try_into().unwrap())
+    }
+
+    fn cbor(value: &Value) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn p384_key() -> EcKey<Private> {
+        EcKey::generate(&EcGroup::from_curve_name(Nid::SECP384R1).unwrap()).unwrap()
+    }
+
+    /// A COSE_Sign1 ES384 message of `claims` signed by `key`.
+    fn sign(key: &EcKey<Private>, claims: Vec<(Value, Value)>) -> Vec<u8> {
+        let protected = cbor(&Value::Map(vec![(
+            int(COSE_HEADER_ALG),
+            int(COSE_ALG_ES384),
+        )]));
+        let payload = cbor(&Value::Map(claims));
+        let to_be_signed = cbor(&Value::Array(vec![
+            Value::Text("Signature1".into()),
+            Value::Bytes(protected.clone()),
+            Value::Bytes(Vec::new()),
+            Value::Bytes(payload.clone()),
+        ]));
+
+        let pkey = PKey::from_ec_key(key.clone()).unwrap();
+        let mut signer = Signer::new(MessageDigest::sha384(), &pkey).unwrap();
+        let der = signer.sign_oneshot_to_vec(&to_be_signed).unwrap();
+        let der = EcdsaSig::from_der(&der).unwrap();
+        let mut signature = der.r().to_vec_padded(48).unwrap();
+        signature.extend(der.s().to_vec_padded(48).unwrap());
+
+        cbor(&Value::Tag(
+            COSE_SIGN1_TAG,
+            Box::new(Value::Array(vec![
+                Value::Bytes(protected),
+                Value::Map(Vec::new()),
+                Value::Bytes(payload),
+                Value::Bytes(signature),
+            ])),
+        ))
+    }
+
+    fn token(cpak: &EcKey<Private>, challenge: &[u8]) -> Vec<u8> {
+        let rak = p384_key();
+        let mut context = BigNumContext::new().unwrap();
+        let rak_point = rak
+            .public_key()
+            .to_bytes(rak.group(), PointConversionForm::UNCOMPRESSED, &mut context)
+            .unwrap();
+        let rak_hash = hash(MessageDigest::sha256(), &rak_point).unwrap().to_vec();
+
+        let realm_token = sign(
+            &rak,
+            vec![
+                (int(CHALLENGE), Value::Bytes(challenge.to_vec())),
+                (int(REALM_PERSONALIZATION_VALUE), Value::Bytes(vec![0; 64])),
+                (int(REALM_HASH_ALGO_ID), Value::Text("sha-256".into())),
+                (int(REALM_PUBLIC_KEY), Value::Bytes(rak_point)),
+                (int(REALM_INITIAL_MEASUREMENT), Value::Bytes(vec![1; 32])),
+                (
+                    int(REALM_EXTENSIBLE_MEASUREMENTS),
+                    Value::Array(vec![Value::Bytes(vec![2; 32]); 4]),
+                ),
+                (
+                    int(REALM_PUBLIC_KEY_HASH_ALGO_ID),
+                    Value::Text("sha-256".into()),
+                ),
+            ],
+        );
+        let platform_token = sign(
+            cpak,
+            vec![
+                (int(CHALLENGE), Value::Bytes(rak_hash)),
+                (
+                    int(PLATFORM_SW_COMPONENTS),
+                    Value::Array(vec![Value::Map(vec![
+                        (int(SW_COMPONENT_MEASUREMENT_TYPE), Value::Text("BL".into())),
+                        (
+                            int(SW_COMPONENT_MEASUREMENT_VALUE),
+                            Value::Bytes(vec![3; 32]),
+                        ),
+                    ])]),
+                ),
+            ],
+        );
+
+        cbor(&Value::Tag(
+            CCA_TOKEN_COLLECTION_TAG,
+            Box::new(Value::Map(vec![
+                (int(PLATFORM_TOKEN), Value::Bytes(platform_token)),
+                (int(REALM_TOKEN), Value::Bytes(realm_token)),
+            ])),
+        ))
+    }
+
+    #[test]
+    fn verify_cca_token() {
+        let cpak = p384_key();
+        let anchors = TrustAnchors::from_pem(&cpak.public_key_to_pem().unwrap()).unwrap();
+        let challenge = [7; 64];
+
+        let evidence = verify_token(&token(&cpak, &challenge), &[], &anchors, &challenge).unwrap();
+        assert_eq!(
+            evidence.realm.cca_realm_initial_measurement,
+            STANDARD.encode([1; 32])
+        );
+        assert_eq!(evidence.realm.cca_realm_extensible_measurements.len(), 4);
+        assert_eq!(
+            evidence.platform.cca_platform_sw_components[0].measurement_type,
+            "BL"
+        );
+
+        // Wrong challenge.
+        assert!(verify_token(&token(&cpak, &challenge), &[], &anchors, &[0; 64]).is_err());
+
+        // Untrusted CPAK.
+        assert!(verify_token(&token(&p384_key(), &challenge), &[], &anchors, &challenge).is_err());
+    }
+}
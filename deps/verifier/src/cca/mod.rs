@@ -15,12 +15,37 @@ use serde::{Deserialize, Serialize};
 use std::{collections::BTreeMap, str};
 use veraison_apiclient::*;
 
+mod local;
+
 const VERAISON_ADDR: &str = "VERAISON_ADDR";
 const DEFAULT_VERAISON_ADDR: &str = "localhost:8080";
 const MEDIA_TYPE: &str = "application/eat-collection; profile=http://arm.com/CCA-SSD/1.0.0";
 
-#[derive(Debug, Default)]
-pub struct CCA {}
+/// Path to a PEM file of the public keys and certificates trusted as, or to
+/// certify, the CPAK. If set, or in offline mode, CCA tokens are verified
+/// locally instead of by the Veraison service.
+const CCA_TRUST_ANCHORS: &str = "CCA_TRUST_ANCHORS";
+
+#[derive(Default)]
+pub struct CCA {
+    trust_anchors: Option<local::TrustAnchors>,
+}
+
+impl CCA {
+    pub fn new() -> Result<Self> {
+        let trust_anchors =
+            match crate::offline::env_or_bundle(CCA_TRUST_ANCHORS, "cca/trust-anchors.pem") {
+                Some(path) => {
+                    let pem = std::fs::read(&path)
+                        .with_context(|| format!("read CCA trust anchors {path}"))?;
+                    Some(local::TrustAnchors::from_pem(&pem)?)
+                }
+                None => None,
+            };
+
+        Ok(Self { trust_anchors })
+    }
+}
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -58,6 +83,11 @@ struct Evidence {
 struct CcaEvidence {
     /// CCA token
     token: Vec<u8>,
+
+    /// DER certificate chain of the CPAK, leaf first, for the trust anchors
+    /// to certify it.
+    #[serde(default)]
+    cpak_chain: Vec<Vec<u8>>,
 }
 
 fn my_evidence_builder(
@@ -88,59 +118,18 @@ impl Verifier for CCA {
         let evidence = serde_json::from_slice::<CcaEvidence>(evidence)
             .context("Deserialize CCA Evidence failed.")?;
 
-        let host_url =
-            std::env::var(VERAISON_ADDR).unwrap_or_else(|_| DEFAULT_VERAISON_ADDR.to_string());
-
-        let discovery = Discovery::from_base_url(format!("http://{:}", host_url))?;
-
-        let verification_api = discovery.get_verification_api().await?;
-
-        let relative_endpoint = verification_api
-            .get_api_endpoint("newChallengeResponseSession")
-            .context("Failed to discover the verification endpoint details.")?;
-
-        let api_endpoint = format!("http://{:}{}", host_url, relative_endpoint);
-
-        // create a ChallengeResponse object
-        let cr = ChallengeResponseBuilder::new()
-            .with_new_session_url(api_endpoint)
-            .build()?;
-
-        let token = evidence.token;
-        let n = Nonce::Value(expected_report_data.clone());
-        let result = match cr.run(n, my_evidence_builder, token.clone()).await {
-            Err(e) => {
-                error!("Error: {}", e);
-                bail!("CCA Attestation failed with error: {:?}", e);
+        let tcb = match &self.trust_anchors {
+            Some(trust_anchors) => local::verify_token(
+                &evidence.token,
+                &evidence.cpak_chain,
+                trust_anchors,
+                &expected_report_data,
+            )?,
+            None if crate::offline::enabled() => {
+                bail!("No CCA trust anchors in the offline bundle")
             }
-            Ok(attestation_result) => attestation_result,
-        };
-
-        // Get back the pub key to decrypt the ear which holds raw evidence and the session nonce
-        let public_key_pem = verification_api.ear_verification_key_as_pem()?;
-        let dk = jwt::DecodingKey::from_ec_pem(public_key_pem.as_bytes())
-            .context("get the decoding key from the pem public key")?;
-        let plain_ear = Ear::from_jwt(result.as_str(), jwt::Algorithm::ES256, &dk)
-            .context("decrypt the ear with the decoding key")?;
-
-        let ear_nonce = plain_ear.nonce.context("get nonce from ear")?;
-        let nonce_byte = base64::engine::general_purpose::STANDARD
-            .decode(ear_nonce.to_string())
-            .context("decode nonce byte from ear")?;
-
-        if expected_report_data != nonce_byte {
-            bail!("report data is different from that in ear's session nonce");
-        }
-
-        let cca_mod = match plain_ear.submods.get("CCA_SSD_PLATFORM") {
-            Some(value) => value,
-            None => bail!("no entry found for CCA_SSD_PLATFORM"),
+            None => veraison_verify(evidence.token, expected_report_data).await?,
         };
-        let evidence = &cca_mod.annotated_evidence;
-
-        // NOTE: CCA validation by the Verasion has some overlapping with the RVPS, the similar validation has been done by the Verasion already.
-        // The generation of CCA evidence here is to align with other verifier, e.g. TDX, to support initdata mechanism and RVPS if that is the case of future planning.
-        let tcb = parse_cca_evidence(evidence)?;
 
         if let InitDataHash::Value(expected_init_data_hash) = expected_init_data_hash {
             debug!("Check the binding of init data.");
@@ -155,8 +144,68 @@ impl Verifier for CCA {
         }
 
         // Return Evidence parsed claim
-        cca_generate_parsed_claim(tcb).map_err(|e| anyhow!("error from CCA Verifier: {:?}", e))
+        let mut claims = cca_generate_parsed_claim(tcb)
+            .map_err(|e| anyhow!("error from CCA Verifier: {:?}", e))?;
+        // Both Veraison and the local verification checked the realm challenge.
+        claims["report_data"] = hex::encode(expected_report_data).into();
+        Ok(claims)
+    }
+}
+
+/// Verify the CCA token by the Veraison service, and return its claims.
+async fn veraison_verify(token: Vec<u8>, expected_report_data: Vec<u8>) -> Result<Evidence> {
+    let host_url =
+        std::env::var(VERAISON_ADDR).unwrap_or_else(|_| DEFAULT_VERAISON_ADDR.to_string());
+
+    let discovery = Discovery::from_base_url(format!("http://{:}", host_url))?;
+
+    let verification_api = discovery.get_verification_api().await?;
+
+    let relative_endpoint = verification_api
+        .get_api_endpoint("newChallengeResponseSession")
+        .context("Failed to discover the verification endpoint details.")?;
+
+    let api_endpoint = format!("http://{:}{}", host_url, relative_endpoint);
+
+    // create a ChallengeResponse object
+    let cr = ChallengeResponseBuilder::new()
+        .with_new_session_url(api_endpoint)
+        .build()?;
+
+    let n = Nonce::Value(expected_report_data.clone());
+    let result = match cr.run(n, my_evidence_builder, token).await {
+        Err(e) => {
+            error!("Error: {}", e);
+            bail!("CCA Attestation failed with error: {:?}", e);
+        }
+        Ok(attestation_result) => attestation_result,
+    };
+
+    // Get back the pub key to decrypt the ear which holds raw evidence and the session nonce
+    let public_key_pem = verification_api.ear_verification_key_as_pem()?;
+    let dk = jwt::DecodingKey::from_ec_pem(public_key_pem.as_bytes())
+        .context("get the decoding key from the pem public key")?;
+    let plain_ear = Ear::from_jwt(result.as_str(), jwt::Algorithm::ES256, &dk)
+        .context("decrypt the ear with the decoding key")?;
+
+    let ear_nonce = plain_ear.nonce.context("get nonce from ear")?;
+    let nonce_byte = base64::engine::general_purpose::STANDARD
+        .decode(ear_nonce.to_string())
+        .context("decode nonce byte from ear")?;
+
+    if expected_report_data != nonce_byte {
+        bail!("report data is different from that in ear's session nonce");
     }
+
+    let cca_mod = match plain_ear.submods.get("CCA_SSD_PLATFORM") {
+        Some(value) => value,
+        None => bail!("no entry found for CCA_SSD_PLATFORM"),
+    };
+    let evidence = &cca_mod.annotated_evidence;
+
+    // NOTE: CCA validation by the Verasion has some overlapping with the RVPS, the similar validation has been done by the Verasion already.
+    // The generation of CCA evidence here is to align with other verifier, e.g. TDX, to support initdata mechanism and RVPS if that is the case of future planning.
+    parse_cca_evidence(evidence)
 }
 
 /// The expected evidence layout looks like below,
@@ -246,8 +295,29 @@ fn parse_cca_evidence(evidence_map: &BTreeMap<String, RawValue>) -> Result<Evide
     Ok(evidence)
 }
 
+/// Besides the evidence itself, the claims hold the hex encoded realm
+/// measurements and init data hash under the keys shared with the other TEEs:
+/// `rim`, `rem` and `init_data`.
 fn cca_generate_parsed_claim(tcb: Evidence) -> Result<TeeEvidenceParsedClaim> {
-    let v = serde_json::to_value(tcb).context("build json value from the cca evidence")?;
+    let to_hex = |value: &str| -> Result<String> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(value)
+            .context("Failed to decode base64")?;
+        Ok(hex::encode(bytes))
+    };
+    let rim = to_hex(&tcb.realm.cca_realm_initial_measurement)?;
+    let rem = tcb
+        .realm
+        .cca_realm_extensible_measurements
+        .iter()
+        .map(|rem| to_hex(rem))
+        .collect::<Result<Vec<_>>>()?;
+    let init_data = to_hex(&tcb.realm.cca_realm_personalization_value)?;
+
+    let mut v = serde_json::to_value(tcb).context("build json value from the cca evidence")?;
+    v["rim"] = rim.into();
+    v["rem"] = rem.into();
+    v["init_data"] = init_data.into();
     Ok(v as TeeEvidenceParsedClaim)
 }
 
@@ -263,6 +333,9 @@ mod tests {
         let tcb = serde_json::from_str::<Evidence>(&evidence).unwrap();
         let parsed_claim = cca_generate_parsed_claim(tcb);
         assert!(parsed_claim.is_ok());
+        let claims = parsed_claim.as_ref().unwrap();
+        assert_eq!(claims["rem"].as_array().unwrap().len(), 4);
+        assert!(claims["rim"].is_string());
         let _ = fs::write(
             "test_data/cca_evidence_claim_output.txt",
             format!("{:?}", parsed_claim.unwrap()),
@@ -0,0 +1,328 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Cache of the collateral of the SGX and TDX quotes, i.e. the QE identity,
+//! the TCB info and the CRLs, so that the quotes can still be verified when
+//! the Intel PCS or the PCCS is not reachable.
+//!
+//! The collateral of a platform is refreshed after `DCAP_COLLATERAL_REFRESH_SECS`
+//! seconds, one day by default, and the cached one is used as long as it can
+//! not be refreshed. It is persisted in `DCAP_COLLATERAL_CACHE_DIR` if set.
+//!
+//! In offline mode, the collateral is only read from the `intel` directory
+//! of the offline bundle, and never fetched nor refreshed.
+
+use anyhow::*;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use core::result::Result::Ok;
+use intel_tee_quote_verification_rs::{
+    quote3_error_t, sgx_ql_qv_result_t, tee_qv_get_collateral, Collateral,
+};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+const DCAP_COLLATERAL_CACHE_DIR: &str = "DCAP_COLLATERAL_CACHE_DIR";
+const DCAP_COLLATERAL_REFRESH_SECS: &str = "DCAP_COLLATERAL_REFRESH_SECS";
+const DEFAULT_REFRESH_SECS: u64 = 24 * 60 * 60;
+
+const PEM_BEGIN: &[u8] = b"-----BEGIN CERTIFICATE-----";
+const PEM_END: &[u8] = b"-----END CERTIFICATE-----";
+
+/// The collateral of a quote, from the cache or fetched by the quote
+/// verification library. `None` lets the library fetch it itself, which
+/// never happens in offline mode.
+pub(crate) fn get_collateral(quote: &[u8]) -> Result<Option<Collateral>> {
+    static CACHE: OnceLock<CollateralCache> = OnceLock::new();
+    let cache = CACHE.get_or_init(CollateralCache::from_env);
+    if cache.offline {
+        let collateral = cache.get(quote, |_| Err(quote3_error_t::SGX_QL_NETWORK_ERROR));
+        if collateral.is_none() {
+            bail!("No collateral of the platform of the quote in the offline bundle");
+        }
+        return Ok(collateral);
+    }
+
+    Ok(cache.get(quote, tee_qv_get_collateral))
+}
+
+/// The TCB status, as named in the Intel TCB info, of the platform of a
+/// quote accepted by the quote verification library.
+pub(crate) fn tcb_status(quote_verification_result: sgx_ql_qv_result_t) -> &'static str {
+    match quote_verification_result {
+        sgx_ql_qv_result_t::SGX_QL_QV_RESULT_OK => "UpToDate",
+        sgx_ql_qv_result_t::SGX_QL_QV_RESULT_SW_HARDENING_NEEDED => "SWHardeningNeeded",
+        sgx_ql_qv_result_t::SGX_QL_QV_RESULT_CONFIG_NEEDED => "ConfigurationNeeded",
+        sgx_ql_qv_result_t::SGX_QL_QV_RESULT_CONFIG_AND_SW_HARDENING_NEEDED => {
+            "ConfigurationAndSWHardeningNeeded"
+        }
+        sgx_ql_qv_result_t::SGX_QL_QV_RESULT_OUT_OF_DATE => "OutOfDate",
+        sgx_ql_qv_result_t::SGX_QL_QV_RESULT_OUT_OF_DATE_CONFIG_NEEDED => {
+            "OutOfDateConfigurationNeeded"
+        }
+        _ => "Invalid",
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct CachedCollateral {
+    /// Seconds since the epoch when the collateral was fetched.
+    fetched_at: u64,
+    major_version: u16,
+    minor_version: u16,
+    tee_type: u32,
+    pck_crl_issuer_chain: String,
+    root_ca_crl: String,
+    pck_crl: String,
+    tcb_info_issuer_chain: String,
+    tcb_info: String,
+    qe_identity_issuer_chain: String,
+    qe_identity: String,
+}
+
+impl CachedCollateral {
+    fn new(collateral: &Collateral, fetched_at: u64) -> Self {
+        Self {
+            fetched_at,
+            major_version: collateral.major_version,
+            minor_version: collateral.minor_version,
+            tee_type: collateral.tee_type,
+            pck_crl_issuer_chain: STANDARD.encode(&collateral.pck_crl_issuer_chain),
+            root_ca_crl: STANDARD.encode(&collateral.root_ca_crl),
+            pck_crl: STANDARD.encode(&collateral.pck_crl),
+            tcb_info_issuer_chain: STANDARD.encode(&collateral.tcb_info_issuer_chain),
+            tcb_info: STANDARD.encode(&collateral.tcb_info),
+            qe_identity_issuer_chain: STANDARD.encode(&collateral.qe_identity_issuer_chain),
+            qe_identity: STANDARD.encode(&collateral.qe_identity),
+        }
+    }
+
+    fn to_collateral(&self) -> Result<Collateral> {
+        let decode = |data: &str| -> Result<Box<[u8]>> { Ok(STANDARD.decode(data)?.into()) };
+        Ok(Collateral {
+            major_version: self.major_version,
+            minor_version: self.minor_version,
+            tee_type: self.tee_type,
+            pck_crl_issuer_chain: decode(&self.pck_crl_issuer_chain)?,
+            root_ca_crl: decode(&self.root_ca_crl)?,
+            pck_crl: decode(&self.pck_crl)?,
+            tcb_info_issuer_chain: decode(&self.tcb_info_issuer_chain)?,
+            tcb_info: decode(&self.tcb_info)?,
+            qe_identity_issuer_chain: decode(&self.qe_identity_issuer_chain)?,
+            qe_identity: decode(&self.qe_identity)?,
+        })
+    }
+}
+
+struct CollateralCache {
+    dir: Option<PathBuf>,
+    refresh: Duration,
+
+    /// Whether the collateral is only read from `dir`.
+    offline: bool,
+    entries: Mutex<HashMap<String, CachedCollateral>>,
+}
+
+impl CollateralCache {
+    fn from_env() -> Self {
+        if let Some(bundle) = crate::offline::bundle() {
+            let mut cache = Self::new(Some(bundle.join("intel")), Duration::MAX);
+            cache.offline = true;
+            return cache;
+        }
+
+        let dir = std::env::var(DCAP_COLLATERAL_CACHE_DIR)
+            .ok()
+            .map(PathBuf::from);
+        let refresh = std::env::var(DCAP_COLLATERAL_REFRESH_SECS)
+            .ok()
+            .and_then(|secs| match secs.parse() {
+                Ok(secs) => Some(secs),
+                Err(_) => {
+                    warn!("Illegal {DCAP_COLLATERAL_REFRESH_SECS} {secs}, the default is used.");
+                    None
+                }
+            })
+            .unwrap_or(DEFAULT_REFRESH_SECS);
+
+        Self::new(dir, Duration::from_secs(refresh))
+    }
+
+    fn new(dir: Option<PathBuf>, refresh: Duration) -> Self {
+        Self {
+            dir,
+            refresh,
+            offline: false,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get(
+        &self,
+        quote: &[u8],
+        fetch: impl FnOnce(&[u8]) -> Result<Collateral, quote3_error_t>,
+    ) -> Option<Collateral> {
+        let Some(key) = platform_key(quote) else {
+            warn!("No PCK certificate in the quote, its collateral is not cached.");
+            return fetch_collateral(quote, fetch);
+        };
+
+        let cached = self.lookup(&key);
+        let now = now();
+        if let Some(cached) = &cached {
+            if now.saturating_sub(cached.fetched_at) < self.refresh.as_secs() {
+                if let Ok(collateral) = cached.to_collateral() {
+                    debug!("Cached collateral of platform {key} is used.");
+                    return Some(collateral);
+                }
+            }
+        }
+
+        match fetch_collateral(quote, fetch) {
+            Some(collateral) => {
+                self.store(&key, CachedCollateral::new(&collateral, now));
+                Some(collateral)
+            }
+            None => {
+                let collateral = cached.and_then(|cached| cached.to_collateral().ok());
+                if collateral.is_some() {
+                    warn!("Collateral of platform {key} can not be refreshed, the cached one is used.");
+                }
+                collateral
+            }
+        }
+    }
+
+    fn lookup(&self, key: &str) -> Option<CachedCollateral> {
+        let mut entries = self.entries.lock().ok()?;
+        if let Some(cached) = entries.get(key) {
+            return Some(cached.clone());
+        }
+
+        let path = self.dir.as_ref()?.join(format!("{key}.json"));
+        let cached: CachedCollateral = std::fs::read(&path)
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())?;
+        entries.insert(key.to_string(), cached.clone());
+        Some(cached)
+    }
+
+    fn store(&self, key: &str, cached: CachedCollateral) {
+        if let Err(e) = self.persist(key, &cached) {
+            warn!("Failed to persist the collateral of platform {key}: {e:?}");
+        }
+
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.insert(key.to_string(), cached);
+        }
+    }
+
+    fn persist(&self, key: &str, cached: &CachedCollateral) -> Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+
+        std::fs::create_dir_all(dir).context("create collateral cache dir")?;
+        std::fs::write(dir.join(format!("{key}.json")), serde_json::to_vec(cached)?)
+            .context("write cached collateral")?;
+        Ok(())
+    }
+}
+
+fn fetch_collateral(
+    quote: &[u8],
+    fetch: impl FnOnce(&[u8]) -> Result<Collateral, quote3_error_t>,
+) -> Option<Collateral> {
+    match fetch(quote) {
+        Ok(collateral) => {
+            debug!("tee_qv_get_collateral successfully returned.");
+            Some(collateral)
+        }
+        Err(e) => {
+            warn!("tee_qv_get_collateral failed: {:#04x}", e as u32);
+            None
+        }
+    }
+}
+
+/// The key of the platform of a quote, i.e. the hash of its TEE type and of
+/// its PCK certificate, the first of its certification data.
+fn platform_key(quote: &[u8]) -> Option<String> {
+    let tee_type = quote.get(4..8)?;
+    let begin = quote
+        .windows(PEM_BEGIN.len())
+        .position(|window| window == PEM_BEGIN)?;
+    let end = quote[begin..]
+        .windows(PEM_END.len())
+        .position(|window| window == PEM_END)?;
+    let pck_cert = &quote[begin..begin + end];
+
+    let mut hasher = Sha256::new();
+    hasher.update(tee_type);
+    hasher.update(pck_cert);
+    Some(hex::encode(hasher.finalize()))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUOTE: &[u8] = b"\x04\x00\x02\x00\x81\x00\x00\x00 ... -----BEGIN CERTIFICATE-----\nPCK\n-----END CERTIFICATE-----\n";
+
+    fn collateral(tcb_info: &[u8]) -> Collateral {
+        Collateral {
+            major_version: 3,
+            minor_version: 1,
+            tee_type: 0x81,
+            pck_crl_issuer_chain: Box::new([]),
+            root_ca_crl: Box::new([]),
+            pck_crl: Box::new([]),
+            tcb_info_issuer_chain: Box::new([]),
+            tcb_info: tcb_info.into(),
+            qe_identity_issuer_chain: Box::new([]),
+            qe_identity: Box::new([]),
+        }
+    }
+
+    #[test]
+    fn cache_collateral() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = CollateralCache::new(Some(dir.path().to_path_buf()), Duration::from_secs(60));
+
+        let fetched = cache.get(QUOTE, |_| Ok(collateral(b"tcb info")));
+        assert_eq!(&*fetched.unwrap().tcb_info, b"tcb info");
+
+        // Fresh collateral is not fetched again, even by another cache
+        // persisting it in the same directory.
+        let cache = CollateralCache::new(Some(dir.path().to_path_buf()), Duration::from_secs(60));
+        let cached = cache.get(QUOTE, |_| panic!("collateral fetched again"));
+        assert_eq!(&*cached.unwrap().tcb_info, b"tcb info");
+    }
+
+    #[test]
+    fn refresh_collateral() {
+        let cache = CollateralCache::new(None, Duration::ZERO);
+        cache.get(QUOTE, |_| Ok(collateral(b"old")));
+
+        let refreshed = cache.get(QUOTE, |_| Ok(collateral(b"new")));
+        assert_eq!(&*refreshed.unwrap().tcb_info, b"new");
+
+        // Stale collateral is used when it can not be refreshed.
+        let stale = cache.get(QUOTE, |_| Err(quote3_error_t::SGX_QL_NETWORK_ERROR));
+        assert_eq!(&*stale.unwrap().tcb_info, b"new");
+    }
+}
@@ -0,0 +1,345 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Appraisal of TDX and SGX quotes delegated to Intel Trust Authority. The
+//! claims of its attestation token are normalized into the ones of the local
+//! TDX and SGX verifiers, so that the same policies apply to both.
+
+use super::*;
+use core::result::Result::Ok;
+use jsonwebtoken::{decode, decode_header, jwk, Algorithm, DecodingKey, Validation};
+use log::{debug, info, warn};
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::BufReader;
+use std::str::FromStr;
+
+/// Token claims of the TDX quotes, with the path of their local TDX claim.
+const TDX_CLAIMS: &[(&str, &str)] = &[
+    ("tdx_mrtd", "quote.body.mr_td"),
+    ("tdx_rtmr0", "quote.body.rtmr_0"),
+    ("tdx_rtmr1", "quote.body.rtmr_1"),
+    ("tdx_rtmr2", "quote.body.rtmr_2"),
+    ("tdx_rtmr3", "quote.body.rtmr_3"),
+    ("tdx_mrseam", "quote.body.mr_seam"),
+    ("tdx_mrsignerseam", "quote.body.mrsigner_seam"),
+    ("tdx_mrconfigid", "quote.body.mr_config_id"),
+    ("tdx_mrowner", "quote.body.mr_owner"),
+    ("tdx_mrownerconfig", "quote.body.mr_owner_config"),
+    ("tdx_seam_attributes", "quote.body.seam_attributes"),
+    ("tdx_td_attributes", "quote.body.td_attributes"),
+    ("tdx_xfam", "quote.body.xfam"),
+    ("tdx_tee_tcb_svn", "quote.body.tcb_svn"),
+    ("tdx_report_data", "quote.body.report_data"),
+];
+
+/// Token claims of the SGX quotes, with the path of their local SGX claim.
+const SGX_CLAIMS: &[(&str, &str)] = &[
+    ("sgx_mrenclave", "body.mr_enclave"),
+    ("sgx_mrsigner", "body.mr_signer"),
+    ("sgx_isvprodid", "body.isv_prod_id"),
+    ("sgx_isvsvn", "body.isv_svn"),
+    ("sgx_config_id", "body.config_id"),
+    ("sgx_report_data", "body.report_data"),
+];
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct IntelTrustAuthorityConfig {
+    /// URL of the Intel Trust Authority API, e.g.
+    /// `https://api.trustauthority.intel.com`.
+    pub base_url: String,
+
+    pub api_key: String,
+
+    /// JSON Web Key Set of the token signing keys of Intel Trust Authority.
+    pub certs_file: String,
+
+    /// Accept the tokens that do not match the Intel Trust Authority
+    /// policies of the API key.
+    #[serde(default)]
+    pub allow_unmatched_policy: bool,
+}
+
+#[derive(Deserialize)]
+struct Evidence {
+    // Base64 encoded TD or SGX quote.
+    quote: String,
+}
+
+#[derive(Deserialize)]
+struct AttestResponse {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+pub struct IntelTrustAuthority {
+    config: IntelTrustAuthorityConfig,
+    certs: jwk::JwkSet,
+    tee: Tee,
+}
+
+impl IntelTrustAuthority {
+    /// The verifier of the `tee` evidence, either TDX or SGX.
+    pub fn new(config: IntelTrustAuthorityConfig, tee: Tee) -> Result<Self> {
+        if tee != Tee::Tdx && tee != Tee::Sgx {
+            bail!("Intel Trust Authority: TEE {tee:?} is not supported");
+        }
+
+        let file = File::open(&config.certs_file).context("open Intel Trust Authority certs")?;
+        let certs = serde_json::from_reader(BufReader::new(file))
+            .context("parse Intel Trust Authority certs")?;
+
+        Ok(Self { config, certs, tee })
+    }
+
+    /// Appraise `quote` and return the verified claims of the token.
+    async fn attest(&self, quote: String) -> Result<Map<String, Value>> {
+        let resp = reqwest::Client::new()
+            .post(format!("{}/appraisal/v1/attest", self.config.base_url))
+            .header(CONTENT_TYPE, "application/json")
+            .header(ACCEPT, "application/json")
+            .header("x-api-key", &self.config.api_key)
+            .json(&json!({ "quote": quote }))
+            .send()
+            .await
+            .context("post Intel Trust Authority attestation request")?;
+
+        let status = resp.status();
+        if status != reqwest::StatusCode::OK {
+            let body = resp
+                .json::<ErrorResponse>()
+                .await
+                .context("parse Intel Trust Authority error response")?;
+            bail!(
+                "Intel Trust Authority attestation request failed: response status={status}, message={}",
+                body.error
+            );
+        }
+
+        let resp = resp
+            .json::<AttestResponse>()
+            .await
+            .context("parse Intel Trust Authority attestation response")?;
+        debug!("Intel Trust Authority token: {}", resp.token);
+
+        let kid = decode_header(&resp.token)?
+            .kid
+            .context("Intel Trust Authority token has no kid")?;
+        let key = self
+            .certs
+            .find(&kid)
+            .with_context(|| format!("unknown Intel Trust Authority key {kid}"))?;
+        let alg = key
+            .common
+            .key_algorithm
+            .context("Intel Trust Authority key has no algorithm")?;
+        let token = decode::<Map<String, Value>>(
+            &resp.token,
+            &DecodingKey::from_jwk(key)?,
+            &Validation::new(Algorithm::from_str(&alg.to_string())?),
+        )
+        .context("verify Intel Trust Authority token")?;
+        info!("Intel Trust Authority token verified.");
+
+        let unmatched = token.claims.get("policy_ids_unmatched");
+        if !self.config.allow_unmatched_policy && unmatched.is_some() {
+            bail!("evidence does not match the Intel Trust Authority policies");
+        }
+
+        Ok(token.claims)
+    }
+}
+
+#[async_trait]
+impl Verifier for IntelTrustAuthority {
+    async fn evaluate(
+        &self,
+        evidence: &[u8],
+        expected_report_data: &ReportData,
+        expected_init_data_hash: &InitDataHash,
+    ) -> Result<TeeEvidenceParsedClaim> {
+        let evidence = serde_json::from_slice::<Evidence>(evidence)
+            .context("Deserialize Intel Trust Authority evidence failed.")?;
+        if self.tee == Tee::Tdx {
+            warn!("Intel Trust Authority only appraises the TD quote, the event logs are ignored.");
+        }
+
+        let token_claims = self.attest(evidence.quote).await?;
+        let claims = normalize_claims(self.tee, &token_claims)?;
+
+        let (init_data_len, init_data_name) = match self.tee {
+            Tee::Tdx => (48, "MRCONFIGID"),
+            _ => (64, "CONFIGID"),
+        };
+        if let ReportData::Value(expected_report_data) = expected_report_data {
+            let expected_report_data = regularize_data(
+                expected_report_data,
+                64,
+                "REPORT_DATA",
+                "Intel Trust Authority",
+            );
+            if claims["report_data"] != json!(hex::encode(expected_report_data)) {
+                bail!("REPORT_DATA is different from that in the Intel Trust Authority token");
+            }
+        }
+        if let InitDataHash::Value(expected_init_data_hash) = expected_init_data_hash {
+            let expected_init_data_hash = regularize_data(
+                expected_init_data_hash,
+                init_data_len,
+                init_data_name,
+                "Intel Trust Authority",
+            );
+            if claims.get("init_data") != Some(&json!(hex::encode(expected_init_data_hash))) {
+                bail!("{init_data_name} is different from that in the Intel Trust Authority token");
+            }
+        }
+
+        Ok(claims)
+    }
+}
+
+/// Normalize the claims of the token of a `tee` quote into the ones of the
+/// local verifier of `tee`, and `tcb_status`.
+fn normalize_claims(tee: Tee, token_claims: &Map<String, Value>) -> Result<TeeEvidenceParsedClaim> {
+    let (attester, mapping, report_data, init_data) = match tee {
+        Tee::Tdx => (
+            "tdx",
+            TDX_CLAIMS,
+            "quote.body.report_data",
+            "quote.body.mr_config_id",
+        ),
+        _ => ("sgx", SGX_CLAIMS, "body.report_data", "body.config_id"),
+    };
+    // The attester claims are nested in the recent tokens, and at the top
+    // level of the others.
+    let attester_claims = match token_claims.get(attester) {
+        Some(Value::Object(claims)) => claims,
+        _ => token_claims,
+    };
+
+    let mut claims = Map::new();
+    for (name, path) in mapping {
+        let value = match attester_claims.get(*name) {
+            Some(Value::Number(number)) => {
+                // ISV product id and SVN, that are hex encoded u16 locally.
+                let number = number
+                    .as_u64()
+                    .and_then(|number| u16::try_from(number).ok())
+                    .with_context(|| format!("illegal Intel Trust Authority claim {name}"))?;
+                Value::String(hex::encode(number.to_le_bytes()))
+            }
+            Some(Value::String(value)) => Value::String(value.to_lowercase()),
+            Some(_) => bail!("illegal Intel Trust Authority claim {name}"),
+            None => continue,
+        };
+        insert_claim(&mut claims, path, value);
+    }
+
+    let report_data = lookup_claim(&claims, report_data)
+        .context("Intel Trust Authority token has no report data")?;
+    claims.insert("report_data".to_string(), report_data);
+    if let Some(init_data) = lookup_claim(&claims, init_data) {
+        claims.insert("init_data".to_string(), init_data);
+    }
+    if let Some(tcb_status) = attester_claims.get("attester_tcb_status") {
+        claims.insert("tcb_status".to_string(), tcb_status.clone());
+    }
+
+    Ok(Value::Object(claims) as TeeEvidenceParsedClaim)
+}
+
+fn insert_claim(claims: &mut Map<String, Value>, path: &str, value: Value) {
+    match path.split_once('.') {
+        Some((key, path)) => {
+            let child = claims
+                .entry(key.to_string())
+                .or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(child) = child {
+                insert_claim(child, path, value);
+            }
+        }
+        None => {
+            claims.insert(path.to_string(), value);
+        }
+    }
+}
+
+fn lookup_claim(claims: &Map<String, Value>, path: &str) -> Option<Value> {
+    match path.split_once('.') {
+        Some((key, path)) => lookup_claim(claims.get(key)?.as_object()?, path),
+        None => claims.get(path).cloned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_tdx_claims() {
+        let token_claims = json!({
+            "tdx": {
+                "attester_tcb_status": "UpToDate",
+                "tdx_mrtd": "705EE9381B8633A9FBE532B52345E8433343D2868959F57889D84CA377C395B689CAC1599CCEA1B7D420483A9CE5F031",
+                "tdx_rtmr0": "00",
+                "tdx_report_data": "7c71fe2c",
+                "tdx_mrconfigid": "0000"
+            },
+            "policy_ids_matched": []
+        });
+        let claims = normalize_claims(Tee::Tdx, token_claims.as_object().unwrap()).unwrap();
+
+        assert_eq!(
+            claims,
+            json!({
+                "quote": {
+                    "body": {
+                        "mr_td": "705ee9381b8633a9fbe532b52345e8433343d2868959f57889d84ca377c395b689cac1599ccea1b7d420483a9ce5f031",
+                        "rtmr_0": "00",
+                        "mr_config_id": "0000",
+                        "report_data": "7c71fe2c"
+                    }
+                },
+                "report_data": "7c71fe2c",
+                "init_data": "0000",
+                "tcb_status": "UpToDate"
+            })
+        );
+    }
+
+    #[test]
+    fn normalize_sgx_claims() {
+        let token_claims = json!({
+            "attester_type": "SGX",
+            "sgx_mrenclave": "8f173e46",
+            "sgx_isvprodid": 1,
+            "sgx_isvsvn": 258,
+            "sgx_report_data": "74657374"
+        });
+        let claims = normalize_claims(Tee::Sgx, token_claims.as_object().unwrap()).unwrap();
+
+        assert_eq!(
+            claims,
+            json!({
+                "body": {
+                    "mr_enclave": "8f173e46",
+                    "isv_prod_id": "0100",
+                    "isv_svn": "0201",
+                    "report_data": "74657374"
+                },
+                "report_data": "74657374"
+            })
+        );
+
+        let token_claims = json!({ "sgx_mrenclave": "8f173e46" });
+        normalize_claims(Tee::Sgx, token_claims.as_object().unwrap()).unwrap_err();
+    }
+}
@@ -9,6 +9,8 @@ pub mod sample;
 
 pub mod eventlog;
 
+pub mod offline;
+
 #[cfg(feature = "az-snp-vtpm-verifier")]
 pub mod az_snp_vtpm;
 
@@ -24,6 +26,9 @@ pub mod tdx;
 #[cfg(feature = "sgx-verifier")]
 pub mod sgx;
 
+#[cfg(any(feature = "tdx-verifier", feature = "sgx-verifier"))]
+mod intel_dcap;
+
 #[cfg(feature = "csv-verifier")]
 pub mod csv;
 
@@ -33,6 +38,15 @@ pub mod cca;
 #[cfg(feature = "se-verifier")]
 pub mod se;
 
+#[cfg(feature = "nvidia-verifier")]
+pub mod nvidia;
+
+#[cfg(feature = "tpm-verifier")]
+pub mod tpm;
+
+#[cfg(feature = "intel-trust-authority-verifier")]
+pub mod intel_trust_authority;
+
 pub fn to_verifier(tee: &Tee) -> Result<Box<dyn Verifier + Send + Sync>> {
     match tee {
         Tee::Sev => todo!(),
@@ -98,7 +112,8 @@ pub fn to_verifier(tee: &Tee) -> Result<Box<dyn Verifier + Send + Sync>> {
         Tee::Cca => {
             cfg_if::cfg_if! {
                 if #[cfg(feature = "cca-verifier")] {
-                    Ok(Box::<cca::CCA>::default() as Box<dyn Verifier + Send + Sync>)
+                    let verifier = cca::CCA::new()?;
+                    Ok(Box::new(verifier) as Box<dyn Verifier + Send + Sync>)
                 } else {
                     bail!("feature `cca-verifier` is not enabled for `verifier` crate.")
                 }
@@ -117,6 +132,44 @@ pub fn to_verifier(tee: &Tee) -> Result<Box<dyn Verifier + Send + Sync>> {
     }
 }
 
+/// The verifier of the evidence of a device attached to a TEE, by verifier
+/// name: `nvidia` for NVIDIA GPUs, `tpm` for TPM 2.0 quotes, or the name of
+/// a hardware TEE, e.g. `azsnpvtpm`. The `sample` verifier checks no
+/// signature, so it is refused.
+pub fn to_device_verifier(device: &str) -> Result<Box<dyn Verifier + Send + Sync>> {
+    match device {
+        "nvidia" => {
+            cfg_if::cfg_if! {
+                if #[cfg(feature = "nvidia-verifier")] {
+                    let verifier = nvidia::NvidiaGpu::new()?;
+                    Ok(Box::new(verifier) as Box<dyn Verifier + Send + Sync>)
+                } else {
+                    bail!("feature `nvidia-verifier` is not enabled for `verifier` crate.")
+                }
+            }
+        }
+        "tpm" => {
+            cfg_if::cfg_if! {
+                if #[cfg(feature = "tpm-verifier")] {
+                    let verifier = tpm::Tpm::new()?;
+                    Ok(Box::new(verifier) as Box<dyn Verifier + Send + Sync>)
+                } else {
+                    bail!("feature `tpm-verifier` is not enabled for `verifier` crate.")
+                }
+            }
+        }
+        _ => {
+            let tee: Tee = serde_json::from_value(serde_json::Value::String(device.to_string()))
+                .map_err(|_| anyhow!("unknown device verifier {device}"))?;
+            if tee == Tee::Sample {
+                bail!("the sample verifier cannot verify the evidence of a device");
+            }
+
+            to_verifier(&tee)
+        }
+    }
+}
+
 pub type TeeEvidenceParsedClaim = serde_json::Value;
 
 pub enum ReportData<'a> {
@@ -0,0 +1,360 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Verifier of the attestation reports of NVIDIA GPUs in confidential
+//! computing mode, e.g. the H100.
+//!
+//! A GPU attestation report is an SPDM GET_MEASUREMENTS exchange signed by
+//! the attestation key of the GPU, certified by the NVIDIA device identity
+//! CA. The measurements of the report are appraised against the golden
+//! values of the RIMs of the driver and VBIOS versions it gives.
+
+use super::*;
+use async_trait::async_trait;
+use base64::Engine;
+use core::result::Result::Ok;
+use log::debug;
+use openssl::stack::Stack;
+use openssl::x509::store::{X509Store, X509StoreBuilder};
+use openssl::x509::{X509StoreContext, X509};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::path::Path;
+
+use report::MeasurementReport;
+use rim::Rim;
+
+mod report;
+mod rim;
+
+/// Path to the PEM NVIDIA device identity root CA certificates.
+const NVIDIA_GPU_ROOT_CA: &str = "NVIDIA_GPU_ROOT_CA";
+
+/// Path to the directory of the trusted RIMs of the drivers and VBIOSes.
+/// Without it, the measurements are not appraised.
+const NVIDIA_RIM_BUNDLE: &str = "NVIDIA_RIM_BUNDLE";
+
+#[derive(Serialize, Deserialize)]
+pub struct NvidiaGpuEvidence {
+    /// The evidence of every GPU attached to the TEE.
+    evidence_list: Vec<GpuEvidence>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GpuEvidence {
+    /// Base64 encoded SPDM GET_MEASUREMENTS request and response.
+    evidence: String,
+
+    /// Base64 encoded PEM certificate chain of the attestation key, leaf
+    /// first.
+    certificate: String,
+}
+
+pub struct NvidiaGpu {
+    root_ca: X509Store,
+    rims: Option<Vec<Rim>>,
+}
+
+impl NvidiaGpu {
+    pub fn new() -> Result<Self> {
+        let path = crate::offline::env_or_bundle(NVIDIA_GPU_ROOT_CA, "nvidia/root-ca.pem")
+            .with_context(|| format!("{NVIDIA_GPU_ROOT_CA} is not set"))?;
+        let root_ca =
+            std::fs::read(&path).with_context(|| format!("read NVIDIA GPU root CA {path}"))?;
+        let rims = match crate::offline::env_or_bundle(NVIDIA_RIM_BUNDLE, "nvidia/rims") {
+            Some(dir) => Some(Rim::load_bundle(Path::new(&dir))?),
+            None => None,
+        };
+
+        Self::with_trust(&root_ca, rims)
+    }
+
+    fn with_trust(root_ca: &[u8], rims: Option<Vec<Rim>>) -> Result<Self> {
+        let mut store = X509StoreBuilder::new()?;
+        for cert in X509::stack_from_pem(root_ca).context("illegal NVIDIA GPU root CA")? {
+            store.add_cert(cert)?;
+        }
+
+        Ok(Self {
+            root_ca: store.build(),
+            rims,
+        })
+    }
+
+    /// Verify the evidence of a GPU, and return its claims.
+    fn verify_gpu(&self, evidence: &GpuEvidence, expected_nonce: &[u8]) -> Result<Value> {
+        let certificate = base64::engine::general_purpose::STANDARD
+            .decode(&evidence.certificate)
+            .context("illegal GPU certificate chain")?;
+        let mut certs = X509::stack_from_pem(&certificate)
+            .context("illegal GPU certificate chain")?
+            .into_iter();
+        let leaf = certs.next().context("empty GPU certificate chain")?;
+        let mut chain = Stack::new()?;
+        for cert in certs {
+            chain.push(cert)?;
+        }
+
+        let mut context = X509StoreContext::new()?;
+        if !context.init(&self.root_ca, &leaf, &chain, |ctx| ctx.verify_cert())? {
+            bail!("GPU attestation key is not certified by the NVIDIA root CA");
+        }
+
+        let report = base64::engine::general_purpose::STANDARD
+            .decode(&evidence.evidence)
+            .context("illegal GPU attestation report")?;
+        let report = MeasurementReport::parse(&report)?;
+        report.verify(&leaf.public_key()?)?;
+
+        if report.request_nonce != expected_nonce {
+            bail!("GPU attestation report nonce is different from the expected report data");
+        }
+
+        let driver_version = report.driver_version().unwrap_or_default();
+        let vbios_version = report.vbios_version().unwrap_or_default();
+        let measurements: Map<String, Value> = report
+            .measurements
+            .iter()
+            .map(|(index, value)| (index.to_string(), hex::encode(value).into()))
+            .collect();
+
+        let mut claims = json!({
+            "driver_version": driver_version,
+            "vbios_version": vbios_version,
+            "measurements": measurements,
+        });
+        if let Some(rims) = &self.rims {
+            claims["measurements_match"] =
+                appraise(&report, rims, &[&driver_version, &vbios_version]).into();
+        }
+
+        Ok(claims)
+    }
+}
+
+/// Whether the measurements of `report` match the golden values of the RIMs
+/// of `versions`. Every version must have a RIM.
+fn appraise(report: &MeasurementReport, rims: &[Rim], versions: &[&str]) -> bool {
+    let mut matches = true;
+    for version in versions {
+        let Some(rim) = rims
+            .iter()
+            .find(|rim| rim.version == version.to_lowercase())
+        else {
+            debug!("No RIM for GPU version {version}");
+            return false;
+        };
+
+        for (index, alternatives) in &rim.measurements {
+            let value = index
+                .checked_add(1)
+                .and_then(|block| report.measurements.get(&block))
+                .map(hex::encode);
+            match value {
+                Some(value) if alternatives.contains(&value) => {}
+                _ => {
+                    debug!("GPU measurement {index} does not match RIM {}", rim.name);
+                    matches = false;
+                }
+            }
+        }
+    }
+
+    matches
+}
+
+#[async_trait]
+impl Verifier for NvidiaGpu {
+    async fn evaluate(
+        &self,
+        evidence: &[u8],
+        expected_report_data: &ReportData,
+        _expected_init_data_hash: &InitDataHash,
+    ) -> Result<TeeEvidenceParsedClaim> {
+        let ReportData::Value(expected_report_data) = expected_report_data else {
+            bail!("NVIDIA GPU verifier must provide report data field!");
+        };
+        let expected_nonce = regularize_data(expected_report_data, 32, "REPORT_DATA", "NVIDIA GPU");
+
+        let evidence = serde_json::from_slice::<NvidiaGpuEvidence>(evidence)
+            .context("Deserialize NVIDIA GPU Evidence failed.")?;
+        if evidence.evidence_list.is_empty() {
+            bail!("NVIDIA GPU evidence has no GPU");
+        }
+
+        let mut claims = Map::new();
+        for (index, gpu) in evidence.evidence_list.iter().enumerate() {
+            let gpu_claims = self
+                .verify_gpu(gpu, &expected_nonce)
+                .with_context(|| format!("verify evidence of GPU {index}"))?;
+            claims.insert(format!("gpu{index}"), gpu_claims);
+        }
+        claims.insert("report_data".into(), hex::encode(expected_nonce).into());
+
+        Ok(Value::Object(claims) as TeeEvidenceParsedClaim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::ecdsa::EcdsaSig;
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::sign::Signer;
+    use openssl::x509::extension::BasicConstraints;
+    use openssl::x509::X509Name;
+
+    fn certificate(
+        name: &str,
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+    ) -> X509 {
+        let mut subject = X509Name::builder().unwrap();
+        subject.append_entry_by_text("CN", name).unwrap();
+        let subject = subject.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&subject).unwrap();
+        cert.set_pubkey(key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        match issuer {
+            Some((issuer, issuer_key)) => {
+                cert.set_issuer_name(issuer.subject_name()).unwrap();
+                cert.sign(issuer_key, MessageDigest::sha384()).unwrap();
+            }
+            None => {
+                cert.append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                    .unwrap();
+                cert.set_issuer_name(&subject).unwrap();
+                cert.sign(key, MessageDigest::sha384()).unwrap();
+            }
+        }
+        cert.build()
+    }
+
+    fn p384_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::SECP384R1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    /// A GET_MEASUREMENTS exchange with two measurement blocks, signed by
+    /// `key`.
+    fn report(key: &PKey<Private>, nonce: &[u8]) -> Vec<u8> {
+        let mut report = vec![0x11, 0xE0, 0x01, 0xFF];
+        report.extend(nonce);
+        report.push(0);
+
+        let mut record = Vec::new();
+        for (index, value) in [(1u8, [0xAA; 48]), (2, [0xBB; 48])] {
+            record.extend([index, 0x01, 51, 0, 0x01, 48, 0]);
+            record.extend(value);
+        }
+        let mut opaque = vec![3, 0, 11, 0];
+        opaque.extend(b"550.54.15\0\0");
+        opaque.extend([6, 0, 8, 0, 0x00, 0x5e, 0x00, 0x96, 0x01, 0, 0, 0]);
+
+        report.extend([0x11, 0x60, 0, 0, 2]);
+        report.extend(&(record.len() as u32).to_le_bytes()[..3]);
+        report.extend(record);
+        report.extend([0x55; 32]);
+        report.extend((opaque.len() as u16).to_le_bytes());
+        report.extend(opaque);
+
+        let mut signer = Signer::new(MessageDigest::sha384(), key).unwrap();
+        let signature = EcdsaSig::from_der(&signer.sign_oneshot_to_vec(&report).unwrap()).unwrap();
+        report.extend(signature.r().to_vec_padded(48).unwrap());
+        report.extend(signature.s().to_vec_padded(48).unwrap());
+        report
+    }
+
+    fn rim(version: &str, index: u8, value: u8) -> Rim {
+        Rim::parse(&format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<SoftwareIdentity xmlns="http://standards.iso.org/iso/19770/-2/2015/schema.xsd" xmlns:SHA384="http://www.w3.org/2001/04/xmldsig-more#sha384" name="GH100 RIM" version="{version}">
+  <Payload>
+    <Resource type="Measurement" index="{index}" active="True" alternatives="2" SHA384:Hash0="{}" SHA384:Hash1="{}" />
+    <Resource type="Measurement" index="9" active="False" SHA384:Hash0="00" />
+  </Payload>
+</SoftwareIdentity>"#,
+            hex::encode([0x00; 48]),
+            hex::encode([value; 48]),
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn verify_gpu_evidence() {
+        let root_key = p384_key();
+        let root = certificate("NVIDIA Device Identity CA", &root_key, None);
+        let ak = p384_key();
+        let ak_cert = certificate("GH100 AK", &ak, Some((&root, &root_key)));
+
+        let nonce = [7; 32];
+        let evidence = |key: &PKey<Private>| {
+            let evidence = NvidiaGpuEvidence {
+                evidence_list: vec![GpuEvidence {
+                    evidence: base64::engine::general_purpose::STANDARD.encode(report(key, &nonce)),
+                    certificate: base64::engine::general_purpose::STANDARD
+                        .encode(ak_cert.to_pem().unwrap()),
+                }],
+            };
+            serde_json::to_vec(&evidence).unwrap()
+        };
+
+        let rims = vec![rim("550.54.15", 0, 0xAA), rim("96.00.5e.00.01", 1, 0xBB)];
+        let verifier = NvidiaGpu::with_trust(&root.to_pem().unwrap(), Some(rims)).unwrap();
+        let claims = verifier
+            .evaluate(
+                &evidence(&ak),
+                &ReportData::Value(&nonce),
+                &InitDataHash::NotProvided,
+            )
+            .await
+            .unwrap();
+        assert_eq!(claims["gpu0"]["driver_version"], "550.54.15");
+        assert_eq!(claims["gpu0"]["vbios_version"], "96.00.5e.00.01");
+        assert_eq!(claims["gpu0"]["measurements"]["2"], hex::encode([0xBB; 48]));
+        assert_eq!(claims["gpu0"]["measurements_match"], true);
+
+        // A golden value mismatch.
+        let rims = vec![rim("550.54.15", 0, 0xAA), rim("96.00.5e.00.01", 1, 0xCC)];
+        let verifier = NvidiaGpu::with_trust(&root.to_pem().unwrap(), Some(rims)).unwrap();
+        let claims = verifier
+            .evaluate(
+                &evidence(&ak),
+                &ReportData::Value(&nonce),
+                &InitDataHash::NotProvided,
+            )
+            .await
+            .unwrap();
+        assert_eq!(claims["gpu0"]["measurements_match"], false);
+
+        // A report not signed by the attestation key, and a wrong nonce.
+        assert!(verifier
+            .evaluate(
+                &evidence(&p384_key()),
+                &ReportData::Value(&nonce),
+                &InitDataHash::NotProvided,
+            )
+            .await
+            .is_err());
+        assert!(verifier
+            .evaluate(
+                &evidence(&ak),
+                &ReportData::Value(&[0; 32]),
+                &InitDataHash::NotProvided,
+            )
+            .await
+            .is_err());
+    }
+}
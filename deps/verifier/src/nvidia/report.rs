@@ -0,0 +1,182 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! SPDM 1.1 GET_MEASUREMENTS request and response, as returned by an NVIDIA
+//! GPU in confidential computing mode.
+
+use anyhow::{bail, Context, Result};
+use openssl::bn::BigNum;
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Public};
+use openssl::sign::Verifier;
+use std::collections::BTreeMap;
+
+const SPDM_GET_MEASUREMENTS: u8 = 0xE0;
+const SPDM_MEASUREMENTS: u8 = 0x60;
+
+const REQUEST_LENGTH: usize = 37;
+const NONCE_LENGTH: usize = 32;
+
+/// ECDSA P-384 signature, as raw r || s.
+const SIGNATURE_LENGTH: usize = 96;
+
+const OPAQUE_FIELD_ID_DRIVER_VERSION: u16 = 3;
+const OPAQUE_FIELD_ID_VBIOS_VERSION: u16 = 6;
+
+pub(super) struct MeasurementReport {
+    /// Nonce of the GET_MEASUREMENTS request.
+    pub request_nonce: Vec<u8>,
+
+    /// Measurement values by measurement block index.
+    pub measurements: BTreeMap<u8, Vec<u8>>,
+
+    /// Opaque data fields of the response by field id.
+    opaque_data: BTreeMap<u16, Vec<u8>>,
+
+    /// The request and the response up to the signature.
+    signed: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+/// Reads the little endian fields of a message.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            bail!("truncated GPU attestation report");
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u24(&mut self) -> Result<usize> {
+        let bytes = self.take(3)?;
+        Ok(usize::from(bytes[0]) | usize::from(bytes[1]) << 8 | usize::from(bytes[2]) << 16)
+    }
+}
+
+impl MeasurementReport {
+    pub fn parse(report: &[u8]) -> Result<Self> {
+        if report.len() < REQUEST_LENGTH + SIGNATURE_LENGTH {
+            bail!("truncated GPU attestation report");
+        }
+
+        let (request, response) = report.split_at(REQUEST_LENGTH);
+        if request[1] != SPDM_GET_MEASUREMENTS {
+            bail!("GPU attestation report has no GET_MEASUREMENTS request");
+        }
+        let request_nonce = request[4..4 + NONCE_LENGTH].to_vec();
+
+        let mut reader = Reader { bytes: response };
+        let header = reader.take(4)?;
+        if header[1] != SPDM_MEASUREMENTS {
+            bail!("GPU attestation report has no MEASUREMENTS response");
+        }
+
+        let blocks = reader.u8()?;
+        let record_len = reader.u24()?;
+        let mut record = Reader {
+            bytes: reader.take(record_len)?,
+        };
+        let mut measurements = BTreeMap::new();
+        for _ in 0..blocks {
+            let index = record.u8()?;
+            let _specification = record.u8()?;
+            let size = usize::from(record.u16()?);
+            let mut block = Reader {
+                bytes: record.take(size)?,
+            };
+            let _value_type = block.u8()?;
+            let value_size = usize::from(block.u16()?);
+            measurements.insert(index, block.take(value_size)?.to_vec());
+        }
+
+        let _response_nonce = reader.take(NONCE_LENGTH)?;
+        let opaque_len = usize::from(reader.u16()?);
+        let mut opaque = Reader {
+            bytes: reader.take(opaque_len)?,
+        };
+        let mut opaque_data = BTreeMap::new();
+        while !opaque.bytes.is_empty() {
+            let id = opaque.u16()?;
+            let size = usize::from(opaque.u16()?);
+            opaque_data.insert(id, opaque.take(size)?.to_vec());
+        }
+
+        let signature = reader.bytes.to_vec();
+        if signature.len() != SIGNATURE_LENGTH {
+            bail!("illegal GPU attestation report signature length");
+        }
+        let signed = report[..report.len() - SIGNATURE_LENGTH].to_vec();
+
+        Ok(Self {
+            request_nonce,
+            measurements,
+            opaque_data,
+            signed,
+            signature,
+        })
+    }
+
+    /// Verify the ECDSA P-384 signature of the report.
+    pub fn verify(&self, key: &PKey<Public>) -> Result<()> {
+        let (r, s) = self.signature.split_at(SIGNATURE_LENGTH / 2);
+        let signature =
+            EcdsaSig::from_private_components(BigNum::from_slice(r)?, BigNum::from_slice(s)?)?
+                .to_der()?;
+
+        let mut verifier = Verifier::new(MessageDigest::sha384(), key)?;
+        if !verifier
+            .verify_oneshot(&signature, &self.signed)
+            .context("verify GPU attestation report signature")?
+        {
+            bail!("GPU attestation report signature verification failed");
+        }
+
+        Ok(())
+    }
+
+    /// The NUL terminated driver version string.
+    pub fn driver_version(&self) -> Option<String> {
+        let version = self.opaque_data.get(&OPAQUE_FIELD_ID_DRIVER_VERSION)?;
+        let version = version.split(|byte| *byte == 0).next().unwrap_or_default();
+        Some(String::from_utf8_lossy(version).into_owned())
+    }
+
+    /// The VBIOS version, formatted as the RIMs do, e.g. `96.00.5e.00.01`.
+    pub fn vbios_version(&self) -> Option<String> {
+        let version = self.opaque_data.get(&OPAQUE_FIELD_ID_VBIOS_VERSION)?;
+        let mut bytes = [0; 8];
+        let len = version.len().min(8);
+        bytes[..len].copy_from_slice(&version[..len]);
+        let version = u64::from_le_bytes(bytes);
+
+        // The low 32 bits give the first four components, the next byte the
+        // last one.
+        let version = format!(
+            "{:02x}.{:02x}.{:02x}.{:02x}.{:02x}",
+            (version >> 24) & 0xff,
+            (version >> 16) & 0xff,
+            (version >> 8) & 0xff,
+            version & 0xff,
+            (version >> 32) & 0xff,
+        );
+        Some(version)
+    }
+}
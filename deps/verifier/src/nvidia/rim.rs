@@ -0,0 +1,115 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! NVIDIA Reference Integrity Manifests (RIMs), the SWID tags giving the
+//! golden measurements of a driver or VBIOS version.
+
+use anyhow::{bail, Context, Result};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+pub(super) struct Rim {
+    pub name: String,
+    pub version: String,
+
+    /// Alternatives of the hex encoded golden values of the active
+    /// measurements, by measurement index. RIM index `n` is the measurement
+    /// block `n + 1` of the attestation report.
+    pub measurements: BTreeMap<u8, Vec<String>>,
+}
+
+impl Rim {
+    pub fn parse(xml: &str) -> Result<Self> {
+        let mut reader = Reader::from_str(xml);
+        let mut rim = Rim {
+            name: String::new(),
+            version: String::new(),
+            measurements: BTreeMap::new(),
+        };
+
+        loop {
+            match reader.read_event().context("illegal RIM")? {
+                Event::Start(element) | Event::Empty(element) => {
+                    match element.local_name().as_ref() {
+                        b"SoftwareIdentity" => {
+                            let attributes = attributes(&element)?;
+                            rim.name = attributes.get("name").cloned().unwrap_or_default();
+                            rim.version = attributes
+                                .get("version")
+                                .context("RIM has no version")?
+                                .to_lowercase();
+                        }
+                        b"Resource" => {
+                            let attributes = attributes(&element)?;
+                            let attribute = |name: &str| attributes.get(name).map(String::as_str);
+                            if attribute("type") != Some("Measurement")
+                                || !attribute("active")
+                                    .unwrap_or_default()
+                                    .eq_ignore_ascii_case("true")
+                            {
+                                continue;
+                            }
+
+                            let index: u8 = attribute("index")
+                                .context("RIM measurement has no index")?
+                                .parse()
+                                .context("illegal RIM measurement index")?;
+                            let alternatives = attributes
+                                .iter()
+                                .filter(|(name, _)| name.starts_with("Hash"))
+                                .map(|(_, value)| value.to_lowercase())
+                                .collect();
+                            rim.measurements.insert(index, alternatives);
+                        }
+                        _ => {}
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        if rim.version.is_empty() {
+            bail!("RIM has no SoftwareIdentity");
+        }
+
+        Ok(rim)
+    }
+
+    /// Load the RIMs of all the `.xml` files of `dir`.
+    pub fn load_bundle(dir: &Path) -> Result<Vec<Self>> {
+        let mut rims = Vec::new();
+        for entry in
+            std::fs::read_dir(dir).with_context(|| format!("read RIM bundle {}", dir.display()))?
+        {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("xml") {
+                continue;
+            }
+
+            let xml = std::fs::read_to_string(&path)
+                .with_context(|| format!("read RIM {}", path.display()))?;
+            rims.push(Self::parse(&xml).with_context(|| format!("parse RIM {}", path.display()))?);
+        }
+
+        Ok(rims)
+    }
+}
+
+/// The attributes of `element` by local name, e.g. `Hash0` for
+/// `SHA384:Hash0`.
+fn attributes(element: &BytesStart) -> Result<BTreeMap<String, String>> {
+    element
+        .attributes()
+        .map(|attribute| {
+            let attribute = attribute.context("illegal RIM attribute")?;
+            let name = String::from_utf8_lossy(attribute.key.local_name().as_ref()).into_owned();
+            let value = attribute.unescape_value()?.into_owned();
+            Ok((name, value))
+        })
+        .collect()
+}
@@ -0,0 +1,54 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Fully offline verification. If `VERIFIER_OFFLINE_BUNDLE` is set, all the
+//! collateral of the verifiers is loaded from the bundle directory, and no
+//! outbound network call is made. The bundle is laid out as follows, and can
+//! be assembled on a connected machine by `tools/offline-bundle.sh`:
+//!
+//! - `snp/`: VCEK and VLEK certificates, in PEM or DER, used for the SNP
+//!   reports without certificate chain.
+//! - `intel/`: collateral of the SGX and TDX platforms, in the format of the
+//!   collateral cache.
+//! - `cca/trust-anchors.pem`: trust anchors of the CCA platforms, see
+//!   `CCA_TRUST_ANCHORS`.
+//! - `nvidia/root-ca.pem` and `nvidia/rims/`: see `NVIDIA_GPU_ROOT_CA` and
+//!   `NVIDIA_RIM_BUNDLE`.
+//! - `tpm/ak-ca.pem`: see `TPM_AK_CA_CERTS`.
+//!
+//! The environment variables of the verifiers still take precedence over
+//! the bundle.
+
+use std::path::PathBuf;
+
+pub const VERIFIER_OFFLINE_BUNDLE: &str = "VERIFIER_OFFLINE_BUNDLE";
+
+/// The offline bundle directory, if the offline mode is enabled.
+pub fn bundle() -> Option<PathBuf> {
+    std::env::var(VERIFIER_OFFLINE_BUNDLE)
+        .ok()
+        .map(PathBuf::from)
+}
+
+/// Whether the offline mode is enabled, i.e. no network call may be made.
+pub fn enabled() -> bool {
+    bundle().is_some()
+}
+
+/// The value of the `env` variable, or else `path` in the offline bundle if
+/// it exists.
+#[cfg(any(
+    feature = "cca-verifier",
+    feature = "nvidia-verifier",
+    feature = "tpm-verifier"
+))]
+pub(crate) fn env_or_bundle(env: &str, path: &str) -> Option<String> {
+    if let Ok(value) = std::env::var(env) {
+        return Some(value);
+    }
+
+    let path = bundle()?.join(path);
+    path.exists().then(|| path.to_string_lossy().into_owned())
+}
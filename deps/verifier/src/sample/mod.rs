@@ -4,7 +4,8 @@ use self::serde::{Deserialize, Serialize};
 use super::*;
 use async_trait::async_trait;
 use base64::Engine;
-use serde_json::json;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
 
 #[derive(Serialize, Deserialize, Debug)]
 struct SampleTeeEvidence {
@@ -15,6 +16,18 @@ struct SampleTeeEvidence {
 
     #[serde(default = "String::default")]
     init_data: String,
+
+    /// Hex measurements emulated by the attester, by name, e.g.
+    /// `{"launch_digest": "..."}`. They are reported as the
+    /// `sample.measurements.<name>` claims.
+    #[serde(default)]
+    measurements: BTreeMap<String, String>,
+
+    /// Launch parameters emulated by the attester, e.g. a guest policy or
+    /// the number of vCPUs. They are reported as the
+    /// `sample.launch_parameters.<name>` string claims.
+    #[serde(default)]
+    launch_parameters: Map<String, Value>,
 }
 
 #[derive(Debug, Default)]
@@ -76,11 +89,86 @@ async fn verify_tee_evidence(
 // Dump the TCB status from the quote.
 // Example: CPU SVN, RTMR, etc.
 fn parse_tee_evidence(quote: &SampleTeeEvidence) -> Result<TeeEvidenceParsedClaim> {
+    // Normalize the measurements like the ones of the real TEEs, so that
+    // they match the reference values of the RVPS.
+    let mut measurements = Map::new();
+    for (name, value) in &quote.measurements {
+        if name.is_empty() || name.contains('.') {
+            bail!("illegal sample measurement name {name:?}");
+        }
+        let value =
+            hex::decode(value).with_context(|| format!("sample measurement {name} is not hex"))?;
+        measurements.insert(name.clone(), json!(hex::encode(value)));
+    }
+
+    // The reference values of the RVPS are strings, like the claims of the
+    // real TEEs.
+    let mut launch_parameters = Map::new();
+    for (name, value) in &quote.launch_parameters {
+        if name.is_empty() || name.contains('.') {
+            bail!("illegal sample launch parameter name {name:?}");
+        }
+        let value = match value {
+            Value::String(value) => value.clone(),
+            Value::Number(_) | Value::Bool(_) => value.to_string(),
+            _ => bail!("sample launch parameter {name} is not a string, a number or a bool"),
+        };
+        launch_parameters.insert(name.clone(), json!(value));
+    }
+
     let claims_map = json!({
         "svn": quote.svn,
         "report_data": quote.report_data,
         "init_data": quote.init_data,
+        "measurements": measurements,
+        "launch_parameters": launch_parameters,
     });
 
     Ok(claims_map as TeeEvidenceParsedClaim)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_injected_claims() {
+        let evidence: SampleTeeEvidence = serde_json::from_value(json!({
+            "svn": "1",
+            "measurements": { "launch_digest": "ABCD" },
+            "launch_parameters": { "vcpus": 2, "debug": false },
+        }))
+        .unwrap();
+        let claims = parse_tee_evidence(&evidence).unwrap();
+        assert_eq!(claims["measurements"]["launch_digest"], json!("abcd"));
+        assert_eq!(claims["launch_parameters"]["vcpus"], json!("2"));
+        assert_eq!(claims["launch_parameters"]["debug"], json!("false"));
+
+        // The evidence of the attesters without injected claims.
+        let evidence: SampleTeeEvidence = serde_json::from_value(json!({ "svn": "1" })).unwrap();
+        let claims = parse_tee_evidence(&evidence).unwrap();
+        assert_eq!(claims["svn"], json!("1"));
+
+        let evidence: SampleTeeEvidence = serde_json::from_value(json!({
+            "svn": "1",
+            "measurements": { "launch_digest": "not hex" },
+        }))
+        .unwrap();
+        assert!(parse_tee_evidence(&evidence).is_err());
+
+        // The names become the keys of the flattened claims.
+        for injected in [
+            json!({ "measurements": { "launch.digest": "abcd" } }),
+            json!({ "launch_parameters": { "": 2 } }),
+            json!({ "launch_parameters": { "policy.debug": true } }),
+        ] {
+            let mut evidence = json!({ "svn": "1" });
+            evidence
+                .as_object_mut()
+                .unwrap()
+                .extend(injected.as_object().unwrap().clone());
+            let evidence: SampleTeeEvidence = serde_json::from_value(evidence).unwrap();
+            assert!(parse_tee_evidence(&evidence).is_err());
+        }
+    }
+}
@@ -7,9 +7,11 @@ use crate::TeeEvidenceParsedClaim;
 use anyhow::{anyhow, bail, Context, Result};
 use core::result::Result::Ok;
 use log::{debug, info, warn};
+use openssl::asn1::Asn1Time;
 use openssl::encrypt::{Decrypter, Encrypter};
 use openssl::pkey::{PKey, Private, Public};
 use openssl::rsa::Padding;
+use openssl::x509::{X509Crl, X509};
 use pv::attest::{
     AdditionalData, AttestationFlags, AttestationItems, AttestationMeasAlg, AttestationMeasurement,
     AttestationRequest, AttestationVersion,
@@ -19,7 +21,9 @@ use pv::request::{BootHdrTags, CertVerifier, HkdVerifier, ReqEncrCtx, Request, S
 use pv::uv::ConfigUid;
 use serde::{Deserialize, Serialize};
 use serde_with::{base64::Base64, hex::Hex, serde_as};
+use std::path::{Path, PathBuf};
 use std::{env, fs};
+use strum::{AsRefStr, EnumString};
 
 const DEFAULT_CERTS_OFFLINE_VERIFICATION: &str = "false";
 
@@ -64,6 +68,238 @@ fn list_files_in_folder(dir: &str) -> Result<Vec<String>> {
     Ok(file_paths)
 }
 
+/// Kinds of the materials of the SE verifier, each kept in its directory.
+/// They are read at each challenge, so that they can be updated at runtime.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, EnumString, AsRefStr)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum SeMaterialKind {
+    /// Host-key documents, whose host keys the attestation requests are
+    /// encrypted for.
+    HostKeyDocument,
+
+    /// Certificates of the chain of the host-key documents.
+    Certificate,
+
+    /// Certificate revocation lists.
+    Crl,
+}
+
+impl SeMaterialKind {
+    fn dir(&self) -> String {
+        match self {
+            SeMaterialKind::HostKeyDocument => env_or_default!(
+                "DEFAULT_SE_HOST_KEY_DOCUMENTS_ROOT",
+                DEFAULT_SE_HOST_KEY_DOCUMENTS_ROOT
+            ),
+            SeMaterialKind::Certificate => {
+                env_or_default!("SE_CERTIFICATES_ROOT", DEFAULT_SE_CERTIFICATES_ROOT)
+            }
+            SeMaterialKind::Crl => env_or_default!(
+                "SE_CERTIFICATE_REVOCATION_LISTS_ROOT",
+                DEFAULT_SE_CERTIFICATE_REVOCATION_LISTS_ROOT
+            ),
+        }
+    }
+}
+
+/// A material of the SE verifier.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct SeMaterialInfo {
+    /// File name of the material in its directory.
+    pub name: String,
+
+    /// Start of the validity of a certificate or of a host-key document, or
+    /// the last update of a CRL.
+    pub valid_from: Option<String>,
+
+    /// End of the validity of a certificate or of a host-key document, or
+    /// the next update of a CRL.
+    pub valid_until: Option<String>,
+}
+
+fn material_path(kind: SeMaterialKind, name: &str) -> Result<PathBuf> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    {
+        bail!("Illegal SE material name '{name}'");
+    }
+
+    Ok(Path::new(&kind.dir()).join(name))
+}
+
+/// Check that the certificate of a host-key document is valid now.
+fn check_validity(cert: &X509) -> Result<()> {
+    let now = Asn1Time::days_from_now(0)?;
+    if cert.not_before() > now {
+        bail!(
+            "The host key document is not valid before {}",
+            cert.not_before()
+        );
+    }
+    if cert.not_after() < now {
+        bail!("The host key document expired on {}", cert.not_after());
+    }
+    Ok(())
+}
+
+fn read_host_key_document(hk: &[u8], hkd: &str) -> Result<X509> {
+    let mut certs = read_certs(hk)?;
+    if certs.is_empty() {
+        bail!("The host key document in '{hkd}' does not contain a X509 certificate");
+    }
+    if certs.len() != 1 {
+        warn!("The host key document in '{hkd}' contains more than one certificate!")
+    }
+    Ok(certs.swap_remove(0))
+}
+
+/// Verify the host-key document against the certificates and the CRLs.
+fn verify_host_key_document(c: &X509) -> Result<()> {
+    #[cfg(debug_assertions)]
+    {
+        const DEFAULT_SE_SKIP_CERTS_VERIFICATION: &str = "false";
+        let skip_certs_env = env_or_default!(
+            "SE_SKIP_CERTS_VERIFICATION",
+            DEFAULT_SE_SKIP_CERTS_VERIFICATION
+        );
+        let skip_certs: bool = skip_certs_env.parse::<bool>().unwrap_or(false);
+        if skip_certs {
+            return Ok(());
+        }
+    }
+
+    let ca_certs = list_files_in_folder(&SeMaterialKind::Certificate.dir())?;
+    let crls = list_files_in_folder(&SeMaterialKind::Crl.dir())?;
+
+    let root_ca_path = env_or_default!("SE_CERTIFICATE_ROOT_CA", DEFAULT_SE_CERTIFICATE_ROOT_CA);
+    let ca_option: Option<String> = if Path::new(&root_ca_path).exists() {
+        Some(root_ca_path)
+    } else {
+        None::<String>
+    };
+    let offline_certs_verify = env_or_default!(
+        "CERTS_OFFLINE_VERIFICATION",
+        DEFAULT_CERTS_OFFLINE_VERIFICATION
+    );
+    let offline_certs_verify: bool =
+        crate::offline::enabled() || offline_certs_verify.parse::<bool>().unwrap_or(false);
+
+    let verifier = CertVerifier::new(
+        ca_certs.as_slice(),
+        crls.as_slice(),
+        ca_option,
+        offline_certs_verify,
+    )?;
+    verifier.verify(c)?;
+    Ok(())
+}
+
+/// Read a host-key document, and check that it is valid and trusted.
+fn load_host_key_document(hkd: &str) -> Result<X509> {
+    let hk = std::fs::read(hkd).context("read host-key document")?;
+    let c = read_host_key_document(&hk, hkd)?;
+    check_validity(&c)?;
+    verify_host_key_document(&c)?;
+    Ok(c)
+}
+
+/// Add the PEM or DER `contents` of a material as `name` in its directory,
+/// replacing the material of the same name. A host-key document must be
+/// valid and trusted, and is used from the next challenge on.
+pub fn add_material(kind: SeMaterialKind, name: &str, contents: &[u8]) -> Result<()> {
+    let path = material_path(kind, name)?;
+    match kind {
+        SeMaterialKind::HostKeyDocument => {
+            let c = read_host_key_document(contents, name)?;
+            check_validity(&c)?;
+            verify_host_key_document(&c)?;
+        }
+        SeMaterialKind::Certificate => {
+            if read_certs(contents)?.is_empty() {
+                bail!("File does not contain a X509 certificate");
+            }
+        }
+        SeMaterialKind::Crl => {
+            X509Crl::from_pem(contents)
+                .or_else(|_| X509Crl::from_der(contents))
+                .context("File does not contain a CRL")?;
+        }
+    }
+
+    fs::create_dir_all(kind.dir())?;
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, &path).with_context(|| format!("add SE material {name}"))?;
+    info!("SE {} '{name}' added.", kind.as_ref());
+    Ok(())
+}
+
+/// Remove the material `name`. Return whether it existed.
+pub fn remove_material(kind: SeMaterialKind, name: &str) -> Result<bool> {
+    match fs::remove_file(material_path(kind, name)?) {
+        Ok(()) => {
+            info!("SE {} '{name}' removed.", kind.as_ref());
+            Ok(true)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// List the materials of `kind`, with their validity.
+pub fn list_materials(kind: SeMaterialKind) -> Result<Vec<SeMaterialInfo>> {
+    let dir = kind.dir();
+    if !Path::new(&dir).exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut materials = Vec::new();
+    for path in list_files_in_folder(&dir)? {
+        let contents = fs::read(&path)?;
+        let (valid_from, valid_until) = match kind {
+            SeMaterialKind::HostKeyDocument | SeMaterialKind::Certificate => {
+                match read_certs(&contents)
+                    .ok()
+                    .and_then(|certs| certs.into_iter().next())
+                {
+                    Some(c) => (
+                        Some(c.not_before().to_string()),
+                        Some(c.not_after().to_string()),
+                    ),
+                    None => (None, None),
+                }
+            }
+            SeMaterialKind::Crl => {
+                match X509Crl::from_pem(&contents).or_else(|_| X509Crl::from_der(&contents)) {
+                    Ok(crl) => (
+                        Some(crl.last_update().to_string()),
+                        crl.next_update().map(|t| t.to_string()),
+                    ),
+                    Err(_) => (None, None),
+                }
+            }
+        };
+
+        let name = Path::new(&path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .to_string();
+        materials.push(SeMaterialInfo {
+            name,
+            valid_from,
+            valid_until,
+        });
+    }
+
+    materials.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(materials)
+}
+
 #[repr(C)]
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
@@ -229,28 +465,6 @@ impl SeVerifierImpl {
     }
 
     pub async fn generate_supplemental_challenge(&self, _tee_parameters: String) -> Result<String> {
-        let se_certificate_root =
-            env_or_default!("SE_CERTIFICATES_ROOT", DEFAULT_SE_CERTIFICATES_ROOT);
-        let ca_certs = list_files_in_folder(&se_certificate_root)?;
-
-        let crl_root = env_or_default!(
-            "SE_CERTIFICATE_REVOCATION_LISTS_ROOT",
-            DEFAULT_SE_CERTIFICATE_REVOCATION_LISTS_ROOT
-        );
-        let crls = list_files_in_folder(&crl_root)?;
-
-        let root_ca_path =
-            env_or_default!("SE_CERTIFICATE_ROOT_CA", DEFAULT_SE_CERTIFICATE_ROOT_CA);
-        let ca_option: Option<String> = if std::path::Path::new(&root_ca_path).exists() {
-            Some(root_ca_path)
-        } else {
-            None::<String>
-        };
-        let offline_certs_verify = env_or_default!(
-            "CERTS_OFFLINE_VERIFICATION",
-            DEFAULT_CERTS_OFFLINE_VERIFICATION
-        );
-        let offline_certs_verify: bool = offline_certs_verify.parse::<bool>().unwrap_or(false);
         let mut attestation_flags = AttestationFlags::default();
         attestation_flags.set_image_phkh();
         attestation_flags.set_attest_phkh();
@@ -260,52 +474,22 @@ impl SeVerifierImpl {
             attestation_flags,
         )?;
 
-        let hkds_root = env_or_default!(
-            "DEFAULT_SE_HOST_KEY_DOCUMENTS_ROOT",
-            DEFAULT_SE_HOST_KEY_DOCUMENTS_ROOT
-        );
-        let hkds = list_files_in_folder(&hkds_root)?;
-        for hkd in &hkds {
-            let hk = std::fs::read(hkd).context("read host-key document")?;
-            let certs = read_certs(&hk)?;
-            if certs.is_empty() {
-                warn!("The host key document in '{hkd}' contains empty certificate!");
-            }
-            if certs.len() != 1 {
-                warn!("The host key document in '{hkd}' contains more than one certificate!")
-            }
-            let c = certs
-                .first()
-                .ok_or(anyhow!("File does not contain a X509 certificate"))?;
-            #[cfg(debug_assertions)]
-            {
-                const DEFAULT_SE_SKIP_CERTS_VERIFICATION: &str = "false";
-                let skip_certs_env = env_or_default!(
-                    "SE_SKIP_CERTS_VERIFICATION",
-                    DEFAULT_SE_SKIP_CERTS_VERIFICATION
-                );
-                let skip_certs: bool = skip_certs_env.parse::<bool>().unwrap_or(false);
-                if !skip_certs {
-                    let verifier = CertVerifier::new(
-                        ca_certs.as_slice(),
-                        crls.as_slice(),
-                        ca_option.clone(),
-                        offline_certs_verify,
-                    )?;
-                    verifier.verify(c)?;
+        // The request is encrypted for all the valid host keys, so that the
+        // host keys are rotated by adding the new host-key documents before
+        // the old ones expire or are removed.
+        let hkds_root = SeMaterialKind::HostKeyDocument.dir();
+        let mut host_keys = 0;
+        for hkd in list_files_in_folder(&hkds_root)? {
+            match load_host_key_document(&hkd) {
+                Ok(c) => {
+                    arcb.add_hostkey(c.public_key()?);
+                    host_keys += 1;
                 }
+                Err(e) => warn!("Skip the host key document in '{hkd}': {e:#}"),
             }
-            #[cfg(not(debug_assertions))]
-            {
-                let verifier = CertVerifier::new(
-                    ca_certs.as_slice(),
-                    crls.as_slice(),
-                    ca_option.clone(),
-                    offline_certs_verify,
-                )?;
-                verifier.verify(c)?;
-            }
-            arcb.add_hostkey(c.public_key()?);
+        }
+        if host_keys == 0 {
+            bail!("No valid host key document in '{hkds_root}'");
         }
 
         let encr_ctx = ReqEncrCtx::random(SymKeyType::Aes256)?;
@@ -336,3 +520,18 @@ impl SeVerifierImpl {
         Ok(challenge)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn material_names() {
+        let path = material_path(SeMaterialKind::HostKeyDocument, "HKD-8651_2.crt").unwrap();
+        assert!(path.ends_with("HKD-8651_2.crt"));
+
+        for name in ["", ".hidden", "../root_ca.crt", "hkds/hkd.crt"] {
+            assert!(material_path(SeMaterialKind::Crl, name).is_err());
+        }
+    }
+}
@@ -14,7 +14,7 @@ use base64::Engine;
 use intel_tee_quote_verification_rs::{
     quote3_error_t, sgx_ql_qv_result_t, sgx_ql_qv_supplemental_t, sgx_ql_request_policy_t,
     sgx_qv_set_enclave_load_policy, tee_get_supplemental_data_version_and_size,
-    tee_qv_get_collateral, tee_supp_data_descriptor_t, tee_verify_quote,
+    tee_supp_data_descriptor_t, tee_verify_quote,
 };
 use log::{debug, warn};
 use scroll::Pread;
@@ -75,7 +75,7 @@ async fn verify_evidence(
 ) -> Result<TeeEvidenceParsedClaim> {
     let quote_bin = base64::engine::general_purpose::STANDARD.decode(evidence.quote)?;
 
-    ecdsa_quote_verification(&quote_bin)
+    let tcb_status = ecdsa_quote_verification(&quote_bin)
         .await
         .context("Evidence's identity verification error.")?;
 
@@ -97,10 +97,13 @@ async fn verify_evidence(
         }
     }
 
-    claims::generate_parsed_claims(quote)
+    let mut claims = claims::generate_parsed_claims(quote)?;
+    claims["tcb_status"] = serde_json::json!(tcb_status);
+    Ok(claims)
 }
 
-async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
+/// Verify the quote and return the TCB status of its platform.
+async fn ecdsa_quote_verification(quote: &[u8]) -> Result<&'static str> {
     let mut supp_data: sgx_ql_qv_supplemental_t = Default::default();
     let mut supp_data_desc = tee_supp_data_descriptor_t {
         major_version: 0,
@@ -146,17 +149,8 @@ async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
         ),
     }
 
-    // get collateral
-    let collateral = match tee_qv_get_collateral(quote) {
-        std::result::Result::Ok(c) => {
-            debug!("tee_qv_get_collateral successfully returned.");
-            Some(c)
-        }
-        Err(e) => {
-            warn!("tee_qv_get_collateral failed: {:#04x}", e as u32);
-            None
-        }
-    };
+    // get collateral, from the cache if the Intel PCS or the PCCS is not reachable
+    let collateral = crate::intel_dcap::get_collateral(quote)?;
 
     // set current time. This is only for sample purposes, in production mode a trusted time should be used.
     //
@@ -211,7 +205,7 @@ async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
         }
     }
 
-    Ok(())
+    Ok(crate::intel_dcap::tcb_status(quote_verification_result))
 }
 
 #[cfg(test)]
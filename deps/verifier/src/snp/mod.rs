@@ -17,6 +17,7 @@ use openssl::{
 use serde_json::json;
 use sev::firmware::guest::AttestationReport;
 use sev::firmware::host::{CertTableEntry, CertType};
+use std::path::Path;
 use std::sync::OnceLock;
 use x509_parser::prelude::*;
 
@@ -32,6 +33,18 @@ const SNP_SPL_OID: Oid<'static> = oid!(1.3.6 .1 .4 .1 .3704 .1 .3 .3);
 const TEE_SPL_OID: Oid<'static> = oid!(1.3.6 .1 .4 .1 .3704 .1 .3 .2);
 const LOADER_SPL_OID: Oid<'static> = oid!(1.3.6 .1 .4 .1 .3704 .1 .3 .1);
 
+/// Versions of the attestation report that are accepted. The versions 3 to 5
+/// only add fields in reserved fields of the version 2.
+const MIN_REPORT_VERSION: u32 = 2;
+const MAX_REPORT_VERSION: u32 = 5;
+
+// Offsets in the report of the fields added by the versions 3 and 5.
+const CPUID_FAM_ID_OFFSET: usize = 0x188;
+const CPUID_MOD_ID_OFFSET: usize = 0x189;
+const CPUID_STEP_OFFSET: usize = 0x18A;
+const LAUNCH_MIT_VECTOR_OFFSET: usize = 0x1F8;
+const CURRENT_MIT_VECTOR_OFFSET: usize = 0x200;
+
 #[derive(Debug)]
 pub struct Snp {
     vendor_certs: VendorCertificates,
@@ -84,13 +97,20 @@ impl Verifier for Snp {
             cert_chain,
         } = serde_json::from_slice(evidence).context("Deserialize Quote failed.")?;
 
-        let Some(cert_chain) = cert_chain else {
-            bail!("Cert chain is unset");
+        let cert_chain = match cert_chain {
+            Some(cert_chain) => cert_chain,
+            None => match crate::offline::bundle() {
+                Some(bundle) => {
+                    bundled_cert_chain(&bundle.join("snp"), &report, &self.vendor_certs)?
+                }
+                None => bail!("Cert chain is unset"),
+            },
         };
 
-        verify_report_signature(&report, &cert_chain, &self.vendor_certs)?;
+        let endorsement_key_type =
+            verify_report_signature(&report, &cert_chain, &self.vendor_certs)?;
 
-        if report.version != 2 {
+        if !(MIN_REPORT_VERSION..=MAX_REPORT_VERSION).contains(&report.version) {
             return Err(anyhow!("Unexpected report version"));
         }
 
@@ -122,7 +142,12 @@ impl Verifier for Snp {
             }
         }
 
-        let claims_map = parse_tee_evidence(&report);
+        let mut claims_map = parse_tee_evidence(&report);
+        let endorsement_key = match endorsement_key_type {
+            CertType::VLEK => "vlek",
+            _ => "vcek",
+        };
+        claims_map["endorsement_key"] = json!(endorsement_key);
         let json = json!(claims_map);
         Ok(json)
     }
@@ -162,17 +187,19 @@ fn get_oid_int(cert: &x509_parser::certificate::TbsCertificate, oid: Oid) -> Res
     val_int.as_u8().context("Unexpected data size")
 }
 
+/// Verify the signature of the report and the certificate chain of its
+/// endorsement key, and return the type of the key, VCEK or VLEK.
 pub(crate) fn verify_report_signature(
     report: &AttestationReport,
     cert_chain: &[CertTableEntry],
     vendor_certs: &VendorCertificates,
-) -> Result<()> {
+) -> Result<CertType> {
     // check cert chain
     let VendorCertificates { ask, ark, asvk } = vendor_certs;
 
     // verify VCEK or VLEK cert chain
     // the key can be either VCEK or VLEK
-    let endorsement_key = verify_cert_chain(cert_chain, ask, ark, asvk)?;
+    let (endorsement_key, endorsement_key_type) = verify_cert_chain(cert_chain, ask, ark, asvk)?;
 
     // OpenSSL bindings do not expose custom extensions
     // Parse the key using x509_parser
@@ -181,12 +208,9 @@ pub(crate) fn verify_report_signature(
         .1
         .tbs_certificate;
 
-    let common_name =
-        get_common_name(&endorsement_key).context("No common name found in certificate")?;
-
-    // if the common name is "VCEK", then the key is a VCEK
-    // so lets check the chip id
-    if common_name == "VCEK"
+    // a VCEK is specific to a chip, so lets check the chip id. A VLEK is
+    // specific to a cloud provider instead.
+    if endorsement_key_type == CertType::VCEK
         && get_oid_octets::<64>(&parsed_endorsement_key, HW_ID_OID)? != report.chip_id
     {
         bail!("Chip ID mismatch");
@@ -220,7 +244,39 @@ pub(crate) fn verify_report_signature(
         return Err(anyhow!("Signature validation failed."));
     }
 
-    Ok(())
+    Ok(endorsement_key_type)
+}
+
+/// The certificate chain of the VCEK or VLEK of the offline bundle `dir`
+/// that signed `report`.
+fn bundled_cert_chain(
+    dir: &Path,
+    report: &AttestationReport,
+    vendor_certs: &VendorCertificates,
+) -> Result<Vec<CertTableEntry>> {
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("read SNP certs {}", dir.display()))?;
+    for entry in entries {
+        let content = std::fs::read(entry?.path())?;
+        let certs = match X509::stack_from_pem(&content) {
+            std::result::Result::Ok(certs) if !certs.is_empty() => certs,
+            _ => vec![X509::from_der(&content)?],
+        };
+
+        for cert in certs {
+            let cert_type = match get_common_name(&cert) {
+                std::result::Result::Ok(name) if name.ends_with("VCEK") => CertType::VCEK,
+                std::result::Result::Ok(name) if name.ends_with("VLEK") => CertType::VLEK,
+                _ => continue,
+            };
+            let cert_chain = vec![CertTableEntry::new(cert_type, cert.to_der()?)];
+            if verify_report_signature(report, &cert_chain, vendor_certs).is_ok() {
+                return Ok(cert_chain);
+            }
+        }
+    }
+
+    bail!("No VCEK or VLEK of the report in the offline bundle")
 }
 
 fn verify_signature(cert: &X509, issuer: &X509, name: &str) -> Result<()> {
@@ -234,7 +290,7 @@ fn verify_cert_chain(
     ask: &X509,
     ark: &X509,
     asvk: &X509,
-) -> Result<X509> {
+) -> Result<(X509, CertType)> {
     // get endorsement keys (VLEK or VCEK)
     let endorsement_keys: Vec<&CertTableEntry> = cert_chain
         .iter()
@@ -264,11 +320,11 @@ fn verify_cert_chain(
         _ => bail!("Certificate not of type versioned endorsement key (VLEK or VCEK)"),
     }
 
-    Ok(decoded_key)
+    Ok((decoded_key, key.cert_type.clone()))
 }
 
 pub(crate) fn parse_tee_evidence(report: &AttestationReport) -> TeeEvidenceParsedClaim {
-    let claims_map = json!({
+    let mut claims_map = json!({
         // policy fields
         "policy_abi_major": format!("{}",report.policy.abi_major()),
         "policy_abi_minor": format!("{}", report.policy.abi_minor()),
@@ -289,8 +345,34 @@ pub(crate) fn parse_tee_evidence(report: &AttestationReport) -> TeeEvidenceParse
 
         // measurement
         "measurement": format!("{}", base64::engine::general_purpose::STANDARD.encode(report.measurement)),
+
+        "version": format!("{}", report.version),
+
+        // HOST_DATA, where the digest of the init data is put
+        "init_data": hex::encode(report.host_data),
     });
 
+    // The fields of the newer versions of the report are in reserved fields
+    // of the version 2.
+    let std::result::Result::Ok(raw) = bincode::serialize(report) else {
+        return claims_map as TeeEvidenceParsedClaim;
+    };
+    if report.version >= 3 {
+        claims_map["cpuid_fam_id"] = json!(format!("{}", raw[CPUID_FAM_ID_OFFSET]));
+        claims_map["cpuid_mod_id"] = json!(format!("{}", raw[CPUID_MOD_ID_OFFSET]));
+        claims_map["cpuid_step"] = json!(format!("{}", raw[CPUID_STEP_OFFSET]));
+    }
+    if report.version >= 5 {
+        let read_u64 = |offset: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&raw[offset..offset + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        claims_map["launch_mit_vector"] = json!(format!("{}", read_u64(LAUNCH_MIT_VECTOR_OFFSET)));
+        claims_map["current_mit_vector"] =
+            json!(format!("{}", read_u64(CURRENT_MIT_VECTOR_OFFSET)));
+    }
+
     claims_map as TeeEvidenceParsedClaim
 }
 
@@ -482,4 +564,66 @@ mod tests {
         let vendor_certs = load_milan_cert_chain().as_ref().unwrap();
         verify_report_signature(&attestation_report, &cert_chain, vendor_certs).unwrap_err();
     }
+
+    #[test]
+    fn check_endorsement_key_type() {
+        let vendor_certs = load_milan_cert_chain().as_ref().unwrap();
+
+        let attestation_report =
+            bincode::deserialize::<AttestationReport>(VCEK_REPORT.as_slice()).unwrap();
+        let cert_chain = vec![CertTableEntry::new(CertType::VCEK, VCEK.to_vec())];
+        let key_type =
+            verify_report_signature(&attestation_report, &cert_chain, vendor_certs).unwrap();
+        assert_eq!(key_type, CertType::VCEK);
+
+        let attestation_report =
+            bincode::deserialize::<AttestationReport>(VLEK_REPORT.as_slice()).unwrap();
+        let cert_chain = vec![CertTableEntry::new(CertType::VLEK, VLEK.to_vec())];
+        let key_type =
+            verify_report_signature(&attestation_report, &cert_chain, vendor_certs).unwrap();
+        assert_eq!(key_type, CertType::VLEK);
+    }
+
+    #[test]
+    fn check_newer_report_claims() {
+        let claims = parse_tee_evidence(
+            &bincode::deserialize::<AttestationReport>(VCEK_REPORT.as_slice()).unwrap(),
+        );
+        assert_eq!(claims["version"], "2");
+        assert_eq!(claims["init_data"], hex::encode([0u8; 32]));
+        assert!(claims.get("cpuid_fam_id").is_none());
+        assert!(claims.get("launch_mit_vector").is_none());
+
+        let mut bytes = VCEK_REPORT.clone();
+        bytes[0] = 5;
+        bytes[CPUID_FAM_ID_OFFSET] = 0x19;
+        bytes[CPUID_MOD_ID_OFFSET] = 0x11;
+        bytes[CPUID_STEP_OFFSET] = 0x01;
+        bytes[LAUNCH_MIT_VECTOR_OFFSET] = 0x03;
+        bytes[CURRENT_MIT_VECTOR_OFFSET + 1] = 0x01;
+        let claims =
+            parse_tee_evidence(&bincode::deserialize::<AttestationReport>(&bytes).unwrap());
+        assert_eq!(claims["version"], "5");
+        assert_eq!(claims["cpuid_fam_id"], "25");
+        assert_eq!(claims["cpuid_mod_id"], "17");
+        assert_eq!(claims["cpuid_step"], "1");
+        assert_eq!(claims["launch_mit_vector"], "3");
+        assert_eq!(claims["current_mit_vector"], "256");
+    }
+
+    #[test]
+    fn check_bundled_cert_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("vcek.der"), VCEK).unwrap();
+        std::fs::write(dir.path().join("vlek.der"), VLEK).unwrap();
+        let vendor_certs = load_milan_cert_chain().as_ref().unwrap();
+
+        let attestation_report =
+            bincode::deserialize::<AttestationReport>(VLEK_REPORT.as_slice()).unwrap();
+        let cert_chain = bundled_cert_chain(dir.path(), &attestation_report, vendor_certs).unwrap();
+        assert_eq!(cert_chain[0].cert_type, CertType::VLEK);
+
+        std::fs::remove_file(dir.path().join("vlek.der")).unwrap();
+        bundled_cert_chain(dir.path(), &attestation_report, vendor_certs).unwrap_err();
+    }
 }
@@ -54,7 +54,7 @@ async fn verify_evidence(
 ) -> Result<TeeEvidenceParsedClaim> {
     // Verify TD quote ECDSA signature.
     let quote_bin = base64::engine::general_purpose::STANDARD.decode(evidence.quote)?;
-    ecdsa_quote_verification(quote_bin.as_slice()).await?;
+    let tcb_status = ecdsa_quote_verification(quote_bin.as_slice()).await?;
 
     info!("Quote DCAP check succeeded.");
 
@@ -127,7 +127,9 @@ async fn verify_evidence(
     };
 
     // Return Evidence parsed claim
-    generate_parsed_claim(quote, ccel_option, aael)
+    let mut claims = generate_parsed_claim(quote, ccel_option, aael)?;
+    claims["tcb_status"] = serde_json::json!(tcb_status);
+    Ok(claims)
 }
 
 #[cfg(test)]
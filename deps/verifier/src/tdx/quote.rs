@@ -4,7 +4,7 @@ use log::{debug, warn};
 use qvl::{
     quote3_error_t, sgx_ql_qv_result_t, sgx_ql_qv_supplemental_t, sgx_ql_request_policy_t,
     sgx_qv_set_enclave_load_policy, tee_get_supplemental_data_version_and_size,
-    tee_qv_get_collateral, tee_supp_data_descriptor_t, tee_verify_quote,
+    tee_supp_data_descriptor_t, tee_verify_quote,
 };
 use scroll::Pread;
 use std::mem;
@@ -394,7 +394,8 @@ pub fn parse_tdx_quote(quote_bin: &[u8]) -> Result<Quote> {
     }
 }
 
-pub async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
+/// Verify the quote and return the TCB status of its platform.
+pub async fn ecdsa_quote_verification(quote: &[u8]) -> Result<&'static str> {
     let mut supp_data: sgx_ql_qv_supplemental_t = Default::default();
     let mut supp_data_desc = tee_supp_data_descriptor_t {
         major_version: 0,
@@ -440,17 +441,8 @@ pub async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
         ),
     }
 
-    // get collateral
-    let collateral = match tee_qv_get_collateral(quote) {
-        Ok(c) => {
-            debug!("tee_qv_get_collateral successfully returned.");
-            Some(c)
-        }
-        Err(e) => {
-            warn!("tee_qv_get_collateral failed: {:#04x}", e as u32);
-            None
-        }
-    };
+    // get collateral, from the cache if the Intel PCS or the PCCS is not reachable
+    let collateral = crate::intel_dcap::get_collateral(quote)?;
 
     // set current time. This is only for sample purposes, in production mode a trusted time should be used.
     //
@@ -505,7 +497,7 @@ pub async fn ecdsa_quote_verification(quote: &[u8]) -> Result<()> {
         }
     }
 
-    Ok(())
+    Ok(crate::intel_dcap::tcb_status(quote_verification_result))
 }
 
 #[cfg(test)]
@@ -0,0 +1,221 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! TCG PC Client crypto agile event logs, as exposed by the firmware in
+//! `/sys/kernel/security/tpm0/binary_bios_measurements`.
+
+use super::quote::message_digest;
+use anyhow::{bail, Context, Result};
+use openssl::hash::{Hasher, MessageDigest};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+const EV_NO_ACTION: u32 = 0x00000003;
+const EV_IPL: u32 = 0x0000000d;
+const EV_EFI_VARIABLE_DRIVER_CONFIG: u32 = 0x80000001;
+const EV_EFI_BOOT_SERVICES_APPLICATION: u32 = 0x80000003;
+
+const SPEC_ID_EVENT_SIGNATURE: &[u8] = b"Spec ID Event03\0";
+const STARTUP_LOCALITY_SIGNATURE: &[u8] = b"StartupLocality\0";
+
+struct Event {
+    pcr: u32,
+    event_type: u32,
+    digests: BTreeMap<u16, Vec<u8>>,
+    data: Vec<u8>,
+}
+
+/// Reads the little endian fields of an event log.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            bail!("truncated TPM event log");
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+}
+
+pub(super) struct EventLog {
+    events: Vec<Event>,
+
+    /// Locality of the TPM2_Startup, that is the initial value of PCR 0.
+    startup_locality: u8,
+}
+
+impl EventLog {
+    pub fn parse(log: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes: log };
+
+        // The first event is in the SHA-1 format, and gives the digest sizes
+        // of the following ones.
+        let _pcr = reader.u32()?;
+        let _event_type = reader.u32()?;
+        let _digest = reader.take(20)?;
+        let size = reader.u32()?;
+        let mut spec_id = Reader {
+            bytes: reader.take(size as usize)?,
+        };
+        if spec_id.take(SPEC_ID_EVENT_SIGNATURE.len())? != SPEC_ID_EVENT_SIGNATURE {
+            bail!("TPM event log is not crypto agile");
+        }
+        // platformClass, specVersionMinor, specVersionMajor, specErrata and
+        // uintnSize.
+        spec_id.take(8)?;
+        let mut digest_sizes = BTreeMap::new();
+        for _ in 0..spec_id.u32()? {
+            let alg = spec_id.u16()?;
+            let size = spec_id.u16()?;
+            digest_sizes.insert(alg, usize::from(size));
+        }
+
+        let mut events = Vec::new();
+        let mut startup_locality = 0;
+        while !reader.bytes.is_empty() {
+            let pcr = reader.u32()?;
+            let event_type = reader.u32()?;
+            let mut digests = BTreeMap::new();
+            for _ in 0..reader.u32()? {
+                let alg = reader.u16()?;
+                let size = *digest_sizes
+                    .get(&alg)
+                    .with_context(|| format!("unknown TPM event log digest {alg:#06x}"))?;
+                digests.insert(alg, reader.take(size)?.to_vec());
+            }
+            let size = reader.u32()?;
+            let data = reader.take(size as usize)?.to_vec();
+
+            if event_type == EV_NO_ACTION && data.starts_with(STARTUP_LOCALITY_SIGNATURE) {
+                startup_locality = data
+                    .get(STARTUP_LOCALITY_SIGNATURE.len())
+                    .copied()
+                    .context("illegal StartupLocality event")?;
+            }
+
+            events.push(Event {
+                pcr,
+                event_type,
+                digests,
+                data,
+            });
+        }
+
+        Ok(Self {
+            events,
+            startup_locality,
+        })
+    }
+
+    /// Replay the events of the `bank` digests, and return the values of the
+    /// PCRs they extend.
+    pub fn replay(&self, bank: u16) -> Result<BTreeMap<u32, Vec<u8>>> {
+        let digest = message_digest(bank)?;
+        let mut pcrs = BTreeMap::new();
+        for event in &self.events {
+            if event.event_type == EV_NO_ACTION {
+                continue;
+            }
+
+            let event_digest = event
+                .digests
+                .get(&bank)
+                .with_context(|| format!("TPM event log has no {bank:#06x} digest"))?;
+            let pcr = pcrs.entry(event.pcr).or_insert_with(|| {
+                let mut pcr = vec![0; digest.size()];
+                if event.pcr == 0 {
+                    pcr[digest.size() - 1] = self.startup_locality;
+                }
+                pcr
+            });
+            *pcr = extend(digest, pcr, event_digest)?;
+        }
+
+        Ok(pcrs)
+    }
+
+    /// The measured boot claims of the event log:
+    /// - `secure_boot`: whether UEFI Secure Boot is enabled.
+    /// - `boot_applications`: the `bank` digests of the UEFI applications
+    ///   loaded, e.g. shim, the boot loader and the kernel.
+    /// - `ipl`: the strings measured by the boot loader, e.g. the kernel
+    ///   command line.
+    pub fn to_parsed_claims(&self, bank: u16) -> Value {
+        let mut secure_boot = false;
+        let mut boot_applications = Vec::new();
+        let mut ipl = Vec::new();
+        for event in &self.events {
+            match event.event_type {
+                EV_EFI_VARIABLE_DRIVER_CONFIG => {
+                    if let Some((name, data)) = uefi_variable(&event.data) {
+                        if name == "SecureBoot" {
+                            secure_boot = data.first() == Some(&1);
+                        }
+                    }
+                }
+                EV_EFI_BOOT_SERVICES_APPLICATION => {
+                    if let Some(digest) = event.digests.get(&bank) {
+                        boot_applications.push(hex::encode(digest));
+                    }
+                }
+                EV_IPL => {
+                    let data = event
+                        .data
+                        .split(|byte| *byte == 0)
+                        .next()
+                        .unwrap_or_default();
+                    ipl.push(String::from_utf8_lossy(data).into_owned());
+                }
+                _ => {}
+            }
+        }
+
+        json!({
+            "secure_boot": secure_boot,
+            "boot_applications": boot_applications,
+            "ipl": ipl,
+        })
+    }
+}
+
+fn extend(digest: MessageDigest, pcr: &[u8], event_digest: &[u8]) -> Result<Vec<u8>> {
+    let mut hasher = Hasher::new(digest)?;
+    hasher.update(pcr)?;
+    hasher.update(event_digest)?;
+    Ok(hasher.finish()?.to_vec())
+}
+
+/// The name and data of the UEFI_VARIABLE_DATA of an event.
+fn uefi_variable(data: &[u8]) -> Option<(String, &[u8])> {
+    let mut reader = Reader { bytes: data };
+    let _guid = reader.take(16).ok()?;
+    let name_len = reader.u64().ok()?;
+    let data_len = reader.u64().ok()?;
+    let name = reader
+        .take(usize::try_from(name_len).ok()?.checked_mul(2)?)
+        .ok()?;
+    let name: Vec<u16> = name
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    let data = reader.take(usize::try_from(data_len).ok()?).ok()?;
+    Some((String::from_utf16_lossy(&name), data))
+}
@@ -0,0 +1,373 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Verifier of TPM 2.0 quotes, for hosts with a TPM but no TEE.
+//!
+//! The quote is signed by an attestation key (AK) certified by a configured
+//! CA, and the TCG event log of the measured boot is replayed against the
+//! quoted PCRs.
+
+use super::*;
+use async_trait::async_trait;
+use base64::Engine;
+use core::result::Result::Ok;
+use log::warn;
+use openssl::hash::Hasher;
+use openssl::stack::Stack;
+use openssl::x509::store::{X509Store, X509StoreBuilder};
+use openssl::x509::{X509StoreContext, X509};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+use eventlog::EventLog;
+use quote::Quote;
+
+mod eventlog;
+mod quote;
+
+/// Path to the PEM certificates of the CAs certifying the attestation keys.
+const TPM_AK_CA_CERTS: &str = "TPM_AK_CA_CERTS";
+
+#[derive(Serialize, Deserialize)]
+pub struct TpmEvidence {
+    /// Base64 encoded TPMS_ATTEST of the quote.
+    quote: String,
+
+    /// Base64 encoded TPMT_SIGNATURE of the quote.
+    signature: String,
+
+    /// Hex encoded values of the quoted PCRs, by PCR index.
+    pcrs: BTreeMap<u32, String>,
+
+    /// Base64 encoded PEM certificate chain of the attestation key, leaf
+    /// first.
+    ak_cert_chain: String,
+
+    /// Base64 encoded TCG event log of the measured boot.
+    #[serde(default)]
+    event_log: Option<String>,
+}
+
+pub struct Tpm {
+    ca: X509Store,
+}
+
+impl Tpm {
+    pub fn new() -> Result<Self> {
+        let path = crate::offline::env_or_bundle(TPM_AK_CA_CERTS, "tpm/ak-ca.pem")
+            .with_context(|| format!("{TPM_AK_CA_CERTS} is not set"))?;
+        let ca = std::fs::read(&path).with_context(|| format!("read TPM AK CA {path}"))?;
+        Self::with_ca(&ca)
+    }
+
+    fn with_ca(ca: &[u8]) -> Result<Self> {
+        let mut store = X509StoreBuilder::new()?;
+        for cert in X509::stack_from_pem(ca).context("illegal TPM AK CA")? {
+            store.add_cert(cert)?;
+        }
+
+        Ok(Self { ca: store.build() })
+    }
+
+    fn verify_ak(&self, ak_cert_chain: &str) -> Result<X509> {
+        let ak_cert_chain = base64::engine::general_purpose::STANDARD
+            .decode(ak_cert_chain)
+            .context("illegal AK certificate chain")?;
+        let mut certs = X509::stack_from_pem(&ak_cert_chain)
+            .context("illegal AK certificate chain")?
+            .into_iter();
+        let ak = certs.next().context("empty AK certificate chain")?;
+        let mut chain = Stack::new()?;
+        for cert in certs {
+            chain.push(cert)?;
+        }
+
+        let mut context = X509StoreContext::new()?;
+        if !context.init(&self.ca, &ak, &chain, |ctx| ctx.verify_cert())? {
+            bail!("TPM attestation key is not certified by a trusted CA");
+        }
+
+        Ok(ak)
+    }
+}
+
+#[async_trait]
+impl Verifier for Tpm {
+    /// The following verification steps are performed:
+    /// 1. The AK certificate chain is rooted in a trusted CA
+    /// 2. TPM Quote has been signed by the AK
+    /// 3. TPM Quote nonce matches report_data
+    /// 4. Digest of the PCR values matches the digest in the Quote
+    /// 5. The event log replays to the PCR values
+    async fn evaluate(
+        &self,
+        evidence: &[u8],
+        expected_report_data: &ReportData,
+        expected_init_data_hash: &InitDataHash,
+    ) -> Result<TeeEvidenceParsedClaim> {
+        let ReportData::Value(expected_report_data) = expected_report_data else {
+            bail!("TPM verifier must provide report data field!");
+        };
+
+        if let InitDataHash::Value(_) = expected_init_data_hash {
+            warn!("TPM verifier does not support verify init data hash, will ignore the input `init_data_hash`.");
+        }
+
+        let evidence = serde_json::from_slice::<TpmEvidence>(evidence)
+            .context("Deserialize TPM Evidence failed.")?;
+
+        let ak = self.verify_ak(&evidence.ak_cert_chain)?;
+
+        let quote = base64::engine::general_purpose::STANDARD
+            .decode(&evidence.quote)
+            .context("illegal TPM quote")?;
+        let quote = Quote::parse(&quote)?;
+        let signature = base64::engine::general_purpose::STANDARD
+            .decode(&evidence.signature)
+            .context("illegal TPM quote signature")?;
+        let digest = quote.verify_signature(&signature, &ak.public_key()?)?;
+
+        if quote.extra_data != *expected_report_data {
+            bail!("TPM quote nonce is different from the expected report data");
+        }
+
+        let mut pcrs = BTreeMap::new();
+        let mut hasher = Hasher::new(digest)?;
+        for index in &quote.pcrs {
+            let value = evidence
+                .pcrs
+                .get(index)
+                .with_context(|| format!("TPM evidence has no value of quoted PCR {index}"))?;
+            let value = hex::decode(value).with_context(|| format!("illegal PCR {index}"))?;
+            hasher.update(&value)?;
+            pcrs.insert(*index, value);
+        }
+        if *hasher.finish()? != quote.pcr_digest[..] {
+            bail!("Digest of PCRs does not match digest in Quote");
+        }
+
+        let pcr_claims: Map<String, Value> = pcrs
+            .iter()
+            .map(|(index, value)| (format!("pcr{index:02}"), hex::encode(value).into()))
+            .collect();
+        let mut claims = json!({
+            "pcrs": pcr_claims,
+            "report_data": hex::encode(expected_report_data),
+        });
+
+        if let Some(event_log) = &evidence.event_log {
+            let event_log = base64::engine::general_purpose::STANDARD
+                .decode(event_log)
+                .context("illegal TPM event log")?;
+            let event_log = EventLog::parse(&event_log)?;
+            for (index, value) in event_log.replay(quote.pcr_bank)? {
+                if let Some(quoted) = pcrs.get(&index) {
+                    if *quoted != value {
+                        bail!("TPM event log does not replay to the quoted PCR {index}");
+                    }
+                }
+            }
+            claims["measured_boot"] = event_log.to_parsed_claims(quote.pcr_bank);
+        }
+
+        Ok(claims as TeeEvidenceParsedClaim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::sign::Signer;
+    use openssl::x509::extension::BasicConstraints;
+    use openssl::x509::X509Name;
+
+    fn certificate(
+        name: &str,
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+    ) -> X509 {
+        let mut subject = X509Name::builder().unwrap();
+        subject.append_entry_by_text("CN", name).unwrap();
+        let subject = subject.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&subject).unwrap();
+        cert.set_pubkey(key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        match issuer {
+            Some((issuer, issuer_key)) => {
+                cert.set_issuer_name(issuer.subject_name()).unwrap();
+                cert.sign(issuer_key, MessageDigest::sha256()).unwrap();
+            }
+            None => {
+                cert.append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                    .unwrap();
+                cert.set_issuer_name(&subject).unwrap();
+                cert.sign(key, MessageDigest::sha256()).unwrap();
+            }
+        }
+        cert.build()
+    }
+
+    fn sha256(data: &[u8]) -> Vec<u8> {
+        openssl::hash::hash(MessageDigest::sha256(), data)
+            .unwrap()
+            .to_vec()
+    }
+
+    /// The UEFI_VARIABLE_DATA of an enabled SecureBoot variable.
+    fn secure_boot_variable() -> Vec<u8> {
+        let mut data = [0x61; 16].to_vec();
+        data.extend(10u64.to_le_bytes());
+        data.extend(1u64.to_le_bytes());
+        for c in "SecureBoot".encode_utf16() {
+            data.extend(c.to_le_bytes());
+        }
+        data.push(1);
+        data
+    }
+
+    /// A crypto agile event log of a SHA-256 bank, with the SecureBoot
+    /// variable and a boot application measured in PCR 7 and 4.
+    fn event_log(boot_application: &[u8]) -> Vec<u8> {
+        // The EV_NO_ACTION Spec ID event.
+        let mut log = Vec::new();
+        log.extend(0u32.to_le_bytes());
+        log.extend(3u32.to_le_bytes());
+        log.extend([0; 20]);
+        let mut spec_id = b"Spec ID Event03\0".to_vec();
+        spec_id.extend([0; 8]);
+        spec_id.extend(1u32.to_le_bytes());
+        spec_id.extend(0x000bu16.to_le_bytes());
+        spec_id.extend(32u16.to_le_bytes());
+        spec_id.push(0);
+        log.extend((spec_id.len() as u32).to_le_bytes());
+        log.extend(spec_id);
+
+        let secure_boot = secure_boot_variable();
+        for (pcr, event_type, data, digest) in [
+            (
+                7u32,
+                0x80000001u32,
+                secure_boot.clone(),
+                sha256(&secure_boot),
+            ),
+            (4, 0x80000003, vec![0; 4], sha256(boot_application)),
+        ] {
+            log.extend(pcr.to_le_bytes());
+            log.extend(event_type.to_le_bytes());
+            log.extend(1u32.to_le_bytes());
+            log.extend(0x000bu16.to_le_bytes());
+            log.extend(digest);
+            log.extend((data.len() as u32).to_le_bytes());
+            log.extend(data);
+        }
+
+        log
+    }
+
+    fn replayed_pcr(events: &[&[u8]]) -> Vec<u8> {
+        events.iter().fold(vec![0; 32], |pcr, event| {
+            sha256(&[pcr.as_slice(), &sha256(event)].concat())
+        })
+    }
+
+    /// A quote of the SHA-256 PCRs 4 and 7 signed with RSASSA.
+    fn quote(ak: &PKey<Private>, nonce: &[u8], pcr4: &[u8], pcr7: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut attest = 0xff544347u32.to_be_bytes().to_vec();
+        attest.extend(0x8018u16.to_be_bytes());
+        attest.extend(2u16.to_be_bytes());
+        attest.extend([0, 0]);
+        attest.extend((nonce.len() as u16).to_be_bytes());
+        attest.extend(nonce);
+        attest.extend([0; 25]);
+        attest.extend(1u32.to_be_bytes());
+        attest.extend(0x000bu16.to_be_bytes());
+        attest.extend([3, 0b1001_0000, 0, 0]);
+        let pcr_digest = sha256(&[pcr4, pcr7].concat());
+        attest.extend(32u16.to_be_bytes());
+        attest.extend(pcr_digest);
+
+        let mut signer = Signer::new(MessageDigest::sha256(), ak).unwrap();
+        let rsa_signature = signer.sign_oneshot_to_vec(&attest).unwrap();
+        let mut signature = 0x0014u16.to_be_bytes().to_vec();
+        signature.extend(0x000bu16.to_be_bytes());
+        signature.extend((rsa_signature.len() as u16).to_be_bytes());
+        signature.extend(rsa_signature);
+
+        (attest, signature)
+    }
+
+    #[tokio::test]
+    async fn verify_tpm_quote() {
+        let ca_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let ca = certificate("TPM AK CA", &ca_key, None);
+        let ak = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let ak_cert = certificate("TPM AK", &ak, Some((&ca, &ca_key)));
+        let verifier = Tpm::with_ca(&ca.to_pem().unwrap()).unwrap();
+
+        let log = event_log(b"shim");
+        let pcr4 = replayed_pcr(&[b"shim"]);
+        let pcr7 = replayed_pcr(&[&secure_boot_variable()]);
+
+        let nonce = [7; 32];
+        let evidence = |pcr4: &[u8], log: &[u8]| {
+            let (attest, signature) = quote(&ak, &nonce, pcr4, &pcr7);
+            serde_json::to_vec(&TpmEvidence {
+                quote: base64::engine::general_purpose::STANDARD.encode(attest),
+                signature: base64::engine::general_purpose::STANDARD.encode(signature),
+                pcrs: BTreeMap::from([(4, hex::encode(pcr4)), (7, hex::encode(&pcr7))]),
+                ak_cert_chain: base64::engine::general_purpose::STANDARD
+                    .encode(ak_cert.to_pem().unwrap()),
+                event_log: Some(base64::engine::general_purpose::STANDARD.encode(log)),
+            })
+            .unwrap()
+        };
+
+        let claims = verifier
+            .evaluate(
+                &evidence(&pcr4, &log),
+                &ReportData::Value(&nonce),
+                &InitDataHash::NotProvided,
+            )
+            .await
+            .unwrap();
+        assert_eq!(claims["pcrs"]["pcr04"], hex::encode(&pcr4));
+        assert_eq!(claims["measured_boot"]["secure_boot"], true);
+        assert_eq!(
+            claims["measured_boot"]["boot_applications"][0],
+            hex::encode(sha256(b"shim"))
+        );
+
+        // The event log of another boot application.
+        assert!(verifier
+            .evaluate(
+                &evidence(&pcr4, &event_log(b"grub")),
+                &ReportData::Value(&nonce),
+                &InitDataHash::NotProvided,
+            )
+            .await
+            .is_err());
+
+        // A wrong nonce.
+        assert!(verifier
+            .evaluate(
+                &evidence(&pcr4, &log),
+                &ReportData::Value(&[0; 32]),
+                &InitDataHash::NotProvided,
+            )
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! TPM 2.0 quotes: the TPMS_ATTEST structure returned by TPM2_Quote, and its
+//! TPMT_SIGNATURE by the attestation key.
+
+use anyhow::{bail, Context, Result};
+use openssl::bn::BigNum;
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Public};
+use openssl::rsa::Padding;
+use openssl::sign::{RsaPssSaltlen, Verifier};
+
+const TPM_GENERATED_VALUE: u32 = 0xff544347;
+const TPM_ST_ATTEST_QUOTE: u16 = 0x8018;
+
+pub(super) const TPM_ALG_SHA256: u16 = 0x000b;
+pub(super) const TPM_ALG_SHA384: u16 = 0x000c;
+pub(super) const TPM_ALG_SHA512: u16 = 0x000d;
+
+const TPM_ALG_RSASSA: u16 = 0x0014;
+const TPM_ALG_RSAPSS: u16 = 0x0016;
+const TPM_ALG_ECDSA: u16 = 0x0018;
+
+/// Reads the big endian fields of a TPM structure.
+pub(super) struct Reader<'a> {
+    pub bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            bail!("truncated TPM structure");
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    /// A TPM2B structure, i.e. a buffer prefixed by its 16 bits size.
+    pub fn tpm2b(&mut self) -> Result<&'a [u8]> {
+        let size = self.u16()?;
+        self.take(size.into())
+    }
+}
+
+pub(super) fn message_digest(hash_alg: u16) -> Result<MessageDigest> {
+    match hash_alg {
+        TPM_ALG_SHA256 => Ok(MessageDigest::sha256()),
+        TPM_ALG_SHA384 => Ok(MessageDigest::sha384()),
+        TPM_ALG_SHA512 => Ok(MessageDigest::sha512()),
+        alg => bail!("unsupported TPM hash algorithm {alg:#06x}"),
+    }
+}
+
+pub(super) struct Quote {
+    /// The qualifying data of the quote, i.e. its nonce.
+    pub extra_data: Vec<u8>,
+
+    /// Hash algorithm of the quoted PCR bank.
+    pub pcr_bank: u16,
+
+    /// Indexes of the quoted PCRs, ascending.
+    pub pcrs: Vec<u32>,
+
+    /// Digest of the quoted PCR values.
+    pub pcr_digest: Vec<u8>,
+
+    attest: Vec<u8>,
+}
+
+impl Quote {
+    /// Parse a TPMS_ATTEST structure of a quote.
+    pub fn parse(attest: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes: attest };
+        if reader.u32()? != TPM_GENERATED_VALUE {
+            bail!("TPM quote is not generated by a TPM");
+        }
+        if reader.u16()? != TPM_ST_ATTEST_QUOTE {
+            bail!("TPM attestation is not a quote");
+        }
+
+        let _qualified_signer = reader.tpm2b()?;
+        let extra_data = reader.tpm2b()?.to_vec();
+        // clockInfo and firmwareVersion.
+        reader.take(17 + 8)?;
+
+        let selections = reader.u32()?;
+        if selections != 1 {
+            bail!("TPM quote must select the PCRs of exactly one bank");
+        }
+        let pcr_bank = reader.u16()?;
+        let size = reader.u8()?;
+        let bitmap = reader.take(size.into())?;
+        let pcrs = (0..u32::from(size) * 8)
+            .filter(|pcr| bitmap[(pcr / 8) as usize] & (1 << (pcr % 8)) != 0)
+            .collect();
+        let pcr_digest = reader.tpm2b()?.to_vec();
+
+        Ok(Self {
+            extra_data,
+            pcr_bank,
+            pcrs,
+            pcr_digest,
+            attest: attest.to_vec(),
+        })
+    }
+
+    /// Verify the TPMT_SIGNATURE of the quote by `ak`, and return its hash
+    /// algorithm, that is also the one of the PCR digest.
+    pub fn verify_signature(&self, signature: &[u8], ak: &PKey<Public>) -> Result<MessageDigest> {
+        let mut reader = Reader { bytes: signature };
+        let sig_alg = reader.u16()?;
+        let digest = message_digest(reader.u16()?)?;
+        let mut verifier = Verifier::new(digest, ak)?;
+
+        let signature = match sig_alg {
+            TPM_ALG_RSASSA => reader.tpm2b()?.to_vec(),
+            TPM_ALG_RSAPSS => {
+                verifier.set_rsa_padding(Padding::PKCS1_PSS)?;
+                // TPMs use either the digest or the maximum salt length, and
+                // OpenSSL detects the salt length when verifying with -2.
+                verifier.set_rsa_pss_saltlen(RsaPssSaltlen::MAXIMUM_LENGTH)?;
+                reader.tpm2b()?.to_vec()
+            }
+            TPM_ALG_ECDSA => {
+                let r = BigNum::from_slice(reader.tpm2b()?)?;
+                let s = BigNum::from_slice(reader.tpm2b()?)?;
+                EcdsaSig::from_private_components(r, s)?.to_der()?
+            }
+            alg => bail!("unsupported TPM signature algorithm {alg:#06x}"),
+        };
+
+        if !verifier
+            .verify_oneshot(&signature, &self.attest)
+            .context("verify TPM quote signature")?
+        {
+            bail!("TPM quote is not signed by the attestation key");
+        }
+
+        Ok(digest)
+    }
+}
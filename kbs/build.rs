@@ -11,5 +11,8 @@ fn main() -> Result<(), String> {
     #[cfg(feature = "tonic-build")]
     tonic_build::compile_protos("../protos/attestation.proto").map_err(|e| format!("{e}"))?;
 
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("../protos/kbs.proto").map_err(|e| format!("{e}"))?;
+
     Ok(())
 }
@@ -0,0 +1,10 @@
+-- Resources of the KBS `Postgres` repository backend.
+CREATE TABLE IF NOT EXISTS resources (
+    repository_name TEXT NOT NULL,
+    resource_type TEXT NOT NULL,
+    resource_tag TEXT NOT NULL,
+    data BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (repository_name, resource_type, resource_tag)
+);
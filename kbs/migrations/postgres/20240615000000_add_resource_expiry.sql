@@ -0,0 +1,4 @@
+-- Time after which a resource is refused and garbage-collected, NULL if it never expires.
+ALTER TABLE resources ADD COLUMN expires_at TIMESTAMPTZ;
+
+CREATE INDEX resources_expires_at ON resources (expires_at) WHERE expires_at IS NOT NULL;
@@ -0,0 +1,9 @@
+-- Sessions of the RCAR handshake of the KBS `Postgres` session store.
+CREATE TABLE IF NOT EXISTS sessions (
+    id TEXT PRIMARY KEY,
+    attested BOOLEAN NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    data TEXT NOT NULL
+);
+
+CREATE INDEX sessions_expires_at ON sessions (expires_at);
@@ -6,9 +6,7 @@ use crate::attestation::Attest;
 use anyhow::*;
 use async_trait::async_trait;
 use attestation_service::{config::Config as AsConfig, AttestationService, Data, HashAlgorithm};
-use base64::{engine::general_purpose::STANDARD, Engine};
 use kbs_types::{Attestation, Challenge, Tee};
-use rand::{thread_rng, Rng};
 use serde_json::json;
 use tokio::sync::RwLock;
 
@@ -42,12 +40,18 @@ impl Attest for BuiltInCoCoAs {
                 HashAlgorithm::Sha384,
                 None,
                 HashAlgorithm::Sha384,
-                vec!["default".into()],
+                // The policies of the TEE configured in the AS.
+                vec![],
             )
             .await
     }
 
-    async fn generate_challenge(&self, tee: Tee, tee_parameters: String) -> Result<Challenge> {
+    async fn generate_challenge(
+        &self,
+        tee: Tee,
+        tee_parameters: String,
+        nonce: String,
+    ) -> Result<Challenge> {
         let nonce = match tee {
             Tee::Se => {
                 self.inner
@@ -56,15 +60,7 @@ impl Attest for BuiltInCoCoAs {
                     .generate_supplemental_challenge(tee, tee_parameters)
                     .await?
             }
-            _ => {
-                let mut nonce: Vec<u8> = vec![0; 32];
-
-                thread_rng()
-                    .try_fill(&mut nonce[..])
-                    .map_err(anyhow::Error::from)?;
-
-                STANDARD.encode(&nonce)
-            }
+            _ => nonce,
         };
 
         let challenge = Challenge {
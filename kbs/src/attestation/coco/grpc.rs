@@ -3,16 +3,13 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::attestation::Attest;
+use crate::telemetry::inject_context;
 use anyhow::*;
 use async_trait::async_trait;
-use base64::{
-    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
-    Engine,
-};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use kbs_types::{Attestation, Challenge, Tee};
 use log::info;
 use mobc::{Manager, Pool};
-use rand::{thread_rng, Rng};
 use serde::Deserialize;
 use serde_json::json;
 use std::collections::HashMap;
@@ -75,10 +72,11 @@ impl GrpcClientPool {
 #[async_trait]
 impl Attest for GrpcClientPool {
     async fn set_policy(&self, policy_id: &str, policy: &str) -> Result<()> {
-        let req = tonic::Request::new(SetPolicyRequest {
+        let mut req = tonic::Request::new(SetPolicyRequest {
             policy_id: policy_id.to_string(),
             policy: policy.to_string(),
         });
+        inject_context(&mut req);
 
         let mut client = { self.pool.lock().await.get().await? };
 
@@ -103,15 +101,17 @@ impl Attest for GrpcClientPool {
             .trim_end_matches('"')
             .trim_start_matches('"')
             .to_string();
-        let req = tonic::Request::new(AttestationRequest {
+        let mut req = tonic::Request::new(AttestationRequest {
             tee,
             evidence: URL_SAFE_NO_PAD.encode(attestation.tee_evidence),
             runtime_data_hash_algorithm: COCO_AS_HASH_ALGORITHM.into(),
             init_data_hash_algorithm: COCO_AS_HASH_ALGORITHM.into(),
             runtime_data: Some(RuntimeData::StructuredRuntimeData(runtime_data_plaintext)),
             init_data: None,
-            policy_ids: vec!["default".to_string()],
+            // The policies of the TEE configured in the AS.
+            policy_ids: vec![],
         });
+        inject_context(&mut req);
 
         let mut client = { self.pool.lock().await.get().await? };
 
@@ -124,13 +124,19 @@ impl Attest for GrpcClientPool {
         Ok(token)
     }
 
-    async fn generate_challenge(&self, tee: Tee, tee_parameters: String) -> Result<Challenge> {
+    async fn generate_challenge(
+        &self,
+        tee: Tee,
+        tee_parameters: String,
+        nonce: String,
+    ) -> Result<Challenge> {
         let nonce = match tee {
             Tee::Se => {
                 let mut inner = HashMap::new();
                 inner.insert(String::from("tee"), String::from("se"));
                 inner.insert(String::from("tee_params"), tee_parameters);
-                let req = tonic::Request::new(ChallengeRequest { inner });
+                let mut req = tonic::Request::new(ChallengeRequest { inner });
+                inject_context(&mut req);
 
                 let mut client = { self.pool.lock().await.get().await? };
 
@@ -140,15 +146,7 @@ impl Attest for GrpcClientPool {
                     .into_inner()
                     .attestation_challenge
             }
-            _ => {
-                let mut nonce: Vec<u8> = vec![0; 32];
-
-                thread_rng()
-                    .try_fill(&mut nonce[..])
-                    .map_err(anyhow::Error::from)?;
-
-                STANDARD.encode(&nonce)
-            }
+            _ => nonce,
         };
 
         let challenge = Challenge {
@@ -6,13 +6,14 @@ use anyhow::*;
 use async_trait::async_trait;
 #[cfg(any(feature = "coco-as-builtin", feature = "coco-as-builtin-no-verifier"))]
 use attestation_service::config::Config as AsConfig;
-use base64::{engine::general_purpose::STANDARD, Engine};
 #[cfg(feature = "coco-as-grpc")]
 use coco::grpc::*;
 #[cfg(feature = "intel-trust-authority-as")]
 use intel_trust_authority::*;
 use kbs_types::{Challenge, Tee};
-use rand::{thread_rng, Rng};
+use opentelemetry::KeyValue;
+
+use crate::telemetry::traced;
 
 #[cfg(not(feature = "intel-trust-authority-as"))]
 pub const AS_TOKEN_TEE_PUBKEY_PATH: &str = "/customized_claims/runtime_data/tee-pubkey";
@@ -40,15 +41,14 @@ pub trait Attest: Send + Sync {
     /// Return Attestation Results Token
     async fn verify(&self, tee: Tee, nonce: &str, attestation: &str) -> Result<String>;
 
-    /// generate the Challenge to pass to attester based on Tee and nonce
-    async fn generate_challenge(&self, _tee: Tee, _tee_parameters: String) -> Result<Challenge> {
-        let mut nonce: Vec<u8> = vec![0; 32];
-
-        thread_rng()
-            .try_fill(&mut nonce[..])
-            .map_err(anyhow::Error::from)?;
-
-        let nonce = STANDARD.encode(&nonce);
+    /// generate the Challenge to pass to attester based on Tee and the nonce
+    /// issued by the KBS
+    async fn generate_challenge(
+        &self,
+        _tee: Tee,
+        _tee_parameters: String,
+        nonce: String,
+    ) -> Result<Challenge> {
         Ok(Challenge {
             nonce,
             extra_params: String::new(),
@@ -91,41 +91,84 @@ impl AttestationService {
     }
 
     pub async fn verify(&self, tee: Tee, nonce: &str, attestation: &str) -> Result<String> {
-        match self {
-            #[cfg(feature = "coco-as-grpc")]
-            AttestationService::CoCoASgRPC(inner) => inner.verify(tee, nonce, attestation).await,
-            #[cfg(any(feature = "coco-as-builtin", feature = "coco-as-builtin-no-verifier"))]
-            AttestationService::CoCoASBuiltIn(inner) => inner.verify(tee, nonce, attestation).await,
-            #[cfg(feature = "intel-trust-authority-as")]
-            AttestationService::IntelTA(inner) => inner.verify(tee, nonce, attestation).await,
-        }
+        let verify = async {
+            match self {
+                #[cfg(feature = "coco-as-grpc")]
+                AttestationService::CoCoASgRPC(inner) => {
+                    inner.verify(tee, nonce, attestation).await
+                }
+                #[cfg(any(feature = "coco-as-builtin", feature = "coco-as-builtin-no-verifier"))]
+                AttestationService::CoCoASBuiltIn(inner) => {
+                    inner.verify(tee, nonce, attestation).await
+                }
+                #[cfg(feature = "intel-trust-authority-as")]
+                AttestationService::IntelTA(inner) => inner.verify(tee, nonce, attestation).await,
+            }
+        };
+        traced(
+            "attestation_service.verify",
+            vec![KeyValue::new("kbs.tee", tee_name(tee))],
+            verify,
+        )
+        .await
     }
 
     pub async fn set_policy(&self, policy_id: &str, policy: &str) -> Result<()> {
-        match self {
-            #[cfg(feature = "coco-as-grpc")]
-            AttestationService::CoCoASgRPC(inner) => inner.set_policy(policy_id, policy).await,
-            #[cfg(any(feature = "coco-as-builtin", feature = "coco-as-builtin-no-verifier"))]
-            AttestationService::CoCoASBuiltIn(inner) => inner.set_policy(policy_id, policy).await,
-            #[cfg(feature = "intel-trust-authority-as")]
-            AttestationService::IntelTA(inner) => inner.set_policy(policy_id, policy).await,
-        }
+        let set_policy = async {
+            match self {
+                #[cfg(feature = "coco-as-grpc")]
+                AttestationService::CoCoASgRPC(inner) => inner.set_policy(policy_id, policy).await,
+                #[cfg(any(feature = "coco-as-builtin", feature = "coco-as-builtin-no-verifier"))]
+                AttestationService::CoCoASBuiltIn(inner) => {
+                    inner.set_policy(policy_id, policy).await
+                }
+                #[cfg(feature = "intel-trust-authority-as")]
+                AttestationService::IntelTA(inner) => inner.set_policy(policy_id, policy).await,
+            }
+        };
+        traced(
+            "attestation_service.set_policy",
+            vec![KeyValue::new("kbs.policy_id", policy_id.to_string())],
+            set_policy,
+        )
+        .await
     }
 
-    pub async fn generate_challenge(&self, tee: Tee, tee_parameters: String) -> Result<Challenge> {
-        match self {
-            #[cfg(feature = "coco-as-grpc")]
-            AttestationService::CoCoASgRPC(inner) => {
-                inner.generate_challenge(tee, tee_parameters).await
+    pub async fn generate_challenge(
+        &self,
+        tee: Tee,
+        tee_parameters: String,
+        nonce: String,
+    ) -> Result<Challenge> {
+        let generate_challenge = async {
+            match self {
+                #[cfg(feature = "coco-as-grpc")]
+                AttestationService::CoCoASgRPC(inner) => {
+                    inner.generate_challenge(tee, tee_parameters, nonce).await
+                }
+                #[cfg(any(feature = "coco-as-builtin", feature = "coco-as-builtin-no-verifier"))]
+                AttestationService::CoCoASBuiltIn(inner) => {
+                    inner.generate_challenge(tee, tee_parameters, nonce).await
+                }
+                #[cfg(feature = "intel-trust-authority-as")]
+                AttestationService::IntelTA(inner) => {
+                    inner.generate_challenge(tee, tee_parameters, nonce).await
+                }
             }
-            #[cfg(any(feature = "coco-as-builtin", feature = "coco-as-builtin-no-verifier"))]
-            AttestationService::CoCoASBuiltIn(inner) => {
-                inner.generate_challenge(tee, tee_parameters).await
-            }
-            #[cfg(feature = "intel-trust-authority-as")]
-            AttestationService::IntelTA(inner) => {
-                inner.generate_challenge(tee, tee_parameters).await
-            }
-        }
+        };
+        traced(
+            "attestation_service.generate_challenge",
+            vec![KeyValue::new("kbs.tee", tee_name(tee))],
+            generate_challenge,
+        )
+        .await
     }
 }
+
+/// The name of `tee` in the RCAR messages, e.g. `sample`.
+pub(crate) fn tee_name(tee: Tee) -> String {
+    serde_json::to_value(tee)
+        .ok()
+        .and_then(|tee| tee.as_str().map(String::from))
+        .unwrap_or_default()
+}
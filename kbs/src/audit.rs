@@ -0,0 +1,605 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Security audit log of the KBS. The requests of the admin API, the policy
+//! changes, the attestations and the requests of the secret resources are
+//! written as JSON records telling who did what, when, and the decision of
+//! the KBS, to a file or to syslog.
+//!
+//! With `hash_chain`, every record carries the SHA-256 digest of the previous
+//! one and its own, as the last `hash` field, so that a record which is
+//! removed or changed breaks the chain, see [`verify_hash_chain`].
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::{web, HttpMessage, HttpRequest};
+use anyhow::{anyhow, bail, Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixDatagram;
+use tokio::sync::Mutex;
+
+#[cfg(any(feature = "as", feature = "policy"))]
+use crate::policy_history::PolicyKind;
+#[cfg(feature = "resource")]
+use crate::resource::ResourceDesc;
+use crate::{http::Error, KBS_MAJOR_VERSION, KBS_PREFIX};
+
+const DEFAULT_SYSLOG_SOCKET: &str = "/dev/log";
+
+/// `previous_hash` of the first record of a chain.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// `authpriv` facility of the syslog messages.
+const SYSLOG_FACILITY: u8 = 10;
+
+/// Security audit log configuration.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AuditConfig {
+    /// Where the records are written.
+    pub destination: AuditDestination,
+
+    /// Chain the records by their SHA-256 digests.
+    #[serde(default)]
+    pub hash_chain: bool,
+}
+
+/// Destination of the audit records.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum AuditDestination {
+    /// A file, appended with a JSON record per line.
+    File { path: PathBuf },
+
+    /// The syslog daemon listening on the Unix socket `socket`, sent a RFC
+    /// 5424 message of the `authpriv` facility per record.
+    Syslog {
+        #[serde(default = "default_syslog_socket")]
+        socket: PathBuf,
+    },
+}
+
+fn default_syslog_socket() -> PathBuf {
+    PathBuf::from(DEFAULT_SYSLOG_SOCKET)
+}
+
+/// Decision of the KBS on an audited operation.
+#[derive(Clone, Copy, Debug, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    /// The operation is done.
+    Allowed,
+
+    /// The operation is refused, e.g. the admin is not authenticated, the
+    /// evidence is rejected or the resource policy denies the resource.
+    Denied,
+
+    /// The operation is allowed, but fails.
+    Failed,
+}
+
+/// An audited operation.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// A request of the admin API, on the `route` pattern of its `path`.
+    AdminOperation {
+        method: String,
+        route: String,
+        path: String,
+    },
+
+    /// A new version of a policy, e.g. `resource`, `repository/<policy id>`
+    /// or `attestation/<policy id>`.
+    PolicyChange { policy: String, version: u64 },
+
+    /// An attestation of the RCAR handshake.
+    Attestation { tee: String },
+
+    /// A request of a secret resource, at `version` or its latest one.
+    SecretRelease {
+        resource: String,
+        version: Option<u64>,
+    },
+}
+
+/// A record of the audit log.
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditRecord {
+    #[serde(with = "time::serde::rfc3339")]
+    pub timestamp: OffsetDateTime,
+
+    /// Who does the operation: the IP address of the client, or the
+    /// component of the KBS, e.g. `policy-watch`.
+    pub actor: String,
+
+    #[serde(flatten)]
+    pub event: AuditEvent,
+
+    pub decision: Decision,
+
+    /// Why the operation is denied or fails.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+
+    /// Hex SHA-256 digest of the previous record, with `hash_chain`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_hash: Option<String>,
+}
+
+enum Sink {
+    File(tokio::fs::File),
+    Syslog(UnixDatagram),
+}
+
+struct AuditWriter {
+    sink: Sink,
+    hash_chain: bool,
+
+    /// Digest of the last written record.
+    previous_hash: String,
+}
+
+impl AuditWriter {
+    async fn new(config: &AuditConfig) -> Result<Self> {
+        let mut previous_hash = GENESIS_HASH.to_string();
+        let sink = match &config.destination {
+            AuditDestination::File { path } => {
+                if config.hash_chain {
+                    if let Some(hash) = last_hash(path).await? {
+                        previous_hash = hash;
+                    }
+                }
+
+                let file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .context("open audit log")?;
+                Sink::File(file)
+            }
+            AuditDestination::Syslog { socket } => {
+                let datagram = UnixDatagram::unbound().context("create syslog socket")?;
+                datagram
+                    .connect(socket)
+                    .with_context(|| format!("connect to syslog at {}", socket.display()))?;
+                Sink::Syslog(datagram)
+            }
+        };
+
+        Ok(Self {
+            sink,
+            hash_chain: config.hash_chain,
+            previous_hash,
+        })
+    }
+
+    async fn write(&mut self, mut record: AuditRecord) -> Result<()> {
+        let (mut line, hash) = match self.hash_chain {
+            true => {
+                record.previous_hash = Some(self.previous_hash.clone());
+                let line = serde_json::to_string(&record)?;
+                let hash = hex::encode(Sha256::digest(&line));
+                let line = format!("{},\"hash\":\"{hash}\"}}", &line[..line.len() - 1]);
+                (line, Some(hash))
+            }
+            false => (serde_json::to_string(&record)?, None),
+        };
+
+        match &mut self.sink {
+            Sink::File(file) => {
+                line.push('\n');
+                file.write_all(line.as_bytes()).await?;
+                file.flush().await?;
+            }
+            Sink::Syslog(datagram) => {
+                // Notice for the allowed operations, warning for the others.
+                let severity = match record.decision {
+                    Decision::Allowed => 5,
+                    _ => 4,
+                };
+                let timestamp = record
+                    .timestamp
+                    .format(&time::format_description::well_known::Rfc3339)?;
+                let message = format!(
+                    "<{}>1 {timestamp} - kbs {} audit - {line}",
+                    SYSLOG_FACILITY * 8 + severity,
+                    std::process::id()
+                );
+                datagram.send(message.as_bytes()).await?;
+            }
+        }
+
+        // The chain goes on from the last record which is written.
+        if let Some(hash) = hash {
+            self.previous_hash = hash;
+        }
+        Ok(())
+    }
+}
+
+/// The `hash` of the last record of the audit log file at `path`, if any.
+async fn last_hash(path: &Path) -> Result<Option<String>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let log = tokio::fs::read_to_string(path)
+        .await
+        .context("read audit log")?;
+    let Some(line) = log.lines().last() else {
+        return Ok(None);
+    };
+    let (_, hash) = split_hash(line)
+        .ok_or_else(|| anyhow!("the last record of the audit log is not hash chained"))?;
+    Ok(Some(hash.to_string()))
+}
+
+/// Split a hash chained record into the record without its `hash`, and its
+/// `hash`.
+fn split_hash(line: &str) -> Option<(String, &str)> {
+    let (record, hash) = line.rsplit_once(",\"hash\":\"")?;
+    let hash = hash.strip_suffix("\"}")?;
+    Some((format!("{record}}}"), hash))
+}
+
+/// Verify the hash chain of the records of an audit log file, from its first
+/// record, and return the number of records.
+pub fn verify_hash_chain(log: &str) -> Result<usize> {
+    let mut previous_hash = GENESIS_HASH.to_string();
+    let mut records = 0;
+    for (line, number) in log.lines().zip(1..) {
+        let (record, hash) =
+            split_hash(line).ok_or_else(|| anyhow!("record {number} is not hash chained"))?;
+        let value: serde_json::Value =
+            serde_json::from_str(&record).with_context(|| format!("parse record {number}"))?;
+        if value["previous_hash"] != previous_hash.as_str() {
+            bail!("record {number} does not follow the previous record");
+        }
+        if hex::encode(Sha256::digest(&record)) != hash {
+            bail!("record {number} is altered");
+        }
+
+        previous_hash = hash.to_string();
+        records = number;
+    }
+
+    Ok(records)
+}
+
+/// The audit log, which does nothing if it is not configured.
+pub struct AuditLog {
+    writer: Option<Mutex<AuditWriter>>,
+}
+
+impl AuditLog {
+    pub async fn new(config: Option<&AuditConfig>) -> Result<Self> {
+        let writer = match config {
+            Some(config) => Some(Mutex::new(AuditWriter::new(config).await?)),
+            None => None,
+        };
+        Ok(Self { writer })
+    }
+
+    /// Write the record of the `event` done by `actor`. A record failing to
+    /// be written is only logged, so that it never fails the operation.
+    pub async fn record(
+        &self,
+        actor: &str,
+        event: AuditEvent,
+        decision: Decision,
+        reason: Option<String>,
+    ) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+
+        let record = AuditRecord {
+            timestamp: OffsetDateTime::now_utc(),
+            actor: actor.to_string(),
+            event,
+            decision,
+            reason,
+            previous_hash: None,
+        };
+        if let Err(e) = writer.lock().await.write(record).await {
+            warn!("Failed to write the audit record: {e:#}");
+        }
+    }
+
+    /// Record the new `version` of the policy `kind`.
+    #[cfg(any(feature = "as", feature = "policy"))]
+    pub(crate) async fn record_policy_change(&self, actor: &str, kind: &PolicyKind, version: u64) {
+        let policy = match kind {
+            PolicyKind::Resource => String::from("resource"),
+            PolicyKind::Repository(policy_id) => format!("repository/{policy_id}"),
+            PolicyKind::Attestation(policy_id) => format!("attestation/{policy_id}"),
+        };
+        self.record(
+            actor,
+            AuditEvent::PolicyChange { policy, version },
+            Decision::Allowed,
+            None,
+        )
+        .await;
+    }
+
+    /// Record the attestation of the `tee` evidence, whose `result` is the
+    /// attestation token.
+    #[cfg(feature = "as")]
+    pub(crate) async fn record_attestation<T>(
+        &self,
+        actor: &str,
+        tee: kbs_types::Tee,
+        result: &anyhow::Result<T>,
+    ) {
+        let (decision, reason) = match result {
+            Ok(_) => (Decision::Allowed, None),
+            Err(e) => (Decision::Denied, Some(format!("{e:#}"))),
+        };
+        let tee = crate::attestation::tee_name(tee);
+        self.record(actor, AuditEvent::Attestation { tee }, decision, reason)
+            .await;
+    }
+
+    /// Record the request of the resource `resource` at `version`, whose
+    /// `result` is the released resource.
+    #[cfg(feature = "resource")]
+    pub(crate) async fn record_release<T>(
+        &self,
+        actor: &str,
+        resource: &ResourceDesc,
+        version: Option<u64>,
+        result: &std::result::Result<T, Error>,
+    ) {
+        let (decision, reason) = match result {
+            Ok(_) => (Decision::Allowed, None),
+            Err(e) => (error_decision(e), Some(e.to_string())),
+        };
+        let resource = format!(
+            "kbs:///{}/{}/{}",
+            resource.repository_name, resource.resource_type, resource.resource_tag
+        );
+        self.record(
+            actor,
+            AuditEvent::SecretRelease { resource, version },
+            decision,
+            reason,
+        )
+        .await;
+    }
+}
+
+/// Whether the error refuses the operation, or the operation fails.
+fn error_decision(e: &Error) -> Decision {
+    match e {
+        Error::FailedAuthentication(_)
+        | Error::UserPublicKeyNotProvided
+        | Error::PolicyReject(_)
+        | Error::AttestationFailed(_) => Decision::Denied,
+        _ => Decision::Failed,
+    }
+}
+
+/// The identity of the admin of an HTTP request, from its verified admin
+/// token, kept in the extensions of the request.
+#[derive(Clone, Debug)]
+pub(crate) struct AdminIdentity(pub String);
+
+/// The actor of an HTTP request: the IP address of the client, followed by
+/// the identity of the admin if the request is authorized by an admin token.
+pub(crate) fn http_actor(request: &HttpRequest) -> String {
+    let address = request
+        .peer_addr()
+        .map_or_else(|| String::from("unknown"), |addr| addr.ip().to_string());
+    match request.extensions().get::<AdminIdentity>() {
+        Some(AdminIdentity(identity)) => format!("{address} ({identity})"),
+        None => address,
+    }
+}
+
+/// Whether the request of `method` on the `route` pattern is one of the
+/// admin API.
+fn is_admin_request(method: &Method, route: &str) -> bool {
+    let prefix = format!("{KBS_PREFIX}/v{KBS_MAJOR_VERSION}/");
+    let Some(route) = route.strip_prefix(&prefix) else {
+        return false;
+    };
+
+    route.starts_with("admin/")
+        || route == "attestation-policy"
+        || route == "resource-policy"
+        || (route.starts_with("resource/")
+            && (method == Method::POST || method == Method::DELETE || method == Method::HEAD))
+}
+
+/// Middleware recording the requests of the admin API in the audit log.
+pub(crate) fn audit_admin_request<S, B>(
+    request: ServiceRequest,
+    service: &S,
+) -> impl Future<Output = std::result::Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let audited = request.match_pattern().and_then(|route| {
+        if !is_admin_request(request.method(), &route) {
+            return None;
+        }
+        let audit_log = request.app_data::<web::Data<AuditLog>>()?.clone();
+        let actor = http_actor(request.request());
+        let event = AuditEvent::AdminOperation {
+            method: request.method().to_string(),
+            route,
+            path: request.path().to_string(),
+        };
+        Some((audit_log, actor, event))
+    });
+
+    let response = service.call(request);
+    async move {
+        let response = response.await;
+        if let Some((audit_log, mut actor, event)) = audited {
+            // The admin is identified by the handler of the request.
+            if let Ok(response) = &response {
+                actor = http_actor(response.request());
+            }
+            let (decision, reason) = match &response {
+                Ok(response) => match response.response().error() {
+                    Some(e) => (
+                        e.as_error::<Error>()
+                            .map_or(Decision::Failed, error_decision),
+                        Some(e.to_string()),
+                    ),
+                    None if response.status().is_client_error()
+                        || response.status().is_server_error() =>
+                    {
+                        (Decision::Failed, Some(response.status().to_string()))
+                    }
+                    None => (Decision::Allowed, None),
+                },
+                Err(e) => (Decision::Failed, Some(e.to_string())),
+            };
+            audit_log.record(&actor, event, decision, reason).await;
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App, HttpResponse};
+    use tempfile::TempDir;
+
+    fn file_config(path: &Path) -> AuditConfig {
+        AuditConfig {
+            destination: AuditDestination::File {
+                path: path.to_path_buf(),
+            },
+            hash_chain: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hash_chain() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("audit.jsonl");
+
+        let audit_log = AuditLog::new(Some(&file_config(&path))).await.unwrap();
+        let event = AuditEvent::PolicyChange {
+            policy: "resource".into(),
+            version: 1,
+        };
+        audit_log
+            .record("127.0.0.1", event.clone(), Decision::Allowed, None)
+            .await;
+        audit_log
+            .record(
+                "127.0.0.1",
+                event.clone(),
+                Decision::Denied,
+                Some("test".into()),
+            )
+            .await;
+
+        // The chain goes on after a restart.
+        let audit_log = AuditLog::new(Some(&file_config(&path))).await.unwrap();
+        audit_log
+            .record("policy-watch", event, Decision::Allowed, None)
+            .await;
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(verify_hash_chain(&log).unwrap(), 3);
+        let records: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records[0]["event"], "policy_change");
+        assert_eq!(records[0]["previous_hash"], GENESIS_HASH);
+        assert_eq!(records[1]["decision"], "denied");
+        assert_eq!(records[1]["reason"], "test");
+        assert_eq!(records[2]["previous_hash"], records[1]["hash"]);
+        assert_eq!(records[2]["actor"], "policy-watch");
+
+        let altered = log.replacen("denied", "allowed", 1);
+        assert!(verify_hash_chain(&altered).is_err());
+        let removed: Vec<&str> = log
+            .lines()
+            .enumerate()
+            .filter(|(i, _)| *i != 1)
+            .map(|(_, l)| l)
+            .collect();
+        assert!(verify_hash_chain(&removed.join("\n")).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_audit_admin_request() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let audit_log = web::Data::new(AuditLog::new(Some(&file_config(&path))).await.unwrap());
+
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(audit_admin_request)
+                .app_data(audit_log)
+                .route(
+                    "/kbs/v0/resource-policy",
+                    web::post().to(|| async {
+                        Err::<HttpResponse, _>(Error::FailedAuthentication("test".into()))
+                    }),
+                )
+                .route(
+                    "/kbs/v0/resource/{repository}/{type}/{tag}",
+                    web::get().to(|| async { HttpResponse::Ok().finish() }),
+                )
+                .route(
+                    "/kbs/v0/resource/{repository}/{type}/{tag}",
+                    web::delete().to(|request: HttpRequest| async move {
+                        request.extensions_mut().insert(AdminIdentity("ops".into()));
+                        HttpResponse::Ok().finish()
+                    }),
+                )
+                .route(
+                    "/kbs/v0/resource/{repository}/{type}/{tag}",
+                    web::head().to(|| async { HttpResponse::Ok().finish() }),
+                ),
+        )
+        .await;
+
+        for request in [
+            test::TestRequest::post().uri("/kbs/v0/resource-policy"),
+            test::TestRequest::get().uri("/kbs/v0/resource/default/key/1"),
+            test::TestRequest::delete()
+                .uri("/kbs/v0/resource/default/key/1")
+                .peer_addr("192.0.2.1:8080".parse().unwrap()),
+            test::TestRequest::default()
+                .method(Method::HEAD)
+                .uri("/kbs/v0/resource/default/key/1"),
+        ] {
+            test::call_service(&app, request.to_request()).await;
+        }
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let records: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        // The resource is not requested through the admin API.
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["event"], "admin_operation");
+        assert_eq!(records[0]["route"], "/kbs/v0/resource-policy");
+        assert_eq!(records[0]["decision"], "denied");
+        assert_eq!(records[1]["method"], "DELETE");
+        assert_eq!(records[1]["path"], "/kbs/v0/resource/default/key/1");
+        assert_eq!(records[1]["decision"], "allowed");
+        assert_eq!(records[1]["actor"], "192.0.2.1 (ops)");
+        assert_eq!(records[2]["method"], "HEAD");
+    }
+}
@@ -7,19 +7,26 @@ use actix_web::HttpRequest;
 use actix_web_httpauth::headers::authorization::{Authorization, Bearer};
 use anyhow::{Context, Result};
 use jwt_simple::prelude::{
-    Ed25519PublicKey, EdDSAPublicKeyLike, NoCustomClaims, VerificationOptions,
+    Ed25519PublicKey, EdDSAPublicKeyLike, NoCustomClaims, Token, VerificationOptions,
 };
 
-pub(crate) fn validate_auth(request: &HttpRequest, public_key: &Ed25519PublicKey) -> Result<()> {
+/// Verify the admin token of the `request` with `public_key`, and return the
+/// identity of the admin, the `sub` claim of the token or its `kid`, if
+/// either is set.
+pub(crate) fn validate_auth(
+    request: &HttpRequest,
+    public_key: &Ed25519PublicKey,
+) -> Result<Option<String>> {
     let bearer = Authorization::<Bearer>::parse(request)
         .context("parse Authorization header failed")?
         .into_scheme();
 
     let token = bearer.token();
 
-    let _claims = public_key
+    let claims = public_key
         .verify_token::<NoCustomClaims>(token, Some(VerificationOptions::default()))
         .context("token verification failed")?;
+    let key_id = Token::decode_metadata(token)?.key_id().map(String::from);
 
-    Ok(())
+    Ok(claims.subject.or(key_id))
 }
@@ -0,0 +1,86 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Run test cases of the resource policies with the policy engine of the KBS
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use kbs::config::KbsConfig;
+use kbs::policy_engine::{run_policy_tests, PolicyEngineType};
+use std::path::{Path, PathBuf};
+
+/// Run a directory of test cases, JSON files of the claims of an attestation
+/// token, a resource path and the expected decision, against the resource
+/// policies of the KBS.
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Directory of the test cases.
+    tests: PathBuf,
+
+    /// KBS config file whose policy engine configuration is used, e.g. for
+    /// its repository policies and data documents.
+    #[arg(short, long, env = "KBS_CONFIG_FILE")]
+    config_file: Option<PathBuf>,
+
+    /// Policy file to test, instead of the policy of the config file.
+    #[arg(short, long)]
+    policy: Option<PathBuf>,
+
+    /// Language of the policy, instead of the one of the config file.
+    #[arg(long, value_enum)]
+    policy_engine_type: Option<PolicyEngineType>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init_from_env(env_logger::Env::new().default_filter_or("warn"));
+
+    let cli = Cli::parse();
+
+    let mut config = match &cli.config_file {
+        Some(config_file) => KbsConfig::try_from(Path::new(config_file))?
+            .policy_engine_config
+            .unwrap_or_default(),
+        None => Default::default(),
+    };
+    if let Some(policy) = cli.policy {
+        config.policy_path = Some(policy);
+    }
+    if let Some(policy_engine_type) = cli.policy_engine_type {
+        config.policy_engine_type = policy_engine_type;
+    }
+
+    // The policy engine creates a default policy where there is none.
+    match &config.policy_path {
+        Some(policy_path) if policy_path.exists() => {}
+        _ => bail!("No policy to test, set one with --policy or the config file"),
+    }
+
+    let results = run_policy_tests(&config, &cli.tests).await?;
+    let mut failed = 0;
+    for result in &results {
+        match &result.actual {
+            _ if result.passed() => println!("ok      {}", result.name),
+            Ok(allowed) => {
+                failed += 1;
+                println!(
+                    "FAILED  {}: expected allowed = {}, got {allowed}",
+                    result.name, result.expected
+                );
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {e}", result.name);
+            }
+        }
+    }
+
+    println!("\n{} passed, {failed} failed", results.len() - failed);
+    if failed > 0 {
+        bail!("{failed} test cases of the resource policy failed");
+    }
+
+    Ok(())
+}
@@ -39,6 +39,11 @@ async fn main() -> Result<()> {
         warn!("insecure APIs are enabled");
     }
 
+    #[cfg(feature = "otel")]
+    if let Some(otel_config) = &kbs_config.otel_config {
+        kbs::telemetry::init(otel_config)?;
+    }
+
     #[cfg(feature = "as")]
     let attestation_service = {
         cfg_if::cfg_if! {
@@ -59,18 +64,45 @@ async fn main() -> Result<()> {
         kbs_config.private_key,
         kbs_config.auth_public_key,
         kbs_config.certificate,
+        kbs_config.client_ca_certificate,
         kbs_config.insecure_http,
         #[cfg(feature = "as")]
         attestation_service,
+        #[cfg(feature = "as")]
+        kbs_config.nonce_config.unwrap_or_default(),
+        #[cfg(feature = "as")]
+        kbs_config.session_store_config.unwrap_or_default(),
+        #[cfg(feature = "as")]
+        kbs_config.session_config.unwrap_or_default(),
         kbs_config.timeout,
         kbs_config.insecure_api,
         #[cfg(feature = "resource")]
         kbs_config.repository_config.unwrap_or_default(),
         #[cfg(feature = "resource")]
+        kbs_config.backup_config,
+        #[cfg(feature = "resource")]
+        kbs_config.replication_config,
+        #[cfg(feature = "resource")]
         kbs_config.attestation_token_config,
-        #[cfg(feature = "opa")]
+        #[cfg(feature = "policy")]
         kbs_config.policy_engine_config.unwrap_or_default(),
+        #[cfg(any(feature = "as", feature = "policy"))]
+        kbs_config.policy_history_config.unwrap_or_default(),
+        #[cfg(any(feature = "as", feature = "policy"))]
+        kbs_config.policy_signing_config,
+        #[cfg(feature = "policy-bundle")]
+        kbs_config.policy_bundle_config,
+        #[cfg(feature = "policy-watch")]
+        kbs_config.policy_watch_config,
+        #[cfg(feature = "grpc")]
+        kbs_config.grpc_server_config,
+        kbs_config.audit_config,
     )?;
 
-    api_server.serve().await.map_err(anyhow::Error::from)
+    let result = api_server.serve().await;
+
+    #[cfg(feature = "otel")]
+    kbs::telemetry::shutdown();
+
+    result
 }
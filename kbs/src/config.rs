@@ -6,10 +6,27 @@
 use crate::attestation::coco::grpc::GrpcConfig;
 #[cfg(feature = "intel-trust-authority-as")]
 use crate::attestation::intel_trust_authority::IntelTrustAuthorityConfig;
+use crate::audit::AuditConfig;
+#[cfg(feature = "grpc")]
+use crate::grpc::GrpcServerConfig;
+#[cfg(feature = "as")]
+use crate::nonce::NonceConfig;
+#[cfg(feature = "policy-bundle")]
+use crate::policy_bundle::PolicyBundleConfig;
 #[cfg(feature = "policy")]
 use crate::policy_engine::PolicyEngineConfig;
+#[cfg(any(feature = "as", feature = "policy"))]
+use crate::policy_history::PolicyHistoryConfig;
+#[cfg(any(feature = "as", feature = "policy"))]
+use crate::policy_signing::PolicySigningConfig;
+#[cfg(feature = "policy-watch")]
+use crate::policy_watch::PolicyWatchConfig;
 #[cfg(feature = "resource")]
-use crate::resource::RepositoryConfig;
+use crate::resource::{BackupConfig, ReplicationConfig, RepositoryConfig};
+#[cfg(feature = "as")]
+use crate::session::{SessionConfig, SessionStoreConfig};
+#[cfg(feature = "otel")]
+use crate::telemetry::OtelConfig;
 #[cfg(feature = "resource")]
 use crate::token::AttestationTokenVerifierConfig;
 use anyhow::anyhow;
@@ -34,6 +51,14 @@ pub struct KbsConfig {
     #[cfg(feature = "resource")]
     pub repository_config: Option<RepositoryConfig>,
 
+    /// Scheduled backups of the resource repository. Disabled if not given.
+    #[cfg(feature = "resource")]
+    pub backup_config: Option<BackupConfig>,
+
+    /// Replication of the resources of a source KBS. Disabled if not given.
+    #[cfg(feature = "resource")]
+    pub replication_config: Option<ReplicationConfig>,
+
     /// Attestation token result broker config.
     #[cfg(feature = "resource")]
     pub attestation_token_config: AttestationTokenVerifierConfig,
@@ -50,6 +75,21 @@ pub struct KbsConfig {
     #[cfg(feature = "intel-trust-authority-as")]
     pub intel_trust_authority_config: IntelTrustAuthorityConfig,
 
+    /// Length, lifetime, single-use enforcement and client binding of the
+    /// nonces of the attestation challenges.
+    #[cfg(feature = "as")]
+    pub nonce_config: Option<NonceConfig>,
+
+    /// TTL, idle timeout and maximum resource requests of the sessions of
+    /// the RCAR handshake. The sessions last the `timeout` if not given.
+    #[cfg(feature = "as")]
+    pub session_config: Option<SessionConfig>,
+
+    /// Where the sessions of the RCAR handshake are kept. In memory if not
+    /// given.
+    #[cfg(feature = "as")]
+    pub session_store_config: Option<SessionStoreConfig>,
+
     /// Socket addresses (IP:port) to listen on, e.g. 127.0.0.1:8080.
     pub sockets: Vec<SocketAddr>,
 
@@ -62,6 +102,10 @@ pub struct KbsConfig {
     /// HTTPS Certificate.
     pub certificate: Option<PathBuf>,
 
+    /// CA certificate used to verify the optional TLS certificates of the
+    /// clients, which the nonces can be bound to.
+    pub client_ca_certificate: Option<PathBuf>,
+
     /// Insecure HTTP.
     /// WARNING: Using this option makes the HTTP connection insecure.
     pub insecure_http: bool,
@@ -79,6 +123,37 @@ pub struct KbsConfig {
     /// specific resources.
     #[cfg(feature = "policy")]
     pub policy_engine_config: Option<PolicyEngineConfig>,
+
+    /// Where the versions of the resource and attestation policies set
+    /// through the admin API are kept.
+    #[cfg(any(feature = "as", feature = "policy"))]
+    pub policy_history_config: Option<PolicyHistoryConfig>,
+
+    /// Policy author key the resource and attestation policies set through
+    /// the admin API must be signed by. The policies are not required to be
+    /// signed if not given.
+    #[cfg(any(feature = "as", feature = "policy"))]
+    pub policy_signing_config: Option<PolicySigningConfig>,
+
+    /// Signed policy bundle pulled from an OCI registry. Disabled if not
+    /// given.
+    #[cfg(feature = "policy-bundle")]
+    pub policy_bundle_config: Option<PolicyBundleConfig>,
+
+    /// Policy files reloaded when they change. Disabled if not given.
+    #[cfg(feature = "policy-watch")]
+    pub policy_watch_config: Option<PolicyWatchConfig>,
+
+    /// gRPC API served alongside the HTTP one. Disabled if not given.
+    #[cfg(feature = "grpc")]
+    pub grpc_server_config: Option<GrpcServerConfig>,
+
+    /// Security audit log. Disabled if not given.
+    pub audit_config: Option<AuditConfig>,
+
+    /// Export of the traces over OTLP. Disabled if not given.
+    #[cfg(feature = "otel")]
+    pub otel_config: Option<OtelConfig>,
 }
 
 impl TryFrom<&Path> for KbsConfig {
@@ -0,0 +1,364 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! gRPC API of the KBS, served alongside the HTTP API for the clients whose
+//! transport is gRPC. It serves the RCAR handshake and the resources, see
+//! `protos/kbs.proto`, with the same sessions, attestation service and
+//! resource policy as the HTTP API.
+
+use anyhow::{Context, Result};
+use log::info;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+#[cfg(feature = "resource")]
+use tokio::sync::RwLock;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+use tonic::{Request, Response, Status};
+
+use crate::audit::AuditLog;
+use crate::http::Error;
+#[cfg(all(feature = "resource", feature = "policy"))]
+use crate::policy_engine::PolicyEngine;
+#[cfg(feature = "resource")]
+use crate::resource::{Repository, ResourceDesc};
+#[cfg(feature = "resource")]
+use crate::token::AttestationTokenVerifier;
+#[cfg(feature = "as")]
+use crate::{
+    attestation::AttestationService,
+    nonce::NonceService,
+    session::{SessionLimits, SessionStore},
+};
+
+use self::api::key_broker_service_server::{KeyBrokerService, KeyBrokerServiceServer};
+use self::api::{
+    AttestRequest, AttestResponse, AuthRequest, AuthResponse, GetResourceRequest,
+    GetResourceResponse,
+};
+
+mod api {
+    tonic::include_proto!("kbs");
+}
+
+/// The gRPC server of the KBS.
+#[derive(Clone, Debug, Deserialize)]
+pub struct GrpcServerConfig {
+    /// Socket address (IP:port) to listen on, e.g. 127.0.0.1:50051.
+    pub socket: SocketAddr,
+}
+
+impl From<Error> for Status {
+    /// The status of the error, whose message is its `ErrorInformation` as
+    /// in the HTTP API.
+    fn from(e: Error) -> Self {
+        let information = e.information();
+        match e {
+            Error::InvalidRequest(_) => Status::invalid_argument(information),
+            Error::PolicyReject(_) => Status::permission_denied(information),
+            Error::ReadSecretFailed(_) => Status::not_found(information),
+            _ => Status::unauthenticated(information),
+        }
+    }
+}
+
+/// The TLS configuration of the certificate and the private key of the KBS,
+/// and of the CA certificate of the optional certificates of the clients.
+pub(crate) fn tls_config(
+    certificate: &Path,
+    private_key: &Path,
+    client_ca_certificate: Option<&Path>,
+) -> Result<ServerTlsConfig> {
+    let certificate = std::fs::read(certificate).context("read certificate")?;
+    let private_key = std::fs::read(private_key).context("read private key")?;
+    let config = ServerTlsConfig::new().identity(Identity::from_pem(certificate, private_key));
+
+    match client_ca_certificate {
+        Some(ca_certificate) => {
+            let ca_certificate =
+                std::fs::read(ca_certificate).context("read client CA certificate")?;
+            Ok(config
+                .client_ca_root(Certificate::from_pem(ca_certificate))
+                .client_auth_optional(true))
+        }
+        None => Ok(config),
+    }
+}
+
+/// The gRPC service of the KBS, which shares its state with the HTTP server.
+pub(crate) struct KbsGrpcService {
+    /// Lifetime and limits of the sessions of the handshake.
+    #[cfg(feature = "as")]
+    pub limits: SessionLimits,
+    #[cfg(feature = "as")]
+    pub sessions: Arc<dyn SessionStore>,
+    #[cfg(feature = "as")]
+    pub attestation_service: Arc<AttestationService>,
+    #[cfg(feature = "as")]
+    pub nonce_service: Arc<NonceService>,
+
+    #[cfg(feature = "resource")]
+    pub repository: Arc<RwLock<dyn Repository + Send + Sync>>,
+    #[cfg(feature = "resource")]
+    pub token_verifier: Arc<RwLock<dyn AttestationTokenVerifier + Send + Sync>>,
+    #[cfg(all(feature = "resource", feature = "policy"))]
+    pub policy_engine: PolicyEngine,
+
+    pub audit_log: Arc<AuditLog>,
+}
+
+impl KbsGrpcService {
+    /// Serve the gRPC API, over TLS if `tls` is given.
+    pub async fn serve(
+        self,
+        config: &GrpcServerConfig,
+        tls: Option<ServerTlsConfig>,
+    ) -> Result<()> {
+        info!(
+            "Starting gRPC{} server at {}",
+            if tls.is_some() { " TLS" } else { "" },
+            config.socket
+        );
+
+        let mut server = Server::builder();
+        if let Some(tls) = tls {
+            server = server.tls_config(tls)?;
+        }
+        server
+            .add_service(KeyBrokerServiceServer::new(self))
+            .serve(config.socket)
+            .await
+            .context("serve gRPC API")
+    }
+}
+
+/// The fingerprint of the TLS certificate of the client of `request`, which
+/// the nonces can be bound to, as for the HTTP API.
+#[cfg(feature = "as")]
+fn client_identity<T>(request: &Request<T>) -> Option<String> {
+    use sha2::{Digest, Sha256};
+
+    let certificates = request.peer_certs()?;
+    let certificate = certificates.first()?;
+    Some(hex::encode(Sha256::digest(certificate.get_ref())))
+}
+
+/// The actor of `request` in the audit log: the IP address of the client.
+fn actor<T>(request: &Request<T>) -> String {
+    request
+        .remote_addr()
+        .map_or_else(|| String::from("unknown"), |addr| addr.ip().to_string())
+}
+
+/// The resource of the path `<repository>/<type>/<tag>`, or `<type>/<tag>` of
+/// the `default` repository.
+#[cfg(feature = "resource")]
+fn resource_desc(resource_path: &str) -> Result<ResourceDesc, Error> {
+    let segments: Vec<&str> = resource_path.split('/').collect();
+    let (repository, r#type, tag) = match segments[..] {
+        [repository, r#type, tag] => (repository, r#type, tag),
+        [r#type, tag] => ("default", r#type, tag),
+        _ => {
+            return Err(Error::InvalidRequest(format!(
+                "illegal resource path {resource_path}"
+            )))
+        }
+    };
+    if [repository, r#type, tag]
+        .iter()
+        .any(|segment| segment.is_empty())
+    {
+        return Err(Error::InvalidRequest(format!(
+            "illegal resource path {resource_path}"
+        )));
+    }
+
+    Ok(ResourceDesc {
+        repository_name: repository.to_string(),
+        resource_type: r#type.to_string(),
+        resource_tag: tag.to_string(),
+    })
+}
+
+#[tonic::async_trait]
+impl KeyBrokerService for KbsGrpcService {
+    async fn auth(&self, request: Request<AuthRequest>) -> Result<Response<AuthResponse>, Status> {
+        info!("gRPC Auth API called.");
+
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "as")] {
+                let client_identity = client_identity(&request);
+                let kbs_request = serde_json::from_str(&request.into_inner().request)
+                    .map_err(|e| Error::InvalidRequest(format!("illegal request: {e}")))?;
+                let session = crate::http::start_session(
+                    kbs_request,
+                    self.limits.ttl,
+                    &self.attestation_service,
+                    &self.nonce_service,
+                    client_identity.as_deref(),
+                )
+                .await?;
+
+                let response = AuthResponse {
+                    session_id: session.id().to_string(),
+                    challenge: serde_json::to_string(session.challenge())
+                        .map_err(|e| Error::FailedAuthentication(format!("serialize challenge: {e}")))?,
+                };
+                self.sessions
+                    .insert(session)
+                    .await
+                    .map_err(|e| Error::SessionStoreFailed(format!("{e:?}")))?;
+
+                Ok(Response::new(response))
+            } else {
+                let _ = request;
+                Err(Status::unimplemented("the KBS has no attestation service"))
+            }
+        }
+    }
+
+    async fn attest(
+        &self,
+        request: Request<AttestRequest>,
+    ) -> Result<Response<AttestResponse>, Status> {
+        info!("gRPC Attest API called.");
+
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "as")] {
+                let client_identity = client_identity(&request);
+                let actor = actor(&request);
+                let request = request.into_inner();
+                let attestation = serde_json::from_str(&request.attestation)
+                    .map_err(|e| Error::InvalidRequest(format!("illegal attestation: {e}")))?;
+                let token = crate::http::attest_session(
+                    self.sessions.as_ref(),
+                    &request.session_id,
+                    &attestation,
+                    &self.attestation_service,
+                    &self.nonce_service,
+                    client_identity.as_deref(),
+                    &self.limits,
+                    &self.audit_log,
+                    &actor,
+                )
+                .await?;
+
+                Ok(Response::new(AttestResponse { token }))
+            } else {
+                let _ = request;
+                Err(Status::unimplemented("the KBS has no attestation service"))
+            }
+        }
+    }
+
+    async fn get_resource(
+        &self,
+        request: Request<GetResourceRequest>,
+    ) -> Result<Response<GetResourceResponse>, Status> {
+        info!("gRPC GetResource API called.");
+
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "resource")] {
+                #[cfg(feature = "policy")]
+                let (client_ip, geo) = self.policy_engine.request_context_config().client(
+                    |name| {
+                        let value = request.metadata().get(name.to_ascii_lowercase())?;
+                        value.to_str().ok().map(String::from)
+                    },
+                    request.remote_addr().map(|addr| addr.ip()),
+                );
+                let actor = actor(&request);
+                let request = request.into_inner();
+
+                let claims = match (request.session_id.is_empty(), request.token.is_empty()) {
+                    #[cfg(feature = "as")]
+                    (false, _) => crate::http::attested_claims(self.sessions.as_ref(), &self.limits, &request.session_id).await?,
+                    (true, false) => self
+                        .token_verifier
+                        .read()
+                        .await
+                        .verify(request.token)
+                        .await
+                        .map_err(|e| Error::TokenParseFailed(format!("verify token failed: {e}")))?,
+                    _ => {
+                        return Err(Error::InvalidRequest(String::from(
+                            "one of `session_id` or `token` must be given",
+                        ))
+                        .into())
+                    }
+                };
+
+                let resource_description = resource_desc(&request.resource_path)?;
+                let repository_name = resource_description.repository_name.clone();
+                let start = std::time::Instant::now();
+                let response = crate::http::release_resource(
+                    &self.repository,
+                    #[cfg(feature = "policy")]
+                    &self.policy_engine,
+                    claims,
+                    resource_description.clone(),
+                    request.version,
+                    #[cfg(feature = "policy")]
+                    client_ip,
+                    #[cfg(feature = "policy")]
+                    geo,
+                )
+                .await;
+                crate::metrics::observe_resource_request(&repository_name, start, &response);
+                self.audit_log
+                    .record_release(&actor, &resource_description, request.version, &response)
+                    .await;
+
+                Ok(Response::new(GetResourceResponse {
+                    response: serde_json::to_string(&response?)
+                        .map_err(|e| Error::JWEFailed(e.to_string()))?,
+                }))
+            } else {
+                let _ = request;
+                Err(Status::unimplemented("the KBS has no resources"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::PolicyDenial;
+    use tonic::Code;
+
+    #[test]
+    fn test_error_status() {
+        let status = Status::from(Error::PolicyReject(PolicyDenial {
+            correlation_id: "42".into(),
+            message: None,
+            advice: vec!["the TEE is not TDX".into()],
+        }));
+        assert_eq!(status.code(), Code::PermissionDenied);
+        let information: serde_json::Value = serde_json::from_str(status.message()).unwrap();
+        assert_eq!(information["correlation_id"], "42");
+        assert_eq!(information["advice"][0], "the TEE is not TDX");
+
+        let status = Status::from(Error::ReadSecretFailed("test".into()));
+        assert_eq!(status.code(), Code::NotFound);
+        let status = Status::from(Error::ExpiredCookie);
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    #[cfg(feature = "resource")]
+    #[test]
+    fn test_resource_desc() {
+        let resource = resource_desc("my_repo/key/1").unwrap();
+        assert_eq!(resource.repository_name, "my_repo");
+        assert_eq!(resource.resource_type, "key");
+        assert_eq!(resource.resource_tag, "1");
+
+        let resource = resource_desc("key/1").unwrap();
+        assert_eq!(resource.repository_name, "default");
+
+        assert!(resource_desc("my_repo/key/1/2").is_err());
+        assert!(resource_desc("my_repo//1").is_err());
+    }
+}
@@ -2,7 +2,11 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{raise_error, session::SessionStatus};
+use crate::{
+    nonce::{ClientIdentity, NonceService},
+    raise_error,
+    session::SessionStatus,
+};
 
 use super::*;
 
@@ -12,87 +16,167 @@ use base64::Engine;
 use kbs_types::Challenge;
 use log::{debug, error, info};
 use serde_json::json;
+use std::time::Instant;
 
 /// POST /auth
 pub(crate) async fn auth(
     request: web::Json<Request>,
-    map: web::Data<SessionMap>,
-    timeout: web::Data<i64>,
+    http_request: HttpRequest,
+    map: web::Data<dyn SessionStore>,
+    limits: web::Data<SessionLimits>,
     attestation_service: web::Data<Arc<AttestationService>>,
+    nonce_service: web::Data<NonceService>,
 ) -> Result<HttpResponse> {
     info!("Auth API called.");
     debug!("Auth Request: {:?}", &request);
 
-    let challenge = attestation_service
-        .generate_challenge(request.tee, request.extra_params.clone())
-        .await
-        .map_err(|e| Error::FailedAuthentication(format!("generate challenge: {e:?}")))?;
-
-    let session = SessionStatus::auth(request.0, **timeout, challenge)
-        .map_err(|e| Error::FailedAuthentication(format!("Session: {e}")))?;
+    let client_identity = http_request.conn_data::<ClientIdentity>();
+    let session = start_session(
+        request.0,
+        limits.ttl,
+        &attestation_service,
+        &nonce_service,
+        client_identity.map(|id| id.0.as_str()),
+    )
+    .await?;
 
     let response = HttpResponse::Ok()
         .cookie(session.cookie())
         .json(session.challenge());
 
-    map.insert(session);
+    map.insert(session)
+        .await
+        .map_err(|e| Error::SessionStoreFailed(format!("{e:?}")))?;
 
     Ok(response)
 }
 
+/// Start a session of the RCAR handshake of `request`, lasting `ttl`, whose
+/// challenge is bound to the `client_identity`, if known. The session is not
+/// inserted in the session store.
+pub(crate) async fn start_session(
+    request: Request,
+    ttl: std::time::Duration,
+    attestation_service: &AttestationService,
+    nonce_service: &NonceService,
+    client_identity: Option<&str>,
+) -> Result<SessionStatus> {
+    let nonce = nonce_service
+        .generate()
+        .map_err(|e| Error::FailedAuthentication(format!("generate nonce: {e:?}")))?;
+    let challenge = attestation_service
+        .generate_challenge(request.tee, request.extra_params.clone(), nonce)
+        .await
+        .map_err(|e| Error::FailedAuthentication(format!("generate challenge: {e:?}")))?;
+
+    let nonce = nonce_service.issue(client_identity)?;
+
+    SessionStatus::auth(request, ttl, challenge, nonce)
+        .map_err(|e| Error::FailedAuthentication(format!("Session: {e}")))
+}
+
 /// POST /attest
 pub(crate) async fn attest(
     attestation: web::Json<Attestation>,
     request: HttpRequest,
-    map: web::Data<SessionMap>,
+    map: web::Data<dyn SessionStore>,
     attestation_service: web::Data<Arc<AttestationService>>,
+    nonce_service: web::Data<NonceService>,
+    limits: web::Data<SessionLimits>,
+    audit_log: web::Data<AuditLog>,
 ) -> Result<HttpResponse> {
     info!("Attest API called.");
     let cookie = request.cookie(KBS_SESSION_ID).ok_or(Error::MissingCookie)?;
 
-    let (tee, nonce) = {
-        let session = map
-            .sessions
-            .get_async(cookie.value())
-            .await
-            .ok_or(Error::InvalidCookie)?;
-        let session = session.get();
-
-        debug!("Session ID {}", session.id());
-
-        if session.is_expired() {
-            raise_error!(Error::ExpiredCookie);
-        }
-
-        if let SessionStatus::Attested { token, .. } = session {
-            debug!(
-                "Session {} is already attested. Skip attestation and return the old token",
-                session.id()
-            );
-            let body = serde_json::to_string(&json!({
-                "token": token,
-            }))
-            .map_err(|e| Error::TokenIssueFailed(format!("Serialize token failed {e}")))?;
-
-            return Ok(HttpResponse::Ok()
-                .cookie(session.cookie())
-                .content_type("application/json")
-                .body(body));
-        }
-
-        let attestation_str = serde_json::to_string_pretty(&attestation.0)
-            .map_err(|_| Error::AttestationFailed("Failed to serialize Attestation".into()))?;
-        debug!("Attestation: {attestation_str}");
-
-        (session.request().tee, session.challenge().nonce.to_string())
-    };
-
-    let attestation_str = serde_json::to_string(&attestation)
+    let client_identity = request.conn_data::<ClientIdentity>();
+    let token = attest_session(
+        map.get_ref(),
+        cookie.value(),
+        &attestation,
+        &attestation_service,
+        &nonce_service,
+        client_identity.map(|id| id.0.as_str()),
+        &limits,
+        &audit_log,
+        &http_actor(&request),
+    )
+    .await?;
+
+    let session = map
+        .get(cookie.value())
+        .await
+        .map_err(|e| Error::SessionStoreFailed(format!("{e:?}")))?
+        .ok_or(Error::InvalidCookie)?;
+
+    let body = serde_json::to_string(&json!({
+        "token": token,
+    }))
+    .map_err(|e| Error::TokenIssueFailed(format!("Serialize token failed {e}")))?;
+
+    Ok(HttpResponse::Ok()
+        .cookie(session.cookie())
+        .content_type("application/json")
+        .body(body))
+}
+
+/// Verify the `attestation` of the session `session_id`, whose challenge is
+/// bound to the `client_identity`, if known, and tell its attestation token.
+/// The attested session is subject to the `limits`. A session which is
+/// already attested tells its token again. The verification is audited as
+/// done by `actor`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn attest_session(
+    map: &dyn SessionStore,
+    session_id: &str,
+    attestation: &Attestation,
+    attestation_service: &AttestationService,
+    nonce_service: &NonceService,
+    client_identity: Option<&str>,
+    limits: &SessionLimits,
+    audit_log: &AuditLog,
+    actor: &str,
+) -> Result<String> {
+    let session = map
+        .get(session_id)
+        .await
+        .map_err(|e| Error::SessionStoreFailed(format!("{e:?}")))?
+        .ok_or(Error::InvalidCookie)?;
+
+    debug!("Session ID {}", session.id());
+
+    if session.is_expired() {
+        raise_error!(Error::ExpiredCookie);
+    }
+
+    if let SessionStatus::Attested { token, .. } = &session {
+        debug!(
+            "Session {} is already attested. Skip attestation and return the old token",
+            session.id()
+        );
+        return Ok(token.clone());
+    }
+
+    let attestation_str = serde_json::to_string_pretty(attestation)
+        .map_err(|_| Error::AttestationFailed("Failed to serialize Attestation".into()))?;
+    debug!("Attestation: {attestation_str}");
+
+    let (tee, nonce) = (session.request().tee, session.challenge().nonce.to_string());
+
+    let mut session = map
+        .consume_nonce(session_id, nonce_service, client_identity)
+        .await
+        .map_err(|e| Error::SessionStoreFailed(format!("{e:?}")))?
+        .ok_or(Error::InvalidCookie)??;
+
+    let attestation_str = serde_json::to_string(attestation)
         .map_err(|e| Error::AttestationFailed(format!("serialize attestation failed : {e:?}")))?;
+    let start = Instant::now();
     let token = attestation_service
         .verify(tee, &nonce, &attestation_str)
-        .await
-        .map_err(|e| Error::AttestationFailed(format!("{e:?}")))?;
+        .await;
+    crate::metrics::observe_attestation(tee, start, &token);
+    audit_log.record_attestation(actor, tee, &token).await;
+    let token = token.map_err(|e| Error::AttestationFailed(format!("{e:?}")))?;
 
     let claims_b64 = token
         .split('.')
@@ -105,22 +189,10 @@ pub(crate) async fn attest(
     )
     .map_err(|e| Error::TokenIssueFailed(format!("Illegal token base64 claims: {e}")))?;
 
-    let mut session = map
-        .sessions
-        .get_async(cookie.value())
+    session.attest(claims, token.clone(), limits);
+    map.insert(session)
         .await
-        .ok_or(Error::InvalidCookie)?;
-    let session = session.get_mut();
+        .map_err(|e| Error::SessionStoreFailed(format!("{e:?}")))?;
 
-    let body = serde_json::to_string(&json!({
-        "token": token,
-    }))
-    .map_err(|e| Error::TokenIssueFailed(format!("Serialize token failed {e}")))?;
-
-    session.attest(claims, token);
-
-    Ok(HttpResponse::Ok()
-        .cookie(session.cookie())
-        .content_type("application/json")
-        .body(body))
+    Ok(token)
 }
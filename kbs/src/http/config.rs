@@ -2,84 +2,471 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::raise_error;
+
 use super::*;
 
+use actix_web::HttpMessage;
+
+#[cfg(feature = "resource")]
+use actix_web::http::header::{ETag, EntityTag, LastModified};
+#[cfg(feature = "resource")]
+use base64::{engine::general_purpose::STANDARD, Engine};
+#[cfg(feature = "resource")]
+use opentelemetry::KeyValue;
+#[cfg(feature = "resource")]
+use std::time::SystemTime;
+#[cfg(feature = "resource")]
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+#[cfg(feature = "resource")]
+use crate::telemetry::traced;
+
+/// Check that the request is made by the admin, i.e. that it has a token
+/// signed by the `user_pub_key`, unless the admin APIs are `insecure`.
+fn authorize_admin(
+    request: &HttpRequest,
+    user_pub_key: &Option<Ed25519PublicKey>,
+    insecure: bool,
+) -> Result<()> {
+    if insecure {
+        return Ok(());
+    }
+
+    let user_pub_key = user_pub_key
+        .as_ref()
+        .ok_or(Error::UserPublicKeyNotProvided)?;
+    let identity = validate_auth(request, user_pub_key).map_err(|e| {
+        Error::FailedAuthentication(format!("Requester is not an authorized user: {e}"))
+    })?;
+    if let Some(identity) = identity {
+        request.extensions_mut().insert(AdminIdentity(identity));
+    }
+
+    Ok(())
+}
+
 #[cfg(feature = "as")]
 #[derive(serde::Deserialize, Debug)]
 pub struct SetPolicyInput {
     policy_id: String,
     policy: String,
+
+    /// Signature of the policy by the policy author, required if a policy
+    /// author key is configured.
+    signature: Option<String>,
+
+    /// Version of the policy signed by the policy author, required with the
+    /// signature.
+    signed_version: Option<u64>,
 }
 
 #[cfg(feature = "as")]
 /// POST /attestation-policy
+///
+/// The policy is kept as a new version, and its diff with the previous
+/// version is returned. If a policy author key is configured, the policy is
+/// only set if it is signed by the key.
 pub(crate) async fn attestation_policy(
     request: HttpRequest,
     input: web::Json<SetPolicyInput>,
     user_pub_key: web::Data<Option<Ed25519PublicKey>>,
     insecure: web::Data<bool>,
     attestation_service: web::Data<Arc<AttestationService>>,
+    policy_history: web::Data<PolicyHistory>,
+    policy_verifier: web::Data<PolicyVerifier>,
+    audit_log: web::Data<AuditLog>,
 ) -> Result<HttpResponse> {
-    if !insecure.get_ref() {
-        let user_pub_key = user_pub_key
-            .as_ref()
-            .as_ref()
-            .ok_or(Error::UserPublicKeyNotProvided)?;
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
 
-        validate_auth(&request, user_pub_key).map_err(|e| {
-            Error::FailedAuthentication(format!("Requester is not an authorized user: {e}"))
-        })?;
-    }
+    let kind = PolicyKind::Attestation(input.policy_id.clone());
+    policy_verifier
+        .verify(
+            &policy_history,
+            &kind,
+            &input.policy,
+            input.signature.as_deref(),
+            input.signed_version,
+        )
+        .await
+        .map_err(|e| Error::PolicyEndpoint(format!("Verify policy signature error {e:#}")))?;
 
     attestation_service
         .set_policy(&input.policy_id, &input.policy)
         .await
         .map_err(|e| Error::PolicyEndpoint(format!("Set policy error {e}")))?;
 
-    Ok(HttpResponse::Ok().finish())
+    let change = policy_history
+        .record(
+            &kind,
+            &input.policy,
+            input.signature.as_deref(),
+            input.signed_version,
+        )
+        .await
+        .map_err(|e| Error::PolicyEndpoint(format!("Record policy version error {e}")))?;
+    audit_log
+        .record_policy_change(&http_actor(&request), &kind, change.version)
+        .await;
+
+    Ok(HttpResponse::Ok().json(change))
 }
 
 #[cfg(feature = "policy")]
 /// POST /resource-policy
+///
+/// The repository policy `policy_id` of the input is set, or the default
+/// policy if not given. The policy is kept as a new version, and its diff
+/// with the previous version is returned. If a policy author key is
+/// configured, the policy is only set if its `signature` is made by the key.
 pub(crate) async fn resource_policy(
     request: HttpRequest,
     input: web::Json<serde_json::Value>,
     user_pub_key: web::Data<Option<Ed25519PublicKey>>,
     insecure: web::Data<bool>,
     policy_engine: web::Data<PolicyEngine>,
+    policy_history: web::Data<PolicyHistory>,
+    policy_verifier: web::Data<PolicyVerifier>,
+    audit_log: web::Data<AuditLog>,
 ) -> Result<HttpResponse> {
-    if !insecure.get_ref() {
-        let user_pub_key = user_pub_key
-            .as_ref()
-            .as_ref()
-            .ok_or(Error::UserPublicKeyNotProvided)?;
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
 
-        validate_auth(&request, user_pub_key).map_err(|e| {
-            Error::FailedAuthentication(format!("Requester is not an authorized user: {e}"))
-        })?;
-    }
+    let input = input.into_inner();
+    let policy = input["policy"]
+        .as_str()
+        .ok_or(Error::PolicyEndpoint(
+            "Get policy from request failed".to_string(),
+        ))?
+        .to_string();
+    let policy_id = input["policy_id"].as_str();
+    let signature = input["signature"].as_str();
+    let signed_version = input["signed_version"].as_u64();
+
+    let kind = match policy_id {
+        Some(policy_id) => PolicyKind::Repository(policy_id.to_string()),
+        None => PolicyKind::Resource,
+    };
+    policy_verifier
+        .verify(&policy_history, &kind, &policy, signature, signed_version)
+        .await
+        .map_err(|e| Error::PolicyEndpoint(format!("Verify policy signature error {e:#}")))?;
 
     policy_engine
-        .0
-        .lock()
-        .await
-        .set_policy(
-            input.into_inner()["policy"]
-                .as_str()
-                .ok_or(Error::PolicyEndpoint(
-                    "Get policy from request failed".to_string(),
-                ))?
-                .to_string(),
+        .set_policy(policy_id, policy.clone())
+        .await
+        .map_err(|e| Error::PolicyEndpoint(format!("Set policy error {e}")))?;
+
+    let change = policy_history
+        .record(&kind, &policy, signature, signed_version)
+        .await
+        .map_err(|e| Error::PolicyEndpoint(format!("Record policy version error {e}")))?;
+    audit_log
+        .record_policy_change(&http_actor(&request), &kind, change.version)
+        .await;
+
+    Ok(HttpResponse::Ok().json(change))
+}
+
+#[cfg(feature = "policy")]
+#[derive(serde::Deserialize, Debug)]
+pub struct ResourcePolicyDryRunInput {
+    /// The candidate policy, base64 encoded. The set policy of the resource
+    /// if not given.
+    policy: Option<String>,
+
+    /// Path of the resource, `<repository>/<type>/<tag>`.
+    resource_path: String,
+
+    /// The attestation claims.
+    claims: Option<serde_json::Value>,
+
+    /// ID of an attested session of the KBS, whose claims are used.
+    session_id: Option<String>,
+
+    /// An attestation token, whose claims are used.
+    token: Option<String>,
+
+    /// Context of the request, e.g. its time. A request made now if not
+    /// given.
+    request: Option<crate::policy_engine::RequestContext>,
+}
+
+/// The attestation claims of the attested session `session_id`.
+#[cfg(all(feature = "policy", feature = "as"))]
+async fn session_claims(map: &dyn SessionStore, session_id: &str) -> Result<String> {
+    use crate::session::SessionStatus;
+
+    let session = map
+        .get(session_id)
+        .await
+        .map_err(|e| Error::SessionStoreFailed(format!("{e:?}")))?
+        .ok_or_else(|| Error::InvalidRequest(format!("unknown session {session_id}")))?;
+    if session.is_expired() {
+        raise_error!(Error::ExpiredCookie);
+    }
+
+    let SessionStatus::Attested {
+        attestation_claims, ..
+    } = session
+    else {
+        raise_error!(Error::InvalidRequest(format!(
+            "session {session_id} is not attested"
+        )));
+    };
+
+    Ok(attestation_claims)
+}
+
+#[cfg(feature = "policy")]
+/// POST /admin/resource-policy/dry-run
+///
+/// Evaluate a candidate resource policy against attestation claims, given
+/// directly or recorded in an attested session or an attestation token,
+/// and trace the evaluation. The set policy is not changed.
+pub(crate) async fn resource_policy_dry_run(
+    request: HttpRequest,
+    input: web::Json<ResourcePolicyDryRunInput>,
+    user_pub_key: web::Data<Option<Ed25519PublicKey>>,
+    insecure: web::Data<bool>,
+    policy_engine: web::Data<PolicyEngine>,
+    #[cfg(feature = "as")] map: web::Data<dyn SessionStore>,
+    #[cfg(feature = "resource")] token_verifier: web::Data<
+        Arc<RwLock<dyn AttestationTokenVerifier + Send + Sync>>,
+    >,
+) -> Result<HttpResponse> {
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
+
+    let input = input.into_inner();
+    let claims = match (input.claims, input.session_id, input.token) {
+        (Some(claims), None, None) => claims.to_string(),
+        #[cfg(feature = "as")]
+        (None, Some(session_id), None) => session_claims(map.get_ref(), &session_id).await?,
+        #[cfg(feature = "resource")]
+        (None, None, Some(token)) => token_verifier
+            .read()
+            .await
+            .verify(token)
+            .await
+            .map_err(|e| Error::TokenParseFailed(format!("verify token failed: {e}")))?,
+        _ => raise_error!(Error::InvalidRequest(String::from(
+            "one of `claims`, `session_id` or `token` must be given"
+        ))),
+    };
+
+    let request = input
+        .request
+        .unwrap_or_else(crate::policy_engine::RequestContext::now);
+    let report = policy_engine
+        .dry_run(input.policy, input.resource_path, claims, &request)
+        .await
+        .map_err(|e| Error::PolicyEndpoint(format!("Dry run policy error {e}")))?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// The policy of the `admin/policy-versions` endpoints.
+#[cfg(any(feature = "as", feature = "policy"))]
+fn policy_kind(request: &HttpRequest) -> PolicyKind {
+    let match_info = request.match_info();
+    match (
+        match_info.get("policy_id"),
+        match_info.get("repository_policy_id"),
+    ) {
+        (Some(policy_id), _) => PolicyKind::Attestation(policy_id.to_string()),
+        (None, Some(policy_id)) => PolicyKind::Repository(policy_id.to_string()),
+        (None, None) => PolicyKind::Resource,
+    }
+}
+
+#[cfg(any(feature = "as", feature = "policy"))]
+/// GET /admin/policy-versions/resource
+/// GET /admin/policy-versions/repository/{repository_policy_id}
+/// GET /admin/policy-versions/attestation/{policy_id}
+pub(crate) async fn list_policy_versions(
+    request: HttpRequest,
+    user_pub_key: web::Data<Option<Ed25519PublicKey>>,
+    insecure: web::Data<bool>,
+    policy_history: web::Data<PolicyHistory>,
+) -> Result<HttpResponse> {
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
+
+    let versions = policy_history
+        .list(&policy_kind(&request))
+        .await
+        .map_err(|e| Error::PolicyEndpoint(format!("List policy versions error {e}")))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "versions": versions })))
+}
+
+#[cfg(any(feature = "as", feature = "policy"))]
+/// GET /admin/policy-versions/resource/{version}
+/// GET /admin/policy-versions/repository/{repository_policy_id}/{version}
+/// GET /admin/policy-versions/attestation/{policy_id}/{version}
+pub(crate) async fn get_policy_version(
+    request: HttpRequest,
+    user_pub_key: web::Data<Option<Ed25519PublicKey>>,
+    insecure: web::Data<bool>,
+    policy_history: web::Data<PolicyHistory>,
+) -> Result<HttpResponse> {
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
+
+    let version = request
+        .match_info()
+        .get("version")
+        .and_then(|version| version.parse().ok())
+        .ok_or_else(|| Error::InvalidRequest(String::from("illegal policy version")))?;
+
+    let version = policy_history
+        .get(&policy_kind(&request), version)
+        .await
+        .map_err(|e| Error::PolicyEndpoint(format!("Get policy version error {e}")))?;
+
+    Ok(HttpResponse::Ok().json(version))
+}
+
+#[cfg(any(feature = "as", feature = "policy"))]
+#[derive(serde::Deserialize, Debug)]
+pub struct RollbackPolicyInput {
+    version: u64,
+
+    /// New signature of the policy of the version by the policy author, for
+    /// a newer `signed_version`. Defaults to the signature of the version,
+    /// which is refused as not newer if a policy author key is configured.
+    signature: Option<String>,
+
+    signed_version: Option<u64>,
+}
+
+#[cfg(feature = "as")]
+/// POST /admin/policy-versions/attestation/{policy_id}
+///
+/// Roll the attestation policy back to the given version, which is kept as
+/// a new version. If a policy author key is configured, the policy of the
+/// version must be signed again for a newer signed version.
+pub(crate) async fn rollback_attestation_policy(
+    request: HttpRequest,
+    input: web::Json<RollbackPolicyInput>,
+    user_pub_key: web::Data<Option<Ed25519PublicKey>>,
+    insecure: web::Data<bool>,
+    attestation_service: web::Data<Arc<AttestationService>>,
+    policy_history: web::Data<PolicyHistory>,
+    policy_verifier: web::Data<PolicyVerifier>,
+    audit_log: web::Data<AuditLog>,
+) -> Result<HttpResponse> {
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
+
+    let kind = policy_kind(&request);
+    let PolicyKind::Attestation(policy_id) = &kind else {
+        raise_error!(Error::InvalidRequest(String::from("missing policy id")));
+    };
+    let version = policy_history
+        .get(&kind, input.version)
+        .await
+        .map_err(|e| Error::PolicyEndpoint(format!("Get policy version error {e}")))?;
+    let signature = input.signature.as_deref().or(version.signature.as_deref());
+    let signed_version = input.signed_version.or(version.signed_version);
+    policy_verifier
+        .verify(
+            &policy_history,
+            &kind,
+            &version.policy,
+            signature,
+            signed_version,
         )
         .await
+        .map_err(|e| Error::PolicyEndpoint(format!("Verify policy signature error {e:#}")))?;
+
+    attestation_service
+        .set_policy(policy_id, &version.policy)
+        .await
         .map_err(|e| Error::PolicyEndpoint(format!("Set policy error {e}")))?;
 
-    Ok(HttpResponse::Ok().finish())
+    let change = policy_history
+        .record(&kind, &version.policy, signature, signed_version)
+        .await
+        .map_err(|e| Error::PolicyEndpoint(format!("Record policy version error {e}")))?;
+    audit_log
+        .record_policy_change(&http_actor(&request), &kind, change.version)
+        .await;
+
+    Ok(HttpResponse::Ok().json(change))
+}
+
+#[cfg(feature = "policy")]
+/// POST /admin/policy-versions/resource
+/// POST /admin/policy-versions/repository/{repository_policy_id}
+///
+/// Roll the resource policy back to the given version, which is kept as a
+/// new version. If a policy author key is configured, the policy of the
+/// version must be signed again for a newer signed version.
+pub(crate) async fn rollback_resource_policy(
+    request: HttpRequest,
+    input: web::Json<RollbackPolicyInput>,
+    user_pub_key: web::Data<Option<Ed25519PublicKey>>,
+    insecure: web::Data<bool>,
+    policy_engine: web::Data<PolicyEngine>,
+    policy_history: web::Data<PolicyHistory>,
+    policy_verifier: web::Data<PolicyVerifier>,
+    audit_log: web::Data<AuditLog>,
+) -> Result<HttpResponse> {
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
+
+    let kind = policy_kind(&request);
+    let policy_id = match &kind {
+        PolicyKind::Repository(policy_id) => Some(policy_id.as_str()),
+        _ => None,
+    };
+    let version = policy_history
+        .get(&kind, input.version)
+        .await
+        .map_err(|e| Error::PolicyEndpoint(format!("Get policy version error {e}")))?;
+    let signature = input.signature.as_deref().or(version.signature.as_deref());
+    let signed_version = input.signed_version.or(version.signed_version);
+    policy_verifier
+        .verify(
+            &policy_history,
+            &kind,
+            &version.policy,
+            signature,
+            signed_version,
+        )
+        .await
+        .map_err(|e| Error::PolicyEndpoint(format!("Verify policy signature error {e:#}")))?;
+
+    policy_engine
+        .set_policy(policy_id, version.policy.clone())
+        .await
+        .map_err(|e| Error::PolicyEndpoint(format!("Set policy error {e}")))?;
+
+    let change = policy_history
+        .record(&kind, &version.policy, signature, signed_version)
+        .await
+        .map_err(|e| Error::PolicyEndpoint(format!("Record policy version error {e}")))?;
+    audit_log
+        .record_policy_change(&http_actor(&request), &kind, change.version)
+        .await;
+
+    Ok(HttpResponse::Ok().json(change))
+}
+
+#[cfg(feature = "resource")]
+#[derive(serde::Deserialize, Debug)]
+pub struct SetResourceQuery {
+    /// Time after which the resource expires, in RFC 3339 format.
+    expires_at: Option<String>,
+
+    /// Lifetime of the resource in seconds.
+    ttl: Option<u64>,
 }
 
 #[cfg(feature = "resource")]
 /// POST /resource/{repository}/{type}/{tag}
 /// POST /resource/{type}/{tag}
+/// POST /resource/{repository}/{type}/{tag}?ttl={seconds}
+/// POST /resource/{repository}/{type}/{tag}?expires_at={RFC 3339 time}
+///
+/// A detached signature of the resource can be given, base64 encoded, in the
+/// `X-Kbs-Resource-Signature` header.
 ///
 /// TODO: Although this endpoint is authenticated through a JSON Web Token (JWT),
 /// only identified users should be able to get a JWT and access it.
@@ -91,41 +478,337 @@ pub(crate) async fn resource_policy(
 pub(crate) async fn set_resource(
     request: HttpRequest,
     data: web::Bytes,
+    query: web::Query<SetResourceQuery>,
     user_pub_key: web::Data<Option<Ed25519PublicKey>>,
     insecure: web::Data<bool>,
     repository: web::Data<Arc<RwLock<dyn Repository + Send + Sync>>>,
 ) -> Result<HttpResponse> {
-    if !insecure.get_ref() {
-        let user_pub_key = user_pub_key
-            .as_ref()
-            .as_ref()
-            .ok_or(Error::UserPublicKeyNotProvided)?;
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
 
-        validate_auth(&request, user_pub_key).map_err(|e| {
-            Error::FailedAuthentication(format!("Requester is not an authorized user: {e}"))
-        })?;
-    }
+    let resource_description = resource_desc(&request)?;
+    let expires_at = match (&query.expires_at, query.ttl) {
+        (Some(_), Some(_)) => {
+            raise_error!(Error::InvalidRequest(String::from(
+                "`expires_at` and `ttl` cannot be both set"
+            )))
+        }
+        (Some(expires_at), None) => Some(
+            OffsetDateTime::parse(expires_at, &Rfc3339)
+                .map_err(|e| Error::InvalidRequest(format!("illegal `expires_at`: {e}")))?,
+        ),
+        (None, Some(ttl)) => {
+            let ttl = i64::try_from(ttl)
+                .map_err(|_| Error::InvalidRequest(String::from("`ttl` is too large")))?;
+            Some(OffsetDateTime::now_utc() + time::Duration::seconds(ttl))
+        }
+        (None, None) => None,
+    };
 
-    let resource_description = ResourceDesc {
-        repository_name: request
-            .match_info()
-            .get("repository")
-            .unwrap_or("default")
-            .to_string(),
-        resource_type: request
-            .match_info()
-            .get("type")
-            .ok_or_else(|| Error::InvalidRequest(String::from("no `type` in url")))?
-            .to_string(),
-        resource_tag: request
-            .match_info()
-            .get("tag")
-            .ok_or_else(|| Error::InvalidRequest(String::from("no `tag` in url")))?
-            .to_string(),
+    let signature = match request.headers().get("X-Kbs-Resource-Signature") {
+        Some(signature) => Some(
+            STANDARD
+                .decode(signature.as_bytes())
+                .map_err(|e| Error::InvalidRequest(format!("illegal signature: {e}")))?,
+        ),
+        None => None,
     };
 
-    set_secret_resource(&repository, resource_description, data.as_ref())
+    set_secret_resource(
+        &repository,
+        resource_description,
+        data.as_ref(),
+        signature.as_deref(),
+        expires_at,
+    )
+    .await
+    .map_err(|e| Error::SetSecretFailed(format!("{e}")))?;
+    Ok(HttpResponse::Ok().content_type("application/json").body(""))
+}
+
+#[cfg(feature = "resource")]
+/// DELETE /resource/{repository}/{type}/{tag}
+/// DELETE /resource/{type}/{tag}
+pub(crate) async fn delete_resource(
+    request: HttpRequest,
+    user_pub_key: web::Data<Option<Ed25519PublicKey>>,
+    insecure: web::Data<bool>,
+    repository: web::Data<Arc<RwLock<dyn Repository + Send + Sync>>>,
+) -> Result<HttpResponse> {
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
+
+    let resource_description = resource_desc(&request)?;
+    if !resource_description.is_valid() {
+        return Err(Error::InvalidRequest("Invalid resource path".to_string()));
+    }
+
+    log::info!(
+        "Delete resource kbs:///{}/{}/{}",
+        resource_description.repository_name,
+        resource_description.resource_type,
+        resource_description.resource_tag
+    );
+
+    let attributes = vec![KeyValue::new(
+        "kbs.repository",
+        resource_description.repository_name.clone(),
+    )];
+    let mut repository = repository.write().await;
+    traced(
+        "repository.delete_secret_resource",
+        attributes,
+        repository.delete_secret_resource(resource_description),
+    )
+    .await
+    .map_err(|e| Error::DeleteSecretFailed(format!("{e:?}")))?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[cfg(feature = "resource")]
+/// GET /admin/resource-versions/{repository}/{type}/{tag}
+/// GET /admin/resource-versions/{type}/{tag}
+pub(crate) async fn list_resource_versions(
+    request: HttpRequest,
+    user_pub_key: web::Data<Option<Ed25519PublicKey>>,
+    insecure: web::Data<bool>,
+    repository: web::Data<Arc<RwLock<dyn Repository + Send + Sync>>>,
+) -> Result<HttpResponse> {
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
+
+    let resource_description = resource_desc(&request)?;
+    if !resource_description.is_valid() {
+        return Err(Error::InvalidRequest("Invalid resource path".to_string()));
+    }
+
+    let versions = repository
+        .read()
+        .await
+        .list_secret_resource_versions(resource_description)
+        .await
+        .map_err(|e| Error::ReadSecretFailed(format!("{e:?}")))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "versions": versions })))
+}
+
+#[cfg(feature = "resource")]
+#[derive(serde::Deserialize, Debug)]
+pub struct RollbackResourceInput {
+    version: u64,
+}
+
+#[cfg(feature = "resource")]
+/// POST /admin/resource-versions/{repository}/{type}/{tag}
+/// POST /admin/resource-versions/{type}/{tag}
+///
+/// Roll the resource back to the given version, which becomes a new latest
+/// version.
+pub(crate) async fn rollback_resource(
+    request: HttpRequest,
+    input: web::Json<RollbackResourceInput>,
+    user_pub_key: web::Data<Option<Ed25519PublicKey>>,
+    insecure: web::Data<bool>,
+    repository: web::Data<Arc<RwLock<dyn Repository + Send + Sync>>>,
+) -> Result<HttpResponse> {
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
+
+    let resource_description = resource_desc(&request)?;
+    if !resource_description.is_valid() {
+        return Err(Error::InvalidRequest("Invalid resource path".to_string()));
+    }
+
+    repository
+        .write()
+        .await
+        .rollback_secret_resource(resource_description, input.version)
         .await
         .map_err(|e| Error::SetSecretFailed(format!("{e}")))?;
-    Ok(HttpResponse::Ok().content_type("application/json").body(""))
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[cfg(feature = "resource")]
+#[derive(serde::Deserialize, Debug)]
+pub struct ListResourcesQuery {
+    /// Only list the resources of this repository.
+    repository: Option<String>,
+}
+
+#[cfg(feature = "resource")]
+/// GET /admin/resources
+/// GET /admin/resources?repository={repository}
+pub(crate) async fn list_resources(
+    request: HttpRequest,
+    query: web::Query<ListResourcesQuery>,
+    user_pub_key: web::Data<Option<Ed25519PublicKey>>,
+    insecure: web::Data<bool>,
+    repository: web::Data<Arc<RwLock<dyn Repository + Send + Sync>>>,
+) -> Result<HttpResponse> {
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
+
+    let mut resources = repository
+        .read()
+        .await
+        .list_secret_resources()
+        .await
+        .map_err(|e| Error::ReadSecretFailed(format!("{e:?}")))?;
+    if let Some(repository_name) = &query.repository {
+        resources.retain(|r| &r.repository_name == repository_name);
+    }
+
+    Ok(HttpResponse::Ok().json(resources))
+}
+
+#[cfg(feature = "resource")]
+/// POST /admin/resources/import
+///
+/// The body is either a JSON manifest or a (gzipped) tarball of
+/// `<repository>/<type>/<tag>` files. Nothing is written if any resource of
+/// the bundle is invalid.
+pub(crate) async fn import_resources(
+    request: HttpRequest,
+    data: web::Bytes,
+    user_pub_key: web::Data<Option<Ed25519PublicKey>>,
+    insecure: web::Data<bool>,
+    repository: web::Data<Arc<RwLock<dyn Repository + Send + Sync>>>,
+) -> Result<HttpResponse> {
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
+
+    let imported = crate::resource::import_resources(&repository, data.as_ref())
+        .await
+        .map_err(|e| Error::SetSecretFailed(format!("{e:?}")))?;
+    log::info!("Imported {imported} resources");
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "imported": imported })))
+}
+
+#[cfg(feature = "resource")]
+#[derive(serde::Deserialize, Debug)]
+pub struct ExportResourcesInput {
+    /// Repositories to export, all repositories if empty.
+    #[serde(default)]
+    repositories: Vec<String>,
+
+    /// If given, the manifest is returned encrypted to this key as a JWE.
+    public_key: Option<kbs_types::TeePubKey>,
+}
+
+#[cfg(feature = "resource")]
+/// POST /admin/resources/export
+pub(crate) async fn export_resources(
+    request: HttpRequest,
+    input: web::Json<ExportResourcesInput>,
+    user_pub_key: web::Data<Option<Ed25519PublicKey>>,
+    insecure: web::Data<bool>,
+    repository: web::Data<Arc<RwLock<dyn Repository + Send + Sync>>>,
+) -> Result<HttpResponse> {
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
+
+    let input = input.into_inner();
+    let manifest = crate::resource::export_resources(&repository, &input.repositories)
+        .await
+        .map_err(|e| Error::ReadSecretFailed(format!("{e:?}")))?;
+    log::info!("Exported {} resources", manifest.resources.len());
+
+    match input.public_key {
+        Some(public_key) => {
+            let manifest =
+                serde_json::to_vec(&manifest).map_err(|e| Error::JWEFailed(e.to_string()))?;
+            Ok(HttpResponse::Ok().json(jwe(public_key, manifest, None)?))
+        }
+        None => Ok(HttpResponse::Ok().json(manifest)),
+    }
+}
+
+#[cfg(feature = "resource")]
+/// GET /admin/resource-cache
+///
+/// Returns the hits, misses, hit rate and number of entries of the resource
+/// cache, or 404 if no cache is configured.
+pub(crate) async fn resource_cache_stats(
+    request: HttpRequest,
+    user_pub_key: web::Data<Option<Ed25519PublicKey>>,
+    insecure: web::Data<bool>,
+    repository: web::Data<Arc<RwLock<dyn Repository + Send + Sync>>>,
+) -> Result<HttpResponse> {
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
+
+    let Some(stats) = repository.read().await.cache_stats().await else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let lookups = stats.hits + stats.misses;
+    let hit_rate = match lookups {
+        0 => 0.0,
+        _ => stats.hits as f64 / lookups as f64,
+    };
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "hits": stats.hits,
+        "misses": stats.misses,
+        "hit_rate": hit_rate,
+        "entries": stats.entries,
+    })))
+}
+
+#[cfg(feature = "resource")]
+/// DELETE /admin/resource-cache
+pub(crate) async fn purge_resource_cache(
+    request: HttpRequest,
+    user_pub_key: web::Data<Option<Ed25519PublicKey>>,
+    insecure: web::Data<bool>,
+    repository: web::Data<Arc<RwLock<dyn Repository + Send + Sync>>>,
+) -> Result<HttpResponse> {
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
+
+    let purged = repository
+        .read()
+        .await
+        .purge_cache()
+        .await
+        .map_err(|e| Error::DeleteSecretFailed(format!("{e:?}")))?;
+    log::info!("Purged {purged} entries of the resource cache");
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "purged": purged })))
+}
+
+#[cfg(feature = "resource")]
+/// HEAD /resource/{repository}/{type}/{tag}
+/// HEAD /resource/{type}/{tag}
+///
+/// The metadata of the resource is returned in the `ETag` (SHA-256 digest of
+/// the data), `Last-Modified`, `X-Kbs-Resource-Size` and
+/// `X-Kbs-Resource-Expires` headers.
+pub(crate) async fn resource_metadata(
+    request: HttpRequest,
+    user_pub_key: web::Data<Option<Ed25519PublicKey>>,
+    insecure: web::Data<bool>,
+    repository: web::Data<Arc<RwLock<dyn Repository + Send + Sync>>>,
+) -> Result<HttpResponse> {
+    authorize_admin(&request, user_pub_key.get_ref(), *insecure.get_ref())?;
+
+    let resource_description = resource_desc(&request)?;
+    if !resource_description.is_valid() {
+        return Err(Error::InvalidRequest("Invalid resource path".to_string()));
+    }
+
+    let metadata = repository
+        .read()
+        .await
+        .secret_resource_metadata(resource_description)
+        .await
+        .map_err(|e| Error::ReadSecretFailed(format!("{e:?}")))?;
+
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header(ETag(EntityTag::new_strong(metadata.checksum)))
+        .insert_header(("X-Kbs-Resource-Size", metadata.size));
+    if let Some(modified_at) = metadata.modified_at {
+        response.insert_header(LastModified(SystemTime::from(modified_at).into()));
+    }
+    if let Some(expires_at) = metadata.expires_at {
+        let expires_at = expires_at
+            .format(&Rfc3339)
+            .map_err(|e| Error::ReadSecretFailed(format!("illegal expiry time: {e}")))?;
+        response.insert_header(("X-Kbs-Resource-Expires", expires_at));
+    }
+
+    Ok(response.finish())
 }
@@ -22,6 +22,30 @@ const ERROR_TYPE_PREFIX: &str = "https://github.com/confidential-containers/kbs/
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Why a resource is denied, told to the client besides the error.
+#[derive(Debug, Default, Serialize)]
+pub struct PolicyDenial {
+    /// ID of the denial in the log of the KBS.
+    pub correlation_id: String,
+
+    /// Message of the operator of the KBS.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+
+    /// Advice of the resource policy, e.g. why the resource is denied.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub advice: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct DenialInformation<'a> {
+    #[serde(flatten)]
+    info: ErrorInformation,
+
+    #[serde(flatten)]
+    denial: &'a PolicyDenial,
+}
+
 #[allow(dead_code)]
 #[derive(Error, AsRefStr, Debug)]
 pub enum Error {
@@ -31,6 +55,9 @@ pub enum Error {
     #[error("Received illegal attestation claims: {0}")]
     AttestationClaimsParseFailed(String),
 
+    #[error("Delete secret failed: {0}")]
+    DeleteSecretFailed(String),
+
     #[error("The cookie is expired")]
     ExpiredCookie,
 
@@ -49,14 +76,17 @@ pub enum Error {
     #[error("The cookie is missing")]
     MissingCookie,
 
+    #[error("The nonce of the attestation is rejected: {0}")]
+    NonceRejected(String),
+
     #[error("Policy error: {0}")]
     PolicyEndpoint(String),
 
     #[error("Resource policy engine evaluate failed: {0}")]
     PolicyEngineFailed(String),
 
-    #[error("Resource not permitted.")]
-    PolicyReject,
+    #[error("Resource not permitted, correlation ID {}", .0.correlation_id)]
+    PolicyReject(PolicyDenial),
 
     #[error("Public key get failed: {0}")]
     PublicKeyGetFailed(String),
@@ -64,9 +94,18 @@ pub enum Error {
     #[error("Read secret failed: {0}")]
     ReadSecretFailed(String),
 
+    #[error("The nonce of the attestation is reused")]
+    ReusedNonce,
+
+    #[error("Session store failed: {0}")]
+    SessionStoreFailed(String),
+
     #[error("Set secret failed: {0}")]
     SetSecretFailed(String),
 
+    #[error("The nonce of the attestation is stale")]
+    StaleNonce,
+
     #[error("Attestation token issue failed: {0}")]
     TokenIssueFailed(String),
 
@@ -95,8 +134,23 @@ macro_rules! raise_error {
     };
 }
 
-impl ResponseError for Error {
-    fn error_response(&self) -> HttpResponse {
+#[cfg(feature = "as")]
+impl From<crate::nonce::NonceError> for Error {
+    fn from(e: crate::nonce::NonceError) -> Self {
+        use crate::nonce::NonceError;
+
+        match e {
+            NonceError::Stale => Error::StaleNonce,
+            NonceError::Reused => Error::ReusedNonce,
+            e => Error::NonceRejected(e.to_string()),
+        }
+    }
+}
+
+impl Error {
+    /// The `ErrorInformation` of the error as JSON, with the denial of a
+    /// rejected resource.
+    pub(crate) fn information(&self) -> String {
         let mut detail = String::new();
 
         // The write macro here will only raise error when OOM of the string.
@@ -109,7 +163,19 @@ impl ResponseError for Error {
         // All the fields inside the ErrorInfo are printable characters, so this
         // error cannot happen.
         // A test covering all the possible error types are given to ensure this.
-        let body = serde_json::to_string(&info).expect("serialize error response failed");
+        match self {
+            Error::PolicyReject(denial) => {
+                serde_json::to_string(&DenialInformation { info, denial })
+            }
+            _ => serde_json::to_string(&info),
+        }
+        .expect("serialize error response failed")
+    }
+}
+
+impl ResponseError for Error {
+    fn error_response(&self) -> HttpResponse {
+        let body = self.information();
 
         // Due to the definition of KBS attestation protocol, we set the http code.
         let mut res = match self {
@@ -127,10 +193,11 @@ impl ResponseError for Error {
 mod tests {
     use rstest::rstest;
 
-    use crate::http::Error;
+    use crate::http::{Error, PolicyDenial};
 
     #[rstest]
     #[case(Error::AttestationFailed("test".into()))]
+    #[case(Error::DeleteSecretFailed("test".into()))]
     #[case(Error::ExpiredCookie)]
     #[case(Error::FailedAuthentication("test".into()))]
     #[case(Error::InvalidCookie)]
@@ -138,11 +205,15 @@ mod tests {
     #[case(Error::MissingCookie)]
     #[case(Error::InvalidRequest("test".into()))]
     #[case(Error::JWEFailed("test".into()))]
+    #[case(Error::NonceRejected("test".into()))]
     #[case(Error::PolicyEndpoint("test".into()))]
-    #[case(Error::PolicyReject)]
+    #[case(Error::PolicyReject(Default::default()))]
     #[case(Error::PublicKeyGetFailed("test".into()))]
     #[case(Error::ReadSecretFailed("test".into()))]
+    #[case(Error::ReusedNonce)]
+    #[case(Error::SessionStoreFailed("test".into()))]
     #[case(Error::SetSecretFailed("test".into()))]
+    #[case(Error::StaleNonce)]
     #[case(Error::TokenIssueFailed("test".into()))]
     #[case(Error::TokenParseFailed("test".into()))]
     #[case(Error::UnAuthenticatedCookie)]
@@ -150,4 +221,25 @@ mod tests {
     fn into_error_response(#[case] err: Error) {
         let _ = actix_web::ResponseError::error_response(&err);
     }
+
+    #[tokio::test]
+    async fn policy_reject_response() {
+        let err = Error::PolicyReject(PolicyDenial {
+            correlation_id: "42".into(),
+            message: None,
+            advice: vec!["the TEE is not TDX".into()],
+        });
+        let res = actix_web::ResponseError::error_response(&err);
+        let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "type": "https://github.com/confidential-containers/kbs/errors/PolicyReject",
+                "detail": "Resource not permitted, correlation ID 42",
+                "correlation_id": "42",
+                "advice": ["the TEE is not TDX"],
+            })
+        );
+    }
 }
@@ -0,0 +1,26 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+
+/// GET /metrics
+///
+/// Returns the Prometheus metrics of the KBS, and of its built-in AS, in the
+/// text exposition format.
+pub(crate) async fn metrics(
+    #[cfg(feature = "as")] map: web::Data<dyn SessionStore>,
+) -> HttpResponse {
+    #[cfg(feature = "as")]
+    match map.count().await {
+        Ok(count) => crate::metrics::observe_sessions(&count),
+        Err(e) => log::warn!("count sessions failed: {e:?}"),
+    }
+
+    match crate::metrics::gather() {
+        Ok(metrics) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(metrics),
+        Err(e) => HttpResponse::InternalServerError().body(format!("gather metrics failed: {e}")),
+    }
+}
@@ -4,13 +4,18 @@
 
 #[cfg(feature = "as")]
 use crate::attestation::{AttestationService, AS_TOKEN_TEE_PUBKEY_PATH};
+use crate::audit::{http_actor, AdminIdentity, AuditLog};
 use crate::auth::validate_auth;
 #[cfg(feature = "policy")]
 use crate::policy_engine::PolicyEngine;
+#[cfg(any(feature = "as", feature = "policy"))]
+use crate::policy_history::{PolicyHistory, PolicyKind};
+#[cfg(any(feature = "as", feature = "policy"))]
+use crate::policy_signing::PolicyVerifier;
 #[cfg(feature = "resource")]
 use crate::resource::{set_secret_resource, Repository, ResourceDesc};
 #[cfg(feature = "as")]
-use crate::session::{SessionMap, KBS_SESSION_ID};
+use crate::session::{SessionLimits, SessionStore, KBS_SESSION_ID};
 #[cfg(feature = "resource")]
 use crate::token::AttestationTokenVerifier;
 use actix_web::Responder;
@@ -25,6 +30,7 @@ mod attest;
 
 mod config;
 mod error;
+mod metrics;
 
 #[cfg(feature = "resource")]
 mod resource;
@@ -41,3 +47,6 @@ pub use self::config::*;
 pub use resource::*;
 
 pub use error::*;
+
+/// RESTful API that exposes the Prometheus metrics
+pub use metrics::*;
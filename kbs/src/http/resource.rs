@@ -10,12 +10,17 @@ use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::Engine;
 use kbs_types::{Response, TeePubKey};
 use log::{debug, error, info};
+use opentelemetry::KeyValue;
 use rand::{rngs::OsRng, Rng};
 use rsa::{BigUint, Pkcs1v15Encrypt, RsaPublicKey};
 use serde::Deserialize;
 use serde_json::{json, Deserializer, Value};
+use std::time::Instant;
 
+#[cfg(feature = "policy")]
+use crate::policy_engine::Obligations;
 use crate::raise_error;
+use crate::telemetry::traced;
 
 use super::*;
 
@@ -24,21 +29,32 @@ const TOKEN_TEE_PUBKEY_PATH: &str = AS_TOKEN_TEE_PUBKEY_PATH;
 #[cfg(not(feature = "as"))]
 const TOKEN_TEE_PUBKEY_PATH: &str = "/customized_claims/runtime_data/tee-pubkey";
 
+#[derive(Deserialize)]
+struct ResourceQuery {
+    /// Version of the resource to get, the latest version if not given.
+    version: Option<u64>,
+}
+
 #[allow(unused_assignments)]
 /// GET /resource/{repository}/{type}/{tag}
 /// GET /resource/{type}/{tag}
+/// GET /resource/{repository}/{type}/{tag}?version={version}
 pub(crate) async fn get_resource(
     request: HttpRequest,
     repository: web::Data<Arc<RwLock<dyn Repository + Send + Sync>>>,
-    #[cfg(feature = "as")] map: web::Data<SessionMap>,
+    #[cfg(feature = "as")] map: web::Data<dyn SessionStore>,
+    #[cfg(feature = "as")] limits: web::Data<SessionLimits>,
     token_verifier: web::Data<Arc<RwLock<dyn AttestationTokenVerifier + Send + Sync>>>,
     #[cfg(feature = "policy")] policy_engine: web::Data<PolicyEngine>,
+    audit_log: web::Data<AuditLog>,
 ) -> Result<HttpResponse> {
     #[allow(unused_mut)]
     let mut claims_option = None;
     #[cfg(feature = "as")]
     {
-        claims_option = get_attest_claims_from_session(&request, map).await.ok();
+        claims_option = get_attest_claims_from_session(&request, map, &limits)
+            .await
+            .ok();
     }
     let claims_str = if let Some(c) = claims_option {
         debug!("Get pkey from session.");
@@ -47,6 +63,64 @@ pub(crate) async fn get_resource(
         debug!("Get pkey from auth header");
         get_attest_claims_from_header(&request, token_verifier).await?
     };
+    let resource_description = resource_desc(&request)?;
+    let query = web::Query::<ResourceQuery>::from_query(request.query_string())
+        .map_err(|e| Error::InvalidRequest(format!("illegal query string: {e}")))?;
+
+    #[cfg(feature = "policy")]
+    let (client_ip, geo) = policy_engine.request_context_config().client(
+        |name| {
+            let value = request.headers().get(name)?;
+            value.to_str().ok().map(String::from)
+        },
+        request.peer_addr().map(|addr| addr.ip()),
+    );
+
+    let repository_name = resource_description.repository_name.clone();
+    let start = Instant::now();
+    let response = release_resource(
+        &repository,
+        #[cfg(feature = "policy")]
+        &policy_engine,
+        claims_str,
+        resource_description.clone(),
+        query.version,
+        #[cfg(feature = "policy")]
+        client_ip,
+        #[cfg(feature = "policy")]
+        geo,
+    )
+    .await;
+    crate::metrics::observe_resource_request(&repository_name, start, &response);
+    audit_log
+        .record_release(
+            &http_actor(&request),
+            &resource_description,
+            query.version,
+            &response,
+        )
+        .await;
+
+    let res = serde_json::to_string(&response?).map_err(|e| Error::JWEFailed(e.to_string()))?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(res))
+}
+
+/// Release the resource of `resource_description`, at `version` or its
+/// latest one, to the TEE of the attestation claims `claims_str` if the
+/// resource policy allows it. The resource is encrypted to the `tee-pubkey`
+/// of the claims. The client is at `client_ip` and `geo`, if known.
+#[allow(unused_assignments)]
+pub(crate) async fn release_resource(
+    repository: &RwLock<dyn Repository + Send + Sync>,
+    #[cfg(feature = "policy")] policy_engine: &PolicyEngine,
+    claims_str: String,
+    resource_description: ResourceDesc,
+    version: Option<u64>,
+    #[cfg(feature = "policy")] client_ip: Option<String>,
+    #[cfg(feature = "policy")] geo: Option<String>,
+) -> Result<Response> {
     let claims: Value = serde_json::from_str(&claims_str).map_err(|e| {
         Error::AttestationClaimsParseFailed(format!("illegal attestation claims: {e}"))
     })?;
@@ -61,24 +135,6 @@ pub(crate) async fn get_resource(
         Error::AttestationClaimsParseFailed(format!("illegal attestation claims: {e}"))
     })?;
 
-    let resource_description = ResourceDesc {
-        repository_name: request
-            .match_info()
-            .get("repository")
-            .unwrap_or("default")
-            .to_string(),
-        resource_type: request
-            .match_info()
-            .get("type")
-            .ok_or_else(|| Error::InvalidRequest(String::from("no `type` in url")))?
-            .to_string(),
-        resource_tag: request
-            .match_info()
-            .get("tag")
-            .ok_or_else(|| Error::InvalidRequest(String::from("no `tag` in url")))?
-            .to_string(),
-    };
-
     if !resource_description.is_valid() {
         return Err(Error::InvalidRequest("Invalid resource path".to_string()));
     }
@@ -90,69 +146,170 @@ pub(crate) async fn get_resource(
         resource_description.resource_tag
     );
 
+    let resource_path = format!(
+        "{}/{}/{}",
+        resource_description.repository_name,
+        resource_description.resource_type,
+        resource_description.resource_tag
+    );
+
+    #[allow(unused_mut)]
+    let mut release_log_level = log::Level::Info;
+    #[allow(unused_mut)]
+    let mut expires_at = None;
     #[cfg(feature = "policy")]
     {
-        let resource_path = format!(
-            "{}/{}/{}",
-            resource_description.repository_name,
-            resource_description.resource_type,
-            resource_description.resource_tag
-        );
-        let resource_allowed = policy_engine
-            .0
-            .lock()
-            .await
-            .evaluate(resource_path, claims_str)
+        let request_context = policy_engine.request_context(&pubkey.k_mod, client_ip, geo);
+        let mut decision = policy_engine
+            .decide(resource_path.clone(), claims_str, &request_context)
             .await
             .map_err(|e| Error::PolicyEngineFailed(e.to_string()))?;
 
-        if !resource_allowed {
-            raise_error!(Error::PolicyReject);
+        if decision.allowed {
+            if let Err(reason) = check_obligations(&decision.obligations, &claims, version) {
+                decision.allowed = false;
+                decision.advice.push(reason);
+            }
+        }
+
+        if !decision.allowed {
+            // The client is told the correlation ID, which the operator finds
+            // the full decision with.
+            let correlation_id = uuid::Uuid::new_v4().to_string();
+            info!(
+                "Resource {resource_path} denied, correlation ID {correlation_id}, rules {:?}, advice {:?}",
+                decision.rules, decision.advice
+            );
+            crate::metrics::observe_policy_denial(&resource_description.repository_name);
+            let (message, advice) = policy_engine.deny_reasons(&decision);
+            raise_error!(Error::PolicyReject(PolicyDenial {
+                correlation_id,
+                message,
+                advice,
+            }));
         }
 
         info!("Resource access request passes policy check.");
+        release_log_level = decision
+            .obligations
+            .log_level
+            .map_or(log::Level::Info, Into::into);
+        expires_at = decision
+            .obligations
+            .max_ttl
+            .map(|max_ttl| time::OffsetDateTime::now_utc().unix_timestamp() + max_ttl as i64);
     }
 
-    let resource_byte = repository
-        .read()
-        .await
-        .read_secret_resource(resource_description)
-        .await
-        .map_err(|e| Error::ReadSecretFailed(format!("{e:?}")))?;
+    let repository = repository.read().await;
+    let attributes = vec![KeyValue::new(
+        "kbs.repository",
+        resource_description.repository_name.clone(),
+    )];
+    let resource_byte = traced("repository.read_secret_resource", attributes, async {
+        match version {
+            Some(version) => {
+                repository
+                    .read_secret_resource_version(resource_description, version)
+                    .await
+            }
+            None => repository.read_secret_resource(resource_description).await,
+        }
+    })
+    .await
+    .map_err(|e| Error::ReadSecretFailed(format!("{e:?}")))?;
 
-    let jwe = jwe(pubkey, resource_byte)?;
+    let jwe = jwe(pubkey, resource_byte, expires_at)?;
+    log::log!(
+        release_log_level,
+        "Resource kbs:///{resource_path} released"
+    );
 
-    let res = serde_json::to_string(&jwe).map_err(|e| Error::JWEFailed(e.to_string()))?;
+    Ok(jwe)
+}
 
-    Ok(HttpResponse::Ok()
-        .content_type("application/json")
-        .body(res))
+/// Check the `obligations` of the resource policy which are enforced
+/// before the resource is released, and tell why they are not met.
+#[cfg(feature = "policy")]
+fn check_obligations(
+    obligations: &Obligations,
+    claims: &Value,
+    version: Option<u64>,
+) -> std::result::Result<(), String> {
+    if let Some(max_attestation_age) = obligations.max_attestation_age {
+        let Some(issued_at) = claims["iat"].as_i64() else {
+            return Err("the attestation token has no issue time".into());
+        };
+        let age = time::OffsetDateTime::now_utc().unix_timestamp() - issued_at;
+        if age > max_attestation_age as i64 {
+            return Err(format!(
+                "the attestation is older than {max_attestation_age} seconds"
+            ));
+        }
+    }
+
+    if obligations.latest_version_only && version.is_some() {
+        return Err("only the latest version of the resource is released".into());
+    }
+
+    Ok(())
+}
+
+/// Get the resource description from the `{repository}/{type}/{tag}` or
+/// `{type}/{tag}` path of the request.
+pub(crate) fn resource_desc(request: &HttpRequest) -> Result<ResourceDesc> {
+    Ok(ResourceDesc {
+        repository_name: request
+            .match_info()
+            .get("repository")
+            .unwrap_or("default")
+            .to_string(),
+        resource_type: request
+            .match_info()
+            .get("type")
+            .ok_or_else(|| Error::InvalidRequest(String::from("no `type` in url")))?
+            .to_string(),
+        resource_tag: request
+            .match_info()
+            .get("tag")
+            .ok_or_else(|| Error::InvalidRequest(String::from("no `tag` in url")))?
+            .to_string(),
+    })
 }
 
 #[cfg(feature = "as")]
 async fn get_attest_claims_from_session(
     request: &HttpRequest,
-    map: web::Data<SessionMap>,
+    map: web::Data<dyn SessionStore>,
+    limits: &SessionLimits,
 ) -> Result<String> {
     // check cookie
-
-    use crate::session::SessionStatus;
     let cookie = request
         .cookie(KBS_SESSION_ID)
         .ok_or(Error::UnAuthenticatedCookie)?;
 
+    attested_claims(map.get_ref(), limits, cookie.value()).await
+}
+
+/// The attestation claims of the attested session `session_id`, whose
+/// resource request is counted against the `limits`.
+#[cfg(feature = "as")]
+pub(crate) async fn attested_claims(
+    map: &dyn SessionStore,
+    limits: &SessionLimits,
+    session_id: &str,
+) -> Result<String> {
+    use crate::session::SessionStatus;
+
     let session = map
-        .sessions
-        .get_async(cookie.value())
+        .request_resource(session_id, limits)
         .await
+        .map_err(|e| Error::SessionStoreFailed(format!("{e:?}")))?
         .ok_or(Error::UnAuthenticatedCookie)?;
 
-    let session = session.get();
-
     info!("Cookie {} request to get resource", session.id());
 
     if session.is_expired() {
-        error!("Expired KBS cookie {}", cookie.value());
+        error!("Expired KBS cookie {session_id}");
         raise_error!(Error::ExpiredCookie);
     }
 
@@ -163,7 +320,7 @@ async fn get_attest_claims_from_session(
         raise_error!(Error::UnAuthenticatedCookie);
     };
 
-    Ok(attestation_claims.to_owned())
+    Ok(attestation_claims)
 }
 
 async fn get_attest_claims_from_header(
@@ -188,7 +345,13 @@ async fn get_attest_claims_from_header(
 const RSA_ALGORITHM: &str = "RSA1_5";
 const AES_GCM_256_ALGORITHM: &str = "A256GCM";
 
-pub(crate) fn jwe(tee_pub_key: TeePubKey, payload_data: Vec<u8>) -> Result<Response> {
+/// Encrypt the `payload_data` to `tee_pub_key`. The protected header tells
+/// when the payload expires, `expires_at` as a Unix timestamp, if given.
+pub(crate) fn jwe(
+    tee_pub_key: TeePubKey,
+    payload_data: Vec<u8>,
+    expires_at: Option<i64>,
+) -> Result<Response> {
     if tee_pub_key.alg != *RSA_ALGORITHM {
         raise_error!(Error::JWEFailed(format!(
             "algorithm is not {RSA_ALGORITHM} but {}",
@@ -225,11 +388,14 @@ pub(crate) fn jwe(tee_pub_key: TeePubKey, payload_data: Vec<u8>) -> Result<Respo
         .encrypt(&mut rng, Pkcs1v15Encrypt, sym_key)
         .map_err(|e| Error::JWEFailed(format!("RSA encrypt sym key failed: {e:?}")))?;
 
-    let protected_header = json!(
+    let mut protected_header = json!(
     {
        "alg": RSA_ALGORITHM.to_string(),
        "enc": AES_GCM_256_ALGORITHM.to_string(),
     });
+    if let Some(expires_at) = expires_at {
+        protected_header["exp"] = json!(expires_at);
+    }
 
     Ok(Response {
         protected: serde_json::to_string(&protected_header)
@@ -240,3 +406,46 @@ pub(crate) fn jwe(tee_pub_key: TeePubKey, payload_data: Vec<u8>) -> Result<Respo
         tag: "".to_string(),
     })
 }
+
+#[cfg(all(test, feature = "policy"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_obligations() {
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let obligations = Obligations {
+            max_attestation_age: Some(300),
+            latest_version_only: true,
+            max_ttl: None,
+            log_level: None,
+        };
+
+        check_obligations(&obligations, &json!({ "iat": now - 10 }), None).unwrap();
+        assert!(check_obligations(&obligations, &json!({ "iat": now - 600 }), None).is_err());
+        assert!(check_obligations(&obligations, &json!({}), None).is_err());
+        assert!(check_obligations(&obligations, &json!({ "iat": now }), Some(1)).is_err());
+        check_obligations(&Obligations::default(), &json!({}), Some(1)).unwrap();
+    }
+
+    #[test]
+    fn test_jwe_expiry() {
+        use rsa::traits::PublicKeyParts;
+
+        let private_key = rsa::RsaPrivateKey::new(&mut OsRng, 2048).unwrap();
+        let tee_pub_key = TeePubKey {
+            kty: "RSA".into(),
+            alg: RSA_ALGORITHM.into(),
+            k_mod: URL_SAFE_NO_PAD.encode(private_key.n().to_bytes_be()),
+            k_exp: URL_SAFE_NO_PAD.encode(private_key.e().to_bytes_be()),
+        };
+
+        let response = jwe(tee_pub_key.clone(), b"secret".to_vec(), Some(1700000000)).unwrap();
+        let protected: Value = serde_json::from_str(&response.protected).unwrap();
+        assert_eq!(protected["exp"], json!(1700000000));
+
+        let response = jwe(tee_pub_key, b"secret".to_vec(), None).unwrap();
+        let protected: Value = serde_json::from_str(&response.protected).unwrap();
+        assert!(protected.get("exp").is_none());
+    }
+}
@@ -23,7 +23,7 @@ use anyhow::{anyhow, bail, Context, Result};
 use attestation::AttestationService;
 use jwt_simple::prelude::Ed25519PublicKey;
 #[cfg(feature = "resource")]
-use resource::RepositoryConfig;
+use resource::{Backup, BackupConfig, Replication, ReplicationConfig, RepositoryConfig};
 use semver::{BuildMetadata, Prerelease, Version, VersionReq};
 #[cfg(feature = "as")]
 use std::sync::Arc;
@@ -37,16 +37,34 @@ use rustls::ServerConfig;
 #[cfg(feature = "openssl")]
 use openssl::ssl::SslAcceptorBuilder;
 
+use crate::audit::{AuditConfig, AuditLog};
+#[cfg(feature = "grpc")]
+use crate::grpc::{GrpcServerConfig, KbsGrpcService};
 #[cfg(feature = "as")]
-use crate::session::SessionMap;
+use crate::nonce::{NonceConfig, NonceService};
+#[cfg(feature = "as")]
+use crate::session::{SessionConfig, SessionLimits, SessionStoreConfig};
 
+#[cfg(feature = "policy-bundle")]
+use crate::policy_bundle::{PolicyBundle, PolicyBundleConfig};
 #[cfg(feature = "policy")]
 use crate::policy_engine::{PolicyEngine, PolicyEngineConfig};
+#[cfg(any(feature = "as", feature = "policy"))]
+use crate::policy_history::{PolicyHistory, PolicyHistoryConfig};
+#[cfg(any(feature = "as", feature = "policy"))]
+use crate::policy_signing::{PolicySigningConfig, PolicyVerifier};
+#[cfg(any(feature = "policy-bundle", feature = "policy-watch"))]
+use crate::policy_targets::PolicyTargets;
+#[cfg(feature = "policy-watch")]
+use crate::policy_watch::{PolicyWatchConfig, PolicyWatcher};
 
 #[cfg(feature = "as")]
 /// Attestation Service
 pub mod attestation;
 
+/// Security audit log
+pub mod audit;
+
 #[allow(unused_imports)]
 /// KBS config
 pub mod config;
@@ -55,6 +73,17 @@ mod auth;
 #[allow(unused_imports)]
 mod http;
 
+/// Prometheus metrics
+mod metrics;
+
+#[cfg(feature = "grpc")]
+/// gRPC API, alongside the HTTP one
+pub mod grpc;
+
+#[cfg(feature = "as")]
+/// Nonces of the attestation challenges
+pub mod nonce;
+
 #[cfg(feature = "resource")]
 mod resource;
 
@@ -68,11 +97,37 @@ mod token;
 /// Resource Policy Engine
 pub mod policy_engine;
 
+#[cfg(any(feature = "as", feature = "policy"))]
+/// Version history of the resource and attestation policies
+pub mod policy_history;
+
+#[cfg(any(feature = "as", feature = "policy"))]
+/// Signatures of the resource and attestation policies
+pub mod policy_signing;
+
+#[cfg(feature = "policy-bundle")]
+/// Policy bundles pulled from OCI registries
+pub mod policy_bundle;
+
+#[cfg(feature = "policy-watch")]
+/// Policies read from watched files
+pub mod policy_watch;
+
+#[cfg(any(feature = "policy-bundle", feature = "policy-watch"))]
+mod policy_targets;
+
+/// OpenTelemetry traces
+pub mod telemetry;
+
 static KBS_PREFIX: &str = "/kbs";
 static KBS_MAJOR_VERSION: u64 = 0;
 static KBS_MINOR_VERSION: u64 = 1;
 static KBS_PATCH_VERSION: u64 = 0;
 
+/// Maximum size of a resource bundle uploaded to `admin/resources/import`.
+#[cfg(feature = "resource")]
+const MAX_RESOURCE_BUNDLE_SIZE: usize = 16 * 1024 * 1024;
+
 lazy_static! {
     static ref VERSION_REQ: VersionReq = {
         let kbs_version = Version {
@@ -103,19 +158,42 @@ pub struct ApiServer {
     /// resource registration
     user_public_key: Option<PathBuf>,
     certificate: Option<PathBuf>,
+    /// CA certificate the certificates of the clients are verified against.
+    client_ca_certificate: Option<PathBuf>,
     insecure: bool,
 
     #[cfg(feature = "as")]
     attestation_service: Arc<AttestationService>,
+    #[cfg(feature = "as")]
+    nonce_config: NonceConfig,
+    #[cfg(feature = "as")]
+    session_store_config: SessionStoreConfig,
+    #[cfg(feature = "as")]
+    session_limits: SessionLimits,
 
     http_timeout: i64,
     insecure_api: bool,
     #[cfg(feature = "resource")]
     repository_config: RepositoryConfig,
     #[cfg(feature = "resource")]
+    backup_config: Option<BackupConfig>,
+    #[cfg(feature = "resource")]
+    replication_config: Option<ReplicationConfig>,
+    #[cfg(feature = "resource")]
     attestation_token_config: AttestationTokenVerifierConfig,
     #[cfg(feature = "policy")]
     policy_engine_config: PolicyEngineConfig,
+    #[cfg(any(feature = "as", feature = "policy"))]
+    policy_history_config: PolicyHistoryConfig,
+    #[cfg(any(feature = "as", feature = "policy"))]
+    policy_signing_config: Option<PolicySigningConfig>,
+    #[cfg(feature = "policy-bundle")]
+    policy_bundle_config: Option<PolicyBundleConfig>,
+    #[cfg(feature = "policy-watch")]
+    policy_watch_config: Option<PolicyWatchConfig>,
+    #[cfg(feature = "grpc")]
+    grpc_server_config: Option<GrpcServerConfig>,
+    audit_config: Option<AuditConfig>,
 }
 
 impl ApiServer {
@@ -125,20 +203,45 @@ impl ApiServer {
         private_key: Option<PathBuf>,
         user_public_key: Option<PathBuf>,
         certificate: Option<PathBuf>,
+        client_ca_certificate: Option<PathBuf>,
         insecure: bool,
 
         #[cfg(feature = "as")] attestation_service: AttestationService,
+        #[cfg(feature = "as")] nonce_config: NonceConfig,
+        #[cfg(feature = "as")] session_store_config: SessionStoreConfig,
+        #[cfg(feature = "as")] session_config: SessionConfig,
 
         http_timeout: i64,
         insecure_api: bool,
         #[cfg(feature = "resource")] repository_config: RepositoryConfig,
+        #[cfg(feature = "resource")] backup_config: Option<BackupConfig>,
+        #[cfg(feature = "resource")] replication_config: Option<ReplicationConfig>,
         #[cfg(feature = "resource")] attestation_token_config: AttestationTokenVerifierConfig,
         #[cfg(feature = "policy")] policy_engine_config: PolicyEngineConfig,
+        #[cfg(any(feature = "as", feature = "policy"))] policy_history_config: PolicyHistoryConfig,
+        #[cfg(any(feature = "as", feature = "policy"))] policy_signing_config: Option<
+            PolicySigningConfig,
+        >,
+        #[cfg(feature = "policy-bundle")] policy_bundle_config: Option<PolicyBundleConfig>,
+        #[cfg(feature = "policy-watch")] policy_watch_config: Option<PolicyWatchConfig>,
+        #[cfg(feature = "grpc")] grpc_server_config: Option<GrpcServerConfig>,
+        audit_config: Option<AuditConfig>,
     ) -> Result<Self> {
         if !insecure && (private_key.is_none() || certificate.is_none()) {
             bail!("Missing HTTPS credentials");
         }
 
+        #[cfg(feature = "as")]
+        if nonce_config.bind_client_identity && (insecure || client_ca_certificate.is_none()) {
+            bail!("Binding the nonces to the client identity requires HTTPS with a client CA certificate");
+        }
+
+        // The watched policy files are not signed by the policy author.
+        #[cfg(feature = "policy-watch")]
+        if policy_watch_config.is_some() && policy_signing_config.is_some() {
+            bail!("The policy files cannot be watched when the policies must be signed by a policy author");
+        }
+
         cfg_if::cfg_if! {
             if #[cfg(not(any(feature = "as", feature = "resource")))] {
                 compile_error!("Must enable at least one of the following features: `as`, `resource`");
@@ -150,25 +253,48 @@ impl ApiServer {
             private_key,
             user_public_key,
             certificate,
+            client_ca_certificate,
             insecure,
 
             #[cfg(feature = "as")]
             attestation_service: Arc::new(attestation_service),
+            #[cfg(feature = "as")]
+            nonce_config,
+            #[cfg(feature = "as")]
+            session_store_config,
+            #[cfg(feature = "as")]
+            session_limits: SessionLimits::new(&session_config, http_timeout)?,
 
             http_timeout,
             insecure_api,
             #[cfg(feature = "resource")]
             repository_config,
             #[cfg(feature = "resource")]
+            backup_config,
+            #[cfg(feature = "resource")]
+            replication_config,
+            #[cfg(feature = "resource")]
             attestation_token_config,
             #[cfg(feature = "policy")]
             policy_engine_config,
+            #[cfg(any(feature = "as", feature = "policy"))]
+            policy_history_config,
+            #[cfg(any(feature = "as", feature = "policy"))]
+            policy_signing_config,
+            #[cfg(feature = "policy-bundle")]
+            policy_bundle_config,
+            #[cfg(feature = "policy-watch")]
+            policy_watch_config,
+            #[cfg(feature = "grpc")]
+            grpc_server_config,
+            audit_config,
         })
     }
 
     #[cfg(feature = "rustls")]
     fn tls_config(&self) -> Result<ServerConfig> {
-        use rustls::{Certificate, PrivateKey};
+        use rustls::server::AllowAnyAnonymousOrAuthenticatedClient;
+        use rustls::{Certificate, PrivateKey, RootCertStore};
         use rustls_pemfile::{certs, read_one, Item};
         use std::fs::File;
         use std::io::BufReader;
@@ -197,16 +323,31 @@ impl ApiServer {
             None | Some(_) => Err(anyhow!("Invalid private key file")),
         }?;
 
-        ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &self.client_ca_certificate {
+            Some(ca_certificate) => {
+                let ca_file = &mut BufReader::new(File::open(ca_certificate)?);
+                let mut roots = RootCertStore::empty();
+                for cert in certs(ca_file)? {
+                    roots
+                        .add(&Certificate(cert))
+                        .map_err(|e| anyhow!("Invalid client CA certificate: {e:?}"))?;
+                }
+
+                builder
+                    .with_client_cert_verifier(AllowAnyAnonymousOrAuthenticatedClient::new(roots))
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        builder
             .with_single_cert(cert_chain, key)
             .map_err(anyhow::Error::from)
     }
 
     #[cfg(feature = "openssl")]
     fn tls_config(&self) -> Result<SslAcceptorBuilder> {
-        use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+        use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
 
         let cert_file = self
             .certificate
@@ -222,10 +363,18 @@ impl ApiServer {
         builder.set_private_key_file(key_file, SslFiletype::PEM)?;
         builder.set_certificate_chain_file(cert_file)?;
 
+        // Clients without certificates are still accepted, the certificates
+        // only identify the clients the nonces are bound to.
+        if let Some(ca_certificate) = &self.client_ca_certificate {
+            builder.set_ca_file(ca_certificate)?;
+            builder.set_verify(SslVerifyMode::PEER);
+        }
+
         Ok(builder)
     }
 
-    /// Start the HTTP server and serve API requests.
+    /// Start the HTTP server, and the gRPC server if configured, and serve API
+    /// requests.
     pub async fn serve(&self) -> Result<()> {
         log::info!(
             "Starting HTTP{} server at {:?}",
@@ -233,28 +382,50 @@ impl ApiServer {
             self.sockets
         );
 
+        metrics::register();
+
         #[cfg(feature = "as")]
-        let (attestation_service, sessions) = {
+        let (attestation_service, sessions, nonce_service) = {
             let attestation_service = web::Data::new(self.attestation_service.clone());
-            let sessions = web::Data::new(SessionMap::new());
+            let sessions = web::Data::from(self.session_store_config.initialize().await?);
             let sessions_clone = sessions.clone();
+            let nonce_service = web::Data::new(NonceService::new(
+                &self.nonce_config,
+                self.session_limits.ttl,
+            )?);
 
             tokio::spawn(async move {
                 loop {
                     tokio::time::sleep(std::time::Duration::from_secs(60)).await;
-                    sessions_clone
-                        .sessions
-                        .retain_async(|_, v| !v.is_expired())
-                        .await;
+                    if let Err(e) = sessions_clone.purge_expired().await {
+                        log::warn!("Purge expired sessions failed: {e:?}");
+                    }
                 }
             });
-            (attestation_service, sessions)
+            (attestation_service, sessions, nonce_service)
         };
 
-        let http_timeout = self.http_timeout;
+        #[cfg(feature = "as")]
+        let session_limits = web::Data::new(self.session_limits.clone());
 
         #[cfg(feature = "resource")]
-        let repository = self.repository_config.initialize()?;
+        let repository = self.repository_config.initialize().await?;
+
+        #[cfg(feature = "resource")]
+        tokio::spawn(resource::purge_expired_secret_resources(repository.clone()));
+
+        #[cfg(feature = "resource")]
+        if let Some(backup_config) = &self.backup_config {
+            let backup = Backup::new(backup_config).await?;
+            backup.restore_on_startup(&repository).await?;
+            tokio::spawn(backup.run(repository.clone()));
+        }
+
+        #[cfg(feature = "resource")]
+        if let Some(replication_config) = &self.replication_config {
+            let replication = Replication::new(replication_config).await?;
+            tokio::spawn(replication.run(repository.clone()));
+        }
 
         #[cfg(feature = "resource")]
         let token_verifier =
@@ -263,6 +434,41 @@ impl ApiServer {
         #[cfg(feature = "policy")]
         let policy_engine = PolicyEngine::new(&self.policy_engine_config).await?;
 
+        #[cfg(any(feature = "as", feature = "policy"))]
+        let policy_history = web::Data::new(PolicyHistory::new(&self.policy_history_config)?);
+
+        let audit_log = web::Data::new(AuditLog::new(self.audit_config.as_ref()).await?);
+
+        #[cfg(any(feature = "as", feature = "policy"))]
+        let policy_verifier =
+            web::Data::new(PolicyVerifier::new(self.policy_signing_config.as_ref())?);
+
+        #[cfg(feature = "policy-bundle")]
+        if let Some(policy_bundle_config) = &self.policy_bundle_config {
+            let policy_bundle = PolicyBundle::new(policy_bundle_config).await?;
+            tokio::spawn(policy_bundle.run(PolicyTargets {
+                policy_engine: policy_engine.clone(),
+                #[cfg(feature = "as")]
+                attestation_service: Some(self.attestation_service.clone()),
+                policy_history: policy_history.clone().into_inner(),
+                audit_log: audit_log.clone().into_inner(),
+                actor: "policy-bundle",
+            }));
+        }
+
+        #[cfg(feature = "policy-watch")]
+        if let Some(policy_watch_config) = &self.policy_watch_config {
+            let policy_watcher = PolicyWatcher::new(policy_watch_config)?;
+            tokio::spawn(policy_watcher.run(PolicyTargets {
+                policy_engine: policy_engine.clone(),
+                #[cfg(feature = "as")]
+                attestation_service: Some(self.attestation_service.clone()),
+                policy_history: policy_history.clone().into_inner(),
+                audit_log: audit_log.clone().into_inner(),
+                actor: "policy-watch",
+            }));
+        }
+
         let user_public_key = match self.insecure_api {
             true => None,
             false => match &self.user_public_key {
@@ -280,22 +486,80 @@ impl ApiServer {
 
         let insecure_api = self.insecure_api;
 
+        #[cfg(feature = "grpc")]
+        let grpc_server = match &self.grpc_server_config {
+            Some(grpc_server_config) => {
+                let tls = match self.insecure {
+                    true => None,
+                    false => Some(grpc::tls_config(
+                        self.certificate
+                            .as_deref()
+                            .ok_or_else(|| anyhow!("Missing certificate"))?,
+                        self.private_key
+                            .as_deref()
+                            .ok_or_else(|| anyhow!("Missing private key"))?,
+                        self.client_ca_certificate.as_deref(),
+                    )?),
+                };
+                let service = KbsGrpcService {
+                    #[cfg(feature = "as")]
+                    limits: self.session_limits.clone(),
+                    #[cfg(feature = "as")]
+                    sessions: sessions.clone().into_inner(),
+                    #[cfg(feature = "as")]
+                    attestation_service: self.attestation_service.clone(),
+                    #[cfg(feature = "as")]
+                    nonce_service: nonce_service.clone().into_inner(),
+                    #[cfg(feature = "resource")]
+                    repository: repository.clone(),
+                    #[cfg(feature = "resource")]
+                    token_verifier: token_verifier.clone(),
+                    #[cfg(all(feature = "resource", feature = "policy"))]
+                    policy_engine: policy_engine.clone(),
+                    audit_log: audit_log.clone().into_inner(),
+                };
+                Some(service.serve(grpc_server_config, tls))
+            }
+            None => None,
+        };
+
         let http_server = HttpServer::new(move || {
             #[allow(unused_mut)]
             let mut server_app = App::new()
                 .wrap(middleware::Logger::default())
-                .app_data(web::Data::new(http_timeout))
+                .wrap_fn(telemetry::trace_request)
+                .wrap_fn(audit::audit_admin_request)
+                .app_data(web::Data::clone(&audit_log))
                 .app_data(web::Data::new(user_public_key.clone()))
-                .app_data(web::Data::new(insecure_api));
+                .app_data(web::Data::new(insecure_api))
+                .service(web::resource("/metrics").route(web::get().to(http::metrics)));
+
+            #[cfg(any(feature = "as", feature = "policy"))]
+            {
+                server_app = server_app
+                    .app_data(web::Data::clone(&policy_history))
+                    .app_data(web::Data::clone(&policy_verifier));
+            }
 
             cfg_if::cfg_if! {
                 if #[cfg(feature = "as")] {
                     server_app = server_app.app_data(web::Data::clone(&sessions))
-                    .app_data(web::Data::clone(&attestation_service)).service(web::resource(kbs_path!("auth")).route(web::post().to(http::auth)))
+                    .app_data(web::Data::clone(&session_limits))
+                    .app_data(web::Data::clone(&attestation_service))
+                    .app_data(web::Data::clone(&nonce_service)).service(web::resource(kbs_path!("auth")).route(web::post().to(http::auth)))
                     .service(web::resource(kbs_path!("attest")).route(web::post().to(http::attest)))
                     .service(
                         web::resource(kbs_path!("attestation-policy"))
                             .route(web::post().to(http::attestation_policy)),
+                    )
+                    .service(
+                        web::resource(kbs_path!("admin/policy-versions/attestation/{policy_id}"))
+                            .route(web::get().to(http::list_policy_versions))
+                            .route(web::post().to(http::rollback_attestation_policy)),
+                    )
+                    .service(
+                        web::resource(kbs_path!("admin/policy-versions/attestation/{policy_id}/{version}"))
+                            .route(web::get().to(http::get_policy_version)),
                     );
             }}
             cfg_if::cfg_if! {
@@ -308,7 +572,35 @@ impl ApiServer {
                             kbs_path!("resource/{type}/{tag}"),
                         ])
                         .route(web::get().to(http::get_resource))
-                        .route(web::post().to(http::set_resource)),
+                        .route(web::head().to(http::resource_metadata))
+                        .route(web::post().to(http::set_resource))
+                        .route(web::delete().to(http::delete_resource)),
+                    )
+                    .service(
+                        web::resource(kbs_path!("admin/resources"))
+                            .route(web::get().to(http::list_resources)),
+                    )
+                    .service(
+                        web::resource(kbs_path!("admin/resources/import"))
+                            .app_data(web::PayloadConfig::new(MAX_RESOURCE_BUNDLE_SIZE))
+                            .route(web::post().to(http::import_resources)),
+                    )
+                    .service(
+                        web::resource(kbs_path!("admin/resources/export"))
+                            .route(web::post().to(http::export_resources)),
+                    )
+                    .service(
+                        web::resource(kbs_path!("admin/resource-cache"))
+                            .route(web::get().to(http::resource_cache_stats))
+                            .route(web::delete().to(http::purge_resource_cache)),
+                    )
+                    .service(
+                        web::resource([
+                            kbs_path!("admin/resource-versions/{repository}/{type}/{tag}"),
+                            kbs_path!("admin/resource-versions/{type}/{tag}"),
+                        ])
+                        .route(web::get().to(http::list_resource_versions))
+                        .route(web::post().to(http::rollback_resource)),
                     );
                 }
             }
@@ -317,30 +609,59 @@ impl ApiServer {
                     server_app = server_app.app_data(web::Data::new(policy_engine.clone()))
                     .service(
                         web::resource(kbs_path!("resource-policy")).route(web::post().to(http::resource_policy)),
+                    )
+                    .service(
+                        web::resource(kbs_path!("admin/resource-policy/dry-run"))
+                            .route(web::post().to(http::resource_policy_dry_run)),
+                    )
+                    .service(
+                        web::resource(kbs_path!("admin/policy-versions/resource"))
+                            .route(web::get().to(http::list_policy_versions))
+                            .route(web::post().to(http::rollback_resource_policy)),
+                    )
+                    .service(
+                        web::resource(kbs_path!("admin/policy-versions/resource/{version}"))
+                            .route(web::get().to(http::get_policy_version)),
+                    )
+                    .service(
+                        web::resource(kbs_path!("admin/policy-versions/repository/{repository_policy_id}"))
+                            .route(web::get().to(http::list_policy_versions))
+                            .route(web::post().to(http::rollback_resource_policy)),
+                    )
+                    .service(
+                        web::resource(kbs_path!("admin/policy-versions/repository/{repository_policy_id}/{version}"))
+                            .route(web::get().to(http::get_policy_version)),
                     );
                 }
             }
             server_app
         });
 
-        if !self.insecure {
-            let tls_server = {
-                cfg_if::cfg_if! {
-                    if #[cfg(feature = "openssl")] {
-                        http_server.bind_openssl(&self.sockets[..], self.tls_config()?)?
-                    } else {
-                        http_server.bind_rustls(&self.sockets[..], self.tls_config()?)?
-                    }
-                }
-            };
+        #[cfg(feature = "as")]
+        let http_server = http_server.on_connect(nonce::record_client_identity);
 
-            tls_server.run().await.map_err(anyhow::Error::from)
+        let http_server = if !self.insecure {
+            cfg_if::cfg_if! {
+                if #[cfg(feature = "openssl")] {
+                    http_server.bind_openssl(&self.sockets[..], self.tls_config()?)?
+                } else {
+                    http_server.bind_rustls(&self.sockets[..], self.tls_config()?)?
+                }
+            }
         } else {
-            http_server
-                .bind(&self.sockets[..])?
-                .run()
-                .await
-                .map_err(anyhow::Error::from)
+            http_server.bind(&self.sockets[..])?
+        };
+        let http_server = async { http_server.run().await.map_err(anyhow::Error::from) };
+
+        #[cfg(feature = "grpc")]
+        if let Some(grpc_server) = grpc_server {
+            // The gRPC server is stopped with the HTTP one, e.g. on SIGTERM.
+            return tokio::select! {
+                result = http_server => result,
+                result = grpc_server => result,
+            };
         }
+
+        http_server.await
     }
 }
@@ -0,0 +1,210 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Prometheus metrics of the KBS, exposed at `/metrics`. They are registered
+//! in the default registry, which also holds the metrics of a built-in AS.
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, Encoder, HistogramVec, IntCounterVec,
+    TextEncoder,
+};
+#[cfg(feature = "as")]
+use prometheus::{register_int_gauge_vec, IntGaugeVec};
+use std::time::Instant;
+
+#[cfg(feature = "resource")]
+use crate::http::Error;
+#[cfg(feature = "as")]
+use crate::session::SessionCount;
+
+#[cfg(feature = "as")]
+lazy_static! {
+    static ref ATTESTATIONS: IntCounterVec = register_int_counter_vec!(
+        "kbs_attestations_total",
+        "Attestations of the RCAR handshake, by TEE type and outcome.",
+        &["tee", "outcome"]
+    )
+    .unwrap();
+    static ref ATTESTATION_DURATION: HistogramVec = register_histogram_vec!(
+        "kbs_attestation_duration_seconds",
+        "Duration of the attestations of the RCAR handshake, by TEE type.",
+        &["tee"]
+    )
+    .unwrap();
+    static ref SESSIONS: IntGaugeVec = register_int_gauge_vec!(
+        "kbs_sessions",
+        "Sessions of the RCAR handshake, by state.",
+        &["state"]
+    )
+    .unwrap();
+}
+
+#[cfg(feature = "resource")]
+lazy_static! {
+    static ref RESOURCE_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "kbs_resource_requests_total",
+        "Resource requests, by repository and outcome.",
+        &["repository", "outcome"]
+    )
+    .unwrap();
+    static ref RESOURCE_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "kbs_resource_request_duration_seconds",
+        "Duration of the resource requests, by repository.",
+        &["repository"]
+    )
+    .unwrap();
+}
+
+#[cfg(all(feature = "resource", feature = "policy"))]
+lazy_static! {
+    static ref POLICY_DENIALS: IntCounterVec = register_int_counter_vec!(
+        "kbs_policy_denials_total",
+        "Resource requests denied by the resource policy, by repository.",
+        &["repository"]
+    )
+    .unwrap();
+}
+
+/// Register the metrics, so that they are exposed before they are observed.
+pub(crate) fn register() {
+    #[cfg(feature = "as")]
+    {
+        lazy_static::initialize(&ATTESTATIONS);
+        lazy_static::initialize(&ATTESTATION_DURATION);
+        lazy_static::initialize(&SESSIONS);
+    }
+    #[cfg(feature = "resource")]
+    {
+        lazy_static::initialize(&RESOURCE_REQUESTS);
+        lazy_static::initialize(&RESOURCE_REQUEST_DURATION);
+    }
+    #[cfg(all(feature = "resource", feature = "policy"))]
+    lazy_static::initialize(&POLICY_DENIALS);
+}
+
+/// Observe the attestation of the `tee` evidence started at `start`.
+#[cfg(feature = "as")]
+pub(crate) fn observe_attestation<T, E>(
+    tee: kbs_types::Tee,
+    start: Instant,
+    result: &Result<T, E>,
+) {
+    let tee = crate::attestation::tee_name(tee);
+    let outcome = match result {
+        Ok(_) => "success",
+        Err(_) => "failure",
+    };
+    ATTESTATIONS.with_label_values(&[&tee, outcome]).inc();
+    ATTESTATION_DURATION
+        .with_label_values(&[&tee])
+        .observe(start.elapsed().as_secs_f64());
+}
+
+/// Observe the request of a resource of `repository` started at `start`.
+/// The outcome is `released`, `denied` by the resource policy, or `failed`.
+#[cfg(feature = "resource")]
+pub(crate) fn observe_resource_request<T>(
+    repository: &str,
+    start: Instant,
+    result: &Result<T, Error>,
+) {
+    let outcome = match result {
+        Ok(_) => "released",
+        Err(Error::PolicyReject(_)) => "denied",
+        Err(_) => "failed",
+    };
+    RESOURCE_REQUESTS
+        .with_label_values(&[repository, outcome])
+        .inc();
+    RESOURCE_REQUEST_DURATION
+        .with_label_values(&[repository])
+        .observe(start.elapsed().as_secs_f64());
+}
+
+/// Observe the denial of a resource of `repository` by the resource policy.
+#[cfg(all(feature = "resource", feature = "policy"))]
+pub(crate) fn observe_policy_denial(repository: &str) {
+    POLICY_DENIALS.with_label_values(&[repository]).inc();
+}
+
+/// Set the gauges of the sessions to their `count`.
+#[cfg(feature = "as")]
+pub(crate) fn observe_sessions(count: &SessionCount) {
+    SESSIONS.with_label_values(&["authed"]).set(count.authed);
+    SESSIONS
+        .with_label_values(&["attested"])
+        .set(count.attested);
+    SESSIONS.with_label_values(&["expired"]).set(count.expired);
+}
+
+/// The metrics of the default registry in the Prometheus text format.
+pub(crate) fn gather() -> Result<String> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "resource")]
+    #[test]
+    fn test_resource_metrics() {
+        register();
+        let requests = |outcome: &str| {
+            RESOURCE_REQUESTS
+                .with_label_values(&["test-metrics", outcome])
+                .get()
+        };
+
+        observe_resource_request("test-metrics", Instant::now(), &Ok(()));
+        observe_resource_request::<()>(
+            "test-metrics",
+            Instant::now(),
+            &Err(Error::PolicyReject(crate::http::PolicyDenial {
+                correlation_id: "42".into(),
+                message: None,
+                advice: vec![],
+            })),
+        );
+        observe_resource_request::<()>(
+            "test-metrics",
+            Instant::now(),
+            &Err(Error::ReadSecretFailed("test".into())),
+        );
+        observe_resource_request("test-metrics", Instant::now(), &Ok(()));
+        assert_eq!(requests("released"), 2);
+        assert_eq!(requests("denied"), 1);
+        assert_eq!(requests("failed"), 1);
+
+        let metrics = gather().unwrap();
+        assert!(metrics.contains(
+            r#"kbs_resource_requests_total{outcome="released",repository="test-metrics"} 2"#
+        ));
+        assert!(metrics.contains(
+            r#"kbs_resource_request_duration_seconds_count{repository="test-metrics"} 4"#
+        ));
+    }
+
+    #[cfg(feature = "as")]
+    #[test]
+    fn test_session_metrics() {
+        register();
+        observe_sessions(&SessionCount {
+            authed: 1,
+            attested: 1,
+            expired: 1,
+        });
+        assert_eq!(SESSIONS.with_label_values(&["authed"]).get(), 1);
+        assert_eq!(SESSIONS.with_label_values(&["attested"]).get(), 1);
+        assert_eq!(SESSIONS.with_label_values(&["expired"]).get(), 1);
+
+        observe_attestation::<_, ()>(kbs_types::Tee::Sample, Instant::now(), &Ok(()));
+        let metrics = gather().unwrap();
+        assert!(metrics.contains(r#"kbs_attestations_total{outcome="success",tee="sample"}"#));
+    }
+}
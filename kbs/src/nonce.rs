@@ -0,0 +1,258 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Nonces of the challenges returned by `/auth`, checked by `/attest`. The
+//! issued nonces are kept in the sessions, so that any KBS sharing the
+//! session store checks them alike.
+
+use std::any::Any;
+use std::time::Duration;
+
+use actix_web::dev::Extensions;
+use anyhow::{bail, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use time::OffsetDateTime;
+
+/// Shortest nonce, in bytes, that can be configured.
+const MIN_NONCE_LENGTH: usize = 16;
+
+fn default_length() -> usize {
+    32
+}
+
+fn default_single_use() -> bool {
+    true
+}
+
+/// Freshness policy of the nonces.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct NonceConfig {
+    /// Length of the nonces in bytes, before base64 encoding.
+    #[serde(default = "default_length")]
+    pub length: usize,
+
+    /// Seconds during which a nonce is accepted by `/attest`. Defaults to the
+    /// session timeout.
+    #[serde(default)]
+    pub lifetime_secs: Option<u64>,
+
+    /// Reject the evidence bound to a nonce that was already used.
+    #[serde(default = "default_single_use")]
+    pub single_use: bool,
+
+    /// Only accept a nonce on the TLS connection of the client certificate
+    /// it was issued to.
+    #[serde(default)]
+    pub bind_client_identity: bool,
+}
+
+impl Default for NonceConfig {
+    fn default() -> Self {
+        Self {
+            length: default_length(),
+            lifetime_secs: None,
+            single_use: default_single_use(),
+            bind_client_identity: false,
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub(crate) enum NonceError {
+    #[error("the nonce is stale")]
+    Stale,
+
+    #[error("the nonce was already used")]
+    Reused,
+
+    #[error("the nonce was issued to another client")]
+    IdentityMismatch,
+
+    #[error("no client certificate to bind the nonce to")]
+    MissingIdentity,
+}
+
+/// The nonce of the challenge of a session, as issued.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub(crate) struct IssuedNonce {
+    #[serde(with = "time::serde::rfc3339")]
+    issued_at: OffsetDateTime,
+    client_identity: Option<String>,
+    used: bool,
+}
+
+/// SHA-256 fingerprint of the TLS certificate of the client, recorded for
+/// each connection by [`record_client_identity`].
+#[derive(Clone)]
+pub(crate) struct ClientIdentity(pub String);
+
+/// `on_connect` callback of the HTTP server recording the [`ClientIdentity`]
+/// of the TLS connections authenticated with a client certificate.
+#[allow(unused_variables)]
+pub(crate) fn record_client_identity(connection: &dyn Any, extensions: &mut Extensions) {
+    let fingerprint = |der: &[u8]| ClientIdentity(hex::encode(Sha256::digest(der)));
+
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "openssl")] {
+            use actix_tls::accept::openssl::TlsStream;
+            use actix_web::rt::net::TcpStream;
+
+            if let Some(stream) = connection.downcast_ref::<TlsStream<TcpStream>>() {
+                if let Some(der) = stream
+                    .ssl()
+                    .peer_certificate()
+                    .and_then(|cert| cert.to_der().ok())
+                {
+                    extensions.insert(fingerprint(&der));
+                }
+            }
+        } else if #[cfg(feature = "rustls")] {
+            use actix_tls::accept::rustls_0_20::TlsStream;
+            use actix_web::rt::net::TcpStream;
+
+            if let Some(stream) = connection.downcast_ref::<TlsStream<TcpStream>>() {
+                if let Some(cert) = stream
+                    .get_ref()
+                    .1
+                    .peer_certificates()
+                    .and_then(|certs| certs.first())
+                {
+                    extensions.insert(fingerprint(&cert.0));
+                }
+            }
+        }
+    }
+}
+
+/// Issues the nonces and enforces their freshness policy.
+pub(crate) struct NonceService {
+    length: usize,
+    lifetime: Duration,
+    single_use: bool,
+    bind_client_identity: bool,
+}
+
+impl NonceService {
+    pub fn new(config: &NonceConfig, session_timeout: Duration) -> Result<Self> {
+        if config.length < MIN_NONCE_LENGTH {
+            bail!("Nonce length must be at least {MIN_NONCE_LENGTH} bytes");
+        }
+
+        let lifetime = config
+            .lifetime_secs
+            .map(Duration::from_secs)
+            .unwrap_or(session_timeout);
+        Ok(Self {
+            length: config.length,
+            lifetime,
+            single_use: config.single_use,
+            bind_client_identity: config.bind_client_identity,
+        })
+    }
+
+    /// Generate a random nonce, to be [`issued`](Self::issue) once bound to
+    /// a challenge.
+    pub fn generate(&self) -> Result<String> {
+        let mut nonce = vec![0; self.length];
+        thread_rng().try_fill(&mut nonce[..])?;
+        Ok(STANDARD.encode(nonce))
+    }
+
+    /// Issue the nonce of a challenge returned to the client, to be kept in
+    /// its session.
+    pub fn issue(
+        &self,
+        client_identity: Option<&str>,
+    ) -> std::result::Result<IssuedNonce, NonceError> {
+        if self.bind_client_identity && client_identity.is_none() {
+            return Err(NonceError::MissingIdentity);
+        }
+
+        Ok(IssuedNonce {
+            issued_at: OffsetDateTime::now_utc(),
+            client_identity: client_identity.map(str::to_string),
+            used: false,
+        })
+    }
+
+    /// Check the freshness of the nonce the evidence of a client is bound to,
+    /// and mark it as used.
+    pub fn consume(
+        &self,
+        issued: &mut IssuedNonce,
+        client_identity: Option<&str>,
+    ) -> std::result::Result<(), NonceError> {
+        if self.bind_client_identity && issued.client_identity.as_deref() != client_identity {
+            return Err(NonceError::IdentityMismatch);
+        }
+
+        if issued.used {
+            return Err(NonceError::Reused);
+        }
+
+        if OffsetDateTime::now_utc() - issued.issued_at >= self.lifetime {
+            return Err(NonceError::Stale);
+        }
+
+        issued.used = self.single_use;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn freshness_policy() {
+        let config = NonceConfig {
+            length: 8,
+            ..Default::default()
+        };
+        assert!(NonceService::new(&config, Duration::from_secs(300)).is_err());
+
+        let service = NonceService::new(&NonceConfig::default(), Duration::from_secs(300)).unwrap();
+        let nonce = service.generate().unwrap();
+        assert_eq!(STANDARD.decode(&nonce).unwrap().len(), 32);
+
+        let mut issued = service.issue(None).unwrap();
+        service.consume(&mut issued, None).unwrap();
+        assert_eq!(service.consume(&mut issued, None), Err(NonceError::Reused));
+
+        // The issued nonce is checked alike once kept in a session store.
+        let issued = service.issue(None).unwrap();
+        let mut stored: IssuedNonce =
+            serde_json::from_str(&serde_json::to_string(&issued).unwrap()).unwrap();
+        assert_eq!(stored, issued);
+        service.consume(&mut stored, None).unwrap();
+
+        let config = NonceConfig {
+            lifetime_secs: Some(0),
+            single_use: false,
+            ..Default::default()
+        };
+        let service = NonceService::new(&config, Duration::from_secs(300)).unwrap();
+        let mut issued = service.issue(None).unwrap();
+        assert_eq!(service.consume(&mut issued, None), Err(NonceError::Stale));
+
+        let config = NonceConfig {
+            single_use: false,
+            bind_client_identity: true,
+            ..Default::default()
+        };
+        let service = NonceService::new(&config, Duration::from_secs(300)).unwrap();
+        assert_eq!(service.issue(None), Err(NonceError::MissingIdentity));
+        let mut issued = service.issue(Some("client-a")).unwrap();
+        assert_eq!(
+            service.consume(&mut issued, Some("client-b")),
+            Err(NonceError::IdentityMismatch)
+        );
+        service.consume(&mut issued, Some("client-a")).unwrap();
+        service.consume(&mut issued, Some("client-a")).unwrap();
+    }
+}
@@ -0,0 +1,717 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Policy bundles pulled from an OCI registry, so that the policies can be
+//! published as artifacts, e.g. by `oras push`, instead of being set
+//! through the admin API.
+//!
+//! The KBS polls the manifest of the bundle, and when it changed, verifies
+//! its cosign signature, i.e. the simple signing payload of tag
+//! `sha256-<digest>.sig` signed by the cosign key, before it applies the
+//! layers of the bundle, by their `org.opencontainers.image.title`:
+//! - `resource.<ext>`: the default resource policy
+//! - `repository/<policy id>.<ext>`: a repository resource policy
+//! - `attestation/<policy id>.<ext>`: an attestation policy
+//! - `data/<name>.json`: a data document of the resource policies
+//!
+//! Every applied policy is kept as a new version of the policy history, with
+//! the version of the bundle, i.e. the `VERSION_ANNOTATION` of its signed
+//! manifest, as its signed version. A bundle older than the last applied
+//! bundle, or than any of its policies in the history, is refused, so that
+//! an older signed bundle tagged again cannot roll the policies back.
+
+use crate::policy_history::PolicyKind;
+use crate::policy_targets::PolicyTargets;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use log::{info, warn};
+use openssl::{
+    hash::MessageDigest,
+    pkey::{PKey, Public},
+    sign::Verifier,
+};
+use reqwest::header::{ACCEPT, WWW_AUTHENTICATE};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+const DEFAULT_POLICY_BUNDLE_INTERVAL: u64 = 300;
+
+const MANIFEST_MEDIA_TYPES: &str =
+    "application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json";
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+const SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+const VERSION_ANNOTATION: &str = "io.confidentialcontainers.kbs.policy-bundle.version";
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PolicyBundleConfig {
+    /// Reference of the bundle, e.g. `registry.example.com/kbs/policies:prod`.
+    pub reference: String,
+
+    /// Path to the cosign public key (PEM) the bundle is signed with.
+    pub cosign_public_key: PathBuf,
+
+    /// Seconds between two pulls. Defaults to 5 minutes.
+    #[serde(default = "default_policy_bundle_interval")]
+    pub interval: u64,
+
+    /// Credentials of the registry, if it requires them.
+    pub username: Option<String>,
+    pub password: Option<String>,
+
+    /// Pull from the registry over plain HTTP, only meant for testing.
+    #[serde(default)]
+    pub insecure_http: bool,
+}
+
+fn default_policy_bundle_interval() -> u64 {
+    DEFAULT_POLICY_BUNDLE_INTERVAL
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+impl Manifest {
+    /// The version of the bundle, from its `VERSION_ANNOTATION`.
+    fn version(&self) -> Result<u64> {
+        self.annotations
+            .get(VERSION_ANNOTATION)
+            .ok_or_else(|| anyhow!("the policy bundle has no {VERSION_ANNOTATION} annotation"))?
+            .parse()
+            .with_context(|| format!("illegal {VERSION_ANNOTATION} annotation"))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    digest: String,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+/// The cosign simple signing payload.
+#[derive(Deserialize)]
+struct SimpleSigning {
+    critical: Critical,
+}
+
+#[derive(Deserialize)]
+struct Critical {
+    image: Image,
+}
+
+#[derive(Deserialize)]
+struct Image {
+    #[serde(rename = "docker-manifest-digest")]
+    docker_manifest_digest: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// What a layer of the bundle is applied to.
+#[derive(Debug, PartialEq)]
+enum Target {
+    Resource,
+    Repository(String),
+    Attestation(String),
+    Data(String),
+}
+
+impl Target {
+    fn from_title(title: &str) -> Option<Self> {
+        let (stem, _extension) = title.rsplit_once('.')?;
+        match stem.split_once('/') {
+            None if stem == "resource" => Some(Self::Resource),
+            Some(("repository", policy_id)) => Some(Self::Repository(policy_id.to_string())),
+            Some(("attestation", policy_id)) => Some(Self::Attestation(policy_id.to_string())),
+            Some(("data", name)) => Some(Self::Data(name.to_string())),
+            _ => None,
+        }
+    }
+}
+
+pub struct PolicyBundle {
+    client: reqwest::Client,
+
+    /// e.g. `https://registry.example.com`.
+    registry_url: String,
+    repository: String,
+
+    /// Tag or digest of the bundle.
+    reference: String,
+
+    cosign_public_key: PKey<Public>,
+    credentials: Option<(String, String)>,
+    interval: Duration,
+
+    /// Bearer token of the registry.
+    token: Mutex<Option<String>>,
+
+    /// Digest of the manifest and version of the last applied bundle.
+    applied: Mutex<Option<(String, u64)>>,
+}
+
+/// Split `reference` into its registry, repository and tag or digest.
+fn parse_reference(reference: &str) -> Result<(String, String, String)> {
+    let (registry, rest) = reference
+        .split_once('/')
+        .ok_or_else(|| anyhow!("policy bundle reference {reference} has no registry"))?;
+    let (repository, tag) = match rest.split_once('@') {
+        Some((repository, digest)) => (repository, Some(digest)),
+        None => (rest, None),
+    };
+    let (repository, tag) = match repository.rsplit_once(':') {
+        Some((repository, t)) if !t.contains('/') => (repository, tag.or(Some(t))),
+        _ => (repository, tag),
+    };
+    if registry.is_empty() || repository.is_empty() {
+        bail!("illegal policy bundle reference {reference}");
+    }
+
+    Ok((
+        registry.to_string(),
+        repository.to_string(),
+        tag.unwrap_or("latest").to_string(),
+    ))
+}
+
+/// Parse the parameters of a Bearer `WWW-Authenticate` challenge.
+fn parse_challenge(challenge: &str) -> Result<HashMap<String, String>> {
+    let mut rest = challenge
+        .strip_prefix("Bearer ")
+        .ok_or_else(|| anyhow!("unsupported registry authentication {challenge}"))?;
+
+    let mut params = HashMap::new();
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim();
+        let (value, tail) = match value.strip_prefix('"') {
+            Some(quoted) => quoted
+                .split_once('"')
+                .ok_or_else(|| anyhow!("illegal registry authentication {challenge}"))?,
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        params.insert(key.to_string(), value.to_string());
+        rest = tail;
+    }
+
+    Ok(params)
+}
+
+/// Verify that the cosign `signature`, base64 encoded, of the simple signing
+/// `payload` is made by `key`, and that the payload signs the manifest
+/// `manifest_digest`.
+fn verify_signature(
+    key: &PKey<Public>,
+    manifest_digest: &str,
+    payload: &[u8],
+    signature: &str,
+) -> Result<()> {
+    let signature = STANDARD
+        .decode(signature)
+        .context("decode cosign signature")?;
+    let mut verifier = Verifier::new(MessageDigest::sha256(), key)?;
+    if !verifier.verify_oneshot(&signature, payload)? {
+        bail!("cosign signature is not made by the cosign key");
+    }
+
+    let payload: SimpleSigning =
+        serde_json::from_slice(payload).context("parse cosign simple signing payload")?;
+    if payload.critical.image.docker_manifest_digest != manifest_digest {
+        bail!(
+            "cosign signature is of manifest {} instead of {manifest_digest}",
+            payload.critical.image.docker_manifest_digest
+        );
+    }
+
+    Ok(())
+}
+
+fn sha256_digest(content: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(Sha256::digest(content)))
+}
+
+impl PolicyBundle {
+    pub async fn new(config: &PolicyBundleConfig) -> Result<Self> {
+        if config.interval == 0 {
+            bail!("policy bundle interval must not be 0");
+        }
+
+        let (registry, repository, reference) = parse_reference(&config.reference)?;
+        let scheme = if config.insecure_http {
+            "http"
+        } else {
+            "https"
+        };
+
+        let cosign_public_key = tokio::fs::read(&config.cosign_public_key)
+            .await
+            .context("read cosign public key")?;
+        let cosign_public_key =
+            PKey::public_key_from_pem(&cosign_public_key).context("parse cosign public key")?;
+
+        let credentials = match (&config.username, &config.password) {
+            (Some(username), Some(password)) => Some((username.clone(), password.clone())),
+            (None, None) => None,
+            _ => bail!("both the username and the password of the registry must be given"),
+        };
+
+        Ok(Self {
+            client: reqwest::Client::new(),
+            registry_url: format!("{scheme}://{registry}"),
+            repository,
+            reference,
+            cosign_public_key,
+            credentials,
+            interval: Duration::from_secs(config.interval),
+            token: Mutex::new(None),
+            applied: Mutex::new(None),
+        })
+    }
+
+    /// Get a bearer token from the registry, for the `challenge` of its
+    /// `WWW-Authenticate` header.
+    async fn authenticate(&self, challenge: &str) -> Result<String> {
+        let params = parse_challenge(challenge)?;
+        let realm = params
+            .get("realm")
+            .ok_or_else(|| anyhow!("registry authentication has no realm"))?;
+        let query: Vec<_> = ["service", "scope"]
+            .into_iter()
+            .filter_map(|key| Some((key, params.get(key)?)))
+            .collect();
+
+        let mut request = self.client.get(realm).query(&query);
+        if let Some((username, password)) = &self.credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+        let response: TokenResponse = request
+            .send()
+            .await?
+            .error_for_status()
+            .context("get registry token")?
+            .json()
+            .await?;
+
+        response
+            .token
+            .or(response.access_token)
+            .ok_or_else(|| anyhow!("registry returned no token"))
+    }
+
+    /// Get `<kind>/<reference>` of the repository of the bundle, i.e. a
+    /// manifest or a blob.
+    async fn get(&self, kind: &str, reference: &str) -> Result<Vec<u8>> {
+        let url = format!(
+            "{}/v2/{}/{kind}/{reference}",
+            self.registry_url, self.repository
+        );
+
+        let mut authenticated = false;
+        loop {
+            let mut request = self.client.get(&url).header(ACCEPT, MANIFEST_MEDIA_TYPES);
+            match (&*self.token.lock().await, &self.credentials) {
+                (Some(token), _) => request = request.bearer_auth(token),
+                (None, Some((username, password))) => {
+                    request = request.basic_auth(username, Some(password))
+                }
+                (None, None) => {}
+            }
+
+            let response = request.send().await?;
+            if response.status() == StatusCode::UNAUTHORIZED && !authenticated {
+                let challenge = response
+                    .headers()
+                    .get(WWW_AUTHENTICATE)
+                    .and_then(|challenge| challenge.to_str().ok())
+                    .ok_or_else(|| anyhow!("registry requires an unknown authentication"))?;
+                *self.token.lock().await = Some(self.authenticate(challenge).await?);
+                authenticated = true;
+                continue;
+            }
+
+            let response = response
+                .error_for_status()
+                .with_context(|| format!("get {kind} {reference}"))?;
+            return Ok(response.bytes().await?.to_vec());
+        }
+    }
+
+    /// Get the blob `digest`, and check its digest.
+    async fn get_blob(&self, digest: &str) -> Result<Vec<u8>> {
+        let blob = self.get("blobs", digest).await?;
+        if sha256_digest(&blob) != digest {
+            bail!("blob {digest} does not match its digest");
+        }
+        Ok(blob)
+    }
+
+    /// Verify that one of the cosign signatures of the manifest
+    /// `manifest_digest` is made by the cosign key.
+    async fn verify(&self, manifest_digest: &str) -> Result<()> {
+        let signature_tag = manifest_digest.replacen(':', "-", 1) + ".sig";
+        let signatures = self
+            .get("manifests", &signature_tag)
+            .await
+            .context("get cosign signatures of the policy bundle")?;
+        let signatures: Manifest =
+            serde_json::from_slice(&signatures).context("parse cosign signatures")?;
+
+        for layer in &signatures.layers {
+            let Some(signature) = layer.annotations.get(SIGNATURE_ANNOTATION) else {
+                continue;
+            };
+            let payload = self.get_blob(&layer.digest).await?;
+            match verify_signature(
+                &self.cosign_public_key,
+                manifest_digest,
+                &payload,
+                signature,
+            ) {
+                Ok(()) => return Ok(()),
+                Err(e) => warn!("Ignoring a cosign signature of the policy bundle: {e:#}"),
+            }
+        }
+
+        bail!("the policy bundle is not signed by the cosign key")
+    }
+
+    /// Pull the bundle, if it changed since it was last applied, and return
+    /// the digest of its manifest, its version and its layers, by their
+    /// title.
+    async fn pull(&self) -> Result<Option<(String, u64, Vec<(String, Vec<u8>)>)>> {
+        let manifest = self.get("manifests", &self.reference).await?;
+        let manifest_digest = sha256_digest(&manifest);
+        let applied_version = match &*self.applied.lock().await {
+            Some((digest, _)) if *digest == manifest_digest => return Ok(None),
+            Some((_, version)) => Some(*version),
+            None => None,
+        };
+
+        self.verify(&manifest_digest).await?;
+
+        let manifest: Manifest =
+            serde_json::from_slice(&manifest).context("parse policy bundle manifest")?;
+        let version = manifest.version()?;
+        if let Some(applied_version) = applied_version {
+            if version <= applied_version {
+                bail!(
+                    "version {version} of the policy bundle is not newer than the applied version {applied_version}"
+                );
+            }
+        }
+
+        let mut layers = Vec::new();
+        for layer in manifest.layers {
+            let Some(title) = layer.annotations.get(TITLE_ANNOTATION) else {
+                warn!(
+                    "Ignoring layer {} of the policy bundle without a title",
+                    layer.digest
+                );
+                continue;
+            };
+            layers.push((title.clone(), self.get_blob(&layer.digest).await?));
+        }
+
+        Ok(Some((manifest_digest, version, layers)))
+    }
+
+    /// Apply the `layers` of the bundle `version`, by their title, and
+    /// return how many were applied. Nothing is applied if one of its
+    /// policies has a newer version in the policy history.
+    async fn apply(
+        &self,
+        targets: &PolicyTargets,
+        version: u64,
+        layers: Vec<(String, Vec<u8>)>,
+    ) -> Result<usize> {
+        let mut targeted = Vec::new();
+        for (title, content) in layers {
+            let Some(target) = Target::from_title(&title) else {
+                warn!("Ignoring layer {title} of the policy bundle");
+                continue;
+            };
+
+            let kind = match target {
+                Target::Resource => PolicyKind::Resource,
+                Target::Repository(policy_id) => PolicyKind::Repository(policy_id),
+                Target::Attestation(policy_id) => PolicyKind::Attestation(policy_id),
+                Target::Data(name) => {
+                    targeted.push((title, Err(name), content));
+                    continue;
+                }
+            };
+
+            // The same bundle is applied again when the KBS restarts.
+            if let Some(latest) = targets.policy_history.latest_signed_version(&kind).await? {
+                if version < latest {
+                    bail!(
+                        "version {version} of the policy bundle is older than the version {latest} of its policy {kind:?}"
+                    );
+                }
+            }
+            targeted.push((title, Ok(kind), content));
+        }
+
+        let mut applied = 0;
+        for (title, target, content) in targeted {
+            let kind = match target {
+                Ok(kind) => kind,
+                Err(name) => {
+                    let document = serde_json::from_slice(&content)
+                        .with_context(|| format!("parse data document {title}"))?;
+                    targets.policy_engine.set_data_document(&name, document)?;
+                    applied += 1;
+                    continue;
+                }
+            };
+
+            if !targets
+                .set_policy(&kind, &URL_SAFE_NO_PAD.encode(&content), Some(version))
+                .await?
+            {
+                warn!("Ignoring layer {title} of the policy bundle without an attestation service");
+                continue;
+            }
+            applied += 1;
+        }
+
+        Ok(applied)
+    }
+
+    /// Pull and apply the bundle if it changed.
+    async fn sync(&self, targets: &PolicyTargets) -> Result<()> {
+        let Some((manifest_digest, version, layers)) = self.pull().await? else {
+            return Ok(());
+        };
+
+        let applied = self.apply(targets, version, layers).await?;
+        info!("Applied {applied} policies and data documents of version {version} of policy bundle {manifest_digest}");
+        *self.applied.lock().await = Some((manifest_digest, version));
+        Ok(())
+    }
+
+    /// Pull the bundle now, then every `interval`.
+    pub(crate) async fn run(self, targets: PolicyTargets) {
+        loop {
+            if let Err(e) = self.sync(&targets).await {
+                warn!("Failed to pull the policy bundle: {e:?}");
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditLog;
+    use crate::policy_engine::{PolicyEngine, PolicyEngineConfig, RequestContext};
+    use crate::policy_history::{PolicyHistory, PolicyHistoryConfig};
+    use openssl::{ec::EcGroup, ec::EcKey, nid::Nid, sign::Signer};
+    use serde_json::json;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_reference() {
+        let reference = parse_reference("registry.example.com:5000/kbs/policies:prod").unwrap();
+        assert_eq!(
+            reference,
+            (
+                "registry.example.com:5000".into(),
+                "kbs/policies".into(),
+                "prod".into()
+            )
+        );
+        let reference = parse_reference("ghcr.io/org/policies").unwrap();
+        assert_eq!(reference.2, "latest");
+        let reference = parse_reference("ghcr.io/org/policies:prod@sha256:abcd").unwrap();
+        assert_eq!(reference.1, "org/policies");
+        assert_eq!(reference.2, "sha256:abcd");
+        assert!(parse_reference("policies").is_err());
+    }
+
+    #[test]
+    fn test_parse_challenge() {
+        let params = parse_challenge(
+            r#"Bearer realm="https://ghcr.io/token",service="ghcr.io",scope="repository:org/policies:pull,push""#,
+        )
+        .unwrap();
+        assert_eq!(params["realm"], "https://ghcr.io/token");
+        assert_eq!(params["service"], "ghcr.io");
+        assert_eq!(params["scope"], "repository:org/policies:pull,push");
+        assert!(parse_challenge(r#"Basic realm="registry""#).is_err());
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let public_key = PKey::public_key_from_pem(&key.public_key_to_pem().unwrap()).unwrap();
+
+        let manifest_digest = sha256_digest(b"manifest");
+        let payload = json!({
+            "critical": {
+                "identity": { "docker-reference": "registry.example.com/kbs/policies" },
+                "image": { "docker-manifest-digest": manifest_digest },
+                "type": "cosign container image signature"
+            },
+            "optional": null
+        })
+        .to_string();
+        let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+        let signature = STANDARD.encode(signer.sign_oneshot_to_vec(payload.as_bytes()).unwrap());
+
+        verify_signature(
+            &public_key,
+            &manifest_digest,
+            payload.as_bytes(),
+            &signature,
+        )
+        .unwrap();
+        let other_digest = sha256_digest(b"other manifest");
+        assert!(
+            verify_signature(&public_key, &other_digest, payload.as_bytes(), &signature).is_err()
+        );
+        let tampered = payload.replace("cosign", "other");
+        assert!(verify_signature(
+            &public_key,
+            &manifest_digest,
+            tampered.as_bytes(),
+            &signature
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_manifest_version() {
+        let manifest: Manifest = serde_json::from_value(json!({
+            "layers": [],
+            "annotations": { (VERSION_ANNOTATION): "3" }
+        }))
+        .unwrap();
+        assert_eq!(manifest.version().unwrap(), 3);
+        let manifest: Manifest = serde_json::from_value(json!({ "layers": [] })).unwrap();
+        assert!(manifest.version().is_err());
+        let manifest: Manifest = serde_json::from_value(json!({
+            "annotations": { (VERSION_ANNOTATION): "latest" }
+        }))
+        .unwrap();
+        assert!(manifest.version().is_err());
+    }
+
+    #[test]
+    fn test_target_from_title() {
+        assert_eq!(Target::from_title("resource.rego"), Some(Target::Resource));
+        assert_eq!(
+            Target::from_title("repository/tenant-a.cel"),
+            Some(Target::Repository("tenant-a".into()))
+        );
+        assert_eq!(
+            Target::from_title("attestation/default.rego"),
+            Some(Target::Attestation("default".into()))
+        );
+        assert_eq!(
+            Target::from_title("data/allowlist.json"),
+            Some(Target::Data("allowlist".into()))
+        );
+        assert_eq!(Target::from_title("README.md"), None);
+    }
+
+    #[cfg(feature = "opa")]
+    #[tokio::test]
+    async fn test_apply() {
+        let dir = TempDir::new().unwrap();
+        let key_path = dir.path().join("cosign.pub");
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = EcKey::generate(&group).unwrap();
+        std::fs::write(&key_path, key.public_key_to_pem().unwrap()).unwrap();
+        let bundle = PolicyBundle::new(&PolicyBundleConfig {
+            reference: "registry.example.com/kbs/policies:prod".into(),
+            cosign_public_key: key_path,
+            interval: 300,
+            username: None,
+            password: None,
+            insecure_http: false,
+        })
+        .await
+        .unwrap();
+
+        let targets = PolicyTargets {
+            policy_engine: PolicyEngine::new(&PolicyEngineConfig {
+                policy_path: Some(dir.path().join("policy.rego")),
+                ..Default::default()
+            })
+            .await
+            .unwrap(),
+            #[cfg(feature = "as")]
+            attestation_service: None,
+            policy_history: Arc::new(
+                PolicyHistory::new(&PolicyHistoryConfig {
+                    dir_path: dir.path().join("history"),
+                })
+                .unwrap(),
+            ),
+            audit_log: Arc::new(AuditLog::new(None).await.unwrap()),
+            actor: "policy-bundle",
+        };
+
+        let policy = "package policy\ndefault allow = false\n\
+                      allow { input[\"tcb-status\"].productId == data.allowlist[_] }\n";
+        let layers = vec![
+            ("resource.rego".to_string(), policy.as_bytes().to_vec()),
+            ("data/allowlist.json".to_string(), br#"["Alice"]"#.to_vec()),
+            (
+                "attestation/default.rego".to_string(),
+                policy.as_bytes().to_vec(),
+            ),
+            ("README.md".to_string(), b"policies".to_vec()),
+        ];
+        assert_eq!(bundle.apply(&targets, 2, layers.clone()).await.unwrap(), 2);
+
+        let input = json!({ "tcb-status": { "productId": "Alice" } }).to_string();
+        let res = targets
+            .policy_engine
+            .evaluate("my_repo/key/1".into(), input, &RequestContext::default())
+            .await;
+        assert!(res.unwrap());
+        let versions = targets
+            .policy_history
+            .list(&PolicyKind::Resource)
+            .await
+            .unwrap();
+        assert_eq!(versions.len(), 1);
+
+        // An older bundle does not roll the policies back, the same one is
+        // applied again.
+        assert!(bundle.apply(&targets, 1, layers.clone()).await.is_err());
+        assert_eq!(bundle.apply(&targets, 2, layers).await.unwrap(), 2);
+        assert_eq!(
+            targets
+                .policy_history
+                .latest_signed_version(&PolicyKind::Resource)
+                .await
+                .unwrap(),
+            Some(2)
+        );
+    }
+}
@@ -0,0 +1,389 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::policy_engine::{
+    DataDocuments, PolicyDecision, PolicyDryRun, PolicyEngineInterface, RequestContext,
+    ResourcePolicyError,
+};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use base64::Engine;
+use cel_interpreter::{Context, Program, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Resource policy written as a CEL expression, which evaluates to whether
+/// the resource is allowed.
+#[derive(Clone)]
+pub struct Cel {
+    policy_path: PathBuf,
+    data: DataDocuments,
+}
+
+impl Cel {
+    pub fn new(policy_path: PathBuf, data: DataDocuments) -> Result<Self, ResourcePolicyError> {
+        std::fs::create_dir_all(policy_path.parent().unwrap())?;
+
+        if !policy_path.as_path().exists() {
+            let policy = std::include_str!("default_policy.cel").to_string();
+            fs::write(&policy_path, policy)?;
+        }
+
+        Ok(Self { policy_path, data })
+    }
+}
+
+/// Evaluate the CEL `policy` against the `resource_path`, the `input_claims`
+/// and the `request`, given the data documents.
+fn evaluate(
+    policy: &str,
+    data: &DataDocuments,
+    resource_path: String,
+    input_claims: &str,
+    request: &RequestContext,
+) -> Result<bool, ResourcePolicyError> {
+    let program = Program::compile(policy).map_err(|_| ResourcePolicyError::PolicyLoadError)?;
+
+    let segments: Vec<&str> = resource_path.split('/').collect();
+    let [repository, r#type, tag] = segments[..] else {
+        return Err(ResourcePolicyError::ResourcePathError);
+    };
+    let resource = HashMap::from([("repository", repository), ("type", r#type), ("tag", tag)]);
+    let input: serde_json::Value =
+        serde_json::from_str(input_claims).map_err(|_| ResourcePolicyError::InputError)?;
+
+    let mut context = Context::default();
+    context
+        .add_variable("resource", resource)
+        .map_err(|_| ResourcePolicyError::ResourcePathError)?;
+    context
+        .add_variable("input", input)
+        .map_err(|_| ResourcePolicyError::InputError)?;
+    context
+        .add_variable("resource_path", resource_path)
+        .map_err(|_| ResourcePolicyError::ResourcePathError)?;
+    context
+        .add_variable("data", &*data.get())
+        .map_err(|_| ResourcePolicyError::DataLoadError)?;
+    context
+        .add_variable("request", request)
+        .map_err(|_| ResourcePolicyError::InputError)?;
+
+    match program.execute(&context) {
+        Ok(Value::Bool(allowed)) => Ok(allowed),
+        Ok(value) => Err(anyhow!("policy evaluated to {value:?} instead of a boolean").into()),
+        Err(e) => Err(anyhow!("{e}").into()),
+    }
+}
+
+#[async_trait]
+impl PolicyEngineInterface for Cel {
+    async fn evaluate(
+        &self,
+        resource_path: String,
+        input_claims: String,
+        request: &RequestContext,
+    ) -> Result<bool, ResourcePolicyError> {
+        let policy = tokio::fs::read_to_string(&self.policy_path)
+            .await
+            .map_err(|_| ResourcePolicyError::PolicyLoadError)?;
+
+        evaluate(&policy, &self.data, resource_path, &input_claims, request)
+    }
+
+    async fn decide(
+        &self,
+        resource_path: String,
+        input_claims: String,
+        request: &RequestContext,
+    ) -> Result<PolicyDecision, ResourcePolicyError> {
+        let policy = tokio::fs::read_to_string(&self.policy_path)
+            .await
+            .map_err(|_| ResourcePolicyError::PolicyLoadError)?;
+
+        Ok(PolicyDecision {
+            allowed: evaluate(&policy, &self.data, resource_path, &input_claims, request)?,
+            rules: Vec::new(),
+            policy_digest: hex::encode(Sha256::digest(&policy)),
+            advice: Vec::new(),
+            obligations: Default::default(),
+        })
+    }
+
+    async fn set_policy(&mut self, policy: String) -> Result<(), ResourcePolicyError> {
+        let policy_bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(policy)?;
+
+        // The policy is checked before it replaces the current one.
+        let policy =
+            std::str::from_utf8(&policy_bytes).map_err(|_| ResourcePolicyError::PolicyLoadError)?;
+        Program::compile(policy).map_err(|_| ResourcePolicyError::PolicyLoadError)?;
+
+        tokio::fs::write(&self.policy_path, policy_bytes).await?;
+
+        Ok(())
+    }
+
+    async fn dry_run(
+        &self,
+        policy: Option<String>,
+        resource_path: String,
+        input_claims: String,
+        request: &RequestContext,
+    ) -> Result<PolicyDryRun, ResourcePolicyError> {
+        let policy = match policy {
+            Some(policy) => {
+                let policy_bytes =
+                    base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(policy)?;
+                String::from_utf8(policy_bytes).map_err(|_| ResourcePolicyError::PolicyLoadError)?
+            }
+            None => tokio::fs::read_to_string(&self.policy_path).await?,
+        };
+
+        let mut report = PolicyDryRun::default();
+        if let Err(e) = Program::compile(&policy) {
+            report.error = Some(format!("{e}"));
+            return Ok(report);
+        }
+        match evaluate(&policy, &self.data, resource_path, &input_claims, request) {
+            Ok(allowed) => report.allowed = allowed,
+            Err(e @ (ResourcePolicyError::ResourcePathError | ResourcePolicyError::InputError)) => {
+                return Err(e)
+            }
+            Err(e) => report.error = Some(e.to_string()),
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_engine::DataDocumentConfig;
+
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use rstest::rstest;
+    use serde_json::json;
+    use tempfile::{NamedTempFile, TempDir};
+
+    fn dummy_input(product_id: &str, svn: u64) -> String {
+        json!({
+            "tee": "sample",
+            "tee-pubkey": "dummy-key",
+            "tcb-status": {
+                "productId": product_id,
+                "svn": svn
+            }
+        })
+        .to_string()
+    }
+
+    async fn set_policy_from_file(cel: &mut Cel, path: &str) -> Result<(), ResourcePolicyError> {
+        let policy = std::fs::read(PathBuf::from(path.to_string())).unwrap();
+        let policy = URL_SAFE_NO_PAD.encode(policy);
+
+        cel.set_policy(policy).await
+    }
+
+    #[tokio::test]
+    async fn test_set_policy() {
+        let tmp_dir = TempDir::new().unwrap();
+        let tmp_file = tmp_dir.path().join("policy.cel");
+        let mut cel = Cel::new(tmp_file.clone(), DataDocuments::default()).unwrap();
+
+        // The default policy rejects the sample TEE.
+        let res = cel
+            .evaluate(
+                "my_repo/Alice/key".into(),
+                dummy_input("Alice", 1),
+                &RequestContext::default(),
+            )
+            .await;
+        assert!(!res.unwrap());
+
+        set_policy_from_file(&mut cel, "test/data/policy_1.cel")
+            .await
+            .unwrap();
+
+        // Invalid policies are rejected, and the current one is kept.
+        let res = set_policy_from_file(&mut cel, "test/data/policy_invalid_1.cel").await;
+        assert!(matches!(
+            res.err().unwrap(),
+            ResourcePolicyError::PolicyLoadError
+        ));
+        assert_eq!(
+            std::fs::read(&tmp_file).unwrap(),
+            std::fs::read("test/data/policy_1.cel").unwrap()
+        );
+
+        // decode error
+        let res = cel.set_policy("123".to_string()).await;
+        assert!(matches!(
+            res.err().unwrap(),
+            ResourcePolicyError::DecodeError(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let tmp_dir = TempDir::new().unwrap();
+        let cel = Cel::new(tmp_dir.path().join("policy.cel"), DataDocuments::default()).unwrap();
+
+        let candidate = URL_SAFE_NO_PAD.encode(std::fs::read("test/data/policy_1.cel").unwrap());
+        let report = cel
+            .dry_run(
+                Some(candidate),
+                "my_repo/Alice/key".into(),
+                dummy_input("Alice", 1),
+                &RequestContext::default(),
+            )
+            .await
+            .unwrap();
+        assert!(report.allowed);
+
+        // The set policy is the default one, which rejects the sample TEE.
+        let report = cel
+            .dry_run(
+                None,
+                "my_repo/Alice/key".into(),
+                dummy_input("Alice", 1),
+                &RequestContext::default(),
+            )
+            .await
+            .unwrap();
+        assert!(!report.allowed);
+        assert!(report.error.is_none());
+
+        let candidate = URL_SAFE_NO_PAD.encode("input.tee ==");
+        let report = cel
+            .dry_run(
+                Some(candidate),
+                "my_repo/Alice/key".into(),
+                dummy_input("Alice", 1),
+                &RequestContext::default(),
+            )
+            .await
+            .unwrap();
+        assert!(report.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_data_documents() {
+        let tmp_dir = TempDir::new().unwrap();
+        let allowlist = tmp_dir.path().join("allowlist.json");
+        std::fs::write(&allowlist, r#"{"products": ["Alice"]}"#).unwrap();
+        let data = DataDocuments::load(
+            &[DataDocumentConfig {
+                name: "allowlist".into(),
+                source: allowlist.to_string_lossy().into(),
+            }],
+            0,
+        )
+        .await
+        .unwrap();
+        let tmp_file = tmp_dir.path().join("policy.cel");
+        std::fs::write(
+            &tmp_file,
+            r#"input["tcb-status"].productId in data.allowlist.products"#,
+        )
+        .unwrap();
+        let cel = Cel::new(tmp_file, data).unwrap();
+
+        let res = cel
+            .evaluate(
+                "my_repo/Alice/key".into(),
+                dummy_input("Alice", 1),
+                &RequestContext::default(),
+            )
+            .await;
+        assert!(res.unwrap());
+        let res = cel
+            .evaluate(
+                "my_repo/Alice/key".into(),
+                dummy_input("Bob", 1),
+                &RequestContext::default(),
+            )
+            .await;
+        assert!(!res.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_request_context() {
+        let tmp_dir = TempDir::new().unwrap();
+        let tmp_file = tmp_dir.path().join("policy.cel");
+        std::fs::write(
+            &tmp_file,
+            r#"request.rate <= 10 && request.client_ip == "192.0.2.1""#,
+        )
+        .unwrap();
+        let cel = Cel::new(tmp_file, DataDocuments::default()).unwrap();
+
+        let mut request = RequestContext {
+            client_ip: Some("192.0.2.1".into()),
+            rate: 1,
+            ..RequestContext::now()
+        };
+        let res = cel
+            .evaluate(
+                "my_repo/Alice/key".into(),
+                dummy_input("Alice", 1),
+                &request,
+            )
+            .await;
+        assert!(res.unwrap());
+
+        request.rate = 11;
+        let res = cel
+            .evaluate(
+                "my_repo/Alice/key".into(),
+                dummy_input("Alice", 1),
+                &request,
+            )
+            .await;
+        assert!(!res.unwrap());
+    }
+
+    #[rstest]
+    #[case("test/data/policy_1.cel", "my_repo/Alice/key", "Alice", 1, Some(true))]
+    #[case("test/data/policy_1.cel", "my_repo/Alice/key", "Bob", 1, Some(false))]
+    #[case("test/data/policy_1.cel", "my_repo/Alice", "Alice", 1, None)]
+    #[case(
+        "test/data/policy_invalid_2.cel",
+        "my_repo/Alice/key",
+        "Alice",
+        1,
+        None
+    )]
+    #[case("test/data/policy_2.cel", "myrepo/secret/secret1", "n", 2, Some(true))]
+    #[case("test/data/policy_2.cel", "myrepo/secret/secret1", "n", 1, Some(false))]
+    #[case("test/data/policy_2.cel", "myrepo/secret/secret2", "n", 3, Some(true))]
+    #[case("test/data/policy_2.cel", "myrepo/secret/secret2", "n", 2, Some(false))]
+    #[case("test/data/policy_2.cel", "myrepo/secret/secret3", "n", 3, Some(false))]
+    #[case("test/data/policy_2.cel", "a/b/secret2", "n", 3, Some(false))]
+    #[tokio::test]
+    async fn test_evaluate(
+        #[case] policy_path: &str,
+        #[case] resource_path: &str,
+        #[case] input_name: &str,
+        #[case] input_svn: u64,
+        #[case] expected: Option<bool>,
+    ) {
+        let tmp_file = NamedTempFile::new().unwrap();
+        let cel = Cel::new(tmp_file.path().to_path_buf(), DataDocuments::default()).unwrap();
+
+        let policy = std::fs::read(policy_path).unwrap();
+        std::fs::write(tmp_file.path(), policy).unwrap();
+
+        let res = cel
+            .evaluate(
+                resource_path.to_string(),
+                dummy_input(input_name, input_svn),
+                &RequestContext::default(),
+            )
+            .await;
+        assert_eq!(res.ok(), expected);
+    }
+}
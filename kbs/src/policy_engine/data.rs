@@ -0,0 +1,197 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! External data documents of the resource policies, e.g. JSON allowlists
+//! or tenant maps, loaded from files or HTTP endpoints and refreshed on an
+//! interval. A document is `data.<name>` in both the Rego policies and the
+//! CEL expressions.
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+
+use super::ResourcePolicyError;
+
+/// A data document of the resource policies.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DataDocumentConfig {
+    /// Name of the document, which is `data.<name>` in the policies. It is
+    /// made of ASCII letters, digits and `_`, and is not `policy` or
+    /// `request`.
+    pub name: String,
+
+    /// Path of the JSON file of the document, or the `http://` or `https://`
+    /// URL it is got from.
+    pub source: String,
+}
+
+impl DataDocumentConfig {
+    async fn fetch(&self) -> Result<Value> {
+        if self.source.starts_with("http://") || self.source.starts_with("https://") {
+            let document = reqwest::get(&self.source)
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+                .context("parse data document")?;
+            return Ok(document);
+        }
+
+        let document = tokio::fs::read(&self.source)
+            .await
+            .context("read data document")?;
+        serde_json::from_slice(&document).context("parse data document")
+    }
+}
+
+/// The loaded data documents, shared by the policy engines.
+#[derive(Clone)]
+pub struct DataDocuments(Arc<RwLock<Arc<Value>>>);
+
+impl Default for DataDocuments {
+    fn default() -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(Value::Object(Map::new())))))
+    }
+}
+
+impl DataDocuments {
+    /// Load the documents, and refresh them every `refresh_interval`
+    /// seconds, if not 0. A document that fails to refresh keeps its last
+    /// loaded content.
+    pub async fn load(
+        configs: &[DataDocumentConfig],
+        refresh_interval: u64,
+    ) -> Result<Self, ResourcePolicyError> {
+        let mut documents = Map::new();
+        for config in configs {
+            if !valid_name(&config.name) || documents.contains_key(&config.name) {
+                return Err(ResourcePolicyError::DataDocumentError(format!(
+                    "illegal or duplicated name {}",
+                    config.name
+                )));
+            }
+
+            let document = config.fetch().await.map_err(|e| {
+                ResourcePolicyError::DataDocumentError(format!(
+                    "load {} from {}: {e:#}",
+                    config.name, config.source
+                ))
+            })?;
+            documents.insert(config.name.clone(), document);
+        }
+
+        let data = Self(Arc::new(RwLock::new(Arc::new(Value::Object(documents)))));
+        if !configs.is_empty() && refresh_interval > 0 {
+            tokio::spawn(refresh(
+                Arc::downgrade(&data.0),
+                configs.to_vec(),
+                Duration::from_secs(refresh_interval),
+            ));
+        }
+
+        Ok(data)
+    }
+
+    /// The documents, as the object of their names.
+    pub fn get(&self) -> Arc<Value> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Set the document `name`, e.g. from a policy bundle.
+    pub fn set(&self, name: &str, document: Value) -> Result<(), ResourcePolicyError> {
+        if !valid_name(name) {
+            return Err(ResourcePolicyError::DataDocumentError(format!(
+                "illegal name {name}"
+            )));
+        }
+
+        let mut documents = self.0.write().unwrap();
+        let mut updated = (**documents).clone();
+        updated[name] = document;
+        *documents = Arc::new(updated);
+        Ok(())
+    }
+}
+
+/// Whether `name` is a legal name of a document.
+fn valid_name(name: &str) -> bool {
+    // `data.policy` is the package of the Rego policies, and `data.request`
+    // the context of the request.
+    !name.is_empty()
+        && name != "policy"
+        && name != "request"
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Refresh the documents until the policy engine is dropped.
+async fn refresh(
+    documents: Weak<RwLock<Arc<Value>>>,
+    configs: Vec<DataDocumentConfig>,
+    interval: Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(documents) = documents.upgrade() else {
+            return;
+        };
+
+        let mut refreshed = (**documents.read().unwrap()).clone();
+        for config in &configs {
+            match config.fetch().await {
+                Ok(document) => refreshed[&config.name] = document,
+                Err(e) => warn!(
+                    "Failed to refresh the data document {} of the resource policy: {e:#}",
+                    config.name
+                ),
+            }
+        }
+        *documents.write().unwrap() = Arc::new(refreshed);
+        info!("Data documents of the resource policy refreshed.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_load_data_documents() {
+        let dir = TempDir::new().unwrap();
+        let allowlist = dir.path().join("allowlist.json");
+        std::fs::write(&allowlist, r#"["Alice", "Bob"]"#).unwrap();
+        let configs = vec![DataDocumentConfig {
+            name: "allowlist".into(),
+            source: allowlist.to_string_lossy().into(),
+        }];
+
+        let data = DataDocuments::load(&configs, 1).await.unwrap();
+        assert_eq!(*data.get(), json!({ "allowlist": ["Alice", "Bob"] }));
+
+        // Refreshed on the interval, and kept if the refresh fails.
+        std::fs::write(&allowlist, r#"["Alice"]"#).unwrap();
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(*data.get(), json!({ "allowlist": ["Alice"] }));
+        std::fs::remove_file(&allowlist).unwrap();
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert_eq!(*data.get(), json!({ "allowlist": ["Alice"] }));
+
+        assert!(DataDocuments::load(&configs, 0).await.is_err());
+        let configs = vec![DataDocumentConfig {
+            name: "resource-path".into(),
+            source: allowlist.to_string_lossy().into(),
+        }];
+        assert!(DataDocuments::load(&configs, 0).await.is_err());
+
+        data.set("tenants", json!({ "tenant-a": "Alice" })).unwrap();
+        assert_eq!(data.get()["tenants"]["tenant-a"], "Alice");
+        assert_eq!(data.get()["allowlist"], json!(["Alice"]));
+        assert!(data.set("policy", json!({})).is_err());
+        assert!(data.set("request", json!({})).is_err());
+    }
+}
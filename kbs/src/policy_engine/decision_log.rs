@@ -0,0 +1,160 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Structured log of the decisions of the resource policies, so that a
+//! denied resource can be debugged without reproducing the request. Every
+//! evaluation is written as a JSON record to the configured sink.
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use time::OffsetDateTime;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// Sink of the decision records.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum DecisionLogConfig {
+    /// The log of the KBS, with target `decision`.
+    Log,
+
+    /// A file, appended with a JSON record per line.
+    File { path: PathBuf },
+
+    /// An HTTP endpoint, POSTed a JSON record per evaluation.
+    Http { url: String },
+}
+
+/// A decision of a resource policy.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct DecisionRecord {
+    #[serde(with = "time::serde::rfc3339")]
+    pub timestamp: OffsetDateTime,
+
+    pub resource_path: String,
+
+    /// ID of the repository policy of the resource, null for the default
+    /// resource policy.
+    pub policy_id: Option<String>,
+
+    /// Hex SHA-256 digest of the policy, as in the policy history.
+    pub policy_digest: Option<String>,
+
+    /// Hex SHA-256 digest of the attestation claims.
+    pub input_digest: String,
+
+    pub allowed: bool,
+
+    /// Rules of the policy which are true, for Rego policies.
+    pub rules: Vec<String>,
+
+    pub latency_us: u64,
+
+    /// Why the policy failed to evaluate, if it did.
+    pub error: Option<String>,
+}
+
+pub(crate) enum DecisionLog {
+    Log,
+    File(Mutex<tokio::fs::File>),
+    Http {
+        client: reqwest::Client,
+        url: String,
+    },
+}
+
+impl DecisionLog {
+    pub async fn new(config: &DecisionLogConfig) -> Result<Self> {
+        match config {
+            DecisionLogConfig::Log => Ok(Self::Log),
+            DecisionLogConfig::File { path } => {
+                let file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .context("open decision log")?;
+                Ok(Self::File(Mutex::new(file)))
+            }
+            DecisionLogConfig::Http { url } => Ok(Self::Http {
+                client: reqwest::Client::new(),
+                url: url.clone(),
+            }),
+        }
+    }
+
+    /// Write the `record`. A record failing to be written is only logged, so
+    /// that it never fails the evaluation.
+    pub async fn write(&self, record: &DecisionRecord) {
+        if let Err(e) = self.try_write(record).await {
+            warn!("Failed to write the policy decision record: {e:#}");
+        }
+    }
+
+    async fn try_write(&self, record: &DecisionRecord) -> Result<()> {
+        match self {
+            Self::Log => info!(target: "decision", "{}", serde_json::to_string(record)?),
+            Self::File(file) => {
+                let mut line = serde_json::to_vec(record)?;
+                line.push(b'\n');
+                let mut file = file.lock().await;
+                file.write_all(&line).await?;
+                file.flush().await?;
+            }
+            Self::Http { client, url } => {
+                // The request is not waited for, so that a slow sink does
+                // not delay the resource.
+                let request = client.post(url).json(record);
+                tokio::spawn(async move {
+                    if let Err(e) = request.send().await.and_then(|r| r.error_for_status()) {
+                        warn!("Failed to send the policy decision record: {e}");
+                    }
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_file_decision_log() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("decisions.jsonl");
+        let log = DecisionLog::new(&DecisionLogConfig::File { path: path.clone() })
+            .await
+            .unwrap();
+
+        let record = DecisionRecord {
+            timestamp: OffsetDateTime::UNIX_EPOCH,
+            resource_path: "my_repo/key/1".into(),
+            policy_id: None,
+            policy_digest: Some("ab".repeat(32)),
+            input_digest: "cd".repeat(32),
+            allowed: false,
+            rules: Vec::new(),
+            latency_us: 42,
+            error: None,
+        };
+        log.write(&record).await;
+        log.write(&record).await;
+
+        let records = std::fs::read_to_string(&path).unwrap();
+        let records: Vec<serde_json::Value> = records
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["resource_path"], "my_repo/key/1");
+        assert_eq!(records[0]["timestamp"], "1970-01-01T00:00:00Z");
+        assert_eq!(records[1]["latency_us"], 42);
+    }
+}
@@ -4,17 +4,52 @@
 
 use anyhow::Result;
 use async_trait::async_trait;
-use serde::Deserialize;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Instant;
 use thiserror::Error;
+use time::OffsetDateTime;
 use tokio::sync::Mutex;
 
 #[cfg(feature = "opa")]
 mod opa;
 
+#[cfg(feature = "cel")]
+mod cel;
+
+mod data;
+pub use data::{DataDocumentConfig, DataDocuments};
+
+mod decision_log;
+use decision_log::DecisionLog;
+pub use decision_log::{DecisionLogConfig, DecisionRecord};
+
+mod profile;
+pub use profile::PolicyProfile;
+
+mod request;
+use request::RequestRates;
+pub use request::{RequestContext, RequestContextConfig};
+
+mod testing;
+pub use testing::{run_policy_tests, PolicyTestCase, PolicyTestResult};
+
 const DEFAULT_POLICY_PATH: &str = "/opa/confidential-containers/kbs/policy.rego";
 
+/// Seconds between the refreshes of the data documents.
+const DEFAULT_DATA_REFRESH_INTERVAL: u64 = 300;
+
+/// Most advice strings told to a denied client, and characters of each.
+const MAX_ADVICE: usize = 8;
+const MAX_ADVICE_LENGTH: usize = 256;
+
+#[cfg(feature = "cel")]
+const DEFAULT_CEL_POLICY_PATH: &str = "/opa/confidential-containers/kbs/policy.cel";
+
 #[derive(Error, Debug)]
 pub enum ResourcePolicyError {
     #[error("Failed to evaluate resource policy {0}")]
@@ -37,6 +72,24 @@ pub enum ResourcePolicyError {
 
     #[error("Failed to load resource policy")]
     PolicyLoadError,
+
+    #[error("Policy engine {0} is not enabled, the feature `{0}` is required")]
+    EngineNotEnabled(&'static str),
+
+    #[error("Invalid repository policy: {0}")]
+    RepositoryPolicyError(String),
+
+    #[error("Unknown repository policy {0}")]
+    UnknownRepositoryPolicy(String),
+
+    #[error("Invalid data document: {0}")]
+    DataDocumentError(String),
+
+    #[error("Failed to open the decision log: {0}")]
+    DecisionLogError(#[source] anyhow::Error),
+
+    #[error("Policy profile {0:?} requires the opa policy engine")]
+    PolicyProfileError(PolicyProfile),
 }
 
 /// Resource policy engine interface
@@ -51,48 +104,857 @@ pub(crate) trait PolicyEngineInterface: Send + Sync {
     /// ([decide_result, extra_output])
     /// decide_result: Boolean value to present whether the evaluate is passed or not.
     /// extra_output: original ouput from policy engine.
+    /// request: Context of the request, e.g. its time.
     async fn evaluate(
         &self,
         resource_path: String,
         input_claims: String,
+        request: &RequestContext,
     ) -> Result<bool, ResourcePolicyError>;
 
+    /// Evaluate like `evaluate`, and tell why, for the decision log.
+    async fn decide(
+        &self,
+        resource_path: String,
+        input_claims: String,
+        request: &RequestContext,
+    ) -> Result<PolicyDecision, ResourcePolicyError>;
+
     /// Set policy (Base64 encode)
     async fn set_policy(&mut self, policy: String) -> Result<(), ResourcePolicyError>;
+
+    /// Evaluate the candidate `policy` (Base64 encode), or the set policy if
+    /// not given, like `evaluate`, and trace the evaluation. The set policy
+    /// is not changed.
+    async fn dry_run(
+        &self,
+        policy: Option<String>,
+        resource_path: String,
+        input_claims: String,
+        request: &RequestContext,
+    ) -> Result<PolicyDryRun, ResourcePolicyError>;
+}
+
+/// The decision of a resource policy.
+pub(crate) struct PolicyDecision {
+    pub allowed: bool,
+
+    /// Rules of the policy which are true, for Rego policies.
+    pub rules: Vec<String>,
+
+    /// Hex SHA-256 digest of the evaluated policy.
+    pub policy_digest: String,
+
+    /// The `advice` of a Rego policy, e.g. why the resource is denied.
+    pub advice: Vec<String>,
+
+    /// The `obligations` of a Rego policy.
+    pub obligations: Obligations,
+}
+
+/// Obligations a Rego policy sets with the resource, e.g.
+/// ```rego
+/// obligations := {"max_attestation_age": 300, "log_level": "warn"}
+/// ```
+/// which the KBS enforces when it releases the resource. A policy setting an
+/// obligation the KBS does not know fails to evaluate, so that the resource
+/// is never released without it.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Obligations {
+    /// Most seconds since the attestation token of the request was issued,
+    /// by its `iat` claim.
+    pub max_attestation_age: Option<u64>,
+
+    /// Only release the latest version of the resource.
+    #[serde(default)]
+    pub latest_version_only: bool,
+
+    /// Most seconds the client may keep the released resource, told as the
+    /// `exp` of the protected header of the response.
+    pub max_ttl: Option<u64>,
+
+    /// Level the release of the resource is logged at, `info` if not set.
+    pub log_level: Option<ObligationLogLevel>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ObligationLogLevel {
+    Info,
+    Warn,
+    Error,
+}
+
+impl From<ObligationLogLevel> for log::Level {
+    fn from(level: ObligationLogLevel) -> Self {
+        match level {
+            ObligationLogLevel::Info => log::Level::Info,
+            ObligationLogLevel::Warn => log::Level::Warn,
+            ObligationLogLevel::Error => log::Level::Error,
+        }
+    }
+}
+
+/// The outcome of a dry run of a resource policy.
+#[derive(Clone, Debug, Default, Serialize, PartialEq)]
+pub struct PolicyDryRun {
+    /// ID of the repository policy of the resource, if one applies.
+    pub policy_id: Option<String>,
+
+    /// Whether the policy allows the resource.
+    pub allowed: bool,
+
+    /// Values of the rules of the policy, for Rego policies.
+    pub rules: Option<serde_json::Value>,
+
+    /// Output of the `print` calls of the policy, for Rego policies.
+    pub prints: Vec<String>,
+
+    /// Why the policy failed to load or evaluate, if it did.
+    pub error: Option<String>,
+}
+
+/// Language of the resource policy.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyEngineType {
+    /// OPA/Rego policy, evaluated by regorus.
+    #[default]
+    Opa,
+
+    /// CEL expression, for simple claim matching.
+    Cel,
 }
 
 /// Policy engine configuration.
 #[derive(Clone, Debug, Deserialize)]
 pub struct PolicyEngineConfig {
+    /// Language of the policy.
+    #[serde(default)]
+    pub policy_engine_type: PolicyEngineType,
+
     /// Path to a file containing a policy for evaluating whether the TCB status has access to
     /// specific resources.
     pub policy_path: Option<PathBuf>,
+
+    /// Built-in policy which replaces the policy at `policy_path` when the
+    /// KBS starts.
+    pub policy_profile: Option<PolicyProfile>,
+
+    /// Policies of the resources of specific repositories, instead of the
+    /// policy above.
+    #[serde(default)]
+    pub repository_policies: Vec<RepositoryPolicyConfig>,
+
+    /// Data documents of the policies, e.g. allowlists, as `data.<name>`.
+    #[serde(default)]
+    pub data_documents: Vec<DataDocumentConfig>,
+
+    /// Seconds between the refreshes of the data documents, 0 to load them
+    /// only once.
+    #[serde(default = "default_data_refresh_interval")]
+    pub data_refresh_interval: u64,
+
+    /// Sink of the records of the decisions of the policies. The decisions
+    /// are not logged if not given.
+    pub decision_log: Option<DecisionLogConfig>,
+
+    /// What the clients are told when their resources are denied.
+    #[serde(default)]
+    pub deny_reasons: DenyReasonConfig,
+
+    /// Where the context of the requests given to the policies is got from.
+    #[serde(default)]
+    pub request_context: RequestContextConfig,
+}
+
+/// What the clients are told when a resource policy denies their resource,
+/// besides the correlation ID of the denial.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct DenyReasonConfig {
+    /// Message told with every denial, e.g. where to get help.
+    pub message: Option<String>,
+
+    /// Tell the `advice` of the Rego policies. Disabled by default, as the
+    /// advice may tell what the policy expects.
+    #[serde(default)]
+    pub advice: bool,
+}
+
+fn default_data_refresh_interval() -> u64 {
+    DEFAULT_DATA_REFRESH_INTERVAL
 }
 
 impl Default for PolicyEngineConfig {
     fn default() -> Self {
         Self {
+            policy_engine_type: PolicyEngineType::default(),
             policy_path: Some(PathBuf::from(DEFAULT_POLICY_PATH)),
+            policy_profile: None,
+            repository_policies: Vec::new(),
+            data_documents: Vec::new(),
+            data_refresh_interval: DEFAULT_DATA_REFRESH_INTERVAL,
+            decision_log: None,
+            deny_reasons: DenyReasonConfig::default(),
+            request_context: RequestContextConfig::default(),
         }
     }
 }
 
+/// Resource policy of the resources of specific repositories.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RepositoryPolicyConfig {
+    /// ID of the policy, used to set it through the admin API.
+    pub id: String,
+
+    /// Prefix of the paths of the resources the policy applies to, in whole
+    /// path segments, e.g. `tenant-a/*` for the resources of repository
+    /// `tenant-a`. The trailing `/*` is optional.
+    pub prefix: String,
+
+    /// Path to a file containing the policy, in the language of the policy
+    /// engine.
+    pub policy_path: PathBuf,
+}
+
+type Engine = Arc<Mutex<dyn PolicyEngineInterface>>;
+
+/// Create the policy engine of the policy at `policy_path`, given the data
+/// documents.
+fn engine(
+    engine_type: &PolicyEngineType,
+    policy_path: Option<PathBuf>,
+    data: &DataDocuments,
+) -> Result<Engine, ResourcePolicyError> {
+    #[cfg(not(any(feature = "opa", feature = "cel")))]
+    compile_error!(
+        "Please enable at least one of the following features: `opa`, `cel` to continue."
+    );
+
+    match engine_type {
+        PolicyEngineType::Opa => {
+            cfg_if::cfg_if! {
+                if #[cfg(feature = "opa")] {
+                    Ok(Arc::new(Mutex::new(opa::Opa::new(policy_path.unwrap_or(PathBuf::from(DEFAULT_POLICY_PATH)), data.clone())?)))
+                } else {
+                    let _ = (policy_path, data);
+                    Err(ResourcePolicyError::EngineNotEnabled("opa"))
+                }
+            }
+        }
+        PolicyEngineType::Cel => {
+            cfg_if::cfg_if! {
+                if #[cfg(feature = "cel")] {
+                    Ok(Arc::new(Mutex::new(cel::Cel::new(policy_path.unwrap_or(PathBuf::from(DEFAULT_CEL_POLICY_PATH)), data.clone())?)))
+                } else {
+                    let _ = (policy_path, data);
+                    Err(ResourcePolicyError::EngineNotEnabled("cel"))
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct RepositoryPolicy {
+    id: String,
+    prefix: String,
+    engine: Engine,
+}
+
 /// Policy Engine
 #[derive(Clone)]
-pub(crate) struct PolicyEngine(pub Arc<Mutex<dyn PolicyEngineInterface>>);
+pub(crate) struct PolicyEngine {
+    /// Policy of the resources no repository policy applies to.
+    default: Engine,
+
+    /// Repository policies, the longest prefix first.
+    repository_policies: Vec<RepositoryPolicy>,
+
+    /// Data documents of the policies.
+    #[cfg(feature = "policy-bundle")]
+    data: DataDocuments,
+
+    decision_log: Option<Arc<DecisionLog>>,
+    deny_reasons: DenyReasonConfig,
+    request_context: RequestContextConfig,
+    request_rates: Arc<RequestRates>,
+}
 
 impl PolicyEngine {
     /// Create and initialize PolicyEngine
     pub async fn new(config: &PolicyEngineConfig) -> Result<Self, ResourcePolicyError> {
-        let policy_engine: Arc<Mutex<dyn PolicyEngineInterface>> = {
-            cfg_if::cfg_if! {
-                if #[cfg(feature = "opa")] {
-                    Arc::new(Mutex::new(opa::Opa::new(config.policy_path.clone().unwrap_or(PathBuf::from(DEFAULT_POLICY_PATH)))?))
-                } else {
-                    compile_error!("Please enable at least one of the following features: `opa` to continue.");
-                }
+        let data =
+            DataDocuments::load(&config.data_documents, config.data_refresh_interval).await?;
+        let default = engine(
+            &config.policy_engine_type,
+            config.policy_path.clone(),
+            &data,
+        )?;
+
+        if let Some(profile) = config.policy_profile {
+            if config.policy_engine_type != PolicyEngineType::Opa {
+                return Err(ResourcePolicyError::PolicyProfileError(profile));
             }
+            if profile == PolicyProfile::AllowAllDebug {
+                warn!("The resource policy profile allows every resource, do not use it in production");
+            }
+            default
+                .lock()
+                .await
+                .set_policy(URL_SAFE_NO_PAD.encode(profile.policy()))
+                .await?;
+        }
+
+        let mut repository_policies: Vec<RepositoryPolicy> = Vec::new();
+        for policy in &config.repository_policies {
+            if policy.id.is_empty()
+                || !policy
+                    .id
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+            {
+                return Err(ResourcePolicyError::RepositoryPolicyError(format!(
+                    "illegal id {}",
+                    policy.id
+                )));
+            }
+            if repository_policies.iter().any(|p| p.id == policy.id) {
+                return Err(ResourcePolicyError::RepositoryPolicyError(format!(
+                    "duplicated id {}",
+                    policy.id
+                )));
+            }
+            // The prefix is made of whole path segments, so that the one of
+            // `tenant-a` does not apply to `tenant-ab`.
+            let prefix = policy.prefix.trim_end_matches('*');
+            if prefix.len() != policy.prefix.len() && !prefix.is_empty() && !prefix.ends_with('/') {
+                return Err(ResourcePolicyError::RepositoryPolicyError(format!(
+                    "prefix of {} does not end with whole path segments",
+                    policy.id
+                )));
+            }
+            let prefix = prefix.trim_end_matches('/');
+            if prefix.is_empty() {
+                return Err(ResourcePolicyError::RepositoryPolicyError(format!(
+                    "empty prefix of {}",
+                    policy.id
+                )));
+            }
+
+            repository_policies.push(RepositoryPolicy {
+                id: policy.id.clone(),
+                prefix: prefix.to_string(),
+                engine: engine(
+                    &config.policy_engine_type,
+                    Some(policy.policy_path.clone()),
+                    &data,
+                )?,
+            });
+        }
+        repository_policies.sort_by_key(|policy| std::cmp::Reverse(policy.prefix.len()));
+
+        let decision_log = match &config.decision_log {
+            Some(config) => Some(Arc::new(
+                DecisionLog::new(config)
+                    .await
+                    .map_err(ResourcePolicyError::DecisionLogError)?,
+            )),
+            None => None,
+        };
+
+        Ok(Self {
+            default,
+            repository_policies,
+            #[cfg(feature = "policy-bundle")]
+            data,
+            decision_log,
+            deny_reasons: config.deny_reasons.clone(),
+            request_context: config.request_context.clone(),
+            request_rates: Arc::new(RequestRates::new(config.request_context.rate_window)),
+        })
+    }
+
+    /// The repository policy of the longest prefix of `resource_path`, if
+    /// one applies. The policies are sorted by decreasing prefix length.
+    fn repository_policy(&self, resource_path: &str) -> Option<&RepositoryPolicy> {
+        self.repository_policies.iter().find(|policy| {
+            resource_path
+                .strip_prefix(&policy.prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+
+    /// Evaluate the policy of the resource at `resource_path`, i.e. the
+    /// repository policy of the longest prefix of the path, or the default
+    /// policy if none applies, for the `request`. The decision is logged to
+    /// the decision log, if configured.
+    pub async fn evaluate(
+        &self,
+        resource_path: String,
+        input_claims: String,
+        request: &RequestContext,
+    ) -> Result<bool, ResourcePolicyError> {
+        if self.decision_log.is_some() {
+            let decision = self.decide(resource_path, input_claims, request).await?;
+            return Ok(decision.allowed);
+        }
+
+        let engine = self
+            .repository_policy(&resource_path)
+            .map_or(&self.default, |policy| &policy.engine);
+        engine
+            .lock()
+            .await
+            .evaluate(resource_path, input_claims, request)
+            .await
+    }
+
+    /// Evaluate like `evaluate`, and tell why the resource is allowed or
+    /// not.
+    pub async fn decide(
+        &self,
+        resource_path: String,
+        input_claims: String,
+        request: &RequestContext,
+    ) -> Result<PolicyDecision, ResourcePolicyError> {
+        let repository_policy = self.repository_policy(&resource_path);
+        let engine = repository_policy.map_or(&self.default, |policy| &policy.engine);
+
+        let Some(decision_log) = &self.decision_log else {
+            return engine
+                .lock()
+                .await
+                .decide(resource_path, input_claims, request)
+                .await;
+        };
+
+        let input_digest = hex::encode(Sha256::digest(&input_claims));
+        let start = Instant::now();
+        let decision = engine
+            .lock()
+            .await
+            .decide(resource_path.clone(), input_claims, request)
+            .await;
+        let latency_us = start.elapsed().as_micros() as u64;
+
+        let mut record = DecisionRecord {
+            timestamp: OffsetDateTime::now_utc(),
+            resource_path,
+            policy_id: repository_policy.map(|policy| policy.id.clone()),
+            policy_digest: None,
+            input_digest,
+            allowed: false,
+            rules: Vec::new(),
+            latency_us,
+            error: None,
+        };
+        match &decision {
+            Ok(decision) => {
+                record.policy_digest = Some(decision.policy_digest.clone());
+                record.allowed = decision.allowed;
+                record.rules = decision.rules.clone();
+            }
+            Err(e) => record.error = Some(e.to_string()),
+        }
+        decision_log.write(&record).await;
+
+        decision
+    }
+
+    /// The message and the advice of the policy told to the client whose
+    /// resource is denied by `decision`, as configured. The advice is
+    /// sanitized, as it may be built from the claims of the client.
+    pub fn deny_reasons(&self, decision: &PolicyDecision) -> (Option<String>, Vec<String>) {
+        let advice = match self.deny_reasons.advice {
+            true => decision
+                .advice
+                .iter()
+                .map(|advice| {
+                    advice
+                        .chars()
+                        .filter(|c| !c.is_control())
+                        .take(MAX_ADVICE_LENGTH)
+                        .collect::<String>()
+                })
+                .filter(|advice| !advice.is_empty())
+                .take(MAX_ADVICE)
+                .collect(),
+            false => Vec::new(),
         };
-        Ok(Self(policy_engine))
+
+        (self.deny_reasons.message.clone(), advice)
+    }
+
+    /// Where the context of the requests is got from.
+    pub fn request_context_config(&self) -> &RequestContextConfig {
+        &self.request_context
+    }
+
+    /// The context of a resource request of `identity` made now, from the
+    /// `client_ip` and the `geo` of the client. The request is counted in the
+    /// rate of the identity.
+    pub fn request_context(
+        &self,
+        identity: &str,
+        client_ip: Option<String>,
+        geo: Option<String>,
+    ) -> RequestContext {
+        RequestContext {
+            client_ip,
+            geo,
+            rate: self.request_rates.record(identity),
+            ..RequestContext::now()
+        }
+    }
+
+    /// Dry run the candidate `policy` (Base64 encode), or the set policy if
+    /// not given, in place of the policy of the resource at `resource_path`.
+    pub async fn dry_run(
+        &self,
+        policy: Option<String>,
+        resource_path: String,
+        input_claims: String,
+        request: &RequestContext,
+    ) -> Result<PolicyDryRun, ResourcePolicyError> {
+        let repository_policy = self.repository_policy(&resource_path);
+        let engine = repository_policy.map_or(&self.default, |policy| &policy.engine);
+
+        let mut report = engine
+            .lock()
+            .await
+            .dry_run(policy, resource_path, input_claims, request)
+            .await?;
+        report.policy_id = repository_policy.map(|policy| policy.id.clone());
+        Ok(report)
+    }
+
+    /// Set the repository policy `policy_id`, or the default policy if not
+    /// given (Base64 encode).
+    pub async fn set_policy(
+        &self,
+        policy_id: Option<&str>,
+        policy: String,
+    ) -> Result<(), ResourcePolicyError> {
+        let engine = match policy_id {
+            Some(policy_id) => {
+                &self
+                    .repository_policies
+                    .iter()
+                    .find(|policy| policy.id == policy_id)
+                    .ok_or_else(|| {
+                        ResourcePolicyError::UnknownRepositoryPolicy(policy_id.to_string())
+                    })?
+                    .engine
+            }
+            None => &self.default,
+        };
+
+        engine.lock().await.set_policy(policy).await
+    }
+
+    /// Set the data document `name` of the policies.
+    #[cfg(feature = "policy-bundle")]
+    pub fn set_data_document(
+        &self,
+        name: &str,
+        document: serde_json::Value,
+    ) -> Result<(), ResourcePolicyError> {
+        self.data.set(name, document)
+    }
+}
+
+#[cfg(all(test, feature = "opa"))]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn input(product_id: &str) -> String {
+        json!({
+            "tee": "sample",
+            "tcb-status": { "productId": product_id }
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_repository_policies() {
+        let dir = TempDir::new().unwrap();
+        let tenant_policy = dir.path().join("tenant-a.rego");
+        std::fs::copy("test/data/policy_1.rego", &tenant_policy).unwrap();
+        let config = PolicyEngineConfig {
+            policy_engine_type: PolicyEngineType::Opa,
+            policy_path: Some(dir.path().join("policy.rego")),
+            repository_policies: vec![RepositoryPolicyConfig {
+                id: "tenant-a".into(),
+                prefix: "tenant-a/*".into(),
+                policy_path: tenant_policy,
+            }],
+            ..Default::default()
+        };
+        let engine = PolicyEngine::new(&config).await.unwrap();
+
+        // The policy of tenant A allows the sample TEE, which the default
+        // policy rejects.
+        let res = engine
+            .evaluate(
+                "tenant-a/key/1".into(),
+                input("key"),
+                &RequestContext::default(),
+            )
+            .await;
+        assert!(res.unwrap());
+        let res = engine
+            .evaluate(
+                "tenant-b/key/1".into(),
+                input("key"),
+                &RequestContext::default(),
+            )
+            .await;
+        assert!(!res.unwrap());
+
+        let policy = URL_SAFE_NO_PAD.encode("package policy\ndefault allow = false\n");
+        engine
+            .set_policy(Some("tenant-a"), policy.clone())
+            .await
+            .unwrap();
+        let res = engine
+            .evaluate(
+                "tenant-a/key/1".into(),
+                input("key"),
+                &RequestContext::default(),
+            )
+            .await;
+        assert!(!res.unwrap());
+        assert!(matches!(
+            engine.set_policy(Some("tenant-c"), policy).await,
+            Err(ResourcePolicyError::UnknownRepositoryPolicy(_))
+        ));
+
+        // A dry run does not change the policy.
+        let candidate = std::fs::read("test/data/policy_1.rego").unwrap();
+        let report = engine
+            .dry_run(
+                Some(URL_SAFE_NO_PAD.encode(candidate)),
+                "tenant-a/key/1".into(),
+                input("key"),
+                &RequestContext::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.policy_id.as_deref(), Some("tenant-a"));
+        assert!(report.allowed);
+        assert_eq!(
+            report.rules.unwrap()["path"],
+            json!(["tenant-a", "key", "1"])
+        );
+        let res = engine
+            .evaluate(
+                "tenant-a/key/1".into(),
+                input("key"),
+                &RequestContext::default(),
+            )
+            .await;
+        assert!(!res.unwrap());
+
+        let report = engine
+            .dry_run(
+                Some(URL_SAFE_NO_PAD.encode("package policy\nallow {")),
+                "tenant-b/key/1".into(),
+                input("key"),
+                &RequestContext::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.policy_id, None);
+        assert!(!report.allowed);
+        assert!(report.error.is_some());
+
+        let mut config = config;
+        config.repository_policies[0].prefix = "*".into();
+        assert!(PolicyEngine::new(&config).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_repository_policy_prefixes() {
+        let dir = TempDir::new().unwrap();
+        let repository_policy = |id: &str, prefix: &str| {
+            let policy_path = dir.path().join(format!("{id}.rego"));
+            std::fs::copy("test/data/policy_1.rego", &policy_path).unwrap();
+            RepositoryPolicyConfig {
+                id: id.into(),
+                prefix: prefix.into(),
+                policy_path,
+            }
+        };
+        let mut config = PolicyEngineConfig {
+            policy_engine_type: PolicyEngineType::Opa,
+            policy_path: Some(dir.path().join("policy.rego")),
+            repository_policies: vec![
+                repository_policy("tenant-a", "tenant-a/*"),
+                repository_policy("tenant-ab", "tenant-ab"),
+                repository_policy("tenant-a-key", "tenant-a/key/"),
+            ],
+            ..Default::default()
+        };
+        let engine = PolicyEngine::new(&config).await.unwrap();
+
+        let policy_id = |path: &str| {
+            engine
+                .repository_policy(path)
+                .map(|policy| policy.id.clone())
+        };
+        assert_eq!(policy_id("tenant-a/cert/1").as_deref(), Some("tenant-a"));
+        assert_eq!(policy_id("tenant-ab/cert/1").as_deref(), Some("tenant-ab"));
+        assert_eq!(policy_id("tenant-a/key/1").as_deref(), Some("tenant-a-key"));
+        assert_eq!(policy_id("tenant-abc/key/1"), None);
+        assert_eq!(policy_id("tenant/key/1"), None);
+
+        config.repository_policies = vec![repository_policy("tenant", "tenant-*")];
+        assert!(PolicyEngine::new(&config).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_data_documents() {
+        let dir = TempDir::new().unwrap();
+        let allowlist = dir.path().join("allowlist.json");
+        std::fs::write(&allowlist, r#"{"products": ["Alice"]}"#).unwrap();
+        let policy = dir.path().join("policy.rego");
+        std::fs::write(
+            &policy,
+            "package policy\ndefault allow = false\n\
+             allow { input[\"tcb-status\"].productId == data.allowlist.products[_] }\n",
+        )
+        .unwrap();
+        let config = PolicyEngineConfig {
+            policy_path: Some(policy),
+            data_documents: vec![DataDocumentConfig {
+                name: "allowlist".into(),
+                source: allowlist.to_string_lossy().into(),
+            }],
+            ..Default::default()
+        };
+        let engine = PolicyEngine::new(&config).await.unwrap();
+
+        let res = engine
+            .evaluate(
+                "my_repo/key/1".into(),
+                input("Alice"),
+                &RequestContext::default(),
+            )
+            .await;
+        assert!(res.unwrap());
+        let res = engine
+            .evaluate(
+                "my_repo/key/1".into(),
+                input("Bob"),
+                &RequestContext::default(),
+            )
+            .await;
+        assert!(!res.unwrap());
+
+        std::fs::remove_file(&allowlist).unwrap();
+        assert!(matches!(
+            PolicyEngine::new(&config).await,
+            Err(ResourcePolicyError::DataDocumentError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_deny_reasons() {
+        let dir = TempDir::new().unwrap();
+        let mut config = PolicyEngineConfig {
+            policy_path: Some(dir.path().join("policy.rego")),
+            ..Default::default()
+        };
+        let decision = PolicyDecision {
+            allowed: false,
+            rules: Vec::new(),
+            policy_digest: String::new(),
+            advice: vec![
+                "the TEE is not TDX".into(),
+                "\n".into(),
+                format!("bad\x1b[0m{}", "a".repeat(300)),
+            ],
+            obligations: Obligations::default(),
+        };
+
+        // The advice is not told by default.
+        let engine = PolicyEngine::new(&config).await.unwrap();
+        assert_eq!(engine.deny_reasons(&decision), (None, Vec::new()));
+
+        config.deny_reasons = DenyReasonConfig {
+            message: Some("Ask the tenant admin".into()),
+            advice: true,
+        };
+        let engine = PolicyEngine::new(&config).await.unwrap();
+        let (message, advice) = engine.deny_reasons(&decision);
+        assert_eq!(message.as_deref(), Some("Ask the tenant admin"));
+        assert_eq!(advice.len(), 2);
+        assert_eq!(advice[0], "the TEE is not TDX");
+        assert!(advice[1].starts_with("bad[0maaa"));
+        assert_eq!(advice[1].chars().count(), MAX_ADVICE_LENGTH);
+    }
+
+    #[tokio::test]
+    async fn test_decision_log() {
+        let dir = TempDir::new().unwrap();
+        let decision_log = dir.path().join("decisions.jsonl");
+        let tenant_a = dir.path().join("tenant-a.rego");
+        std::fs::write(&tenant_a, "package policy\ndefault allow = true\n").unwrap();
+        let config = PolicyEngineConfig {
+            policy_path: Some(dir.path().join("policy.rego")),
+            repository_policies: vec![RepositoryPolicyConfig {
+                id: "tenant-a".into(),
+                prefix: "tenant-a/*".into(),
+                policy_path: tenant_a.clone(),
+            }],
+            decision_log: Some(DecisionLogConfig::File {
+                path: decision_log.clone(),
+            }),
+            ..Default::default()
+        };
+        let engine = PolicyEngine::new(&config).await.unwrap();
+
+        let res = engine
+            .evaluate(
+                "my_repo/key/1".into(),
+                input("Alice"),
+                &RequestContext::default(),
+            )
+            .await;
+        assert!(!res.unwrap());
+        let res = engine
+            .evaluate(
+                "tenant-a/key/1".into(),
+                input("Alice"),
+                &RequestContext::default(),
+            )
+            .await;
+        assert!(res.unwrap());
+
+        let records: Vec<serde_json::Value> = std::fs::read_to_string(&decision_log)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["resource_path"], "my_repo/key/1");
+        assert_eq!(records[0]["policy_id"], serde_json::Value::Null);
+        assert_eq!(records[0]["allowed"], false);
+        assert_eq!(
+            records[0]["input_digest"],
+            hex::encode(Sha256::digest(input("Alice")))
+        );
+        assert_eq!(records[1]["policy_id"], "tenant-a");
+        assert_eq!(records[1]["allowed"], true);
+        assert_eq!(records[1]["rules"], json!(["allow"]));
+        assert_eq!(
+            records[1]["policy_digest"],
+            hex::encode(Sha256::digest(std::fs::read(&tenant_a).unwrap()))
+        );
     }
 }
@@ -2,19 +2,25 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::policy_engine::{PolicyEngineInterface, ResourcePolicyError};
+use crate::policy_engine::{
+    DataDocuments, Obligations, PolicyDecision, PolicyDryRun, PolicyEngineInterface,
+    RequestContext, ResourcePolicyError,
+};
+use anyhow::Context;
 use async_trait::async_trait;
 use base64::Engine;
+use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::PathBuf;
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Opa {
     policy_path: PathBuf,
+    data: DataDocuments,
 }
 
 impl Opa {
-    pub fn new(policy_path: PathBuf) -> Result<Self, ResourcePolicyError> {
+    pub fn new(policy_path: PathBuf, data: DataDocuments) -> Result<Self, ResourcePolicyError> {
         std::fs::create_dir_all(policy_path.parent().unwrap())?;
 
         if !policy_path.as_path().exists() {
@@ -22,16 +28,86 @@ impl Opa {
             fs::write(&policy_path, policy)?;
         }
 
-        Ok(Self { policy_path })
+        Ok(Self { policy_path, data })
     }
 }
 
+/// Add the data documents, the `resource_path` and the `request` as data and
+/// the `input_claims` as input of the `engine`.
+fn prepare(
+    engine: &mut regorus::Engine,
+    data: &DataDocuments,
+    resource_path: &str,
+    input_claims: &str,
+    request: &RequestContext,
+) -> Result<(), ResourcePolicyError> {
+    let documents = regorus::Value::from_json_str(&data.get().to_string())
+        .map_err(|_| ResourcePolicyError::DataLoadError)?;
+    engine
+        .add_data(documents)
+        .map_err(|_| ResourcePolicyError::DataLoadError)?;
+
+    // Add resource path as data
+    let resource_path_object =
+        regorus::Value::from_json_str(&format!("{{\"resource-path\":\"{}\"}}", resource_path))
+            .map_err(|_| ResourcePolicyError::ResourcePathError)?;
+
+    engine
+        .add_data(resource_path_object)
+        .map_err(|_| ResourcePolicyError::DataLoadError)?;
+
+    // Add the context of the request as data
+    let request_object =
+        regorus::Value::from_json_str(&serde_json::json!({ "request": request }).to_string())
+            .map_err(|_| ResourcePolicyError::DataLoadError)?;
+    engine
+        .add_data(request_object)
+        .map_err(|_| ResourcePolicyError::DataLoadError)?;
+
+    // Add TCB claims as input
+    engine
+        .set_input_json(input_claims)
+        .map_err(|_| ResourcePolicyError::InputError)?;
+
+    Ok(())
+}
+
+/// The values of the rules of the policy, which tell why the resource is
+/// allowed or not.
+fn rules(engine: &mut regorus::Engine) -> Option<serde_json::Value> {
+    engine
+        .eval_query("data.policy".to_string(), false)
+        .ok()
+        .and_then(|results| results.result.into_iter().next())
+        .and_then(|result| result.expressions.into_iter().next())
+        .and_then(|expression| serde_json::to_value(&expression.value).ok())
+}
+
+/// The obligations set by the optional `obligations` rule of the policy.
+fn obligations(engine: &mut regorus::Engine) -> anyhow::Result<Obligations> {
+    let results = engine.eval_query("data.policy.obligations".to_string(), false)?;
+    let Some(value) = results
+        .result
+        .first()
+        .and_then(|result| result.expressions.first())
+        .map(|expression| &expression.value)
+    else {
+        return Ok(Obligations::default());
+    };
+    if *value == regorus::Value::Undefined {
+        return Ok(Obligations::default());
+    }
+
+    serde_json::from_str(&value.to_json_str()?).context("illegal obligations")
+}
+
 #[async_trait]
 impl PolicyEngineInterface for Opa {
     async fn evaluate(
         &self,
         resource_path: String,
         input_claims: String,
+        request: &RequestContext,
     ) -> Result<bool, ResourcePolicyError> {
         let mut engine = regorus::Engine::new();
 
@@ -40,22 +116,118 @@ impl PolicyEngineInterface for Opa {
             .add_policy_from_file(self.policy_path.clone())
             .map_err(|_| ResourcePolicyError::PolicyLoadError)?;
 
-        // Add resource path as data
-        let resource_path_object =
-            regorus::Value::from_json_str(&format!("{{\"resource-path\":\"{}\"}}", resource_path))
-                .map_err(|_| ResourcePolicyError::ResourcePathError)?;
+        prepare(
+            &mut engine,
+            &self.data,
+            &resource_path,
+            &input_claims,
+            request,
+        )?;
 
-        engine
-            .add_data(resource_path_object)
-            .map_err(|_| ResourcePolicyError::DataLoadError)?;
+        let res = engine.eval_bool_query("data.policy.allow".to_string(), false)?;
+        Ok(res)
+    }
+
+    async fn decide(
+        &self,
+        resource_path: String,
+        input_claims: String,
+        request: &RequestContext,
+    ) -> Result<PolicyDecision, ResourcePolicyError> {
+        let policy = tokio::fs::read_to_string(&self.policy_path)
+            .await
+            .map_err(|_| ResourcePolicyError::PolicyLoadError)?;
+        let policy_digest = hex::encode(Sha256::digest(&policy));
 
-        // Add TCB claims as input
+        let mut engine = regorus::Engine::new();
         engine
-            .set_input_json(&input_claims)
-            .map_err(|_| ResourcePolicyError::InputError)?;
+            .add_policy(self.policy_path.to_string_lossy().to_string(), policy)
+            .map_err(|_| ResourcePolicyError::PolicyLoadError)?;
+        prepare(
+            &mut engine,
+            &self.data,
+            &resource_path,
+            &input_claims,
+            request,
+        )?;
 
-        let res = engine.eval_bool_query("data.policy.allow".to_string(), false)?;
-        Ok(res)
+        let allowed = engine.eval_bool_query("data.policy.allow".to_string(), false)?;
+        let (rules, advice) = match rules(&mut engine) {
+            Some(serde_json::Value::Object(rules)) => {
+                // `advice` is a set of strings, or a string.
+                let advice = match rules.get("advice") {
+                    Some(serde_json::Value::Array(advice)) => advice
+                        .iter()
+                        .filter_map(|advice| advice.as_str().map(String::from))
+                        .collect(),
+                    Some(serde_json::Value::String(advice)) => vec![advice.clone()],
+                    _ => Vec::new(),
+                };
+                let rules = rules
+                    .into_iter()
+                    .filter(|(_, value)| value == &serde_json::Value::Bool(true))
+                    .map(|(rule, _)| rule)
+                    .collect();
+                (rules, advice)
+            }
+            _ => (Vec::new(), Vec::new()),
+        };
+        let obligations = obligations(&mut engine)?;
+
+        Ok(PolicyDecision {
+            allowed,
+            rules,
+            policy_digest,
+            advice,
+            obligations,
+        })
+    }
+
+    async fn dry_run(
+        &self,
+        policy: Option<String>,
+        resource_path: String,
+        input_claims: String,
+        request: &RequestContext,
+    ) -> Result<PolicyDryRun, ResourcePolicyError> {
+        let (path, policy) = match policy {
+            Some(policy) => {
+                let policy_bytes =
+                    base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(policy)?;
+                let policy = String::from_utf8(policy_bytes)
+                    .map_err(|_| ResourcePolicyError::PolicyLoadError)?;
+                ("candidate.rego".to_string(), policy)
+            }
+            None => (
+                self.policy_path.to_string_lossy().to_string(),
+                tokio::fs::read_to_string(&self.policy_path).await?,
+            ),
+        };
+
+        let mut report = PolicyDryRun::default();
+        let mut engine = regorus::Engine::new();
+        if let Err(e) = engine.add_policy(path, policy) {
+            report.error = Some(format!("{e}"));
+            return Ok(report);
+        }
+        prepare(
+            &mut engine,
+            &self.data,
+            &resource_path,
+            &input_claims,
+            request,
+        )?;
+
+        engine.set_gather_prints(true);
+        match engine.eval_bool_query("data.policy.allow".to_string(), false) {
+            Ok(allowed) => report.allowed = allowed,
+            Err(e) => report.error = Some(format!("{e}")),
+        }
+        report.prints = engine.take_prints().unwrap_or_default();
+
+        report.rules = rules(&mut engine);
+
+        Ok(report)
     }
 
     async fn set_policy(&mut self, policy: String) -> Result<(), ResourcePolicyError> {
@@ -70,6 +242,7 @@ impl PolicyEngineInterface for Opa {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::policy_engine::ObligationLogLevel;
 
     use base64::engine::general_purpose::URL_SAFE_NO_PAD;
     use rstest::rstest;
@@ -117,7 +290,7 @@ mod tests {
     async fn test_set_policy() {
         let tmp_dir = TempDir::new().unwrap();
         let tmp_file = tmp_dir.path().join("policy.rego");
-        let mut opa = Opa::new(tmp_file).unwrap();
+        let mut opa = Opa::new(tmp_file, DataDocuments::default()).unwrap();
 
         set_policy_from_file(&mut opa, "test/data/policy_1.rego")
             .await
@@ -140,6 +313,192 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn test_dry_run() {
+        let tmp_file = NamedTempFile::new().unwrap();
+        let mut opa = Opa::new(tmp_file.path().to_path_buf(), DataDocuments::default()).unwrap();
+        set_policy_from_file(&mut opa, "test/data/policy_1.rego")
+            .await
+            .unwrap();
+
+        let report = opa
+            .dry_run(
+                None,
+                "my_repo/Alice/key".into(),
+                dummy_input("Alice", 1),
+                &RequestContext::default(),
+            )
+            .await
+            .unwrap();
+        assert!(report.allowed);
+        assert_eq!(report.rules.unwrap()["allow"], json!(true));
+
+        let candidate = URL_SAFE_NO_PAD
+            .encode("package policy\ndefault allow = false\nallow { print(\"svn\", input[\"tcb-status\"].svn); false }\n");
+        let report = opa
+            .dry_run(
+                Some(candidate),
+                "my_repo/Alice/key".into(),
+                dummy_input("Alice", 1),
+                &RequestContext::default(),
+            )
+            .await
+            .unwrap();
+        assert!(!report.allowed);
+        assert_eq!(report.prints.len(), 1);
+        assert!(report.prints[0].contains("svn 1"));
+        assert_eq!(
+            std::fs::read(tmp_file.path()).unwrap(),
+            std::fs::read("test/data/policy_1.rego").unwrap()
+        );
+
+        let res = opa
+            .dry_run(
+                None,
+                "my_repo/Alice/key".into(),
+                "{".into(),
+                &RequestContext::default(),
+            )
+            .await;
+        assert!(matches!(res, Err(ResourcePolicyError::InputError)));
+    }
+
+    #[tokio::test]
+    async fn test_decide() {
+        let tmp_file = NamedTempFile::new().unwrap();
+        let mut opa = Opa::new(tmp_file.path().to_path_buf(), DataDocuments::default()).unwrap();
+        set_policy_from_file(&mut opa, "test/data/policy_1.rego")
+            .await
+            .unwrap();
+
+        let decision = opa
+            .decide(
+                "my_repo/Alice/key".into(),
+                dummy_input("Alice", 1),
+                &RequestContext::default(),
+            )
+            .await
+            .unwrap();
+        assert!(decision.allowed);
+        assert_eq!(decision.rules, ["allow"]);
+        assert_eq!(
+            decision.policy_digest,
+            hex::encode(Sha256::digest(
+                std::fs::read("test/data/policy_1.rego").unwrap()
+            ))
+        );
+
+        let decision = opa
+            .decide(
+                "my_repo/Alice/key".into(),
+                dummy_input("Bob", 1),
+                &RequestContext::default(),
+            )
+            .await
+            .unwrap();
+        assert!(!decision.allowed);
+        assert!(decision.rules.is_empty());
+        assert!(decision.advice.is_empty());
+
+        let policy = "package policy\ndefault allow = false\n\
+                      allow { input.tee == \"tdx\" }\n\
+                      advice contains \"the TEE is not TDX\" { input.tee != \"tdx\" }\n\
+                      advice contains \"the debug mode is on\" { input.debug }\n";
+        opa.set_policy(URL_SAFE_NO_PAD.encode(policy))
+            .await
+            .unwrap();
+        let input = json!({ "tee": "sample", "debug": true }).to_string();
+        let mut decision = opa
+            .decide(
+                "my_repo/Alice/key".into(),
+                input,
+                &RequestContext::default(),
+            )
+            .await
+            .unwrap();
+        assert!(!decision.allowed);
+        decision.advice.sort();
+        assert_eq!(
+            decision.advice,
+            ["the TEE is not TDX", "the debug mode is on"]
+        );
+        assert_eq!(decision.obligations, Obligations::default());
+
+        let policy = "package policy\ndefault allow = true\n\
+                      obligations := {\"max_attestation_age\": 300, \"max_ttl\": 60, \"log_level\": \"warn\"}\n";
+        opa.set_policy(URL_SAFE_NO_PAD.encode(policy))
+            .await
+            .unwrap();
+        let decision = opa
+            .decide(
+                "my_repo/Alice/key".into(),
+                dummy_input("Alice", 1),
+                &RequestContext::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            decision.obligations,
+            Obligations {
+                max_attestation_age: Some(300),
+                latest_version_only: false,
+                max_ttl: Some(60),
+                log_level: Some(ObligationLogLevel::Warn),
+            }
+        );
+
+        // An obligation the KBS does not know is never ignored.
+        let policy = "package policy\ndefault allow = true\n\
+                      obligations := {\"wrap_with\": \"hsm\"}\n";
+        opa.set_policy(URL_SAFE_NO_PAD.encode(policy))
+            .await
+            .unwrap();
+        let res = opa
+            .decide(
+                "my_repo/Alice/key".into(),
+                dummy_input("Alice", 1),
+                &RequestContext::default(),
+            )
+            .await;
+        assert!(matches!(res, Err(ResourcePolicyError::EvaluationError(_))));
+    }
+
+    #[rstest]
+    #[case("2024-05-01T09:30:00Z", 1, true)]
+    #[case("2024-05-01T09:30:00Z", 11, false)]
+    #[case("2024-05-01T20:00:00Z", 1, false)]
+    #[tokio::test]
+    async fn test_request_context(#[case] time: &str, #[case] rate: u64, #[case] expected: bool) {
+        let tmp_file = NamedTempFile::new().unwrap();
+        let mut opa = Opa::new(tmp_file.path().to_path_buf(), DataDocuments::default()).unwrap();
+        let policy = "package policy
+default allow = false
+                      allow {
+                          hour := time.clock(time.parse_rfc3339_ns(data.request.time))[0]
+                          hour >= 8
+                          hour < 18
+                          data.request.rate <= 10
+                      }
+";
+        opa.set_policy(URL_SAFE_NO_PAD.encode(policy))
+            .await
+            .unwrap();
+
+        let request = RequestContext {
+            time: time.into(),
+            rate,
+            ..Default::default()
+        };
+        let res = opa
+            .evaluate(
+                "my_repo/Alice/key".into(),
+                dummy_input("Alice", 1),
+                &request,
+            )
+            .await;
+        assert_eq!(res.unwrap(), expected);
+    }
+
     #[rstest]
     #[case("test/data/policy_1.rego", "my_repo/Alice/key", "Alice", 1, Ok(true))]
     #[case("test/data/policy_4.rego", "my_repo/Alice/key", "Alice", 1, Ok(true))]
@@ -182,14 +541,18 @@ mod tests {
         #[case] expected: Result<bool, ResourcePolicyError>,
     ) {
         let tmp_file = NamedTempFile::new().unwrap();
-        let mut opa = Opa::new(tmp_file.path().to_path_buf()).unwrap();
+        let mut opa = Opa::new(tmp_file.path().to_path_buf(), DataDocuments::default()).unwrap();
 
         set_policy_from_file(&mut opa, policy_path).await.unwrap();
 
         let resource_path = resource_path.to_string();
 
         let res = opa
-            .evaluate(resource_path.clone(), dummy_input(input_name, input_svn))
+            .evaluate(
+                resource_path.clone(),
+                dummy_input(input_name, input_svn),
+                &RequestContext::default(),
+            )
             .await;
 
         if let Ok(actual) = res {
@@ -0,0 +1,10 @@
+# Allow All Debug Profile
+# -----------------------
+#
+# Allows every resource to every TEE, including the sample TEE, e.g. to
+# develop the workloads before their policies are written. Never use it in
+# production.
+
+package policy
+
+default allow = true
@@ -0,0 +1,58 @@
+# Measurement Match Only Profile
+# ------------------------------
+#
+# Allows the resources to the TEEs whose measurements all match their
+# reference values. A measurement is a claim of `tcb-status` which has
+# reference values in `reference-data`, e.g.
+# ```
+# {
+#     "tee": "tdx",
+#     "tcb-status": { "tdx.quote.body.mr_td": "705ee9...", ... },
+#     "reference-data": { "tdx.quote.body.mr_td": ["705ee9..."] }
+# }
+# ```
+# At least one measurement is required, and the sample TEE is denied.
+
+package policy
+
+default allow = false
+
+tcb := input["tcb-status"]
+
+references := object.get(input, "reference-data", {})
+
+measurements contains claim {
+	some claim
+	references[claim]
+	_ = tcb[claim]
+}
+
+mismatches contains claim {
+	some claim
+	measurements[claim]
+	not matches(claim)
+}
+
+matches(claim) {
+	tcb[claim] == references[claim][_]
+}
+
+allow {
+	input.tee != "sample"
+	count(measurements) > 0
+	count(mismatches) == 0
+}
+
+advice contains "the sample TEE is not trusted" {
+	input.tee == "sample"
+}
+
+advice contains "no measurement has reference values" {
+	count(measurements) == 0
+}
+
+advice contains msg {
+	some claim
+	mismatches[claim]
+	msg := sprintf("measurement %s does not match its reference values", [claim])
+}
@@ -0,0 +1,97 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Built-in Rego resource policies, which the operators choose by name
+//! instead of writing a policy.
+
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PolicyProfile {
+    /// The measurements of the TEE match their reference values.
+    MeasurementMatchOnly,
+
+    /// The measurements of the TEE match their reference values, it is not
+    /// in debug mode, and its trust vectors are affirming.
+    StrictTcb,
+
+    /// Every resource is allowed, only meant for development.
+    AllowAllDebug,
+}
+
+impl PolicyProfile {
+    /// The Rego policy of the profile.
+    pub fn policy(&self) -> &'static str {
+        match self {
+            Self::MeasurementMatchOnly => include_str!("measurement_match_only.rego"),
+            Self::StrictTcb => include_str!("strict_tcb.rego"),
+            Self::AllowAllDebug => include_str!("allow_all_debug.rego"),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "opa"))]
+mod tests {
+    use super::*;
+    use crate::policy_engine::{PolicyEngine, PolicyEngineConfig, RequestContext};
+    use rstest::rstest;
+    use serde_json::{json, Value};
+    use tempfile::TempDir;
+
+    fn claims(tee: &str, mr_td: &str, debug: bool, trust_vector: Value) -> Value {
+        json!({
+            "tee": tee,
+            "tcb-status": {
+                "tdx.quote.body.mr_td": mr_td,
+                "tdx.td_attributes.debug": debug,
+            },
+            "reference-data": { "tdx.quote.body.mr_td": ["aa", "bb"] },
+            "evaluation-reports": [
+                { "policy-id": "default", "trust-vector": trust_vector },
+            ],
+        })
+    }
+
+    #[rstest]
+    #[case(PolicyProfile::MeasurementMatchOnly, claims("tdx", "bb", true, json!({ "hardware": 97 })), true)]
+    #[case(PolicyProfile::MeasurementMatchOnly, claims("tdx", "cc", false, json!({})), false)]
+    #[case(PolicyProfile::MeasurementMatchOnly, claims("sample", "aa", false, json!({})), false)]
+    #[case(PolicyProfile::MeasurementMatchOnly, json!({ "tee": "tdx", "tcb-status": { "tdx.quote.body.mr_td": "aa" } }), false)]
+    #[case(PolicyProfile::StrictTcb, claims("tdx", "aa", false, json!({ "hardware": 2 })), true)]
+    #[case(PolicyProfile::StrictTcb, claims("tdx", "aa", true, json!({ "hardware": 2 })), false)]
+    #[case(PolicyProfile::StrictTcb, claims("tdx", "aa", false, json!({ "hardware": 32 })), false)]
+    #[case(PolicyProfile::StrictTcb, claims("tdx", "aa", false, json!({ "hardware": -32 })), true)]
+    #[case(PolicyProfile::StrictTcb, claims("tdx", "aa", false, json!({ "hardware": 2, "executables": -100 })), false)]
+    #[case(PolicyProfile::StrictTcb, claims("tdx", "cc", false, json!({})), false)]
+    #[case(PolicyProfile::AllowAllDebug, claims("sample", "cc", true, json!({})), true)]
+    #[tokio::test]
+    async fn test_policy_profiles(
+        #[case] profile: PolicyProfile,
+        #[case] claims: Value,
+        #[case] expected: bool,
+    ) {
+        let dir = TempDir::new().unwrap();
+        let engine = PolicyEngine::new(&PolicyEngineConfig {
+            policy_path: Some(dir.path().join("policy.rego")),
+            policy_profile: Some(profile),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let decision = engine
+            .decide(
+                "my_repo/key/1".into(),
+                claims.to_string(),
+                &RequestContext::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(decision.allowed, expected);
+        if !expected {
+            assert!(!decision.advice.is_empty());
+        }
+    }
+}
@@ -0,0 +1,103 @@
+# Strict TCB Profile
+# ------------------
+#
+# Allows the resources to the TEEs whose measurements all match their
+# reference values, as the measurement match only profile, and which
+# besides
+# - are not in debug mode, i.e. have no claim of `tcb-status` named with
+#   `debug` which is true,
+# - have no claim of the trust vectors of their `evaluation-reports` of the
+#   warning or contraindicated tiers, i.e. 32 and above or -33 and below.
+
+package policy
+
+default allow = false
+
+tcb := input["tcb-status"]
+
+references := object.get(input, "reference-data", {})
+
+measurements contains claim {
+	some claim
+	references[claim]
+	_ = tcb[claim]
+}
+
+mismatches contains claim {
+	some claim
+	measurements[claim]
+	not matches(claim)
+}
+
+matches(claim) {
+	tcb[claim] == references[claim][_]
+}
+
+debug contains claim {
+	some claim
+	value := tcb[claim]
+	contains(lower(claim), "debug")
+	enabled(value)
+}
+
+enabled(value) {
+	value == true
+}
+
+enabled(value) {
+	value == "true"
+}
+
+enabled(value) {
+	value == 1
+}
+
+enabled(value) {
+	value == "1"
+}
+
+untrusted contains claim {
+	some i, claim
+	report := object.get(input, "evaluation-reports", [])[i]
+	report["trust-vector"][claim] >= 32
+}
+
+untrusted contains claim {
+	some i, claim
+	report := object.get(input, "evaluation-reports", [])[i]
+	report["trust-vector"][claim] <= -33
+}
+
+allow {
+	input.tee != "sample"
+	count(measurements) > 0
+	count(mismatches) == 0
+	count(debug) == 0
+	count(untrusted) == 0
+}
+
+advice contains "the sample TEE is not trusted" {
+	input.tee == "sample"
+}
+
+advice contains "no measurement has reference values" {
+	count(measurements) == 0
+}
+
+advice contains msg {
+	some claim
+	mismatches[claim]
+	msg := sprintf("measurement %s does not match its reference values", [claim])
+}
+
+advice contains msg {
+	some claim
+	debug[claim]
+	msg := sprintf("the TEE is in debug mode, as %s", [claim])
+}
+
+advice contains msg {
+	some claim
+	untrusted[claim]
+	msg := sprintf("the trust vector claim %s is not affirming", [claim])
+}
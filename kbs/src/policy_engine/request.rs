@@ -0,0 +1,220 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Context of the resource requests, i.e. when, from where and how often
+//! they are made, so that the policies can restrict the releases to time
+//! windows or deny anomalous requests. The context is `data.request` in the
+//! Rego policies and `request` in the CEL expressions.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// Seconds of the window the request rates are counted in.
+const DEFAULT_RATE_WINDOW: u64 = 60;
+
+/// Trusted proxies in front of the KBS.
+const DEFAULT_TRUSTED_PROXIES: usize = 1;
+
+/// Identities tracked before those without a request in the window are
+/// forgotten.
+const MAX_IDENTITIES: usize = 4096;
+
+/// Where the context of the requests is got from.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RequestContextConfig {
+    /// Header of the IP of the client, e.g. `X-Forwarded-For`, set by a
+    /// trusted proxy in front of the KBS. The IP of the peer if not given.
+    pub client_ip_header: Option<String>,
+
+    /// Number of trusted proxies in front of the KBS, each appending the
+    /// address it receives the request from to the list of the
+    /// `client_ip_header`. The address appended by the outermost one is the
+    /// client IP, the ones before it are set by the client.
+    #[serde(default = "default_trusted_proxies")]
+    pub trusted_proxies: usize,
+
+    /// Header of the geolocation of the client, e.g. `CF-IPCountry`, set by
+    /// a trusted proxy in front of the KBS.
+    pub geo_header: Option<String>,
+
+    /// Seconds of the window the request rates are counted in.
+    #[serde(default = "default_rate_window")]
+    pub rate_window: u64,
+}
+
+fn default_rate_window() -> u64 {
+    DEFAULT_RATE_WINDOW
+}
+
+fn default_trusted_proxies() -> usize {
+    DEFAULT_TRUSTED_PROXIES
+}
+
+impl Default for RequestContextConfig {
+    fn default() -> Self {
+        Self {
+            client_ip_header: None,
+            trusted_proxies: DEFAULT_TRUSTED_PROXIES,
+            geo_header: None,
+            rate_window: DEFAULT_RATE_WINDOW,
+        }
+    }
+}
+
+impl RequestContextConfig {
+    /// The IP and the geolocation of the client of a request, given the
+    /// values of its headers and the IP of the `peer` it is received from.
+    pub fn client(
+        &self,
+        header: impl Fn(&str) -> Option<String>,
+        peer: Option<IpAddr>,
+    ) -> (Option<String>, Option<String>) {
+        let client_ip = match &self.client_ip_header {
+            // The proxies append to a list, e.g. of `X-Forwarded-For`, so
+            // the client IP is the `trusted_proxies`-th address from the end.
+            // A shorter list was not set by the proxies.
+            Some(name) => header(name).and_then(|ips| {
+                let ips: Vec<&str> = ips.split(',').map(str::trim).collect();
+                ips.len()
+                    .checked_sub(self.trusted_proxies)
+                    .and_then(|index| ips.get(index))
+                    .map(|ip| ip.to_string())
+            }),
+            None => peer.map(|ip| ip.to_string()),
+        };
+        let geo = self.geo_header.as_deref().and_then(header);
+
+        (client_ip, geo)
+    }
+}
+
+/// The context of a resource request.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct RequestContext {
+    /// Time of the request, RFC 3339.
+    pub time: String,
+
+    /// Time of the request, in seconds since the Unix epoch.
+    pub timestamp: i64,
+
+    /// IP of the client, if known.
+    pub client_ip: Option<String>,
+
+    /// Geolocation of the client, if a trusted proxy tells it.
+    pub geo: Option<String>,
+
+    /// Resource requests of the attested identity in the rate window, this
+    /// one included.
+    pub rate: u64,
+}
+
+impl RequestContext {
+    /// The context of a request made now, of unknown client.
+    pub fn now() -> Self {
+        let now = OffsetDateTime::now_utc();
+        Self {
+            time: now.format(&Rfc3339).unwrap_or_default(),
+            timestamp: now.unix_timestamp(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Times of the recent resource requests, by identity.
+pub(crate) struct RequestRates {
+    window: Duration,
+    requests: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl RequestRates {
+    pub fn new(window: u64) -> Self {
+        Self {
+            window: Duration::from_secs(window),
+            requests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a request of `identity`, and tell how many it made in the
+    /// window, this one included.
+    pub fn record(&self, identity: &str) -> u64 {
+        let now = Instant::now();
+        let in_window = |time: &Instant| now.duration_since(*time) <= self.window;
+
+        let mut requests = self.requests.lock().unwrap();
+        if requests.len() >= MAX_IDENTITIES {
+            requests.retain(|_, times| times.back().is_some_and(in_window));
+        }
+
+        let times = requests.entry(identity.to_string()).or_default();
+        while times.front().is_some_and(|time| !in_window(time)) {
+            times.pop_front();
+        }
+        times.push_back(now);
+        times.len() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_context() {
+        let rates = RequestRates::new(1);
+        assert_eq!(rates.record("Alice"), 1);
+        assert_eq!(rates.record("Alice"), 2);
+        assert_eq!(rates.record("Bob"), 1);
+
+        std::thread::sleep(Duration::from_millis(1100));
+        assert_eq!(rates.record("Alice"), 1);
+
+        let config = RequestContextConfig {
+            client_ip_header: Some("X-Forwarded-For".into()),
+            geo_header: Some("CF-IPCountry".into()),
+            ..Default::default()
+        };
+        // The first address is set by the client, and the last one by the
+        // trusted proxy.
+        let header = |name: &str| match name {
+            "X-Forwarded-For" => Some("192.0.2.1, 198.51.100.1".to_string()),
+            _ => None,
+        };
+        let peer = Some(IpAddr::from([203, 0, 113, 1]));
+        assert_eq!(
+            config.client(header, peer),
+            (Some("198.51.100.1".to_string()), None)
+        );
+        let config = RequestContextConfig {
+            trusted_proxies: 2,
+            ..config
+        };
+        assert_eq!(
+            config.client(header, peer),
+            (Some("192.0.2.1".to_string()), None)
+        );
+        let config = RequestContextConfig {
+            trusted_proxies: 3,
+            ..config
+        };
+        assert_eq!(config.client(header, peer), (None, None));
+        assert_eq!(
+            RequestContextConfig::default().client(header, peer),
+            (Some("203.0.113.1".to_string()), None)
+        );
+
+        let context = RequestContext::now();
+        assert!(context.timestamp > 0);
+        assert_eq!(
+            OffsetDateTime::parse(&context.time, &Rfc3339)
+                .unwrap()
+                .unix_timestamp(),
+            context.timestamp
+        );
+    }
+}
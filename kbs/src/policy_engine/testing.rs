@@ -0,0 +1,159 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Test cases of the resource policies, run with the policy engine of the
+//! KBS, so that a policy change can be checked, e.g. in CI, with the same
+//! semantics as when the KBS evaluates it.
+//!
+//! A test case is a JSON file, e.g.
+//! ```json
+//! {
+//!     "resource_path": "my_repo/key/1",
+//!     "claims": { "tee": "sample", "tcb-status": { "productId": "key" } },
+//!     "allowed": true
+//! }
+//! ```
+//! with an optional `request` context, e.g. `{ "time": "2024-05-01T09:30:00Z",
+//! "rate": 3 }`, a request made now if not given.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+use super::{PolicyEngine, PolicyEngineConfig, RequestContext};
+
+/// A test case of the resource policies.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PolicyTestCase {
+    /// Path of the requested resource, `<repository>/<type>/<tag>`.
+    pub resource_path: String,
+
+    /// Claims of the attestation token of the request.
+    pub claims: serde_json::Value,
+
+    /// Context of the request, a request made now if not given.
+    pub request: Option<RequestContext>,
+
+    /// Whether the policy is expected to allow the resource.
+    pub allowed: bool,
+}
+
+/// The result of a test case.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PolicyTestResult {
+    /// Name of the file of the test case.
+    pub name: String,
+
+    pub expected: bool,
+
+    /// Whether the policy allowed the resource, or why it failed to evaluate.
+    pub actual: Result<bool, String>,
+}
+
+impl PolicyTestResult {
+    pub fn passed(&self) -> bool {
+        self.actual == Ok(self.expected)
+    }
+}
+
+/// Run the test cases, the `*.json` files of `dir` in the order of their
+/// names, against the resource policies of `config`.
+pub async fn run_policy_tests(
+    config: &PolicyEngineConfig,
+    dir: &Path,
+) -> Result<Vec<PolicyTestResult>> {
+    let mut config = config.clone();
+    config.decision_log = None;
+    config.data_refresh_interval = 0;
+    let engine = PolicyEngine::new(&config)
+        .await
+        .context("create policy engine")?;
+
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir).context("read test cases")? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut results = Vec::new();
+    for path in paths {
+        let case = std::fs::read(&path).context("read test case")?;
+        let case: PolicyTestCase = serde_json::from_slice(&case)
+            .with_context(|| format!("parse test case {}", path.display()))?;
+
+        let request = case.request.unwrap_or_else(RequestContext::now);
+        let actual = engine
+            .evaluate(case.resource_path, case.claims.to_string(), &request)
+            .await
+            .map_err(|e| e.to_string());
+        results.push(PolicyTestResult {
+            name: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into(),
+            expected: case.allowed,
+            actual,
+        });
+    }
+
+    Ok(results)
+}
+
+#[cfg(all(test, feature = "opa"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_run_policy_tests() {
+        let dir = TempDir::new().unwrap();
+        let case = |resource_path: &str, product_id: &str, allowed: bool| {
+            json!({
+                "resource_path": resource_path,
+                "claims": { "tee": "sample", "tcb-status": { "productId": product_id } },
+                "allowed": allowed,
+            })
+            .to_string()
+        };
+        std::fs::write(
+            dir.path().join("1-allowed.json"),
+            case("repo/Alice/key", "Alice", true),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("2-denied.json"),
+            case("repo/Alice/key", "Bob", false),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("3-wrong.json"),
+            case("repo/Alice/key", "Bob", true),
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("README.md"), "not a test case").unwrap();
+
+        let config = PolicyEngineConfig {
+            policy_path: Some("test/data/policy_1.rego".into()),
+            ..Default::default()
+        };
+        let results = run_policy_tests(&config, dir.path()).await.unwrap();
+        let names: Vec<&str> = results.iter().map(|result| result.name.as_str()).collect();
+        assert_eq!(names, ["1-allowed.json", "2-denied.json", "3-wrong.json"]);
+        assert!(results[0].passed());
+        assert!(results[1].passed());
+        assert!(!results[2].passed());
+        assert_eq!(results[2].actual, Ok(false));
+
+        std::fs::write(dir.path().join("4-invalid.json"), "{}").unwrap();
+        assert!(run_policy_tests(&config, dir.path()).await.is_err());
+    }
+}
@@ -0,0 +1,311 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Version history of the resource and attestation policies set through the
+//! admin API, so that a bad policy push can be reviewed and rolled back.
+//! Every set policy is kept as a new version, numbered from 1, under
+//! `<dir_path>/resource/<version>`,
+//! `<dir_path>/repository/<policy id>/<version>` or
+//! `<dir_path>/attestation/<policy id>/<version>`.
+
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+use std::path::PathBuf;
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+
+const DEFAULT_DIR_PATH: &str = "/opt/confidential-containers/kbs/policy-history";
+
+/// Policy history configuration.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PolicyHistoryConfig {
+    /// Directory of the versions of the policies.
+    #[serde(default = "default_dir_path")]
+    pub dir_path: PathBuf,
+}
+
+fn default_dir_path() -> PathBuf {
+    PathBuf::from(DEFAULT_DIR_PATH)
+}
+
+impl Default for PolicyHistoryConfig {
+    fn default() -> Self {
+        Self {
+            dir_path: default_dir_path(),
+        }
+    }
+}
+
+/// A versioned policy.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PolicyKind {
+    /// The default resource policy of the KBS.
+    Resource,
+
+    /// A repository resource policy of the KBS, by policy id.
+    Repository(String),
+
+    /// An attestation policy of the AS, by policy id.
+    Attestation(String),
+}
+
+/// A version of a policy, without its content.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct PolicyVersionInfo {
+    pub version: u64,
+
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+
+    /// Hex SHA-256 digest of the policy.
+    pub digest: String,
+}
+
+/// A version of a policy.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct PolicyVersion {
+    #[serde(flatten)]
+    pub info: PolicyVersionInfo,
+
+    /// The policy, base64 encoded as it is set through the admin API.
+    pub policy: String,
+
+    /// Signature of the policy by the policy author, base64 encoded, if
+    /// it is signed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+
+    /// Version of the policy signed by the policy author, if it is signed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_version: Option<u64>,
+}
+
+/// A policy change, as the new version and its unified diff with the
+/// previous version.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct PolicyChange {
+    pub version: u64,
+    pub diff: String,
+}
+
+pub struct PolicyHistory {
+    dir_path: PathBuf,
+
+    /// Serializes the recording of the versions, so that they are numbered
+    /// in order.
+    lock: Mutex<()>,
+}
+
+impl PolicyHistory {
+    pub fn new(config: &PolicyHistoryConfig) -> Result<Self> {
+        std::fs::create_dir_all(&config.dir_path).context("create policy history directory")?;
+        Ok(Self {
+            dir_path: config.dir_path.clone(),
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn versions_dir(&self, kind: &PolicyKind) -> Result<PathBuf> {
+        match kind {
+            PolicyKind::Resource => Ok(self.dir_path.join("resource")),
+            PolicyKind::Repository(policy_id) | PolicyKind::Attestation(policy_id) => {
+                if policy_id.is_empty()
+                    || !policy_id
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+                {
+                    bail!("illegal policy id {policy_id}");
+                }
+                let dir = match kind {
+                    PolicyKind::Repository(_) => "repository",
+                    _ => "attestation",
+                };
+                Ok(self.dir_path.join(dir).join(policy_id))
+            }
+        }
+    }
+
+    async fn versions(&self, kind: &PolicyKind) -> Result<Vec<u64>> {
+        let versions_dir = self.versions_dir(kind)?;
+        if !versions_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut versions = Vec::new();
+        let mut entries = tokio::fs::read_dir(versions_dir)
+            .await
+            .context("read policy history directory")?;
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(version) = entry.file_name().to_str().and_then(|v| v.parse().ok()) {
+                versions.push(version);
+            }
+        }
+        versions.sort_unstable();
+        Ok(versions)
+    }
+
+    /// List the versions of the policy, oldest first.
+    pub async fn list(&self, kind: &PolicyKind) -> Result<Vec<PolicyVersionInfo>> {
+        let mut infos = Vec::new();
+        for version in self.versions(kind).await? {
+            infos.push(self.get(kind, version).await?.info);
+        }
+        Ok(infos)
+    }
+
+    /// Get the given version of the policy.
+    pub async fn get(&self, kind: &PolicyKind, version: u64) -> Result<PolicyVersion> {
+        let path = self.versions_dir(kind)?.join(version.to_string());
+        if !path.exists() {
+            bail!("no version {version} of the policy");
+        }
+        let record = tokio::fs::read(path).await.context("read policy version")?;
+        serde_json::from_slice(&record).context("parse policy version")
+    }
+
+    /// The highest version of the policy signed by the policy author.
+    pub async fn latest_signed_version(&self, kind: &PolicyKind) -> Result<Option<u64>> {
+        let mut latest = None;
+        for version in self.versions(kind).await? {
+            latest = latest.max(self.get(kind, version).await?.signed_version);
+        }
+        Ok(latest)
+    }
+
+    /// Record the `policy`, base64 encoded, with its `signature` and the
+    /// `signed_version` it signs, as the new version of the policy, and
+    /// return its diff with the previous version.
+    pub async fn record(
+        &self,
+        kind: &PolicyKind,
+        policy: &str,
+        signature: Option<&str>,
+        signed_version: Option<u64>,
+    ) -> Result<PolicyChange> {
+        let content = URL_SAFE_NO_PAD.decode(policy).context("decode policy")?;
+
+        let _guard = self.lock.lock().await;
+        let versions = self.versions(kind).await?;
+        let previous = match versions.last() {
+            Some(version) => URL_SAFE_NO_PAD.decode(self.get(kind, *version).await?.policy)?,
+            None => Vec::new(),
+        };
+        let version = versions.last().copied().unwrap_or_default() + 1;
+
+        let record = PolicyVersion {
+            info: PolicyVersionInfo {
+                version,
+                created_at: OffsetDateTime::now_utc(),
+                digest: hex::encode(Sha256::digest(&content)),
+            },
+            policy: policy.to_string(),
+            signature: signature.map(str::to_string),
+            signed_version,
+        };
+        let versions_dir = self.versions_dir(kind)?;
+        tokio::fs::create_dir_all(&versions_dir)
+            .await
+            .context("create policy history directory")?;
+        tokio::fs::write(
+            versions_dir.join(version.to_string()),
+            serde_json::to_vec(&record)?,
+        )
+        .await
+        .context("write policy version")?;
+
+        Ok(PolicyChange {
+            version,
+            diff: diff(&previous, &content, version),
+        })
+    }
+}
+
+/// Unified diff of the `previous` version and the version `version` of a
+/// policy.
+fn diff(previous: &[u8], current: &[u8], version: u64) -> String {
+    let previous = String::from_utf8_lossy(previous);
+    let current = String::from_utf8_lossy(current);
+    TextDiff::from_lines(&previous, &current)
+        .unified_diff()
+        .header(
+            &format!("version {}", version.saturating_sub(1)),
+            &format!("version {version}"),
+        )
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn encode(policy: &str) -> String {
+        URL_SAFE_NO_PAD.encode(policy)
+    }
+
+    #[tokio::test]
+    async fn test_policy_history() {
+        let dir = TempDir::new().unwrap();
+        let history = PolicyHistory::new(&PolicyHistoryConfig {
+            dir_path: dir.path().to_path_buf(),
+        })
+        .unwrap();
+        let kind = PolicyKind::Attestation("default".into());
+
+        let change = history
+            .record(&kind, &encode("package policy\nallow = true\n"), None, None)
+            .await
+            .unwrap();
+        assert_eq!(change.version, 1);
+        assert!(change.diff.contains("+allow = true"));
+
+        let change = history
+            .record(
+                &kind,
+                &encode("package policy\nallow = false\n"),
+                Some("c2ln"),
+                Some(7),
+            )
+            .await
+            .unwrap();
+        assert_eq!(change.version, 2);
+        assert!(change.diff.contains("-allow = true\n+allow = false"));
+        assert!(!change.diff.contains("-package policy"));
+
+        let versions = history.list(&kind).await.unwrap();
+        assert_eq!(
+            versions.iter().map(|v| v.version).collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert_eq!(
+            history.get(&kind, 1).await.unwrap().policy,
+            encode("package policy\nallow = true\n")
+        );
+        assert_eq!(
+            history.get(&kind, 2).await.unwrap().signature.as_deref(),
+            Some("c2ln")
+        );
+        assert!(history.get(&kind, 3).await.is_err());
+        assert_eq!(history.latest_signed_version(&kind).await.unwrap(), Some(7));
+
+        // The policies are versioned separately.
+        assert!(history
+            .list(&PolicyKind::Resource)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(history
+            .list(&PolicyKind::Repository("default".into()))
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(history
+            .list(&PolicyKind::Attestation("../resource".into()))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,211 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Signatures of the resource and attestation policies set through the
+//! admin API. When a policy author key is configured, a policy is only
+//! activated if it is signed by the key, so that a compromised admin
+//! credential cannot silently weaken the policies.
+//!
+//! A signature is the Ed25519 signature of the [`signed_message`] of the
+//! policy, base64 encoded like the policy. It binds the policy to its target
+//! and to a version chosen by the policy author, which must be newer than
+//! the versions signed before, so that an older signed policy cannot be set
+//! again, nor a policy signed for another target.
+
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jwt_simple::prelude::{Ed25519PublicKey, EdDSAPublicKeyLike};
+use serde::Deserialize;
+use std::path::PathBuf;
+
+use crate::policy_history::{PolicyHistory, PolicyKind};
+
+/// Policy signing configuration.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PolicySigningConfig {
+    /// Ed25519 public key (PEM) of the policy author.
+    pub author_public_key: PathBuf,
+}
+
+/// Verifies the signatures of the policies, if a policy author key is
+/// configured.
+pub struct PolicyVerifier {
+    author_public_key: Option<Ed25519PublicKey>,
+}
+
+impl PolicyVerifier {
+    pub fn new(config: Option<&PolicySigningConfig>) -> Result<Self> {
+        let author_public_key = match config {
+            Some(config) => {
+                let pem = std::fs::read_to_string(&config.author_public_key)
+                    .context("read policy author public key")?;
+                let key =
+                    Ed25519PublicKey::from_pem(&pem).context("parse policy author public key")?;
+                Some(key)
+            }
+            None => None,
+        };
+
+        Ok(Self { author_public_key })
+    }
+
+    /// Verify the `signature` of the `policy` of `kind`, both base64
+    /// encoded, for its `signed_version`, which must be newer than the
+    /// versions of the policy signed before. Any policy is accepted if no
+    /// policy author key is configured.
+    pub async fn verify(
+        &self,
+        history: &PolicyHistory,
+        kind: &PolicyKind,
+        policy: &str,
+        signature: Option<&str>,
+        signed_version: Option<u64>,
+    ) -> Result<()> {
+        let Some(author_public_key) = &self.author_public_key else {
+            return Ok(());
+        };
+        let Some(signature) = signature else {
+            bail!("the policy is not signed by the policy author");
+        };
+        let Some(signed_version) = signed_version else {
+            bail!("the signed version of the policy is not given");
+        };
+
+        if let Some(latest) = history.latest_signed_version(kind).await? {
+            if signed_version <= latest {
+                bail!(
+                    "signed version {signed_version} of the policy is not newer than the signed version {latest}"
+                );
+            }
+        }
+
+        let policy = URL_SAFE_NO_PAD.decode(policy).context("decode policy")?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .context("decode policy signature")?;
+        let signature =
+            ed25519_compact::Signature::from_slice(&signature).context("parse policy signature")?;
+        author_public_key
+            .public_key()
+            .as_ref()
+            .verify(signed_message(kind, signed_version, &policy), &signature)
+            .context("the policy signature is not made by the policy author")?;
+
+        Ok(())
+    }
+}
+
+/// The message signed by the policy author: the target of the policy, i.e.
+/// `resource`, `repository` or `attestation`, its id, empty for the default
+/// resource policy, both prefixed by their length, and the version of the
+/// policy, all as 64-bit big-endian integers, followed by the policy.
+pub fn signed_message(kind: &PolicyKind, signed_version: u64, policy: &[u8]) -> Vec<u8> {
+    let (target, policy_id) = match kind {
+        PolicyKind::Resource => ("resource", ""),
+        PolicyKind::Repository(policy_id) => ("repository", policy_id.as_str()),
+        PolicyKind::Attestation(policy_id) => ("attestation", policy_id.as_str()),
+    };
+
+    let mut message = Vec::new();
+    for field in [target, policy_id] {
+        message.extend_from_slice(&(field.len() as u64).to_be_bytes());
+        message.extend_from_slice(field.as_bytes());
+    }
+    message.extend_from_slice(&signed_version.to_be_bytes());
+    message.extend_from_slice(policy);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_history::PolicyHistoryConfig;
+    use jwt_simple::prelude::Ed25519KeyPair;
+    use tempfile::{NamedTempFile, TempDir};
+
+    #[tokio::test]
+    async fn test_verify_policy_signature() {
+        let author = Ed25519KeyPair::generate();
+        let key_file = NamedTempFile::new().unwrap();
+        std::fs::write(key_file.path(), author.public_key().to_pem()).unwrap();
+        let verifier = PolicyVerifier::new(Some(&PolicySigningConfig {
+            author_public_key: key_file.path().to_path_buf(),
+        }))
+        .unwrap();
+        let dir = TempDir::new().unwrap();
+        let history = PolicyHistory::new(&PolicyHistoryConfig {
+            dir_path: dir.path().to_path_buf(),
+        })
+        .unwrap();
+
+        let kind = PolicyKind::Repository("tenant-a".into());
+        let key_pair = ed25519_compact::KeyPair::from_slice(&author.to_bytes()).unwrap();
+        let sign = |kind: &PolicyKind, version: u64, policy: &str| {
+            let signature = key_pair
+                .sk
+                .sign(signed_message(kind, version, policy.as_bytes()), None);
+            URL_SAFE_NO_PAD.encode(signature)
+        };
+
+        let policy = "package policy\ndefault allow = false\n";
+        let signature = sign(&kind, 1, policy);
+        let policy = URL_SAFE_NO_PAD.encode(policy);
+        verifier
+            .verify(&history, &kind, &policy, Some(&signature), Some(1))
+            .await
+            .unwrap();
+
+        assert!(verifier
+            .verify(&history, &kind, &policy, None, Some(1))
+            .await
+            .is_err());
+        assert!(verifier
+            .verify(&history, &kind, &policy, Some(&signature), None)
+            .await
+            .is_err());
+        let weakened = URL_SAFE_NO_PAD.encode("package policy\ndefault allow = true\n");
+        assert!(verifier
+            .verify(&history, &kind, &weakened, Some(&signature), Some(1))
+            .await
+            .is_err());
+
+        // The signature is bound to the target and the version of the policy.
+        assert!(verifier
+            .verify(
+                &history,
+                &PolicyKind::Resource,
+                &policy,
+                Some(&signature),
+                Some(1)
+            )
+            .await
+            .is_err());
+        assert!(verifier
+            .verify(&history, &kind, &policy, Some(&signature), Some(2))
+            .await
+            .is_err());
+
+        // A version signed before is not accepted again.
+        history
+            .record(&kind, &policy, Some(&signature), Some(1))
+            .await
+            .unwrap();
+        assert!(verifier
+            .verify(&history, &kind, &policy, Some(&signature), Some(1))
+            .await
+            .is_err());
+        let signature = sign(&kind, 2, "package policy\ndefault allow = false\n");
+        verifier
+            .verify(&history, &kind, &policy, Some(&signature), Some(2))
+            .await
+            .unwrap();
+
+        // Without a policy author key, the signatures are not required.
+        let verifier = PolicyVerifier::new(None).unwrap();
+        verifier
+            .verify(&history, &kind, &weakened, None, None)
+            .await
+            .unwrap();
+    }
+}
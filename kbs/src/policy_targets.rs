@@ -0,0 +1,80 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Where the policies which are not set through the admin API, e.g. pulled
+//! from a policy bundle or read from watched files, are applied.
+
+#[cfg(feature = "as")]
+use crate::attestation::AttestationService;
+use crate::audit::AuditLog;
+use crate::policy_engine::PolicyEngine;
+use crate::policy_history::{PolicyHistory, PolicyKind};
+use anyhow::{Context, Result};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub(crate) struct PolicyTargets {
+    pub policy_engine: PolicyEngine,
+    #[cfg(feature = "as")]
+    pub attestation_service: Option<Arc<AttestationService>>,
+    pub policy_history: Arc<PolicyHistory>,
+    pub audit_log: Arc<AuditLog>,
+
+    /// Who sets the policies in the audit log, e.g. `policy-watch`.
+    pub actor: &'static str,
+}
+
+impl PolicyTargets {
+    /// Set the `policy`, base64 encoded, and keep it as a new version of the
+    /// policy history, with its `signed_version` if it is signed. Return
+    /// whether it is set, i.e. `false` for an attestation policy without an
+    /// attestation service.
+    pub async fn set_policy(
+        &self,
+        kind: &PolicyKind,
+        policy: &str,
+        signed_version: Option<u64>,
+    ) -> Result<bool> {
+        match kind {
+            PolicyKind::Resource => {
+                self.policy_engine
+                    .set_policy(None, policy.to_string())
+                    .await?
+            }
+            PolicyKind::Repository(policy_id) => {
+                self.policy_engine
+                    .set_policy(Some(policy_id), policy.to_string())
+                    .await?
+            }
+            PolicyKind::Attestation(policy_id) => {
+                if !self.set_attestation_policy(policy_id, policy).await? {
+                    return Ok(false);
+                }
+            }
+        }
+
+        let change = self
+            .policy_history
+            .record(kind, policy, None, signed_version)
+            .await
+            .context("record policy version")?;
+        self.audit_log
+            .record_policy_change(self.actor, kind, change.version)
+            .await;
+        Ok(true)
+    }
+
+    /// Set the attestation policy `policy_id`, and return whether there is
+    /// an attestation service to set it.
+    async fn set_attestation_policy(&self, policy_id: &str, policy: &str) -> Result<bool> {
+        #[cfg(feature = "as")]
+        if let Some(attestation_service) = &self.attestation_service {
+            attestation_service.set_policy(policy_id, policy).await?;
+            return Ok(true);
+        }
+
+        let _ = (policy_id, policy);
+        Ok(false)
+    }
+}
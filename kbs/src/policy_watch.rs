@@ -0,0 +1,230 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Policies read from files which the KBS watches, e.g. the files of a
+//! Kubernetes ConfigMap, so that the policies can be changed without the
+//! admin API.
+//!
+//! The directories of the files are watched, rather than the files, as a
+//! ConfigMap is updated by replacing the symbolic link of its directory. On
+//! a change, every file whose content changed is set like through the admin
+//! API, and kept as a new version of the policy history. A file which is
+//! missing or fails to be set leaves the current policy in place.
+//!
+//! The files are not signed, so they cannot be watched when a policy author
+//! key is configured.
+
+use crate::policy_history::PolicyKind;
+use crate::policy_targets::PolicyTargets;
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use log::{info, warn};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Time the changes are collected for before the policies are reloaded, as
+/// an update usually emits several events.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct PolicyWatchConfig {
+    /// File of the default resource policy.
+    pub resource_policy: Option<PathBuf>,
+
+    /// Files of the repository resource policies, by policy id.
+    #[serde(default)]
+    pub repository_policies: HashMap<String, PathBuf>,
+
+    /// Files of the attestation policies, by policy id.
+    #[serde(default)]
+    pub attestation_policies: HashMap<String, PathBuf>,
+}
+
+pub struct PolicyWatcher {
+    files: Vec<(PolicyKind, PathBuf)>,
+
+    /// Hex SHA-256 digests of the last applied contents of the files.
+    applied: HashMap<PathBuf, String>,
+
+    events: mpsc::UnboundedReceiver<()>,
+
+    // Watches until it is dropped.
+    _watcher: RecommendedWatcher,
+}
+
+impl PolicyWatcher {
+    pub fn new(config: &PolicyWatchConfig) -> Result<Self> {
+        let mut files = Vec::new();
+        if let Some(path) = &config.resource_policy {
+            files.push((PolicyKind::Resource, path.clone()));
+        }
+        for (policy_id, path) in &config.repository_policies {
+            files.push((PolicyKind::Repository(policy_id.clone()), path.clone()));
+        }
+        for (policy_id, path) in &config.attestation_policies {
+            files.push((PolicyKind::Attestation(policy_id.clone()), path.clone()));
+        }
+
+        let (sender, events) = mpsc::unbounded_channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+                Ok(_) => {
+                    let _ = sender.send(());
+                }
+                Err(e) => warn!("Failed to watch the policy files: {e}"),
+            })
+            .context("create policy file watcher")?;
+
+        let dirs: HashSet<&Path> = files
+            .iter()
+            .map(|(_, path)| match path.parent() {
+                // The parent of a bare file name is "".
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            })
+            .collect();
+        for dir in dirs {
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .with_context(|| format!("watch {}", dir.display()))?;
+        }
+
+        Ok(Self {
+            files,
+            applied: HashMap::new(),
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    /// Set the policies of the files whose content changed since they were
+    /// last applied, and return how many were set.
+    async fn reload(&mut self, targets: &PolicyTargets) -> usize {
+        let mut reloaded = 0;
+        for (kind, path) in &self.files {
+            let policy = match tokio::fs::read(path).await {
+                Ok(policy) => policy,
+                Err(e) => {
+                    warn!("Failed to read the policy file {}: {e}", path.display());
+                    continue;
+                }
+            };
+            let digest = hex::encode(Sha256::digest(&policy));
+            if self.applied.get(path) == Some(&digest) {
+                continue;
+            }
+
+            match targets
+                .set_policy(kind, &URL_SAFE_NO_PAD.encode(&policy), None)
+                .await
+            {
+                Ok(true) => {
+                    info!("Policy {kind:?} reloaded from {}", path.display());
+                    reloaded += 1;
+                }
+                Ok(false) => warn!(
+                    "Ignoring the policy file {} without an attestation service",
+                    path.display()
+                ),
+                Err(e) => {
+                    warn!("Failed to reload the policy file {}: {e:?}", path.display());
+                    continue;
+                }
+            }
+            self.applied.insert(path.clone(), digest);
+        }
+
+        reloaded
+    }
+
+    /// Set the policies of the files now, then whenever they change.
+    pub(crate) async fn run(mut self, targets: PolicyTargets) {
+        self.reload(&targets).await;
+        while self.events.recv().await.is_some() {
+            tokio::time::sleep(DEBOUNCE).await;
+            while self.events.try_recv().is_ok() {}
+            self.reload(&targets).await;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "opa"))]
+mod tests {
+    use super::*;
+    use crate::audit::AuditLog;
+    use crate::policy_engine::{PolicyEngine, PolicyEngineConfig, RequestContext};
+    use crate::policy_history::{PolicyHistory, PolicyHistoryConfig};
+    use serde_json::json;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_reload_policy_files() {
+        let dir = TempDir::new().unwrap();
+        let watched = dir.path().join("configmap");
+        std::fs::create_dir(&watched).unwrap();
+        let policy_file = watched.join("policy.rego");
+        std::fs::write(&policy_file, "package policy\ndefault allow = true\n").unwrap();
+
+        let targets = PolicyTargets {
+            policy_engine: PolicyEngine::new(&PolicyEngineConfig {
+                policy_path: Some(dir.path().join("policy.rego")),
+                ..Default::default()
+            })
+            .await
+            .unwrap(),
+            #[cfg(feature = "as")]
+            attestation_service: None,
+            policy_history: Arc::new(
+                PolicyHistory::new(&PolicyHistoryConfig {
+                    dir_path: dir.path().join("history"),
+                })
+                .unwrap(),
+            ),
+            audit_log: Arc::new(AuditLog::new(None).await.unwrap()),
+            actor: "policy-watch",
+        };
+        let watcher = PolicyWatcher::new(&PolicyWatchConfig {
+            resource_policy: Some(policy_file.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+        tokio::spawn(watcher.run(targets.clone()));
+
+        let evaluate = || async {
+            let input = json!({ "tcb-status": { "productId": "Alice" } }).to_string();
+            targets
+                .policy_engine
+                .evaluate("my_repo/key/1".into(), input, &RequestContext::default())
+                .await
+                .unwrap()
+        };
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(evaluate().await);
+
+        // Replaced like a ConfigMap, by renaming the new file.
+        let new_file = watched.join(".policy.rego.new");
+        std::fs::write(&new_file, "package policy\ndefault allow = false\n").unwrap();
+        std::fs::rename(&new_file, &policy_file).unwrap();
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert!(!evaluate().await);
+
+        // A removed file keeps the current policy.
+        std::fs::remove_file(&policy_file).unwrap();
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert!(!evaluate().await);
+
+        let versions = targets
+            .policy_history
+            .list(&PolicyKind::Resource)
+            .await
+            .unwrap();
+        assert_eq!(versions.len(), 2);
+    }
+}
@@ -0,0 +1,259 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::envelope::{Envelope, MasterKeyConfig};
+use super::{Repository, ResourceDesc};
+use anyhow::{anyhow, Context, Result};
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_secretsmanager::primitives::Blob;
+use log::info;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AwsSecretsManagerRepoDesc {
+    /// AWS region. If not given, the region is resolved from the environment
+    /// (`AWS_REGION`, profile, IMDS...).
+    pub region: Option<String>,
+
+    /// Optional prefix of the secret names, e.g. `kbs/`.
+    pub secret_prefix: Option<String>,
+
+    /// Id or ARN of a KMS key. If given, resources are envelope encrypted
+    /// with data keys generated by this key, so that their size is not
+    /// limited by KMS.
+    pub kms_key_id: Option<String>,
+}
+
+pub struct AwsSecretsManagerBackend {
+    secrets_manager: aws_sdk_secretsmanager::Client,
+    kms: aws_sdk_kms::Client,
+    secret_prefix: String,
+    kms_key_id: Option<String>,
+    envelope: Option<Envelope>,
+}
+
+#[async_trait::async_trait]
+impl Repository for AwsSecretsManagerBackend {
+    async fn read_secret_resource(&self, resource_desc: ResourceDesc) -> Result<Vec<u8>> {
+        let name = secret_name(&self.secret_prefix, &resource_desc);
+        let secret = self
+            .secrets_manager
+            .get_secret_value()
+            .secret_id(&name)
+            .send()
+            .await
+            .with_context(|| format!("failed to get secret `{name}` from AWS Secrets Manager"))?;
+
+        let data = match (secret.secret_binary(), secret.secret_string()) {
+            (Some(binary), _) => binary.as_ref().to_vec(),
+            (None, Some(string)) => string.as_bytes().to_vec(),
+            (None, None) => return Err(anyhow!("secret `{name}` has no value")),
+        };
+
+        self.open(&resource_desc, data)
+            .await
+            .with_context(|| format!("failed to decrypt secret `{name}`"))
+    }
+
+    async fn write_secret_resource(
+        &mut self,
+        resource_desc: ResourceDesc,
+        data: &[u8],
+    ) -> Result<()> {
+        let name = secret_name(&self.secret_prefix, &resource_desc);
+        let data = Blob::new(
+            self.seal(&resource_desc, data)
+                .await
+                .with_context(|| format!("failed to encrypt secret `{name}`"))?,
+        );
+
+        let updated = self
+            .secrets_manager
+            .put_secret_value()
+            .secret_id(&name)
+            .secret_binary(data.clone())
+            .send()
+            .await;
+
+        match updated {
+            Ok(_) => Ok(()),
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(|e| e.is_resource_not_found_exception()) =>
+            {
+                info!("Create new secret `{name}` in AWS Secrets Manager");
+                self.secrets_manager
+                    .create_secret()
+                    .name(&name)
+                    .secret_binary(data)
+                    .send()
+                    .await
+                    .with_context(|| {
+                        format!("failed to create secret `{name}` in AWS Secrets Manager")
+                    })?;
+                Ok(())
+            }
+            Err(e) => Err(e)
+                .with_context(|| format!("failed to put secret `{name}` to AWS Secrets Manager")),
+        }
+    }
+
+    /// The secret is deleted without a recovery window, so that a resource
+    /// with the same path can be set again right away.
+    async fn delete_secret_resource(&mut self, resource_desc: ResourceDesc) -> Result<()> {
+        let name = secret_name(&self.secret_prefix, &resource_desc);
+        self.secrets_manager
+            .delete_secret()
+            .secret_id(&name)
+            .force_delete_without_recovery(true)
+            .send()
+            .await
+            .with_context(|| {
+                format!("failed to delete secret `{name}` from AWS Secrets Manager")
+            })?;
+        Ok(())
+    }
+}
+
+impl AwsSecretsManagerBackend {
+    pub async fn new(repo_desc: &AwsSecretsManagerRepoDesc) -> Result<Self> {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest());
+        if let Some(region) = &repo_desc.region {
+            loader = loader.region(Region::new(region.clone()));
+        }
+
+        let sdk_config = loader.load().await;
+        let envelope = match &repo_desc.kms_key_id {
+            Some(key_id) => Some(
+                Envelope::new(&MasterKeyConfig::AwsKms {
+                    key_id: key_id.clone(),
+                    region: repo_desc.region.clone(),
+                })
+                .await?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            secrets_manager: aws_sdk_secretsmanager::Client::new(&sdk_config),
+            kms: aws_sdk_kms::Client::new(&sdk_config),
+            secret_prefix: repo_desc.secret_prefix.clone().unwrap_or_default(),
+            kms_key_id: repo_desc.kms_key_id.clone(),
+            envelope,
+        })
+    }
+
+    /// Envelope encrypt the data of a resource if a KMS key is given, with
+    /// the resource path as AAD.
+    async fn seal(&self, resource_desc: &ResourceDesc, data: &[u8]) -> Result<Vec<u8>> {
+        match &self.envelope {
+            Some(envelope) => {
+                envelope
+                    .seal(data, secret_name("", resource_desc).as_bytes())
+                    .await
+            }
+            None => Ok(data.to_vec()),
+        }
+    }
+
+    /// Decrypt the data of a resource if a KMS key is given. Secrets written
+    /// before the envelope encryption, which are KMS ciphertexts rather than
+    /// JSON, are still decrypted with KMS directly.
+    async fn open(&self, resource_desc: &ResourceDesc, data: Vec<u8>) -> Result<Vec<u8>> {
+        let (Some(envelope), Some(kms_key_id)) = (&self.envelope, &self.kms_key_id) else {
+            return Ok(data);
+        };
+
+        if data.first() == Some(&b'{') {
+            return envelope
+                .open(&data, secret_name("", resource_desc).as_bytes())
+                .await;
+        }
+
+        let plaintext = self
+            .kms
+            .decrypt()
+            .key_id(kms_key_id)
+            .ciphertext_blob(Blob::new(data))
+            .send()
+            .await
+            .context("failed to unwrap secret with AWS KMS")?
+            .plaintext
+            .ok_or_else(|| anyhow!("AWS KMS returned no plaintext"))?;
+        Ok(plaintext.into_inner())
+    }
+}
+
+fn secret_name(prefix: &str, resource_desc: &ResourceDesc) -> String {
+    format!(
+        "{prefix}{}/{}/{}",
+        resource_desc.repository_name, resource_desc.resource_type, resource_desc.resource_tag
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use rstest::rstest;
+
+    use super::AwsSecretsManagerBackend;
+    use crate::resource::envelope::{Envelope, MasterKeyConfig};
+    use crate::resource::ResourceDesc;
+
+    #[rstest]
+    #[case("", "default/key/1")]
+    #[case("kbs/", "kbs/default/key/1")]
+    fn secret_name(#[case] prefix: &str, #[case] expected: &str) {
+        let resource_desc = ResourceDesc {
+            repository_name: "default".into(),
+            resource_type: "key".into(),
+            resource_tag: "1".into(),
+        };
+
+        assert_eq!(super::secret_name(prefix, &resource_desc), expected);
+    }
+
+    /// Resources larger than the 4 KiB plaintext limit of KMS are sealed,
+    /// and bound to their path.
+    #[tokio::test]
+    async fn seal_large_resource() {
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        key_file.write_all(&[7; 32]).unwrap();
+        let envelope = Envelope::new(&MasterKeyConfig::File {
+            key_path: key_file.path().to_string_lossy().to_string(),
+        })
+        .await
+        .unwrap();
+
+        let sdk_config = aws_config::SdkConfig::builder()
+            .behavior_version(aws_config::BehaviorVersion::latest())
+            .build();
+        let backend = AwsSecretsManagerBackend {
+            secrets_manager: aws_sdk_secretsmanager::Client::new(&sdk_config),
+            kms: aws_sdk_kms::Client::new(&sdk_config),
+            secret_prefix: String::new(),
+            kms_key_id: Some("alias/kbs".into()),
+            envelope: Some(envelope),
+        };
+
+        let resource_desc = ResourceDesc {
+            repository_name: "default".into(),
+            resource_type: "key".into(),
+            resource_tag: "1".into(),
+        };
+        let data = vec![42; 16 * 1024];
+        let sealed = backend.seal(&resource_desc, &data).await.unwrap();
+        assert_eq!(
+            backend.open(&resource_desc, sealed.clone()).await.unwrap(),
+            data
+        );
+
+        let other_desc = ResourceDesc {
+            resource_tag: "2".into(),
+            ..resource_desc
+        };
+        assert!(backend.open(&other_desc, sealed).await.is_err());
+    }
+}
@@ -0,0 +1,322 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{Repository, ResourceDesc};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Deserializer};
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+const KEY_VAULT_API_VERSION: &str = "7.4";
+const KEY_VAULT_RESOURCE: &str = "https://vault.azure.net";
+const IMDS_TOKEN_ENDPOINT: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+const IMDS_API_VERSION: &str = "2018-02-01";
+const DEFAULT_AUTHORITY_HOST: &str = "https://login.microsoftonline.com";
+
+/// An access token is refreshed once less than this much of its lifetime is left.
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AzureKeyVaultRepoDesc {
+    /// URL of the key vault, e.g. `https://my-vault.vault.azure.net`.
+    pub vault_url: String,
+
+    /// Credential used to get access tokens for the key vault.
+    pub credential: AzureCredential,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "method")]
+pub enum AzureCredential {
+    /// Managed identity of the Azure VM the KBS runs in.
+    ManagedIdentity {
+        /// Client id of a user-assigned identity. The system-assigned
+        /// identity is used if not given.
+        client_id: Option<String>,
+    },
+
+    /// Service principal with a client secret.
+    ServicePrincipal {
+        tenant_id: String,
+        client_id: String,
+        client_secret: String,
+        /// Microsoft Entra authority host. Defaults to
+        /// `https://login.microsoftonline.com`.
+        authority_host: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    /// IMDS returns this as a string, Entra ID as a number.
+    #[serde(deserialize_with = "deserialize_u64_or_string")]
+    expires_in: u64,
+}
+
+#[derive(Deserialize)]
+struct SecretBundle {
+    value: String,
+}
+
+struct AccessToken {
+    token: String,
+    expires_at: Instant,
+}
+
+impl AccessToken {
+    fn is_expiring(&self) -> bool {
+        Instant::now() + TOKEN_REFRESH_MARGIN >= self.expires_at
+    }
+}
+
+pub struct AzureKeyVaultBackend {
+    client: reqwest::Client,
+    vault_url: String,
+    credential: AzureCredential,
+    token: RwLock<Option<AccessToken>>,
+}
+
+#[async_trait::async_trait]
+impl Repository for AzureKeyVaultBackend {
+    async fn read_secret_resource(&self, resource_desc: ResourceDesc) -> Result<Vec<u8>> {
+        let name = secret_name(&resource_desc)?;
+        let response = self
+            .client
+            .get(self.secret_url(&name))
+            .bearer_auth(self.token().await?)
+            .send()
+            .await
+            .context("failed to get secret from Azure Key Vault")?;
+
+        if !response.status().is_success() {
+            bail!(
+                "Azure Key Vault returned {} when getting secret `{name}`",
+                response.status()
+            );
+        }
+
+        let secret: SecretBundle = response
+            .json()
+            .await
+            .context("illegal Azure Key Vault secret response")?;
+        Ok(secret.value.into_bytes())
+    }
+
+    async fn write_secret_resource(
+        &mut self,
+        resource_desc: ResourceDesc,
+        data: &[u8],
+    ) -> Result<()> {
+        let name = secret_name(&resource_desc)?;
+        let value = std::str::from_utf8(data)
+            .context("Azure Key Vault backend only supports UTF-8 resources")?;
+        let response = self
+            .client
+            .put(self.secret_url(&name))
+            .bearer_auth(self.token().await?)
+            .json(&json!({ "value": value }))
+            .send()
+            .await
+            .context("failed to set secret to Azure Key Vault")?;
+
+        if !response.status().is_success() {
+            bail!(
+                "Azure Key Vault returned {} when setting secret `{name}`",
+                response.status()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// On vaults with soft-delete enabled, the secret is kept as a deleted
+    /// secret until it is purged or its retention period is over.
+    async fn delete_secret_resource(&mut self, resource_desc: ResourceDesc) -> Result<()> {
+        let name = secret_name(&resource_desc)?;
+        let response = self
+            .client
+            .delete(self.secret_url(&name))
+            .bearer_auth(self.token().await?)
+            .send()
+            .await
+            .context("failed to delete secret from Azure Key Vault")?;
+
+        if !response.status().is_success() {
+            bail!(
+                "Azure Key Vault returned {} when deleting secret `{name}`",
+                response.status()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl AzureKeyVaultBackend {
+    pub fn new(repo_desc: &AzureKeyVaultRepoDesc) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .build()
+            .context("build Azure Key Vault HTTP client")?;
+
+        Ok(Self {
+            client,
+            vault_url: repo_desc.vault_url.trim_end_matches('/').to_string(),
+            credential: repo_desc.credential.clone(),
+            token: RwLock::new(None),
+        })
+    }
+
+    fn secret_url(&self, name: &str) -> String {
+        format!(
+            "{}/secrets/{name}?api-version={KEY_VAULT_API_VERSION}",
+            self.vault_url
+        )
+    }
+
+    /// Get a valid access token, fetching a new one if there is none yet or
+    /// the cached one is about to expire.
+    async fn token(&self) -> Result<String> {
+        if let Some(token) = &*self.token.read().await {
+            if !token.is_expiring() {
+                return Ok(token.token.clone());
+            }
+        }
+
+        let mut token = self.token.write().await;
+        if let Some(token) = &*token {
+            if !token.is_expiring() {
+                return Ok(token.token.clone());
+            }
+        }
+
+        let response = match &self.credential {
+            AzureCredential::ManagedIdentity { client_id } => {
+                let mut query = vec![
+                    ("api-version", IMDS_API_VERSION),
+                    ("resource", KEY_VAULT_RESOURCE),
+                ];
+                if let Some(client_id) = client_id {
+                    query.push(("client_id", client_id.as_str()));
+                }
+
+                self.client
+                    .get(IMDS_TOKEN_ENDPOINT)
+                    .header("Metadata", "true")
+                    .query(&query)
+                    .send()
+                    .await
+                    .context("request managed identity token")?
+            }
+            AzureCredential::ServicePrincipal {
+                tenant_id,
+                client_id,
+                client_secret,
+                authority_host,
+            } => {
+                let authority_host = authority_host
+                    .as_deref()
+                    .unwrap_or(DEFAULT_AUTHORITY_HOST)
+                    .trim_end_matches('/');
+                let scope = format!("{KEY_VAULT_RESOURCE}/.default");
+                self.client
+                    .post(format!("{authority_host}/{tenant_id}/oauth2/v2.0/token"))
+                    .form(&[
+                        ("grant_type", "client_credentials"),
+                        ("client_id", client_id.as_str()),
+                        ("client_secret", client_secret.as_str()),
+                        ("scope", scope.as_str()),
+                    ])
+                    .send()
+                    .await
+                    .context("request service principal token")?
+            }
+        };
+
+        if !response.status().is_success() {
+            bail!("Azure token endpoint returned {}", response.status());
+        }
+
+        let response: TokenResponse = response
+            .json()
+            .await
+            .context("illegal Azure token response")?;
+        let access_token = response.access_token.clone();
+        *token = Some(AccessToken {
+            token: response.access_token,
+            expires_at: Instant::now() + Duration::from_secs(response.expires_in),
+        });
+
+        Ok(access_token)
+    }
+}
+
+/// Azure Key Vault secret names may only contain alphanumeric characters and
+/// dashes, so `repo/type/tag` is mapped to `repo-type-tag`.
+fn secret_name(resource_desc: &ResourceDesc) -> Result<String> {
+    let name = format!(
+        "{}-{}-{}",
+        resource_desc.repository_name, resource_desc.resource_type, resource_desc.resource_tag
+    );
+
+    if name.len() > 127 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        bail!("`{name}` is not a valid Azure Key Vault secret name");
+    }
+
+    Ok(name)
+}
+
+fn deserialize_u64_or_string<'de, D>(deserializer: D) -> std::result::Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum U64OrString {
+        U64(u64),
+        String(String),
+    }
+
+    match U64OrString::deserialize(deserializer)? {
+        U64OrString::U64(v) => Ok(v),
+        U64OrString::String(s) => s.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::resource::ResourceDesc;
+
+    use super::TokenResponse;
+
+    #[rstest]
+    #[case("default", "key", "1", Some("default-key-1"))]
+    #[case("default", "key_type", "1", None)]
+    #[case("default", "key", "a.b", None)]
+    fn secret_name(
+        #[case] repository: &str,
+        #[case] resource_type: &str,
+        #[case] tag: &str,
+        #[case] expected: Option<&str>,
+    ) {
+        let resource_desc = ResourceDesc {
+            repository_name: repository.into(),
+            resource_type: resource_type.into(),
+            resource_tag: tag.into(),
+        };
+
+        assert_eq!(super::secret_name(&resource_desc).ok().as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case(r#"{"access_token": "t", "expires_in": "3599"}"#)]
+    #[case(r#"{"access_token": "t", "expires_in": 3599}"#)]
+    fn parse_token_response(#[case] response: &str) {
+        let response: TokenResponse = serde_json::from_str(response).unwrap();
+        assert_eq!(response.expires_in, 3599);
+    }
+}
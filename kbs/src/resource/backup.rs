@@ -0,0 +1,512 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Scheduled backups of the resource repository.
+//!
+//! A snapshot holds all the resources of the repository, and the files of the
+//! configured state directories (e.g. the `work_dir` of the attestation
+//! service). Snapshots are envelope encrypted and stored in a local directory
+//! or in an S3 bucket, and the latest one can be restored on startup.
+
+use super::bundle::{export_resources, import_manifest, Manifest};
+use super::envelope::{Envelope, MasterKeyConfig};
+use super::Repository;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::sync::RwLock;
+
+const DEFAULT_BACKUP_INTERVAL: u64 = 3600;
+const DEFAULT_BACKUP_RETENTION: usize = 24;
+
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const SNAPSHOT_SUFFIX: &str = ".json";
+
+/// Name of the directory of the snapshots in an S3 bucket.
+#[cfg(feature = "s3")]
+const S3_SNAPSHOT_DIR: &str = "snapshots";
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct BackupConfig {
+    /// Where the snapshots are stored.
+    pub destination: BackupDestination,
+
+    /// Master key encrypting the snapshots.
+    pub encryption: MasterKeyConfig,
+
+    /// Seconds between two snapshots. Defaults to an hour.
+    #[serde(default = "default_backup_interval")]
+    pub interval: u64,
+
+    /// Number of snapshots kept, older ones are deleted. Defaults to 24.
+    #[serde(default = "default_backup_retention")]
+    pub retention: usize,
+
+    /// Directories whose files are saved with the resources, such as the
+    /// `work_dir` of the attestation service.
+    #[serde(default)]
+    pub state_dirs: Vec<PathBuf>,
+
+    /// Restore the latest snapshot on startup if the repository is empty.
+    #[serde(default)]
+    pub restore_on_startup: bool,
+}
+
+fn default_backup_interval() -> u64 {
+    DEFAULT_BACKUP_INTERVAL
+}
+
+fn default_backup_retention() -> usize {
+    DEFAULT_BACKUP_RETENTION
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum BackupDestination {
+    LocalFs {
+        dir_path: String,
+    },
+
+    /// The snapshots are stored under `snapshots/` below the bucket prefix.
+    #[cfg(feature = "s3")]
+    S3(super::s3::S3RepoDesc),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    #[serde(with = "time::serde::rfc3339")]
+    created_at: OffsetDateTime,
+
+    #[serde(flatten)]
+    manifest: Manifest,
+
+    #[serde(default)]
+    files: Vec<StateFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StateFile {
+    path: PathBuf,
+
+    /// Base64 encoded file content.
+    data: String,
+}
+
+#[async_trait::async_trait]
+trait SnapshotStore {
+    async fn put(&self, name: &str, data: Vec<u8>) -> Result<()>;
+
+    async fn get(&self, name: &str) -> Result<Vec<u8>>;
+
+    async fn delete(&self, name: &str) -> Result<()>;
+
+    /// List the names of all the stored objects.
+    async fn list(&self) -> Result<Vec<String>>;
+}
+
+pub struct Backup {
+    store: Box<dyn SnapshotStore + Send + Sync>,
+    envelope: Envelope,
+    interval: Duration,
+    retention: usize,
+    state_dirs: Vec<PathBuf>,
+    restore_on_startup: bool,
+}
+
+impl Backup {
+    pub async fn new(config: &BackupConfig) -> Result<Self> {
+        if config.interval == 0 {
+            bail!("backup interval must not be 0");
+        }
+
+        if config.retention == 0 {
+            bail!("backup retention must not be 0");
+        }
+
+        let store: Box<dyn SnapshotStore + Send + Sync> = match &config.destination {
+            BackupDestination::LocalFs { dir_path } => {
+                tokio::fs::create_dir_all(dir_path)
+                    .await
+                    .context("create backup directory")?;
+                Box::new(LocalFsStore {
+                    dir_path: PathBuf::from(dir_path),
+                })
+            }
+            #[cfg(feature = "s3")]
+            BackupDestination::S3(desc) => Box::new(super::s3::S3Backend::new(desc).await?),
+        };
+
+        Ok(Self {
+            store,
+            envelope: Envelope::new(&config.encryption).await?,
+            interval: Duration::from_secs(config.interval),
+            retention: config.retention,
+            state_dirs: config.state_dirs.clone(),
+            restore_on_startup: config.restore_on_startup,
+        })
+    }
+
+    /// Take a snapshot of the repository and the state directories, then
+    /// delete the snapshots beyond the retention. Returns the name of the
+    /// new snapshot.
+    pub async fn snapshot(
+        &self,
+        repository: &Arc<RwLock<dyn Repository + Send + Sync>>,
+    ) -> Result<String> {
+        let mut files = Vec::new();
+        for dir in &self.state_dirs {
+            read_state_dir(dir, &mut files)
+                .await
+                .with_context(|| format!("read state directory {}", dir.display()))?;
+        }
+
+        let snapshot = Snapshot {
+            created_at: OffsetDateTime::now_utc(),
+            manifest: export_resources(repository, &[]).await?,
+            files,
+        };
+
+        let name = snapshot_name(&snapshot.created_at);
+        let sealed = self
+            .envelope
+            .seal(&serde_json::to_vec(&snapshot)?, name.as_bytes())
+            .await?;
+        self.store.put(&name, sealed).await?;
+
+        let snapshots = self.snapshots().await?;
+        for name in snapshots.iter().rev().skip(self.retention) {
+            info!("Delete backup {name}");
+            self.store.delete(name).await?;
+        }
+
+        Ok(name)
+    }
+
+    /// Restore the latest snapshot if `restore_on_startup` is set and the
+    /// repository is empty. State files are only written where missing.
+    /// Returns the name of the restored snapshot, if any.
+    pub async fn restore_on_startup(
+        &self,
+        repository: &Arc<RwLock<dyn Repository + Send + Sync>>,
+    ) -> Result<Option<String>> {
+        if !self.restore_on_startup {
+            return Ok(None);
+        }
+
+        let stored = repository
+            .read()
+            .await
+            .list_secret_resources()
+            .await
+            .context("backups require a repository that supports listing")?;
+        if !stored.is_empty() {
+            info!("Repository is not empty, skip restoring a backup");
+            return Ok(None);
+        }
+
+        let Some(name) = self.snapshots().await?.pop() else {
+            info!("No backup to restore");
+            return Ok(None);
+        };
+
+        self.restore(repository, &name).await?;
+        Ok(Some(name))
+    }
+
+    async fn restore(
+        &self,
+        repository: &Arc<RwLock<dyn Repository + Send + Sync>>,
+        name: &str,
+    ) -> Result<()> {
+        let sealed = self.store.get(name).await?;
+        let snapshot = self
+            .envelope
+            .open(&sealed, name.as_bytes())
+            .await
+            .with_context(|| format!("decrypt backup {name}"))?;
+        let snapshot: Snapshot = serde_json::from_slice(&snapshot).context("illegal backup")?;
+
+        for file in snapshot.files {
+            if file.path.exists() {
+                continue;
+            }
+
+            let data = STANDARD
+                .decode(&file.data)
+                .with_context(|| format!("illegal data of {}", file.path.display()))?;
+            if let Some(parent) = file.path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(&file.path, data)
+                .await
+                .with_context(|| format!("restore {}", file.path.display()))?;
+        }
+
+        let restored = import_manifest(repository, snapshot.manifest).await?;
+        info!(
+            "Restored {restored} resources from backup {name} taken at {}",
+            snapshot.created_at
+        );
+        Ok(())
+    }
+
+    /// Names of the stored snapshots, oldest first.
+    async fn snapshots(&self) -> Result<Vec<String>> {
+        let mut snapshots: Vec<(i64, String)> = self
+            .store
+            .list()
+            .await?
+            .into_iter()
+            .filter_map(|name| Some((snapshot_timestamp(&name)?, name)))
+            .collect();
+        snapshots.sort_unstable();
+        Ok(snapshots.into_iter().map(|(_, name)| name).collect())
+    }
+
+    /// Take a snapshot every `interval`.
+    pub async fn run(self, repository: Arc<RwLock<dyn Repository + Send + Sync>>) {
+        loop {
+            tokio::time::sleep(self.interval).await;
+            match self.snapshot(&repository).await {
+                Ok(name) => info!("Created backup {name}"),
+                Err(e) => warn!("Failed to back up the repository: {e:?}"),
+            }
+        }
+    }
+}
+
+fn snapshot_name(created_at: &OffsetDateTime) -> String {
+    format!(
+        "{SNAPSHOT_PREFIX}{}{SNAPSHOT_SUFFIX}",
+        created_at.unix_timestamp()
+    )
+}
+
+fn snapshot_timestamp(name: &str) -> Option<i64> {
+    name.strip_prefix(SNAPSHOT_PREFIX)?
+        .strip_suffix(SNAPSHOT_SUFFIX)?
+        .parse()
+        .ok()
+}
+
+/// Collect the regular files below `dir`, symbolic links are not followed.
+async fn read_state_dir(dir: &Path, files: &mut Vec<StateFile>) -> Result<()> {
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                dirs.push(entry.path());
+            } else if file_type.is_file() {
+                files.push(StateFile {
+                    data: STANDARD.encode(tokio::fs::read(entry.path()).await?),
+                    path: entry.path(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+struct LocalFsStore {
+    dir_path: PathBuf,
+}
+
+#[async_trait::async_trait]
+impl SnapshotStore for LocalFsStore {
+    async fn put(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        // Write to a temporary file first, so that a crash never leaves a
+        // truncated snapshot behind.
+        let tmp_path = self.dir_path.join(format!(".{name}"));
+        tokio::fs::write(&tmp_path, data)
+            .await
+            .context("write backup")?;
+        tokio::fs::rename(&tmp_path, self.dir_path.join(name))
+            .await
+            .context("write backup")
+    }
+
+    async fn get(&self, name: &str) -> Result<Vec<u8>> {
+        tokio::fs::read(self.dir_path.join(name))
+            .await
+            .with_context(|| format!("read backup {name}"))
+    }
+
+    async fn delete(&self, name: &str) -> Result<()> {
+        tokio::fs::remove_file(self.dir_path.join(name))
+            .await
+            .with_context(|| format!("delete backup {name}"))
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir_path)
+            .await
+            .context("list backups")?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                names.push(
+                    entry
+                        .file_name()
+                        .into_string()
+                        .map_err(|name| anyhow!("illegal backup file name {name:?}"))?,
+                );
+            }
+        }
+
+        Ok(names)
+    }
+}
+
+#[cfg(feature = "s3")]
+#[async_trait::async_trait]
+impl SnapshotStore for super::s3::S3Backend {
+    async fn put(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        self.put_object(&format!("{S3_SNAPSHOT_DIR}/{name}"), data)
+            .await
+    }
+
+    async fn get(&self, name: &str) -> Result<Vec<u8>> {
+        self.get_object(&format!("{S3_SNAPSHOT_DIR}/{name}")).await
+    }
+
+    async fn delete(&self, name: &str) -> Result<()> {
+        self.delete_object(&format!("{S3_SNAPSHOT_DIR}/{name}"))
+            .await
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        self.list_objects(S3_SNAPSHOT_DIR).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use tokio::sync::RwLock;
+
+    use super::{Backup, BackupConfig, BackupDestination};
+    use crate::resource::{
+        envelope::MasterKeyConfig,
+        local_fs::{LocalFs, LocalFsRepoDesc},
+        Repository, ResourceDesc,
+    };
+
+    async fn local_fs(dir: &std::path::Path) -> Arc<RwLock<dyn Repository + Send + Sync>> {
+        std::fs::create_dir_all(dir).unwrap();
+        let repo_desc = LocalFsRepoDesc {
+            dir_path: Some(dir.to_string_lossy().to_string()),
+            encryption: None,
+        };
+        Arc::new(RwLock::new(LocalFs::new(&repo_desc).await.unwrap()))
+    }
+
+    #[tokio::test]
+    async fn snapshot_and_restore() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        key_file.write_all(&[7; 32]).unwrap();
+
+        let state_dir = tmp_dir.path().join("state");
+        std::fs::create_dir_all(state_dir.join("ca")).unwrap();
+        std::fs::write(state_dir.join("ca/ca.key"), b"ca key").unwrap();
+
+        let config = BackupConfig {
+            destination: BackupDestination::LocalFs {
+                dir_path: tmp_dir.path().join("backups").to_string_lossy().to_string(),
+            },
+            encryption: MasterKeyConfig::File {
+                key_path: key_file.path().to_string_lossy().to_string(),
+            },
+            interval: 60,
+            retention: 2,
+            state_dirs: vec![state_dir.clone()],
+            restore_on_startup: true,
+        };
+        let backup = Backup::new(&config).await.unwrap();
+
+        let source = local_fs(&tmp_dir.path().join("source")).await;
+        let resource_desc = ResourceDesc {
+            repository_name: "default".into(),
+            resource_type: "key".into(),
+            resource_tag: "1".into(),
+        };
+        source
+            .write()
+            .await
+            .write_secret_resource(resource_desc.clone(), b"secret")
+            .await
+            .unwrap();
+
+        let name = backup.snapshot(&source).await.unwrap();
+        let sealed = std::fs::read(tmp_dir.path().join("backups").join(&name)).unwrap();
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+
+        // A non-empty repository is left untouched.
+        assert!(backup.restore_on_startup(&source).await.unwrap().is_none());
+
+        std::fs::remove_dir_all(&state_dir).unwrap();
+        let target = local_fs(&tmp_dir.path().join("target")).await;
+        assert_eq!(
+            backup.restore_on_startup(&target).await.unwrap(),
+            Some(name)
+        );
+        let data = target
+            .read()
+            .await
+            .read_secret_resource(resource_desc)
+            .await
+            .unwrap();
+        assert_eq!(data, b"secret");
+        assert_eq!(
+            std::fs::read(state_dir.join("ca/ca.key")).unwrap(),
+            b"ca key"
+        );
+    }
+
+    #[tokio::test]
+    async fn retention() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        key_file.write_all(&[7; 32]).unwrap();
+
+        let backups = tmp_dir.path().join("backups");
+        std::fs::create_dir_all(&backups).unwrap();
+        for timestamp in [100, 200, 300] {
+            std::fs::write(backups.join(format!("snapshot-{timestamp}.json")), b"").unwrap();
+        }
+
+        let config = BackupConfig {
+            destination: BackupDestination::LocalFs {
+                dir_path: backups.to_string_lossy().to_string(),
+            },
+            encryption: MasterKeyConfig::File {
+                key_path: key_file.path().to_string_lossy().to_string(),
+            },
+            interval: 60,
+            retention: 2,
+            state_dirs: Vec::new(),
+            restore_on_startup: false,
+        };
+        let backup = Backup::new(&config).await.unwrap();
+        let name = backup
+            .snapshot(&local_fs(&tmp_dir.path().join("repository")).await)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            backup.snapshots().await.unwrap(),
+            ["snapshot-300.json", &name]
+        );
+    }
+}
@@ -0,0 +1,354 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Bulk import and export of resources.
+//!
+//! Resources are imported from a JSON manifest or from a (gzipped) tarball
+//! whose files are laid out as `<repository>/<type>/<tag>`, and exported as a
+//! JSON manifest.
+
+use super::{set_secret_resource, Repository, ResourceDesc};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::sync::Arc;
+use time::OffsetDateTime;
+use tokio::sync::RwLock;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub resources: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Resource path, i.e. `<repository>/<type>/<tag>`.
+    pub path: String,
+
+    /// Base64 encoded resource data.
+    pub data: String,
+
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub expires_at: Option<OffsetDateTime>,
+
+    /// Base64 encoded detached signature of the resource, required by a
+    /// verified repository with a signature key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+struct Resource {
+    resource_desc: ResourceDesc,
+    data: Vec<u8>,
+    expires_at: Option<OffsetDateTime>,
+    signature: Option<Vec<u8>>,
+}
+
+/// Import all the resources of a JSON manifest or a tarball into the
+/// repository, and return how many were imported. The bundle is parsed and
+/// validated as a whole before any resource is written.
+pub(crate) async fn import_resources(
+    repository: &Arc<RwLock<dyn Repository + Send + Sync>>,
+    bundle: &[u8],
+) -> Result<usize> {
+    write_resources(repository, parse_bundle(bundle)?).await
+}
+
+/// Import all the resources of a manifest into the repository.
+pub(crate) async fn import_manifest(
+    repository: &Arc<RwLock<dyn Repository + Send + Sync>>,
+    manifest: Manifest,
+) -> Result<usize> {
+    write_resources(repository, manifest_resources(manifest)?).await
+}
+
+async fn write_resources(
+    repository: &Arc<RwLock<dyn Repository + Send + Sync>>,
+    resources: Vec<Resource>,
+) -> Result<usize> {
+    for (imported, resource) in resources.iter().enumerate() {
+        set_secret_resource(
+            repository,
+            resource.resource_desc.clone(),
+            &resource.data,
+            resource.signature.as_deref(),
+            resource.expires_at,
+        )
+        .await
+        .with_context(|| {
+            format!(
+                "import {}, {imported} resources imported before",
+                path(&resource.resource_desc)
+            )
+        })?;
+    }
+
+    Ok(resources.len())
+}
+
+/// Export the resources of the given repositories, or of all repositories if
+/// none is given, as a JSON manifest.
+pub(crate) async fn export_resources(
+    repository: &Arc<RwLock<dyn Repository + Send + Sync>>,
+    repositories: &[String],
+) -> Result<Manifest> {
+    let repository = repository.read().await;
+    let mut manifest = Manifest::default();
+    for metadata in repository.list_secret_resources().await? {
+        if !repositories.is_empty() && !repositories.contains(&metadata.repository_name) {
+            continue;
+        }
+
+        let resource_desc = ResourceDesc {
+            repository_name: metadata.repository_name,
+            resource_type: metadata.resource_type,
+            resource_tag: metadata.resource_tag,
+        };
+
+        if metadata
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= OffsetDateTime::now_utc())
+        {
+            continue;
+        }
+
+        let (data, signature) = repository
+            .read_signed_secret_resource(resource_desc.clone())
+            .await
+            .with_context(|| format!("export {}", path(&resource_desc)))?;
+        manifest.resources.push(ManifestEntry {
+            path: path(&resource_desc),
+            data: STANDARD.encode(data),
+            expires_at: metadata.expires_at,
+            signature: signature.map(|signature| STANDARD.encode(signature)),
+        });
+    }
+
+    Ok(manifest)
+}
+
+fn parse_bundle(bundle: &[u8]) -> Result<Vec<Resource>> {
+    if bundle.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{') {
+        let manifest: Manifest = serde_json::from_slice(bundle).context("illegal manifest")?;
+        return manifest_resources(manifest);
+    }
+
+    if bundle.starts_with(GZIP_MAGIC) {
+        parse_tarball(flate2::read::GzDecoder::new(bundle))
+    } else {
+        parse_tarball(bundle)
+    }
+}
+
+fn manifest_resources(manifest: Manifest) -> Result<Vec<Resource>> {
+    manifest
+        .resources
+        .into_iter()
+        .map(|entry| {
+            Ok(Resource {
+                resource_desc: parse_path(&entry.path)?,
+                data: STANDARD
+                    .decode(&entry.data)
+                    .with_context(|| format!("illegal data of {}", entry.path))?,
+                expires_at: entry.expires_at,
+                signature: entry
+                    .signature
+                    .map(|signature| STANDARD.decode(signature))
+                    .transpose()
+                    .with_context(|| format!("illegal signature of {}", entry.path))?,
+            })
+        })
+        .collect()
+}
+
+fn parse_tarball(tarball: impl Read) -> Result<Vec<Resource>> {
+    let mut resources = Vec::new();
+    let mut archive = tar::Archive::new(tarball);
+    for entry in archive.entries().context("illegal tarball")? {
+        let mut entry = entry.context("illegal tarball entry")?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let entry_path = entry
+            .path()
+            .context("illegal tarball entry path")?
+            .to_str()
+            .ok_or_else(|| anyhow!("tarball entry path is not UTF-8"))?
+            .to_string();
+        let resource_desc = parse_path(&entry_path)?;
+
+        let mut data = Vec::new();
+        entry
+            .read_to_end(&mut data)
+            .with_context(|| format!("read tarball entry {entry_path}"))?;
+        resources.push(Resource {
+            resource_desc,
+            data,
+            expires_at: None,
+            signature: None,
+        });
+    }
+
+    Ok(resources)
+}
+
+pub(super) fn parse_path(path: &str) -> Result<ResourceDesc> {
+    let path = path.trim_start_matches("./");
+    let components: Vec<&str> = path.split('/').collect();
+    let [repository_name, resource_type, resource_tag] = components[..] else {
+        bail!("`{path}` is not a `<repository>/<type>/<tag>` resource path");
+    };
+
+    let resource_desc = ResourceDesc {
+        repository_name: repository_name.to_string(),
+        resource_type: resource_type.to_string(),
+        resource_tag: resource_tag.to_string(),
+    };
+
+    if [repository_name, resource_type, resource_tag]
+        .iter()
+        .any(|c| c.is_empty() || c.starts_with('.'))
+        || !resource_desc.is_valid()
+    {
+        bail!("invalid resource path `{path}`");
+    }
+
+    Ok(resource_desc)
+}
+
+pub(super) fn path(resource_desc: &ResourceDesc) -> String {
+    format!(
+        "{}/{}/{}",
+        resource_desc.repository_name, resource_desc.resource_type, resource_desc.resource_tag
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use openssl::pkey::PKey;
+    use openssl::sign::Signer;
+    use rstest::rstest;
+    use tokio::sync::RwLock;
+
+    use crate::resource::{
+        integrity::{signed_message, VerifiedRepoDesc, VerifiedRepository},
+        local_fs::LocalFsRepoDesc,
+        Repository, RepositoryConfig,
+    };
+
+    #[rstest]
+    #[case("default/key/1", true)]
+    #[case("./default/key/1", true)]
+    #[case("key/1", false)]
+    #[case("default/../key/1", false)]
+    #[case("default/key/.versions", false)]
+    #[case("default//1", false)]
+    fn parse_path(#[case] path: &str, #[case] valid: bool) {
+        assert_eq!(super::parse_path(path).is_ok(), valid);
+    }
+
+    #[test]
+    fn parse_manifest() {
+        let manifest = br#"{"resources": [
+            {"path": "default/key/1", "data": "dGVzdGRhdGE="},
+            {"path": "default/key/2", "data": "", "expires_at": "2024-07-01T00:00:00Z", "signature": "c2ln"}
+        ]}"#;
+
+        let resources = super::parse_bundle(manifest).unwrap();
+        assert_eq!(resources.len(), 2);
+        assert_eq!(resources[0].data, b"testdata");
+        assert!(resources[0].expires_at.is_none());
+        assert!(resources[0].signature.is_none());
+        assert!(resources[1].expires_at.is_some());
+        assert_eq!(resources[1].signature.as_deref(), Some(&b"sig"[..]));
+    }
+
+    async fn verified(dir: &Path, key_path: &Path) -> Arc<RwLock<dyn Repository + Send + Sync>> {
+        let desc = VerifiedRepoDesc {
+            signature_key: Some(key_path.to_string_lossy().to_string()),
+            backend: Box::new(RepositoryConfig::LocalFs(LocalFsRepoDesc {
+                dir_path: Some(dir.to_string_lossy().to_string()),
+                encryption: None,
+            })),
+        };
+        Arc::new(RwLock::new(VerifiedRepository::new(&desc).await.unwrap()))
+    }
+
+    #[tokio::test]
+    async fn export_import_signed() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let signing_key = PKey::generate_ed25519().unwrap();
+        let key_path = tmp_dir.path().join("signature-key.pem");
+        std::fs::write(&key_path, signing_key.public_key_to_pem().unwrap()).unwrap();
+
+        let source = verified(&tmp_dir.path().join("source"), &key_path).await;
+        let target = verified(&tmp_dir.path().join("target"), &key_path).await;
+
+        let resource_desc = super::parse_path("default/key/1").unwrap();
+        let mut signer = Signer::new_without_digest(&signing_key).unwrap();
+        let signature = signer
+            .sign_oneshot_to_vec(&signed_message("default/key/1", b"data"))
+            .unwrap();
+        source
+            .write()
+            .await
+            .write_signed_secret_resource(resource_desc.clone(), b"data", &signature)
+            .await
+            .unwrap();
+
+        // The signatures are exported, so that the resources can be imported
+        // into another verified repository.
+        let manifest = super::export_resources(&source, &[]).await.unwrap();
+        assert!(manifest.resources[0].signature.is_some());
+        assert_eq!(super::import_manifest(&target, manifest).await.unwrap(), 1);
+        assert_eq!(
+            target
+                .read()
+                .await
+                .read_secret_resource(resource_desc)
+                .await
+                .unwrap(),
+            b"data"
+        );
+
+        // Unsigned resources are refused.
+        let manifest = br#"{"resources": [{"path": "default/key/2", "data": "ZGF0YQ=="}]}"#;
+        assert!(super::import_resources(&target, manifest).await.is_err());
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn parse_tarball(#[case] gzip: bool) {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in [("default/key/1", b"one"), ("other/cert/2", b"two")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o600);
+            header.set_cksum();
+            builder.append_data(&mut header, path, &data[..]).unwrap();
+        }
+        let mut tarball = builder.into_inner().unwrap();
+
+        if gzip {
+            use std::io::Write;
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&tarball).unwrap();
+            tarball = encoder.finish().unwrap();
+        }
+
+        let resources = super::parse_bundle(&tarball).unwrap();
+        assert_eq!(resources.len(), 2);
+        assert_eq!(resources[1].resource_desc.repository_name, "other");
+        assert_eq!(resources[1].data, b"two");
+    }
+}
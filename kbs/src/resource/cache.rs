@@ -0,0 +1,487 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Read-through cache of the resources of a slow (remote) repository.
+
+use super::{Repository, RepositoryConfig, ResourceDesc, ResourceMetadata};
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use tokio::sync::RwLock;
+
+const DEFAULT_CACHE_TTL: u64 = 60;
+const DEFAULT_CACHE_MAX_ENTRIES: usize = 1024;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CachedRepoDesc {
+    /// Seconds a resource is served from the cache before it is read again
+    /// from the backend. Defaults to 60.
+    #[serde(default = "default_cache_ttl")]
+    pub ttl: u64,
+
+    /// Maximum number of cached resources. Defaults to 1024.
+    #[serde(default = "default_cache_max_entries")]
+    pub max_entries: usize,
+
+    pub backend: Box<RepositoryConfig>,
+}
+
+fn default_cache_ttl() -> u64 {
+    DEFAULT_CACHE_TTL
+}
+
+fn default_cache_max_entries() -> usize {
+    DEFAULT_CACHE_MAX_ENTRIES
+}
+
+/// Statistics of the resource cache, as returned by the admin API.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+impl CacheStats {
+    fn add(&mut self, other: &CacheStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.entries += other.entries;
+    }
+
+    /// Sum the statistics of several caches, `None` if there is no cache.
+    pub fn sum<'a>(stats: impl IntoIterator<Item = &'a CacheStats>) -> Option<CacheStats> {
+        stats.into_iter().fold(None, |sum, stats| {
+            let mut sum = sum.unwrap_or_default();
+            sum.add(stats);
+            Some(sum)
+        })
+    }
+}
+
+type CacheKey = (String, String, String);
+
+struct CacheEntry {
+    data: Vec<u8>,
+    cached_at: Instant,
+
+    /// Expiry of the resource in the backend, so that it is never served
+    /// from the cache once expired.
+    expires_at: Option<OffsetDateTime>,
+}
+
+impl CacheEntry {
+    fn is_fresh(&self, ttl: Duration) -> bool {
+        let expired = match self.expires_at {
+            Some(expires_at) => expires_at <= OffsetDateTime::now_utc(),
+            None => false,
+        };
+
+        self.cached_at.elapsed() < ttl && !expired
+    }
+}
+
+pub struct CachedRepository {
+    backend: Arc<RwLock<dyn Repository + Send + Sync>>,
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<CacheKey, CacheEntry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CachedRepository {
+    /// The cached backend is initialized through [`RepositoryConfig::initialize`],
+    /// so the future is boxed to break the recursion between both.
+    pub fn new(
+        repo_desc: &CachedRepoDesc,
+    ) -> Pin<Box<dyn Future<Output = Result<Self>> + Send + '_>> {
+        Box::pin(async move {
+            if let RepositoryConfig::Cached(_) = *repo_desc.backend {
+                bail!("caches cannot be nested");
+            }
+
+            if repo_desc.max_entries == 0 {
+                bail!("cache `max_entries` must not be 0");
+            }
+
+            Ok(Self {
+                backend: repo_desc.backend.initialize().await?,
+                ttl: Duration::from_secs(repo_desc.ttl),
+                max_entries: repo_desc.max_entries,
+                entries: Mutex::new(HashMap::new()),
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+            })
+        })
+    }
+
+    fn entries(&self) -> Result<std::sync::MutexGuard<'_, HashMap<CacheKey, CacheEntry>>> {
+        self.entries
+            .lock()
+            .map_err(|_| anyhow!("resource cache lock poisoned"))
+    }
+
+    fn get(&self, resource_desc: &ResourceDesc) -> Result<Option<Vec<u8>>> {
+        let entries = self.entries()?;
+        Ok(entries
+            .get(&cache_key(resource_desc))
+            .filter(|entry| entry.is_fresh(self.ttl))
+            .map(|entry| entry.data.clone()))
+    }
+
+    fn insert(
+        &self,
+        resource_desc: &ResourceDesc,
+        data: Vec<u8>,
+        expires_at: Option<OffsetDateTime>,
+    ) -> Result<()> {
+        let mut entries = self.entries()?;
+        entries.retain(|_, entry| entry.is_fresh(self.ttl));
+        if entries.len() >= self.max_entries {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.cached_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(
+            cache_key(resource_desc),
+            CacheEntry {
+                data,
+                cached_at: Instant::now(),
+                expires_at,
+            },
+        );
+        Ok(())
+    }
+
+    fn invalidate(&self, resource_desc: &ResourceDesc) -> Result<()> {
+        self.entries()?.remove(&cache_key(resource_desc));
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Repository for CachedRepository {
+    async fn read_secret_resource(&self, resource_desc: ResourceDesc) -> Result<Vec<u8>> {
+        if let Some(data) = self.get(&resource_desc)? {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(data);
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        Ok(self
+            .read_secret_resource_with_metadata(resource_desc)
+            .await?
+            .0)
+    }
+
+    /// The metadata are not cached, so the resource is read from the backend,
+    /// and cached until it expires at the latest.
+    async fn read_secret_resource_with_metadata(
+        &self,
+        resource_desc: ResourceDesc,
+    ) -> Result<(Vec<u8>, ResourceMetadata)> {
+        let (data, metadata) = self
+            .backend
+            .read()
+            .await
+            .read_secret_resource_with_metadata(resource_desc.clone())
+            .await?;
+        self.insert(&resource_desc, data.clone(), metadata.expires_at)?;
+        Ok((data, metadata))
+    }
+
+    async fn write_secret_resource(
+        &mut self,
+        resource_desc: ResourceDesc,
+        data: &[u8],
+    ) -> Result<()> {
+        self.invalidate(&resource_desc)?;
+        self.backend
+            .write()
+            .await
+            .write_secret_resource(resource_desc, data)
+            .await
+    }
+
+    async fn write_signed_secret_resource(
+        &mut self,
+        resource_desc: ResourceDesc,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        self.invalidate(&resource_desc)?;
+        self.backend
+            .write()
+            .await
+            .write_signed_secret_resource(resource_desc, data, signature)
+            .await
+    }
+
+    /// Signatures are not cached, so the resource is read from the backend.
+    async fn read_signed_secret_resource(
+        &self,
+        resource_desc: ResourceDesc,
+    ) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+        self.backend
+            .read()
+            .await
+            .read_signed_secret_resource(resource_desc)
+            .await
+    }
+
+    async fn list_secret_resources(&self) -> Result<Vec<ResourceMetadata>> {
+        self.backend.read().await.list_secret_resources().await
+    }
+
+    async fn secret_resource_metadata(
+        &self,
+        resource_desc: ResourceDesc,
+    ) -> Result<ResourceMetadata> {
+        self.backend
+            .read()
+            .await
+            .secret_resource_metadata(resource_desc)
+            .await
+    }
+
+    async fn delete_secret_resource(&mut self, resource_desc: ResourceDesc) -> Result<()> {
+        self.invalidate(&resource_desc)?;
+        self.backend
+            .write()
+            .await
+            .delete_secret_resource(resource_desc)
+            .await
+    }
+
+    async fn supports_expiry(&self, resource_desc: &ResourceDesc) -> bool {
+        self.backend
+            .read()
+            .await
+            .supports_expiry(resource_desc)
+            .await
+    }
+
+    async fn set_secret_resource_expiry(
+        &mut self,
+        resource_desc: ResourceDesc,
+        expires_at: Option<OffsetDateTime>,
+    ) -> Result<()> {
+        self.invalidate(&resource_desc)?;
+        self.backend
+            .write()
+            .await
+            .set_secret_resource_expiry(resource_desc, expires_at)
+            .await
+    }
+
+    async fn purge_expired_secret_resources(&mut self) -> Result<Vec<ResourceDesc>> {
+        let purged = self
+            .backend
+            .write()
+            .await
+            .purge_expired_secret_resources()
+            .await?;
+        for resource_desc in &purged {
+            self.invalidate(resource_desc)?;
+        }
+
+        Ok(purged)
+    }
+
+    async fn read_secret_resource_version(
+        &self,
+        resource_desc: ResourceDesc,
+        version: u64,
+    ) -> Result<Vec<u8>> {
+        self.backend
+            .read()
+            .await
+            .read_secret_resource_version(resource_desc, version)
+            .await
+    }
+
+    async fn list_secret_resource_versions(&self, resource_desc: ResourceDesc) -> Result<Vec<u64>> {
+        self.backend
+            .read()
+            .await
+            .list_secret_resource_versions(resource_desc)
+            .await
+    }
+
+    async fn rollback_secret_resource(
+        &mut self,
+        resource_desc: ResourceDesc,
+        version: u64,
+    ) -> Result<()> {
+        self.invalidate(&resource_desc)?;
+        self.backend
+            .write()
+            .await
+            .rollback_secret_resource(resource_desc, version)
+            .await
+    }
+
+    async fn cache_stats(&self) -> Option<CacheStats> {
+        Some(CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries().map(|entries| entries.len()).unwrap_or(0),
+        })
+    }
+
+    async fn purge_cache(&self) -> Result<usize> {
+        let mut entries = self.entries()?;
+        let purged = entries.len();
+        entries.clear();
+        Ok(purged)
+    }
+}
+
+fn cache_key(resource_desc: &ResourceDesc) -> CacheKey {
+    (
+        resource_desc.repository_name.clone(),
+        resource_desc.resource_type.clone(),
+        resource_desc.resource_tag.clone(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use time::OffsetDateTime;
+
+    use super::{CachedRepoDesc, CachedRepository};
+    use crate::resource::{local_fs::LocalFsRepoDesc, Repository, RepositoryConfig, ResourceDesc};
+
+    #[tokio::test]
+    async fn read_through() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut repository = CachedRepository::new(&CachedRepoDesc {
+            ttl: 60,
+            max_entries: 1,
+            backend: Box::new(RepositoryConfig::LocalFs(LocalFsRepoDesc {
+                dir_path: Some(tmp_dir.path().to_string_lossy().to_string()),
+                encryption: None,
+            })),
+        })
+        .await
+        .unwrap();
+
+        let resource_desc = |tag: &str| ResourceDesc {
+            repository_name: "default".into(),
+            resource_type: "key".into(),
+            resource_tag: tag.into(),
+        };
+        for tag in ["1", "2"] {
+            repository
+                .write_secret_resource(resource_desc(tag), tag.as_bytes())
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            repository
+                .read_secret_resource(resource_desc("1"))
+                .await
+                .unwrap(),
+            b"1"
+        );
+        assert_eq!(
+            repository
+                .read_secret_resource(resource_desc("1"))
+                .await
+                .unwrap(),
+            b"1"
+        );
+
+        // Writes through KBS are never served stale.
+        repository
+            .write_secret_resource(resource_desc("1"), b"new")
+            .await
+            .unwrap();
+        assert_eq!(
+            repository
+                .read_secret_resource(resource_desc("1"))
+                .await
+                .unwrap(),
+            b"new"
+        );
+
+        // The oldest entry is evicted once `max_entries` is reached.
+        repository
+            .read_secret_resource(resource_desc("2"))
+            .await
+            .unwrap();
+        let stats = repository.cache_stats().await.unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 3, 1));
+
+        assert_eq!(repository.purge_cache().await.unwrap(), 1);
+        assert_eq!(repository.cache_stats().await.unwrap().entries, 0);
+    }
+
+    #[tokio::test]
+    async fn read_with_metadata() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut repository = CachedRepository::new(&CachedRepoDesc {
+            ttl: 60,
+            max_entries: 10,
+            backend: Box::new(RepositoryConfig::LocalFs(LocalFsRepoDesc {
+                dir_path: Some(tmp_dir.path().to_string_lossy().to_string()),
+                encryption: None,
+            })),
+        })
+        .await
+        .unwrap();
+
+        let resource_desc = ResourceDesc {
+            repository_name: "default".into(),
+            resource_type: "key".into(),
+            resource_tag: "1".into(),
+        };
+        let expires_at = OffsetDateTime::now_utc() + Duration::from_secs(3600);
+        repository
+            .write_secret_resource(resource_desc.clone(), b"secret")
+            .await
+            .unwrap();
+        repository
+            .set_secret_resource_expiry(resource_desc.clone(), Some(expires_at))
+            .await
+            .unwrap();
+
+        let (data, metadata) = repository
+            .read_secret_resource_with_metadata(resource_desc.clone())
+            .await
+            .unwrap();
+        assert_eq!(data, b"secret");
+        assert_eq!(metadata.size, 6);
+        assert_eq!(
+            metadata.expires_at.map(|e| e.unix_timestamp()),
+            Some(expires_at.unix_timestamp())
+        );
+
+        // The resource read with its metadata is cached.
+        assert_eq!(
+            repository
+                .read_secret_resource(resource_desc)
+                .await
+                .unwrap(),
+            b"secret"
+        );
+        let stats = repository.cache_stats().await.unwrap();
+        assert_eq!((stats.hits, stats.misses), (1, 0));
+    }
+}
@@ -0,0 +1,381 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Envelope encryption of resources at rest.
+//!
+//! Every resource is encrypted with a fresh AES-256-GCM data key, and the
+//! data key is stored next to the ciphertext wrapped by a master key that
+//! never touches the disk in plaintext (a key file, AWS KMS or an HSM).
+
+use aes_gcm::{
+    aead::{Aead, OsRng, Payload},
+    Aes256Gcm, KeyInit, Nonce,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+const MASTER_KEY_LENGTH: usize = 32;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum MasterKeyConfig {
+    /// A raw 32 byte AES-256 key read from a file.
+    File { key_path: String },
+
+    /// A symmetric AWS KMS key.
+    #[cfg(feature = "aws")]
+    AwsKms {
+        /// Id or ARN of the KMS key.
+        key_id: String,
+        /// AWS region. If not given, the region is resolved from the
+        /// environment.
+        region: Option<String>,
+    },
+
+    /// An AES key stored on a PKCS#11 token.
+    #[cfg(feature = "pkcs11")]
+    Pkcs11 {
+        /// Path to the PKCS#11 module.
+        module: String,
+        /// Label of the token to use. The first slot with a token is used if
+        /// not given.
+        token_label: Option<String>,
+        /// User PIN of the token.
+        pin: String,
+        /// Label of the AES key on the token.
+        key_label: String,
+    },
+}
+
+/// On-disk format of an encrypted resource.
+#[derive(Serialize, Deserialize)]
+struct EncryptedResource {
+    wrapped_key: String,
+    iv: String,
+    ciphertext: String,
+}
+
+#[async_trait::async_trait]
+trait MasterKey {
+    async fn wrap(&self, data_key: &[u8]) -> Result<Vec<u8>>;
+
+    async fn unwrap(&self, wrapped_key: &[u8]) -> Result<Vec<u8>>;
+
+    /// Generate a new data key, and return it in plaintext and wrapped.
+    async fn generate_data_key(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        let data_key = Aes256Gcm::generate_key(&mut OsRng).to_vec();
+        let wrapped_key = self.wrap(&data_key).await?;
+        Ok((data_key, wrapped_key))
+    }
+}
+
+pub struct Envelope {
+    master_key: Box<dyn MasterKey + Send + Sync>,
+}
+
+impl Envelope {
+    pub async fn new(config: &MasterKeyConfig) -> Result<Self> {
+        let master_key: Box<dyn MasterKey + Send + Sync> = match config {
+            MasterKeyConfig::File { key_path } => Box::new(FileMasterKey::new(key_path)?),
+            #[cfg(feature = "aws")]
+            MasterKeyConfig::AwsKms { key_id, region } => {
+                Box::new(AwsKmsMasterKey::new(key_id, region.as_deref()).await)
+            }
+            #[cfg(feature = "pkcs11")]
+            MasterKeyConfig::Pkcs11 {
+                module,
+                token_label,
+                pin,
+                key_label,
+            } => Box::new(Pkcs11MasterKey::new(
+                module,
+                token_label.as_deref(),
+                pin,
+                key_label,
+            )?),
+        };
+
+        Ok(Self { master_key })
+    }
+
+    /// Encrypt `data` with a new data key. `aad` binds the ciphertext to the
+    /// resource, so that it cannot be moved to another path.
+    pub async fn seal(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let (data_key, wrapped_key) = self.master_key.generate_data_key().await?;
+        let iv = rand::thread_rng().gen::<[u8; 12]>();
+        let ciphertext = Aes256Gcm::new_from_slice(&data_key)
+            .map_err(|_| anyhow!("illegal data key length {}", data_key.len()))?
+            .encrypt(Nonce::from_slice(&iv), Payload { msg: data, aad })
+            .map_err(|e| anyhow!("encrypt resource: {e}"))?;
+
+        let encrypted = EncryptedResource {
+            wrapped_key: STANDARD.encode(wrapped_key),
+            iv: STANDARD.encode(iv),
+            ciphertext: STANDARD.encode(ciphertext),
+        };
+        Ok(serde_json::to_vec(&encrypted)?)
+    }
+
+    pub async fn open(&self, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let encrypted: EncryptedResource =
+            serde_json::from_slice(sealed).context("resource is not envelope encrypted")?;
+        let wrapped_key = STANDARD
+            .decode(encrypted.wrapped_key)
+            .context("illegal wrapped data key")?;
+        let iv = STANDARD.decode(encrypted.iv).context("illegal iv")?;
+        let ciphertext = STANDARD
+            .decode(encrypted.ciphertext)
+            .context("illegal ciphertext")?;
+        if iv.len() != 12 {
+            bail!("illegal iv length {}", iv.len());
+        }
+
+        let data_key = self.master_key.unwrap(&wrapped_key).await?;
+        let cipher = Aes256Gcm::new_from_slice(&data_key)
+            .map_err(|_| anyhow!("illegal data key length {}", data_key.len()))?;
+        cipher
+            .decrypt(
+                Nonce::from_slice(&iv),
+                Payload {
+                    msg: &ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| anyhow!("decrypt resource: authentication failed"))
+    }
+}
+
+/// Master key held in memory, wrapping data keys with AES-256-GCM.
+struct FileMasterKey {
+    cipher: Aes256Gcm,
+}
+
+impl FileMasterKey {
+    fn new(key_path: &str) -> Result<Self> {
+        let key = std::fs::read(key_path).context("read master key file")?;
+        if key.len() != MASTER_KEY_LENGTH {
+            bail!(
+                "master key must be {MASTER_KEY_LENGTH} bytes long, got {}",
+                key.len()
+            );
+        }
+
+        Ok(Self {
+            cipher: Aes256Gcm::new_from_slice(&key).expect("key length checked"),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl MasterKey for FileMasterKey {
+    async fn wrap(&self, data_key: &[u8]) -> Result<Vec<u8>> {
+        let iv = rand::thread_rng().gen::<[u8; 12]>();
+        let mut wrapped = iv.to_vec();
+        wrapped.extend(
+            self.cipher
+                .encrypt(Nonce::from_slice(&iv), data_key)
+                .map_err(|e| anyhow!("wrap data key: {e}"))?,
+        );
+        Ok(wrapped)
+    }
+
+    async fn unwrap(&self, wrapped_key: &[u8]) -> Result<Vec<u8>> {
+        if wrapped_key.len() < 12 {
+            bail!("wrapped data key is too short");
+        }
+
+        let (iv, wrapped_key) = wrapped_key.split_at(12);
+        self.cipher
+            .decrypt(Nonce::from_slice(iv), wrapped_key)
+            .map_err(|_| anyhow!("unwrap data key: wrong master key or corrupted resource"))
+    }
+}
+
+#[cfg(feature = "aws")]
+struct AwsKmsMasterKey {
+    client: aws_sdk_kms::Client,
+    key_id: String,
+}
+
+#[cfg(feature = "aws")]
+impl AwsKmsMasterKey {
+    async fn new(key_id: &str, region: Option<&str>) -> Self {
+        let mut loader = aws_config::defaults(aws_config::BehaviorVersion::latest());
+        if let Some(region) = region {
+            loader = loader.region(aws_config::Region::new(region.to_string()));
+        }
+
+        Self {
+            client: aws_sdk_kms::Client::new(&loader.load().await),
+            key_id: key_id.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "aws")]
+#[async_trait::async_trait]
+impl MasterKey for AwsKmsMasterKey {
+    async fn wrap(&self, data_key: &[u8]) -> Result<Vec<u8>> {
+        let wrapped = self
+            .client
+            .encrypt()
+            .key_id(&self.key_id)
+            .plaintext(aws_sdk_kms::primitives::Blob::new(data_key))
+            .send()
+            .await
+            .context("wrap data key with AWS KMS")?
+            .ciphertext_blob
+            .ok_or_else(|| anyhow!("AWS KMS returned no wrapped data key"))?;
+        Ok(wrapped.into_inner())
+    }
+
+    async fn unwrap(&self, wrapped_key: &[u8]) -> Result<Vec<u8>> {
+        let data_key = self
+            .client
+            .decrypt()
+            .key_id(&self.key_id)
+            .ciphertext_blob(aws_sdk_kms::primitives::Blob::new(wrapped_key))
+            .send()
+            .await
+            .context("unwrap data key with AWS KMS")?
+            .plaintext
+            .ok_or_else(|| anyhow!("AWS KMS returned no data key"))?;
+        Ok(data_key.into_inner())
+    }
+
+    /// KMS generates the data key and returns it both in plaintext and
+    /// wrapped, in a single call.
+    async fn generate_data_key(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        let generated = self
+            .client
+            .generate_data_key()
+            .key_id(&self.key_id)
+            .key_spec(aws_sdk_kms::types::DataKeySpec::Aes256)
+            .send()
+            .await
+            .context("generate data key with AWS KMS")?;
+        let data_key = generated
+            .plaintext
+            .ok_or_else(|| anyhow!("AWS KMS returned no data key"))?;
+        let wrapped_key = generated
+            .ciphertext_blob
+            .ok_or_else(|| anyhow!("AWS KMS returned no wrapped data key"))?;
+        Ok((data_key.into_inner(), wrapped_key.into_inner()))
+    }
+}
+
+/// Master key on a PKCS#11 token. Data keys are wrapped with `CKM_AES_CBC_PAD`,
+/// which every token supports; their integrity is protected by the GCM tag of
+/// the resource they encrypt.
+#[cfg(feature = "pkcs11")]
+struct Pkcs11MasterKey {
+    session: super::pkcs11::Pkcs11Session,
+    key: cryptoki::object::ObjectHandle,
+}
+
+#[cfg(feature = "pkcs11")]
+impl Pkcs11MasterKey {
+    fn new(module: &str, token_label: Option<&str>, pin: &str, key_label: &str) -> Result<Self> {
+        let (session, _) = super::pkcs11::open_session(module, token_label, pin)?;
+        let key = super::pkcs11::find_object(&session, key_label)?
+            .ok_or_else(|| anyhow!("no PKCS#11 master key `{key_label}`"))?;
+
+        Ok(Self {
+            session: super::pkcs11::Pkcs11Session::new(session),
+            key,
+        })
+    }
+}
+
+#[cfg(feature = "pkcs11")]
+#[async_trait::async_trait]
+impl MasterKey for Pkcs11MasterKey {
+    async fn wrap(&self, data_key: &[u8]) -> Result<Vec<u8>> {
+        let iv = rand::thread_rng().gen::<[u8; 16]>();
+        let key = self.key;
+        let data_key = data_key.to_vec();
+        let mut wrapped = iv.to_vec();
+        wrapped.extend(
+            self.session
+                .run(move |session| {
+                    session
+                        .encrypt(
+                            &cryptoki::mechanism::Mechanism::AesCbcPad(iv),
+                            key,
+                            &data_key,
+                        )
+                        .context("wrap data key with PKCS#11 master key")
+                })
+                .await?,
+        );
+        Ok(wrapped)
+    }
+
+    async fn unwrap(&self, wrapped_key: &[u8]) -> Result<Vec<u8>> {
+        if wrapped_key.len() < 16 {
+            bail!("wrapped data key is too short");
+        }
+
+        let (iv, wrapped_key) = wrapped_key.split_at(16);
+        let iv: [u8; 16] = iv.try_into().expect("split at 16");
+        let key = self.key;
+        let wrapped_key = wrapped_key.to_vec();
+        self.session
+            .run(move |session| {
+                session
+                    .decrypt(
+                        &cryptoki::mechanism::Mechanism::AesCbcPad(iv),
+                        key,
+                        &wrapped_key,
+                    )
+                    .context("unwrap data key with PKCS#11 master key")
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{Envelope, MasterKeyConfig};
+
+    async fn file_envelope(key: &[u8]) -> (Envelope, tempfile::NamedTempFile) {
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        key_file.write_all(key).unwrap();
+        let envelope = Envelope::new(&MasterKeyConfig::File {
+            key_path: key_file.path().to_string_lossy().to_string(),
+        })
+        .await
+        .unwrap();
+        (envelope, key_file)
+    }
+
+    #[tokio::test]
+    async fn seal_and_open() {
+        let (envelope, _key_file) = file_envelope(&[7; 32]).await;
+        let sealed = envelope.seal(b"secret", b"default/key/1").await.unwrap();
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+
+        let data = envelope.open(&sealed, b"default/key/1").await.unwrap();
+        assert_eq!(data, b"secret");
+
+        assert!(envelope.open(&sealed, b"default/key/2").await.is_err());
+
+        let (other, _key_file) = file_envelope(&[8; 32]).await;
+        assert!(other.open(&sealed, b"default/key/1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn illegal_master_key_length() {
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        key_file.write_all(&[7; 16]).unwrap();
+        let config = MasterKeyConfig::File {
+            key_path: key_file.path().to_string_lossy().to_string(),
+        };
+        assert!(Envelope::new(&config).await.is_err());
+    }
+}
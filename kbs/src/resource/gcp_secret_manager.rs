@@ -0,0 +1,421 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{Repository, ResourceDesc};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use jwt_simple::prelude::{Claims, RS256KeyPair, RSAKeyPairLike};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+const SECRET_MANAGER_ENDPOINT: &str = "https://secretmanager.googleapis.com/v1";
+const METADATA_TOKEN_ENDPOINT: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+const CLOUD_PLATFORM_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+const JWT_BEARER_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+
+/// An access token is refreshed once less than this much of its lifetime is left.
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GcpSecretManagerRepoDesc {
+    /// The GCP project holding the secrets.
+    pub project_id: String,
+
+    /// Path to a service account JSON key file. If not given, the service
+    /// account attached to the workload is used through the metadata server
+    /// (e.g. GKE workload identity or the GCE default service account).
+    pub credentials_path: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ServiceAccountKey {
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScopeClaims {
+    scope: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Deserialize)]
+struct AccessSecretVersionResponse {
+    payload: SecretPayload,
+}
+
+#[derive(Deserialize)]
+struct SecretPayload {
+    data: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListSecretVersionsResponse {
+    #[serde(default)]
+    versions: Vec<SecretVersion>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SecretVersion {
+    /// `projects/*/secrets/*/versions/<version>`
+    name: String,
+}
+
+struct AccessToken {
+    token: String,
+    expires_at: Instant,
+}
+
+impl AccessToken {
+    fn is_expiring(&self) -> bool {
+        Instant::now() + TOKEN_REFRESH_MARGIN >= self.expires_at
+    }
+}
+
+pub struct GcpSecretManagerBackend {
+    client: reqwest::Client,
+    project_id: String,
+    service_account: Option<ServiceAccountKey>,
+    token: RwLock<Option<AccessToken>>,
+}
+
+#[async_trait::async_trait]
+impl Repository for GcpSecretManagerBackend {
+    async fn read_secret_resource(&self, resource_desc: ResourceDesc) -> Result<Vec<u8>> {
+        self.access_secret(&secret_id(&resource_desc)?, "latest")
+            .await
+    }
+
+    async fn write_secret_resource(
+        &mut self,
+        resource_desc: ResourceDesc,
+        data: &[u8],
+    ) -> Result<()> {
+        let secret_id = secret_id(&resource_desc)?;
+        let token = self.token().await?;
+
+        let response = self
+            .client
+            .get(self.secret_url(&secret_id))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .context("failed to get secret from GCP Secret Manager")?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            info!("Create new secret `{secret_id}` in GCP Secret Manager");
+            let response = self
+                .client
+                .post(format!(
+                    "{SECRET_MANAGER_ENDPOINT}/projects/{}/secrets",
+                    self.project_id
+                ))
+                .query(&[("secretId", &secret_id)])
+                .bearer_auth(&token)
+                .json(&json!({ "replication": { "automatic": {} } }))
+                .send()
+                .await
+                .context("failed to create secret in GCP Secret Manager")?;
+
+            if !response.status().is_success() {
+                bail!(
+                    "GCP Secret Manager returned {} when creating secret `{secret_id}`",
+                    response.status()
+                );
+            }
+        } else if !response.status().is_success() {
+            bail!(
+                "GCP Secret Manager returned {} when getting secret `{secret_id}`",
+                response.status()
+            );
+        }
+
+        let response = self
+            .client
+            .post(format!("{}:addVersion", self.secret_url(&secret_id)))
+            .bearer_auth(&token)
+            .json(&json!({ "payload": { "data": STANDARD.encode(data) } }))
+            .send()
+            .await
+            .context("failed to add secret version to GCP Secret Manager")?;
+
+        if !response.status().is_success() {
+            bail!(
+                "GCP Secret Manager returned {} when adding a version to secret `{secret_id}`",
+                response.status()
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn delete_secret_resource(&mut self, resource_desc: ResourceDesc) -> Result<()> {
+        let secret_id = secret_id(&resource_desc)?;
+        let response = self
+            .client
+            .delete(self.secret_url(&secret_id))
+            .bearer_auth(self.token().await?)
+            .send()
+            .await
+            .context("failed to delete secret from GCP Secret Manager")?;
+
+        if !response.status().is_success() {
+            bail!(
+                "GCP Secret Manager returned {} when deleting secret `{secret_id}`",
+                response.status()
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn read_secret_resource_version(
+        &self,
+        resource_desc: ResourceDesc,
+        version: u64,
+    ) -> Result<Vec<u8>> {
+        self.access_secret(&secret_id(&resource_desc)?, &version.to_string())
+            .await
+    }
+
+    async fn list_secret_resource_versions(&self, resource_desc: ResourceDesc) -> Result<Vec<u64>> {
+        let secret_id = secret_id(&resource_desc)?;
+        let token = self.token().await?;
+        let mut versions = Vec::new();
+        let mut page_token = None;
+        loop {
+            let mut query = vec![("filter", "state:ENABLED".to_string())];
+            if let Some(page_token) = page_token {
+                query.push(("pageToken", page_token));
+            }
+
+            let response = self
+                .client
+                .get(format!("{}/versions", self.secret_url(&secret_id)))
+                .query(&query)
+                .bearer_auth(&token)
+                .send()
+                .await
+                .context("failed to list secret versions from GCP Secret Manager")?;
+
+            if !response.status().is_success() {
+                bail!(
+                    "GCP Secret Manager returned {} when listing versions of secret `{secret_id}`",
+                    response.status()
+                );
+            }
+
+            let response: ListSecretVersionsResponse = response
+                .json()
+                .await
+                .context("illegal GCP Secret Manager response")?;
+            versions.extend(
+                response
+                    .versions
+                    .iter()
+                    .filter_map(|v| v.name.rsplit('/').next()?.parse::<u64>().ok()),
+            );
+
+            match response.next_page_token {
+                Some(next) if !next.is_empty() => page_token = Some(next),
+                _ => break,
+            }
+        }
+
+        versions.sort_unstable();
+        Ok(versions)
+    }
+}
+
+impl GcpSecretManagerBackend {
+    pub fn new(repo_desc: &GcpSecretManagerRepoDesc) -> Result<Self> {
+        let service_account = match &repo_desc.credentials_path {
+            Some(path) => {
+                let key = std::fs::read(path).context("read GCP service account key")?;
+                let key: ServiceAccountKey =
+                    serde_json::from_slice(&key).context("illegal GCP service account key")?;
+                Some(key)
+            }
+            None => None,
+        };
+
+        let client = reqwest::Client::builder()
+            .build()
+            .context("build GCP Secret Manager HTTP client")?;
+
+        Ok(Self {
+            client,
+            project_id: repo_desc.project_id.clone(),
+            service_account,
+            token: RwLock::new(None),
+        })
+    }
+
+    /// Access the payload of a secret version, either a version number or
+    /// `latest`.
+    async fn access_secret(&self, secret_id: &str, version: &str) -> Result<Vec<u8>> {
+        let response = self
+            .client
+            .get(format!(
+                "{}/versions/{version}:access",
+                self.secret_url(secret_id)
+            ))
+            .bearer_auth(self.token().await?)
+            .send()
+            .await
+            .context("failed to access secret from GCP Secret Manager")?;
+
+        if !response.status().is_success() {
+            bail!(
+                "GCP Secret Manager returned {} when accessing version `{version}` of secret `{secret_id}`",
+                response.status()
+            );
+        }
+
+        let secret: AccessSecretVersionResponse = response
+            .json()
+            .await
+            .context("illegal GCP Secret Manager response")?;
+        STANDARD
+            .decode(secret.payload.data)
+            .context("illegal GCP Secret Manager secret payload")
+    }
+
+    fn secret_url(&self, secret_id: &str) -> String {
+        format!(
+            "{SECRET_MANAGER_ENDPOINT}/projects/{}/secrets/{secret_id}",
+            self.project_id
+        )
+    }
+
+    /// Get a valid access token, fetching a new one if there is none yet or
+    /// the cached one is about to expire.
+    async fn token(&self) -> Result<String> {
+        if let Some(token) = &*self.token.read().await {
+            if !token.is_expiring() {
+                return Ok(token.token.clone());
+            }
+        }
+
+        let mut token = self.token.write().await;
+        if let Some(token) = &*token {
+            if !token.is_expiring() {
+                return Ok(token.token.clone());
+            }
+        }
+
+        let response = match &self.service_account {
+            Some(service_account) => {
+                let assertion = service_account_assertion(service_account)?;
+                self.client
+                    .post(&service_account.token_uri)
+                    .form(&[
+                        ("grant_type", JWT_BEARER_GRANT_TYPE),
+                        ("assertion", assertion.as_str()),
+                    ])
+                    .send()
+                    .await
+                    .context("request service account token")?
+            }
+            None => self
+                .client
+                .get(METADATA_TOKEN_ENDPOINT)
+                .header("Metadata-Flavor", "Google")
+                .send()
+                .await
+                .context("request metadata server token")?,
+        };
+
+        if !response.status().is_success() {
+            bail!("GCP token endpoint returned {}", response.status());
+        }
+
+        let response: TokenResponse = response
+            .json()
+            .await
+            .context("illegal GCP token response")?;
+        let access_token = response.access_token.clone();
+        *token = Some(AccessToken {
+            token: response.access_token,
+            expires_at: Instant::now() + Duration::from_secs(response.expires_in),
+        });
+
+        Ok(access_token)
+    }
+}
+
+/// Build the self-signed JWT exchanged for an access token, see
+/// <https://developers.google.com/identity/protocols/oauth2/service-account#authorizingrequests>
+fn service_account_assertion(service_account: &ServiceAccountKey) -> Result<String> {
+    let key = RS256KeyPair::from_pem(&service_account.private_key)
+        .map_err(|e| anyhow!("illegal GCP service account private key: {e}"))?;
+    let claims = Claims::with_custom_claims(
+        ScopeClaims {
+            scope: CLOUD_PLATFORM_SCOPE.to_string(),
+        },
+        jwt_simple::prelude::Duration::from_hours(1),
+    )
+    .with_issuer(&service_account.client_email)
+    .with_audience(&service_account.token_uri);
+
+    key.sign(claims)
+        .map_err(|e| anyhow!("sign GCP service account assertion: {e}"))
+}
+
+/// GCP secret ids may only contain alphanumeric characters, dashes and
+/// underscores, so `repo/type/tag` is mapped to `repo-type-tag`.
+fn secret_id(resource_desc: &ResourceDesc) -> Result<String> {
+    let id = format!(
+        "{}-{}-{}",
+        resource_desc.repository_name, resource_desc.resource_type, resource_desc.resource_tag
+    );
+
+    if id.len() > 255
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!("`{id}` is not a valid GCP secret id");
+    }
+
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::resource::ResourceDesc;
+
+    #[rstest]
+    #[case("default", "key", "1", Some("default-key-1"))]
+    #[case("default", "key_type", "1", Some("default-key_type-1"))]
+    #[case("default", "key", "a.b", None)]
+    fn secret_id(
+        #[case] repository: &str,
+        #[case] resource_type: &str,
+        #[case] tag: &str,
+        #[case] expected: Option<&str>,
+    ) {
+        let resource_desc = ResourceDesc {
+            repository_name: repository.into(),
+            resource_type: resource_type.into(),
+            resource_tag: tag.into(),
+        };
+
+        assert_eq!(super::secret_id(&resource_desc).ok().as_deref(), expected);
+    }
+}
@@ -0,0 +1,532 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Integrity verification of the resources of a repository.
+//!
+//! Every resource is stored with the SHA-256 digest of its path and data, and
+//! optionally a detached signature of them by the resource owner. Both are
+//! verified whenever the resource is read, so that tampered content, or the
+//! content of another resource moved to its path, is never released.
+//!
+//! The digest alone only detects corrupted resources: the writers of the
+//! backend can compute it again, so protection against them requires the
+//! signatures.
+
+use super::{CacheStats, Repository, RepositoryConfig, ResourceDesc, ResourceMetadata};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{Id, PKey, Public};
+use openssl::sign::Verifier;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use time::OffsetDateTime;
+use tokio::sync::RwLock;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct VerifiedRepoDesc {
+    /// Path to the PEM public key (Ed25519, ECDSA or RSA) verifying the
+    /// detached signatures of the resources. If given, resources without a
+    /// valid signature are refused.
+    pub signature_key: Option<String>,
+
+    pub backend: Box<RepositoryConfig>,
+}
+
+/// Format of a resource in the backend.
+#[derive(Serialize, Deserialize)]
+struct VerifiedResource {
+    /// Hex encoded SHA-256 digest of the [`signed_message`] of the resource.
+    sha256: String,
+
+    /// Base64 encoded detached signature of the [`signed_message`] of the
+    /// resource.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+
+    /// Base64 encoded data.
+    data: String,
+}
+
+pub struct VerifiedRepository {
+    backend: Arc<RwLock<dyn Repository + Send + Sync>>,
+    signature_key: Option<PKey<Public>>,
+}
+
+impl VerifiedRepository {
+    /// The verified backend is initialized through [`RepositoryConfig::initialize`],
+    /// so the future is boxed to break the recursion between both.
+    pub fn new(
+        repo_desc: &VerifiedRepoDesc,
+    ) -> Pin<Box<dyn Future<Output = Result<Self>> + Send + '_>> {
+        Box::pin(async move {
+            if let RepositoryConfig::Verified(_) = *repo_desc.backend {
+                bail!("verified repositories cannot be nested");
+            }
+
+            let signature_key = match &repo_desc.signature_key {
+                Some(path) => {
+                    let pem = tokio::fs::read(path)
+                        .await
+                        .with_context(|| format!("read signature key {path}"))?;
+                    Some(PKey::public_key_from_pem(&pem).context("illegal signature key")?)
+                }
+                None => {
+                    log::warn!(
+                        "The verified repository has no signature key: it only detects corrupted resources"
+                    );
+                    None
+                }
+            };
+
+            Ok(Self {
+                backend: repo_desc.backend.initialize().await?,
+                signature_key,
+            })
+        })
+    }
+
+    fn seal(
+        &self,
+        resource_desc: &ResourceDesc,
+        data: &[u8],
+        signature: Option<&[u8]>,
+    ) -> Result<Vec<u8>> {
+        let message = signed_message(&resource_path(resource_desc), data);
+        match (&self.signature_key, signature) {
+            (Some(key), Some(signature)) => verify_signature(key, &message, signature)?,
+            (Some(_), None) => bail!("the repository only accepts signed resources"),
+            (None, Some(_)) => bail!("no signature key is configured to verify the resource"),
+            (None, None) => {}
+        }
+
+        let resource = VerifiedResource {
+            sha256: hex::encode(Sha256::digest(&message)),
+            signature: signature.map(|signature| STANDARD.encode(signature)),
+            data: STANDARD.encode(data),
+        };
+        Ok(serde_json::to_vec(&resource)?)
+    }
+
+    /// Verify a stored resource, and return its data and signature.
+    fn open(
+        &self,
+        resource_desc: &ResourceDesc,
+        resource_byte: &[u8],
+    ) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+        let path = resource_path(resource_desc);
+        let resource: VerifiedResource = serde_json::from_slice(resource_byte)
+            .with_context(|| format!("resource {path} is not integrity protected"))?;
+        let data = STANDARD
+            .decode(&resource.data)
+            .with_context(|| format!("illegal data of resource {path}"))?;
+
+        let message = signed_message(&path, &data);
+        if hex::encode(Sha256::digest(&message)) != resource.sha256 {
+            bail!("integrity check of resource {path} failed: digest mismatch");
+        }
+
+        let signature = resource
+            .signature
+            .map(|signature| STANDARD.decode(signature))
+            .transpose()
+            .with_context(|| format!("illegal signature of resource {path}"))?;
+        if let Some(key) = &self.signature_key {
+            let signature = signature
+                .as_ref()
+                .ok_or_else(|| anyhow!("resource {path} is not signed"))?;
+            verify_signature(key, &message, signature)
+                .with_context(|| format!("integrity check of resource {path} failed"))?;
+        }
+
+        Ok((data, signature))
+    }
+}
+
+#[async_trait::async_trait]
+impl Repository for VerifiedRepository {
+    async fn read_secret_resource(&self, resource_desc: ResourceDesc) -> Result<Vec<u8>> {
+        let resource_byte = self
+            .backend
+            .read()
+            .await
+            .read_secret_resource(resource_desc.clone())
+            .await?;
+        Ok(self.open(&resource_desc, &resource_byte)?.0)
+    }
+
+    async fn write_secret_resource(
+        &mut self,
+        resource_desc: ResourceDesc,
+        data: &[u8],
+    ) -> Result<()> {
+        let resource_byte = self.seal(&resource_desc, data, None)?;
+        self.backend
+            .write()
+            .await
+            .write_secret_resource(resource_desc, &resource_byte)
+            .await
+    }
+
+    async fn write_signed_secret_resource(
+        &mut self,
+        resource_desc: ResourceDesc,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        let resource_byte = self.seal(&resource_desc, data, Some(signature))?;
+        self.backend
+            .write()
+            .await
+            .write_secret_resource(resource_desc, &resource_byte)
+            .await
+    }
+
+    async fn read_signed_secret_resource(
+        &self,
+        resource_desc: ResourceDesc,
+    ) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+        let resource_byte = self
+            .backend
+            .read()
+            .await
+            .read_secret_resource(resource_desc.clone())
+            .await?;
+        self.open(&resource_desc, &resource_byte)
+    }
+
+    /// The size and checksum reported by the backend are the ones of the
+    /// stored record, so they are computed again from the verified data. A
+    /// resource failing its verification is listed with the error, so that
+    /// the other resources are still listed.
+    async fn list_secret_resources(&self) -> Result<Vec<ResourceMetadata>> {
+        let listed = self.backend.read().await.list_secret_resources().await?;
+        let mut resources = Vec::new();
+        for metadata in listed {
+            match self.verified_metadata(metadata.clone()).await {
+                Ok(metadata) => resources.push(metadata),
+                Err(e) => resources.push(ResourceMetadata {
+                    integrity_error: Some(format!("{e:#}")),
+                    ..metadata
+                }),
+            }
+        }
+
+        Ok(resources)
+    }
+
+    async fn secret_resource_metadata(
+        &self,
+        resource_desc: ResourceDesc,
+    ) -> Result<ResourceMetadata> {
+        let metadata = self
+            .backend
+            .read()
+            .await
+            .secret_resource_metadata(resource_desc)
+            .await?;
+        self.verified_metadata(metadata).await
+    }
+
+    async fn read_secret_resource_with_metadata(
+        &self,
+        resource_desc: ResourceDesc,
+    ) -> Result<(Vec<u8>, ResourceMetadata)> {
+        let (resource_byte, metadata) = self
+            .backend
+            .read()
+            .await
+            .read_secret_resource_with_metadata(resource_desc.clone())
+            .await?;
+        let data = self.open(&resource_desc, &resource_byte)?.0;
+        let metadata = ResourceMetadata {
+            created_at: metadata.created_at,
+            modified_at: metadata.modified_at,
+            expires_at: metadata.expires_at,
+            ..ResourceMetadata::new(resource_desc, &data)
+        };
+        Ok((data, metadata))
+    }
+
+    async fn delete_secret_resource(&mut self, resource_desc: ResourceDesc) -> Result<()> {
+        self.backend
+            .write()
+            .await
+            .delete_secret_resource(resource_desc)
+            .await
+    }
+
+    async fn supports_expiry(&self, resource_desc: &ResourceDesc) -> bool {
+        self.backend
+            .read()
+            .await
+            .supports_expiry(resource_desc)
+            .await
+    }
+
+    async fn set_secret_resource_expiry(
+        &mut self,
+        resource_desc: ResourceDesc,
+        expires_at: Option<OffsetDateTime>,
+    ) -> Result<()> {
+        self.backend
+            .write()
+            .await
+            .set_secret_resource_expiry(resource_desc, expires_at)
+            .await
+    }
+
+    async fn purge_expired_secret_resources(&mut self) -> Result<Vec<ResourceDesc>> {
+        self.backend
+            .write()
+            .await
+            .purge_expired_secret_resources()
+            .await
+    }
+
+    async fn read_secret_resource_version(
+        &self,
+        resource_desc: ResourceDesc,
+        version: u64,
+    ) -> Result<Vec<u8>> {
+        let resource_byte = self
+            .backend
+            .read()
+            .await
+            .read_secret_resource_version(resource_desc.clone(), version)
+            .await?;
+        Ok(self.open(&resource_desc, &resource_byte)?.0)
+    }
+
+    async fn list_secret_resource_versions(&self, resource_desc: ResourceDesc) -> Result<Vec<u64>> {
+        self.backend
+            .read()
+            .await
+            .list_secret_resource_versions(resource_desc)
+            .await
+    }
+
+    /// The stored record of the version is restored as is, so that the
+    /// signature of a signed version is kept.
+    async fn rollback_secret_resource(
+        &mut self,
+        resource_desc: ResourceDesc,
+        version: u64,
+    ) -> Result<()> {
+        self.read_secret_resource_version(resource_desc.clone(), version)
+            .await?;
+        self.backend
+            .write()
+            .await
+            .rollback_secret_resource(resource_desc, version)
+            .await
+    }
+
+    async fn cache_stats(&self) -> Option<CacheStats> {
+        self.backend.read().await.cache_stats().await
+    }
+
+    async fn purge_cache(&self) -> Result<usize> {
+        self.backend.read().await.purge_cache().await
+    }
+}
+
+impl VerifiedRepository {
+    async fn verified_metadata(&self, metadata: ResourceMetadata) -> Result<ResourceMetadata> {
+        // An expired resource cannot be read anymore, and is never released.
+        if metadata
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= OffsetDateTime::now_utc())
+        {
+            return Ok(metadata);
+        }
+
+        let resource_desc = ResourceDesc {
+            repository_name: metadata.repository_name.clone(),
+            resource_type: metadata.resource_type.clone(),
+            resource_tag: metadata.resource_tag.clone(),
+        };
+        let data = self.read_secret_resource(resource_desc.clone()).await?;
+        Ok(ResourceMetadata {
+            created_at: metadata.created_at,
+            modified_at: metadata.modified_at,
+            expires_at: metadata.expires_at,
+            ..ResourceMetadata::new(resource_desc, &data)
+        })
+    }
+}
+
+fn resource_path(resource_desc: &ResourceDesc) -> String {
+    format!(
+        "{}/{}/{}",
+        resource_desc.repository_name, resource_desc.resource_type, resource_desc.resource_tag
+    )
+}
+
+/// The message which the digest and the signature of the resource of `path`
+/// are computed over: the length of the path as a 64-bit big-endian integer,
+/// the path, and the data. It binds the data to the path of the resource.
+pub(crate) fn signed_message(path: &str, data: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(8 + path.len() + data.len());
+    message.extend_from_slice(&(path.len() as u64).to_be_bytes());
+    message.extend_from_slice(path.as_bytes());
+    message.extend_from_slice(data);
+    message
+}
+
+/// Verify a detached signature of `data`. Ed25519 signatures are over the
+/// data itself, ECDSA and RSA (PKCS#1 v1.5) signatures over its SHA-256
+/// digest.
+fn verify_signature(key: &PKey<Public>, data: &[u8], signature: &[u8]) -> Result<()> {
+    let mut verifier = match key.id() {
+        Id::ED25519 => Verifier::new_without_digest(key)?,
+        _ => Verifier::new(MessageDigest::sha256(), key)?,
+    };
+    if !verifier.verify_oneshot(signature, data)? {
+        bail!("invalid signature");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::pkey::PKey;
+    use openssl::sign::Signer;
+
+    use super::{signed_message, VerifiedRepoDesc, VerifiedRepository};
+    use crate::resource::{local_fs::LocalFsRepoDesc, Repository, RepositoryConfig, ResourceDesc};
+
+    #[tokio::test]
+    async fn verify_resources() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let signing_key = PKey::generate_ed25519().unwrap();
+        let key_path = tmp_dir.path().join("signature-key.pem");
+        std::fs::write(&key_path, signing_key.public_key_to_pem().unwrap()).unwrap();
+
+        let repo_dir = tmp_dir.path().join("repository");
+        let mut repository = VerifiedRepository::new(&VerifiedRepoDesc {
+            signature_key: Some(key_path.to_string_lossy().to_string()),
+            backend: Box::new(RepositoryConfig::LocalFs(LocalFsRepoDesc {
+                dir_path: Some(repo_dir.to_string_lossy().to_string()),
+                encryption: None,
+            })),
+        })
+        .await
+        .unwrap();
+
+        let resource_desc = ResourceDesc {
+            repository_name: "default".into(),
+            resource_type: "key".into(),
+            resource_tag: "1".into(),
+        };
+        let sign = |data: &[u8]| {
+            let mut signer = Signer::new_without_digest(&signing_key).unwrap();
+            signer
+                .sign_oneshot_to_vec(&signed_message("default/key/1", data))
+                .unwrap()
+        };
+
+        // Unsigned and wrongly signed resources are refused.
+        assert!(repository
+            .write_secret_resource(resource_desc.clone(), b"data")
+            .await
+            .is_err());
+        assert!(repository
+            .write_signed_secret_resource(resource_desc.clone(), b"data", &sign(b"other"))
+            .await
+            .is_err());
+
+        repository
+            .write_signed_secret_resource(resource_desc.clone(), b"data", &sign(b"data"))
+            .await
+            .unwrap();
+        let data = repository
+            .read_secret_resource(resource_desc.clone())
+            .await
+            .unwrap();
+        assert_eq!(data, b"data");
+
+        let metadata = repository
+            .secret_resource_metadata(resource_desc.clone())
+            .await
+            .unwrap();
+        assert_eq!(metadata.size, 4);
+
+        // A resource moved to another path is never released.
+        let resource_path = repo_dir.join("default/key/1");
+        std::fs::copy(&resource_path, repo_dir.join("default/key/2")).unwrap();
+        let moved_desc = ResourceDesc {
+            resource_tag: "2".into(),
+            ..resource_desc.clone()
+        };
+        assert!(repository.read_secret_resource(moved_desc).await.is_err());
+
+        // Tampered content is never released.
+        let tampered = std::fs::read_to_string(&resource_path)
+            .unwrap()
+            .replace("ZGF0YQ==", "ZGF0Yg==");
+        std::fs::write(&resource_path, tampered).unwrap();
+        assert!(repository
+            .read_secret_resource(resource_desc)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn digest_binds_path() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut repository = VerifiedRepository::new(&VerifiedRepoDesc {
+            signature_key: None,
+            backend: Box::new(RepositoryConfig::LocalFs(LocalFsRepoDesc {
+                dir_path: Some(tmp_dir.path().to_string_lossy().to_string()),
+                encryption: None,
+            })),
+        })
+        .await
+        .unwrap();
+
+        let resource_desc = ResourceDesc {
+            repository_name: "tenant-a".into(),
+            resource_type: "key".into(),
+            resource_tag: "prod".into(),
+        };
+        repository
+            .write_secret_resource(resource_desc.clone(), b"data")
+            .await
+            .unwrap();
+
+        // The record of `tenant-a/key/prod` moved to `tenant-b/key/prod`.
+        std::fs::create_dir_all(tmp_dir.path().join("tenant-b/key")).unwrap();
+        std::fs::copy(
+            tmp_dir.path().join("tenant-a/key/prod"),
+            tmp_dir.path().join("tenant-b/key/prod"),
+        )
+        .unwrap();
+        let moved_desc = ResourceDesc {
+            repository_name: "tenant-b".into(),
+            ..resource_desc.clone()
+        };
+        assert!(repository.read_secret_resource(moved_desc).await.is_err());
+        assert_eq!(
+            repository
+                .read_secret_resource(resource_desc)
+                .await
+                .unwrap(),
+            b"data"
+        );
+
+        // The moved record is listed with its integrity error, next to the
+        // verified resource.
+        let mut listed = repository.list_secret_resources().await.unwrap();
+        listed.sort_by(|a, b| a.repository_name.cmp(&b.repository_name));
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].integrity_error, None);
+        assert_eq!(listed[0].size, 4);
+        assert!(listed[1].integrity_error.is_some());
+    }
+}
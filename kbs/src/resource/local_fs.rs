@@ -2,45 +2,56 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use super::{Repository, ResourceDesc};
-use anyhow::{Context, Result};
+use super::{
+    envelope::{Envelope, MasterKeyConfig},
+    Repository, ResourceDesc, ResourceMetadata,
+};
+use anyhow::{bail, Context, Result};
 use serde::Deserialize;
 use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
 
 pub const DEFAULT_REPO_DIR_PATH: &str = "/opt/confidential-containers/kbs/repository";
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct LocalFsRepoDesc {
     pub dir_path: Option<String>,
+
+    /// Master key used to envelope encrypt the resources on disk. Resources
+    /// are stored in plaintext if not given.
+    pub encryption: Option<MasterKeyConfig>,
 }
 
 impl Default for LocalFsRepoDesc {
     fn default() -> Self {
         Self {
             dir_path: Some(DEFAULT_REPO_DIR_PATH.to_string()),
+            encryption: None,
         }
     }
 }
 
 pub struct LocalFs {
     pub repo_dir_path: String,
+    envelope: Option<Envelope>,
 }
 
+/// Name of the directory holding the history of the resources of a type,
+/// i.e. `<repository>/<type>/.versions/<tag>/<version>`.
+const VERSIONS_DIR: &str = ".versions";
+
+/// Name of the directory holding the expiry time of the resources of a type,
+/// i.e. `<repository>/<type>/.expiry/<tag>`, in seconds since the epoch.
+const EXPIRY_DIR: &str = ".expiry";
+
 #[async_trait::async_trait]
 impl Repository for LocalFs {
     async fn read_secret_resource(&self, resource_desc: ResourceDesc) -> Result<Vec<u8>> {
-        let mut resource_path = PathBuf::from(&self.repo_dir_path);
-
-        let ref_resource_path = format!(
-            "{}/{}/{}",
-            resource_desc.repository_name, resource_desc.resource_type, resource_desc.resource_tag
-        );
-        resource_path.push(ref_resource_path);
-
-        let resource_byte = tokio::fs::read(&resource_path)
+        self.check_expiry(&resource_desc).await?;
+        let resource_byte = tokio::fs::read(self.resource_path(&resource_desc))
             .await
             .context("read resource from local fs")?;
-        Ok(resource_byte)
+        self.open(&resource_desc, resource_byte).await
     }
 
     async fn write_secret_resource(
@@ -48,38 +59,372 @@ impl Repository for LocalFs {
         resource_desc: ResourceDesc,
         data: &[u8],
     ) -> Result<()> {
-        let mut resource_path = PathBuf::from(&self.repo_dir_path);
-        resource_path.push(resource_desc.repository_name);
-        resource_path.push(resource_desc.resource_type);
+        let data = match &self.envelope {
+            Some(envelope) => {
+                let ref_resource_path = ref_resource_path(&resource_desc);
+                envelope
+                    .seal(data, ref_resource_path.as_bytes())
+                    .await
+                    .with_context(|| format!("encrypt resource {ref_resource_path}"))?
+            }
+            None => data.to_vec(),
+        };
 
-        if !Path::new(&resource_path).exists() {
-            tokio::fs::create_dir_all(&resource_path)
+        let resource_path = self.resource_path(&resource_desc);
+        let versions_dir = self.versions_dir(&resource_desc);
+        if !versions_dir.exists() {
+            tokio::fs::create_dir_all(&versions_dir)
                 .await
                 .context("create new resource path")?;
         }
 
-        resource_path.push(resource_desc.resource_tag);
+        let versions = self.versions(&resource_desc).await?;
+        let mut version = versions.last().copied().unwrap_or_default() + 1;
+        if versions.is_empty() && resource_path.exists() {
+            // Keep a resource written before versioning as its first version.
+            tokio::fs::copy(&resource_path, versions_dir.join("1"))
+                .await
+                .context("save resource version")?;
+            version = 2;
+        }
 
+        tokio::fs::write(versions_dir.join(version.to_string()), &data)
+            .await
+            .context("write resource version")?;
         tokio::fs::write(resource_path, data)
             .await
             .context("write local fs")
     }
+
+    async fn read_secret_resource_version(
+        &self,
+        resource_desc: ResourceDesc,
+        version: u64,
+    ) -> Result<Vec<u8>> {
+        self.check_expiry(&resource_desc).await?;
+        let versions = self
+            .list_secret_resource_versions(resource_desc.clone())
+            .await?;
+        if !versions.contains(&version) {
+            bail!(
+                "no version {version} of resource {}",
+                ref_resource_path(&resource_desc)
+            );
+        }
+
+        let mut version_path = self.versions_dir(&resource_desc).join(version.to_string());
+        if !version_path.exists() {
+            // The only version of a resource written before versioning.
+            version_path = self.resource_path(&resource_desc);
+        }
+
+        let resource_byte = tokio::fs::read(version_path)
+            .await
+            .context("read resource version from local fs")?;
+        self.open(&resource_desc, resource_byte).await
+    }
+
+    async fn list_secret_resource_versions(&self, resource_desc: ResourceDesc) -> Result<Vec<u64>> {
+        let versions = self.versions(&resource_desc).await?;
+        if versions.is_empty() && self.resource_path(&resource_desc).exists() {
+            return Ok(vec![1]);
+        }
+
+        Ok(versions)
+    }
+
+    async fn supports_expiry(&self, _resource_desc: &ResourceDesc) -> bool {
+        true
+    }
+
+    async fn set_secret_resource_expiry(
+        &mut self,
+        resource_desc: ResourceDesc,
+        expires_at: Option<OffsetDateTime>,
+    ) -> Result<()> {
+        let expiry_path = self.expiry_path(&resource_desc);
+        let Some(expires_at) = expires_at else {
+            if expiry_path.exists() {
+                tokio::fs::remove_file(expiry_path)
+                    .await
+                    .context("remove resource expiry")?;
+            }
+            return Ok(());
+        };
+
+        if let Some(expiry_dir) = expiry_path.parent() {
+            tokio::fs::create_dir_all(expiry_dir)
+                .await
+                .context("create resource expiry path")?;
+        }
+
+        tokio::fs::write(expiry_path, expires_at.unix_timestamp().to_string())
+            .await
+            .context("write resource expiry")
+    }
+
+    async fn purge_expired_secret_resources(&mut self) -> Result<Vec<ResourceDesc>> {
+        let mut purged = Vec::new();
+        for resource_desc in self.resources(EXPIRY_DIR).await? {
+            if self.is_expired(&resource_desc).await? {
+                self.remove(&resource_desc).await?;
+                purged.push(resource_desc);
+            }
+        }
+
+        Ok(purged)
+    }
+
+    async fn delete_secret_resource(&mut self, resource_desc: ResourceDesc) -> Result<()> {
+        if !self.resource_path(&resource_desc).exists() {
+            bail!("resource {} not found", ref_resource_path(&resource_desc));
+        }
+
+        self.remove(&resource_desc).await
+    }
+
+    async fn list_secret_resources(&self) -> Result<Vec<ResourceMetadata>> {
+        let mut resources = Vec::new();
+        for resource_desc in self.resources("").await? {
+            resources.push(self.secret_resource_metadata(resource_desc).await?);
+        }
+
+        Ok(resources)
+    }
+
+    async fn secret_resource_metadata(
+        &self,
+        resource_desc: ResourceDesc,
+    ) -> Result<ResourceMetadata> {
+        Ok(self.read_with_metadata(resource_desc).await?.1)
+    }
+
+    async fn read_secret_resource_with_metadata(
+        &self,
+        resource_desc: ResourceDesc,
+    ) -> Result<(Vec<u8>, ResourceMetadata)> {
+        self.check_expiry(&resource_desc).await?;
+        self.read_with_metadata(resource_desc).await
+    }
 }
 
 impl LocalFs {
-    pub fn new(repo_desc: &LocalFsRepoDesc) -> Result<Self> {
+    pub async fn new(repo_desc: &LocalFsRepoDesc) -> Result<Self> {
+        let envelope = match &repo_desc.encryption {
+            Some(config) => Some(Envelope::new(config).await?),
+            None => None,
+        };
+
         Ok(Self {
             repo_dir_path: repo_desc
                 .dir_path
                 .clone()
                 .unwrap_or(DEFAULT_REPO_DIR_PATH.to_string()),
+            envelope,
         })
     }
+
+    fn resource_path(&self, resource_desc: &ResourceDesc) -> PathBuf {
+        let mut resource_path = PathBuf::from(&self.repo_dir_path);
+        resource_path.push(ref_resource_path(resource_desc));
+        resource_path
+    }
+
+    fn versions_dir(&self, resource_desc: &ResourceDesc) -> PathBuf {
+        let mut versions_dir = PathBuf::from(&self.repo_dir_path);
+        versions_dir.push(&resource_desc.repository_name);
+        versions_dir.push(&resource_desc.resource_type);
+        versions_dir.push(VERSIONS_DIR);
+        versions_dir.push(&resource_desc.resource_tag);
+        versions_dir
+    }
+
+    fn expiry_path(&self, resource_desc: &ResourceDesc) -> PathBuf {
+        let mut expiry_path = PathBuf::from(&self.repo_dir_path);
+        expiry_path.push(&resource_desc.repository_name);
+        expiry_path.push(&resource_desc.resource_type);
+        expiry_path.push(EXPIRY_DIR);
+        expiry_path.push(&resource_desc.resource_tag);
+        expiry_path
+    }
+
+    /// Read a resource with its metadata, even if it has expired.
+    async fn read_with_metadata(
+        &self,
+        resource_desc: ResourceDesc,
+    ) -> Result<(Vec<u8>, ResourceMetadata)> {
+        let file_metadata = tokio::fs::metadata(self.resource_path(&resource_desc))
+            .await
+            .context("read resource metadata from local fs")?;
+        let resource_byte = tokio::fs::read(self.resource_path(&resource_desc))
+            .await
+            .context("read resource from local fs")?;
+        let data = self.open(&resource_desc, resource_byte).await?;
+        let expires_at = self.expiry(&resource_desc).await?;
+
+        let mut metadata = ResourceMetadata::new(resource_desc, &data);
+        metadata.created_at = file_metadata.created().ok().map(OffsetDateTime::from);
+        metadata.modified_at = file_metadata.modified().ok().map(OffsetDateTime::from);
+        metadata.expires_at = expires_at;
+        Ok((data, metadata))
+    }
+
+    async fn expiry(&self, resource_desc: &ResourceDesc) -> Result<Option<OffsetDateTime>> {
+        let expiry_path = self.expiry_path(resource_desc);
+        if !expiry_path.exists() {
+            return Ok(None);
+        }
+
+        let expires_at: i64 = tokio::fs::read_to_string(expiry_path)
+            .await
+            .context("read resource expiry")?
+            .trim()
+            .parse()
+            .context("illegal resource expiry")?;
+        let expires_at =
+            OffsetDateTime::from_unix_timestamp(expires_at).context("illegal resource expiry")?;
+        Ok(Some(expires_at))
+    }
+
+    async fn is_expired(&self, resource_desc: &ResourceDesc) -> Result<bool> {
+        Ok(self
+            .expiry(resource_desc)
+            .await?
+            .is_some_and(|expires_at| OffsetDateTime::now_utc() >= expires_at))
+    }
+
+    async fn check_expiry(&self, resource_desc: &ResourceDesc) -> Result<()> {
+        if self.is_expired(resource_desc).await? {
+            bail!("resource {} has expired", ref_resource_path(resource_desc));
+        }
+
+        Ok(())
+    }
+
+    /// Remove a resource together with its history and expiry.
+    async fn remove(&self, resource_desc: &ResourceDesc) -> Result<()> {
+        let resource_path = self.resource_path(resource_desc);
+        if resource_path.exists() {
+            tokio::fs::remove_file(resource_path)
+                .await
+                .context("remove resource")?;
+        }
+
+        let versions_dir = self.versions_dir(resource_desc);
+        if versions_dir.exists() {
+            tokio::fs::remove_dir_all(versions_dir)
+                .await
+                .context("remove resource versions")?;
+        }
+
+        let expiry_path = self.expiry_path(resource_desc);
+        if expiry_path.exists() {
+            tokio::fs::remove_file(expiry_path)
+                .await
+                .context("remove resource expiry")?;
+        }
+
+        Ok(())
+    }
+
+    /// All the resources of the repository, i.e. the files in
+    /// `<repository>/<type>/<subdir>`. `subdir` is one of the bookkeeping
+    /// directories, or empty for the resources themselves.
+    async fn resources(&self, subdir: &str) -> Result<Vec<ResourceDesc>> {
+        let mut resources = Vec::new();
+        let repo_dir = PathBuf::from(&self.repo_dir_path);
+        for repository_name in entries(&repo_dir, true).await? {
+            let repository_dir = repo_dir.join(&repository_name);
+            for resource_type in entries(&repository_dir, true).await? {
+                let dir = repository_dir.join(&resource_type).join(subdir);
+                if !dir.exists() {
+                    continue;
+                }
+
+                for resource_tag in entries(&dir, false).await? {
+                    resources.push(ResourceDesc {
+                        repository_name: repository_name.clone(),
+                        resource_type: resource_type.clone(),
+                        resource_tag,
+                    });
+                }
+            }
+        }
+
+        Ok(resources)
+    }
+
+    /// The versions kept in the history of a resource, sorted.
+    async fn versions(&self, resource_desc: &ResourceDesc) -> Result<Vec<u64>> {
+        let versions_dir = self.versions_dir(resource_desc);
+        if !versions_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut versions = Vec::new();
+        let mut entries = tokio::fs::read_dir(versions_dir)
+            .await
+            .context("read resource versions")?;
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(version) = entry.file_name().to_str().and_then(|v| v.parse().ok()) {
+                versions.push(version);
+            }
+        }
+
+        versions.sort_unstable();
+        Ok(versions)
+    }
+
+    async fn open(&self, resource_desc: &ResourceDesc, resource_byte: Vec<u8>) -> Result<Vec<u8>> {
+        match &self.envelope {
+            Some(envelope) => {
+                let ref_resource_path = ref_resource_path(resource_desc);
+                envelope
+                    .open(&resource_byte, ref_resource_path.as_bytes())
+                    .await
+                    .with_context(|| format!("decrypt resource {ref_resource_path}"))
+            }
+            None => Ok(resource_byte),
+        }
+    }
+}
+
+/// Names of the subdirectories, or of the files of `dir`, skipping the
+/// hidden ones used for bookkeeping.
+async fn entries(dir: &Path, dirs: bool) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir)
+        .await
+        .with_context(|| format!("read directory {}", dir.display()))?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_type().await?.is_dir() != dirs {
+            continue;
+        }
+
+        match entry.file_name().to_str() {
+            Some(name) if !name.starts_with('.') => names.push(name.to_string()),
+            _ => {}
+        }
+    }
+
+    Ok(names)
+}
+
+fn ref_resource_path(resource_desc: &ResourceDesc) -> String {
+    format!(
+        "{}/{}/{}",
+        resource_desc.repository_name, resource_desc.resource_type, resource_desc.resource_tag
+    )
 }
 
 #[cfg(test)]
 mod tests {
+    use std::io::Write;
+
+    use time::{Duration, OffsetDateTime};
+
     use crate::resource::{
+        envelope::MasterKeyConfig,
         local_fs::{LocalFs, LocalFsRepoDesc},
         Repository, ResourceDesc,
     };
@@ -91,9 +436,45 @@ mod tests {
         let tmp_dir = tempfile::tempdir().expect("create temp dir failed");
         let repo_desc = LocalFsRepoDesc {
             dir_path: Some(tmp_dir.path().to_string_lossy().to_string()),
+            encryption: None,
+        };
+
+        let mut local_fs = LocalFs::new(&repo_desc)
+            .await
+            .expect("create local fs failed");
+        let resource_desc = ResourceDesc {
+            repository_name: "default".into(),
+            resource_type: "test".into(),
+            resource_tag: "test".into(),
+        };
+
+        local_fs
+            .write_secret_resource(resource_desc.clone(), TEST_DATA)
+            .await
+            .expect("write secret resource failed");
+        let data = local_fs
+            .read_secret_resource(resource_desc)
+            .await
+            .expect("read secret resource failed");
+
+        assert_eq!(&data[..], TEST_DATA);
+    }
+
+    #[tokio::test]
+    async fn write_and_read_encrypted_resource() {
+        let tmp_dir = tempfile::tempdir().expect("create temp dir failed");
+        let mut key_file = tempfile::NamedTempFile::new().expect("create key file failed");
+        key_file.write_all(&[7; 32]).expect("write key file failed");
+        let repo_desc = LocalFsRepoDesc {
+            dir_path: Some(tmp_dir.path().to_string_lossy().to_string()),
+            encryption: Some(MasterKeyConfig::File {
+                key_path: key_file.path().to_string_lossy().to_string(),
+            }),
         };
 
-        let mut local_fs = LocalFs::new(&repo_desc).expect("create local fs failed");
+        let mut local_fs = LocalFs::new(&repo_desc)
+            .await
+            .expect("create local fs failed");
         let resource_desc = ResourceDesc {
             repository_name: "default".into(),
             resource_type: "test".into(),
@@ -104,6 +485,10 @@ mod tests {
             .write_secret_resource(resource_desc.clone(), TEST_DATA)
             .await
             .expect("write secret resource failed");
+        let on_disk = std::fs::read(tmp_dir.path().join("default/test/test"))
+            .expect("read resource file failed");
+        assert!(!on_disk.windows(TEST_DATA.len()).any(|w| w == TEST_DATA));
+
         let data = local_fs
             .read_secret_resource(resource_desc)
             .await
@@ -111,4 +496,199 @@ mod tests {
 
         assert_eq!(&data[..], TEST_DATA);
     }
+
+    #[tokio::test]
+    async fn resource_versions() {
+        let tmp_dir = tempfile::tempdir().expect("create temp dir failed");
+        let repo_desc = LocalFsRepoDesc {
+            dir_path: Some(tmp_dir.path().to_string_lossy().to_string()),
+            encryption: None,
+        };
+
+        let mut local_fs = LocalFs::new(&repo_desc)
+            .await
+            .expect("create local fs failed");
+        let resource_desc = ResourceDesc {
+            repository_name: "default".into(),
+            resource_type: "test".into(),
+            resource_tag: "test".into(),
+        };
+
+        for data in [b"v1", b"v2", b"v3"] {
+            local_fs
+                .write_secret_resource(resource_desc.clone(), data)
+                .await
+                .expect("write secret resource failed");
+        }
+
+        let versions = local_fs
+            .list_secret_resource_versions(resource_desc.clone())
+            .await
+            .expect("list versions failed");
+        assert_eq!(versions, vec![1, 2, 3]);
+
+        let data = local_fs
+            .read_secret_resource_version(resource_desc.clone(), 2)
+            .await
+            .expect("read version failed");
+        assert_eq!(&data[..], b"v2");
+        assert!(local_fs
+            .read_secret_resource_version(resource_desc.clone(), 4)
+            .await
+            .is_err());
+
+        local_fs
+            .rollback_secret_resource(resource_desc.clone(), 1)
+            .await
+            .expect("rollback failed");
+        let data = local_fs
+            .read_secret_resource(resource_desc.clone())
+            .await
+            .expect("read secret resource failed");
+        assert_eq!(&data[..], b"v1");
+
+        let versions = local_fs
+            .list_secret_resource_versions(resource_desc)
+            .await
+            .expect("list versions failed");
+        assert_eq!(versions, vec![1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn expired_resource() {
+        let tmp_dir = tempfile::tempdir().expect("create temp dir failed");
+        let repo_desc = LocalFsRepoDesc {
+            dir_path: Some(tmp_dir.path().to_string_lossy().to_string()),
+            encryption: None,
+        };
+
+        let mut local_fs = LocalFs::new(&repo_desc)
+            .await
+            .expect("create local fs failed");
+        let expired = ResourceDesc {
+            repository_name: "default".into(),
+            resource_type: "test".into(),
+            resource_tag: "expired".into(),
+        };
+        let valid = ResourceDesc {
+            resource_tag: "valid".into(),
+            ..expired.clone()
+        };
+
+        for (resource_desc, expires_at) in [
+            (&expired, OffsetDateTime::now_utc() - Duration::minutes(1)),
+            (&valid, OffsetDateTime::now_utc() + Duration::hours(1)),
+        ] {
+            local_fs
+                .write_secret_resource(resource_desc.clone(), TEST_DATA)
+                .await
+                .expect("write secret resource failed");
+            local_fs
+                .set_secret_resource_expiry(resource_desc.clone(), Some(expires_at))
+                .await
+                .expect("set expiry failed");
+        }
+
+        assert!(local_fs
+            .read_secret_resource(expired.clone())
+            .await
+            .is_err());
+        local_fs
+            .read_secret_resource(valid.clone())
+            .await
+            .expect("read secret resource failed");
+
+        let purged = local_fs
+            .purge_expired_secret_resources()
+            .await
+            .expect("purge failed");
+        assert_eq!(purged.len(), 1);
+        assert_eq!(purged[0].resource_tag, "expired");
+        assert!(!tmp_dir.path().join("default/test/expired").exists());
+        assert!(tmp_dir.path().join("default/test/valid").exists());
+    }
+
+    #[tokio::test]
+    async fn delete_resource() {
+        let tmp_dir = tempfile::tempdir().expect("create temp dir failed");
+        let repo_desc = LocalFsRepoDesc {
+            dir_path: Some(tmp_dir.path().to_string_lossy().to_string()),
+            encryption: None,
+        };
+
+        let mut local_fs = LocalFs::new(&repo_desc)
+            .await
+            .expect("create local fs failed");
+        let resource_desc = ResourceDesc {
+            repository_name: "default".into(),
+            resource_type: "test".into(),
+            resource_tag: "test".into(),
+        };
+
+        local_fs
+            .write_secret_resource(resource_desc.clone(), TEST_DATA)
+            .await
+            .expect("write secret resource failed");
+        local_fs
+            .delete_secret_resource(resource_desc.clone())
+            .await
+            .expect("delete secret resource failed");
+
+        assert!(local_fs
+            .read_secret_resource(resource_desc.clone())
+            .await
+            .is_err());
+        assert!(local_fs
+            .list_secret_resource_versions(resource_desc.clone())
+            .await
+            .expect("list versions failed")
+            .is_empty());
+        assert!(local_fs
+            .delete_secret_resource(resource_desc)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn list_resources() {
+        let tmp_dir = tempfile::tempdir().expect("create temp dir failed");
+        let repo_desc = LocalFsRepoDesc {
+            dir_path: Some(tmp_dir.path().to_string_lossy().to_string()),
+            encryption: None,
+        };
+
+        let mut local_fs = LocalFs::new(&repo_desc)
+            .await
+            .expect("create local fs failed");
+        for tag in ["a", "b"] {
+            let resource_desc = ResourceDesc {
+                repository_name: "default".into(),
+                resource_type: "test".into(),
+                resource_tag: tag.into(),
+            };
+            local_fs
+                .write_secret_resource(resource_desc.clone(), TEST_DATA)
+                .await
+                .expect("write secret resource failed");
+            local_fs
+                .write_secret_resource(resource_desc, TEST_DATA)
+                .await
+                .expect("write secret resource failed");
+        }
+
+        let mut resources = local_fs
+            .list_secret_resources()
+            .await
+            .expect("list resources failed");
+        resources.sort_by(|a, b| a.resource_tag.cmp(&b.resource_tag));
+
+        let tags: Vec<_> = resources.iter().map(|r| r.resource_tag.as_str()).collect();
+        assert_eq!(tags, ["a", "b"]);
+        assert_eq!(resources[0].size, TEST_DATA.len() as u64);
+        assert_eq!(
+            resources[0].checksum,
+            "810ff2fb242a5dee4220f2cb0e6a519891fb67f2f828a6cab4ef8894633b1f50"
+        );
+        assert!(resources[0].modified_at.is_some());
+    }
 }
@@ -3,17 +3,57 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use anyhow::*;
-use serde::Deserialize;
+use log::{info, warn};
+use opentelemetry::KeyValue;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::Path;
 use std::sync::Arc;
+use time::OffsetDateTime;
 use tokio::sync::RwLock;
 
+use crate::telemetry::traced;
+
+mod backup;
+mod bundle;
+mod cache;
+mod envelope;
+mod integrity;
 mod local_fs;
+mod replication;
+
+pub use backup::{Backup, BackupConfig};
+pub(crate) use bundle::{export_resources, import_resources};
+pub use cache::CacheStats;
+pub use replication::{Replication, ReplicationConfig};
 
 #[cfg(feature = "aliyun")]
 mod aliyun_kms;
 
+#[cfg(feature = "s3")]
+mod s3;
+
+#[cfg(feature = "vault")]
+mod vault_kv;
+
+#[cfg(feature = "azure")]
+mod azure_key_vault;
+
+#[cfg(feature = "aws")]
+mod aws_secrets_manager;
+
+#[cfg(feature = "gcp")]
+mod gcp_secret_manager;
+
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
+
+#[cfg(feature = "postgres")]
+mod postgres;
+
+mod router;
+
 /// Interface of a `Repository`.
 #[async_trait::async_trait]
 pub trait Repository {
@@ -26,8 +66,135 @@ pub trait Repository {
         resource_desc: ResourceDesc,
         data: &[u8],
     ) -> Result<()>;
+
+    /// Write a secret resource with a detached signature of its data, that
+    /// is verified before the resource is released.
+    async fn write_signed_secret_resource(
+        &mut self,
+        _resource_desc: ResourceDesc,
+        _data: &[u8],
+        _signature: &[u8],
+    ) -> Result<()> {
+        bail!("the repository does not support resource signatures")
+    }
+
+    /// Read a secret resource with the detached signature it was written
+    /// with, if any.
+    async fn read_signed_secret_resource(
+        &self,
+        resource_desc: ResourceDesc,
+    ) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+        Ok((self.read_secret_resource(resource_desc).await?, None))
+    }
+
+    /// List all the secret resources of the repository with their metadata.
+    async fn list_secret_resources(&self) -> Result<Vec<ResourceMetadata>> {
+        bail!("the repository does not support listing resources")
+    }
+
+    /// Get the metadata of a secret resource. By default the resource is read
+    /// to compute its size and checksum, and its timestamps are unknown.
+    async fn secret_resource_metadata(
+        &self,
+        resource_desc: ResourceDesc,
+    ) -> Result<ResourceMetadata> {
+        let data = self.read_secret_resource(resource_desc.clone()).await?;
+        Ok(ResourceMetadata::new(resource_desc, &data))
+    }
+
+    /// Read a secret resource with its metadata. By default the metadata are
+    /// computed from the data, and only got from the repository if it
+    /// supports the expiry of the resource.
+    async fn read_secret_resource_with_metadata(
+        &self,
+        resource_desc: ResourceDesc,
+    ) -> Result<(Vec<u8>, ResourceMetadata)> {
+        let data = self.read_secret_resource(resource_desc.clone()).await?;
+        let metadata = match self.supports_expiry(&resource_desc).await {
+            true => self.secret_resource_metadata(resource_desc).await?,
+            false => ResourceMetadata::new(resource_desc, &data),
+        };
+        Ok((data, metadata))
+    }
+
+    /// Delete a secret resource, including all its versions.
+    async fn delete_secret_resource(&mut self, _resource_desc: ResourceDesc) -> Result<()> {
+        bail!("the repository does not support deleting resources")
+    }
+
+    /// Whether the repository supports the expiry of the given resource.
+    async fn supports_expiry(&self, _resource_desc: &ResourceDesc) -> bool {
+        false
+    }
+
+    /// Set the time after which a secret resource expires and is refused, or
+    /// clear it with `None`.
+    async fn set_secret_resource_expiry(
+        &mut self,
+        _resource_desc: ResourceDesc,
+        expires_at: Option<OffsetDateTime>,
+    ) -> Result<()> {
+        if expires_at.is_some() {
+            bail!("the repository does not support resource expiry");
+        }
+
+        Ok(())
+    }
+
+    /// Delete the secret resources that have expired, and return them.
+    async fn purge_expired_secret_resources(&mut self) -> Result<Vec<ResourceDesc>> {
+        Ok(Vec::new())
+    }
+
+    /// Read the given version of a secret resource. Versions are numbered
+    /// from 1, every write creates a new version.
+    async fn read_secret_resource_version(
+        &self,
+        _resource_desc: ResourceDesc,
+        _version: u64,
+    ) -> Result<Vec<u8>> {
+        bail!("the repository does not support resource versioning")
+    }
+
+    /// List the available versions of a secret resource, oldest first.
+    async fn list_secret_resource_versions(
+        &self,
+        _resource_desc: ResourceDesc,
+    ) -> Result<Vec<u64>> {
+        bail!("the repository does not support resource versioning")
+    }
+
+    /// Roll a secret resource back to the given version. The content of that
+    /// version is written again, so that it becomes the latest version.
+    async fn rollback_secret_resource(
+        &mut self,
+        resource_desc: ResourceDesc,
+        version: u64,
+    ) -> Result<()>
+    where
+        Self: Sync,
+    {
+        let data = self
+            .read_secret_resource_version(resource_desc.clone(), version)
+            .await?;
+        self.write_secret_resource(resource_desc, &data).await
+    }
+
+    /// Statistics of the resource cache, `None` if the repository is not
+    /// cached.
+    async fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+
+    /// Drop all the cached resources, and return how many were cached.
+    async fn purge_cache(&self) -> Result<usize> {
+        Ok(0)
+    }
 }
 
+/// How often the expired resources are deleted from the repository.
+const EXPIRED_RESOURCES_PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
 #[derive(Debug, Clone)]
 pub struct ResourceDesc {
     pub repository_name: String,
@@ -48,6 +215,52 @@ impl ResourceDesc {
     }
 }
 
+/// Metadata of a stored secret resource, as returned by the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceMetadata {
+    pub repository_name: String,
+    pub resource_type: String,
+    pub resource_tag: String,
+
+    /// Size of the resource data in bytes.
+    pub size: u64,
+
+    /// Hex encoded SHA-256 digest of the resource data.
+    pub checksum: String,
+
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub created_at: Option<OffsetDateTime>,
+
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub modified_at: Option<OffsetDateTime>,
+
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub expires_at: Option<OffsetDateTime>,
+
+    /// Why the integrity of the resource cannot be verified, in a listing
+    /// of a verified repository. The size and checksum are then the ones of
+    /// the stored record.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub integrity_error: Option<String>,
+}
+
+impl ResourceMetadata {
+    /// Metadata of the resource `data`, without timestamps.
+    pub fn new(resource_desc: ResourceDesc, data: &[u8]) -> Self {
+        Self {
+            repository_name: resource_desc.repository_name,
+            resource_type: resource_desc.resource_type,
+            resource_tag: resource_desc.resource_tag,
+            size: data.len() as u64,
+            checksum: hex::encode(Sha256::digest(data)),
+            created_at: None,
+            modified_at: None,
+            expires_at: None,
+            integrity_error: None,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(tag = "type")]
 pub enum RepositoryConfig {
@@ -55,10 +268,41 @@ pub enum RepositoryConfig {
 
     #[cfg(feature = "aliyun")]
     Aliyun(aliyun_kms::AliyunKmsBackendConfig),
+
+    #[cfg(feature = "s3")]
+    S3(s3::S3RepoDesc),
+
+    #[cfg(feature = "vault")]
+    VaultKv(vault_kv::VaultKvRepoDesc),
+
+    #[cfg(feature = "azure")]
+    AzureKeyVault(azure_key_vault::AzureKeyVaultRepoDesc),
+
+    #[cfg(feature = "aws")]
+    AwsSecretsManager(aws_secrets_manager::AwsSecretsManagerRepoDesc),
+
+    #[cfg(feature = "gcp")]
+    GcpSecretManager(gcp_secret_manager::GcpSecretManagerRepoDesc),
+
+    #[cfg(feature = "pkcs11")]
+    Pkcs11(pkcs11::Pkcs11RepoDesc),
+
+    #[cfg(feature = "postgres")]
+    Postgres(postgres::PostgresRepoDesc),
+
+    /// Several repositories, selected by the repository name of the resource.
+    Routed(router::RoutedRepoDesc),
+
+    /// A repository whose resources are cached in memory.
+    Cached(cache::CachedRepoDesc),
+
+    /// A repository whose resources are verified against their digest, and
+    /// optionally their signature, on every read.
+    Verified(integrity::VerifiedRepoDesc),
 }
 
 impl RepositoryConfig {
-    pub fn initialize(&self) -> Result<Arc<RwLock<dyn Repository + Send + Sync>>> {
+    pub async fn initialize(&self) -> Result<Arc<RwLock<dyn Repository + Send + Sync>>> {
         match self {
             Self::LocalFs(desc) => {
                 // Create repository dir.
@@ -75,7 +319,7 @@ impl RepositoryConfig {
                     fs::create_dir_all(format!("{}/default", &dir_path))?;
                 }
 
-                Ok(Arc::new(RwLock::new(local_fs::LocalFs::new(desc)?))
+                Ok(Arc::new(RwLock::new(local_fs::LocalFs::new(desc).await?))
                     as Arc<RwLock<dyn Repository + Send + Sync>>)
             }
             #[cfg(feature = "aliyun")]
@@ -83,6 +327,53 @@ impl RepositoryConfig {
                 let client = aliyun_kms::AliyunKmsBackend::new(config)?;
                 Ok(Arc::new(RwLock::new(client)) as Arc<RwLock<dyn Repository + Send + Sync>>)
             }
+            #[cfg(feature = "s3")]
+            Self::S3(desc) => {
+                let client = s3::S3Backend::new(desc).await?;
+                Ok(Arc::new(RwLock::new(client)) as Arc<RwLock<dyn Repository + Send + Sync>>)
+            }
+            #[cfg(feature = "vault")]
+            Self::VaultKv(desc) => {
+                let client = vault_kv::VaultKvBackend::new(desc).await?;
+                Ok(Arc::new(RwLock::new(client)) as Arc<RwLock<dyn Repository + Send + Sync>>)
+            }
+            #[cfg(feature = "azure")]
+            Self::AzureKeyVault(desc) => {
+                let client = azure_key_vault::AzureKeyVaultBackend::new(desc)?;
+                Ok(Arc::new(RwLock::new(client)) as Arc<RwLock<dyn Repository + Send + Sync>>)
+            }
+            #[cfg(feature = "aws")]
+            Self::AwsSecretsManager(desc) => {
+                let client = aws_secrets_manager::AwsSecretsManagerBackend::new(desc).await?;
+                Ok(Arc::new(RwLock::new(client)) as Arc<RwLock<dyn Repository + Send + Sync>>)
+            }
+            #[cfg(feature = "gcp")]
+            Self::GcpSecretManager(desc) => {
+                let client = gcp_secret_manager::GcpSecretManagerBackend::new(desc)?;
+                Ok(Arc::new(RwLock::new(client)) as Arc<RwLock<dyn Repository + Send + Sync>>)
+            }
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(desc) => {
+                let client = pkcs11::Pkcs11Backend::new(desc)?;
+                Ok(Arc::new(RwLock::new(client)) as Arc<RwLock<dyn Repository + Send + Sync>>)
+            }
+            #[cfg(feature = "postgres")]
+            Self::Postgres(desc) => {
+                let client = postgres::PostgresBackend::new(desc).await?;
+                Ok(Arc::new(RwLock::new(client)) as Arc<RwLock<dyn Repository + Send + Sync>>)
+            }
+            Self::Routed(desc) => {
+                let client = router::RoutedRepository::new(desc).await?;
+                Ok(Arc::new(RwLock::new(client)) as Arc<RwLock<dyn Repository + Send + Sync>>)
+            }
+            Self::Cached(desc) => {
+                let client = cache::CachedRepository::new(desc).await?;
+                Ok(Arc::new(RwLock::new(client)) as Arc<RwLock<dyn Repository + Send + Sync>>)
+            }
+            Self::Verified(desc) => {
+                let client = integrity::VerifiedRepository::new(desc).await?;
+                Ok(Arc::new(RwLock::new(client)) as Arc<RwLock<dyn Repository + Send + Sync>>)
+            }
         }
     }
 }
@@ -93,14 +384,68 @@ impl Default for RepositoryConfig {
     }
 }
 
+/// Write a secret resource, with its detached signature if given, and set its
+/// expiry. Both are done under the same lock, so that the resource is never
+/// served with a stale expiry.
 pub(crate) async fn set_secret_resource(
     repository: &Arc<RwLock<dyn Repository + Send + Sync>>,
     resource_desc: ResourceDesc,
     data: &[u8],
+    signature: Option<&[u8]>,
+    expires_at: Option<OffsetDateTime>,
 ) -> Result<()> {
-    repository
-        .write()
-        .await
-        .write_secret_resource(resource_desc, data)
-        .await
+    let mut repository = repository.write().await;
+    if expires_at.is_some() && !repository.supports_expiry(&resource_desc).await {
+        bail!("the repository does not support resource expiry");
+    }
+
+    let attributes = vec![KeyValue::new(
+        "kbs.repository",
+        resource_desc.repository_name.clone(),
+    )];
+    traced("repository.write_secret_resource", attributes, async {
+        match signature {
+            Some(signature) => {
+                repository
+                    .write_signed_secret_resource(resource_desc.clone(), data, signature)
+                    .await?
+            }
+            None => {
+                repository
+                    .write_secret_resource(resource_desc.clone(), data)
+                    .await?
+            }
+        }
+        repository
+            .set_secret_resource_expiry(resource_desc, expires_at)
+            .await
+    })
+    .await
+}
+
+/// Periodically delete the expired secret resources.
+pub(crate) async fn purge_expired_secret_resources(
+    repository: Arc<RwLock<dyn Repository + Send + Sync>>,
+) {
+    loop {
+        tokio::time::sleep(EXPIRED_RESOURCES_PURGE_INTERVAL).await;
+        let purged = repository
+            .write()
+            .await
+            .purge_expired_secret_resources()
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to purge expired resources: {e:?}");
+                Vec::new()
+            });
+
+        for resource_desc in purged {
+            info!(
+                "Purged expired resource kbs:///{}/{}/{}",
+                resource_desc.repository_name,
+                resource_desc.resource_type,
+                resource_desc.resource_tag
+            );
+        }
+    }
 }
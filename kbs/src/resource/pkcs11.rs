@@ -0,0 +1,332 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{Repository, ResourceDesc};
+use anyhow::{anyhow, bail, Context, Result};
+use cryptoki::{
+    context::{CInitializeArgs, Pkcs11},
+    mechanism::Mechanism,
+    object::{Attribute, AttributeType, ObjectClass, ObjectHandle},
+    session::{Session, UserType},
+    types::AuthPin,
+};
+use log::info;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+
+/// `CKA_APPLICATION` of the data objects created by KBS.
+const KBS_APPLICATION: &[u8] = b"kbs";
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Pkcs11RepoDesc {
+    /// Path to the PKCS#11 module, e.g. `/usr/lib/softhsm/libsofthsm2.so`.
+    pub module: String,
+
+    /// Label of the token to use. The first slot with a token is used if
+    /// not given.
+    pub token_label: Option<String>,
+
+    /// User PIN of the token.
+    pub pin: String,
+
+    /// Label of an AES key on the token. If given, sensitive secret keys are
+    /// released wrapped by this key (`CKM_AES_KEY_WRAP_PAD`) instead of being
+    /// refused.
+    pub wrapping_key_label: Option<String>,
+
+    /// Release a PKCS#11 URI referring to the object instead of its value,
+    /// for workloads that have their own access to the token.
+    #[serde(default)]
+    pub release_handles: bool,
+}
+
+/// A PKCS#11 session shared by the async tasks. The PKCS#11 calls block, so
+/// they are run on the blocking threads of the runtime, and only one thread
+/// can use the session at a time.
+#[derive(Clone)]
+pub(super) struct Pkcs11Session(Arc<Mutex<Session>>);
+
+impl Pkcs11Session {
+    pub(super) fn new(session: Session) -> Self {
+        Self(Arc::new(Mutex::new(session)))
+    }
+
+    /// Run `f` with the session on a blocking thread.
+    pub(super) async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Session) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let session = self.0.clone();
+        tokio::task::spawn_blocking(move || {
+            let session = session
+                .lock()
+                .map_err(|_| anyhow!("PKCS#11 session lock poisoned"))?;
+            f(&session)
+        })
+        .await
+        .context("PKCS#11 task")?
+    }
+}
+
+pub struct Pkcs11Backend {
+    session: Pkcs11Session,
+    token_label: String,
+    wrapping_key_label: Option<String>,
+    release_handles: bool,
+}
+
+#[async_trait::async_trait]
+impl Repository for Pkcs11Backend {
+    async fn read_secret_resource(&self, resource_desc: ResourceDesc) -> Result<Vec<u8>> {
+        let label = object_label(&resource_desc);
+        let token_label = self.token_label.clone();
+        let wrapping_key_label = self.wrapping_key_label.clone();
+        let release_handles = self.release_handles;
+        self.session
+            .run(move |session| {
+                read_object(
+                    session,
+                    &label,
+                    &token_label,
+                    wrapping_key_label.as_deref(),
+                    release_handles,
+                )
+            })
+            .await
+    }
+
+    async fn write_secret_resource(
+        &mut self,
+        resource_desc: ResourceDesc,
+        data: &[u8],
+    ) -> Result<()> {
+        let label = object_label(&resource_desc);
+        let data = data.to_vec();
+        self.session
+            .run(move |session| {
+                if let Some(object) = find_object(session, &label)? {
+                    info!("Replace PKCS#11 object `{label}`");
+                    session
+                        .destroy_object(object)
+                        .with_context(|| format!("destroy PKCS#11 object `{label}`"))?;
+                }
+
+                session
+                    .create_object(&[
+                        Attribute::Class(ObjectClass::DATA),
+                        Attribute::Token(true),
+                        Attribute::Private(true),
+                        Attribute::Application(KBS_APPLICATION.to_vec()),
+                        Attribute::Label(label.as_bytes().to_vec()),
+                        Attribute::Value(data),
+                    ])
+                    .with_context(|| format!("create PKCS#11 object `{label}`"))?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Only the data objects created by KBS can be deleted, keys provisioned
+    /// on the token are left to the token administrator.
+    async fn delete_secret_resource(&mut self, resource_desc: ResourceDesc) -> Result<()> {
+        let label = object_label(&resource_desc);
+        self.session
+            .run(move |session| {
+                let object = session
+                    .find_objects(&[
+                        Attribute::Class(ObjectClass::DATA),
+                        Attribute::Application(KBS_APPLICATION.to_vec()),
+                        Attribute::Label(label.as_bytes().to_vec()),
+                    ])
+                    .with_context(|| format!("find PKCS#11 object `{label}`"))?
+                    .first()
+                    .copied()
+                    .ok_or_else(|| {
+                        anyhow!("no PKCS#11 data object labeled `{label}` created by KBS")
+                    })?;
+
+                session
+                    .destroy_object(object)
+                    .with_context(|| format!("destroy PKCS#11 object `{label}`"))
+            })
+            .await
+    }
+}
+
+impl Pkcs11Backend {
+    pub fn new(repo_desc: &Pkcs11RepoDesc) -> Result<Self> {
+        let (session, token_label) = open_session(
+            &repo_desc.module,
+            repo_desc.token_label.as_deref(),
+            &repo_desc.pin,
+        )?;
+
+        Ok(Self {
+            session: Pkcs11Session::new(session),
+            token_label,
+            wrapping_key_label: repo_desc.wrapping_key_label.clone(),
+            release_handles: repo_desc.release_handles,
+        })
+    }
+}
+
+/// Load `module` and log in to the token labeled `token_label`, or to the
+/// first token found. Returns the session and the label of the token.
+pub(super) fn open_session(
+    module: &str,
+    token_label: Option<&str>,
+    pin: &str,
+) -> Result<(Session, String)> {
+    let pkcs11 = Pkcs11::new(module).context("load PKCS#11 module")?;
+    pkcs11
+        .initialize(CInitializeArgs::OsThreads)
+        .context("initialize PKCS#11 module")?;
+
+    let mut token = None;
+    for slot in pkcs11.get_slots_with_token()? {
+        let label = pkcs11.get_token_info(slot)?.label().to_string();
+        match token_label {
+            Some(wanted) if wanted != label => continue,
+            _ => {
+                token = Some((slot, label));
+                break;
+            }
+        }
+    }
+
+    let Some((slot, token_label)) = token else {
+        bail!("no matching PKCS#11 token found");
+    };
+
+    let session = pkcs11
+        .open_rw_session(slot)
+        .context("open PKCS#11 session")?;
+    session
+        .login(UserType::User, Some(&AuthPin::new(pin.to_string())))
+        .context("log in to PKCS#11 token")?;
+    info!("Logged in to PKCS#11 token `{token_label}`");
+
+    Ok((session, token_label))
+}
+
+/// Read the value of the object labeled `label`, or wrap it by the wrapping
+/// key if it is a sensitive key, or return its URI if `release_handles`.
+fn read_object(
+    session: &Session,
+    label: &str,
+    token_label: &str,
+    wrapping_key_label: Option<&str>,
+    release_handles: bool,
+) -> Result<Vec<u8>> {
+    let object = find_object(session, label)?
+        .ok_or_else(|| anyhow!("no PKCS#11 object labeled `{label}`"))?;
+
+    if release_handles {
+        let uri = pkcs11_uri(token_label, label);
+        return Ok(serde_json::to_vec(&json!({ "uri": uri }))?);
+    }
+
+    let mut class = None;
+    let mut sensitive = false;
+    let mut extractable = false;
+    for attribute in session
+        .get_attributes(
+            object,
+            &[
+                AttributeType::Class,
+                AttributeType::Sensitive,
+                AttributeType::Extractable,
+            ],
+        )
+        .context("get PKCS#11 object attributes")?
+    {
+        match attribute {
+            Attribute::Class(c) => class = Some(c),
+            Attribute::Sensitive(s) => sensitive = s,
+            Attribute::Extractable(e) => extractable = e,
+            _ => {}
+        }
+    }
+
+    match class {
+        Some(ObjectClass::DATA) => object_value(session, object),
+        Some(ObjectClass::SECRET_KEY) if !sensitive => object_value(session, object),
+        Some(ObjectClass::SECRET_KEY) => {
+            let Some(wrapping_key_label) = wrapping_key_label else {
+                bail!("`{label}` is a sensitive key and no wrapping key is configured");
+            };
+
+            if !extractable {
+                bail!("`{label}` is a sensitive key that cannot be extracted");
+            }
+
+            let wrapping_key = find_object(session, wrapping_key_label)?
+                .ok_or_else(|| anyhow!("no PKCS#11 wrapping key `{wrapping_key_label}`"))?;
+            session
+                .wrap_key(&Mechanism::AesKeyWrapPad, wrapping_key, object)
+                .with_context(|| format!("wrap PKCS#11 key `{label}`"))
+        }
+        _ => bail!("PKCS#11 object `{label}` is neither a data object nor a secret key"),
+    }
+}
+
+pub(super) fn find_object(session: &Session, label: &str) -> Result<Option<ObjectHandle>> {
+    let objects = session
+        .find_objects(&[Attribute::Label(label.as_bytes().to_vec())])
+        .with_context(|| format!("find PKCS#11 object `{label}`"))?;
+    Ok(objects.first().copied())
+}
+
+fn object_value(session: &Session, object: ObjectHandle) -> Result<Vec<u8>> {
+    match session
+        .get_attributes(object, &[AttributeType::Value])
+        .context("get PKCS#11 object value")?
+        .pop()
+    {
+        Some(Attribute::Value(value)) => Ok(value),
+        _ => bail!("PKCS#11 object has no readable value"),
+    }
+}
+
+fn object_label(resource_desc: &ResourceDesc) -> String {
+    format!(
+        "{}/{}/{}",
+        resource_desc.repository_name, resource_desc.resource_type, resource_desc.resource_tag
+    )
+}
+
+/// Build a [RFC 7512](https://www.rfc-editor.org/rfc/rfc7512) URI for an object.
+fn pkcs11_uri(token_label: &str, object_label: &str) -> String {
+    format!(
+        "pkcs11:token={};object={}",
+        percent_encode(token_label),
+        percent_encode(object_label)
+    )
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("kbs", "default/key/1", "pkcs11:token=kbs;object=default%2Fkey%2F1")]
+    #[case("my token", "a/b/c", "pkcs11:token=my%20token;object=a%2Fb%2Fc")]
+    fn pkcs11_uri(#[case] token: &str, #[case] object: &str, #[case] expected: &str) {
+        assert_eq!(super::pkcs11_uri(token, object), expected);
+    }
+}
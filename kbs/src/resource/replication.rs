@@ -0,0 +1,468 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Replication of the resources of a source KBS.
+//!
+//! A replica periodically exports the resources of the source KBS through its
+//! admin API, and writes the changed ones to its own repository. The checksum
+//! of every synced resource is kept in a state file, so that a resource
+//! changed on the replica since the last sync is detected as a conflict
+//! instead of being silently overwritten.
+
+use super::bundle::{parse_path, path, Manifest, ManifestEntry};
+use super::{set_secret_resource, Repository, ResourceDesc};
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use jwt_simple::prelude::{Claims, Duration as JwtDuration, Ed25519KeyPair, EdDSAKeyPairLike};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::sync::RwLock;
+
+const DEFAULT_REPLICATION_INTERVAL: u64 = 300;
+const DEFAULT_REPLICATION_STATE_PATH: &str = "/opt/confidential-containers/kbs/replication.json";
+
+const EXPORT_PATH: &str = "kbs/v0/admin/resources/export";
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReplicationConfig {
+    /// Root URL of the source KBS, e.g. `https://kbs.example.com:8080`.
+    pub source_url: String,
+
+    /// Path to the Ed25519 private key authenticating to the admin API of the
+    /// source KBS.
+    pub auth_private_key: String,
+
+    /// Path to a PEM certificate to trust for the HTTPS server of the source
+    /// KBS, besides the system roots.
+    pub ca_cert_path: Option<String>,
+
+    /// Repositories to replicate, all repositories if empty.
+    #[serde(default)]
+    pub repositories: Vec<String>,
+
+    /// Seconds between two syncs. Defaults to 5 minutes.
+    #[serde(default = "default_replication_interval")]
+    pub interval: u64,
+
+    /// What to do with a resource changed on the replica since the last
+    /// sync.
+    #[serde(default)]
+    pub conflict_policy: ConflictPolicy,
+
+    /// File keeping the checksums of the synced resources.
+    #[serde(default = "default_replication_state_path")]
+    pub state_path: PathBuf,
+
+    /// Allow a plain HTTP source URL. Resources are then replicated in the
+    /// clear, so this is only meant for testing.
+    #[serde(default)]
+    pub insecure_http: bool,
+}
+
+fn default_replication_interval() -> u64 {
+    DEFAULT_REPLICATION_INTERVAL
+}
+
+fn default_replication_state_path() -> PathBuf {
+    PathBuf::from(DEFAULT_REPLICATION_STATE_PATH)
+}
+
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+pub enum ConflictPolicy {
+    /// Keep the resource of the replica, and log the conflict.
+    #[default]
+    Skip,
+
+    /// Overwrite the resource of the replica with the one of the source.
+    Source,
+}
+
+/// Checksums of the resources as of the last sync, by resource path.
+#[derive(Default, Serialize, Deserialize)]
+struct SyncState {
+    resources: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SyncReport {
+    pub written: usize,
+    pub deleted: usize,
+    pub conflicts: usize,
+}
+
+#[derive(Serialize)]
+struct ExportRequest<'a> {
+    repositories: &'a [String],
+}
+
+pub struct Replication {
+    client: reqwest::Client,
+    export_url: String,
+    auth_key: Ed25519KeyPair,
+    repositories: Vec<String>,
+    interval: Duration,
+    conflict_policy: ConflictPolicy,
+    state_path: PathBuf,
+}
+
+impl Replication {
+    pub async fn new(config: &ReplicationConfig) -> Result<Self> {
+        if config.interval == 0 {
+            bail!("replication interval must not be 0");
+        }
+
+        if !config.insecure_http && !config.source_url.starts_with("https://") {
+            bail!("replication source URL must be HTTPS unless `insecure_http` is set");
+        }
+
+        let auth_key = tokio::fs::read_to_string(&config.auth_private_key)
+            .await
+            .context("read replication auth private key")?;
+        let auth_key = Ed25519KeyPair::from_pem(&auth_key)
+            .map_err(|e| anyhow::anyhow!("illegal replication auth private key: {e}"))?;
+
+        let mut builder = reqwest::Client::builder();
+        if let Some(ca_cert_path) = &config.ca_cert_path {
+            let pem = tokio::fs::read(ca_cert_path)
+                .await
+                .context("read replication source CA certificate")?;
+            let cert = reqwest::Certificate::from_pem(&pem)
+                .context("parse replication source CA certificate")?;
+            builder = builder.add_root_certificate(cert);
+        }
+
+        Ok(Self {
+            client: builder.build().context("build replication HTTP client")?,
+            export_url: format!("{}/{EXPORT_PATH}", config.source_url.trim_end_matches('/')),
+            auth_key,
+            repositories: config.repositories.clone(),
+            interval: Duration::from_secs(config.interval),
+            conflict_policy: config.conflict_policy,
+            state_path: config.state_path.clone(),
+        })
+    }
+
+    /// Sync the repository with the source KBS once.
+    pub async fn sync(
+        &self,
+        repository: &Arc<RwLock<dyn Repository + Send + Sync>>,
+    ) -> Result<SyncReport> {
+        let manifest = self.fetch().await?;
+        self.apply(repository, manifest).await
+    }
+
+    async fn fetch(&self) -> Result<Manifest> {
+        let token = self
+            .auth_key
+            .sign(Claims::create(JwtDuration::from_mins(5)))
+            .map_err(|e| anyhow::anyhow!("sign replication token: {e}"))?;
+        let response = self
+            .client
+            .post(&self.export_url)
+            .bearer_auth(token)
+            .json(&ExportRequest {
+                repositories: &self.repositories,
+            })
+            .send()
+            .await
+            .context("export resources from the replication source")?;
+        if response.status() != reqwest::StatusCode::OK {
+            bail!(
+                "export resources from the replication source failed: {} {}",
+                response.status(),
+                response.text().await.unwrap_or_default()
+            );
+        }
+
+        response
+            .json()
+            .await
+            .context("illegal manifest from the replication source")
+    }
+
+    async fn apply(
+        &self,
+        repository: &Arc<RwLock<dyn Repository + Send + Sync>>,
+        manifest: Manifest,
+    ) -> Result<SyncReport> {
+        let mut state = self.load_state().await?;
+        state
+            .resources
+            .retain(|path, _| self.is_replicated(path.split('/').next().unwrap_or_default()));
+        let replica: HashMap<String, (String, Option<OffsetDateTime>)> = repository
+            .read()
+            .await
+            .list_secret_resources()
+            .await
+            .context("replication requires a repository that supports listing")?
+            .into_iter()
+            .filter(|r| self.is_replicated(&r.repository_name))
+            .map(|r| {
+                let resource_desc = ResourceDesc {
+                    repository_name: r.repository_name,
+                    resource_type: r.resource_type,
+                    resource_tag: r.resource_tag,
+                };
+                (path(&resource_desc), (r.checksum, r.expires_at))
+            })
+            .collect();
+
+        let mut report = SyncReport::default();
+        let mut source_paths = Vec::new();
+        for entry in manifest.resources {
+            source_paths.push(entry.path.clone());
+            let data = STANDARD
+                .decode(&entry.data)
+                .with_context(|| format!("illegal data of {}", entry.path))?;
+            let checksum = hex::encode(Sha256::digest(&data));
+
+            let conflict = match replica.get(&entry.path) {
+                Some((local_checksum, expires_at)) if *local_checksum == checksum => {
+                    if *expires_at != entry.expires_at {
+                        self.write(repository, &entry, &data).await?;
+                        report.written += 1;
+                    }
+                    state.resources.insert(entry.path, checksum);
+                    continue;
+                }
+                Some((local_checksum, _)) => {
+                    state.resources.get(&entry.path) != Some(local_checksum)
+                }
+                None => state.resources.contains_key(&entry.path),
+            };
+
+            if conflict && self.conflict_policy == ConflictPolicy::Skip {
+                warn!(
+                    "Replication conflict on {}: changed on the replica since the last sync, keep the replica resource",
+                    entry.path
+                );
+                report.conflicts += 1;
+                continue;
+            }
+
+            self.write(repository, &entry, &data).await?;
+            report.written += 1;
+            state.resources.insert(entry.path, checksum);
+        }
+
+        // Resources synced before and since deleted from the source.
+        let deleted: Vec<String> = state
+            .resources
+            .keys()
+            .filter(|path| !source_paths.contains(path))
+            .cloned()
+            .collect();
+        for path in deleted {
+            let synced = state.resources.remove(&path);
+            let Some((local_checksum, _)) = replica.get(&path) else {
+                continue;
+            };
+
+            if synced.as_ref() != Some(local_checksum)
+                && self.conflict_policy == ConflictPolicy::Skip
+            {
+                warn!(
+                    "Replication conflict on {path}: deleted from the source but changed on the replica, keep the replica resource"
+                );
+                report.conflicts += 1;
+                continue;
+            }
+
+            repository
+                .write()
+                .await
+                .delete_secret_resource(parse_path(&path)?)
+                .await
+                .with_context(|| format!("delete replicated resource {path}"))?;
+            report.deleted += 1;
+        }
+
+        self.save_state(&state).await?;
+        Ok(report)
+    }
+
+    async fn write(
+        &self,
+        repository: &Arc<RwLock<dyn Repository + Send + Sync>>,
+        entry: &ManifestEntry,
+        data: &[u8],
+    ) -> Result<()> {
+        let signature = entry
+            .signature
+            .as_ref()
+            .map(|signature| STANDARD.decode(signature))
+            .transpose()
+            .with_context(|| format!("illegal signature of {}", entry.path))?;
+        set_secret_resource(
+            repository,
+            parse_path(&entry.path)?,
+            data,
+            signature.as_deref(),
+            entry.expires_at,
+        )
+        .await
+        .with_context(|| format!("write replicated resource {}", entry.path))
+    }
+
+    fn is_replicated(&self, repository_name: &str) -> bool {
+        self.repositories.is_empty() || self.repositories.iter().any(|r| r == repository_name)
+    }
+
+    async fn load_state(&self) -> Result<SyncState> {
+        if !self.state_path.exists() {
+            return Ok(SyncState::default());
+        }
+
+        let state = tokio::fs::read(&self.state_path)
+            .await
+            .context("read replication state")?;
+        serde_json::from_slice(&state).context("illegal replication state")
+    }
+
+    async fn save_state(&self, state: &SyncState) -> Result<()> {
+        if let Some(parent) = self.state_path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("create replication state directory")?;
+        }
+
+        // Write to a temporary file first, so that a crash never leaves a
+        // truncated state behind.
+        let mut tmp_path = self.state_path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        tokio::fs::write(&tmp_path, serde_json::to_vec(state)?)
+            .await
+            .context("write replication state")?;
+        tokio::fs::rename(&tmp_path, &self.state_path)
+            .await
+            .context("write replication state")
+    }
+
+    /// Sync with the source KBS now, then every `interval`.
+    pub async fn run(self, repository: Arc<RwLock<dyn Repository + Send + Sync>>) {
+        loop {
+            match self.sync(&repository).await {
+                Ok(report) => info!(
+                    "Replicated resources: {} written, {} deleted, {} conflicts",
+                    report.written, report.deleted, report.conflicts
+                ),
+                Err(e) => warn!("Failed to replicate the resources: {e:?}"),
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use jwt_simple::prelude::Ed25519KeyPair;
+    use tokio::sync::RwLock;
+
+    use super::{ConflictPolicy, Replication, ReplicationConfig, SyncReport};
+    use crate::resource::{
+        bundle::{parse_path, Manifest, ManifestEntry},
+        local_fs::{LocalFs, LocalFsRepoDesc},
+        Repository,
+    };
+
+    fn manifest(resources: &[(&str, &str)]) -> Manifest {
+        Manifest {
+            resources: resources
+                .iter()
+                .map(|(path, data)| ManifestEntry {
+                    path: path.to_string(),
+                    data: STANDARD.encode(data),
+                    expires_at: None,
+                    signature: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn sync_with_conflicts() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let key_path = tmp_dir.path().join("auth.key");
+        std::fs::write(&key_path, Ed25519KeyPair::generate().to_pem()).unwrap();
+
+        let replication = Replication::new(&ReplicationConfig {
+            source_url: "http://127.0.0.1:8080".into(),
+            auth_private_key: key_path.to_string_lossy().to_string(),
+            ca_cert_path: None,
+            repositories: Vec::new(),
+            interval: 300,
+            conflict_policy: ConflictPolicy::Skip,
+            state_path: tmp_dir.path().join("replication.json"),
+            insecure_http: true,
+        })
+        .await
+        .unwrap();
+
+        let repo_dir = tmp_dir.path().join("repository");
+        std::fs::create_dir_all(&repo_dir).unwrap();
+        let repo_desc = LocalFsRepoDesc {
+            dir_path: Some(repo_dir.to_string_lossy().to_string()),
+            encryption: None,
+        };
+        let repository: Arc<RwLock<dyn Repository + Send + Sync>> =
+            Arc::new(RwLock::new(LocalFs::new(&repo_desc).await.unwrap()));
+
+        let report = replication
+            .apply(
+                &repository,
+                manifest(&[("default/key/1", "one"), ("default/key/2", "two")]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            SyncReport {
+                written: 2,
+                deleted: 0,
+                conflicts: 0
+            }
+        );
+
+        // Changed on the replica since the last sync: a conflict, the replica
+        // resource is kept.
+        repository
+            .write()
+            .await
+            .write_secret_resource(parse_path("default/key/2").unwrap(), b"local")
+            .await
+            .unwrap();
+        let report = replication
+            .apply(
+                &repository,
+                manifest(&[("default/key/1", "new"), ("default/key/2", "two")]),
+            )
+            .await
+            .unwrap();
+        assert_eq!((report.written, report.conflicts), (1, 1));
+
+        // Deleted from the source.
+        let report = replication
+            .apply(&repository, manifest(&[("default/key/2", "two")]))
+            .await
+            .unwrap();
+        assert_eq!(report.deleted, 1);
+
+        let data = repository
+            .read()
+            .await
+            .read_secret_resource(parse_path("default/key/2").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(data, b"local");
+    }
+}
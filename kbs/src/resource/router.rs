@@ -0,0 +1,345 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Routing of the resources to several repositories by repository name.
+
+use super::{CacheStats, Repository, RepositoryConfig, ResourceDesc, ResourceMetadata};
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use time::OffsetDateTime;
+use tokio::sync::RwLock;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RoutedRepoDesc {
+    /// Routes in order of precedence, the first matching route is used.
+    pub routes: Vec<RouteConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RouteConfig {
+    /// Repository names routed to the backend: an exact name, a prefix
+    /// followed by `*` like `team-*`, or `*` for all the repositories.
+    pub repository: String,
+
+    pub backend: RepositoryConfig,
+}
+
+struct Route {
+    pattern: String,
+    backend: Arc<RwLock<dyn Repository + Send + Sync>>,
+}
+
+pub struct RoutedRepository {
+    routes: Vec<Route>,
+}
+
+impl RoutedRepository {
+    /// The routed backends are initialized through [`RepositoryConfig::initialize`],
+    /// so the future is boxed to break the recursion between both.
+    pub fn new(
+        repo_desc: &RoutedRepoDesc,
+    ) -> Pin<Box<dyn Future<Output = Result<Self>> + Send + '_>> {
+        Box::pin(async move {
+            if repo_desc.routes.is_empty() {
+                bail!("no route configured");
+            }
+
+            let mut routes = Vec::new();
+            for route in &repo_desc.routes {
+                if let RepositoryConfig::Routed(_) = route.backend {
+                    bail!("routes cannot be nested");
+                }
+
+                routes.push(Route {
+                    pattern: route.repository.clone(),
+                    backend: route.backend.initialize().await?,
+                });
+            }
+
+            Ok(Self { routes })
+        })
+    }
+
+    fn route_index(&self, repository_name: &str) -> Option<usize> {
+        self.routes
+            .iter()
+            .position(|route| matches(&route.pattern, repository_name))
+    }
+
+    fn backend(
+        &self,
+        resource_desc: &ResourceDesc,
+    ) -> Result<&Arc<RwLock<dyn Repository + Send + Sync>>> {
+        self.route_index(&resource_desc.repository_name)
+            .map(|index| &self.routes[index].backend)
+            .ok_or_else(|| {
+                anyhow!(
+                    "no route for repository `{}`",
+                    resource_desc.repository_name
+                )
+            })
+    }
+}
+
+#[async_trait::async_trait]
+impl Repository for RoutedRepository {
+    async fn read_secret_resource(&self, resource_desc: ResourceDesc) -> Result<Vec<u8>> {
+        self.backend(&resource_desc)?
+            .read()
+            .await
+            .read_secret_resource(resource_desc)
+            .await
+    }
+
+    async fn write_secret_resource(
+        &mut self,
+        resource_desc: ResourceDesc,
+        data: &[u8],
+    ) -> Result<()> {
+        self.backend(&resource_desc)?
+            .write()
+            .await
+            .write_secret_resource(resource_desc, data)
+            .await
+    }
+
+    async fn write_signed_secret_resource(
+        &mut self,
+        resource_desc: ResourceDesc,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        self.backend(&resource_desc)?
+            .write()
+            .await
+            .write_signed_secret_resource(resource_desc, data, signature)
+            .await
+    }
+
+    async fn read_signed_secret_resource(
+        &self,
+        resource_desc: ResourceDesc,
+    ) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+        self.backend(&resource_desc)?
+            .read()
+            .await
+            .read_signed_secret_resource(resource_desc)
+            .await
+    }
+
+    /// Only the resources of the repositories routed to a backend are listed
+    /// from it, so that a backend shared by mistake is not listed twice.
+    async fn list_secret_resources(&self) -> Result<Vec<ResourceMetadata>> {
+        let mut resources = Vec::new();
+        for (index, route) in self.routes.iter().enumerate() {
+            let listed = route.backend.read().await.list_secret_resources().await?;
+            resources.extend(
+                listed
+                    .into_iter()
+                    .filter(|r| self.route_index(&r.repository_name) == Some(index)),
+            );
+        }
+
+        Ok(resources)
+    }
+
+    async fn secret_resource_metadata(
+        &self,
+        resource_desc: ResourceDesc,
+    ) -> Result<ResourceMetadata> {
+        self.backend(&resource_desc)?
+            .read()
+            .await
+            .secret_resource_metadata(resource_desc)
+            .await
+    }
+
+    async fn read_secret_resource_with_metadata(
+        &self,
+        resource_desc: ResourceDesc,
+    ) -> Result<(Vec<u8>, ResourceMetadata)> {
+        self.backend(&resource_desc)?
+            .read()
+            .await
+            .read_secret_resource_with_metadata(resource_desc)
+            .await
+    }
+
+    async fn delete_secret_resource(&mut self, resource_desc: ResourceDesc) -> Result<()> {
+        self.backend(&resource_desc)?
+            .write()
+            .await
+            .delete_secret_resource(resource_desc)
+            .await
+    }
+
+    async fn supports_expiry(&self, resource_desc: &ResourceDesc) -> bool {
+        match self.backend(resource_desc) {
+            Ok(backend) => backend.read().await.supports_expiry(resource_desc).await,
+            Err(_) => false,
+        }
+    }
+
+    async fn set_secret_resource_expiry(
+        &mut self,
+        resource_desc: ResourceDesc,
+        expires_at: Option<OffsetDateTime>,
+    ) -> Result<()> {
+        self.backend(&resource_desc)?
+            .write()
+            .await
+            .set_secret_resource_expiry(resource_desc, expires_at)
+            .await
+    }
+
+    async fn purge_expired_secret_resources(&mut self) -> Result<Vec<ResourceDesc>> {
+        let mut purged = Vec::new();
+        for route in &self.routes {
+            purged.extend(
+                route
+                    .backend
+                    .write()
+                    .await
+                    .purge_expired_secret_resources()
+                    .await?,
+            );
+        }
+
+        Ok(purged)
+    }
+
+    async fn read_secret_resource_version(
+        &self,
+        resource_desc: ResourceDesc,
+        version: u64,
+    ) -> Result<Vec<u8>> {
+        self.backend(&resource_desc)?
+            .read()
+            .await
+            .read_secret_resource_version(resource_desc, version)
+            .await
+    }
+
+    async fn list_secret_resource_versions(&self, resource_desc: ResourceDesc) -> Result<Vec<u64>> {
+        self.backend(&resource_desc)?
+            .read()
+            .await
+            .list_secret_resource_versions(resource_desc)
+            .await
+    }
+
+    async fn rollback_secret_resource(
+        &mut self,
+        resource_desc: ResourceDesc,
+        version: u64,
+    ) -> Result<()> {
+        self.backend(&resource_desc)?
+            .write()
+            .await
+            .rollback_secret_resource(resource_desc, version)
+            .await
+    }
+
+    async fn cache_stats(&self) -> Option<CacheStats> {
+        let mut stats = Vec::new();
+        for route in &self.routes {
+            stats.extend(route.backend.read().await.cache_stats().await);
+        }
+
+        CacheStats::sum(&stats)
+    }
+
+    async fn purge_cache(&self) -> Result<usize> {
+        let mut purged = 0;
+        for route in &self.routes {
+            purged += route.backend.read().await.purge_cache().await?;
+        }
+
+        Ok(purged)
+    }
+}
+
+fn matches(pattern: &str, repository_name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => repository_name.starts_with(prefix),
+        None => pattern == repository_name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::{RouteConfig, RoutedRepoDesc, RoutedRepository};
+    use crate::resource::{local_fs::LocalFsRepoDesc, Repository, RepositoryConfig, ResourceDesc};
+
+    #[rstest]
+    #[case("prod", "prod", true)]
+    #[case("prod", "production", false)]
+    #[case("team-*", "team-a", true)]
+    #[case("team-*", "prod", false)]
+    #[case("*", "default", true)]
+    fn matches(#[case] pattern: &str, #[case] repository_name: &str, #[case] expected: bool) {
+        assert_eq!(super::matches(pattern, repository_name), expected);
+    }
+
+    #[tokio::test]
+    async fn route_by_repository() {
+        let prod_dir = tempfile::tempdir().unwrap();
+        let other_dir = tempfile::tempdir().unwrap();
+        let local_fs = |dir: &tempfile::TempDir| {
+            RepositoryConfig::LocalFs(LocalFsRepoDesc {
+                dir_path: Some(dir.path().to_string_lossy().to_string()),
+                encryption: None,
+            })
+        };
+        let mut repository = RoutedRepository::new(&RoutedRepoDesc {
+            routes: vec![
+                RouteConfig {
+                    repository: "prod".into(),
+                    backend: local_fs(&prod_dir),
+                },
+                RouteConfig {
+                    repository: "*".into(),
+                    backend: local_fs(&other_dir),
+                },
+            ],
+        })
+        .await
+        .unwrap();
+
+        for repository_name in ["prod", "dev"] {
+            let resource_desc = ResourceDesc {
+                repository_name: repository_name.into(),
+                resource_type: "key".into(),
+                resource_tag: "1".into(),
+            };
+            repository
+                .write_secret_resource(resource_desc.clone(), repository_name.as_bytes())
+                .await
+                .unwrap();
+            let data = repository
+                .read_secret_resource(resource_desc)
+                .await
+                .unwrap();
+            assert_eq!(data, repository_name.as_bytes());
+        }
+
+        assert!(prod_dir.path().join("prod/key/1").exists());
+        assert!(!prod_dir.path().join("dev/key/1").exists());
+        assert!(other_dir.path().join("dev/key/1").exists());
+
+        let listed: Vec<String> = repository
+            .list_secret_resources()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.repository_name)
+            .collect();
+        assert_eq!(listed, ["prod", "dev"]);
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{Repository, ResourceDesc};
+use anyhow::{Context, Result};
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_s3::{config::Credentials, primitives::ByteStream, Client};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct S3RepoDesc {
+    /// Name of the bucket where the resources are stored.
+    pub bucket: String,
+
+    /// Optional key prefix inside the bucket, e.g. `kbs/resources`.
+    pub prefix: Option<String>,
+
+    /// Region of the bucket. If not given, the region is resolved from the
+    /// environment (`AWS_REGION`, profile, IMDS...).
+    pub region: Option<String>,
+
+    /// Custom endpoint of an S3-compatible service, e.g. a MinIO server.
+    pub endpoint: Option<String>,
+
+    /// Static access key id. If not given, credentials are resolved from the
+    /// default AWS credential chain.
+    pub access_key_id: Option<String>,
+
+    /// Static secret access key, used together with `access_key_id`.
+    pub secret_access_key: Option<String>,
+
+    /// Use path-style addressing (`endpoint/bucket/key`). Most S3-compatible
+    /// services like MinIO require this.
+    #[serde(default)]
+    pub force_path_style: bool,
+}
+
+pub struct S3Backend {
+    client: Client,
+    bucket: String,
+    prefix: String,
+}
+
+#[async_trait::async_trait]
+impl Repository for S3Backend {
+    async fn read_secret_resource(&self, resource_desc: ResourceDesc) -> Result<Vec<u8>> {
+        self.get_object(&resource_path(&resource_desc)).await
+    }
+
+    async fn write_secret_resource(
+        &mut self,
+        resource_desc: ResourceDesc,
+        data: &[u8],
+    ) -> Result<()> {
+        self.put_object(&resource_path(&resource_desc), data.to_vec())
+            .await
+    }
+
+    async fn delete_secret_resource(&mut self, resource_desc: ResourceDesc) -> Result<()> {
+        self.delete_object(&resource_path(&resource_desc)).await
+    }
+}
+
+impl S3Backend {
+    pub async fn new(repo_desc: &S3RepoDesc) -> Result<Self> {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest());
+        if let Some(region) = &repo_desc.region {
+            loader = loader.region(Region::new(region.clone()));
+        }
+
+        if let Some(endpoint) = &repo_desc.endpoint {
+            loader = loader.endpoint_url(endpoint);
+        }
+
+        if let (Some(access_key_id), Some(secret_access_key)) =
+            (&repo_desc.access_key_id, &repo_desc.secret_access_key)
+        {
+            loader = loader.credentials_provider(Credentials::new(
+                access_key_id,
+                secret_access_key,
+                None,
+                None,
+                "kbs-config",
+            ));
+        }
+
+        let sdk_config = loader.load().await;
+        let s3_config = aws_sdk_s3::config::Builder::from(&sdk_config)
+            .force_path_style(repo_desc.force_path_style)
+            .build();
+
+        Ok(Self {
+            client: Client::from_conf(s3_config),
+            bucket: repo_desc.bucket.clone(),
+            prefix: repo_desc
+                .prefix
+                .clone()
+                .unwrap_or_default()
+                .trim_matches('/')
+                .to_string(),
+        })
+    }
+
+    /// Get the object at `name` under the prefix.
+    pub(super) async fn get_object(&self, name: &str) -> Result<Vec<u8>> {
+        let key = object_key(&self.prefix, name);
+        let object = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .with_context(|| format!("failed to get object `{key}` from S3"))?;
+
+        let data = object
+            .body
+            .collect()
+            .await
+            .context("failed to read object body from S3")?;
+        Ok(data.into_bytes().to_vec())
+    }
+
+    pub(super) async fn put_object(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        let key = object_key(&self.prefix, name);
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .body(ByteStream::from(data))
+            .send()
+            .await
+            .with_context(|| format!("failed to put object `{key}` to S3"))?;
+        Ok(())
+    }
+
+    pub(super) async fn delete_object(&self, name: &str) -> Result<()> {
+        let key = object_key(&self.prefix, name);
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .with_context(|| format!("failed to delete object `{key}` from S3"))?;
+        Ok(())
+    }
+
+    /// List the names of the objects directly under `dir` below the prefix.
+    pub(super) async fn list_objects(&self, dir: &str) -> Result<Vec<String>> {
+        let key_prefix = format!("{}/", object_key(&self.prefix, dir));
+        let mut names = Vec::new();
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&key_prefix)
+            .delimiter("/")
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            let page = page.context("failed to list objects from S3")?;
+            names.extend(
+                page.contents()
+                    .iter()
+                    .filter_map(|object| object.key()?.strip_prefix(&key_prefix))
+                    .map(str::to_string),
+            );
+        }
+
+        Ok(names)
+    }
+}
+
+fn resource_path(resource_desc: &ResourceDesc) -> String {
+    format!(
+        "{}/{}/{}",
+        resource_desc.repository_name, resource_desc.resource_type, resource_desc.resource_tag
+    )
+}
+
+fn object_key(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        return name.to_string();
+    }
+
+    format!("{prefix}/{name}")
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::resource::ResourceDesc;
+
+    #[rstest]
+    #[case("", "default/key/1")]
+    #[case("kbs", "kbs/default/key/1")]
+    #[case("kbs/resources", "kbs/resources/default/key/1")]
+    fn object_key(#[case] prefix: &str, #[case] expected: &str) {
+        let resource_desc = ResourceDesc {
+            repository_name: "default".into(),
+            resource_type: "key".into(),
+            resource_tag: "1".into(),
+        };
+
+        assert_eq!(
+            super::object_key(prefix, &super::resource_path(&resource_desc)),
+            expected
+        );
+    }
+}
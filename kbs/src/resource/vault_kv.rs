@@ -0,0 +1,544 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{Repository, ResourceDesc};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+const DEFAULT_MOUNT_PATH: &str = "secret";
+const DEFAULT_APPROLE_MOUNT_PATH: &str = "approle";
+const DEFAULT_FIELD: &str = "value";
+
+/// A token is renewed once less than this much of its lease is left.
+const TOKEN_RENEW_MARGIN: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct VaultKvRepoDesc {
+    /// Address of the Vault server, e.g. `https://vault.example.com:8200`.
+    pub address: String,
+
+    /// Mount path of the KV v2 secrets engine. Defaults to `secret`.
+    pub mount_path: Option<String>,
+
+    /// Optional path prefix under the mount, e.g. `kbs`.
+    pub path_prefix: Option<String>,
+
+    /// The secret field holding the resource data. Defaults to `value`.
+    pub field: Option<String>,
+
+    /// Vault Enterprise namespace.
+    pub namespace: Option<String>,
+
+    /// Path to a PEM CA certificate used to verify the Vault server.
+    pub ca_cert_path: Option<String>,
+
+    /// Authentication method.
+    pub auth: VaultAuth,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "method")]
+pub enum VaultAuth {
+    Token {
+        token: String,
+    },
+    AppRole {
+        role_id: String,
+        secret_id: String,
+        /// Mount path of the AppRole auth method. Defaults to `approle`.
+        mount_path: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+struct AuthResponse {
+    auth: AuthInfo,
+}
+
+#[derive(Deserialize)]
+struct AuthInfo {
+    client_token: String,
+    lease_duration: u64,
+    renewable: bool,
+}
+
+#[derive(Deserialize)]
+struct LookupResponse {
+    data: LookupData,
+}
+
+#[derive(Deserialize)]
+struct LookupData {
+    ttl: u64,
+    renewable: bool,
+}
+
+#[derive(Deserialize)]
+struct KvReadResponse {
+    data: KvData,
+}
+
+#[derive(Deserialize)]
+struct KvData {
+    data: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct KvMetadataResponse {
+    data: KvMetadata,
+}
+
+#[derive(Deserialize)]
+struct KvMetadata {
+    versions: HashMap<String, KvVersionMetadata>,
+}
+
+#[derive(Deserialize)]
+struct KvVersionMetadata {
+    /// Empty unless the version is soft deleted.
+    deletion_time: String,
+    destroyed: bool,
+}
+
+struct VaultToken {
+    token: String,
+    renewable: bool,
+    /// `None` for tokens without a TTL, e.g. root tokens.
+    expires_at: Option<Instant>,
+}
+
+impl VaultToken {
+    fn new(token: String, ttl: u64, renewable: bool) -> Self {
+        let expires_at = match ttl {
+            0 => None,
+            ttl => Some(Instant::now() + Duration::from_secs(ttl)),
+        };
+
+        Self {
+            token,
+            renewable,
+            expires_at,
+        }
+    }
+
+    fn needs_renewal(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => Instant::now() + TOKEN_RENEW_MARGIN >= expires_at,
+            None => false,
+        }
+    }
+}
+
+pub struct VaultKvBackend {
+    client: reqwest::Client,
+    address: String,
+    mount_path: String,
+    path_prefix: String,
+    field: String,
+    namespace: Option<String>,
+    auth: VaultAuth,
+    token: RwLock<VaultToken>,
+}
+
+#[async_trait::async_trait]
+impl Repository for VaultKvBackend {
+    async fn read_secret_resource(&self, resource_desc: ResourceDesc) -> Result<Vec<u8>> {
+        self.read_secret(&resource_desc, None).await
+    }
+
+    async fn write_secret_resource(
+        &mut self,
+        resource_desc: ResourceDesc,
+        data: &[u8],
+    ) -> Result<()> {
+        let value =
+            std::str::from_utf8(data).context("Vault KV backend only supports UTF-8 resources")?;
+        let mut secret = Map::new();
+        secret.insert(self.field.clone(), Value::String(value.to_string()));
+
+        let url = self.secret_url(&resource_desc);
+        let response = self
+            .request(reqwest::Method::POST, &url)
+            .await?
+            .json(&json!({ "data": secret }))
+            .send()
+            .await
+            .context("failed to write secret to Vault")?;
+
+        if !response.status().is_success() {
+            bail!(
+                "Vault returned {} when writing `{}`",
+                response.status(),
+                self.secret_path(&resource_desc)
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn read_secret_resource_version(
+        &self,
+        resource_desc: ResourceDesc,
+        version: u64,
+    ) -> Result<Vec<u8>> {
+        self.read_secret(&resource_desc, Some(version)).await
+    }
+
+    /// Deleting the metadata of a KV v2 secret permanently deletes all its
+    /// versions.
+    async fn delete_secret_resource(&mut self, resource_desc: ResourceDesc) -> Result<()> {
+        let url = self.metadata_url(&resource_desc);
+        let response = self
+            .request(reqwest::Method::DELETE, &url)
+            .await?
+            .send()
+            .await
+            .context("failed to delete secret from Vault")?;
+
+        if !response.status().is_success() {
+            bail!(
+                "Vault returned {} when deleting `{}`",
+                response.status(),
+                self.secret_path(&resource_desc)
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn list_secret_resource_versions(&self, resource_desc: ResourceDesc) -> Result<Vec<u64>> {
+        let url = self.metadata_url(&resource_desc);
+        let response = self
+            .request(reqwest::Method::GET, &url)
+            .await?
+            .send()
+            .await
+            .context("failed to read secret metadata from Vault")?;
+
+        if !response.status().is_success() {
+            bail!(
+                "Vault returned {} when reading the metadata of `{}`",
+                response.status(),
+                self.secret_path(&resource_desc)
+            );
+        }
+
+        let metadata: KvMetadataResponse = response
+            .json()
+            .await
+            .context("illegal Vault KV metadata response")?;
+        let mut versions: Vec<u64> = metadata
+            .data
+            .versions
+            .into_iter()
+            .filter(|(_, v)| !v.destroyed && v.deletion_time.is_empty())
+            .filter_map(|(version, _)| version.parse().ok())
+            .collect();
+        versions.sort_unstable();
+        Ok(versions)
+    }
+}
+
+impl VaultKvBackend {
+    pub async fn new(repo_desc: &VaultKvRepoDesc) -> Result<Self> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(ca_cert_path) = &repo_desc.ca_cert_path {
+            let pem = tokio::fs::read(ca_cert_path)
+                .await
+                .context("read Vault CA certificate")?;
+            let cert =
+                reqwest::Certificate::from_pem(&pem).context("parse Vault CA certificate")?;
+            builder = builder.add_root_certificate(cert);
+        }
+
+        let client = builder.build().context("build Vault HTTP client")?;
+        let address = repo_desc.address.trim_end_matches('/').to_string();
+        let token = login(&client, &address, &repo_desc.namespace, &repo_desc.auth).await?;
+
+        Ok(Self {
+            client,
+            address,
+            mount_path: repo_desc
+                .mount_path
+                .clone()
+                .unwrap_or(DEFAULT_MOUNT_PATH.to_string())
+                .trim_matches('/')
+                .to_string(),
+            path_prefix: repo_desc
+                .path_prefix
+                .clone()
+                .unwrap_or_default()
+                .trim_matches('/')
+                .to_string(),
+            field: repo_desc.field.clone().unwrap_or(DEFAULT_FIELD.to_string()),
+            namespace: repo_desc.namespace.clone(),
+            auth: repo_desc.auth.clone(),
+            token: RwLock::new(token),
+        })
+    }
+
+    /// Read the latest or the given version of a secret.
+    async fn read_secret(
+        &self,
+        resource_desc: &ResourceDesc,
+        version: Option<u64>,
+    ) -> Result<Vec<u8>> {
+        let mut url = self.secret_url(resource_desc);
+        if let Some(version) = version {
+            url = format!("{url}?version={version}");
+        }
+
+        let response = self
+            .request(reqwest::Method::GET, &url)
+            .await?
+            .send()
+            .await
+            .context("failed to read secret from Vault")?;
+
+        if !response.status().is_success() {
+            bail!(
+                "Vault returned {} when reading `{}`",
+                response.status(),
+                self.secret_path(resource_desc)
+            );
+        }
+
+        let secret: KvReadResponse = response
+            .json()
+            .await
+            .context("illegal Vault KV read response")?;
+        extract_field(secret.data.data, &self.field)
+    }
+
+    fn secret_path(&self, resource_desc: &ResourceDesc) -> String {
+        secret_path(&self.path_prefix, resource_desc)
+    }
+
+    fn secret_url(&self, resource_desc: &ResourceDesc) -> String {
+        format!(
+            "{}/v1/{}/data/{}",
+            self.address,
+            self.mount_path,
+            self.secret_path(resource_desc)
+        )
+    }
+
+    fn metadata_url(&self, resource_desc: &ResourceDesc) -> String {
+        format!(
+            "{}/v1/{}/metadata/{}",
+            self.address,
+            self.mount_path,
+            self.secret_path(resource_desc)
+        )
+    }
+
+    /// Build an authenticated request, renewing the Vault token first if it
+    /// is about to expire.
+    async fn request(&self, method: reqwest::Method, url: &str) -> Result<reqwest::RequestBuilder> {
+        let token = self.token().await?;
+        let mut builder = self
+            .client
+            .request(method, url)
+            .header("X-Vault-Token", token);
+        if let Some(namespace) = &self.namespace {
+            builder = builder.header("X-Vault-Namespace", namespace);
+        }
+
+        Ok(builder)
+    }
+
+    async fn token(&self) -> Result<String> {
+        {
+            let token = self.token.read().await;
+            if !token.needs_renewal() {
+                return Ok(token.token.clone());
+            }
+        }
+
+        let mut token = self.token.write().await;
+        // Another request may have renewed the token meanwhile.
+        if !token.needs_renewal() {
+            return Ok(token.token.clone());
+        }
+
+        if token.renewable {
+            match renew(&self.client, &self.address, &self.namespace, &token.token).await {
+                Ok(renewed) => {
+                    info!("Vault token renewed");
+                    *token = renewed;
+                    return Ok(token.token.clone());
+                }
+                Err(e) => warn!("Vault token renewal failed: {e:?}"),
+            }
+        }
+
+        if let VaultAuth::Token { .. } = self.auth {
+            bail!("Vault token is about to expire and cannot be renewed");
+        }
+
+        *token = login(&self.client, &self.address, &self.namespace, &self.auth).await?;
+        info!("Re-logged in to Vault");
+        Ok(token.token.clone())
+    }
+}
+
+async fn login(
+    client: &reqwest::Client,
+    address: &str,
+    namespace: &Option<String>,
+    auth: &VaultAuth,
+) -> Result<VaultToken> {
+    match auth {
+        VaultAuth::Token { token } => {
+            let mut request = client
+                .get(format!("{address}/v1/auth/token/lookup-self"))
+                .header("X-Vault-Token", token);
+            if let Some(namespace) = namespace {
+                request = request.header("X-Vault-Namespace", namespace);
+            }
+
+            let response = request.send().await.context("lookup Vault token")?;
+            if !response.status().is_success() {
+                bail!("Vault token lookup returned {}", response.status());
+            }
+
+            let lookup: LookupResponse = response
+                .json()
+                .await
+                .context("illegal Vault token lookup response")?;
+            Ok(VaultToken::new(
+                token.clone(),
+                lookup.data.ttl,
+                lookup.data.renewable,
+            ))
+        }
+        VaultAuth::AppRole {
+            role_id,
+            secret_id,
+            mount_path,
+        } => {
+            let mount_path = mount_path
+                .clone()
+                .unwrap_or(DEFAULT_APPROLE_MOUNT_PATH.to_string());
+            let mut request = client
+                .post(format!("{address}/v1/auth/{mount_path}/login"))
+                .json(&json!({ "role_id": role_id, "secret_id": secret_id }));
+            if let Some(namespace) = namespace {
+                request = request.header("X-Vault-Namespace", namespace);
+            }
+
+            let response = request.send().await.context("Vault AppRole login")?;
+            if !response.status().is_success() {
+                bail!("Vault AppRole login returned {}", response.status());
+            }
+
+            let auth: AuthResponse = response
+                .json()
+                .await
+                .context("illegal Vault login response")?;
+            Ok(VaultToken::new(
+                auth.auth.client_token,
+                auth.auth.lease_duration,
+                auth.auth.renewable,
+            ))
+        }
+    }
+}
+
+async fn renew(
+    client: &reqwest::Client,
+    address: &str,
+    namespace: &Option<String>,
+    token: &str,
+) -> Result<VaultToken> {
+    let mut request = client
+        .post(format!("{address}/v1/auth/token/renew-self"))
+        .header("X-Vault-Token", token);
+    if let Some(namespace) = namespace {
+        request = request.header("X-Vault-Namespace", namespace);
+    }
+
+    let response = request.send().await.context("renew Vault token")?;
+    if !response.status().is_success() {
+        bail!("Vault token renewal returned {}", response.status());
+    }
+
+    let auth: AuthResponse = response
+        .json()
+        .await
+        .context("illegal Vault renewal response")?;
+    Ok(VaultToken::new(
+        auth.auth.client_token,
+        auth.auth.lease_duration,
+        auth.auth.renewable,
+    ))
+}
+
+fn secret_path(prefix: &str, resource_desc: &ResourceDesc) -> String {
+    let path = format!(
+        "{}/{}/{}",
+        resource_desc.repository_name, resource_desc.resource_type, resource_desc.resource_tag
+    );
+
+    if prefix.is_empty() {
+        return path;
+    }
+
+    format!("{prefix}/{path}")
+}
+
+/// Get the resource data from the given field of a KV secret. String values
+/// are returned as is, other JSON values are returned serialized.
+fn extract_field(mut secret: Map<String, Value>, field: &str) -> Result<Vec<u8>> {
+    let value = secret
+        .remove(field)
+        .ok_or_else(|| anyhow!("no field `{field}` in the Vault secret"))?;
+
+    match value {
+        Value::String(s) => Ok(s.into_bytes()),
+        other => Ok(serde_json::to_vec(&other)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use serde_json::{json, Value};
+
+    use crate::resource::ResourceDesc;
+
+    #[rstest]
+    #[case("", "default/key/1")]
+    #[case("kbs", "kbs/default/key/1")]
+    fn secret_path(#[case] prefix: &str, #[case] expected: &str) {
+        let resource_desc = ResourceDesc {
+            repository_name: "default".into(),
+            resource_type: "key".into(),
+            resource_tag: "1".into(),
+        };
+
+        assert_eq!(super::secret_path(prefix, &resource_desc), expected);
+    }
+
+    #[rstest]
+    #[case(json!({"value": "secret"}), "value", Some(b"secret".to_vec()))]
+    #[case(json!({"value": {"a": 1}}), "value", Some(br#"{"a":1}"#.to_vec()))]
+    #[case(json!({"other": "secret"}), "value", None)]
+    fn extract_field(
+        #[case] secret: Value,
+        #[case] field: &str,
+        #[case] expected: Option<Vec<u8>>,
+    ) {
+        let Value::Object(secret) = secret else {
+            panic!("secret must be an object");
+        };
+
+        assert_eq!(super::extract_field(secret, field).ok(), expected);
+    }
+}
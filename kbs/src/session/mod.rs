@@ -0,0 +1,578 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use actix_web::cookie::{time::OffsetDateTime, Cookie};
+use anyhow::{bail, Result};
+use kbs_types::{Challenge, Request};
+use log::warn;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::nonce::{IssuedNonce, NonceError, NonceService};
+
+#[cfg(feature = "session-postgres")]
+pub mod postgres;
+
+pub(crate) static KBS_SESSION_ID: &str = "kbs-session-id";
+
+/// Finite State Machine model for RCAR handshake
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub(crate) enum SessionStatus {
+    Authed {
+        request: Request,
+        challenge: Challenge,
+        /// The nonce of the challenge, checked by `/attest`.
+        nonce: IssuedNonce,
+        id: String,
+        #[serde(with = "time::serde::rfc3339")]
+        timeout: OffsetDateTime,
+    },
+
+    Attested {
+        attestation_claims: String,
+        token: String,
+        id: String,
+        #[serde(with = "time::serde::rfc3339")]
+        timeout: OffsetDateTime,
+        /// The session expires if no resource is requested until then.
+        #[serde(default, with = "time::serde::rfc3339::option")]
+        idle_timeout: Option<OffsetDateTime>,
+        /// Resource requests the session still serves, unlimited if `None`.
+        #[serde(default)]
+        resource_requests_left: Option<u64>,
+    },
+}
+
+/// Lifetime and limits of the sessions.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct SessionConfig {
+    /// Seconds a session lasts from its `/auth`. Defaults to the `timeout`
+    /// of the KBS.
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+
+    /// Seconds after which an attested session which serves no resource
+    /// request expires. Never if not given.
+    #[serde(default)]
+    pub idle_timeout_secs: Option<u64>,
+
+    /// Resource requests an attested session serves, after which it
+    /// expires. Unlimited if not given.
+    #[serde(default)]
+    pub max_resource_requests: Option<u64>,
+}
+
+/// Lifetime and limits of the sessions, enforced by the KBS.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SessionLimits {
+    pub ttl: Duration,
+    pub idle_timeout: Option<Duration>,
+    pub max_resource_requests: Option<u64>,
+}
+
+impl SessionLimits {
+    /// The limits of `config`, whose sessions last `timeout_min` minutes if
+    /// it has no TTL.
+    pub fn new(config: &SessionConfig, timeout_min: i64) -> Result<Self> {
+        if [
+            config.ttl_secs,
+            config.idle_timeout_secs,
+            config.max_resource_requests,
+        ]
+        .contains(&Some(0))
+        {
+            bail!("The session TTL, idle timeout and maximum resource requests must be positive");
+        }
+
+        Ok(Self {
+            ttl: config
+                .ttl_secs
+                .map(Duration::from_secs)
+                .unwrap_or(Duration::from_secs(timeout_min.max(0) as u64 * 60)),
+            idle_timeout: config.idle_timeout_secs.map(Duration::from_secs),
+            max_resource_requests: config.max_resource_requests,
+        })
+    }
+}
+
+macro_rules! impl_member {
+    ($attr: ident, $typ: ident) => {
+        pub fn $attr(&self) -> &$typ {
+            match self {
+                SessionStatus::Authed { $attr, .. } => $attr,
+                SessionStatus::Attested { $attr, .. } => $attr,
+            }
+        }
+    };
+    ($attr: ident, $typ: ident, $branch: ident) => {
+        pub fn $attr(&self) -> &$typ {
+            match self {
+                SessionStatus::$branch { $attr, .. } => $attr,
+                _ => panic!("unexpected status"),
+            }
+        }
+    };
+}
+
+impl SessionStatus {
+    pub fn auth(
+        request: Request,
+        ttl: Duration,
+        challenge: Challenge,
+        nonce: IssuedNonce,
+    ) -> Result<Self> {
+        let version = Version::parse(&request.version).map_err(anyhow::Error::from)?;
+        if !crate::VERSION_REQ.matches(&version) {
+            bail!("Invalid Request version {}", request.version);
+        }
+        let id = Uuid::new_v4().as_simple().to_string();
+
+        let timeout = OffsetDateTime::now_utc() + ttl;
+
+        Ok(Self::Authed {
+            request,
+            challenge,
+            nonce,
+            id,
+            timeout,
+        })
+    }
+
+    pub fn cookie<'a>(&self) -> Cookie<'a> {
+        match self {
+            SessionStatus::Authed { id, timeout, .. } => Cookie::build(KBS_SESSION_ID, id.clone())
+                .expires(*timeout)
+                .finish(),
+            SessionStatus::Attested { id, timeout, .. } => {
+                Cookie::build(KBS_SESSION_ID, id.clone())
+                    .expires(*timeout)
+                    .finish()
+            }
+        }
+    }
+
+    impl_member!(request, Request, Authed);
+    impl_member!(challenge, Challenge, Authed);
+    impl_member!(id, str);
+
+    /// When the session expires: at its `timeout`, at its idle timeout, or
+    /// already if it serves no more resource requests.
+    pub fn expires_at(&self) -> OffsetDateTime {
+        match self {
+            SessionStatus::Authed { timeout, .. } => *timeout,
+            SessionStatus::Attested {
+                resource_requests_left: Some(0),
+                ..
+            } => OffsetDateTime::UNIX_EPOCH,
+            SessionStatus::Attested {
+                timeout,
+                idle_timeout,
+                ..
+            } => idle_timeout.map_or(*timeout, |idle_timeout| idle_timeout.min(*timeout)),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at() < OffsetDateTime::now_utc()
+    }
+
+    /// Check the nonce of the challenge of the session with the
+    /// `nonce_service`, and mark it as used. The nonce of an attested session
+    /// was used.
+    pub fn consume_nonce(
+        &mut self,
+        nonce_service: &NonceService,
+        client_identity: Option<&str>,
+    ) -> std::result::Result<(), NonceError> {
+        match self {
+            SessionStatus::Authed { nonce, .. } => nonce_service.consume(nonce, client_identity),
+            SessionStatus::Attested { .. } => Err(NonceError::Reused),
+        }
+    }
+
+    pub fn attest(&mut self, attestation_claims: String, token: String, limits: &SessionLimits) {
+        match self {
+            SessionStatus::Authed { id, timeout, .. } => {
+                *self = SessionStatus::Attested {
+                    attestation_claims,
+                    token,
+                    id: id.clone(),
+                    timeout: *timeout,
+                    idle_timeout: limits
+                        .idle_timeout
+                        .map(|idle_timeout| OffsetDateTime::now_utc() + idle_timeout),
+                    resource_requests_left: limits.max_resource_requests,
+                };
+            }
+            SessionStatus::Attested { .. } => {
+                warn!("session already attested.");
+            }
+        }
+    }
+
+    /// Count a resource request of the session, if it is attested and not
+    /// expired, and restart its idle timeout. Tell whether it is counted.
+    #[cfg(feature = "resource")]
+    pub fn request_resource(&mut self, limits: &SessionLimits) -> bool {
+        if self.is_expired() {
+            return false;
+        }
+        let SessionStatus::Attested {
+            idle_timeout,
+            resource_requests_left,
+            ..
+        } = self
+        else {
+            return false;
+        };
+
+        *idle_timeout = limits
+            .idle_timeout
+            .map(|limit| OffsetDateTime::now_utc() + limit);
+        if let Some(left) = resource_requests_left {
+            *left -= 1;
+        }
+        true
+    }
+}
+
+/// Numbers of the sessions of a store, by state.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct SessionCount {
+    pub authed: i64,
+    pub attested: i64,
+    pub expired: i64,
+}
+
+/// Where the sessions of the RCAR handshake are kept, which the KBS replicas
+/// sharing it serve alike.
+#[async_trait::async_trait]
+pub(crate) trait SessionStore: Send + Sync {
+    /// Insert `session`, replacing the one of the same ID.
+    async fn insert(&self, session: SessionStatus) -> Result<()>;
+
+    /// The session `id`, even if it is expired.
+    async fn get(&self, id: &str) -> Result<Option<SessionStatus>>;
+
+    /// Consume the nonce of the session `id` with the `nonce_service`, see
+    /// [`SessionStatus::consume_nonce`], atomically, so that the replicas
+    /// sharing the store accept a single-use nonce only once. The session
+    /// with its nonce consumed, or why the nonce is rejected.
+    async fn consume_nonce(
+        &self,
+        id: &str,
+        nonce_service: &NonceService,
+        client_identity: Option<&str>,
+    ) -> Result<Option<std::result::Result<SessionStatus, NonceError>>>;
+
+    /// Count a resource request of the session `id` with the `limits`, see
+    /// [`SessionStatus::request_resource`], atomically. The session as it
+    /// was before the request.
+    #[cfg(feature = "resource")]
+    async fn request_resource(
+        &self,
+        id: &str,
+        limits: &SessionLimits,
+    ) -> Result<Option<SessionStatus>>;
+
+    /// Remove the expired sessions.
+    async fn purge_expired(&self) -> Result<()>;
+
+    /// Count the sessions, by state.
+    async fn count(&self) -> Result<SessionCount>;
+}
+
+/// Store of the sessions.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+pub enum SessionStoreConfig {
+    /// The sessions are kept in memory, and lost when the KBS restarts.
+    #[default]
+    Memory,
+
+    /// The sessions are kept in a PostgreSQL database, which the KBS
+    /// replicas can share.
+    #[cfg(feature = "session-postgres")]
+    Postgres(postgres::PostgresSessionStoreDesc),
+}
+
+impl SessionStoreConfig {
+    pub(crate) async fn initialize(&self) -> Result<Arc<dyn SessionStore>> {
+        match self {
+            Self::Memory => Ok(Arc::new(SessionMap::new())),
+            #[cfg(feature = "session-postgres")]
+            Self::Postgres(desc) => Ok(Arc::new(postgres::PostgresSessionStore::new(desc).await?)),
+        }
+    }
+}
+
+/// Sessions kept in memory.
+pub(crate) struct SessionMap {
+    sessions: scc::HashMap<String, SessionStatus>,
+}
+
+impl SessionMap {
+    pub fn new() -> Self {
+        SessionMap {
+            sessions: scc::HashMap::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionStore for SessionMap {
+    async fn insert(&self, session: SessionStatus) -> Result<()> {
+        self.sessions
+            .upsert_async(session.id().to_string(), session)
+            .await;
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<SessionStatus>> {
+        Ok(self
+            .sessions
+            .read_async(id, |_, session| session.clone())
+            .await)
+    }
+
+    async fn consume_nonce(
+        &self,
+        id: &str,
+        nonce_service: &NonceService,
+        client_identity: Option<&str>,
+    ) -> Result<Option<std::result::Result<SessionStatus, NonceError>>> {
+        Ok(self
+            .sessions
+            .update_async(id, |_, session| {
+                session
+                    .consume_nonce(nonce_service, client_identity)
+                    .map(|_| session.clone())
+            })
+            .await)
+    }
+
+    #[cfg(feature = "resource")]
+    async fn request_resource(
+        &self,
+        id: &str,
+        limits: &SessionLimits,
+    ) -> Result<Option<SessionStatus>> {
+        Ok(self
+            .sessions
+            .update_async(id, |_, session| {
+                let before = session.clone();
+                session.request_resource(limits);
+                before
+            })
+            .await)
+    }
+
+    async fn purge_expired(&self) -> Result<()> {
+        self.sessions
+            .retain_async(|_, session| !session.is_expired())
+            .await;
+        Ok(())
+    }
+
+    async fn count(&self) -> Result<SessionCount> {
+        let mut count = SessionCount::default();
+        self.sessions
+            .scan_async(|_, session| match session {
+                _ if session.is_expired() => count.expired += 1,
+                SessionStatus::Authed { .. } => count.authed += 1,
+                SessionStatus::Attested { .. } => count.attested += 1,
+            })
+            .await;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: SessionLimits = SessionLimits {
+        ttl: Duration::from_secs(300),
+        idle_timeout: None,
+        max_resource_requests: None,
+    };
+
+    fn authed_session() -> SessionStatus {
+        let request = Request {
+            version: "0.1.0".into(),
+            tee: kbs_types::Tee::Sample,
+            extra_params: String::new(),
+        };
+        let challenge = Challenge {
+            nonce: "42".into(),
+            extra_params: String::new(),
+        };
+        let nonce_service =
+            NonceService::new(&Default::default(), LIMITS.ttl).expect("nonce service");
+        let nonce = nonce_service.issue(None).unwrap();
+        SessionStatus::auth(request, LIMITS.ttl, challenge, nonce).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_consume_nonce() {
+        let nonce_service = NonceService::new(&Default::default(), LIMITS.ttl).unwrap();
+        let map = SessionMap::new();
+        let session = authed_session();
+        let id = session.id().to_string();
+        map.insert(session).await.unwrap();
+
+        // The nonce kept in the session is only accepted once.
+        map.consume_nonce(&id, &nonce_service, None)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            map.consume_nonce(&id, &nonce_service, None).await.unwrap(),
+            Some(Err(NonceError::Reused))
+        ));
+
+        let mut session = map.get(&id).await.unwrap().unwrap();
+        session.attest("{}".into(), "token".into(), &LIMITS);
+        map.insert(session).await.unwrap();
+        assert!(matches!(
+            map.consume_nonce(&id, &nonce_service, None).await.unwrap(),
+            Some(Err(NonceError::Reused))
+        ));
+        assert!(map
+            .consume_nonce("unknown", &nonce_service, None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_session_map() {
+        let map = SessionMap::new();
+        let session = authed_session();
+        let id = session.id().to_string();
+        map.insert(session.clone()).await.unwrap();
+        let mut expired = authed_session();
+        if let SessionStatus::Authed { timeout, .. } = &mut expired {
+            *timeout = OffsetDateTime::now_utc() - Duration::from_secs(60);
+        }
+        map.insert(expired).await.unwrap();
+
+        // An attested session replaces the authed one.
+        let mut attested = session;
+        attested.attest("{}".into(), "token".into(), &LIMITS);
+        map.insert(attested).await.unwrap();
+        let Some(SessionStatus::Attested { token, .. }) = map.get(&id).await.unwrap() else {
+            panic!("the session is not attested");
+        };
+        assert_eq!(token, "token");
+        assert_eq!(
+            map.count().await.unwrap(),
+            SessionCount {
+                authed: 0,
+                attested: 1,
+                expired: 1,
+            }
+        );
+
+        map.purge_expired().await.unwrap();
+        assert_eq!(map.count().await.unwrap().expired, 0);
+        assert!(map.get("unknown").await.unwrap().is_none());
+    }
+
+    #[cfg(feature = "resource")]
+    #[tokio::test]
+    async fn test_session_limits() {
+        let limits = SessionLimits {
+            idle_timeout: Some(Duration::from_secs(60)),
+            max_resource_requests: Some(2),
+            ..LIMITS
+        };
+        let map = SessionMap::new();
+        let mut session = authed_session();
+        let id = session.id().to_string();
+
+        // An authed session serves no resource request.
+        assert!(!session.request_resource(&limits));
+        session.attest("{}".into(), "token".into(), &limits);
+        map.insert(session).await.unwrap();
+
+        // The session expires once it served its resource requests.
+        for left in [2, 1] {
+            let before = map.request_resource(&id, &limits).await.unwrap().unwrap();
+            assert!(matches!(
+                before,
+                SessionStatus::Attested { resource_requests_left: Some(l), .. } if l == left
+            ));
+            assert!(!before.is_expired());
+        }
+        let before = map.request_resource(&id, &limits).await.unwrap().unwrap();
+        assert!(before.is_expired());
+        assert!(map
+            .request_resource("unknown", &limits)
+            .await
+            .unwrap()
+            .is_none());
+
+        // The session expires when it is idle for too long, unless a resource
+        // request restarts its idle timeout.
+        let mut session = authed_session();
+        session.attest("{}".into(), "token".into(), &limits);
+        assert!(session.request_resource(&limits));
+        let SessionStatus::Attested {
+            timeout,
+            idle_timeout,
+            ..
+        } = &mut session
+        else {
+            panic!("the session is not attested");
+        };
+        assert!(*timeout > OffsetDateTime::now_utc());
+        assert!(idle_timeout.unwrap() > OffsetDateTime::now_utc() + Duration::from_secs(30));
+        *idle_timeout = Some(OffsetDateTime::now_utc() - Duration::from_secs(1));
+        assert!(session.is_expired());
+        assert!(!session.request_resource(&limits));
+    }
+
+    #[test]
+    fn test_session_limits_config() {
+        let limits = SessionLimits::new(&SessionConfig::default(), 5).unwrap();
+        assert_eq!(limits, LIMITS);
+
+        let config = SessionConfig {
+            ttl_secs: Some(30),
+            idle_timeout_secs: Some(10),
+            max_resource_requests: Some(3),
+        };
+        let limits = SessionLimits::new(&config, 5).unwrap();
+        assert_eq!(limits.ttl, Duration::from_secs(30));
+        assert_eq!(limits.idle_timeout, Some(Duration::from_secs(10)));
+        assert_eq!(limits.max_resource_requests, Some(3));
+
+        let config = SessionConfig {
+            max_resource_requests: Some(0),
+            ..Default::default()
+        };
+        assert!(SessionLimits::new(&config, 5).is_err());
+    }
+
+    #[test]
+    fn test_session_serde() {
+        let mut session = authed_session();
+        let authed: SessionStatus =
+            serde_json::from_str(&serde_json::to_string(&session).unwrap()).unwrap();
+        assert_eq!(authed.challenge().nonce, "42");
+        assert_eq!(authed.expires_at(), session.expires_at());
+
+        session.attest("{}".into(), "token".into(), &LIMITS);
+        let attested: SessionStatus =
+            serde_json::from_str(&serde_json::to_string(&session).unwrap()).unwrap();
+        assert!(matches!(&attested, SessionStatus::Attested { token, .. } if token == "token"));
+        assert_eq!(attested.id(), session.id());
+    }
+}
@@ -0,0 +1,249 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! OpenTelemetry traces of the KBS: a span for each HTTP request, whose
+//! parent is the `traceparent` of the request, with the spans of the calls
+//! of the attestation service and of the resource repositories nested in it.
+//! The trace context is propagated to a remote CoCo AS.
+//!
+//! The spans are exported over OTLP when the `otel` feature is enabled and
+//! `otel_config` is given, and are not recorded otherwise.
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderMap, HeaderName};
+use opentelemetry::propagation::Extractor;
+#[cfg(feature = "coco-as-grpc")]
+use opentelemetry::propagation::Injector;
+use opentelemetry::trace::{FutureExt, SpanKind, Status, TraceContextExt, Tracer};
+use opentelemetry::{global, Context, KeyValue};
+#[cfg(feature = "otel")]
+use serde::Deserialize;
+use std::future::Future;
+
+/// Name of the tracer of the KBS.
+const TRACER_NAME: &str = "kbs";
+
+#[cfg(feature = "otel")]
+const DEFAULT_ENDPOINT: &str = "http://127.0.0.1:4317";
+
+#[cfg(feature = "otel")]
+const DEFAULT_SERVICE_NAME: &str = "kbs";
+
+/// Export of the traces over OTLP.
+#[cfg(feature = "otel")]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct OtelConfig {
+    /// OTLP gRPC endpoint of the collector the spans are exported to.
+    #[serde(default = "default_endpoint")]
+    pub endpoint: String,
+
+    /// `service.name` of the spans.
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+
+    /// Ratio of the traces started by the KBS which are sampled, from 0 to
+    /// 1. The traces started by the clients are sampled as they tell.
+    #[serde(default = "default_sampling_ratio")]
+    pub sampling_ratio: f64,
+}
+
+#[cfg(feature = "otel")]
+fn default_endpoint() -> String {
+    DEFAULT_ENDPOINT.into()
+}
+
+#[cfg(feature = "otel")]
+fn default_service_name() -> String {
+    DEFAULT_SERVICE_NAME.into()
+}
+
+#[cfg(feature = "otel")]
+fn default_sampling_ratio() -> f64 {
+    1.0
+}
+
+/// Install the OTLP exporter of the spans and the W3C trace context
+/// propagator.
+#[cfg(feature = "otel")]
+pub fn init(config: &OtelConfig) -> anyhow::Result<()> {
+    use anyhow::Context as _;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::trace::{self, Sampler};
+
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sampling_ratio)));
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(&config.endpoint),
+        )
+        .with_trace_config(trace::config().with_sampler(sampler).with_resource(
+            opentelemetry_sdk::Resource::new(vec![KeyValue::new(
+                "service.name",
+                config.service_name.clone(),
+            )]),
+        ))
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .context("install OTLP exporter")?;
+    global::set_text_map_propagator(opentelemetry_sdk::propagation::TraceContextPropagator::new());
+
+    log::info!("Exporting traces to {}", config.endpoint);
+    Ok(())
+}
+
+/// Export the spans which are not yet.
+#[cfg(feature = "otel")]
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+/// Middleware tracing the HTTP requests, in a span of the route of the
+/// request which the spans of its handler are nested in.
+pub(crate) fn trace_request<S, B>(
+    request: ServiceRequest,
+    service: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    let route = request
+        .match_pattern()
+        .unwrap_or_else(|| String::from("unmatched"));
+    let span = global::tracer(TRACER_NAME)
+        .span_builder(format!("{} {route}", request.method()))
+        .with_kind(SpanKind::Server)
+        .with_attributes(vec![
+            KeyValue::new("http.request.method", request.method().to_string()),
+            KeyValue::new("http.route", route),
+            KeyValue::new("url.path", request.path().to_string()),
+        ])
+        .start_with_context(&global::tracer(TRACER_NAME), &parent);
+    let context = parent.with_span(span);
+
+    let response = service.call(request).with_context(context.clone());
+    async move {
+        let response = response.await;
+        let span = context.span();
+        match &response {
+            Ok(response) => {
+                let status = response.status();
+                span.set_attribute(KeyValue::new(
+                    "http.response.status_code",
+                    i64::from(status.as_u16()),
+                ));
+                if status.is_server_error() {
+                    span.set_status(Status::error(status.to_string()));
+                }
+            }
+            Err(e) => span.set_status(Status::error(e.to_string())),
+        }
+        span.end();
+        response
+    }
+}
+
+/// Run `future` in a span `name` with `attributes`, nested in the current
+/// one, whose status is the error of its result.
+pub(crate) async fn traced<T, E, F>(
+    name: &'static str,
+    attributes: Vec<KeyValue>,
+    future: F,
+) -> Result<T, E>
+where
+    E: std::fmt::Display,
+    F: Future<Output = Result<T, E>>,
+{
+    let span = global::tracer(TRACER_NAME)
+        .span_builder(name)
+        .with_attributes(attributes)
+        .start(&global::tracer(TRACER_NAME));
+    let context = Context::current_with_span(span);
+
+    let result = future.with_context(context.clone()).await;
+    if let Err(e) = &result {
+        context.span().set_status(Status::error(e.to_string()));
+    }
+    context.span().end();
+    result
+}
+
+/// Injects the trace context in the metadata of a gRPC request.
+#[cfg(feature = "coco-as-grpc")]
+struct MetadataInjector<'a>(&'a mut tonic::metadata::MetadataMap);
+
+#[cfg(feature = "coco-as-grpc")]
+impl Injector for MetadataInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        let Ok(key) = tonic::metadata::MetadataKey::from_bytes(key.as_bytes()) else {
+            return;
+        };
+        if let Ok(value) = value.parse() {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+/// Propagate the current trace context in the metadata of a gRPC request.
+#[cfg(feature = "coco-as-grpc")]
+pub(crate) fn inject_context<T>(request: &mut tonic::Request<T>) {
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(
+            &Context::current(),
+            &mut MetadataInjector(request.metadata_mut()),
+        )
+    });
+}
+
+#[cfg(all(test, feature = "otel"))]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+
+    #[actix_web::test]
+    async fn test_trace_request() {
+        global::set_text_map_propagator(
+            opentelemetry_sdk::propagation::TraceContextPropagator::new(),
+        );
+
+        let app = test::init_service(App::new().wrap_fn(trace_request).route(
+            "/test",
+            web::get().to(|| async {
+                // The spans of the handler are in the trace of the request.
+                let trace_id = traced("test", vec![], async {
+                    Ok::<_, String>(Context::current().span().span_context().trace_id())
+                })
+                .await
+                .unwrap();
+                HttpResponse::Ok().body(trace_id.to_string())
+            }),
+        ))
+        .await;
+
+        let request = test::TestRequest::get()
+            .uri("/test")
+            .insert_header((
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            ))
+            .to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        assert_eq!(body, "4bf92f3577b34da6a3ce929d0e0e4736");
+    }
+}
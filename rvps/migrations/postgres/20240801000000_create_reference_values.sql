@@ -0,0 +1,5 @@
+-- Reference values of the RVPS `Postgres` store.
+CREATE TABLE IF NOT EXISTS reference_values (
+    name TEXT PRIMARY KEY,
+    reference_value JSONB NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Audit log of the changes of the reference values of the RVPS `Postgres` store.
+CREATE TABLE IF NOT EXISTS reference_value_audit (
+    id BIGSERIAL PRIMARY KEY,
+    record JSONB NOT NULL
+);
@@ -0,0 +1,87 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Audit log of the changes of the reference values, i.e. who changed which
+//! reference value and how. The store is rolled back by undoing the changes
+//! logged after a record.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use strum::AsRefStr;
+
+use crate::ReferenceValue;
+
+/// Actor of the changes made by the garbage collection.
+pub const GC_ACTOR: &str = "gc";
+
+/// Actor of the registrations of unauthenticated clients.
+pub const ANONYMOUS_ACTOR: &str = "anonymous";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, AsRefStr)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum AuditAction {
+    /// The reference value is registered, or replaced, from a message.
+    Register,
+
+    /// The reference value is superseded by one of a message.
+    Supersede,
+
+    /// The advisories of the reference value are set by a message.
+    Annotate,
+
+    /// The reference value is deleted by an admin.
+    Delete,
+
+    /// The expired or superseded reference value is garbage collected.
+    Collect,
+
+    /// The change of the reference value is undone by a rollback.
+    Rollback,
+}
+
+/// A change of a reference value.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuditRecord {
+    /// Id of the record, increasing with the changes. Assigned by the store.
+    #[serde(default)]
+    pub id: u64,
+
+    pub timestamp: DateTime<Utc>,
+
+    /// Who made the change, e.g. the name of a provider or of an admin.
+    pub actor: String,
+
+    pub action: AuditAction,
+
+    /// Name of the changed reference value.
+    pub name: String,
+
+    /// The reference value before the change, if it existed.
+    pub previous: Option<ReferenceValue>,
+
+    /// The reference value after the change, if it still exists.
+    pub current: Option<ReferenceValue>,
+}
+
+impl AuditRecord {
+    pub fn new(
+        actor: &str,
+        action: AuditAction,
+        name: &str,
+        previous: Option<ReferenceValue>,
+        current: Option<ReferenceValue>,
+    ) -> Self {
+        Self {
+            id: 0,
+            timestamp: Utc::now(),
+            actor: actor.to_string(),
+            action,
+            name: name.to_string(),
+            previous,
+            current,
+        }
+    }
+}
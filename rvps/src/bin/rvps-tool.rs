@@ -1,6 +1,7 @@
 //! This tool is to connect the RVPS
 
 use anyhow::*;
+use base64::{engine::general_purpose::STANDARD, Engine};
 use clap::{Args, Parser};
 use log::info;
 use shadow_rs::shadow;
@@ -10,8 +11,9 @@ pub mod rvps_api {
 }
 
 use crate::rvps_api::{
-    reference_value_provider_service_client::ReferenceValueProviderServiceClient,
-    ReferenceValueQueryRequest, ReferenceValueRegisterRequest,
+    reference_value_provider_service_client::ReferenceValueProviderServiceClient, AuditLogRequest,
+    ReferenceValueDeleteRequest, ReferenceValueListRequest, ReferenceValueQueryRequest,
+    ReferenceValueRegisterRequest, ReferenceValueRollbackRequest,
 };
 
 shadow!(build);
@@ -19,10 +21,38 @@ shadow!(build);
 /// Default address of RVPS
 const DEFAULT_ADDR: &str = "http://127.0.0.1:50003";
 
-async fn register(addr: &str, provenance_path: &str) -> Result<()> {
+/// Authenticate the request with the bearer token of `token_path`.
+fn authorize<T>(req: &mut tonic::Request<T>, token_path: &str) -> Result<()> {
+    let token = std::fs::read_to_string(token_path).context("read token")?;
+    let authorization = format!("Bearer {}", token.trim())
+        .parse()
+        .context("illegal token")?;
+    req.metadata_mut().insert("authorization", authorization);
+    Ok(())
+}
+
+async fn register(addr: &str, provenance_path: &str, token_path: Option<&str>) -> Result<()> {
     let message = std::fs::read_to_string(provenance_path).context("read provenance")?;
+    register_message(addr, message, token_path).await
+}
+
+/// Register the reference values of the bundle of `bundle_path`, as
+/// published with a release of the guest images.
+async fn import_bundle(args: ImportBundleArgs) -> Result<()> {
+    let bundle = std::fs::read(&args.path).context("read bundle")?;
+    let message = serde_json::json!({
+        "type": "bundle",
+        "payload": STANDARD.encode(bundle),
+    });
+    register_message(&args.addr, message.to_string(), args.token_path.as_deref()).await
+}
+
+async fn register_message(addr: &str, message: String, token_path: Option<&str>) -> Result<()> {
     let mut client = ReferenceValueProviderServiceClient::connect(addr.to_string()).await?;
-    let req = tonic::Request::new(ReferenceValueRegisterRequest { message });
+    let mut req = tonic::Request::new(ReferenceValueRegisterRequest { message });
+    if let Some(token_path) = token_path {
+        authorize(&mut req, token_path)?;
+    }
 
     client.register_reference_value(req).await?;
 
@@ -46,6 +76,73 @@ async fn query(addr: &str, name: &str) -> Result<()> {
     Ok(())
 }
 
+async fn list(args: ListArgs) -> Result<()> {
+    let mut client = ReferenceValueProviderServiceClient::connect(args.addr).await?;
+    let req = tonic::Request::new(ReferenceValueListRequest {
+        name_prefix: args.name_prefix.unwrap_or_default(),
+        provenance: args.provenance.unwrap_or_default(),
+        since: args.since.unwrap_or_default(),
+        until: args.until.unwrap_or_default(),
+        page_size: args.page_size.unwrap_or_default(),
+        page_token: args.page_token.unwrap_or_default(),
+    });
+
+    let res = client.list_reference_values(req).await?.into_inner();
+    info!(
+        "List reference values succeeded:\n {}",
+        res.reference_values
+    );
+    if !res.next_page_token.is_empty() {
+        info!("Next page token: {}", res.next_page_token);
+    }
+    Ok(())
+}
+
+async fn delete(args: DeleteArgs) -> Result<()> {
+    let mut client = ReferenceValueProviderServiceClient::connect(args.addr).await?;
+    let mut req = tonic::Request::new(ReferenceValueDeleteRequest {
+        name: args.name.clone(),
+    });
+    authorize(&mut req, &args.token_path)?;
+
+    let deleted = client
+        .delete_reference_value(req)
+        .await?
+        .into_inner()
+        .deleted;
+    match deleted {
+        true => info!("Delete reference value of {} succeeded.", args.name),
+        false => info!("No reference value of {}.", args.name),
+    }
+    Ok(())
+}
+
+async fn rollback(args: RollbackArgs) -> Result<()> {
+    let mut client = ReferenceValueProviderServiceClient::connect(args.addr).await?;
+    let mut req = tonic::Request::new(ReferenceValueRollbackRequest {
+        audit_id: args.audit_id,
+    });
+    authorize(&mut req, &args.token_path)?;
+
+    let names = client
+        .rollback_reference_values(req)
+        .await?
+        .into_inner()
+        .names;
+    info!("Roll back reference values succeeded:\n {names:?}");
+    Ok(())
+}
+
+async fn audit(args: AuditArgs) -> Result<()> {
+    let mut client = ReferenceValueProviderServiceClient::connect(args.addr).await?;
+    let mut req = tonic::Request::new(AuditLogRequest { since: args.since });
+    authorize(&mut req, &args.token_path)?;
+
+    let records = client.get_audit_log(req).await?.into_inner().records;
+    info!("Get audit log succeeded:\n {records}");
+    Ok(())
+}
+
 /// RVPS command-line arguments.
 #[derive(Parser)]
 #[command(name = "rvps-tool")]
@@ -55,8 +152,23 @@ enum Cli {
     /// Register reference values
     Register(RegisterArgs),
 
+    /// Register the reference values of a bundle published with a release
+    ImportBundle(ImportBundleArgs),
+
     /// Query reference values
     Query(QueryArgs),
+
+    /// List the registered reference values, a page at a time
+    List(ListArgs),
+
+    /// Delete a reference value, as an admin
+    Delete(DeleteArgs),
+
+    /// Roll the reference values back to an audit record, as an admin
+    Rollback(RollbackArgs),
+
+    /// Get the audit log of the reference values, as an admin
+    Audit(AuditArgs),
 }
 
 #[derive(Args)]
@@ -69,6 +181,28 @@ struct RegisterArgs {
     /// The path to the provenance json file
     #[arg(short, long)]
     path: String,
+
+    /// The path to the token of the provider, if the RVPS authenticates
+    /// the providers
+    #[arg(short, long)]
+    token_path: Option<String>,
+}
+
+#[derive(Args)]
+#[command(author, version, about, long_about = None)]
+struct ImportBundleArgs {
+    /// The address of target RVPS
+    #[arg(short, long, default_value = DEFAULT_ADDR)]
+    addr: String,
+
+    /// The path to the bundle json file
+    #[arg(short, long)]
+    path: String,
+
+    /// The path to the token of the provider, if the RVPS authenticates
+    /// the providers
+    #[arg(short, long)]
+    token_path: Option<String>,
 }
 
 #[derive(Args)]
@@ -83,6 +217,87 @@ struct QueryArgs {
     name: String,
 }
 
+#[derive(Args)]
+#[command(author, version, about, long_about = None)]
+struct ListArgs {
+    /// The address of target RVPS
+    #[arg(short, long, default_value = DEFAULT_ADDR)]
+    addr: String,
+
+    /// Only the reference values whose name starts with the prefix
+    #[arg(short, long)]
+    name_prefix: Option<String>,
+
+    /// Only the reference values registered from provenance of the type
+    #[arg(long)]
+    provenance: Option<String>,
+
+    /// Only the reference values registered since the Unix time, in seconds
+    #[arg(long)]
+    since: Option<i64>,
+
+    /// Only the reference values registered until the Unix time, in seconds
+    #[arg(long)]
+    until: Option<i64>,
+
+    /// Maximum number of reference values of the page
+    #[arg(long)]
+    page_size: Option<u32>,
+
+    /// Token of the page, as returned with the previous page
+    #[arg(long)]
+    page_token: Option<String>,
+}
+
+#[derive(Args)]
+#[command(author, version, about, long_about = None)]
+struct DeleteArgs {
+    /// The address of target RVPS
+    #[arg(short, long, default_value = DEFAULT_ADDR)]
+    addr: String,
+
+    /// The name of the reference value to delete
+    #[arg(short, long)]
+    name: String,
+
+    /// The path to the token of the admin
+    #[arg(short, long)]
+    token_path: String,
+}
+
+#[derive(Args)]
+#[command(author, version, about, long_about = None)]
+struct RollbackArgs {
+    /// The address of target RVPS
+    #[arg(short, long, default_value = DEFAULT_ADDR)]
+    addr: String,
+
+    /// The id of the audit record to roll back to. The changes logged after
+    /// it are undone
+    #[arg(short = 'i', long)]
+    audit_id: u64,
+
+    /// The path to the token of the admin
+    #[arg(short, long)]
+    token_path: String,
+}
+
+#[derive(Args)]
+#[command(author, version, about, long_about = None)]
+struct AuditArgs {
+    /// The address of target RVPS
+    #[arg(short, long, default_value = DEFAULT_ADDR)]
+    addr: String,
+
+    /// Only the audit records after the record of this id
+    #[arg(short, long, default_value_t = 0)]
+    since: u64,
+
+    /// The path to the token of the admin
+    #[arg(short, long)]
+    token_path: String,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
@@ -99,7 +314,12 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli {
-        Cli::Register(para) => register(&para.addr, &para.path).await,
+        Cli::Register(para) => register(&para.addr, &para.path, para.token_path.as_deref()).await,
+        Cli::ImportBundle(para) => import_bundle(para).await,
         Cli::Query(para) => query(&para.addr, &para.name).await,
+        Cli::List(para) => list(para).await,
+        Cli::Delete(para) => delete(para).await,
+        Cli::Rollback(para) => rollback(para).await,
+        Cli::Audit(para) => audit(para).await,
     }
 }
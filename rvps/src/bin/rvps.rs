@@ -51,5 +51,5 @@ async fn main() -> Result<()> {
 
     let socket = config.address.parse().context("parse socket addr failed")?;
 
-    server::start(socket, config.into()).await
+    server::start(socket, config).await
 }
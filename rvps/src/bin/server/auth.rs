@@ -0,0 +1,140 @@
+//! Authentication of the providers registering reference values, and of
+//! the admins, by the JWT bearer token in the `authorization` metadata of
+//! the request, signed with their Ed25519 key.
+
+use anyhow::{anyhow, bail, Context, Result};
+use jwt_simple::prelude::{
+    Clock, Duration, Ed25519PublicKey, EdDSAPublicKeyLike, NoCustomClaims, VerificationOptions,
+};
+use reference_value_provider_service::config::Provider;
+use tonic::metadata::MetadataMap;
+
+use super::config::Admin;
+
+/// Longest validity of a token, so that a leaked token is not usable for
+/// long.
+const MAX_TOKEN_VALIDITY_HOURS: u64 = 24;
+
+/// An identity authenticated by its Ed25519 key.
+pub trait Identity: Clone {
+    fn name(&self) -> &str;
+
+    /// Path of the PEM Ed25519 public key of the identity.
+    fn public_key_path(&self) -> &str;
+}
+
+impl Identity for Provider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn public_key_path(&self) -> &str {
+        &self.public_key_path
+    }
+}
+
+impl Identity for Admin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn public_key_path(&self) -> &str {
+        &self.public_key_path
+    }
+}
+
+pub struct Authenticator<T> {
+    identities: Vec<(T, Ed25519PublicKey)>,
+}
+
+impl<T: Identity> Authenticator<T> {
+    pub fn new(identities: &[T]) -> Result<Self> {
+        let identities = identities
+            .iter()
+            .map(|identity| {
+                let public_key = std::fs::read_to_string(identity.public_key_path())
+                    .with_context(|| format!("read public key of {}", identity.name()))?;
+                let public_key = Ed25519PublicKey::from_pem(&public_key)
+                    .with_context(|| format!("parse public key of {}", identity.name()))?;
+                Ok((identity.clone(), public_key))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { identities })
+    }
+
+    /// The identity whose token is in the `metadata` of a request. The token
+    /// must expire, at most [`MAX_TOKEN_VALIDITY_HOURS`] from now.
+    pub fn authenticate(&self, metadata: &MetadataMap) -> Result<&T> {
+        let token = metadata
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| anyhow!("no bearer token"))?;
+
+        let max_validity = Duration::from_hours(MAX_TOKEN_VALIDITY_HOURS);
+        let options = VerificationOptions {
+            max_validity: Some(max_validity),
+            ..Default::default()
+        };
+        let (identity, claims) = self
+            .identities
+            .iter()
+            .find_map(|(identity, public_key)| {
+                public_key
+                    .verify_token::<NoCustomClaims>(token, Some(options.clone()))
+                    .ok()
+                    .map(|claims| (identity, claims))
+            })
+            .ok_or_else(|| anyhow!("token of no known identity"))?;
+
+        let expires_at = claims
+            .expires_at
+            .ok_or_else(|| anyhow!("token has no `exp` claim"))?;
+        if expires_at > Clock::now_since_epoch() + max_validity {
+            bail!("token is valid for more than {MAX_TOKEN_VALIDITY_HOURS} hours");
+        }
+
+        Ok(identity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jwt_simple::prelude::{
+        Claims, Duration, Ed25519KeyPair, EdDSAKeyPairLike, JWTClaims, NoCustomClaims,
+    };
+    use rstest::rstest;
+    use tonic::metadata::MetadataMap;
+
+    use super::Authenticator;
+    use crate::server::config::Admin;
+
+    #[rstest]
+    #[case(Some(Duration::from_mins(5)), true)]
+    #[case(Some(Duration::from_hours(48)), false)]
+    #[case(None, false)]
+    fn authenticate(#[case] valid_for: Option<Duration>, #[case] accepted: bool) {
+        let key_pair = Ed25519KeyPair::generate();
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(key_file.path(), key_pair.public_key().to_pem()).unwrap();
+        let authenticator = Authenticator::new(&[Admin {
+            name: "admin".into(),
+            public_key_path: key_file.path().to_string_lossy().to_string(),
+        }])
+        .unwrap();
+
+        let claims: JWTClaims<NoCustomClaims> = match valid_for {
+            Some(valid_for) => Claims::create(valid_for),
+            None => JWTClaims {
+                expires_at: None,
+                ..Claims::create(Duration::from_mins(5))
+            },
+        };
+        let token = key_pair.sign(claims).unwrap();
+        let mut metadata = MetadataMap::new();
+        metadata.insert("authorization", format!("Bearer {token}").parse().unwrap());
+
+        assert_eq!(authenticator.authenticate(&metadata).is_ok(), accepted);
+    }
+}
@@ -1,5 +1,11 @@
 use anyhow::{Context, Result};
-use reference_value_provider_service::{config::DEFAULT_STORAGE_TYPE, Config as CrateConfig};
+use reference_value_provider_service::{
+    config::{
+        BundlePublisher, FederationConfig, Provider, SigstoreConfig, TrustedBuilder,
+        DEFAULT_GC_INTERVAL_SECS, DEFAULT_STORAGE_TYPE,
+    },
+    Config as CrateConfig,
+};
 use serde::Deserialize;
 use serde_json::{json, Value};
 
@@ -10,6 +16,45 @@ pub struct Config {
     pub address: String,
     pub store_type: String,
     pub store_config: Value,
+
+    #[serde(default)]
+    pub trusted_builders: Vec<TrustedBuilder>,
+
+    #[serde(default)]
+    pub bundle_publishers: Vec<BundlePublisher>,
+
+    #[serde(default)]
+    pub sigstore: Option<SigstoreConfig>,
+
+    #[serde(default = "default_gc_interval_secs")]
+    pub gc_interval_secs: u64,
+
+    #[serde(default)]
+    pub federation: Option<FederationConfig>,
+
+    /// Providers allowed to register reference values. If empty, any client
+    /// can register them.
+    #[serde(default)]
+    pub providers: Vec<Provider>,
+
+    /// Admins allowed to delete and roll back reference values. If empty,
+    /// nobody can.
+    #[serde(default)]
+    pub admins: Vec<Admin>,
+}
+
+/// An admin of the reference values, e.g. an incident responder.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Admin {
+    /// Name of the admin, logged in the audit log.
+    pub name: String,
+
+    /// Path of the PEM Ed25519 public key verifying the tokens of the admin.
+    pub public_key_path: String,
+}
+
+fn default_gc_interval_secs() -> u64 {
+    DEFAULT_GC_INTERVAL_SECS
 }
 
 impl From<Config> for CrateConfig {
@@ -17,6 +62,11 @@ impl From<Config> for CrateConfig {
         CrateConfig {
             store_type: val.store_type,
             store_config: val.store_config,
+            trusted_builders: val.trusted_builders,
+            bundle_publishers: val.bundle_publishers,
+            sigstore: val.sigstore,
+            gc_interval_secs: val.gc_interval_secs,
+            federation: val.federation,
         }
     }
 }
@@ -26,6 +76,13 @@ impl Default for Config {
         Self {
             store_type: DEFAULT_STORAGE_TYPE.to_string(),
             store_config: json!({}),
+            trusted_builders: Vec::new(),
+            bundle_publishers: Vec::new(),
+            sigstore: None,
+            gc_interval_secs: DEFAULT_GC_INTERVAL_SECS,
+            federation: None,
+            providers: Vec::new(),
+            admins: Vec::new(),
             address: DEFAULT_ADDR.to_string(),
         }
     }
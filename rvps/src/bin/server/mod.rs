@@ -1,9 +1,13 @@
 use anyhow::{Context, Result};
-use log::{debug, info};
-use reference_value_provider_service::{Config, Core};
+use auth::Authenticator;
+use log::{debug, info, warn};
+use reference_value_provider_service::{
+    audit::AuditRecord, config::Provider, query::ReferenceValueQuery, Core,
+};
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast::error::RecvError, mpsc, Mutex};
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::transport::Server;
 use tonic::{Request, Response, Status};
 
@@ -11,24 +15,69 @@ use crate::rvps_api::reference_value_provider_service_server::{
     ReferenceValueProviderService, ReferenceValueProviderServiceServer,
 };
 use crate::rvps_api::{
+    AuditLogRequest, AuditLogResponse, ReferenceValueChange, ReferenceValueDeleteRequest,
+    ReferenceValueDeleteResponse, ReferenceValueListRequest, ReferenceValueListResponse,
     ReferenceValueQueryRequest, ReferenceValueQueryResponse, ReferenceValueRegisterRequest,
-    ReferenceValueRegisterResponse,
+    ReferenceValueRegisterResponse, ReferenceValueRollbackRequest, ReferenceValueRollbackResponse,
+    WatchReferenceValuesRequest,
 };
 
+use self::config::Admin;
+
+mod auth;
 pub mod config;
 
+/// Number of changes buffered for a subscriber.
+const WATCH_BUFFER: usize = 64;
+
+fn to_change(record: &AuditRecord) -> ReferenceValueChange {
+    ReferenceValueChange {
+        id: record.id,
+        name: record.name.clone(),
+        action: record.action.as_ref().to_string(),
+        timestamp: record.timestamp.timestamp(),
+    }
+}
+
 pub struct RVPSServer {
     rvps: Arc<Mutex<Core>>,
+
+    /// Authenticator of the providers, if the registration is restricted
+    /// to them.
+    authenticator: Option<Authenticator<Provider>>,
+
+    /// Authenticator of the admins.
+    admins: Authenticator<Admin>,
 }
 
 impl RVPSServer {
-    pub fn new(rvps: Arc<Mutex<Core>>) -> Self {
-        Self { rvps }
+    pub fn new(
+        rvps: Arc<Mutex<Core>>,
+        authenticator: Option<Authenticator<Provider>>,
+        admins: Authenticator<Admin>,
+    ) -> Self {
+        Self {
+            rvps,
+            authenticator,
+            admins,
+        }
+    }
+
+    /// The name of the admin making the `request`.
+    #[allow(clippy::result_large_err)]
+    fn authenticate_admin<T>(&self, request: &Request<T>) -> Result<String, Status> {
+        let admin = self
+            .admins
+            .authenticate(request.metadata())
+            .map_err(|e| Status::unauthenticated(format!("Authenticate admin: {e}")))?;
+        Ok(admin.name.clone())
     }
 }
 
 #[tonic::async_trait]
 impl ReferenceValueProviderService for RVPSServer {
+    type WatchReferenceValuesStream = ReceiverStream<Result<ReferenceValueChange, Status>>;
+
     async fn query_reference_value(
         &self,
         request: Request<ReferenceValueQueryRequest>,
@@ -37,21 +86,23 @@ impl ReferenceValueProviderService for RVPSServer {
 
         info!("query {}", request.name);
 
-        let rvs = self
+        let digests = self
             .rvps
             .lock()
             .await
             .get_digests(&request.name)
             .await
             .map_err(|e| Status::aborted(format!("Query reference value: {e}")))?
-            .map(|rvs| rvs.hash_values)
             .unwrap_or_default();
-        let reference_value_results = serde_json::to_string(&rvs)
+        let reference_value_results = serde_json::to_string(&digests.hash_values)
             .map_err(|e| Status::aborted(format!("Serde reference value: {e}")))?;
         info!("Reference values: {}", reference_value_results);
+        let advisories = serde_json::to_string(&digests.advisories)
+            .map_err(|e| Status::aborted(format!("Serde advisories: {e}")))?;
 
         let res = ReferenceValueQueryResponse {
             reference_value_results,
+            advisories,
         };
         Ok(Response::new(res))
     }
@@ -60,26 +111,197 @@ impl ReferenceValueProviderService for RVPSServer {
         &self,
         request: Request<ReferenceValueRegisterRequest>,
     ) -> Result<Response<ReferenceValueRegisterResponse>, Status> {
+        let provider = match &self.authenticator {
+            Some(authenticator) => Some(
+                authenticator
+                    .authenticate(request.metadata())
+                    .map_err(|e| Status::unauthenticated(format!("Authenticate provider: {e}")))?,
+            ),
+            None => None,
+        };
         let request = request.into_inner();
 
         debug!("registry reference value: {}", request.message);
 
-        self.rvps
+        let mut rvps = self.rvps.lock().await;
+        match provider {
+            Some(provider) => {
+                info!("register reference value of provider {}", provider.name);
+                rvps.verify_and_extract_as(&request.message, provider).await
+            }
+            None => rvps.verify_and_extract(&request.message).await,
+        }
+        .map_err(|e| Status::aborted(format!("Register reference value: {e}")))?;
+
+        let res = ReferenceValueRegisterResponse {};
+        Ok(Response::new(res))
+    }
+
+    async fn list_reference_values(
+        &self,
+        request: Request<ReferenceValueListRequest>,
+    ) -> Result<Response<ReferenceValueListResponse>, Status> {
+        let request = request.into_inner();
+
+        debug!("list reference values: {request:?}");
+
+        let query = ReferenceValueQuery {
+            name_prefix: (!request.name_prefix.is_empty()).then_some(request.name_prefix),
+            provenance: (!request.provenance.is_empty()).then_some(request.provenance),
+            since: (request.since != 0).then_some(request.since),
+            until: (request.until != 0).then_some(request.until),
+            page_size: (request.page_size != 0).then_some(request.page_size as usize),
+            page_token: (!request.page_token.is_empty()).then_some(request.page_token),
+        };
+        let page = self
+            .rvps
             .lock()
             .await
-            .verify_and_extract(&request.message)
+            .query_reference_values(&query)
             .await
-            .map_err(|e| Status::aborted(format!("Register reference value: {e}")))?;
+            .map_err(|e| Status::aborted(format!("List reference values: {e}")))?;
+        let reference_values = serde_json::to_string(&page.reference_values)
+            .map_err(|e| Status::internal(format!("Serde reference values: {e}")))?;
 
-        let res = ReferenceValueRegisterResponse {};
+        let res = ReferenceValueListResponse {
+            reference_values,
+            next_page_token: page.next_page_token.unwrap_or_default(),
+        };
+        Ok(Response::new(res))
+    }
+
+    async fn watch_reference_values(
+        &self,
+        request: Request<WatchReferenceValuesRequest>,
+    ) -> Result<Response<Self::WatchReferenceValuesStream>, Status> {
+        let since = request.into_inner().since;
+
+        info!("watch reference values since change {since}");
+
+        let (records, mut changes) = self
+            .rvps
+            .lock()
+            .await
+            .watch(since)
+            .await
+            .map_err(|e| Status::aborted(format!("Watch reference values: {e}")))?;
+
+        let (tx, rx) = mpsc::channel(WATCH_BUFFER);
+        tokio::spawn(async move {
+            let mut last = since;
+            for record in records {
+                last = record.id;
+                if tx.send(Ok(to_change(&record))).await.is_err() {
+                    return;
+                }
+            }
+
+            loop {
+                let status = match changes.recv().await {
+                    // Replayed already.
+                    Ok(record) if record.id <= last => continue,
+                    Ok(record) => {
+                        last = record.id;
+                        if tx.send(Ok(to_change(&record))).await.is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                    Err(RecvError::Lagged(missed)) => Status::data_loss(format!(
+                        "{missed} changes are missed, resume since change {last}"
+                    )),
+                    Err(RecvError::Closed) => Status::unavailable("RVPS is shut down"),
+                };
+                let _ = tx.send(Err(status)).await;
+                return;
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn delete_reference_value(
+        &self,
+        request: Request<ReferenceValueDeleteRequest>,
+    ) -> Result<Response<ReferenceValueDeleteResponse>, Status> {
+        let admin = self.authenticate_admin(&request)?;
+        let request = request.into_inner();
+
+        info!("{admin} deletes reference value {}", request.name);
+
+        let deleted = self
+            .rvps
+            .lock()
+            .await
+            .delete(&request.name, &admin)
+            .await
+            .map_err(|e| Status::aborted(format!("Delete reference value: {e}")))?
+            .is_some();
+
+        let res = ReferenceValueDeleteResponse { deleted };
+        Ok(Response::new(res))
+    }
+
+    async fn rollback_reference_values(
+        &self,
+        request: Request<ReferenceValueRollbackRequest>,
+    ) -> Result<Response<ReferenceValueRollbackResponse>, Status> {
+        let admin = self.authenticate_admin(&request)?;
+        let request = request.into_inner();
+
+        info!(
+            "{admin} rolls reference values back to audit record {}",
+            request.audit_id
+        );
+
+        let names = self
+            .rvps
+            .lock()
+            .await
+            .rollback(request.audit_id, &admin)
+            .await
+            .map_err(|e| Status::aborted(format!("Roll back reference values: {e}")))?;
+
+        let res = ReferenceValueRollbackResponse { names };
+        Ok(Response::new(res))
+    }
+
+    async fn get_audit_log(
+        &self,
+        request: Request<AuditLogRequest>,
+    ) -> Result<Response<AuditLogResponse>, Status> {
+        self.authenticate_admin(&request)?;
+        let request = request.into_inner();
+
+        let records = self
+            .rvps
+            .lock()
+            .await
+            .audit_log(request.since)
+            .await
+            .map_err(|e| Status::aborted(format!("Get audit log: {e}")))?;
+        let records = serde_json::to_string(&records)
+            .map_err(|e| Status::internal(format!("Serde audit records: {e}")))?;
+
+        let res = AuditLogResponse { records };
         Ok(Response::new(res))
     }
 }
 
-pub async fn start(socket: SocketAddr, config: Config) -> Result<()> {
-    let service = Core::new(config)?;
+pub async fn start(socket: SocketAddr, config: config::Config) -> Result<()> {
+    let authenticator = match config.providers.is_empty() {
+        true => {
+            warn!("No provider is configured, any client can register reference values.");
+            None
+        }
+        false => Some(Authenticator::new(&config.providers).context("load providers")?),
+    };
+
+    let admins = Authenticator::new(&config.admins).context("load admins")?;
+
+    let service = Core::new(config.into()).await?;
     let inner = Arc::new(Mutex::new(service));
-    let rvps_server = RVPSServer::new(inner.clone());
+    let rvps_server = RVPSServer::new(inner.clone(), authenticator, admins);
 
     Server::builder()
         .add_service(ReferenceValueProviderServiceServer::new(rvps_server))
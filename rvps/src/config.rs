@@ -12,6 +12,175 @@ pub const DEFAULT_STORAGE_TYPE: &str = "LocalFs";
 pub struct Config {
     pub store_type: String,
     pub store_config: Value,
+
+    /// Builders whose SLSA provenance is trusted.
+    #[serde(default)]
+    pub trusted_builders: Vec<TrustedBuilder>,
+
+    /// Publishers whose reference value bundles are trusted.
+    #[serde(default)]
+    pub bundle_publishers: Vec<BundlePublisher>,
+
+    /// If set, the messages are required to be signed with Sigstore.
+    #[serde(default)]
+    pub sigstore: Option<SigstoreConfig>,
+
+    /// Interval of the garbage collection of the expired and superseded
+    /// reference values, in seconds. `0` disables it.
+    #[serde(default = "default_gc_interval_secs")]
+    pub gc_interval_secs: u64,
+
+    /// If set, the reference values are pulled from upstream RVPS.
+    #[serde(default)]
+    pub federation: Option<FederationConfig>,
+}
+
+pub const DEFAULT_GC_INTERVAL_SECS: u64 = 3600;
+
+fn default_gc_interval_secs() -> u64 {
+    DEFAULT_GC_INTERVAL_SECS
+}
+
+/// Trust policy of the Sigstore signatures of the messages.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct SigstoreConfig {
+    /// Path of the PEM certificates of the Fulcio CA.
+    pub fulcio_certs_path: String,
+
+    /// Path of the PEM public key of the Rekor log.
+    pub rekor_public_key_path: String,
+
+    /// Identities trusted to sign the messages.
+    pub identities: Vec<SigstoreIdentity>,
+}
+
+/// An identity of a Fulcio certificate, e.g. the CI workflow signing the
+/// messages.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct SigstoreIdentity {
+    /// OIDC issuer of the identity, e.g.
+    /// `https://token.actions.githubusercontent.com`.
+    pub issuer: String,
+
+    /// Email or URI of the identity.
+    pub subject: String,
+}
+
+/// A builder of artifacts, e.g. a CI workflow, signing the SLSA provenance
+/// of the artifacts it builds.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct TrustedBuilder {
+    /// Id of the builder in the SLSA provenance.
+    pub id: String,
+
+    /// Path of the PEM public key of the builder.
+    pub public_key_path: String,
+}
+
+/// A publisher of reference value bundles, e.g. the project releasing the
+/// guest images, signing the bundle of each release.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct BundlePublisher {
+    /// Name of the publisher in the bundles.
+    pub name: String,
+
+    /// Path of the PEM public key of the publisher.
+    pub public_key_path: String,
+
+    /// Prefixes of the names of the reference values the publisher may
+    /// publish. All of them if empty.
+    #[serde(default)]
+    pub name_prefixes: Vec<String>,
+}
+
+impl BundlePublisher {
+    /// Whether the publisher may publish the reference value `name`.
+    pub fn allows_name(&self, name: &str) -> bool {
+        self.name_prefixes.is_empty()
+            || self
+                .name_prefixes
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+    }
+}
+
+pub const DEFAULT_SYNC_INTERVAL_SECS: u64 = 300;
+
+fn default_sync_interval_secs() -> u64 {
+    DEFAULT_SYNC_INTERVAL_SECS
+}
+
+/// Upstream RVPS the reference values are pulled from, and how they are
+/// merged with the local ones.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct FederationConfig {
+    /// Interval of the pulls, in seconds.
+    #[serde(default = "default_sync_interval_secs")]
+    pub interval_secs: u64,
+
+    /// Priority of the reference values registered locally.
+    #[serde(default)]
+    pub local_priority: i64,
+
+    pub upstreams: Vec<Upstream>,
+}
+
+/// An upstream RVPS, e.g. hosted by a vendor or by the organization.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Upstream {
+    /// Name of the upstream, recorded as the origin of its reference values.
+    pub name: String,
+
+    /// gRPC address of the upstream, e.g. `http://rvps.example.com:50003`.
+    pub address: String,
+
+    /// A reference value of the upstream replaces one of the same name only
+    /// if its priority is higher than the one of the origin of the latter.
+    #[serde(default)]
+    pub priority: i64,
+
+    /// Only the reference values whose name starts with the prefix are
+    /// pulled.
+    #[serde(default)]
+    pub name_prefix: Option<String>,
+}
+
+/// A supply-chain system allowed to register reference values.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Provider {
+    /// Name of the provider, e.g. `kata-ci`.
+    pub name: String,
+
+    /// Path of the PEM Ed25519 public key verifying the tokens of the
+    /// provider.
+    pub public_key_path: String,
+
+    /// Provenance types of the messages the provider may register. All of
+    /// them if empty.
+    #[serde(default)]
+    pub types: Vec<String>,
+
+    /// Prefixes of the names of the reference values the provider may
+    /// register or supersede. All of them if empty.
+    #[serde(default)]
+    pub name_prefixes: Vec<String>,
+}
+
+impl Provider {
+    /// Whether the provider may register messages of provenance type `r#type`.
+    pub fn allows_type(&self, r#type: &str) -> bool {
+        self.types.is_empty() || self.types.iter().any(|t| t == r#type)
+    }
+
+    /// Whether the provider may register or supersede the reference value
+    /// `name`.
+    pub fn allows_name(&self, name: &str) -> bool {
+        self.name_prefixes.is_empty()
+            || self
+                .name_prefixes
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+    }
 }
 
 impl Default for Config {
@@ -19,6 +188,11 @@ impl Default for Config {
         Self {
             store_type: DEFAULT_STORAGE_TYPE.to_string(),
             store_config: json!({}),
+            trusted_builders: Vec::new(),
+            bundle_publishers: Vec::new(),
+            sigstore: None,
+            gc_interval_secs: DEFAULT_GC_INTERVAL_SECS,
+            federation: None,
         }
     }
 }
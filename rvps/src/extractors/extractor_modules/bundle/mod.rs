@@ -0,0 +1,133 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Reference values of the bundles published with the releases of the guest
+//! images. The manifest of the bundle is verified by the bundle pre-processor
+//! ware before it gets here, and its reference values are registered.
+
+use anyhow::*;
+use base64::Engine;
+use chrono::{DateTime, Months, Timelike, Utc};
+use log::info;
+use serde::Deserialize;
+
+use crate::{
+    reference_value::{HashValuePair, REFERENCE_VALUE_VERSION},
+    ReferenceValue,
+};
+
+use super::Extractor;
+
+#[derive(Deserialize)]
+struct Manifest {
+    publisher: String,
+
+    /// Release of the guest images, e.g. `kata-containers-3.5.0`.
+    release: String,
+
+    /// When the reference values of the release expire.
+    #[serde(default)]
+    expired: Option<DateTime<Utc>>,
+
+    reference_values: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    name: String,
+    hash_values: Vec<HashValuePair>,
+}
+
+/// The reference value will be expired in the default time (months), if
+/// the manifest does not tell.
+const DEFAULT_EXPIRED_TIME: u32 = 12;
+
+#[derive(Default)]
+pub struct BundleExtractor;
+
+impl Extractor for BundleExtractor {
+    fn verify_and_extract(&self, provenance_base64: &str) -> Result<Vec<ReferenceValue>> {
+        let manifest = base64::engine::general_purpose::STANDARD
+            .decode(provenance_base64)
+            .context("base64 decode")?;
+        let manifest: Manifest =
+            serde_json::from_slice(&manifest).context("deserialize bundle manifest")?;
+
+        let expired = match manifest.expired {
+            Some(expired) => expired,
+            None => Utc::now()
+                .with_nanosecond(0)
+                .and_then(|t| t.checked_add_months(Months::new(DEFAULT_EXPIRED_TIME)))
+                .ok_or_else(|| anyhow!("Expired time calculated overflowed"))?,
+        };
+
+        let rvs = manifest
+            .reference_values
+            .into_iter()
+            .map(|entry| {
+                if entry.hash_values.is_empty() {
+                    bail!("reference value {} of the bundle has no digest", entry.name);
+                }
+                Ok(ReferenceValue {
+                    version: REFERENCE_VALUE_VERSION.into(),
+                    name: entry.name,
+                    expired,
+                    hash_value: entry.hash_values,
+                    not_before: None,
+                    superseded_by: None,
+                    provenance: None,
+                    registered: None,
+                    origin: None,
+                    advisories: Vec::new(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        info!(
+            "{} reference values of release {} of {} are extracted.",
+            rvs.len(),
+            manifest.release,
+            manifest.publisher
+        );
+
+        Ok(rvs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn extract_bundle_reference_values() {
+        let manifest = json!({
+            "publisher": "confidential-containers",
+            "release": "kata-containers-3.5.0",
+            "expired": "2030-01-01T00:00:00Z",
+            "reference_values": [{
+                "name": "kata-kernel",
+                "hash_values": [
+                    { "alg": "sha384", "value": "ab".repeat(48) },
+                    { "alg": "sha384", "value": "cd".repeat(48) },
+                ],
+            }],
+        });
+        let provenance = base64::engine::general_purpose::STANDARD.encode(manifest.to_string());
+        let rvs = BundleExtractor.verify_and_extract(&provenance).unwrap();
+
+        assert_eq!(rvs.len(), 1);
+        assert_eq!(rvs[0].name(), "kata-kernel");
+        assert_eq!(rvs[0].hash_values().len(), 2);
+        assert_eq!(rvs[0].expired().to_rfc3339(), "2030-01-01T00:00:00+00:00");
+
+        let manifest = json!({
+            "publisher": "confidential-containers",
+            "release": "kata-containers-3.5.0",
+            "reference_values": [{ "name": "kata-kernel", "hash_values": [] }],
+        });
+        let provenance = base64::engine::general_purpose::STANDARD.encode(manifest.to_string());
+        assert!(BundleExtractor.verify_and_extract(&provenance).is_err());
+    }
+}
@@ -0,0 +1,426 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Reference values of the IETF CoRIM (Concise Reference Integrity Manifest)
+//! bundles, as published by the silicon and firmware vendors. The reference
+//! triples of the CoMID tags of the bundle are extracted, see
+//! <https://datatracker.ietf.org/doc/draft-ietf-rats-corim/>.
+
+use std::collections::BTreeMap;
+
+use anyhow::*;
+use base64::Engine;
+use chrono::{DateTime, Months, Timelike, Utc};
+use ciborium::value::Value;
+use log::{debug, warn};
+
+use crate::{
+    reference_value::{HashValuePair, REFERENCE_VALUE_VERSION},
+    ReferenceValue,
+};
+
+use super::Extractor;
+
+/// CBOR tags of the CoRIM bundles.
+const TAG_COSE_SIGN1: u64 = 18;
+const TAG_CORIM: u64 = 501;
+const TAG_COMID: u64 = 506;
+const TAG_MIN_SVN: u64 = 553;
+
+/// The reference value will be expired in the default time (months), if
+/// the CoRIM has no validity.
+const DEFAULT_EXPIRED_TIME: u32 = 12;
+
+#[derive(Default)]
+pub struct CorimExtractor;
+
+impl Extractor for CorimExtractor {
+    fn verify_and_extract(&self, provenance_base64: &str) -> Result<Vec<ReferenceValue>> {
+        let provenance = base64::engine::general_purpose::STANDARD
+            .decode(provenance_base64)
+            .context("base64 decode")?;
+        let corim: Value =
+            ciborium::de::from_reader(provenance.as_slice()).context("deserialize CoRIM")?;
+        let corim = unwrap_corim(corim)?;
+
+        let expired = match field(&corim, 4).map(validity).transpose()? {
+            Some(expired) => expired,
+            None => Utc::now()
+                .with_nanosecond(0)
+                .and_then(|t| t.checked_add_months(Months::new(DEFAULT_EXPIRED_TIME)))
+                .ok_or_else(|| anyhow!("Expired time calculated overflowed"))?,
+        };
+        if expired <= Utc::now() {
+            bail!("CoRIM expired at {expired}");
+        }
+
+        let tags = field(&corim, 1)
+            .and_then(Value::as_array)
+            .ok_or_else(|| anyhow!("CoRIM has no tags"))?;
+        let mut values = BTreeMap::new();
+        for tag in tags {
+            match tag {
+                Value::Tag(TAG_COMID, comid) => {
+                    let comid = comid
+                        .as_bytes()
+                        .ok_or_else(|| anyhow!("CoMID tag is not a byte string"))?;
+                    let comid: Value =
+                        ciborium::de::from_reader(comid.as_slice()).context("deserialize CoMID")?;
+                    extract_comid(&comid, &mut values)?;
+                }
+                Value::Tag(tag, _) => debug!("Skip the CoRIM tag of type {tag}."),
+                _ => bail!("CoRIM tag is not tagged"),
+            }
+        }
+
+        Ok(values
+            .into_iter()
+            .map(|(name, hash_value)| ReferenceValue {
+                version: REFERENCE_VALUE_VERSION.into(),
+                name,
+                expired,
+                hash_value,
+                not_before: None,
+                superseded_by: None,
+                provenance: None,
+                registered: None,
+                origin: None,
+                advisories: Vec::new(),
+            })
+            .collect())
+    }
+}
+
+/// Return the entries of the `corim-map` of a signed or unsigned CoRIM.
+/// The signature of a signed CoRIM is not verified.
+fn unwrap_corim(corim: Value) -> Result<Vec<(Value, Value)>> {
+    match corim {
+        Value::Tag(TAG_COSE_SIGN1, sign1) => {
+            let Value::Array(sign1) = *sign1 else {
+                bail!("COSE_Sign1 of the signed CoRIM is not an array");
+            };
+            let payload = sign1
+                .get(2)
+                .and_then(Value::as_bytes)
+                .ok_or_else(|| anyhow!("signed CoRIM has no payload"))?;
+            warn!("The signature of the signed CoRIM is not verified.");
+            let corim: Value = ciborium::de::from_reader(payload.as_slice())
+                .context("deserialize the payload of the signed CoRIM")?;
+            unwrap_corim(corim)
+        }
+        Value::Tag(TAG_CORIM, corim) => match *corim {
+            Value::Map(corim) => Ok(corim),
+            _ => bail!("CoRIM is not a map"),
+        },
+        Value::Map(corim) => Ok(corim),
+        _ => bail!("not a CoRIM"),
+    }
+}
+
+/// The value of the integer key `key` of a CBOR map.
+fn field(map: &[(Value, Value)], key: i128) -> Option<&Value> {
+    map.iter()
+        .find(|(k, _)| k.as_integer().map(i128::from) == Some(key))
+        .map(|(_, v)| v)
+}
+
+fn as_map(value: &Value) -> Option<&[(Value, Value)]> {
+    value.as_map().map(Vec::as_slice)
+}
+
+/// The `not-after` of the `validity-map` of the CoRIM.
+fn validity(validity: &Value) -> Result<DateTime<Utc>> {
+    let not_after = as_map(validity)
+        .and_then(|validity| field(validity, 1))
+        .ok_or_else(|| anyhow!("CoRIM validity has no not-after"))?;
+
+    // A `time` (tag 1) is an epoch time, a `tdate` (tag 0) a RFC 3339 one.
+    match not_after {
+        Value::Tag(0, date) => {
+            let date = date
+                .as_text()
+                .ok_or_else(|| anyhow!("illegal CoRIM not-after"))?;
+            Ok(DateTime::parse_from_rfc3339(date)?.with_timezone(&Utc))
+        }
+        Value::Tag(_, time) => epoch(time),
+        time => epoch(time),
+    }
+}
+
+fn epoch(time: &Value) -> Result<DateTime<Utc>> {
+    let time = time
+        .as_integer()
+        .and_then(|time| i64::try_from(time).ok())
+        .ok_or_else(|| anyhow!("illegal CoRIM not-after"))?;
+    DateTime::from_timestamp(time, 0).ok_or_else(|| anyhow!("illegal CoRIM not-after"))
+}
+
+/// Add the reference values of the reference triples of a CoMID to `values`.
+fn extract_comid(comid: &Value, values: &mut BTreeMap<String, Vec<HashValuePair>>) -> Result<()> {
+    let comid = as_map(comid).ok_or_else(|| anyhow!("CoMID is not a map"))?;
+    let Some(triples) = field(comid, 4).and_then(as_map) else {
+        bail!("CoMID has no triples");
+    };
+    let Some(reference_triples) = field(triples, 0) else {
+        debug!("Skip the CoMID without reference triples.");
+        return Ok(());
+    };
+
+    let reference_triples = reference_triples
+        .as_array()
+        .ok_or_else(|| anyhow!("illegal CoMID reference triples"))?;
+    for triple in reference_triples {
+        let [environment, measurements] = triple.as_array().map(Vec::as_slice).unwrap_or(&[])
+        else {
+            bail!("illegal CoMID reference triple");
+        };
+        let environment = environment_name(environment);
+        let measurements = measurements
+            .as_array()
+            .ok_or_else(|| anyhow!("illegal CoMID measurements"))?;
+        for measurement in measurements {
+            extract_measurement(environment.as_deref(), measurement, values)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The `<vendor>/<model>` of the class of an environment, if any.
+fn environment_name(environment: &Value) -> Option<String> {
+    let class = as_map(environment)
+        .and_then(|environment| field(environment, 0))
+        .and_then(as_map)?;
+    let name: Vec<_> = [1, 2]
+        .into_iter()
+        .filter_map(|key| field(class, key).and_then(Value::as_text))
+        .collect();
+    (!name.is_empty()).then(|| name.join("/"))
+}
+
+fn extract_measurement(
+    environment: Option<&str>,
+    measurement: &Value,
+    values: &mut BTreeMap<String, Vec<HashValuePair>>,
+) -> Result<()> {
+    let measurement = as_map(measurement).ok_or_else(|| anyhow!("illegal CoMID measurement"))?;
+    let mval = field(measurement, 1)
+        .and_then(as_map)
+        .ok_or_else(|| anyhow!("CoMID measurement has no values"))?;
+
+    // The measurement is named after its key, or its name.
+    let key = match field(measurement, 0) {
+        Some(Value::Text(key)) => Some(key.clone()),
+        Some(Value::Integer(key)) => Some(i128::from(*key).to_string()),
+        _ => field(mval, 11).and_then(Value::as_text).map(String::from),
+    };
+    let name = match (environment, key) {
+        (Some(environment), Some(key)) => format!("{environment}/{key}"),
+        (None, Some(key)) => key,
+        (Some(environment), None) => environment.to_string(),
+        (None, None) => {
+            warn!("Skip the CoMID measurement without name.");
+            return Ok(());
+        }
+    };
+
+    let mut add = |name: String, alg: &str, value: String| {
+        let pair = HashValuePair::new(alg.into(), value);
+        let pairs = values.entry(name).or_default();
+        if !pairs.contains(&pair) {
+            pairs.push(pair);
+        }
+    };
+
+    if let Some(version) = field(mval, 0)
+        .and_then(as_map)
+        .and_then(|version| field(version, 0))
+        .and_then(Value::as_text)
+    {
+        add(format!("{name}.version"), "none", version.to_string());
+    }
+
+    match field(mval, 1) {
+        Some(Value::Tag(TAG_MIN_SVN, _)) => {
+            warn!("Skip the minimum SVN of {name}, only the exact SVNs are supported.")
+        }
+        Some(svn) => {
+            let svn = match svn {
+                Value::Tag(_, svn) => svn.as_ref(),
+                svn => svn,
+            };
+            let svn = svn
+                .as_integer()
+                .map(i128::from)
+                .ok_or_else(|| anyhow!("illegal SVN of {name}"))?;
+            add(format!("{name}.svn"), "none", svn.to_string());
+        }
+        None => {}
+    }
+
+    if let Some(digests) = field(mval, 2) {
+        let digests = digests
+            .as_array()
+            .ok_or_else(|| anyhow!("illegal digests of {name}"))?;
+        for digest in digests {
+            let [alg, value] = digest.as_array().map(Vec::as_slice).unwrap_or(&[]) else {
+                bail!("illegal digest of {name}");
+            };
+            let value = value
+                .as_bytes()
+                .ok_or_else(|| anyhow!("illegal digest of {name}"))?;
+            let Some(alg) = digest_alg(alg) else {
+                warn!("Skip the digest of {name} with unsupported algorithm {alg:?}.");
+                continue;
+            };
+            add(name.clone(), &alg, hex::encode(value));
+        }
+    }
+
+    if let Some(raw_value) = field(mval, 4) {
+        let raw_value = match raw_value {
+            Value::Tag(_, raw_value) => raw_value.as_ref(),
+            raw_value => raw_value,
+        };
+        let raw_value = raw_value
+            .as_bytes()
+            .ok_or_else(|| anyhow!("illegal raw value of {name}"))?;
+        add(format!("{name}.raw_value"), "none", hex::encode(raw_value));
+    }
+
+    Ok(())
+}
+
+/// Name of a hash algorithm of the IANA Named Information registry.
+fn digest_alg(alg: &Value) -> Option<String> {
+    match alg {
+        Value::Text(alg) => Some(alg.replace("sha-", "sha")),
+        Value::Integer(alg) => {
+            let alg = match i128::from(*alg) {
+                1 => "sha256",
+                7 => "sha384",
+                8 => "sha512",
+                10 => "sha3-256",
+                11 => "sha3-384",
+                12 => "sha3-512",
+                _ => return None,
+            };
+            Some(alg.into())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cbor(value: &Value) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(value, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn map(entries: Vec<(i64, Value)>) -> Value {
+        Value::Map(entries.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    fn comid() -> Value {
+        let environment = map(vec![(
+            0,
+            map(vec![(1, "ACME".into()), (2, "RoadRunner".into())]),
+        )]);
+        let firmware = map(vec![
+            (0, "firmware".into()),
+            (
+                1,
+                map(vec![
+                    (0, map(vec![(0, "1.2.3".into())])),
+                    (1, Value::Tag(552, Box::new(3.into()))),
+                    (
+                        2,
+                        Value::Array(vec![
+                            Value::Array(vec![1.into(), Value::Bytes(vec![0xab; 32])]),
+                            Value::Array(vec![7.into(), Value::Bytes(vec![0xcd; 48])]),
+                            Value::Array(vec![99.into(), Value::Bytes(vec![0; 4])]),
+                        ]),
+                    ),
+                ]),
+            ),
+        ]);
+        let bootloader = map(vec![(
+            1,
+            map(vec![
+                (11, "bootloader".into()),
+                (1, Value::Tag(TAG_MIN_SVN, Box::new(2.into()))),
+            ]),
+        )]);
+        let triple = Value::Array(vec![environment, Value::Array(vec![firmware, bootloader])]);
+        map(vec![
+            (1, map(vec![(0, "comid".into())])),
+            (4, map(vec![(0, Value::Array(vec![triple]))])),
+        ])
+    }
+
+    fn corim(validity: Option<i64>) -> Value {
+        let mut corim = vec![
+            (0, "corim".into()),
+            (
+                1,
+                Value::Array(vec![Value::Tag(
+                    TAG_COMID,
+                    Box::new(Value::Bytes(cbor(&comid()))),
+                )]),
+            ),
+        ];
+        if let Some(not_after) = validity {
+            corim.push((4, map(vec![(1, Value::Tag(1, Box::new(not_after.into())))])));
+        }
+        Value::Tag(TAG_CORIM, Box::new(map(corim)))
+    }
+
+    fn extract(corim: &Value) -> Result<Vec<ReferenceValue>> {
+        let provenance = base64::engine::general_purpose::STANDARD.encode(cbor(corim));
+        CorimExtractor.verify_and_extract(&provenance)
+    }
+
+    #[test]
+    fn extract_corim_reference_values() {
+        let rvs = extract(&corim(None)).unwrap();
+        let names: Vec<_> = rvs.iter().map(|rv| rv.name().as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "ACME/RoadRunner/firmware",
+                "ACME/RoadRunner/firmware.svn",
+                "ACME/RoadRunner/firmware.version",
+            ]
+        );
+        assert_eq!(
+            rvs[0].hash_values(),
+            &vec![
+                HashValuePair::new("sha256".into(), "ab".repeat(32)),
+                HashValuePair::new("sha384".into(), "cd".repeat(48)),
+            ]
+        );
+        assert_eq!(*rvs[1].hash_values()[0].value(), "3");
+        assert_eq!(*rvs[2].hash_values()[0].value(), "1.2.3");
+
+        let not_after = Utc::now().timestamp() + 3600;
+        let rvs = extract(&corim(Some(not_after))).unwrap();
+        assert_eq!(rvs[0].expired().timestamp(), not_after);
+        extract(&corim(Some(not_after - 7200))).unwrap_err();
+
+        // Signed CoRIM.
+        let sign1 = Value::Array(vec![
+            Value::Bytes(vec![]),
+            Value::Map(vec![]),
+            Value::Bytes(cbor(&corim(None))),
+            Value::Bytes(vec![0; 64]),
+        ]);
+        let rvs = extract(&Value::Tag(TAG_COSE_SIGN1, Box::new(sign1))).unwrap();
+        assert_eq!(rvs.len(), 3);
+    }
+}
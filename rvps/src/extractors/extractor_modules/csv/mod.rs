@@ -0,0 +1,155 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Reference values of Hygon CSV guests, e.g. the launch measurement
+//! computed by the CSV guest image tooling. They are named after the claims
+//! of the CSV verifier and encoded like them, so that the default policy
+//! matches them directly.
+
+use anyhow::*;
+use base64::Engine;
+use chrono::{Months, Timelike, Utc};
+use serde::Deserialize;
+
+use crate::{
+    reference_value::{HashValuePair, REFERENCE_VALUE_VERSION},
+    ReferenceValue,
+};
+
+use super::Extractor;
+
+/// Hex encoded values of a CSV guest. Each field can hold several accepted
+/// values, and be omitted.
+#[derive(Deserialize)]
+pub struct Provenance {
+    /// SM3 launch measurement.
+    #[serde(default)]
+    measurement: Vec<String>,
+
+    /// SM3 digest of the public key of the guest owner.
+    #[serde(default)]
+    user_pubkey_digest: Vec<String>,
+
+    #[serde(default)]
+    vm_id: Vec<String>,
+
+    #[serde(default)]
+    vm_version: Vec<String>,
+
+    /// Months after which the reference values expire.
+    #[serde(default = "default_expired_months")]
+    expired_months: u32,
+}
+
+/// Claims of the CSV verifier, with the length and the hash algorithm of
+/// their value.
+const CSV_CLAIMS: &[(&str, usize, &str)] = &[
+    ("csv.measurement", 32, "sm3"),
+    ("csv.user_pubkey_digest", 32, "sm3"),
+    ("csv.vm_id", 16, "none"),
+    ("csv.vm_version", 16, "none"),
+];
+
+/// The reference value will be expired in the default time (months)
+const DEFAULT_EXPIRED_TIME: u32 = 12;
+
+fn default_expired_months() -> u32 {
+    DEFAULT_EXPIRED_TIME
+}
+
+#[derive(Default)]
+pub struct CsvExtractor;
+
+impl Extractor for CsvExtractor {
+    fn verify_and_extract(&self, provenance_base64: &str) -> Result<Vec<ReferenceValue>> {
+        let provenance = base64::engine::general_purpose::STANDARD
+            .decode(provenance_base64)
+            .context("base64 decode")?;
+        let payload: Provenance =
+            serde_json::from_slice(&provenance).context("deseralize CSV provenance")?;
+
+        let expired = Utc::now()
+            .with_nanosecond(0)
+            .and_then(|t| t.checked_add_months(Months::new(payload.expired_months)))
+            .ok_or_else(|| anyhow!("Expired time calculated overflowed"))?;
+
+        let values = [
+            &payload.measurement,
+            &payload.user_pubkey_digest,
+            &payload.vm_id,
+            &payload.vm_version,
+        ];
+        let mut res = Vec::new();
+        for ((name, len, alg), values) in CSV_CLAIMS.iter().zip(values) {
+            if values.is_empty() {
+                continue;
+            }
+
+            let hash_value = values
+                .iter()
+                .map(|value| {
+                    let value = hex::decode(value.trim_start_matches("0x"))
+                        .with_context(|| format!("{name} is not hex encoded"))?;
+                    if value.len() != *len {
+                        bail!("{name} is {} bytes long, {len} expected", value.len());
+                    }
+
+                    let value = base64::engine::general_purpose::STANDARD.encode(value);
+                    Ok(HashValuePair::new(alg.to_string(), value))
+                })
+                .collect::<Result<_>>()?;
+            res.push(ReferenceValue {
+                version: REFERENCE_VALUE_VERSION.into(),
+                name: name.to_string(),
+                expired,
+                hash_value,
+                not_before: None,
+                superseded_by: None,
+                provenance: None,
+                registered: None,
+                origin: None,
+                advisories: Vec::new(),
+            });
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn extract_csv_reference_values() {
+        let provenance = json!({
+            "measurement": [
+                "0x0b2ff1fe6b9e8a1b4e6ac5d2a0fd4bb0f4fcb6ddc1fbd8e3a2b7e7bfe2da1c63",
+                "ad7c0a6d93fa8c5a5f3e84c7b3b1dbd1e8b9d5d70e6c2af1c49e8d8e4d8d4f21"
+            ],
+            "vm_id": ["00112233445566778899aabbccddeeff"]
+        });
+        let provenance = base64::engine::general_purpose::STANDARD.encode(provenance.to_string());
+        let rvs = CsvExtractor.verify_and_extract(&provenance).unwrap();
+
+        assert_eq!(rvs.len(), 2);
+        assert_eq!(rvs[0].name(), "csv.measurement");
+        assert_eq!(
+            rvs[0].hash_values()[0],
+            HashValuePair::new(
+                "sm3".into(),
+                "Cy/x/mueihtOasXSoP1LsPT8tt3B+9jjorfnv+LaHGM=".into()
+            )
+        );
+        assert_eq!(rvs[0].hash_values().len(), 2);
+        assert_eq!(rvs[1].name(), "csv.vm_id");
+        assert_eq!(*rvs[1].hash_values()[0].value(), "ABEiM0RVZneImaq7zN3u/w==");
+
+        let provenance = json!({ "measurement": ["0011"] });
+        let provenance = base64::engine::general_purpose::STANDARD.encode(provenance.to_string());
+        CsvExtractor.verify_and_extract(&provenance).unwrap_err();
+    }
+}
@@ -13,6 +13,19 @@ use crate::ReferenceValue;
 #[cfg(feature = "in-toto")]
 pub mod in_toto;
 
+#[cfg(feature = "bundle")]
+pub mod bundle;
+
+#[cfg(feature = "corim")]
+pub mod corim;
+
+#[cfg(feature = "slsa")]
+pub mod slsa;
+
+#[cfg(feature = "swid")]
+pub mod swid;
+
+pub mod csv;
 pub mod sample;
 
 /// Extractor is a standard interface that all provenance extractors
@@ -41,6 +54,40 @@ impl Default for ExtractorModuleList {
             mod_list.insert("sample".to_string(), instantiate_func);
         }
 
+        {
+            let instantiate_func: ExtractorInstantiateFunc =
+                Box::new(|| -> ExtractorInstance { Box::<csv::CsvExtractor>::default() });
+            mod_list.insert("csv".to_string(), instantiate_func);
+        }
+
+        #[cfg(feature = "bundle")]
+        {
+            let instantiate_func: ExtractorInstantiateFunc =
+                Box::new(|| -> ExtractorInstance { Box::<bundle::BundleExtractor>::default() });
+            mod_list.insert("bundle".to_string(), instantiate_func);
+        }
+
+        #[cfg(feature = "corim")]
+        {
+            let instantiate_func: ExtractorInstantiateFunc =
+                Box::new(|| -> ExtractorInstance { Box::<corim::CorimExtractor>::default() });
+            mod_list.insert("corim".to_string(), instantiate_func);
+        }
+
+        #[cfg(feature = "slsa")]
+        {
+            let instantiate_func: ExtractorInstantiateFunc =
+                Box::new(|| -> ExtractorInstance { Box::<slsa::SlsaExtractor>::default() });
+            mod_list.insert("slsa".to_string(), instantiate_func);
+        }
+
+        #[cfg(feature = "swid")]
+        {
+            let instantiate_func: ExtractorInstantiateFunc =
+                Box::new(|| -> ExtractorInstance { Box::<swid::SwidExtractor>::default() });
+            mod_list.insert("swid".to_string(), instantiate_func);
+        }
+
         #[cfg(feature = "in-toto")]
         {
             let instantiate_func: ExtractorInstantiateFunc =
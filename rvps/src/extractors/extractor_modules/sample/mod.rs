@@ -61,6 +61,12 @@ impl Extractor for SampleExtractor {
                         name: name.to_string(),
                         expired,
                         hash_value: rvs,
+                        not_before: None,
+                        superseded_by: None,
+                        provenance: None,
+                        registered: None,
+                        origin: None,
+                        advisories: Vec::new(),
                     }),
                     None => {
                         warn!("Expired time calculated overflowed for reference value of {name}.");
@@ -0,0 +1,115 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Reference values of the SLSA provenance. The in-toto statement is
+//! verified by the SLSA pre-processor ware before it gets here, and the
+//! digests of its subjects, i.e. the artifacts built in CI, are registered.
+
+use anyhow::*;
+use base64::Engine;
+use chrono::{Months, Timelike, Utc};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::{
+    reference_value::{HashValuePair, REFERENCE_VALUE_VERSION},
+    ReferenceValue,
+};
+
+use super::Extractor;
+
+#[derive(Deserialize)]
+struct Statement {
+    subject: Vec<Subject>,
+}
+
+#[derive(Deserialize)]
+struct Subject {
+    name: String,
+
+    /// Hex digests of the artifact, by algorithm.
+    digest: BTreeMap<String, String>,
+}
+
+/// The reference value will be expired in the default time (months)
+const DEFAULT_EXPIRED_TIME: u32 = 12;
+
+#[derive(Default)]
+pub struct SlsaExtractor;
+
+impl Extractor for SlsaExtractor {
+    fn verify_and_extract(&self, provenance_base64: &str) -> Result<Vec<ReferenceValue>> {
+        let statement = base64::engine::general_purpose::STANDARD
+            .decode(provenance_base64)
+            .context("base64 decode")?;
+        let statement: Statement =
+            serde_json::from_slice(&statement).context("deserialize in-toto statement")?;
+
+        let expired = Utc::now()
+            .with_nanosecond(0)
+            .and_then(|t| t.checked_add_months(Months::new(DEFAULT_EXPIRED_TIME)))
+            .ok_or_else(|| anyhow!("Expired time calculated overflowed"))?;
+
+        statement
+            .subject
+            .into_iter()
+            .map(|subject| {
+                let hash_value = subject
+                    .digest
+                    .into_iter()
+                    .map(|(alg, value)| {
+                        hex::decode(&value).with_context(|| {
+                            format!("{alg} digest of {} is not hex", subject.name)
+                        })?;
+                        Ok(HashValuePair::new(alg, value.to_lowercase()))
+                    })
+                    .collect::<Result<_>>()?;
+                Ok(ReferenceValue {
+                    version: REFERENCE_VALUE_VERSION.into(),
+                    name: subject.name,
+                    expired,
+                    hash_value,
+                    not_before: None,
+                    superseded_by: None,
+                    provenance: None,
+                    registered: None,
+                    origin: None,
+                    advisories: Vec::new(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn extract_slsa_reference_values() {
+        let statement = json!({
+            "_type": "https://in-toto.io/Statement/v1",
+            "subject": [{
+                "name": "kata-containers-initrd.img",
+                "digest": { "sha256": "AB".repeat(32), "sha512": "cd".repeat(64) },
+            }],
+            "predicateType": "https://slsa.dev/provenance/v1",
+            "predicate": {},
+        });
+        let provenance = base64::engine::general_purpose::STANDARD.encode(statement.to_string());
+        let rvs = SlsaExtractor.verify_and_extract(&provenance).unwrap();
+
+        assert_eq!(rvs.len(), 1);
+        assert_eq!(rvs[0].name(), "kata-containers-initrd.img");
+        assert_eq!(
+            rvs[0].hash_values(),
+            &vec![
+                HashValuePair::new("sha256".into(), "ab".repeat(32)),
+                HashValuePair::new("sha512".into(), "cd".repeat(64)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Reference values of the SWID tags (ISO/IEC 19770-2), and of the TCG
+//! Reference Integrity Manifests (RIMs) based on them, as shipped by the
+//! OEMs with the platform firmware. The digests of the files and of the
+//! measurements of the payload of the tag are extracted, see
+//! <https://trustedcomputinggroup.org/resource/tcg-reference-integrity-manifest-rim-information-model/>.
+
+use std::collections::BTreeMap;
+
+use anyhow::*;
+use base64::Engine;
+use chrono::{Months, Timelike, Utc};
+use log::{debug, warn};
+use quick_xml::{
+    events::{BytesStart, Event},
+    name::ResolveResult,
+    NsReader,
+};
+
+use crate::{
+    reference_value::{HashValuePair, REFERENCE_VALUE_VERSION},
+    ReferenceValue,
+};
+
+use super::Extractor;
+
+/// The reference value will be expired in the default time (months), as
+/// the SWID tags have no validity.
+const DEFAULT_EXPIRED_TIME: u32 = 12;
+
+/// Namespaces of the hash attributes, by hash algorithm.
+const HASH_NAMESPACES: &[(&str, &str)] = &[
+    ("http://www.w3.org/2001/04/xmlenc#sha256", "sha256"),
+    ("http://www.w3.org/2001/04/xmldsig-more#sha384", "sha384"),
+    ("http://www.w3.org/2001/04/xmlenc#sha512", "sha512"),
+];
+
+#[derive(Default)]
+pub struct SwidExtractor;
+
+impl Extractor for SwidExtractor {
+    fn verify_and_extract(&self, provenance_base64: &str) -> Result<Vec<ReferenceValue>> {
+        let provenance = base64::engine::general_purpose::STANDARD
+            .decode(provenance_base64)
+            .context("base64 decode")?;
+        let tag = String::from_utf8(provenance).context("SWID tag is not UTF-8")?;
+        let values = parse(&tag)?;
+
+        let expired = Utc::now()
+            .with_nanosecond(0)
+            .and_then(|t| t.checked_add_months(Months::new(DEFAULT_EXPIRED_TIME)))
+            .ok_or_else(|| anyhow!("Expired time calculated overflowed"))?;
+
+        Ok(values
+            .into_iter()
+            .map(|(name, hash_value)| ReferenceValue {
+                version: REFERENCE_VALUE_VERSION.into(),
+                name,
+                expired,
+                hash_value,
+                not_before: None,
+                superseded_by: None,
+                provenance: None,
+                registered: None,
+                origin: None,
+                advisories: Vec::new(),
+            })
+            .collect())
+    }
+}
+
+/// A digest of the payload, named after its path in the payload.
+struct Digest {
+    path: String,
+    hash_values: Vec<HashValuePair>,
+}
+
+/// Parse the SWID tag and return its reference values by name.
+fn parse(tag: &str) -> Result<BTreeMap<String, Vec<HashValuePair>>> {
+    let mut reader = NsReader::from_str(tag);
+    let mut name = None;
+    let mut version = None;
+    let mut platform = None;
+    let mut signed = false;
+    let mut in_payload = false;
+    let mut directories: Vec<String> = Vec::new();
+    let mut digests = Vec::new();
+
+    loop {
+        let (event, start) = match reader.read_event().context("illegal SWID tag")? {
+            Event::Start(element) => (element, true),
+            Event::Empty(element) => (element, false),
+            Event::End(element) => {
+                match element.local_name().as_ref() {
+                    b"Payload" => in_payload = false,
+                    b"Directory" if in_payload => {
+                        directories.pop();
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        let attributes = attributes(&reader, &event)?;
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|attribute| attribute.name == name)
+                .map(|attribute| attribute.value.clone())
+        };
+        match event.local_name().as_ref() {
+            b"SoftwareIdentity" => {
+                name = attribute("name");
+                version = attribute("version");
+            }
+            // The platform of a TCG RIM, given by the attributes of its Meta.
+            b"Meta" => {
+                if let (Some(manufacturer), Some(model)) = (
+                    attribute("platformManufacturerStr"),
+                    attribute("platformModel"),
+                ) {
+                    platform = Some(format!("{manufacturer}/{model}"));
+                }
+            }
+            b"Signature" => signed = true,
+            b"Payload" => in_payload = start,
+            b"Directory" if in_payload && start => {
+                directories.push(attribute("name").unwrap_or_default());
+            }
+            b"File" if in_payload => {
+                let Some(file) = attribute("name") else {
+                    warn!("Skip the SWID file without name.");
+                    continue;
+                };
+                digests.push(Digest {
+                    path: path(&directories, &file),
+                    hash_values: hash_values(&attributes),
+                });
+            }
+            // The measurements of a TCG component RIM, e.g. of a firmware.
+            b"Resource" if in_payload && attribute("type").as_deref() == Some("Measurement") => {
+                if attribute("active").is_some_and(|active| !active.eq_ignore_ascii_case("true")) {
+                    continue;
+                }
+                let Some(measurement) = attribute("name")
+                    .or_else(|| attribute("index").map(|index| format!("measurement-{index}")))
+                else {
+                    warn!("Skip the SWID measurement without name or index.");
+                    continue;
+                };
+                digests.push(Digest {
+                    path: path(&directories, &measurement),
+                    hash_values: hash_values(&attributes),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    // The reference values are named after the platform of the RIM, or the
+    // software of the tag.
+    let prefix = platform
+        .or(name)
+        .ok_or_else(|| anyhow!("SWID tag has no SoftwareIdentity"))?;
+    if signed {
+        warn!("The signature of the SWID tag {prefix} is not verified.");
+    }
+
+    let mut values: BTreeMap<String, Vec<HashValuePair>> = BTreeMap::new();
+    if let Some(version) = version {
+        values.insert(
+            format!("{prefix}.version"),
+            vec![HashValuePair::new("none".into(), version)],
+        );
+    }
+    for digest in digests {
+        if digest.hash_values.is_empty() {
+            debug!("Skip the SWID payload {} without digest.", digest.path);
+            continue;
+        }
+        let pairs = values
+            .entry(format!("{prefix}/{}", digest.path))
+            .or_default();
+        for pair in digest.hash_values {
+            if !pairs.contains(&pair) {
+                pairs.push(pair);
+            }
+        }
+    }
+
+    Ok(values)
+}
+
+struct Attribute {
+    /// Local name of the attribute, e.g. `hash` for `SHA256:hash`.
+    name: String,
+
+    /// Hash algorithm of the namespace of the attribute, if any.
+    alg: Option<&'static str>,
+
+    value: String,
+}
+
+fn attributes(reader: &NsReader<&[u8]>, element: &BytesStart) -> Result<Vec<Attribute>> {
+    element
+        .attributes()
+        .map(|attribute| {
+            let attribute = attribute.context("illegal SWID attribute")?;
+            let (namespace, name) = reader.resolve_attribute(attribute.key);
+            let alg = match namespace {
+                ResolveResult::Bound(namespace) => HASH_NAMESPACES
+                    .iter()
+                    .find(|(uri, _)| uri.as_bytes() == namespace.as_ref())
+                    .map(|(_, alg)| *alg),
+                _ => None,
+            };
+            Ok(Attribute {
+                name: String::from_utf8_lossy(name.as_ref()).into_owned(),
+                alg,
+                value: attribute.unescape_value()?.into_owned(),
+            })
+        })
+        .collect()
+}
+
+/// The hex digests of the hash attributes, e.g. `SHA256:hash` of a file or
+/// `SHA384:Hash0` of a measurement.
+fn hash_values(attributes: &[Attribute]) -> Vec<HashValuePair> {
+    attributes
+        .iter()
+        .filter(|attribute| attribute.name.to_ascii_lowercase().starts_with("hash"))
+        .filter_map(|attribute| {
+            let Some(alg) = attribute.alg else {
+                warn!(
+                    "Skip the SWID digest {} of unsupported algorithm.",
+                    attribute.value
+                );
+                return None;
+            };
+            Some(HashValuePair::new(
+                alg.into(),
+                attribute.value.to_lowercase(),
+            ))
+        })
+        .collect()
+}
+
+fn path(directories: &[String], name: &str) -> String {
+    directories
+        .iter()
+        .filter(|directory| !directory.is_empty())
+        .map(String::as_str)
+        .chain([name])
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TCG_RIM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<SoftwareIdentity xmlns="http://standards.iso.org/iso/19770/-2/2015/schema.xsd"
+    xmlns:SHA256="http://www.w3.org/2001/04/xmlenc#sha256"
+    xmlns:SHA384="http://www.w3.org/2001/04/xmldsig-more#sha384"
+    xmlns:rim="https://trustedcomputinggroup.org/resource/tcg-reference-integrity-manifest-rim-information-model/"
+    name="Firmware" tagId="acme-roadrunner-fw" version="1.2.3">
+  <Entity name="ACME" regid="acme.example" role="softwareCreator tagCreator"/>
+  <Meta rim:platformManufacturerStr="ACME" rim:platformModel="RoadRunner"/>
+  <Payload>
+    <Directory name="rim">
+      <File name="roadrunner.rimel" size="7549" SHA256:hash="ABABABABABABABABABABABABABABABABABABABABABABABABABABABABABABABAB"/>
+      <File name="unhashed.bin"/>
+    </Directory>
+    <Resource type="Measurement" index="0" active="True" SHA384:Hash0="cdcd" SHA384:Hash1="efef"/>
+    <Resource type="Measurement" index="1" active="False" SHA384:Hash0="0000"/>
+  </Payload>
+  <Signature xmlns="http://www.w3.org/2000/09/xmldsig#"/>
+</SoftwareIdentity>"#;
+
+    fn extract(tag: &str) -> Result<Vec<ReferenceValue>> {
+        let provenance = base64::engine::general_purpose::STANDARD.encode(tag);
+        SwidExtractor.verify_and_extract(&provenance)
+    }
+
+    #[test]
+    fn extract_swid_reference_values() {
+        let rvs = extract(TCG_RIM).unwrap();
+        let names: Vec<_> = rvs.iter().map(|rv| rv.name().as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "ACME/RoadRunner.version",
+                "ACME/RoadRunner/measurement-0",
+                "ACME/RoadRunner/rim/roadrunner.rimel",
+            ]
+        );
+        assert_eq!(*rvs[0].hash_values()[0].value(), "1.2.3");
+        assert_eq!(
+            rvs[1].hash_values(),
+            &vec![
+                HashValuePair::new("sha384".into(), "cdcd".into()),
+                HashValuePair::new("sha384".into(), "efef".into()),
+            ]
+        );
+        assert_eq!(
+            rvs[2].hash_values(),
+            &vec![HashValuePair::new("sha256".into(), "ab".repeat(32))]
+        );
+
+        // A plain SWID tag is named after its software.
+        let tag = r#"<SoftwareIdentity xmlns:SHA256="http://www.w3.org/2001/04/xmlenc#sha256" name="shim" version="15.8">
+  <Payload><File name="shimx64.efi" SHA256:hash="aa"/></Payload>
+</SoftwareIdentity>"#;
+        let rvs = extract(tag).unwrap();
+        assert_eq!(rvs[1].name(), "shim/shimx64.efi");
+
+        extract("<Payload/>").unwrap_err();
+        extract("<SoftwareIdentity name=\"shim\"><Payload></File></SoftwareIdentity>").unwrap_err();
+    }
+}
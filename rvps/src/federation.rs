@@ -0,0 +1,273 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Federation of the RVPS with upstream RVPS, e.g. hosted by the vendors of
+//! the guest components. The reference values of the upstreams are pulled
+//! periodically and merged with the local ones. A reference value of an
+//! upstream replaces one of the same name if it comes from the same
+//! upstream, or if its upstream has a higher priority than the origin of
+//! the replaced one.
+
+use std::{collections::HashSet, sync::Weak, time::Duration};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+
+use crate::{
+    audit::{AuditAction, AuditRecord},
+    config::{FederationConfig, Upstream},
+    ReferenceValue, Store,
+};
+
+mod rvps_api {
+    tonic::include_proto!("reference");
+}
+
+use rvps_api::{
+    reference_value_provider_service_client::ReferenceValueProviderServiceClient,
+    ReferenceValueListRequest,
+};
+
+/// Maximum number of reference values pulled at a time.
+const PULL_PAGE_SIZE: u32 = 1000;
+
+/// Actor of the changes made by the pulls from `upstream`.
+pub fn upstream_actor(upstream: &str) -> String {
+    format!("upstream:{upstream}")
+}
+
+/// Priority of the origin of a stored reference value. The reference values
+/// of the upstreams which are not configured anymore have the lowest one.
+fn priority(federation: &FederationConfig, origin: Option<&String>) -> i64 {
+    match origin {
+        None => federation.local_priority,
+        Some(origin) => federation
+            .upstreams
+            .iter()
+            .find(|upstream| &upstream.name == origin)
+            .map_or(i64::MIN, |upstream| upstream.priority),
+    }
+}
+
+/// Pull all the reference values of `upstream`, a page at a time.
+async fn pull(upstream: &Upstream) -> Result<Vec<ReferenceValue>> {
+    let mut client = ReferenceValueProviderServiceClient::connect(upstream.address.clone())
+        .await
+        .with_context(|| format!("connect to upstream {}", upstream.name))?;
+
+    let mut rvs = Vec::new();
+    let mut page_token = String::new();
+    loop {
+        let req = tonic::Request::new(ReferenceValueListRequest {
+            name_prefix: upstream.name_prefix.clone().unwrap_or_default(),
+            page_size: PULL_PAGE_SIZE,
+            page_token,
+            ..Default::default()
+        });
+        let res = client
+            .list_reference_values(req)
+            .await
+            .with_context(|| format!("list reference values of upstream {}", upstream.name))?
+            .into_inner();
+        let page: Vec<ReferenceValue> = serde_json::from_str(&res.reference_values)
+            .with_context(|| format!("parse reference values of upstream {}", upstream.name))?;
+        rvs.extend(page);
+
+        if res.next_page_token.is_empty() {
+            break;
+        }
+        page_token = res.next_page_token;
+    }
+
+    Ok(rvs)
+}
+
+/// Merge the reference values `rvs`, all of them pulled from `upstream`,
+/// into the `store`. The reference values formerly pulled from `upstream`
+/// which are not in `rvs` anymore are deleted. Return the names of the
+/// changed reference values.
+pub async fn merge(
+    store: &(dyn Store + Send + Sync),
+    federation: &FederationConfig,
+    upstream: &Upstream,
+    rvs: Vec<ReferenceValue>,
+) -> Result<Vec<String>> {
+    let actor = upstream_actor(&upstream.name);
+    let mut changed = Vec::new();
+    let mut pulled = HashSet::new();
+    for mut rv in rvs {
+        let name = rv.name().to_owned();
+        if upstream
+            .name_prefix
+            .as_ref()
+            .is_some_and(|prefix| !name.starts_with(prefix))
+        {
+            continue;
+        }
+        rv.origin = Some(upstream.name.clone());
+        pulled.insert(name.clone());
+
+        let old = store.get(&name).await?;
+        if let Some(old) = &old {
+            if *old == rv {
+                continue;
+            }
+            if old.origin() != rv.origin()
+                && priority(federation, old.origin()) >= upstream.priority
+            {
+                continue;
+            }
+        }
+
+        store.set(name.clone(), rv.clone()).await?;
+        let record = AuditRecord::new(&actor, AuditAction::Register, &name, old, Some(rv));
+        store.append_audit(record).await?;
+        changed.push(name);
+    }
+
+    for rv in store.get_all().await? {
+        if rv.origin() != Some(&upstream.name) || pulled.contains(rv.name()) {
+            continue;
+        }
+        let name = rv.name().to_owned();
+        store.delete(&name).await?;
+        info!(
+            "Reference value of {name} is deleted, as it is removed from upstream {}.",
+            upstream.name
+        );
+        let record = AuditRecord::new(&actor, AuditAction::Delete, &name, Some(rv), None);
+        store.append_audit(record).await?;
+        changed.push(name);
+    }
+
+    Ok(changed)
+}
+
+/// Pull the reference values of all the upstreams of `federation` into the
+/// `store`. A failing upstream does not prevent the sync of the others.
+pub async fn sync(store: &(dyn Store + Send + Sync), federation: &FederationConfig) {
+    for upstream in &federation.upstreams {
+        let changed = match pull(upstream).await {
+            Ok(rvs) => merge(store, federation, upstream, rvs).await,
+            Err(e) => Err(e),
+        };
+        match changed {
+            Ok(changed) if !changed.is_empty() => info!(
+                "{} reference values are synced from upstream {}.",
+                changed.len(),
+                upstream.name
+            ),
+            Ok(_) => {}
+            Err(e) => warn!(
+                "Sync of reference values from upstream {} failed: {e:#}",
+                upstream.name
+            ),
+        }
+    }
+}
+
+/// Sync the `store` with the upstreams of `federation` every interval of
+/// the latter until the store is dropped.
+pub fn spawn_federation(store: Weak<dyn Store + Send + Sync>, federation: FederationConfig) {
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        warn!("No tokio runtime, reference values are not synced from upstreams.");
+        return;
+    };
+
+    runtime.spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(federation.interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+            let Some(store) = store.upgrade() else {
+                break;
+            };
+            sync(&*store, &federation).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::store::StoreType;
+
+    fn rv(name: &str, digest: &str) -> ReferenceValue {
+        ReferenceValue::new()
+            .expect("create ReferenceValue failed.")
+            .set_name(name)
+            .add_hash_value("sha256".into(), digest.into())
+    }
+
+    fn upstream(name: &str, priority: i64) -> Upstream {
+        Upstream {
+            name: name.into(),
+            address: String::new(),
+            priority,
+            name_prefix: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn merge_by_priority() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StoreType::LocalFs
+            .to_store(json!({ "file_path": dir.path() }))
+            .await
+            .unwrap();
+        let vendor = upstream("vendor", 10);
+        let mirror = upstream("mirror", -10);
+        let federation = FederationConfig {
+            interval_secs: 300,
+            local_priority: 0,
+            upstreams: vec![vendor.clone(), mirror.clone()],
+        };
+
+        store
+            .set("initrd".into(), rv("initrd", "aa"))
+            .await
+            .unwrap();
+        store
+            .set("kernel".into(), rv("kernel", "bb"))
+            .await
+            .unwrap();
+
+        // The vendor overrides the local reference values, the mirror not.
+        let changed = merge(&*store, &federation, &mirror, vec![rv("kernel", "cc")])
+            .await
+            .unwrap();
+        assert!(changed.is_empty());
+        let changed = merge(
+            &*store,
+            &federation,
+            &vendor,
+            vec![rv("kernel", "dd"), rv("shim", "ee")],
+        )
+        .await
+        .unwrap();
+        assert_eq!(changed, vec!["kernel", "shim"]);
+        let kernel = store.get("kernel").await.unwrap().unwrap();
+        assert_eq!(kernel.hash_values()[0].value(), "dd");
+        assert_eq!(kernel.origin().map(String::as_str), Some("vendor"));
+        assert_eq!(store.get("initrd").await.unwrap(), Some(rv("initrd", "aa")));
+
+        // Unchanged reference values are not rewritten, removed ones are
+        // deleted.
+        let changed = merge(&*store, &federation, &vendor, vec![rv("kernel", "dd")])
+            .await
+            .unwrap();
+        assert_eq!(changed, vec!["shim"]);
+        assert!(store.get("shim").await.unwrap().is_none());
+
+        let records = store.get_audit(0).await.unwrap();
+        assert_eq!(records.len(), 3);
+        assert!(records
+            .iter()
+            .all(|record| record.actor == "upstream:vendor"));
+        assert_eq!(records[2].action, AuditAction::Delete);
+    }
+}
@@ -3,20 +3,27 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+pub mod audit;
 pub mod config;
 pub mod extractors;
+#[cfg(feature = "federation")]
+pub mod federation;
 pub mod pre_processor;
+pub mod query;
 pub mod reference_value;
 pub mod store;
+pub mod watch;
 
 pub use config::Config;
 
 pub mod native;
 pub use native::Core;
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-pub use reference_value::{ReferenceValue, TrustedDigest};
+pub use reference_value::{Advisory, ReferenceValue, Severity, TrustedDigest};
 pub use store::Store;
 
 /// Default version of Message
@@ -30,12 +37,39 @@ static MESSAGE_VERSION: &str = "0.1.0";
 /// * `version`: version of this message.
 /// * `payload`: content of the provenance, JSON encoded.
 /// * `type`: provenance type of the payload.
+/// * `sigstore_bundle`: Sigstore bundle of the payload, required if the
+///   RVPS verifies the Sigstore signatures of the messages.
+/// * `lifecycle`: lifecycle of the reference values of the payload.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Message {
     #[serde(default = "default_version")]
     version: String,
     payload: String,
     r#type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sigstore_bundle: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lifecycle: Option<Lifecycle>,
+    /// Advisories of the reference values by name, either of the message
+    /// or of the ones registered before.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    advisories: HashMap<String, Vec<Advisory>>,
+}
+
+/// Lifecycle of the reference values of a message, overriding the validity
+/// given by their provenance.
+/// * `not_before`: time before which the reference values are not valid yet.
+/// * `not_after`: expired time of the reference values.
+/// * `supersedes`: names of the reference values superseded by the ones
+///   of the message, mapped to the name of their superseding reference value.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Lifecycle {
+    #[serde(default)]
+    not_before: Option<DateTime<Utc>>,
+    #[serde(default)]
+    not_after: Option<DateTime<Utc>>,
+    #[serde(default)]
+    supersedes: HashMap<String, String>,
 }
 
 /// Set the default version for Message
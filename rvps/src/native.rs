@@ -4,34 +4,93 @@
 //
 
 use anyhow::{bail, Context, Result};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Timelike, Utc};
 use log::{info, warn};
-use std::time::SystemTime;
+use std::{
+    sync::{Arc, Weak},
+    time::{Duration, SystemTime},
+};
+use tokio::sync::broadcast;
 
-use crate::{store::StoreType, Config};
+use crate::{
+    audit::{AuditAction, AuditRecord, ANONYMOUS_ACTOR, GC_ACTOR},
+    config::Provider,
+    query::{ReferenceValuePage, ReferenceValueQuery},
+    store::StoreType,
+    watch::WatchedStore,
+    Config,
+};
 
 use super::{
     extractors::{Extractors, ExtractorsImpl},
     pre_processor::{PreProcessor, PreProcessorAPI},
-    Message, Store, TrustedDigest, MESSAGE_VERSION,
+    Message, ReferenceValue, Store, TrustedDigest, MESSAGE_VERSION,
 };
 
 /// The core of the RVPS, s.t. componants except communication componants.
 pub struct Core {
     pre_processor: PreProcessor,
     extractors: ExtractorsImpl,
-    store: Box<dyn Store + Send + Sync>,
+    store: Arc<WatchedStore>,
 }
 
 impl Core {
     /// Instantiate  a new RVPS Core
-    pub fn new(config: Config) -> Result<Self> {
-        let pre_processor = PreProcessor::default();
+    pub async fn new(config: Config) -> Result<Self> {
+        #[allow(unused_mut)]
+        let mut pre_processor = PreProcessor::default();
+
+        // The Sigstore signatures are verified on the messages as received.
+        if let Some(sigstore) = &config.sigstore {
+            cfg_if::cfg_if! {
+                if #[cfg(feature = "sigstore")] {
+                    pre_processor.add_ware(Box::new(
+                        crate::pre_processor::sigstore::SigstoreVerifier::new(sigstore)
+                            .context("load Sigstore trust policy")?,
+                    ));
+                } else {
+                    let _ = sigstore;
+                    bail!("feature `sigstore` is required to verify the Sigstore signatures");
+                }
+            }
+        }
+
+        #[cfg(feature = "slsa")]
+        pre_processor.add_ware(Box::new(
+            crate::pre_processor::slsa::SlsaVerifier::new(&config.trusted_builders)
+                .context("load trusted builders")?,
+        ));
+
+        #[cfg(feature = "bundle")]
+        pre_processor.add_ware(Box::new(
+            crate::pre_processor::bundle::BundleVerifier::new(&config.bundle_publishers)
+                .context("load bundle publishers")?,
+        ));
 
         let extractors = ExtractorsImpl::default();
 
         let store_type = StoreType::try_from(&config.store_type[..])?;
-        let store = store_type.to_store(config.store_config)?;
+        let store = Arc::new(WatchedStore::new(
+            store_type.to_store(config.store_config).await?,
+        ));
+        let weak_store: Weak<dyn Store + Send + Sync> = Arc::<WatchedStore>::downgrade(&store);
+        if config.gc_interval_secs > 0 {
+            spawn_garbage_collection(
+                weak_store.clone(),
+                Duration::from_secs(config.gc_interval_secs),
+            );
+        }
+
+        if let Some(federation) = config.federation {
+            cfg_if::cfg_if! {
+                if #[cfg(feature = "federation")] {
+                    crate::federation::spawn_federation(weak_store, federation);
+                } else {
+                    let _ = federation;
+                    bail!("feature `federation` is required to pull reference values from upstreams");
+                }
+            }
+        }
 
         Ok(Core {
             pre_processor,
@@ -47,6 +106,21 @@ impl Core {
     }
 
     pub async fn verify_and_extract(&mut self, message: &str) -> Result<()> {
+        self.register(message, None).await
+    }
+
+    /// Like [`Core::verify_and_extract`], for a message of the `provider`,
+    /// which is only allowed to register its provenance types and reference
+    /// values.
+    pub async fn verify_and_extract_as(
+        &mut self,
+        message: &str,
+        provider: &Provider,
+    ) -> Result<()> {
+        self.register(message, Some(provider)).await
+    }
+
+    async fn register(&mut self, message: &str, provider: Option<&Provider>) -> Result<()> {
         let mut message: Message = serde_json::from_str(message).context("parse message")?;
 
         // Judge the version field
@@ -58,27 +132,195 @@ impl Core {
             );
         }
 
+        if let Some(provider) = provider {
+            if !provider.allows_type(&message.r#type) {
+                bail!(
+                    "Provider {} is not allowed to register provenance of type {}.",
+                    provider.name,
+                    message.r#type
+                );
+            }
+        }
+
         self.pre_processor.process(&mut message)?;
 
-        let rv = self.extractors.process(message)?;
-        for v in rv.iter() {
-            let old = self.store.set(v.name().to_string(), v.clone()).await?;
-            if let Some(old) = old {
+        let lifecycle = message.lifecycle.take().unwrap_or_default();
+        let mut advisories = std::mem::take(&mut message.advisories);
+        let provenance = message.r#type.clone();
+        let registered = Utc::now().with_nanosecond(0);
+        let mut rv = self.extractors.process(message)?;
+        for v in rv.iter_mut() {
+            if let Some(advisories) = advisories.remove(v.name()) {
+                v.advisories = advisories;
+            }
+            v.provenance = Some(provenance.clone());
+            v.registered = registered;
+            if let Some(not_before) = lifecycle.not_before {
+                v.not_before = Some(not_before.with_nanosecond(0).unwrap_or(not_before));
+            }
+            if let Some(not_after) = lifecycle.not_after {
+                v.expired = not_after.with_nanosecond(0).unwrap_or(not_after);
+            }
+            if v.not_before
+                .is_some_and(|not_before| not_before > v.expired)
+            {
+                bail!(
+                    "Reference value of {} expires before it is valid.",
+                    v.name()
+                );
+            }
+        }
+        if let Some(provider) = provider {
+            let names = rv
+                .iter()
+                .map(|v| v.name())
+                .chain(lifecycle.supersedes.keys())
+                .chain(advisories.keys());
+            for name in names {
+                if !provider.allows_name(name) {
+                    bail!(
+                        "Provider {} is not allowed to register reference value of {name}.",
+                        provider.name
+                    );
+                }
+            }
+        }
+        for (superseded, superseding) in &lifecycle.supersedes {
+            if superseded == superseding || !rv.iter().any(|v| v.name() == superseding) {
+                bail!("Reference value of {superseded} is not superseded by one of the message.");
+            }
+        }
+
+        let actor = provider.map_or(ANONYMOUS_ACTOR, |provider| &provider.name);
+        for v in rv {
+            let name = v.name().to_string();
+            let old = self.store.set(name.clone(), v.clone()).await?;
+            if let Some(old) = &old {
                 info!("Old Reference value of {} is replaced.", old.name());
             }
+            let record = AuditRecord::new(actor, AuditAction::Register, &name, old, Some(v));
+            self.store.append_audit(record).await?;
+        }
+
+        for (superseded, superseding) in lifecycle.supersedes {
+            match self.store.get(&superseded).await? {
+                Some(old) => {
+                    let new = old.clone().set_superseded_by(&superseding);
+                    self.store.set(superseded.clone(), new.clone()).await?;
+                    info!("Reference value of {superseded} is superseded by {superseding}.");
+                    let record = AuditRecord::new(
+                        actor,
+                        AuditAction::Supersede,
+                        &superseded,
+                        Some(old),
+                        Some(new),
+                    );
+                    self.store.append_audit(record).await?;
+                }
+                None => warn!("Superseded reference value of {superseded} does not exist."),
+            }
+        }
+
+        // The remaining advisories are of the reference values registered
+        // before, e.g. of a firmware found vulnerable after its release.
+        for (name, advisories) in advisories {
+            match self.store.get(&name).await? {
+                Some(old) => {
+                    let new = old.clone().set_advisories(advisories);
+                    self.store.set(name.clone(), new.clone()).await?;
+                    info!("Advisories of reference value of {name} are set.");
+                    let record =
+                        AuditRecord::new(actor, AuditAction::Annotate, &name, Some(old), Some(new));
+                    self.store.append_audit(record).await?;
+                }
+                None => warn!("Annotated reference value of {name} does not exist."),
+            }
         }
 
         Ok(())
     }
 
+    /// Delete the reference value `name` on behalf of `actor`, and return it
+    /// if it existed.
+    pub async fn delete(&mut self, name: &str, actor: &str) -> Result<Option<ReferenceValue>> {
+        let old = self.store.delete(name).await?;
+        if old.is_some() {
+            info!("Reference value of {name} is deleted by {actor}.");
+            let record = AuditRecord::new(actor, AuditAction::Delete, name, old.clone(), None);
+            self.store.append_audit(record).await?;
+        }
+
+        Ok(old)
+    }
+
+    /// Roll the reference values back to their state right after the audit
+    /// record `audit_id`, on behalf of `actor`, by undoing the changes
+    /// logged after it, the latest first. The rollback is itself logged, so
+    /// that it can be rolled back too. Return the names of the rolled back
+    /// reference values.
+    pub async fn rollback(&mut self, audit_id: u64, actor: &str) -> Result<Vec<String>> {
+        let records = self.store.get_audit(audit_id).await?;
+        let mut names = Vec::new();
+        for record in records.into_iter().rev() {
+            let current = match record.previous.clone() {
+                Some(previous) => {
+                    self.store.set(record.name.clone(), previous).await?;
+                    record.previous
+                }
+                None => {
+                    self.store.delete(&record.name).await?;
+                    None
+                }
+            };
+            let rollback = AuditRecord::new(
+                actor,
+                AuditAction::Rollback,
+                &record.name,
+                record.current,
+                current,
+            );
+            self.store.append_audit(rollback).await?;
+
+            if !names.contains(&record.name) {
+                names.push(record.name);
+            }
+        }
+        info!("Reference values are rolled back to audit record {audit_id} by {actor}.");
+
+        Ok(names)
+    }
+
+    /// Subscribe to the changes of the reference values, i.e. the records
+    /// appended to the audit log. Return the records after the record
+    /// `since` appended so far, unless `since` is `0`, and a receiver of the
+    /// next ones. The receiver may get again some of the returned records,
+    /// which have lower ids than the next ones.
+    pub async fn watch(
+        &self,
+        since: u64,
+    ) -> Result<(Vec<AuditRecord>, broadcast::Receiver<AuditRecord>)> {
+        let changes = self.store.subscribe();
+        let records = match since {
+            0 => Vec::new(),
+            since => self.store.get_audit(since).await?,
+        };
+
+        Ok((records, changes))
+    }
+
+    /// The records of the audit log after the record `since`.
+    pub async fn audit_log(&self, since: u64) -> Result<Vec<AuditRecord>> {
+        self.store.get_audit(since).await
+    }
+
     pub async fn get_digests(&self, name: &str) -> Result<Option<TrustedDigest>> {
         let rv = self.store.get(name).await?;
         match rv {
             None => Ok(None),
             Some(rv) => {
                 let now: DateTime<Utc> = DateTime::from(SystemTime::now());
-                if now > *rv.expired() {
-                    warn!("Reference value of {} is expired.", name);
+                if !is_valid(&*self.store, &rv, &now).await? {
+                    warn!("Reference value of {} is expired or superseded.", name);
                     return Ok(None);
                 }
 
@@ -91,8 +333,389 @@ impl Core {
                 Ok(Some(TrustedDigest {
                     name: name.to_owned(),
                     hash_values,
+                    advisories: rv.advisories,
                 }))
             }
         }
     }
+
+    /// Query the reference values, a page at a time.
+    pub async fn query_reference_values(
+        &self,
+        query: &ReferenceValueQuery,
+    ) -> Result<ReferenceValuePage> {
+        let rvs = self.store.get_all().await?;
+        Ok(query.page(rvs))
+    }
+
+    /// Delete the reference values which are expired or superseded, and
+    /// return their names.
+    pub async fn collect_garbage(&self) -> Result<Vec<String>> {
+        collect_garbage(&*self.store).await
+    }
+}
+
+/// Whether the reference value `rv` is valid at `now`. A reference value is
+/// not valid once its superseding reference value is valid.
+async fn is_valid(
+    store: &(dyn Store + Send + Sync),
+    rv: &ReferenceValue,
+    now: &DateTime<Utc>,
+) -> Result<bool> {
+    Ok(rv.is_valid_at(now) && !is_superseded(store, rv, now).await?)
+}
+
+async fn is_superseded(
+    store: &(dyn Store + Send + Sync),
+    rv: &ReferenceValue,
+    now: &DateTime<Utc>,
+) -> Result<bool> {
+    let Some(superseding) = rv.superseded_by() else {
+        return Ok(false);
+    };
+    let superseded = store
+        .get(superseding)
+        .await?
+        .is_some_and(|superseding| superseding.is_valid_at(now));
+    Ok(superseded)
+}
+
+async fn collect_garbage(store: &(dyn Store + Send + Sync)) -> Result<Vec<String>> {
+    let now: DateTime<Utc> = DateTime::from(SystemTime::now());
+    let mut deleted = Vec::new();
+    for rv in store.get_all().await? {
+        // Reference values which are not valid yet are kept.
+        if now > *rv.expired() || is_superseded(store, &rv, &now).await? {
+            let name = rv.name().to_owned();
+            store.delete(&name).await?;
+            info!("Reference value of {name} is deleted.");
+            let record = AuditRecord::new(GC_ACTOR, AuditAction::Collect, &name, Some(rv), None);
+            store.append_audit(record).await?;
+            deleted.push(name);
+        }
+    }
+
+    Ok(deleted)
+}
+
+/// Collect the garbage of the `store` every `interval` until the store is
+/// dropped.
+fn spawn_garbage_collection(store: Weak<dyn Store + Send + Sync>, interval: Duration) {
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        warn!("No tokio runtime, reference values are not garbage collected.");
+        return;
+    };
+
+    runtime.spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let Some(store) = store.upgrade() else {
+                break;
+            };
+            if let Err(e) = collect_garbage(&*store).await {
+                warn!("Garbage collection of reference values failed: {e:#}");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use chrono::{Duration, Utc};
+    use serde_json::json;
+
+    use super::*;
+    use crate::Severity;
+
+    fn message(rvs: serde_json::Value, lifecycle: serde_json::Value) -> String {
+        json!({
+            "version": MESSAGE_VERSION,
+            "type": "sample",
+            "payload": base64::engine::general_purpose::STANDARD.encode(rvs.to_string()),
+            "lifecycle": lifecycle,
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn lifecycle_and_garbage_collection() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut core = Core::new(Config {
+            store_type: "LocalFs".into(),
+            store_config: json!({ "file_path": dir.path() }),
+            gc_interval_secs: 0,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let now = Utc::now();
+        core.verify_and_extract(&message(json!({ "kernel-1": ["aa"] }), json!({})))
+            .await
+            .unwrap();
+        core.verify_and_extract(&message(
+            json!({ "initrd": ["bb"] }),
+            json!({ "not_after": now - Duration::hours(1) }),
+        ))
+        .await
+        .unwrap();
+        assert!(core.get_digests("kernel-1").await.unwrap().is_some());
+        assert!(core.get_digests("initrd").await.unwrap().is_none());
+        let page = core
+            .query_reference_values(&ReferenceValueQuery {
+                provenance: Some("sample".into()),
+                since: Some(now.timestamp() - 1),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(page.reference_values.len(), 2);
+
+        // The rotated kernel is valid in an hour, the old one until then.
+        core.verify_and_extract(&message(
+            json!({ "kernel-2": ["cc"] }),
+            json!({
+                "not_before": now + Duration::hours(1),
+                "supersedes": { "kernel-1": "kernel-2" },
+            }),
+        ))
+        .await
+        .unwrap();
+        assert!(core.get_digests("kernel-1").await.unwrap().is_some());
+        assert!(core.get_digests("kernel-2").await.unwrap().is_none());
+        assert_eq!(core.collect_garbage().await.unwrap(), vec!["initrd"]);
+
+        core.verify_and_extract(&message(
+            json!({ "kernel-2": ["cc"] }),
+            json!({ "supersedes": { "kernel-1": "kernel-2" } }),
+        ))
+        .await
+        .unwrap();
+        assert!(core.get_digests("kernel-1").await.unwrap().is_none());
+        assert!(core.get_digests("kernel-2").await.unwrap().is_some());
+        assert_eq!(core.collect_garbage().await.unwrap(), vec!["kernel-1"]);
+
+        // Only the reference values of the message supersede others.
+        assert!(core
+            .verify_and_extract(&message(
+                json!({ "kernel-3": ["dd"] }),
+                json!({ "supersedes": { "kernel-2": "kernel-4" } }),
+            ))
+            .await
+            .is_err());
+        assert!(core
+            .verify_and_extract(&message(
+                json!({ "kernel-3": ["dd"] }),
+                json!({
+                    "not_before": now + Duration::hours(2),
+                    "not_after": now + Duration::hours(1),
+                }),
+            ))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn provider_authorization() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut core = Core::new(Config {
+            store_type: "LocalFs".into(),
+            store_config: json!({ "file_path": dir.path() }),
+            gc_interval_secs: 0,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let provider = Provider {
+            name: "kata-ci".into(),
+            public_key_path: String::new(),
+            types: vec!["sample".into()],
+            name_prefixes: vec!["kata-".into()],
+        };
+
+        core.verify_and_extract(&message(json!({ "kernel-1": ["aa"] }), json!({})))
+            .await
+            .unwrap();
+        core.verify_and_extract_as(
+            &message(json!({ "kata-kernel": ["bb"] }), json!({})),
+            &provider,
+        )
+        .await
+        .unwrap();
+
+        // Neither the reference values of others, nor their supersession.
+        assert!(core
+            .verify_and_extract_as(
+                &message(json!({ "kernel-2": ["cc"] }), json!({})),
+                &provider
+            )
+            .await
+            .is_err());
+        assert!(core
+            .verify_and_extract_as(
+                &message(
+                    json!({ "kata-kernel-2": ["cc"] }),
+                    json!({ "supersedes": { "kernel-1": "kata-kernel-2" } }),
+                ),
+                &provider,
+            )
+            .await
+            .is_err());
+        assert!(core.get_digests("kernel-2").await.unwrap().is_none());
+        assert!(core.get_digests("kata-kernel-2").await.unwrap().is_none());
+
+        let message = json!({ "version": MESSAGE_VERSION, "type": "slsa", "payload": "" });
+        assert!(core
+            .verify_and_extract_as(&message.to_string(), &provider)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn advisories() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut core = Core::new(Config {
+            store_type: "LocalFs".into(),
+            store_config: json!({ "file_path": dir.path() }),
+            gc_interval_secs: 0,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let advisory = json!({ "id": "CVE-2024-1234", "severity": "high" });
+        let message = |rvs: serde_json::Value, advisories: serde_json::Value| {
+            json!({
+                "version": MESSAGE_VERSION,
+                "type": "sample",
+                "payload": base64::engine::general_purpose::STANDARD.encode(rvs.to_string()),
+                "advisories": advisories,
+            })
+            .to_string()
+        };
+
+        core.verify_and_extract(&message(
+            json!({ "firmware": ["aa"], "kernel": ["bb"] }),
+            json!({ "firmware": [advisory] }),
+        ))
+        .await
+        .unwrap();
+        let firmware = core.get_digests("firmware").await.unwrap().unwrap();
+        assert_eq!(firmware.hash_values, vec!["aa"]);
+        assert_eq!(firmware.advisories[0].id, "CVE-2024-1234");
+        assert_eq!(firmware.advisories[0].severity, Severity::High);
+        assert!(core
+            .get_digests("kernel")
+            .await
+            .unwrap()
+            .unwrap()
+            .advisories
+            .is_empty());
+
+        // The kernel is found vulnerable after its registration, and the
+        // firmware fixed.
+        core.verify_and_extract(&message(
+            json!({}),
+            json!({ "kernel": [advisory], "firmware": [] }),
+        ))
+        .await
+        .unwrap();
+        let kernel = core.get_digests("kernel").await.unwrap().unwrap();
+        assert_eq!(kernel.hash_values, vec!["bb"]);
+        assert_eq!(kernel.advisories.len(), 1);
+        assert!(core
+            .get_digests("firmware")
+            .await
+            .unwrap()
+            .unwrap()
+            .advisories
+            .is_empty());
+        let records = core.audit_log(0).await.unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(records[2..]
+            .iter()
+            .all(|record| record.action == AuditAction::Annotate));
+    }
+
+    #[tokio::test]
+    async fn delete_and_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut core = Core::new(Config {
+            store_type: "LocalFs".into(),
+            store_config: json!({ "file_path": dir.path() }),
+            gc_interval_secs: 0,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        core.verify_and_extract(&message(json!({ "kernel": ["aa"] }), json!({})))
+            .await
+            .unwrap();
+        let snapshot = core.audit_log(0).await.unwrap().last().unwrap().id;
+
+        // A bad measurement is published, and the good one deleted.
+        core.verify_and_extract(&message(
+            json!({ "kernel": ["bad"], "initrd": ["bb"] }),
+            json!({}),
+        ))
+        .await
+        .unwrap();
+        assert!(core.delete("kernel", "alice").await.unwrap().is_some());
+        assert!(core.delete("kernel", "alice").await.unwrap().is_none());
+        assert!(core.get_digests("kernel").await.unwrap().is_none());
+
+        let records = core.audit_log(snapshot).await.unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2].actor, "alice");
+        assert_eq!(records[2].action, AuditAction::Delete);
+
+        let mut names = core.rollback(snapshot, "bob").await.unwrap();
+        names.sort();
+        assert_eq!(names, vec!["initrd", "kernel"]);
+        assert_eq!(
+            core.get_digests("kernel")
+                .await
+                .unwrap()
+                .unwrap()
+                .hash_values,
+            vec!["aa"]
+        );
+        assert!(core.get_digests("initrd").await.unwrap().is_none());
+
+        let records = core.audit_log(snapshot).await.unwrap();
+        assert_eq!(records.len(), 6);
+        assert!(records[3..]
+            .iter()
+            .all(|record| record.actor == "bob" && record.action == AuditAction::Rollback));
+    }
+
+    #[tokio::test]
+    async fn watch_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut core = Core::new(Config {
+            store_type: "LocalFs".into(),
+            store_config: json!({ "file_path": dir.path() }),
+            gc_interval_secs: 0,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        core.verify_and_extract(&message(json!({ "kernel": ["aa"] }), json!({})))
+            .await
+            .unwrap();
+        let (records, mut changes) = core.watch(0).await.unwrap();
+        assert!(records.is_empty());
+
+        core.delete("kernel", "alice").await.unwrap();
+        let change = changes.recv().await.unwrap();
+        assert_eq!(change.name, "kernel");
+        assert_eq!(change.action, AuditAction::Delete);
+
+        // The changes missed by a subscriber are replayed.
+        let (records, _) = core.watch(change.id - 1).await.unwrap();
+        assert_eq!(records, vec![change]);
+    }
 }
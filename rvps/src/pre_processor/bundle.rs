@@ -0,0 +1,234 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Verifier of the reference value bundles. A bundle is published with
+//! each release of the guest images, e.g. of the kernels, initrds and
+//! firmware of CoCo, and is the manifest of the reference values of the
+//! release signed by its publisher. The signature and the publisher are
+//! verified against the trusted publishers of the RVPS, and the verified
+//! manifest is given to the extractor, which registers its reference values.
+
+use std::collections::HashMap;
+
+use anyhow::*;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::debug;
+use openssl::pkey::{PKey, Public};
+use serde::Deserialize;
+
+use crate::{config::BundlePublisher, Message};
+
+use super::{signature::verify_signature, Next, Ware};
+
+/// Type of the messages of the reference value bundles.
+pub const BUNDLE_TYPE: &str = "bundle";
+
+/// A signed bundle, as published.
+#[derive(Deserialize)]
+struct Bundle {
+    /// The manifest, base64 encoded.
+    manifest: String,
+
+    /// Signature of the manifest by the publisher, base64 encoded.
+    signature: String,
+}
+
+/// The fields of the manifest checked against its publisher.
+#[derive(Deserialize)]
+struct Signed {
+    publisher: String,
+    release: String,
+    reference_values: Vec<Named>,
+}
+
+#[derive(Deserialize)]
+struct Named {
+    name: String,
+}
+
+struct Publisher {
+    config: BundlePublisher,
+    public_key: PKey<Public>,
+}
+
+/// Verify the reference value bundles against the trusted publishers, so
+/// that only the releases of them are registered.
+pub struct BundleVerifier {
+    publishers: Vec<Publisher>,
+}
+
+impl BundleVerifier {
+    pub fn new(bundle_publishers: &[BundlePublisher]) -> Result<Self> {
+        let publishers = bundle_publishers
+            .iter()
+            .map(|publisher| {
+                let public_key = std::fs::read(&publisher.public_key_path).with_context(|| {
+                    format!(
+                        "read public key {} of bundle publisher",
+                        publisher.public_key_path
+                    )
+                })?;
+                let public_key = PKey::public_key_from_pem(&public_key).with_context(|| {
+                    format!(
+                        "parse public key {} of bundle publisher",
+                        publisher.public_key_path
+                    )
+                })?;
+                Ok(Publisher {
+                    config: publisher.clone(),
+                    public_key,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { publishers })
+    }
+
+    /// Verify the bundle `bundle_base64` and return its verified manifest.
+    fn verify(&self, bundle_base64: &str) -> Result<Vec<u8>> {
+        if self.publishers.is_empty() {
+            bail!("no bundle publisher is configured");
+        }
+
+        let bundle = STANDARD.decode(bundle_base64).context("base64 decode")?;
+        let bundle: Bundle = serde_json::from_slice(&bundle).context("deserialize bundle")?;
+        let manifest = STANDARD
+            .decode(&bundle.manifest)
+            .context("base64 decode bundle manifest")?;
+        let signature = STANDARD
+            .decode(&bundle.signature)
+            .context("base64 decode bundle signature")?;
+
+        // The manifest is signed by the key of the publisher it names.
+        let signed: Signed =
+            serde_json::from_slice(&manifest).context("deserialize bundle manifest")?;
+        let publisher = self
+            .publishers
+            .iter()
+            .find(|publisher| publisher.config.name == signed.publisher)
+            .ok_or_else(|| anyhow!("bundle publisher {} is not trusted", signed.publisher))?;
+        if !verify_signature(&publisher.public_key, &manifest, &signature) {
+            bail!(
+                "bundle is not signed by the trusted key of publisher {}",
+                signed.publisher
+            );
+        }
+        if let Some(rv) = signed
+            .reference_values
+            .iter()
+            .find(|rv| !publisher.config.allows_name(&rv.name))
+        {
+            bail!(
+                "bundle publisher {} may not publish reference value {}",
+                signed.publisher,
+                rv.name
+            );
+        }
+        debug!(
+            "Bundle of release {} verified, published by {}.",
+            signed.release, signed.publisher
+        );
+
+        Ok(manifest)
+    }
+}
+
+impl Ware for BundleVerifier {
+    fn handle(
+        &self,
+        message: &mut Message,
+        context: &mut HashMap<String, String>,
+        next: Next<'_>,
+    ) -> Result<()> {
+        if message.r#type == BUNDLE_TYPE {
+            let manifest = self.verify(&message.payload).context("verify bundle")?;
+            message.payload = STANDARD.encode(manifest);
+        }
+
+        next.run(message, context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pre_processor::{PreProcessor, PreProcessorAPI};
+    use openssl::{pkey::Private, sign::Signer};
+    use serde_json::json;
+
+    fn manifest(publisher: &str, name: &str) -> Vec<u8> {
+        json!({
+            "publisher": publisher,
+            "release": "kata-containers-3.5.0",
+            "reference_values": [{
+                "name": name,
+                "hash_values": [{ "alg": "sha384", "value": "ab".repeat(48) }],
+            }],
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn bundle(key: &PKey<Private>, manifest: &[u8]) -> String {
+        let signature = Signer::new_without_digest(key)
+            .unwrap()
+            .sign_oneshot_to_vec(manifest)
+            .unwrap();
+        let bundle = json!({
+            "manifest": STANDARD.encode(manifest),
+            "signature": STANDARD.encode(signature),
+        });
+        STANDARD.encode(bundle.to_string())
+    }
+
+    fn process(pre_processor: &PreProcessor, payload: String) -> Result<Message> {
+        let mut message = Message {
+            version: "0.1.0".into(),
+            payload,
+            r#type: BUNDLE_TYPE.into(),
+            sigstore_bundle: None,
+            lifecycle: None,
+            advisories: HashMap::new(),
+        };
+        pre_processor.process(&mut message)?;
+        Ok(message)
+    }
+
+    #[test]
+    fn verify_bundle() {
+        let key = PKey::generate_ed25519().unwrap();
+        let other_key = PKey::generate_ed25519().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let public_key_path = dir.path().join("publisher.pub");
+        std::fs::write(&public_key_path, key.public_key_to_pem().unwrap()).unwrap();
+        let mut pre_processor = PreProcessor::default();
+        pre_processor.add_ware(Box::new(
+            BundleVerifier::new(&[BundlePublisher {
+                name: "confidential-containers".into(),
+                public_key_path: public_key_path.to_string_lossy().into(),
+                name_prefixes: vec!["kata-".into()],
+            }])
+            .unwrap(),
+        ));
+
+        let manifest = manifest("confidential-containers", "kata-kernel");
+        let message = process(&pre_processor, bundle(&key, &manifest)).unwrap();
+        assert_eq!(STANDARD.decode(message.payload).unwrap(), manifest);
+
+        // Signed by an untrusted key, published by another publisher, or
+        // publishing a reference value out of the prefixes of the publisher.
+        assert!(process(&pre_processor, bundle(&other_key, &manifest)).is_err());
+        let evil = self::manifest("evil", "kata-kernel");
+        assert!(process(&pre_processor, bundle(&key, &evil)).is_err());
+        let firmware = self::manifest("confidential-containers", "ovmf");
+        assert!(process(&pre_processor, bundle(&key, &firmware)).is_err());
+
+        // Without publishers no bundle is accepted.
+        let mut pre_processor = PreProcessor::default();
+        pre_processor.add_ware(Box::new(BundleVerifier::new(&[]).unwrap()));
+        assert!(process(&pre_processor, bundle(&key, &manifest)).is_err());
+    }
+}
@@ -11,6 +11,18 @@ use anyhow::*;
 
 use super::Message;
 
+#[cfg(any(feature = "bundle", feature = "sigstore", feature = "slsa"))]
+mod signature;
+
+#[cfg(feature = "bundle")]
+pub mod bundle;
+
+#[cfg(feature = "sigstore")]
+pub mod sigstore;
+
+#[cfg(feature = "slsa")]
+pub mod slsa;
+
 /// A Ware loaded in Pre-Processor will process all the messages passing
 /// through the Pre-Processor. A series of Wares organized in order can
 /// process all the messages in need before they are consumed by the
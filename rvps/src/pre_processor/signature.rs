@@ -0,0 +1,39 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Signatures of the provenance verified by the wares.
+
+use openssl::{
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{Id, PKey, Public},
+    sign::Verifier,
+};
+
+/// Whether `signature` of `data` is verified by `public_key`. The ECDSA keys
+/// are used with the digest of their curve size, the RSA keys with SHA-256.
+pub(crate) fn verify_signature(public_key: &PKey<Public>, data: &[u8], signature: &[u8]) -> bool {
+    let verified = match public_key.id() {
+        Id::ED25519 => Verifier::new_without_digest(public_key)
+            .and_then(|mut verifier| verifier.verify_oneshot(signature, data)),
+        id => {
+            let digest = match public_key
+                .ec_key()
+                .ok()
+                .and_then(|key| key.group().curve_name())
+            {
+                Some(Nid::SECP384R1) if id == Id::EC => MessageDigest::sha384(),
+                Some(Nid::SECP521R1) if id == Id::EC => MessageDigest::sha512(),
+                _ => MessageDigest::sha256(),
+            };
+            Verifier::new(digest, public_key).and_then(|mut verifier| {
+                verifier.update(data)?;
+                verifier.verify(signature)
+            })
+        }
+    };
+
+    verified.unwrap_or(false)
+}
@@ -0,0 +1,716 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Verifier of the Sigstore signatures of the messages. When it is
+//! configured, every message carries the Sigstore bundle of its payload,
+//! signed keyless by an identity of the trust policy, i.e. with a Fulcio
+//! certificate, and logged in Rekor. The bundle is verified offline against
+//! the Fulcio CA and the Rekor public key before the message is extracted.
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::*;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::debug;
+use openssl::{
+    pkey::{PKey, Public},
+    sha::sha256,
+    stack::Stack,
+    x509::{store::X509StoreBuilder, verify::X509VerifyParam, X509StoreContext, X509},
+};
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Value};
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+
+use crate::{
+    config::{SigstoreConfig, SigstoreIdentity},
+    Message,
+};
+
+use super::{signature::verify_signature, Next, Ware};
+
+/// Fulcio extensions of the OIDC issuer of the identity, as a raw string
+/// (deprecated) and as a DER UTF8String.
+const OIDC_ISSUER_V1_OID: &str = "1.3.6.1.4.1.57264.1.1";
+const OIDC_ISSUER_V2_OID: &str = "1.3.6.1.4.1.57264.1.8";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Bundle {
+    verification_material: VerificationMaterial,
+    message_signature: MessageSignature,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerificationMaterial {
+    /// Certificates of the bundles before v0.3, from the leaf.
+    #[serde(default)]
+    x509_certificate_chain: Option<CertificateChain>,
+
+    /// Leaf certificate of the v0.3 bundles.
+    #[serde(default)]
+    certificate: Option<RawBytes>,
+
+    #[serde(default)]
+    tlog_entries: Vec<TlogEntry>,
+}
+
+#[derive(Deserialize)]
+struct CertificateChain {
+    certificates: Vec<RawBytes>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawBytes {
+    raw_bytes: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TlogEntry {
+    #[serde(deserialize_with = "int64")]
+    log_index: i64,
+    log_id: LogId,
+    #[serde(deserialize_with = "int64")]
+    integrated_time: i64,
+    inclusion_promise: Option<InclusionPromise>,
+    inclusion_proof: Option<InclusionProof>,
+    canonicalized_body: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogId {
+    key_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InclusionPromise {
+    signed_entry_timestamp: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InclusionProof {
+    #[serde(deserialize_with = "int64")]
+    log_index: i64,
+    root_hash: String,
+    #[serde(deserialize_with = "int64")]
+    tree_size: i64,
+    hashes: Vec<String>,
+    checkpoint: Checkpoint,
+}
+
+#[derive(Deserialize)]
+struct Checkpoint {
+    envelope: String,
+}
+
+#[derive(Deserialize)]
+struct MessageSignature {
+    signature: String,
+}
+
+/// The int64 of the bundles are JSON strings.
+fn int64<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<i64, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::String(value) => value.parse().map_err(serde::de::Error::custom),
+        Value::Number(value) => value
+            .as_i64()
+            .ok_or_else(|| serde::de::Error::custom("illegal int64")),
+        _ => Err(serde::de::Error::custom("illegal int64")),
+    }
+}
+
+/// Verify the Sigstore bundles of the messages, so that only the reference
+/// values signed by the identities of the trust policy are registered.
+pub struct SigstoreVerifier {
+    fulcio_certs: Vec<X509>,
+    rekor_public_key: PKey<Public>,
+    identities: Vec<SigstoreIdentity>,
+}
+
+impl SigstoreVerifier {
+    pub fn new(config: &SigstoreConfig) -> Result<Self> {
+        let fulcio_certs = std::fs::read(&config.fulcio_certs_path)
+            .with_context(|| format!("read Fulcio certificates {}", config.fulcio_certs_path))?;
+        let fulcio_certs = X509::stack_from_pem(&fulcio_certs)
+            .with_context(|| format!("parse Fulcio certificates {}", config.fulcio_certs_path))?;
+        let rekor_public_key = std::fs::read(&config.rekor_public_key_path)
+            .with_context(|| format!("read Rekor public key {}", config.rekor_public_key_path))?;
+        let rekor_public_key = PKey::public_key_from_pem(&rekor_public_key)
+            .with_context(|| format!("parse Rekor public key {}", config.rekor_public_key_path))?;
+
+        Ok(Self {
+            fulcio_certs,
+            rekor_public_key,
+            identities: config.identities.clone(),
+        })
+    }
+
+    /// Verify that `bundle` is a Sigstore bundle of `payload`.
+    fn verify(&self, payload: &[u8], bundle: &Value) -> Result<()> {
+        let bundle =
+            Bundle::deserialize(bundle).context("deserialize Sigstore bundle of the message")?;
+        let material = &bundle.verification_material;
+        let certificates = match (&material.x509_certificate_chain, &material.certificate) {
+            (Some(chain), _) => chain.certificates.iter().collect(),
+            (None, Some(certificate)) => vec![certificate],
+            (None, None) => bail!("Sigstore bundle has no certificate"),
+        };
+        let certificates = certificates
+            .into_iter()
+            .map(|certificate| {
+                let der = STANDARD.decode(&certificate.raw_bytes)?;
+                Ok(X509::from_der(&der)?)
+            })
+            .collect::<Result<Vec<_>>>()
+            .context("parse certificates of the Sigstore bundle")?;
+        let (leaf, intermediates) = certificates
+            .split_first()
+            .ok_or_else(|| anyhow!("Sigstore bundle has no certificate"))?;
+
+        let signature = STANDARD
+            .decode(&bundle.message_signature.signature)
+            .context("base64 decode Sigstore signature")?;
+        if !verify_signature(&leaf.public_key()?, payload, &signature) {
+            bail!("Sigstore signature of the message is not verified by its certificate");
+        }
+
+        // The signature was logged in Rekor when the certificate was valid.
+        let entry = material
+            .tlog_entries
+            .first()
+            .ok_or_else(|| anyhow!("Sigstore bundle has no Rekor entry"))?;
+        self.verify_tlog_entry(entry, payload, &signature, leaf)
+            .context("verify Rekor entry")?;
+        self.verify_certificate(leaf, intermediates, entry.integrated_time)
+            .context("verify Fulcio certificate")?;
+
+        Ok(())
+    }
+
+    fn verify_tlog_entry(
+        &self,
+        entry: &TlogEntry,
+        payload: &[u8],
+        signature: &[u8],
+        leaf: &X509,
+    ) -> Result<()> {
+        let log_id = STANDARD.decode(&entry.log_id.key_id)?;
+        if log_id != sha256(&self.rekor_public_key.public_key_to_der()?) {
+            bail!("Rekor entry is not logged by the trusted Rekor log");
+        }
+
+        // The entry is the one of the signature of the payload.
+        let body = STANDARD
+            .decode(&entry.canonicalized_body)
+            .context("base64 decode Rekor entry")?;
+        let rekord: Value = serde_json::from_slice(&body).context("deserialize Rekor entry")?;
+        if rekord["kind"] != "hashedrekord" {
+            bail!("unexpected Rekor entry kind {}", rekord["kind"]);
+        }
+        let spec = &rekord["spec"];
+        let logged_signature = spec["signature"]["content"]
+            .as_str()
+            .and_then(|content| STANDARD.decode(content).ok());
+        let logged_certificate = spec["signature"]["publicKey"]["content"]
+            .as_str()
+            .and_then(|content| STANDARD.decode(content).ok())
+            .and_then(|pem| X509::from_pem(&pem).ok());
+        if spec["data"]["hash"]["algorithm"] != "sha256"
+            || spec["data"]["hash"]["value"] != hex::encode(sha256(payload))
+            || logged_signature.as_deref() != Some(signature)
+            || logged_certificate.map(|cert| cert.to_der()).transpose()? != Some(leaf.to_der()?)
+        {
+            bail!("Rekor entry is not the one of the message signature");
+        }
+
+        // The signed entry timestamp of Rekor authenticates the integrated
+        // time of the entry.
+        let promise = entry
+            .inclusion_promise
+            .as_ref()
+            .ok_or_else(|| anyhow!("Rekor entry has no signed entry timestamp"))?;
+        let timestamp = json!(BTreeMap::from([
+            ("body", json!(entry.canonicalized_body)),
+            ("integratedTime", json!(entry.integrated_time)),
+            ("logID", json!(hex::encode(&log_id))),
+            ("logIndex", json!(entry.log_index)),
+        ]));
+        let timestamp_signature = STANDARD.decode(&promise.signed_entry_timestamp)?;
+        if !verify_signature(
+            &self.rekor_public_key,
+            timestamp.to_string().as_bytes(),
+            &timestamp_signature,
+        ) {
+            bail!("signed entry timestamp is not verified by the Rekor public key");
+        }
+
+        let proof = entry
+            .inclusion_proof
+            .as_ref()
+            .ok_or_else(|| anyhow!("Rekor entry has no inclusion proof"))?;
+        let root_hash = STANDARD.decode(&proof.root_hash)?;
+        let hashes = proof
+            .hashes
+            .iter()
+            .map(|hash| STANDARD.decode(hash))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let leaf_hash = sha256(&[&[0][..], &body].concat());
+        if root_from_inclusion_proof(proof.log_index, proof.tree_size, leaf_hash, &hashes)
+            .as_ref()
+            .map(|root| &root[..])
+            != Some(&root_hash[..])
+        {
+            bail!("inclusion proof of the Rekor entry is not verified");
+        }
+        self.verify_checkpoint(&proof.checkpoint.envelope, proof.tree_size, &root_hash)
+    }
+
+    /// Verify that the signed checkpoint of the Rekor log commits to the tree
+    /// of the inclusion proof.
+    fn verify_checkpoint(&self, checkpoint: &str, tree_size: i64, root_hash: &[u8]) -> Result<()> {
+        let (note, signatures) = checkpoint
+            .split_once("\n\n")
+            .ok_or_else(|| anyhow!("illegal Rekor checkpoint"))?;
+        let note = format!("{note}\n");
+        let mut lines = note.lines().skip(1);
+        let size: i64 = lines
+            .next()
+            .and_then(|size| size.parse().ok())
+            .ok_or_else(|| anyhow!("illegal Rekor checkpoint size"))?;
+        let root = lines
+            .next()
+            .and_then(|root| STANDARD.decode(root).ok())
+            .ok_or_else(|| anyhow!("illegal Rekor checkpoint root hash"))?;
+        if size != tree_size || root != root_hash {
+            bail!("Rekor checkpoint is not the one of the inclusion proof");
+        }
+
+        // Each signature line is `— <name> <base64 key hint and signature>`.
+        let signed = signatures
+            .lines()
+            .filter_map(|line| line.strip_prefix("\u{2014} "))
+            .filter_map(|line| line.rsplit_once(' '))
+            .filter_map(|(_, signature)| STANDARD.decode(signature).ok())
+            .any(|signature| {
+                signature.len() > 4
+                    && verify_signature(&self.rekor_public_key, note.as_bytes(), &signature[4..])
+            });
+        if !signed {
+            bail!("Rekor checkpoint is not signed by the Rekor public key");
+        }
+
+        Ok(())
+    }
+
+    /// Verify the certificate chain at `time`, and its identity against the
+    /// trust policy.
+    fn verify_certificate(&self, leaf: &X509, intermediates: &[X509], time: i64) -> Result<()> {
+        let mut store = X509StoreBuilder::new()?;
+        for certificate in &self.fulcio_certs {
+            store.add_cert(certificate.clone())?;
+        }
+        let mut param = X509VerifyParam::new()?;
+        param.set_time(time as _);
+        store.set_param(&param)?;
+        let store = store.build();
+
+        let mut chain = Stack::new()?;
+        for certificate in intermediates {
+            chain.push(certificate.clone())?;
+        }
+        let mut context = X509StoreContext::new()?;
+        let (verified, error) = context.init(&store, leaf, &chain, |context| {
+            let verified = context.verify_cert()?;
+            std::result::Result::Ok((verified, context.error()))
+        })?;
+        if !verified {
+            bail!("certificate is not issued by the Fulcio CA: {error}");
+        }
+
+        let der = leaf.to_der()?;
+        let (_, certificate) = X509Certificate::from_der(&der)
+            .map_err(|e| anyhow!("parse Fulcio certificate: {e}"))?;
+        let (issuer, subject) = identity(&certificate)?;
+        debug!("Sigstore signature of {subject} issued by {issuer}.");
+        if !self
+            .identities
+            .iter()
+            .any(|identity| identity.issuer == issuer && identity.subject == subject)
+        {
+            bail!("{subject} issued by {issuer} is not a trusted Sigstore identity");
+        }
+
+        Ok(())
+    }
+}
+
+impl Ware for SigstoreVerifier {
+    fn handle(
+        &self,
+        message: &mut Message,
+        context: &mut HashMap<String, String>,
+        next: Next<'_>,
+    ) -> Result<()> {
+        let bundle = message
+            .sigstore_bundle
+            .as_ref()
+            .ok_or_else(|| anyhow!("message has no Sigstore bundle"))?;
+        self.verify(message.payload.as_bytes(), bundle)
+            .context("verify Sigstore signature of the message")?;
+
+        next.run(message, context)
+    }
+}
+
+/// The OIDC issuer and the subject, i.e. the email or the URI, of a Fulcio
+/// certificate.
+fn identity(certificate: &X509Certificate) -> Result<(String, String)> {
+    let mut issuer = None;
+    for extension in certificate.extensions() {
+        match extension.oid.to_id_string().as_str() {
+            OIDC_ISSUER_V2_OID => issuer = Some(der_utf8_string(extension.value)?),
+            OIDC_ISSUER_V1_OID if issuer.is_none() => {
+                issuer = Some(String::from_utf8(extension.value.to_vec())?)
+            }
+            _ => {}
+        }
+    }
+    let issuer = issuer.ok_or_else(|| anyhow!("Fulcio certificate has no OIDC issuer"))?;
+
+    let subject = certificate
+        .subject_alternative_name()
+        .map_err(|e| anyhow!("parse Fulcio certificate SAN: {e}"))?
+        .and_then(|san| {
+            san.value.general_names.iter().find_map(|name| match name {
+                GeneralName::RFC822Name(email) => Some(email.to_string()),
+                GeneralName::URI(uri) => Some(uri.to_string()),
+                _ => None,
+            })
+        })
+        .ok_or_else(|| anyhow!("Fulcio certificate has no identity"))?;
+
+    Ok((issuer, subject))
+}
+
+fn der_utf8_string(der: &[u8]) -> Result<String> {
+    let (length, value) = match der {
+        [0x0c, 0x81, length, value @ ..] => (*length as usize, value),
+        [0x0c, length, value @ ..] if *length < 0x80 => (*length as usize, value),
+        _ => bail!("illegal DER UTF8String"),
+    };
+    if value.len() != length {
+        bail!("illegal DER UTF8String");
+    }
+
+    Ok(String::from_utf8(value.to_vec())?)
+}
+
+fn hash_children(left: &[u8], right: &[u8]) -> [u8; 32] {
+    sha256(&[&[1][..], left, right].concat())
+}
+
+/// Root hash of the Merkle tree of `tree_size` leaves given by the inclusion
+/// proof of the leaf at `index`, see RFC 9162 2.1.3.2.
+fn root_from_inclusion_proof(
+    index: i64,
+    tree_size: i64,
+    leaf_hash: [u8; 32],
+    proof: &[Vec<u8>],
+) -> Option<[u8; 32]> {
+    if index < 0 || index >= tree_size {
+        return None;
+    }
+
+    let (mut fn_, mut sn) = (index, tree_size - 1);
+    let mut root = leaf_hash;
+    for hash in proof {
+        if sn == 0 {
+            return None;
+        }
+        if fn_ & 1 == 1 || fn_ == sn {
+            root = hash_children(hash, &root);
+            while fn_ & 1 == 0 && fn_ != 0 {
+                fn_ >>= 1;
+                sn >>= 1;
+            }
+        } else {
+            root = hash_children(&root, hash);
+        }
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+
+    (sn == 0).then_some(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pre_processor::{PreProcessor, PreProcessorAPI};
+    use openssl::{
+        asn1::{Asn1Object, Asn1OctetString, Asn1Time},
+        bn::BigNum,
+        ec::{EcGroup, EcKey},
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::Private,
+        sign::Signer,
+        x509::{
+            extension::{BasicConstraints, SubjectAlternativeName},
+            X509Builder, X509Extension, X509NameBuilder,
+        },
+    };
+
+    const ISSUER: &str = "https://token.actions.githubusercontent.com";
+    const SUBJECT: &str =
+        "https://github.com/acme/firmware/.github/workflows/release.yml@refs/heads/main";
+
+    fn key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn sign(key: &PKey<Private>, data: &[u8]) -> Vec<u8> {
+        let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+        signer.update(data).unwrap();
+        signer.sign_to_vec().unwrap()
+    }
+
+    fn certificate(
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+        subject: &str,
+        validity: (i64, i64),
+    ) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        let common_name = if issuer.is_some() { "leaf" } else { "sigstore" };
+        name.append_entry_by_text("CN", common_name).unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder
+            .set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::from_unix(validity.0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::from_unix(validity.1).unwrap())
+            .unwrap();
+        let signing_key = match issuer {
+            Some((ca, ca_key)) => {
+                builder.set_issuer_name(ca.subject_name()).unwrap();
+                let san = SubjectAlternativeName::new()
+                    .uri(subject)
+                    .build(&builder.x509v3_context(Some(ca), None))
+                    .unwrap();
+                builder.append_extension(san).unwrap();
+                let oid = Asn1Object::from_str(OIDC_ISSUER_V2_OID).unwrap();
+                let value = [&[0x0c, ISSUER.len() as u8][..], ISSUER.as_bytes()].concat();
+                let value = Asn1OctetString::new_from_bytes(&value).unwrap();
+                builder
+                    .append_extension(X509Extension::new_from_der(&oid, false, &value).unwrap())
+                    .unwrap();
+                ca_key
+            }
+            None => {
+                builder.set_issuer_name(&name).unwrap();
+                builder
+                    .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                    .unwrap();
+                key
+            }
+        };
+        builder.sign(signing_key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    /// A Sigstore bundle of `payload` logged at the index 2 of a Rekor log of
+    /// 3 entries.
+    fn bundle(
+        payload: &[u8],
+        leaf_key: &PKey<Private>,
+        leaf: &X509,
+        rekor_key: &PKey<Private>,
+        integrated_time: i64,
+    ) -> Value {
+        let signature = sign(leaf_key, payload);
+        let body = json!({
+            "apiVersion": "0.0.1",
+            "kind": "hashedrekord",
+            "spec": {
+                "data": { "hash": { "algorithm": "sha256", "value": hex::encode(sha256(payload)) } },
+                "signature": {
+                    "content": STANDARD.encode(&signature),
+                    "publicKey": { "content": STANDARD.encode(leaf.to_pem().unwrap()) },
+                },
+            },
+        })
+        .to_string();
+        let log_id = sha256(&rekor_key.public_key_to_der().unwrap());
+
+        let leaves: Vec<_> = [
+            b"first".to_vec(),
+            b"second".to_vec(),
+            body.as_bytes().to_vec(),
+        ]
+        .iter()
+        .map(|leaf| sha256(&[&[0][..], leaf].concat()))
+        .collect();
+        let root = hash_children(&hash_children(&leaves[0], &leaves[1]), &leaves[2]);
+        let proof = [hash_children(&leaves[0], &leaves[1])];
+
+        let body = STANDARD.encode(body);
+        let timestamp = json!({
+            "body": body,
+            "integratedTime": integrated_time,
+            "logID": hex::encode(log_id),
+            "logIndex": 1002,
+        });
+        let note = format!(
+            "rekor.sigstore.dev - 1193050959916656506\n3\n{}\n",
+            STANDARD.encode(root)
+        );
+        let note_signature = [&log_id[..4], &sign(rekor_key, note.as_bytes())].concat();
+
+        json!({
+            "mediaType": "application/vnd.dev.sigstore.bundle+json;version=0.2",
+            "verificationMaterial": {
+                "x509CertificateChain": {
+                    "certificates": [{ "rawBytes": STANDARD.encode(leaf.to_der().unwrap()) }],
+                },
+                "tlogEntries": [{
+                    "logIndex": "1002",
+                    "logId": { "keyId": STANDARD.encode(log_id) },
+                    "kindVersion": { "kind": "hashedrekord", "version": "0.0.1" },
+                    "integratedTime": integrated_time.to_string(),
+                    "inclusionPromise": {
+                        "signedEntryTimestamp": STANDARD.encode(sign(rekor_key, timestamp.to_string().as_bytes())),
+                    },
+                    "inclusionProof": {
+                        "logIndex": "2",
+                        "rootHash": STANDARD.encode(root),
+                        "treeSize": "3",
+                        "hashes": proof.iter().map(|hash| STANDARD.encode(hash)).collect::<Vec<_>>(),
+                        "checkpoint": {
+                            "envelope": format!("{note}\n\u{2014} rekor.sigstore.dev {}\n", STANDARD.encode(note_signature)),
+                        },
+                    },
+                    "canonicalizedBody": body,
+                }],
+            },
+            "messageSignature": { "signature": STANDARD.encode(signature) },
+        })
+    }
+
+    #[test]
+    fn verify_sigstore_bundle() {
+        let now = chrono::Utc::now().timestamp();
+        let (ca_key, leaf_key, rekor_key) = (key(), key(), key());
+        // Fulcio certificates are valid for 10 minutes.
+        let ca = certificate(&ca_key, None, "", (now - 3600, now + 3600 * 24));
+        let leaf = certificate(
+            &leaf_key,
+            Some((&ca, &ca_key)),
+            SUBJECT,
+            (now - 60, now + 540),
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let fulcio_certs_path = dir.path().join("fulcio.pem");
+        std::fs::write(&fulcio_certs_path, ca.to_pem().unwrap()).unwrap();
+        let rekor_public_key_path = dir.path().join("rekor.pub");
+        std::fs::write(
+            &rekor_public_key_path,
+            rekor_key.public_key_to_pem().unwrap(),
+        )
+        .unwrap();
+        let config = SigstoreConfig {
+            fulcio_certs_path: fulcio_certs_path.to_string_lossy().into(),
+            rekor_public_key_path: rekor_public_key_path.to_string_lossy().into(),
+            identities: vec![SigstoreIdentity {
+                issuer: ISSUER.into(),
+                subject: SUBJECT.into(),
+            }],
+        };
+        let mut pre_processor = PreProcessor::default();
+        pre_processor.add_ware(Box::new(SigstoreVerifier::new(&config).unwrap()));
+
+        let process = |payload: &str, bundle: Option<Value>| {
+            let mut message = Message {
+                version: "0.1.0".into(),
+                payload: payload.into(),
+                r#type: "sample".into(),
+                sigstore_bundle: bundle,
+                lifecycle: None,
+                advisories: HashMap::new(),
+            };
+            pre_processor.process(&mut message)
+        };
+
+        let payload = STANDARD.encode(r#"{"firmware": ["abcd"]}"#);
+        let signed = bundle(payload.as_bytes(), &leaf_key, &leaf, &rekor_key, now);
+        process(&payload, Some(signed.clone())).unwrap();
+
+        // Unsigned, or signed for another payload.
+        assert!(process(&payload, None).is_err());
+        assert!(process("e30=", Some(signed.clone())).is_err());
+
+        // Logged after the certificate expired.
+        let late = bundle(payload.as_bytes(), &leaf_key, &leaf, &rekor_key, now + 3600);
+        assert!(process(&payload, Some(late)).is_err());
+
+        // Logged in another Rekor log.
+        let other = bundle(payload.as_bytes(), &leaf_key, &leaf, &key(), now);
+        assert!(process(&payload, Some(other)).is_err());
+
+        // Tampered inclusion proof.
+        let mut tampered = signed;
+        tampered["verificationMaterial"]["tlogEntries"][0]["inclusionProof"]["logIndex"] =
+            json!("1");
+        assert!(process(&payload, Some(tampered)).is_err());
+
+        // Signed by an untrusted identity.
+        let other_leaf = certificate(
+            &leaf_key,
+            Some((&ca, &ca_key)),
+            "https://github.com/evil/firmware/.github/workflows/release.yml@refs/heads/main",
+            (now - 60, now + 540),
+        );
+        let other = bundle(payload.as_bytes(), &leaf_key, &other_leaf, &rekor_key, now);
+        assert!(process(&payload, Some(other)).is_err());
+    }
+
+    #[test]
+    fn inclusion_proof() {
+        let leaves: Vec<_> = (0u8..5).map(|leaf| sha256(&[0, leaf])).collect();
+        let (h01, h23) = (
+            hash_children(&leaves[0], &leaves[1]),
+            hash_children(&leaves[2], &leaves[3]),
+        );
+        let root = hash_children(&hash_children(&h01, &h23), &leaves[4]);
+
+        let proof = [leaves[2].to_vec(), h01.to_vec(), leaves[4].to_vec()];
+        assert_eq!(
+            root_from_inclusion_proof(3, 5, leaves[3], &proof),
+            Some(root)
+        );
+        let proof = [hash_children(&h01, &h23).to_vec()];
+        assert_eq!(
+            root_from_inclusion_proof(4, 5, leaves[4], &proof),
+            Some(root)
+        );
+        assert_eq!(root_from_inclusion_proof(5, 5, leaves[4], &proof), None);
+    }
+}
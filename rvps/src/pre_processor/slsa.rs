@@ -0,0 +1,298 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Verifier of the SLSA provenance. The in-toto attestations of type `slsa`
+//! are DSSE envelopes of in-toto statements, signed by the builder of the
+//! artifacts in CI. The signature and the builder are verified against the
+//! trusted builders of the RVPS, and the verified statement is given to the
+//! extractor, which registers the digests of the subjects of the statement.
+
+use std::collections::HashMap;
+
+use anyhow::*;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::debug;
+use openssl::pkey::{PKey, Public};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{config::TrustedBuilder, Message};
+
+use super::{signature::verify_signature, Next, Ware};
+
+/// Type of the messages of the SLSA provenance.
+pub const SLSA_PROVENANCE_TYPE: &str = "slsa";
+
+const IN_TOTO_PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+
+const IN_TOTO_STATEMENT_TYPES: &[&str] = &[
+    "https://in-toto.io/Statement/v0.1",
+    "https://in-toto.io/Statement/v1",
+];
+
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(rename = "payloadType")]
+    payload_type: String,
+
+    payload: String,
+
+    signatures: Vec<Signature>,
+}
+
+#[derive(Deserialize)]
+struct Signature {
+    sig: String,
+}
+
+struct Builder {
+    id: String,
+    public_key: PKey<Public>,
+}
+
+/// Verify the SLSA provenance against the trusted builders, so that only the
+/// artifacts built by them are registered.
+pub struct SlsaVerifier {
+    builders: Vec<Builder>,
+}
+
+impl SlsaVerifier {
+    pub fn new(trusted_builders: &[TrustedBuilder]) -> Result<Self> {
+        let builders = trusted_builders
+            .iter()
+            .map(|builder| {
+                let public_key = std::fs::read(&builder.public_key_path).with_context(|| {
+                    format!(
+                        "read public key {} of trusted builder",
+                        builder.public_key_path
+                    )
+                })?;
+                let public_key = PKey::public_key_from_pem(&public_key).with_context(|| {
+                    format!(
+                        "parse public key {} of trusted builder",
+                        builder.public_key_path
+                    )
+                })?;
+                Ok(Builder {
+                    id: builder.id.clone(),
+                    public_key,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { builders })
+    }
+
+    /// Verify the DSSE envelope `envelope_base64` and return the verified
+    /// in-toto statement.
+    fn verify(&self, envelope_base64: &str) -> Result<Vec<u8>> {
+        if self.builders.is_empty() {
+            bail!("no trusted builder of the SLSA provenance is configured");
+        }
+
+        let envelope = STANDARD.decode(envelope_base64).context("base64 decode")?;
+        let envelope: Envelope =
+            serde_json::from_slice(&envelope).context("deserialize DSSE envelope")?;
+        if envelope.payload_type != IN_TOTO_PAYLOAD_TYPE {
+            bail!("unexpected DSSE payload type {}", envelope.payload_type);
+        }
+        let statement = STANDARD
+            .decode(&envelope.payload)
+            .context("base64 decode DSSE payload")?;
+
+        // Pre-authentication encoding of the payload, as signed.
+        let pae = [
+            format!(
+                "DSSEv1 {} {} {} ",
+                envelope.payload_type.len(),
+                envelope.payload_type,
+                statement.len()
+            )
+            .as_bytes(),
+            &statement,
+        ]
+        .concat();
+
+        let signers: Vec<_> = self
+            .builders
+            .iter()
+            .filter(|builder| {
+                envelope.signatures.iter().any(|signature| {
+                    STANDARD
+                        .decode(&signature.sig)
+                        .is_ok_and(|sig| verify_signature(&builder.public_key, &pae, &sig))
+                })
+            })
+            .collect();
+        if signers.is_empty() {
+            bail!("SLSA provenance is not signed by a trusted builder");
+        }
+
+        // The provenance is signed by the key of the builder it names.
+        let builder_id = builder_id(&statement)?;
+        if !signers.iter().any(|builder| builder.id == builder_id) {
+            bail!("SLSA provenance built by {builder_id} is not signed by its trusted key");
+        }
+        debug!("SLSA provenance verified, built by {builder_id}.");
+
+        Ok(statement)
+    }
+}
+
+impl Ware for SlsaVerifier {
+    fn handle(
+        &self,
+        message: &mut Message,
+        context: &mut HashMap<String, String>,
+        next: Next<'_>,
+    ) -> Result<()> {
+        if message.r#type == SLSA_PROVENANCE_TYPE {
+            let statement = self
+                .verify(&message.payload)
+                .context("verify SLSA provenance")?;
+            message.payload = STANDARD.encode(statement);
+        }
+
+        next.run(message, context)
+    }
+}
+
+/// The id of the builder of the SLSA provenance `statement`.
+fn builder_id(statement: &[u8]) -> Result<String> {
+    let statement: Value =
+        serde_json::from_slice(statement).context("deserialize in-toto statement")?;
+    let statement_type = statement["_type"].as_str().unwrap_or_default();
+    if !IN_TOTO_STATEMENT_TYPES.contains(&statement_type) {
+        bail!("unexpected in-toto statement type {statement_type}");
+    }
+
+    let predicate = &statement["predicate"];
+    let builder = match statement["predicateType"].as_str().unwrap_or_default() {
+        "https://slsa.dev/provenance/v0.2" => &predicate["builder"],
+        "https://slsa.dev/provenance/v1" => &predicate["runDetails"]["builder"],
+        predicate_type => bail!("unexpected SLSA predicate type {predicate_type}"),
+    };
+
+    builder["id"]
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| anyhow!("SLSA provenance has no builder id"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pre_processor::{PreProcessor, PreProcessorAPI};
+    use openssl::{
+        ec::{EcGroup, EcKey},
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::Private,
+        sign::Signer,
+    };
+    use serde_json::json;
+
+    const BUILDER_ID: &str = "https://github.com/slsa-framework/slsa-github-generator/.github/workflows/generator_generic_slsa3.yml@refs/tags/v1.9.0";
+
+    fn statement(builder_id: &str) -> Vec<u8> {
+        json!({
+            "_type": "https://in-toto.io/Statement/v1",
+            "subject": [{
+                "name": "kata-containers-initrd.img",
+                "digest": { "sha256": "ab".repeat(32) },
+            }],
+            "predicateType": "https://slsa.dev/provenance/v1",
+            "predicate": {
+                "buildDefinition": {},
+                "runDetails": { "builder": { "id": builder_id } },
+            },
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn envelope(key: &PKey<Private>, statement: &[u8]) -> String {
+        let pae = [
+            format!(
+                "DSSEv1 {} {IN_TOTO_PAYLOAD_TYPE} {} ",
+                IN_TOTO_PAYLOAD_TYPE.len(),
+                statement.len()
+            )
+            .as_bytes(),
+            statement,
+        ]
+        .concat();
+        let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+        signer.update(&pae).unwrap();
+        let envelope = json!({
+            "payloadType": IN_TOTO_PAYLOAD_TYPE,
+            "payload": STANDARD.encode(statement),
+            "signatures": [{ "keyid": "", "sig": STANDARD.encode(signer.sign_to_vec().unwrap()) }],
+        });
+        STANDARD.encode(envelope.to_string())
+    }
+
+    fn process(pre_processor: &PreProcessor, payload: String) -> Result<Message> {
+        let mut message = Message {
+            version: "0.1.0".into(),
+            payload,
+            r#type: SLSA_PROVENANCE_TYPE.into(),
+            sigstore_bundle: None,
+            lifecycle: None,
+            advisories: HashMap::new(),
+        };
+        pre_processor.process(&mut message)?;
+        Ok(message)
+    }
+
+    #[test]
+    fn verify_slsa_provenance() {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let other_key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let public_key_path = dir.path().join("builder.pub");
+        std::fs::write(&public_key_path, key.public_key_to_pem().unwrap()).unwrap();
+        let mut pre_processor = PreProcessor::default();
+        pre_processor.add_ware(Box::new(
+            SlsaVerifier::new(&[TrustedBuilder {
+                id: BUILDER_ID.into(),
+                public_key_path: public_key_path.to_string_lossy().into(),
+            }])
+            .unwrap(),
+        ));
+
+        let message = process(&pre_processor, envelope(&key, &statement(BUILDER_ID))).unwrap();
+        assert_eq!(
+            STANDARD.decode(message.payload).unwrap(),
+            statement(BUILDER_ID)
+        );
+
+        // Signed by an untrusted key, or built by another builder.
+        assert!(process(&pre_processor, envelope(&other_key, &statement(BUILDER_ID))).is_err());
+        assert!(process(
+            &pre_processor,
+            envelope(&key, &statement("https://evil.example"))
+        )
+        .is_err());
+
+        // Nothing but the SLSA provenance is verified.
+        let mut message = Message {
+            version: "0.1.0".into(),
+            payload: "{}".into(),
+            r#type: "sample".into(),
+            sigstore_bundle: None,
+            lifecycle: None,
+            advisories: HashMap::new(),
+        };
+        pre_processor.process(&mut message).unwrap();
+
+        // Without trusted builders no SLSA provenance is accepted.
+        let mut pre_processor = PreProcessor::default();
+        pre_processor.add_ware(Box::new(SlsaVerifier::new(&[]).unwrap()));
+        assert!(process(&pre_processor, envelope(&key, &statement(BUILDER_ID))).is_err());
+    }
+}
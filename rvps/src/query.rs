@@ -0,0 +1,158 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Queries of the reference values registered in the RVPS, paged by name.
+
+use serde::{Deserialize, Serialize};
+
+use crate::ReferenceValue;
+
+/// Number of reference values of a page, if not given by the query.
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Maximum number of reference values of a page.
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// Filter of the reference values. The fields that are not set match all
+/// the reference values.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct ReferenceValueQuery {
+    /// Prefix of the names of the reference values.
+    pub name_prefix: Option<String>,
+
+    /// Provenance type of the messages registering the reference values,
+    /// e.g. `sample`.
+    pub provenance: Option<String>,
+
+    /// Earliest Unix time of the registration, in seconds.
+    pub since: Option<i64>,
+
+    /// Latest Unix time of the registration, in seconds.
+    pub until: Option<i64>,
+
+    /// Maximum number of returned reference values, [`DEFAULT_PAGE_SIZE`]
+    /// by default and at most [`MAX_PAGE_SIZE`].
+    pub page_size: Option<usize>,
+
+    /// The `next_page_token` of the previous page, to get the next one.
+    pub page_token: Option<String>,
+}
+
+/// A page of the reference values matching a query, ordered by name.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct ReferenceValuePage {
+    pub reference_values: Vec<ReferenceValue>,
+
+    /// Token to get the next page, if there are more matching reference
+    /// values.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_page_token: Option<String>,
+}
+
+impl ReferenceValueQuery {
+    pub fn matches(&self, rv: &ReferenceValue) -> bool {
+        let registered = rv.registered().map(|registered| registered.timestamp());
+        self.name_prefix
+            .iter()
+            .all(|prefix| rv.name().starts_with(prefix))
+            && self
+                .provenance
+                .iter()
+                .all(|provenance| rv.provenance() == Some(provenance))
+            && self
+                .since
+                .iter()
+                .all(|since| registered.is_some_and(|registered| registered >= *since))
+            && self
+                .until
+                .iter()
+                .all(|until| registered.is_some_and(|registered| registered <= *until))
+    }
+
+    /// The page of the reference values `rvs` matching the query.
+    pub fn page(&self, rvs: Vec<ReferenceValue>) -> ReferenceValuePage {
+        let page_size = self
+            .page_size
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+
+        let mut rvs: Vec<_> = rvs
+            .into_iter()
+            .filter(|rv| {
+                self.page_token
+                    .iter()
+                    .all(|token| rv.name().as_str() > token.as_str())
+                    && self.matches(rv)
+            })
+            .collect();
+        rvs.sort_by(|a, b| a.name().cmp(b.name()));
+
+        let next_page_token = match rvs.len() > page_size {
+            true => {
+                rvs.truncate(page_size);
+                rvs.last().map(|rv| rv.name().to_owned())
+            }
+            false => None,
+        };
+
+        ReferenceValuePage {
+            reference_values: rvs,
+            next_page_token,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+
+    fn rv(name: &str, provenance: &str, registered: i64) -> ReferenceValue {
+        ReferenceValue::new()
+            .expect("create ReferenceValue failed.")
+            .set_name(name)
+            .set_provenance(provenance)
+            .set_registered(Utc.timestamp_opt(registered, 0).unwrap())
+    }
+
+    #[test]
+    fn query_pages() {
+        let rvs = vec![
+            rv("kernel-2", "sample", 20),
+            rv("initrd", "sample", 10),
+            rv("kernel-1", "sample", 10),
+            rv("kernel-3", "slsa", 30),
+        ];
+
+        let query = ReferenceValueQuery {
+            name_prefix: Some("kernel-".into()),
+            page_size: Some(2),
+            ..Default::default()
+        };
+        let page = query.page(rvs.clone());
+        assert_eq!(page.reference_values, vec![rvs[2].clone(), rvs[0].clone()]);
+        assert_eq!(page.next_page_token.as_deref(), Some("kernel-2"));
+
+        let query = ReferenceValueQuery {
+            page_token: page.next_page_token,
+            ..query
+        };
+        let page = query.page(rvs.clone());
+        assert_eq!(page.reference_values, vec![rvs[3].clone()]);
+        assert_eq!(page.next_page_token, None);
+
+        let query = ReferenceValueQuery {
+            provenance: Some("sample".into()),
+            since: Some(15),
+            until: Some(25),
+            ..Default::default()
+        };
+        assert_eq!(
+            query.page(rvs.clone()).reference_values,
+            vec![rvs[0].clone()]
+        );
+    }
+}
@@ -35,6 +35,31 @@ impl HashValuePair {
     }
 }
 
+/// Severity of an advisory, as rated by the vendor, e.g. after the CVSS
+/// score of the vulnerability.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    #[serde(alias = "moderate")]
+    Medium,
+    High,
+    Critical,
+}
+
+/// A vendor advisory of the artifact, e.g. a known vulnerability of a
+/// firmware version, in the spirit of the OSV format.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Advisory {
+    /// Id of the advisory, e.g. `CVE-2024-1234` or an OSV id.
+    pub id: String,
+    pub severity: Severity,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
 /// Helper to deserialize an expired time
 fn primitive_date_time_from_str<'de, D: Deserializer<'de>>(
     d: D,
@@ -56,6 +81,18 @@ fn primitive_date_time_from_str<'de, D: Deserializer<'de>>(
 /// * `expired`: expired time for this reference value.
 /// * `hash_value`: A set of key-value pairs, each indicates a hash
 /// algorithm and its relative hash value for the artifact.
+/// * `not_before`: optional time before which this reference value is
+/// not valid yet.
+/// * `superseded_by`: optional name of the reference value superseding
+/// this one. This one is not valid once the superseding one is.
+/// * `provenance`: optional provenance type of the message which
+/// registered this reference value.
+/// * `registered`: optional time when this reference value was
+/// registered.
+/// * `origin`: optional name of the upstream RVPS this reference value
+/// is pulled from. It is registered locally if not set.
+/// * `advisories`: vendor advisories of the artifact, e.g. known
+/// vulnerabilities, given to the policies of the AS.
 /// The actual struct deliver from RVPS to AS is
 /// [`TrustedDigest`], whose simple structure is easy
 /// for AS to handle.
@@ -68,6 +105,26 @@ pub struct ReferenceValue {
     pub expired: DateTime<Utc>,
     #[serde(rename = "hash-value")]
     pub hash_value: Vec<HashValuePair>,
+    #[serde(
+        default,
+        rename = "not-before",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub not_before: Option<DateTime<Utc>>,
+    #[serde(
+        default,
+        rename = "superseded-by",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub superseded_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registered: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub advisories: Vec<Advisory>,
 }
 
 /// Set the default version for ReferenceValue
@@ -88,6 +145,12 @@ impl ReferenceValue {
                 .with_nanosecond(0)
                 .ok_or_else(|| anyhow!("set nanosecond failed."))?,
             hash_value: Vec::new(),
+            not_before: None,
+            superseded_by: None,
+            provenance: None,
+            registered: None,
+            origin: None,
+            advisories: Vec::new(),
         })
     }
 
@@ -134,6 +197,80 @@ impl ReferenceValue {
     pub fn name(&self) -> &String {
         &self.name
     }
+
+    /// Set the time before which the ReferenceValue is not valid yet.
+    pub fn set_not_before(mut self, not_before: DateTime<Utc>) -> Self {
+        self.not_before = Some(
+            not_before
+                .with_nanosecond(0)
+                .expect("Set nanosecond failed."),
+        );
+        self
+    }
+
+    /// Get the time before which the ReferenceValue is not valid yet.
+    pub fn not_before(&self) -> Option<&DateTime<Utc>> {
+        self.not_before.as_ref()
+    }
+
+    /// Set the name of the reference value superseding the ReferenceValue.
+    pub fn set_superseded_by(mut self, name: &str) -> Self {
+        self.superseded_by = Some(name.into());
+        self
+    }
+
+    /// Get the name of the reference value superseding the ReferenceValue.
+    pub fn superseded_by(&self) -> Option<&String> {
+        self.superseded_by.as_ref()
+    }
+
+    /// Set the provenance type of the message registering the ReferenceValue.
+    pub fn set_provenance(mut self, provenance: &str) -> Self {
+        self.provenance = Some(provenance.into());
+        self
+    }
+
+    /// Get the provenance type of the message registering the ReferenceValue.
+    pub fn provenance(&self) -> Option<&String> {
+        self.provenance.as_ref()
+    }
+
+    /// Set the time when the ReferenceValue is registered.
+    pub fn set_registered(mut self, registered: DateTime<Utc>) -> Self {
+        self.registered = Some(
+            registered
+                .with_nanosecond(0)
+                .expect("Set nanosecond failed."),
+        );
+        self
+    }
+
+    /// Get the time when the ReferenceValue is registered.
+    pub fn registered(&self) -> Option<&DateTime<Utc>> {
+        self.registered.as_ref()
+    }
+
+    /// Get the name of the upstream RVPS the ReferenceValue is pulled from.
+    pub fn origin(&self) -> Option<&String> {
+        self.origin.as_ref()
+    }
+
+    /// Set the advisories of the ReferenceValue.
+    pub fn set_advisories(mut self, advisories: Vec<Advisory>) -> Self {
+        self.advisories = advisories;
+        self
+    }
+
+    /// Get the advisories of the ReferenceValue.
+    pub fn advisories(&self) -> &Vec<Advisory> {
+        &self.advisories
+    }
+
+    /// Whether `time` is within the validity of the ReferenceValue, i.e.
+    /// between its not-before and its expired time.
+    pub fn is_valid_at(&self, time: &DateTime<Utc>) -> bool {
+        time <= &self.expired && self.not_before.iter().all(|not_before| not_before <= time)
+    }
 }
 
 /// Trusted Digest is what RVPS actually delivered to
@@ -141,12 +278,17 @@ impl ReferenceValue {
 /// * `name`: The name of the artifact, e.g., `linux-1.1.1`
 /// * `hash_values`: digests that have been verified and can
 /// be trusted, so we can refer them as `trusted digests`.
+/// * `advisories`: vendor advisories of the artifact, so that a policy
+/// can refuse a known-vulnerable artifact whose digest matches.
 #[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
 pub struct TrustedDigest {
     /// The resource name.
     pub name: String,
     /// The reference hash values, base64 coded.
     pub hash_values: Vec<String>,
+    /// The vendor advisories of the artifact.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub advisories: Vec<Advisory>,
 }
 
 #[cfg(test)]
@@ -203,4 +345,16 @@ mod test {
         let deserialized_rf: ReferenceValue = serde_json::from_str(&rv_json).unwrap();
         assert_eq!(deserialized_rf, rv);
     }
+
+    #[test]
+    fn reference_value_validity() {
+        let rv = ReferenceValue::new()
+            .expect("create ReferenceValue failed.")
+            .set_expired(Utc.with_ymd_and_hms(1971, 1, 1, 0, 0, 0).unwrap())
+            .set_not_before(Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap());
+
+        assert!(rv.is_valid_at(&Utc.with_ymd_and_hms(1970, 6, 1, 0, 0, 0).unwrap()));
+        assert!(!rv.is_valid_at(&Utc.with_ymd_and_hms(1969, 6, 1, 0, 0, 0).unwrap()));
+        assert!(!rv.is_valid_at(&Utc.with_ymd_and_hms(1971, 6, 1, 0, 0, 0).unwrap()));
+    }
 }
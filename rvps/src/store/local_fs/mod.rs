@@ -10,7 +10,7 @@ use async_trait::async_trait;
 use serde::Deserialize;
 use serde_json::Value;
 
-use crate::ReferenceValue;
+use crate::{audit::AuditRecord, ReferenceValue};
 
 use super::Store;
 
@@ -18,10 +18,14 @@ use super::Store;
 /// which is created by sled engine.
 const FILE_PATH: &str = "/opt/confidential-containers/attestation-service/reference_values";
 
+/// Tree of the audit log, keyed by the big endian ids of the records.
+const AUDIT_TREE: &str = "audit";
+
 /// `LocalFs` implements [`Store`] trait. And
 /// it uses rocksdb inside.
 pub struct LocalFs {
     engine: sled::Db,
+    audit: sled::Tree,
 }
 
 fn default_file_path() -> String {
@@ -39,7 +43,8 @@ impl LocalFs {
     pub fn new(config: Value) -> Result<Self> {
         let config: Config = serde_json::from_value(config)?;
         let engine = sled::open(config.file_path)?;
-        Ok(Self { engine })
+        let audit = engine.open_tree(AUDIT_TREE)?;
+        Ok(Self { engine, audit })
     }
 }
 
@@ -72,6 +77,51 @@ impl Store for LocalFs {
             None => Ok(None),
         }
     }
+
+    async fn delete(&self, name: &str) -> Result<Option<ReferenceValue>> {
+        let res = match self.engine.remove(name).context("remove from sled")? {
+            Some(v) => {
+                let v = serde_json::from_slice(&v)?;
+                Ok(Some(v))
+            }
+            None => Ok(None),
+        };
+
+        self.engine.flush()?;
+        res
+    }
+
+    async fn get_all(&self) -> Result<Vec<ReferenceValue>> {
+        self.engine
+            .iter()
+            .values()
+            .map(|v| {
+                let v = v.context("read from sled")?;
+                Ok(serde_json::from_slice(&v)?)
+            })
+            .collect()
+    }
+
+    async fn append_audit(&self, mut record: AuditRecord) -> Result<u64> {
+        // Ids generated by sled are increasing, and never 0.
+        record.id = self.engine.generate_id()? + 1;
+        self.audit
+            .insert(record.id.to_be_bytes(), serde_json::to_vec(&record)?)
+            .context("insert into sled")?;
+        self.audit.flush()?;
+        Ok(record.id)
+    }
+
+    async fn get_audit(&self, since: u64) -> Result<Vec<AuditRecord>> {
+        self.audit
+            .range((since + 1).to_be_bytes()..)
+            .values()
+            .map(|v| {
+                let v = v.context("read from sled")?;
+                Ok(serde_json::from_slice(&v)?)
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
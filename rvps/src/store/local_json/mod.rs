@@ -1,7 +1,7 @@
 use std::{fs, path::PathBuf};
 
 use super::Store;
-use crate::ReferenceValue;
+use crate::{audit::AuditRecord, ReferenceValue};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use log::debug;
@@ -16,6 +16,21 @@ pub struct LocalJson {
     lock: RwLock<i32>,
 }
 
+impl LocalJson {
+    /// The audit log is kept next to the reference values.
+    fn audit_file_path(&self) -> String {
+        format!("{}.audit", self.file_path)
+    }
+
+    async fn read_audit(&self) -> Result<Vec<AuditRecord>> {
+        match tokio::fs::read(self.audit_file_path()).await {
+            Ok(file) => Ok(serde_json::from_slice(&file)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
 fn default_file_path() -> String {
     FILE_PATH.to_string()
 }
@@ -71,4 +86,44 @@ impl Store for LocalJson {
         let rv = rvs.into_iter().find(|rv| rv.name == name);
         Ok(rv)
     }
+
+    async fn delete(&self, name: &str) -> Result<Option<ReferenceValue>> {
+        let _guard = self.lock.write().await;
+        let file = tokio::fs::read(&self.file_path).await?;
+        let mut rvs: Vec<ReferenceValue> = serde_json::from_slice(&file)?;
+        let Some(index) = rvs.iter().position(|rv| rv.name == name) else {
+            return Ok(None);
+        };
+        let res = rvs.remove(index);
+
+        let contents = serde_json::to_vec(&rvs)?;
+        tokio::fs::write(&self.file_path, contents).await?;
+        Ok(Some(res))
+    }
+
+    async fn get_all(&self) -> Result<Vec<ReferenceValue>> {
+        let _guard = self.lock.read().await;
+        let file = tokio::fs::read(&self.file_path).await?;
+        let rvs = serde_json::from_slice(&file)?;
+        Ok(rvs)
+    }
+
+    async fn append_audit(&self, mut record: AuditRecord) -> Result<u64> {
+        let _guard = self.lock.write().await;
+        let mut records = self.read_audit().await?;
+        record.id = records.last().map(|last| last.id).unwrap_or_default() + 1;
+        let id = record.id;
+        records.push(record);
+
+        let contents = serde_json::to_vec(&records)?;
+        tokio::fs::write(self.audit_file_path(), contents).await?;
+        Ok(id)
+    }
+
+    async fn get_audit(&self, since: u64) -> Result<Vec<AuditRecord>> {
+        let _guard = self.lock.read().await;
+        let mut records = self.read_audit().await?;
+        records.retain(|record| record.id > since);
+        Ok(records)
+    }
 }
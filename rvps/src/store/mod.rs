@@ -14,19 +14,24 @@ use strum::EnumString;
 use self::local_fs::LocalFs;
 use self::local_json::LocalJson;
 
-use super::ReferenceValue;
+use super::{audit::AuditRecord, ReferenceValue};
 
 pub mod local_fs;
 pub mod local_json;
 
+#[cfg(feature = "postgres")]
+pub mod postgres;
+
 #[derive(Deserialize, Debug, Clone, EnumString)]
 pub enum StoreType {
     LocalFs,
     LocalJson,
+    #[cfg(feature = "postgres")]
+    Postgres,
 }
 
 impl StoreType {
-    pub fn to_store(&self, config: Value) -> Result<Box<dyn Store + Send + Sync>> {
+    pub async fn to_store(&self, config: Value) -> Result<Box<dyn Store + Send + Sync>> {
         match self {
             StoreType::LocalFs => {
                 Ok(Box::new(LocalFs::new(config)?) as Box<dyn Store + Send + Sync>)
@@ -34,6 +39,11 @@ impl StoreType {
             StoreType::LocalJson => {
                 Ok(Box::new(LocalJson::new(config)?) as Box<dyn Store + Send + Sync>)
             }
+            #[cfg(feature = "postgres")]
+            StoreType::Postgres => {
+                Ok(Box::new(postgres::Postgres::new(config).await?)
+                    as Box<dyn Store + Send + Sync>)
+            }
         }
     }
 }
@@ -51,4 +61,18 @@ pub trait Store {
 
     // Retrieve a reference value
     async fn get(&self, name: &str) -> Result<Option<ReferenceValue>>;
+
+    /// Delete a reference value. If the given `name` exists,
+    /// return the deleted `Some<ReferenceValue>`, otherwise return `None`
+    async fn delete(&self, name: &str) -> Result<Option<ReferenceValue>>;
+
+    /// Retrieve all the reference values
+    async fn get_all(&self) -> Result<Vec<ReferenceValue>>;
+
+    /// Append a record to the audit log, and return the id assigned to it.
+    async fn append_audit(&self, record: AuditRecord) -> Result<u64>;
+
+    /// Retrieve the records of the audit log after the record `since`,
+    /// ordered by id.
+    async fn get_audit(&self, since: u64) -> Result<Vec<AuditRecord>>;
 }
@@ -0,0 +1,70 @@
+// Copyright (c) 2024 by The Confidential Container Authors.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Notifications of the changes of the reference values, e.g. to invalidate
+//! the reference values cached by the AS. Each change of a reference value
+//! is logged in the audit log, so the subscribers are notified of the audit
+//! records as they are appended. Only the changes made through this RVPS are
+//! notified, not the ones of the other replicas sharing its store.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::broadcast;
+
+use crate::{audit::AuditRecord, ReferenceValue, Store};
+
+/// Number of changes kept for the subscribers lagging behind. A subscriber
+/// lagging further misses changes.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Store notifying the subscribers of the audit records appended to it.
+pub struct WatchedStore {
+    store: Box<dyn Store + Send + Sync>,
+    changes: broadcast::Sender<AuditRecord>,
+}
+
+impl WatchedStore {
+    pub fn new(store: Box<dyn Store + Send + Sync>) -> Self {
+        let (changes, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self { store, changes }
+    }
+
+    /// Subscribe to the changes appended from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<AuditRecord> {
+        self.changes.subscribe()
+    }
+}
+
+#[async_trait]
+impl Store for WatchedStore {
+    async fn set(&self, name: String, rv: ReferenceValue) -> Result<Option<ReferenceValue>> {
+        self.store.set(name, rv).await
+    }
+
+    async fn get(&self, name: &str) -> Result<Option<ReferenceValue>> {
+        self.store.get(name).await
+    }
+
+    async fn delete(&self, name: &str) -> Result<Option<ReferenceValue>> {
+        self.store.delete(name).await
+    }
+
+    async fn get_all(&self) -> Result<Vec<ReferenceValue>> {
+        self.store.get_all().await
+    }
+
+    async fn append_audit(&self, mut record: AuditRecord) -> Result<u64> {
+        let id = self.store.append_audit(record.clone()).await?;
+        record.id = id;
+
+        // Nobody may be subscribed.
+        let _ = self.changes.send(record);
+        Ok(id)
+    }
+
+    async fn get_audit(&self, since: u64) -> Result<Vec<AuditRecord>> {
+        self.store.get_audit(since).await
+    }
+}
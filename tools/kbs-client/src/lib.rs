@@ -4,15 +4,21 @@
 
 //! KBS client SDK.
 
-use anyhow::{anyhow, bail, Result};
-use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
 use base64::Engine;
 use jwt_simple::prelude::{Claims, Duration, Ed25519KeyPair, EdDSAKeyPairLike};
 use kbs_protocol::evidence_provider::NativeEvidenceProvider;
 use kbs_protocol::token_provider::TestTokenProvider;
 use kbs_protocol::KbsClientBuilder;
 use kbs_protocol::KbsClientCapabilities;
-use serde::Serialize;
+use kbs_types::{Response, TeePubKey};
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::traits::PublicKeyParts;
+use rsa::{Pkcs1v15Encrypt, RsaPrivateKey};
+use serde::{Deserialize, Serialize};
 
 const KBS_URL_PREFIX: &str = "kbs/v0";
 
@@ -108,6 +114,12 @@ pub struct SetPolicyInput {
     pub r#type: String,
     pub policy_id: String,
     pub policy: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signed_version: Option<u64>,
 }
 
 /// Set attestation policy
@@ -117,15 +129,23 @@ pub struct SetPolicyInput {
 /// - policy_bytes: Policy file content in `Vec<u8>`.
 /// - [policy_type]: Policy type. Default value is "rego".
 /// - [policy_id]: Policy ID. Default value is "default".
+/// - [signature]: Ed25519 signature of the policy by the policy author, required if KBS is
+///   configured with a policy author key.
+/// - [signed_version]: Version of the policy signed by the policy author, required with the
+///   signature. It must be newer than the version of the active policy.
 /// - kbs_root_certs_pem: Custom HTTPS root certificate of KBS server. It can be left blank.
+///
+/// Returns the new version of the policy, with its diff with the previous version.
 pub async fn set_attestation_policy(
     url: &str,
     auth_key: String,
     policy_bytes: Vec<u8>,
     policy_type: Option<String>,
     policy_id: Option<String>,
+    signature: Option<Vec<u8>>,
+    signed_version: Option<u64>,
     kbs_root_certs_pem: Vec<String>,
-) -> Result<()> {
+) -> Result<PolicyChange> {
     let auth_private_key = Ed25519KeyPair::from_pem(&auth_key)?;
     let claims = Claims::create(Duration::from_hours(2));
     let token = auth_private_key.sign(claims)?;
@@ -137,6 +157,8 @@ pub async fn set_attestation_policy(
         r#type: policy_type.unwrap_or("rego".to_string()),
         policy_id: policy_id.unwrap_or("default".to_string()),
         policy: URL_SAFE_NO_PAD.encode(policy_bytes.clone()),
+        signature: signature.map(|signature| URL_SAFE_NO_PAD.encode(signature)),
+        signed_version,
     };
 
     let res = http_client
@@ -148,7 +170,7 @@ pub async fn set_attestation_policy(
         .await?;
 
     match res.status() {
-        reqwest::StatusCode::OK => Ok(()),
+        reqwest::StatusCode::OK => Ok(res.json::<PolicyChange>().await?),
         _ => {
             bail!("Request Failed, Response: {:?}", res.text().await?)
         }
@@ -158,6 +180,15 @@ pub async fn set_attestation_policy(
 #[derive(Clone, Serialize)]
 struct ResourcePolicyData {
     pub policy: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signed_version: Option<u64>,
 }
 
 /// Set resource policy
@@ -165,13 +196,23 @@ struct ResourcePolicyData {
 /// - url: KBS server root URL.
 /// - auth_key: KBS owner's authenticate private key (PEM string).
 /// - policy_bytes: Policy file content in `Vec<u8>`.
+/// - [policy_id]: ID of a repository policy. The default resource policy if not given.
+/// - [signature]: Ed25519 signature of the policy by the policy author, required if KBS is
+///   configured with a policy author key.
+/// - [signed_version]: Version of the policy signed by the policy author, required with the
+///   signature. It must be newer than the version of the active policy.
 /// - kbs_root_certs_pem: Custom HTTPS root certificate of KBS server. It can be left blank.
+///
+/// Returns the new version of the policy, with its diff with the previous version.
 pub async fn set_resource_policy(
     url: &str,
     auth_key: String,
     policy_bytes: Vec<u8>,
+    policy_id: Option<String>,
+    signature: Option<Vec<u8>>,
+    signed_version: Option<u64>,
     kbs_root_certs_pem: Vec<String>,
-) -> Result<()> {
+) -> Result<PolicyChange> {
     let auth_private_key = Ed25519KeyPair::from_pem(&auth_key)?;
     let claims = Claims::create(Duration::from_hours(2));
     let token = auth_private_key.sign(claims)?;
@@ -181,6 +222,9 @@ pub async fn set_resource_policy(
     let set_policy_url = format!("{}/{KBS_URL_PREFIX}/resource-policy", url);
     let post_input = ResourcePolicyData {
         policy: URL_SAFE_NO_PAD.encode(policy_bytes.clone()),
+        policy_id,
+        signature: signature.map(|signature| URL_SAFE_NO_PAD.encode(signature)),
+        signed_version,
     };
 
     let res = http_client
@@ -194,7 +238,261 @@ pub async fn set_resource_policy(
     if res.status() != reqwest::StatusCode::OK {
         bail!("Request Failed, Response: {:?}", res.text().await?);
     }
-    Ok(())
+    Ok(res.json::<PolicyChange>().await?)
+}
+
+#[derive(Serialize)]
+struct ResourcePolicyDryRunInput {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    policy: Option<String>,
+    resource_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    claims: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+}
+
+/// Dry run a candidate resource policy, without setting it.
+/// Input parameters:
+/// - url: KBS server root URL.
+/// - auth_key: KBS owner's authenticate private key (PEM string).
+/// - [policy_bytes]: Candidate policy file content. The set policy of the resource if not given.
+/// - resource_path: Resource path, format must be `<top>/<middle>/<tail>`, e.g. `alice/key/example`.
+/// - [claims]: Attestation claims to evaluate the policy against.
+/// - [session_id]: ID of an attested KBS session, whose claims are used instead.
+/// - [token]: Attestation token, whose claims are used instead.
+/// - kbs_root_certs_pem: Custom HTTPS root certificate of KBS server. It can be left blank.
+///
+/// Returns the report of the dry run, with whether the resource is allowed.
+#[allow(clippy::too_many_arguments)]
+pub async fn dry_run_resource_policy(
+    url: &str,
+    auth_key: String,
+    policy_bytes: Option<Vec<u8>>,
+    resource_path: &str,
+    claims: Option<serde_json::Value>,
+    session_id: Option<String>,
+    token: Option<String>,
+    kbs_root_certs_pem: Vec<String>,
+) -> Result<serde_json::Value> {
+    let auth_private_key = Ed25519KeyPair::from_pem(&auth_key)?;
+    let claims_set = Claims::create(Duration::from_hours(2));
+    let auth_token = auth_private_key.sign(claims_set)?;
+
+    let http_client = build_http_client(kbs_root_certs_pem)?;
+
+    let dry_run_url = format!("{}/{KBS_URL_PREFIX}/admin/resource-policy/dry-run", url);
+    let post_input = ResourcePolicyDryRunInput {
+        policy: policy_bytes.map(|policy| URL_SAFE_NO_PAD.encode(policy)),
+        resource_path: resource_path.to_string(),
+        claims,
+        session_id,
+        token,
+    };
+
+    let res = http_client
+        .post(dry_run_url)
+        .header("Content-Type", "application/json")
+        .bearer_auth(auth_token)
+        .json(&post_input)
+        .send()
+        .await?;
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(res.json::<serde_json::Value>().await?),
+        _ => {
+            bail!("Request Failed, Response: {:?}", res.text().await?)
+        }
+    }
+}
+
+/// A new version of a policy set to KBS.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PolicyChange {
+    pub version: u64,
+
+    /// Unified diff of the policy with its previous version.
+    pub diff: String,
+}
+
+/// A version of a policy, without its content.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PolicyVersionInfo {
+    pub version: u64,
+
+    /// When the version is set, in RFC 3339 format.
+    pub created_at: String,
+
+    /// Hex SHA-256 digest of the policy.
+    pub digest: String,
+}
+
+#[derive(Deserialize)]
+struct PolicyVersions {
+    versions: Vec<PolicyVersionInfo>,
+}
+
+#[derive(Deserialize)]
+struct PolicyVersion {
+    policy: String,
+}
+
+#[derive(Serialize)]
+struct RollbackPolicyInput {
+    version: u64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signed_version: Option<u64>,
+}
+
+/// A versioned policy of KBS.
+#[derive(Clone, Copy, Debug)]
+pub enum PolicyRef<'a> {
+    /// The default resource policy.
+    Resource,
+
+    /// A repository resource policy, by ID.
+    Repository(&'a str),
+
+    /// An attestation policy, by ID.
+    Attestation(&'a str),
+}
+
+fn policy_versions_url(url: &str, policy: PolicyRef<'_>) -> String {
+    match policy {
+        PolicyRef::Resource => format!("{}/{KBS_URL_PREFIX}/admin/policy-versions/resource", url),
+        PolicyRef::Repository(policy_id) => format!(
+            "{}/{KBS_URL_PREFIX}/admin/policy-versions/repository/{}",
+            url, policy_id
+        ),
+        PolicyRef::Attestation(policy_id) => format!(
+            "{}/{KBS_URL_PREFIX}/admin/policy-versions/attestation/{}",
+            url, policy_id
+        ),
+    }
+}
+
+/// List the versions of a policy in KBS.
+/// Input parameters:
+/// - url: KBS server root URL.
+/// - auth_key: KBS owner's authenticate private key (PEM string).
+/// - policy: The policy.
+/// - kbs_root_certs_pem: Custom HTTPS root certificate of KBS server. It can be left blank.
+pub async fn list_policy_versions(
+    url: &str,
+    auth_key: String,
+    policy: PolicyRef<'_>,
+    kbs_root_certs_pem: Vec<String>,
+) -> Result<Vec<PolicyVersionInfo>> {
+    let auth_private_key = Ed25519KeyPair::from_pem(&auth_key)?;
+    let claims = Claims::create(Duration::from_hours(2));
+    let token = auth_private_key.sign(claims)?;
+
+    let http_client = build_http_client(kbs_root_certs_pem)?;
+
+    let res = http_client
+        .get(policy_versions_url(url, policy))
+        .bearer_auth(token)
+        .send()
+        .await?;
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(res.json::<PolicyVersions>().await?.versions),
+        _ => {
+            bail!("Request Failed, Response: {:?}", res.text().await?)
+        }
+    }
+}
+
+/// Get a version of a policy from KBS.
+/// Input parameters:
+/// - url: KBS server root URL.
+/// - auth_key: KBS owner's authenticate private key (PEM string).
+/// - policy: The policy.
+/// - version: The version to get.
+/// - kbs_root_certs_pem: Custom HTTPS root certificate of KBS server. It can be left blank.
+///
+/// Returns the policy file content of the version.
+pub async fn get_policy_version(
+    url: &str,
+    auth_key: String,
+    policy: PolicyRef<'_>,
+    version: u64,
+    kbs_root_certs_pem: Vec<String>,
+) -> Result<Vec<u8>> {
+    let auth_private_key = Ed25519KeyPair::from_pem(&auth_key)?;
+    let claims = Claims::create(Duration::from_hours(2));
+    let token = auth_private_key.sign(claims)?;
+
+    let http_client = build_http_client(kbs_root_certs_pem)?;
+
+    let version_url = format!("{}/{version}", policy_versions_url(url, policy));
+    let res = http_client
+        .get(version_url)
+        .bearer_auth(token)
+        .send()
+        .await?;
+    match res.status() {
+        reqwest::StatusCode::OK => {
+            let version = res.json::<PolicyVersion>().await?;
+            URL_SAFE_NO_PAD
+                .decode(version.policy)
+                .context("decode policy")
+        }
+        _ => {
+            bail!("Request Failed, Response: {:?}", res.text().await?)
+        }
+    }
+}
+
+/// Roll a policy in KBS back to a previous version.
+/// Input parameters:
+/// - url: KBS server root URL.
+/// - auth_key: KBS owner's authenticate private key (PEM string).
+/// - policy: The policy.
+/// - version: The version to roll back to.
+/// - [signature]: New Ed25519 signature of the policy of the version by the policy author,
+///   required if KBS is configured with a policy author key.
+/// - [signed_version]: Version of the policy signed by the policy author, required with the
+///   signature. It must be newer than the version of the active policy.
+/// - kbs_root_certs_pem: Custom HTTPS root certificate of KBS server. It can be left blank.
+///
+/// Returns the new version of the policy, with its diff with the previous version.
+pub async fn rollback_policy(
+    url: &str,
+    auth_key: String,
+    policy: PolicyRef<'_>,
+    version: u64,
+    signature: Option<Vec<u8>>,
+    signed_version: Option<u64>,
+    kbs_root_certs_pem: Vec<String>,
+) -> Result<PolicyChange> {
+    let auth_private_key = Ed25519KeyPair::from_pem(&auth_key)?;
+    let claims = Claims::create(Duration::from_hours(2));
+    let token = auth_private_key.sign(claims)?;
+
+    let http_client = build_http_client(kbs_root_certs_pem)?;
+
+    let res = http_client
+        .post(policy_versions_url(url, policy))
+        .header("Content-Type", "application/json")
+        .bearer_auth(token)
+        .json(&RollbackPolicyInput {
+            version,
+            signature: signature.map(|signature| URL_SAFE_NO_PAD.encode(signature)),
+            signed_version,
+        })
+        .send()
+        .await?;
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(res.json::<PolicyChange>().await?),
+        _ => {
+            bail!("Request Failed, Response: {:?}", res.text().await?)
+        }
+    }
 }
 
 /// Set secret resource to KBS.
@@ -233,6 +531,389 @@ pub async fn set_resource(
     }
 }
 
+/// Set secret resource to KBS, with a detached signature of the resource
+/// path and data that KBS verifies before releasing the resource.
+/// Input parameters:
+/// - url: KBS server root URL.
+/// - auth_key: KBS owner's authenticate private key (PEM string).
+/// - resource_bytes: Resource data in `Vec<u8>`
+/// - signature: Detached signature of the length of the path (64-bit big-endian), the path and the data.
+/// - path: Resource path, format must be `<top>/<middle>/<tail>`, e.g. `alice/key/example`.
+/// - kbs_root_certs_pem: Custom HTTPS root certificate of KBS server. It can be left blank.
+pub async fn set_signed_resource(
+    url: &str,
+    auth_key: String,
+    resource_bytes: Vec<u8>,
+    signature: Vec<u8>,
+    path: &str,
+    kbs_root_certs_pem: Vec<String>,
+) -> Result<()> {
+    let auth_private_key = Ed25519KeyPair::from_pem(&auth_key)?;
+    let claims = Claims::create(Duration::from_hours(2));
+    let token = auth_private_key.sign(claims)?;
+
+    let http_client = build_http_client(kbs_root_certs_pem)?;
+
+    let resource_url = format!("{}/{KBS_URL_PREFIX}/resource/{}", url, path);
+    let res = http_client
+        .post(resource_url)
+        .header("Content-Type", "application/octet-stream")
+        .header("X-Kbs-Resource-Signature", STANDARD.encode(signature))
+        .bearer_auth(token)
+        .body(resource_bytes)
+        .send()
+        .await?;
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(()),
+        _ => {
+            bail!("Request Failed, Response: {:?}", res.text().await?)
+        }
+    }
+}
+
+/// List the versions of a secret resource in KBS.
+/// Input parameters:
+/// - url: KBS server root URL.
+/// - auth_key: KBS owner's authenticate private key (PEM string).
+/// - path: Resource path, format must be `<top>/<middle>/<tail>`, e.g. `alice/key/example`.
+/// - kbs_root_certs_pem: Custom HTTPS root certificate of KBS server. It can be left blank.
+pub async fn list_resource_versions(
+    url: &str,
+    auth_key: String,
+    path: &str,
+    kbs_root_certs_pem: Vec<String>,
+) -> Result<Vec<u64>> {
+    let auth_private_key = Ed25519KeyPair::from_pem(&auth_key)?;
+    let claims = Claims::create(Duration::from_hours(2));
+    let token = auth_private_key.sign(claims)?;
+
+    let http_client = build_http_client(kbs_root_certs_pem)?;
+
+    let versions_url = format!("{}/{KBS_URL_PREFIX}/admin/resource-versions/{}", url, path);
+    let res = http_client
+        .get(versions_url)
+        .bearer_auth(token)
+        .send()
+        .await?;
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(res.json::<ResourceVersions>().await?.versions),
+        _ => {
+            bail!("Request Failed, Response: {:?}", res.text().await?)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ResourceVersions {
+    versions: Vec<u64>,
+}
+
+#[derive(Serialize)]
+struct RollbackResourceInput {
+    version: u64,
+}
+
+/// Roll a secret resource in KBS back to a previous version.
+/// Input parameters:
+/// - url: KBS server root URL.
+/// - auth_key: KBS owner's authenticate private key (PEM string).
+/// - path: Resource path, format must be `<top>/<middle>/<tail>`, e.g. `alice/key/example`.
+/// - version: The version to roll back to.
+/// - kbs_root_certs_pem: Custom HTTPS root certificate of KBS server. It can be left blank.
+pub async fn rollback_resource(
+    url: &str,
+    auth_key: String,
+    path: &str,
+    version: u64,
+    kbs_root_certs_pem: Vec<String>,
+) -> Result<()> {
+    let auth_private_key = Ed25519KeyPair::from_pem(&auth_key)?;
+    let claims = Claims::create(Duration::from_hours(2));
+    let token = auth_private_key.sign(claims)?;
+
+    let http_client = build_http_client(kbs_root_certs_pem)?;
+
+    let versions_url = format!("{}/{KBS_URL_PREFIX}/admin/resource-versions/{}", url, path);
+    let res = http_client
+        .post(versions_url)
+        .header("Content-Type", "application/json")
+        .bearer_auth(token)
+        .json(&RollbackResourceInput { version })
+        .send()
+        .await?;
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(()),
+        _ => {
+            bail!("Request Failed, Response: {:?}", res.text().await?)
+        }
+    }
+}
+
+/// Delete secret resource from KBS.
+/// Input parameters:
+/// - url: KBS server root URL.
+/// - auth_key: KBS owner's authenticate private key (PEM string).
+/// - path: Resource path, format must be `<top>/<middle>/<tail>`, e.g. `alice/key/example`.
+/// - kbs_root_certs_pem: Custom HTTPS root certificate of KBS server. It can be left blank.
+pub async fn delete_resource(
+    url: &str,
+    auth_key: String,
+    path: &str,
+    kbs_root_certs_pem: Vec<String>,
+) -> Result<()> {
+    let auth_private_key = Ed25519KeyPair::from_pem(&auth_key)?;
+    let claims = Claims::create(Duration::from_hours(2));
+    let token = auth_private_key.sign(claims)?;
+
+    let http_client = build_http_client(kbs_root_certs_pem)?;
+
+    let resource_url = format!("{}/{KBS_URL_PREFIX}/resource/{}", url, path);
+    let res = http_client
+        .delete(resource_url)
+        .bearer_auth(token)
+        .send()
+        .await?;
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(()),
+        _ => {
+            bail!("Request Failed, Response: {:?}", res.text().await?)
+        }
+    }
+}
+
+/// List the secret resources stored in KBS with their metadata.
+/// Input parameters:
+/// - url: KBS server root URL.
+/// - auth_key: KBS owner's authenticate private key (PEM string).
+/// - [repository]: Only list the resources of this repository.
+/// - kbs_root_certs_pem: Custom HTTPS root certificate of KBS server. It can be left blank.
+pub async fn list_resources(
+    url: &str,
+    auth_key: String,
+    repository: Option<String>,
+    kbs_root_certs_pem: Vec<String>,
+) -> Result<serde_json::Value> {
+    let auth_private_key = Ed25519KeyPair::from_pem(&auth_key)?;
+    let claims = Claims::create(Duration::from_hours(2));
+    let token = auth_private_key.sign(claims)?;
+
+    let http_client = build_http_client(kbs_root_certs_pem)?;
+
+    let resources_url = format!("{}/{KBS_URL_PREFIX}/admin/resources", url);
+    let mut request = http_client.get(resources_url).bearer_auth(token);
+    if let Some(repository) = repository {
+        request = request.query(&[("repository", repository)]);
+    }
+
+    let res = request.send().await?;
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(res.json().await?),
+        _ => {
+            bail!("Request Failed, Response: {:?}", res.text().await?)
+        }
+    }
+}
+
+/// Import a bundle of secret resources to KBS in one call.
+/// Input parameters:
+/// - url: KBS server root URL.
+/// - auth_key: KBS owner's authenticate private key (PEM string).
+/// - bundle: A JSON manifest, or a (gzipped) tarball of `<top>/<middle>/<tail>` resource files.
+/// - kbs_root_certs_pem: Custom HTTPS root certificate of KBS server. It can be left blank.
+///
+/// Returns the number of imported resources.
+pub async fn import_resources(
+    url: &str,
+    auth_key: String,
+    bundle: Vec<u8>,
+    kbs_root_certs_pem: Vec<String>,
+) -> Result<u64> {
+    let auth_private_key = Ed25519KeyPair::from_pem(&auth_key)?;
+    let claims = Claims::create(Duration::from_hours(2));
+    let token = auth_private_key.sign(claims)?;
+
+    let http_client = build_http_client(kbs_root_certs_pem)?;
+
+    let import_url = format!("{}/{KBS_URL_PREFIX}/admin/resources/import", url);
+    let res = http_client
+        .post(import_url)
+        .header("Content-Type", "application/octet-stream")
+        .bearer_auth(token)
+        .body(bundle)
+        .send()
+        .await?;
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(res.json::<ImportedResources>().await?.imported),
+        _ => {
+            bail!("Request Failed, Response: {:?}", res.text().await?)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ImportedResources {
+    imported: u64,
+}
+
+#[derive(Serialize)]
+struct ExportResourcesInput {
+    repositories: Vec<String>,
+    public_key: Option<TeePubKey>,
+}
+
+/// Export the secret resources of KBS as a JSON manifest, that can be
+/// imported again with [`import_resources`].
+/// Input parameters:
+/// - url: KBS server root URL.
+/// - auth_key: KBS owner's authenticate private key (PEM string).
+/// - repositories: Repositories to export, all repositories if empty.
+/// - [encryption_key_pem]: Operator's RSA private key (PEM format). If given, KBS encrypts the
+///     manifest to its public part, and the manifest is decrypted locally.
+/// - kbs_root_certs_pem: Custom HTTPS root certificate of KBS server. It can be left blank.
+pub async fn export_resources(
+    url: &str,
+    auth_key: String,
+    repositories: Vec<String>,
+    encryption_key_pem: Option<String>,
+    kbs_root_certs_pem: Vec<String>,
+) -> Result<serde_json::Value> {
+    let auth_private_key = Ed25519KeyPair::from_pem(&auth_key)?;
+    let claims = Claims::create(Duration::from_hours(2));
+    let token = auth_private_key.sign(claims)?;
+
+    let encryption_key = match encryption_key_pem {
+        Some(pem) => Some(
+            RsaPrivateKey::from_pkcs8_pem(&pem)
+                .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&pem))
+                .context("illegal RSA encryption key")?,
+        ),
+        None => None,
+    };
+    let public_key = encryption_key.as_ref().map(|key| TeePubKey {
+        kty: "RSA".to_string(),
+        alg: "RSA1_5".to_string(),
+        k_mod: URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+        k_exp: URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+    });
+
+    let http_client = build_http_client(kbs_root_certs_pem)?;
+
+    let export_url = format!("{}/{KBS_URL_PREFIX}/admin/resources/export", url);
+    let res = http_client
+        .post(export_url)
+        .header("Content-Type", "application/json")
+        .bearer_auth(token)
+        .json(&ExportResourcesInput {
+            repositories,
+            public_key,
+        })
+        .send()
+        .await?;
+    if res.status() != reqwest::StatusCode::OK {
+        bail!("Request Failed, Response: {:?}", res.text().await?);
+    }
+
+    match encryption_key {
+        Some(key) => {
+            let manifest = decrypt_response(&key, res.json::<Response>().await?)?;
+            Ok(serde_json::from_slice(&manifest)?)
+        }
+        None => Ok(res.json().await?),
+    }
+}
+
+/// Get the hits, misses, hit rate and number of entries of the KBS resource cache.
+/// Input parameters:
+/// - url: KBS server root URL.
+/// - auth_key: KBS owner's authenticate private key (PEM string).
+/// - kbs_root_certs_pem: Custom HTTPS root certificate of KBS server. It can be left blank.
+pub async fn resource_cache_stats(
+    url: &str,
+    auth_key: String,
+    kbs_root_certs_pem: Vec<String>,
+) -> Result<serde_json::Value> {
+    let auth_private_key = Ed25519KeyPair::from_pem(&auth_key)?;
+    let claims = Claims::create(Duration::from_hours(2));
+    let token = auth_private_key.sign(claims)?;
+
+    let http_client = build_http_client(kbs_root_certs_pem)?;
+
+    let cache_url = format!("{}/{KBS_URL_PREFIX}/admin/resource-cache", url);
+    let res = http_client.get(cache_url).bearer_auth(token).send().await?;
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(res.json().await?),
+        reqwest::StatusCode::NOT_FOUND => bail!("No resource cache is configured"),
+        _ => {
+            bail!("Request Failed, Response: {:?}", res.text().await?)
+        }
+    }
+}
+
+/// Purge all the entries of the KBS resource cache.
+/// Input parameters:
+/// - url: KBS server root URL.
+/// - auth_key: KBS owner's authenticate private key (PEM string).
+/// - kbs_root_certs_pem: Custom HTTPS root certificate of KBS server. It can be left blank.
+///
+/// Returns the number of purged entries.
+pub async fn purge_resource_cache(
+    url: &str,
+    auth_key: String,
+    kbs_root_certs_pem: Vec<String>,
+) -> Result<u64> {
+    let auth_private_key = Ed25519KeyPair::from_pem(&auth_key)?;
+    let claims = Claims::create(Duration::from_hours(2));
+    let token = auth_private_key.sign(claims)?;
+
+    let http_client = build_http_client(kbs_root_certs_pem)?;
+
+    let cache_url = format!("{}/{KBS_URL_PREFIX}/admin/resource-cache", url);
+    let res = http_client
+        .delete(cache_url)
+        .bearer_auth(token)
+        .send()
+        .await?;
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(res.json::<PurgedCacheEntries>().await?.purged),
+        _ => {
+            bail!("Request Failed, Response: {:?}", res.text().await?)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PurgedCacheEntries {
+    purged: u64,
+}
+
+/// Decrypt a KBS JWE response: the AES-256-GCM content key is wrapped with
+/// RSA PKCS#1 v1.5.
+fn decrypt_response(key: &RsaPrivateKey, response: Response) -> Result<Vec<u8>> {
+    let wrapped_key = URL_SAFE_NO_PAD
+        .decode(&response.encrypted_key)
+        .context("illegal encrypted key")?;
+    let iv = URL_SAFE_NO_PAD.decode(&response.iv).context("illegal iv")?;
+    let mut ciphertext = URL_SAFE_NO_PAD
+        .decode(&response.ciphertext)
+        .context("illegal ciphertext")?;
+    ciphertext.extend(
+        URL_SAFE_NO_PAD
+            .decode(&response.tag)
+            .context("illegal tag")?,
+    );
+    if iv.len() != 12 {
+        bail!("illegal iv length {}", iv.len());
+    }
+
+    let content_key = key
+        .decrypt(Pkcs1v15Encrypt, &wrapped_key)
+        .context("unwrap content key")?;
+    Aes256Gcm::new_from_slice(&content_key)
+        .map_err(|_| anyhow!("illegal content key length {}", content_key.len()))?
+        .decrypt(Nonce::from_slice(&iv), ciphertext.as_slice())
+        .map_err(|_| anyhow!("decrypt response: authentication failed"))
+}
+
 fn build_http_client(kbs_root_certs_pem: Vec<String>) -> Result<reqwest::Client> {
     let mut client_builder =
         reqwest::Client::builder().user_agent(format!("kbs-client/{}", env!("CARGO_PKG_VERSION")));
@@ -8,6 +8,7 @@ use anyhow::{bail, Result};
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
 use clap::{Args, Parser, Subcommand};
+use kbs_client::PolicyRef;
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -95,6 +96,14 @@ enum ConfigCommands {
         /// Policy file path
         #[clap(long, value_parser)]
         policy_file: PathBuf,
+
+        /// File of the Ed25519 signature of the policy file by the policy author
+        #[clap(long, value_parser)]
+        signature_file: Option<PathBuf>,
+
+        /// Version of the policy signed by the policy author
+        #[clap(long, value_parser, requires = "signature_file")]
+        signed_version: Option<u64>,
     },
 
     /// Set resource policy
@@ -102,6 +111,76 @@ enum ConfigCommands {
         /// Policy file path
         #[clap(long, value_parser)]
         policy_file: PathBuf,
+
+        /// Repository policy ID, e.g "tenant-a". The default resource policy if not set
+        #[clap(long, value_parser)]
+        id: Option<String>,
+
+        /// File of the Ed25519 signature of the policy file by the policy author
+        #[clap(long, value_parser)]
+        signature_file: Option<PathBuf>,
+
+        /// Version of the policy signed by the policy author
+        #[clap(long, value_parser, requires = "signature_file")]
+        signed_version: Option<u64>,
+    },
+
+    /// Dry run a candidate resource policy against attestation claims, without setting it
+    #[clap(group(clap::ArgGroup::new("claims_source").required(true).args(["claims_file", "session_id", "token_file"])))]
+    DryRunResourcePolicy {
+        /// Candidate policy file path. The set policy of the resource if not set
+        #[clap(long, value_parser)]
+        policy_file: Option<PathBuf>,
+
+        /// KBS Resource path, e.g my_repo/resource_type/123abc
+        #[clap(long, value_parser)]
+        path: String,
+
+        /// JSON file of the attestation claims
+        #[clap(long, value_parser)]
+        claims_file: Option<PathBuf>,
+
+        /// ID of an attested KBS session, whose claims are used
+        #[clap(long, value_parser)]
+        session_id: Option<String>,
+
+        /// File of an attestation token, whose claims are used
+        #[clap(long, value_parser)]
+        token_file: Option<PathBuf>,
+    },
+
+    /// List the versions of the resource policy, or of an attestation policy
+    ListPolicyVersions {
+        #[clap(flatten)]
+        policy: PolicyArgs,
+    },
+
+    /// Get a version of the resource policy, or of an attestation policy
+    GetPolicyVersion {
+        #[clap(flatten)]
+        policy: PolicyArgs,
+
+        /// The version to get
+        #[clap(long, value_parser)]
+        version: u64,
+    },
+
+    /// Roll the resource policy, or an attestation policy, back to a previous version
+    RollbackPolicy {
+        #[clap(flatten)]
+        policy: PolicyArgs,
+
+        /// The version to roll back to
+        #[clap(long, value_parser)]
+        version: u64,
+
+        /// File of the new Ed25519 signature of the policy of the version by the policy author
+        #[clap(long, value_parser)]
+        signature_file: Option<PathBuf>,
+
+        /// Version of the policy signed by the policy author
+        #[clap(long, value_parser, requires = "signature_file")]
+        signed_version: Option<u64>,
     },
 
     /// Set confidential resource
@@ -114,7 +193,91 @@ enum ConfigCommands {
         /// Resource file path
         #[clap(long, value_parser)]
         resource_file: PathBuf,
+
+        /// Detached signature file of the resource, verified by KBS before
+        /// releasing the resource
+        #[clap(long, value_parser)]
+        signature_file: Option<PathBuf>,
+    },
+
+    /// Delete confidential resource
+    DeleteResource {
+        /// KBS Resource path, e.g my_repo/resource_type/123abc
+        #[clap(long, value_parser)]
+        path: String,
+    },
+
+    /// List the confidential resources with their metadata
+    ListResources {
+        /// Only list the resources of this repository
+        #[clap(long, value_parser)]
+        repository: Option<String>,
     },
+
+    /// Import a bundle of confidential resources
+    ImportResources {
+        /// A JSON manifest, or a (gzipped) tarball of `<repository>/<type>/<tag>` files
+        #[clap(long, value_parser)]
+        file: PathBuf,
+    },
+
+    /// Export confidential resources as a JSON manifest
+    ExportResources {
+        /// Repository to export, can be repeated. All repositories are exported if not set
+        #[clap(long, value_parser)]
+        repository: Vec<String>,
+
+        /// RSA private key file path (PEM format). If set, the manifest is
+        /// encrypted to the public part of this key on its way from KBS
+        #[clap(long, value_parser)]
+        encryption_key_file: Option<PathBuf>,
+    },
+
+    /// Show the hit rate statistics of the resource cache
+    ResourceCacheStats,
+
+    /// Purge the resource cache
+    PurgeResourceCache,
+
+    /// List the versions of a confidential resource
+    ListResourceVersions {
+        /// KBS Resource path, e.g my_repo/resource_type/123abc
+        #[clap(long, value_parser)]
+        path: String,
+    },
+
+    /// Roll a confidential resource back to a previous version
+    RollbackResource {
+        /// KBS Resource path, e.g my_repo/resource_type/123abc
+        #[clap(long, value_parser)]
+        path: String,
+
+        /// The version to roll back to
+        #[clap(long, value_parser)]
+        version: u64,
+    },
+}
+
+/// The versioned policy, the default resource policy if neither is set.
+#[derive(Args)]
+struct PolicyArgs {
+    /// Attestation policy ID, e.g "default"
+    #[clap(long, value_parser, conflicts_with = "repository_policy_id")]
+    id: Option<String>,
+
+    /// Repository resource policy ID, e.g "tenant-a"
+    #[clap(long, value_parser)]
+    repository_policy_id: Option<String>,
+}
+
+impl PolicyArgs {
+    fn policy(&self) -> PolicyRef<'_> {
+        match (&self.id, &self.repository_policy_id) {
+            (Some(id), _) => PolicyRef::Attestation(id),
+            (None, Some(id)) => PolicyRef::Repository(id),
+            (None, None) => PolicyRef::Resource,
+        }
+    }
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -182,54 +345,250 @@ async fn main() -> Result<()> {
                     r#type,
                     id,
                     policy_file,
+                    signature_file,
+                    signed_version,
                 } => {
                     let policy_bytes = std::fs::read(policy_file)?;
-                    kbs_client::set_attestation_policy(
+                    let signature = signature_file.map(std::fs::read).transpose()?;
+                    let change = kbs_client::set_attestation_policy(
                         &cli.url,
                         auth_key.clone(),
                         policy_bytes.clone(),
                         r#type,
                         id,
+                        signature,
+                        signed_version,
                         kbs_cert.clone(),
                     )
                     .await?;
                     println!(
-                        "Set attestation policy success \n policy: {}",
-                        STANDARD.encode(policy_bytes)
+                        "Set attestation policy success \n policy: {}\n version: {}\n{}",
+                        STANDARD.encode(policy_bytes),
+                        change.version,
+                        change.diff
                     );
                 }
-                ConfigCommands::SetResourcePolicy { policy_file } => {
+                ConfigCommands::SetResourcePolicy {
+                    policy_file,
+                    id,
+                    signature_file,
+                    signed_version,
+                } => {
                     let policy_bytes = std::fs::read(policy_file)?;
-                    kbs_client::set_resource_policy(
+                    let signature = signature_file.map(std::fs::read).transpose()?;
+                    let change = kbs_client::set_resource_policy(
                         &cli.url,
                         auth_key.clone(),
                         policy_bytes.clone(),
+                        id,
+                        signature,
+                        signed_version,
                         kbs_cert.clone(),
                     )
                     .await?;
                     println!(
-                        "Set resource policy success \n policy: {}",
-                        STANDARD.encode(policy_bytes)
+                        "Set resource policy success \n policy: {}\n version: {}\n{}",
+                        STANDARD.encode(policy_bytes),
+                        change.version,
+                        change.diff
                     );
                 }
-                ConfigCommands::SetResource {
+                ConfigCommands::DryRunResourcePolicy {
+                    policy_file,
                     path,
-                    resource_file,
+                    claims_file,
+                    session_id,
+                    token_file,
                 } => {
-                    let resource_bytes = std::fs::read(resource_file)?;
-                    kbs_client::set_resource(
+                    let policy_bytes = policy_file.map(std::fs::read).transpose()?;
+                    let claims = match claims_file {
+                        Some(claims_file) => {
+                            Some(serde_json::from_slice(&std::fs::read(claims_file)?)?)
+                        }
+                        None => None,
+                    };
+                    let token = token_file
+                        .map(std::fs::read_to_string)
+                        .transpose()?
+                        .map(|token| token.trim().to_string());
+                    let report = kbs_client::dry_run_resource_policy(
                         &cli.url,
                         auth_key.clone(),
-                        resource_bytes.clone(),
+                        policy_bytes,
                         &path,
+                        claims,
+                        session_id,
+                        token,
+                        kbs_cert.clone(),
+                    )
+                    .await?;
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                }
+                ConfigCommands::ListPolicyVersions { policy } => {
+                    let versions = kbs_client::list_policy_versions(
+                        &cli.url,
+                        auth_key.clone(),
+                        policy.policy(),
+                        kbs_cert.clone(),
+                    )
+                    .await?;
+                    println!("{}", serde_json::to_string_pretty(&versions)?);
+                }
+                ConfigCommands::GetPolicyVersion { policy, version } => {
+                    let policy = kbs_client::get_policy_version(
+                        &cli.url,
+                        auth_key.clone(),
+                        policy.policy(),
+                        version,
+                        kbs_cert.clone(),
+                    )
+                    .await?;
+                    print!("{}", String::from_utf8_lossy(&policy));
+                }
+                ConfigCommands::RollbackPolicy {
+                    policy,
+                    version,
+                    signature_file,
+                    signed_version,
+                } => {
+                    let signature = signature_file.map(std::fs::read).transpose()?;
+                    let change = kbs_client::rollback_policy(
+                        &cli.url,
+                        auth_key.clone(),
+                        policy.policy(),
+                        version,
+                        signature,
+                        signed_version,
                         kbs_cert.clone(),
                     )
                     .await?;
+                    println!(
+                        "Rollback policy success \n version: {}\n{}",
+                        change.version, change.diff
+                    );
+                }
+                ConfigCommands::SetResource {
+                    path,
+                    resource_file,
+                    signature_file,
+                } => {
+                    let resource_bytes = std::fs::read(resource_file)?;
+                    match signature_file {
+                        Some(signature_file) => {
+                            kbs_client::set_signed_resource(
+                                &cli.url,
+                                auth_key.clone(),
+                                resource_bytes.clone(),
+                                std::fs::read(signature_file)?,
+                                &path,
+                                kbs_cert.clone(),
+                            )
+                            .await?
+                        }
+                        None => {
+                            kbs_client::set_resource(
+                                &cli.url,
+                                auth_key.clone(),
+                                resource_bytes.clone(),
+                                &path,
+                                kbs_cert.clone(),
+                            )
+                            .await?
+                        }
+                    }
                     println!(
                         "Set resource success \n resource: {}",
                         STANDARD.encode(resource_bytes)
                     );
                 }
+                ConfigCommands::DeleteResource { path } => {
+                    kbs_client::delete_resource(
+                        &cli.url,
+                        auth_key.clone(),
+                        &path,
+                        kbs_cert.clone(),
+                    )
+                    .await?;
+                    println!("Delete resource success \n path: {path}");
+                }
+                ConfigCommands::ListResources { repository } => {
+                    let resources = kbs_client::list_resources(
+                        &cli.url,
+                        auth_key.clone(),
+                        repository,
+                        kbs_cert.clone(),
+                    )
+                    .await?;
+                    println!("{}", serde_json::to_string_pretty(&resources)?);
+                }
+                ConfigCommands::ImportResources { file } => {
+                    let bundle = std::fs::read(file)?;
+                    let imported = kbs_client::import_resources(
+                        &cli.url,
+                        auth_key.clone(),
+                        bundle,
+                        kbs_cert.clone(),
+                    )
+                    .await?;
+                    println!("Import resources success \n imported: {imported}");
+                }
+                ConfigCommands::ExportResources {
+                    repository,
+                    encryption_key_file,
+                } => {
+                    let encryption_key = match encryption_key_file {
+                        Some(f) => Some(std::fs::read_to_string(f)?),
+                        None => None,
+                    };
+                    let manifest = kbs_client::export_resources(
+                        &cli.url,
+                        auth_key.clone(),
+                        repository,
+                        encryption_key,
+                        kbs_cert.clone(),
+                    )
+                    .await?;
+                    println!("{}", serde_json::to_string_pretty(&manifest)?);
+                }
+                ConfigCommands::ResourceCacheStats => {
+                    let stats = kbs_client::resource_cache_stats(
+                        &cli.url,
+                        auth_key.clone(),
+                        kbs_cert.clone(),
+                    )
+                    .await?;
+                    println!("{}", serde_json::to_string_pretty(&stats)?);
+                }
+                ConfigCommands::PurgeResourceCache => {
+                    let purged = kbs_client::purge_resource_cache(
+                        &cli.url,
+                        auth_key.clone(),
+                        kbs_cert.clone(),
+                    )
+                    .await?;
+                    println!("Purge resource cache success \n purged: {purged}");
+                }
+                ConfigCommands::ListResourceVersions { path } => {
+                    let versions = kbs_client::list_resource_versions(
+                        &cli.url,
+                        auth_key.clone(),
+                        &path,
+                        kbs_cert.clone(),
+                    )
+                    .await?;
+                    println!("{}", serde_json::to_string(&versions)?);
+                }
+                ConfigCommands::RollbackResource { path, version } => {
+                    kbs_client::rollback_resource(
+                        &cli.url,
+                        auth_key.clone(),
+                        &path,
+                        version,
+                        kbs_cert.clone(),
+                    )
+                    .await?;
+                    println!("Rollback resource success \n version: {version}");
+                }
             }
         }
     }
@@ -0,0 +1,104 @@
+#!/bin/bash
+#
+# Copyright (c) 2024 by The Confidential Container Authors.
+#
+# SPDX-License-Identifier: Apache-2.0
+#
+# Assemble, on a connected machine, the offline bundle of the verifiers of
+# the attestation service. The attestation service uses it when
+# VERIFIER_OFFLINE_BUNDLE is set to the bundle directory.
+
+set -euo pipefail
+
+KDS_URL=${KDS_URL:-https://kdsintf.amd.com}
+
+usage() {
+	cat <<EOF
+Usage: $0 -o <bundle dir> [options]
+
+Options:
+  --snp-vcek <product> <chip id> <bl> <tee> <snp> <ucode>
+                                 Fetch the VCEK of an SNP chip (hex chip id) at a
+                                 TCB from the AMD KDS, e.g. product Milan or Genoa.
+  --snp-cert <file>              Add a VCEK or VLEK certificate, in PEM or DER.
+  --intel-collateral <dir>       Add the SGX/TDX collateral cached in <dir> by an
+                                 attestation service run with DCAP_COLLATERAL_CACHE_DIR.
+  --cca-trust-anchors <file>     Add the CCA trust anchors, in PEM.
+  --nvidia-root-ca <file>        Add the NVIDIA GPU attestation root CA, in PEM.
+  --nvidia-rims <dir>            Add the NVIDIA RIMs of <dir>.
+  --tpm-ak-ca <file>             Add the TPM AK CA certificates, in PEM.
+EOF
+	exit 1
+}
+
+bundle=""
+declare -a actions=()
+while [[ $# -gt 0 ]]; do
+	case "$1" in
+	-o)
+		bundle="$2"
+		shift 2
+		;;
+	--snp-vcek)
+		[[ $# -ge 7 ]] || usage
+		actions+=("snp_vcek $2 $3 $4 $5 $6 $7")
+		shift 7
+		;;
+	--snp-cert | --intel-collateral | --cca-trust-anchors | --nvidia-root-ca | --nvidia-rims | --tpm-ak-ca)
+		[[ $# -ge 2 ]] || usage
+		action="${1#--}"
+		actions+=("${action//-/_} $2")
+		shift 2
+		;;
+	*)
+		usage
+		;;
+	esac
+done
+[[ -n "$bundle" ]] || usage
+
+snp_vcek() {
+	local product="$1" chip_id="$2"
+	local query="blSPL=$3&teeSPL=$4&snpSPL=$5&ucodeSPL=$6"
+	mkdir -p "$bundle/snp"
+	curl -sSf -o "$bundle/snp/vcek-${chip_id:0:16}-$3-$4-$5-$6.der" \
+		"$KDS_URL/vcek/v1/$product/$chip_id?$query"
+}
+
+snp_cert() {
+	mkdir -p "$bundle/snp"
+	cp "$1" "$bundle/snp/"
+}
+
+intel_collateral() {
+	mkdir -p "$bundle/intel"
+	cp "$1"/*.json "$bundle/intel/"
+}
+
+cca_trust_anchors() {
+	mkdir -p "$bundle/cca"
+	cp "$1" "$bundle/cca/trust-anchors.pem"
+}
+
+nvidia_root_ca() {
+	mkdir -p "$bundle/nvidia"
+	cp "$1" "$bundle/nvidia/root-ca.pem"
+}
+
+nvidia_rims() {
+	mkdir -p "$bundle/nvidia/rims"
+	cp "$1"/*.xml "$bundle/nvidia/rims/"
+}
+
+tpm_ak_ca() {
+	mkdir -p "$bundle/tpm"
+	cp "$1" "$bundle/tpm/ak-ca.pem"
+}
+
+mkdir -p "$bundle"
+for action in "${actions[@]}"; do
+	# shellcheck disable=SC2086
+	$action
+done
+
+echo "Offline bundle assembled in $bundle"